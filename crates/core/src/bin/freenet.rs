@@ -3,11 +3,10 @@ use clap::Parser;
 use freenet::{
     config::{Config, ConfigArgs},
     dev_tool::NodeConfig,
-    local_node::{Executor, OperationMode},
-    run_local_node, run_network_node,
+    local_node::OperationMode,
+    run_network_node,
     server::serve_gateway,
 };
-use std::sync::Arc;
 
 async fn run(config: Config) -> anyhow::Result<()> {
     match config.mode {
@@ -18,25 +17,34 @@ async fn run(config: Config) -> anyhow::Result<()> {
 
 async fn run_local(config: Config) -> anyhow::Result<()> {
     tracing::info!("Starting freenet node in local mode");
-    let socket = config.ws_api;
+    let address = config.ws_api.address;
+    if !address.is_loopback() {
+        anyhow::bail!("invalid ip: {address}, expecting localhost");
+    }
 
-    let executor = Executor::from_config(Arc::new(config), None)
+    let clients = serve_gateway(config.ws_api, config.client_storage_dir()).await?;
+    let node_config = NodeConfig::builder(config)
         .await
-        .map_err(anyhow::Error::msg)?;
+        .and_then(|builder| builder.build())
+        .with_context(|| "failed while loading node config")?;
 
-    run_local_node(executor, socket)
+    let node = node_config
+        .build_local(clients)
         .await
-        .map_err(anyhow::Error::msg)
+        .with_context(|| "failed while building the local node")?;
+
+    node.run().await
 }
 
 async fn run_network(config: Config) -> anyhow::Result<()> {
     tracing::info!("Starting freenet node in network mode");
 
-    let clients = serve_gateway(config.ws_api).await;
+    let clients = serve_gateway(config.ws_api, config.client_storage_dir()).await?;
     tracing::info!("Initializing node configuration");
 
-    let node_config = NodeConfig::new(config)
+    let node_config = NodeConfig::builder(config)
         .await
+        .and_then(|builder| builder.build())
         .with_context(|| "failed while loading node config")?;
 
     let node = node_config
@@ -13,24 +13,48 @@ use std::fmt::Debug;
 use std::fmt::Display;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::Instrument;
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{broadcast, mpsc};
 
 use crate::contract::{ClientResponsesReceiver, ContractHandlerEvent};
 use crate::message::{NodeEvent, QueryResult};
-use crate::node::OpManager;
+use crate::node::{NodeLifecycleEvent, OpManager};
 use crate::operations::{get, put, update, OpError};
 use crate::{config::GlobalExecutor, contract::StoreResponse};
 
+pub use notifications::{notification_channel, NotificationReceiver, NotificationSender};
+
 pub(crate) mod combinator;
+pub mod notifications;
 #[cfg(feature = "websocket")]
 pub(crate) mod websocket;
 
 pub(crate) type BoxedClient = Box<dyn ClientEventsProxy + Send + 'static>;
 pub type HostResult = Result<HostResponse, ClientError>;
 
+/// Notification sent to a client subscribed to a contract.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClientNotification {
+    /// A change in the state of the contract, or an error of the subscription.
+    Result(HostResult),
+    /// An event the contract emitted while executing, which is not reflected in its state.
+    ContractEvent { key: ContractKey, payload: Vec<u8> },
+}
+
+/// Change in the connectivity of the node with the network, sent to all the clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ConnectivityEvent {
+    /// All connections were lost for a while, the node is joining the network again.
+    Disconnected,
+    /// Connected to the network again after having been disconnected for the given time.
+    Reconnected { offline_for: Duration },
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[repr(transparent)]
 pub struct ClientId(usize);
@@ -105,8 +129,10 @@ impl From<String> for AuthToken {
 pub struct OpenRequest<'a> {
     pub client_id: ClientId,
     pub request: Box<ClientRequest<'a>>,
-    pub notification_channel: Option<UnboundedSender<HostResult>>,
+    pub notification_channel: Option<NotificationSender>,
     pub token: Option<AuthToken>,
+    /// Contract the client was attested for when it connected, if any.
+    pub attested_contract: Option<ContractInstanceId>,
 }
 
 impl Display for OpenRequest<'_> {
@@ -133,10 +159,11 @@ impl<'a> OpenRequest<'a> {
             request,
             notification_channel: None,
             token: None,
+            attested_contract: None,
         }
     }
 
-    pub fn with_notification(mut self, ch: UnboundedSender<HostResult>) -> Self {
+    pub fn with_notification(mut self, ch: NotificationSender) -> Self {
         self.notification_channel = Some(ch);
         self
     }
@@ -145,6 +172,11 @@ impl<'a> OpenRequest<'a> {
         self.token = token;
         self
     }
+
+    pub fn with_attested_contract(mut self, contract: Option<ContractInstanceId>) -> Self {
+        self.attested_contract = contract;
+        self
+    }
 }
 
 pub trait ClientEventsProxy {
@@ -158,6 +190,15 @@ pub trait ClientEventsProxy {
         id: ClientId,
         response: Result<HostResponse, ClientError>,
     ) -> BoxFuture<Result<(), ClientError>>;
+
+    /// Lets all the connected client applications know the connectivity of the node changed.
+    /// Ignored by the proxies whose clients can't be told about it.
+    fn notify_connectivity(
+        &mut self,
+        _event: ConnectivityEvent,
+    ) -> BoxFuture<Result<(), ClientError>> {
+        Box::pin(async { Ok(()) })
+    }
 }
 
 /// Process client events.
@@ -170,6 +211,9 @@ pub async fn client_event_handling<ClientEv>(
     ClientEv: ClientEventsProxy + Send + 'static,
 {
     let mut callbacks = FuturesUnordered::new();
+    let mut lifecycle_events = op_manager.ring.lifecycle_events.subscribe();
+    let (get_batcher, batch_gets) = get::GetBatcher::new(op_manager.clone());
+    GlobalExecutor::spawn(batch_gets.instrument(tracing::Span::current()));
     loop {
         tokio::select! {
             client_request = client_events.recv() => {
@@ -193,7 +237,7 @@ pub async fn client_event_handling<ClientEv>(
                     break;
                 }
                 let cli_id = req.client_id;
-                if let Some(mut cb) = process_open_request(req, op_manager.clone()).await {
+                if let Some(mut cb) = process_open_request(req, op_manager.clone(), get_batcher.clone()).await {
                     callbacks.push(async move { cb.recv().await.map(|r| (cli_id, r)) });
                 }
             }
@@ -208,6 +252,24 @@ pub async fn client_event_handling<ClientEv>(
                     }
                 }
             }
+            event = lifecycle_events.recv() => {
+                let event = match event {
+                    Ok(NodeLifecycleEvent::Disconnected) => ConnectivityEvent::Disconnected,
+                    Ok(NodeLifecycleEvent::Reconnected { offline_for }) => {
+                        ConnectivityEvent::Reconnected { offline_for }
+                    }
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::debug!(%missed, "missed node lifecycle events");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if let Err(err) = client_events.notify_connectivity(event).await {
+                    tracing::debug!("channel closed: {err}");
+                    break;
+                }
+            }
             res = callbacks.next(), if !callbacks.is_empty() => {
                 if let Some(Some((cli_id, res))) = res {
                     let res = match res {
@@ -223,6 +285,8 @@ pub async fn client_event_handling<ClientEv>(
                                 contract,
                             }))
                         }
+                        QueryResult::Failed(error) => Err(error),
+                        QueryResult::Delegate(response) => Ok(response),
                     };
                     if let Err(err) = client_events.send(cli_id, res).await {
                         tracing::debug!("channel closed: {err}");
@@ -238,10 +302,15 @@ pub async fn client_event_handling<ClientEv>(
 async fn process_open_request(
     mut request: OpenRequest<'static>,
     op_manager: Arc<OpManager>,
+    get_batcher: get::GetBatcher,
 ) -> Option<mpsc::Receiver<QueryResult>> {
     let (callback_tx, callback_rx) = if matches!(
         &*request.request,
-        ClientRequest::NodeQueries(_) | ClientRequest::ContractOp(ContractRequest::Get { .. })
+        ClientRequest::NodeQueries(_)
+            | ClientRequest::DelegateOp(_)
+            | ClientRequest::ContractOp(
+                ContractRequest::Get { .. } | ContractRequest::Update { .. }
+            )
     ) {
         let (tx, rx) = mpsc::channel(1);
         (Some(tx), Some(rx))
@@ -254,8 +323,7 @@ async fn process_open_request(
         let client_id = request.client_id;
 
         // fixme: communicate back errors in this loop to the client somehow
-        let subscription_listener: Option<UnboundedSender<HostResult>> =
-            request.notification_channel.take();
+        let subscription_listener: Option<NotificationSender> = request.notification_channel.take();
         match *request.request {
             ClientRequest::ContractOp(ops) => {
                 match ops {
@@ -318,8 +386,27 @@ async fn process_open_request(
                             }) => Err(OpError::from(err)),
                             Err(err) => Err(err.into()),
                             Ok(_) => Err(OpError::UnexpectedOpState),
-                        }
-                        .expect("update query failed");
+                        };
+                        let new_state = match new_state {
+                            Ok(new_state) => new_state,
+                            Err(err) => {
+                                tracing::debug!(%key, "Update refused: {err}");
+                                let error = match err {
+                                    OpError::ExecutorError(err) if err.is_request() => {
+                                        ErrorKind::RequestError(err.unwrap_request())
+                                    }
+                                    err => ErrorKind::OperationError {
+                                        cause: format!("{err}").into(),
+                                    },
+                                };
+                                callback_tx
+                                    .unwrap()
+                                    .send(QueryResult::Failed(error.into()))
+                                    .await
+                                    .ok();
+                                return;
+                            }
+                        };
 
                         let op = update::start_op(key, new_state, related_contracts);
 
@@ -389,7 +476,8 @@ async fn process_open_request(
                                 .ch_outbound
                                 .waiting_for_transaction_result(op.id, client_id)
                                 .await;
-                            if let Err(err) = get::request_get(&op_manager, op, vec![]).await {
+                            // gets started close together are sent to the network as a batch
+                            if let Err(err) = get_batcher.request_get(op).await {
                                 tracing::error!("{}", err);
                             }
                         }
@@ -429,7 +517,42 @@ async fn process_open_request(
                     }
                 }
             }
-            ClientRequest::DelegateOp(_op) => todo!("FIXME: delegate op"),
+            ClientRequest::DelegateOp(req) => {
+                let key = req.key().clone();
+                let result = match op_manager
+                    .notify_contract_handler(ContractHandlerEvent::DelegateQuery {
+                        req,
+                        attested_contract: request.attested_contract,
+                    })
+                    .await
+                {
+                    Ok(ContractHandlerEvent::DelegateResponse { response, .. }) => {
+                        response.map(QueryResult::Delegate).map_err(|err| {
+                            if err.is_request() {
+                                ErrorKind::RequestError(err.unwrap_request())
+                            } else {
+                                ErrorKind::OperationError {
+                                    cause: format!("{err}").into(),
+                                }
+                            }
+                        })
+                    }
+                    Ok(_) => Err(ErrorKind::OperationError {
+                        cause: "unexpected contract handler response".into(),
+                    }),
+                    Err(err) => Err(ErrorKind::OperationError {
+                        cause: format!("{err}").into(),
+                    }),
+                };
+                if let Err(err) = &result {
+                    tracing::debug!(%key, "Delegate request failed: {err}");
+                }
+                callback_tx
+                    .expect("should be set")
+                    .send(result.unwrap_or_else(|err| QueryResult::Failed(err.into())))
+                    .await
+                    .ok();
+            }
             ClientRequest::Disconnect { .. } => unreachable!(),
             ClientRequest::NodeQueries(_) => {
                 tracing::debug!("Received node queries from user event");
@@ -582,6 +705,7 @@ pub(crate) mod test {
                                     .into(),
                                 notification_channel: None,
                                 token: None,
+                                attested_contract: None,
                             };
                             return Ok(res.into_owned());
                         } else if pk == self.key {
@@ -593,6 +717,7 @@ pub(crate) mod test {
                                     .into(),
                                 notification_channel: None,
                                 token: None,
+                                attested_contract: None,
                             };
                             return Ok(res.into_owned());
                         }
@@ -685,6 +810,7 @@ pub(crate) mod test {
                                             .into(),
                                         notification_channel: None,
                                         token: None,
+                                        attested_contract: None,
                                     };
                                     return Ok(res.into_owned());
                                 }
@@ -6,10 +6,16 @@ use futures::stream::FuturesUnordered;
 use futures::{FutureExt, StreamExt};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
-use super::{BoxedClient, ClientError, ClientId, HostResult, OpenRequest};
+use super::{BoxedClient, ClientError, ClientId, ConnectivityEvent, HostResult, OpenRequest};
 
 type HostIncomingMsg = Result<OpenRequest<'static>, ClientError>;
 
+/// Message from the host to one of the combined clients.
+enum HostOutgoingMsg {
+    Response(ClientId, HostResult),
+    Connectivity(ConnectivityEvent),
+}
+
 type ClientEventsFut =
     BoxFuture<'static, (usize, Receiver<HostIncomingMsg>, Option<HostIncomingMsg>)>;
 
@@ -17,7 +23,7 @@ type ClientEventsFut =
 pub struct ClientEventsCombinator<const N: usize> {
     pending_futs: FuturesUnordered<ClientEventsFut>,
     /// receiving end of the different client applications from the node
-    clients: [Sender<(ClientId, HostResult)>; N],
+    clients: [Sender<HostOutgoingMsg>; N],
     /// a map of the individual protocols, external, sending client events ids to an internal list of ids
     external_clients: [HashMap<ClientId, ClientId>; N],
     /// a map of the external id to which protocol it belongs (represented by the index in the array)
@@ -68,38 +74,42 @@ impl<const N: usize> super::ClientEventsProxy for ClientEventsCombinator<N> {
     fn recv(&mut self) -> BoxFuture<'_, Result<OpenRequest<'static>, ClientError>> {
         async {
             let Some((idx, mut rx, res)) = self.pending_futs.next().await else {
-                unreachable!();
+                // every client is gone
+                return Err(ErrorKind::Shutdown.into());
+            };
+            let Some(res) = res else {
+                // the client shut down, it is not listened to anymore
+                tracing::debug!("client proxy #{idx} disconnected");
+                return Err(ErrorKind::TransportProtocolDisconnect.into());
             };
 
-            let res = res
-                .map(|res| {
-                    match res {
-                        Ok(OpenRequest {
-                            client_id: external,
-                            request,
-                            notification_channel,
-                            token,
-                        }) => {
-                            let id = *self.external_clients[idx]
-                                .entry(external)
-                                .or_insert_with(|| {
-                                    // add a new mapped external client id
-                                    let internal = ClientId::next();
-                                    self.internal_clients.insert(internal, (idx, external));
-                                    internal
-                                });
-                            tracing::debug!("received request for proxy #{idx}; internal_id={id}; external_id={external}; req={request}");
-                            Ok(OpenRequest {
-                                client_id: id,
-                                request,
-                                notification_channel,
-                                token,
-                            })
-                        }
-                        err @ Err(_) => err,
-                    }
-                })
-                .unwrap_or_else(|| Err(ErrorKind::TransportProtocolDisconnect.into()));
+            let res = match res {
+                Ok(OpenRequest {
+                    client_id: external,
+                    request,
+                    notification_channel,
+                    token,
+                    attested_contract,
+                }) => {
+                    let id = *self.external_clients[idx]
+                        .entry(external)
+                        .or_insert_with(|| {
+                            // add a new mapped external client id
+                            let internal = ClientId::next();
+                            self.internal_clients.insert(internal, (idx, external));
+                            internal
+                        });
+                    tracing::debug!("received request for proxy #{idx}; internal_id={id}; external_id={external}; req={request}");
+                    Ok(OpenRequest {
+                        client_id: id,
+                        request,
+                        notification_channel,
+                        token,
+                        attested_contract,
+                    })
+                }
+                err @ Err(_) => err,
+            };
 
             self.pending_futs.push(
                 async move {
@@ -125,47 +135,72 @@ impl<const N: usize> super::ClientEventsProxy for ClientEventsCombinator<N> {
                 .get(&internal)
                 .ok_or(ErrorKind::UnknownClient(internal.0))?;
             self.clients[*idx]
-                .send((*external, response))
+                .send(HostOutgoingMsg::Response(*external, response))
                 .await
                 .map_err(|_| ErrorKind::TransportProtocolDisconnect)?;
             Ok(())
         }
         .boxed()
     }
+
+    fn notify_connectivity(
+        &mut self,
+        event: ConnectivityEvent,
+    ) -> BoxFuture<'_, Result<(), ClientError>> {
+        async move {
+            for client in &self.clients {
+                // clients which shut down are not listened to anymore
+                let _ = client.send(HostOutgoingMsg::Connectivity(event)).await;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
 }
 
 async fn client_fn(
     mut client: BoxedClient,
-    mut rx: Receiver<(ClientId, HostResult)>,
+    mut rx: Receiver<HostOutgoingMsg>,
     tx_host: Sender<Result<OpenRequest<'static>, ClientError>>,
 ) {
     loop {
         tokio::select! {
             host_msg = rx.recv() => {
-                if let Some((client_id, response)) = host_msg {
-                    if client.send(client_id, response).await.is_err() {
+                let sent = match host_msg {
+                    Some(HostOutgoingMsg::Response(client_id, response)) => {
+                        client.send(client_id, response).await
+                    }
+                    Some(HostOutgoingMsg::Connectivity(event)) => {
+                        client.notify_connectivity(event).await
+                    }
+                    None => {
+                        tracing::debug!("disconnected host");
                         break;
                     }
-                } else {
-                    tracing::debug!("disconnected host");
+                };
+                if sent.is_err() {
                     break;
                 }
             }
             client_msg = client.recv() => {
                 match client_msg {
-                    Ok(OpenRequest { client_id,  request, notification_channel, token }) => {
+                    Ok(OpenRequest { client_id,  request, notification_channel, token, attested_contract }) => {
                         tracing::debug!("received msg @ combinator from external id {client_id}, msg: {request}");
-                        if tx_host.send(Ok(OpenRequest { client_id,  request, notification_channel, token })).await.is_err() {
+                        if tx_host.send(Ok(OpenRequest { client_id,  request, notification_channel, token, attested_contract })).await.is_err() {
                             break;
                         }
                     }
-                    Err(err) if matches!(err.kind(), ErrorKind::ChannelClosed) =>{
+                    Err(err) if matches!(err.kind(), ErrorKind::ChannelClosed | ErrorKind::Disconnect | ErrorKind::Shutdown) =>{
                         tracing::debug!("disconnected client");
                         let _ = tx_host.send(Err(err)).await;
                         break;
                     }
                     Err(err) => {
-                        panic!("Error of kind: {err} not handled");
+                        // errors of a single request, the client keeps running
+                        tracing::debug!("client error @ combinator: {err}");
+                        if tx_host.send(Err(err)).await.is_err() {
+                            break;
+                        }
                     }
                 }
             }
@@ -223,6 +258,19 @@ mod test {
             }
             .boxed()
         }
+
+        fn notify_connectivity(
+            &mut self,
+            _event: ConnectivityEvent,
+        ) -> BoxFuture<'_, Result<(), ClientError>> {
+            async {
+                self.tx
+                    .send(self.id)
+                    .await
+                    .map_err(|_| ErrorKind::ChannelClosed.into())
+            }
+            .boxed()
+        }
     }
 
     fn setup_proxies() -> ([BoxedClient; 3], Vec<Sender<usize>>, Vec<Receiver<usize>>) {
@@ -319,4 +367,17 @@ mod test {
 
         try_join!(sending, receiving).unwrap();
     }
+
+    #[tokio::test]
+    async fn connectivity_reaches_every_proxy() {
+        let (proxies, _senders, mut receivers) = setup_proxies();
+        let mut combinator = ClientEventsCombinator::new(proxies);
+        combinator
+            .notify_connectivity(ConnectivityEvent::Disconnected)
+            .await
+            .unwrap();
+        for (idx, receiver) in receivers.iter_mut().enumerate() {
+            assert_eq!(receiver.recv().await, Some(idx));
+        }
+    }
 }
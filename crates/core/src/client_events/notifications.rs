@@ -0,0 +1,191 @@
+//! Channel delivering the notifications of a subscription to the client.
+//!
+//! The channel is bounded: once a client falls behind, the oldest pending notification is
+//! dropped to make room for the new one, so a slow client never holds up the executor nor
+//! makes the node buffer an unbounded amount of notifications for it.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::Mutex;
+use tokio::sync::{
+    mpsc::error::{SendError, TryRecvError},
+    Notify,
+};
+
+use super::ClientNotification;
+
+/// Notifications kept for a client before dropping the oldest ones.
+pub const NOTIFICATION_CAPACITY: usize = 64;
+
+struct Shared {
+    items: Mutex<VecDeque<ClientNotification>>,
+    capacity: usize,
+    pending: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+/// Creates a channel keeping up to [`NOTIFICATION_CAPACITY`] notifications.
+pub fn notification_channel() -> (NotificationSender, NotificationReceiver) {
+    notification_channel_with_capacity(NOTIFICATION_CAPACITY)
+}
+
+pub(crate) fn notification_channel_with_capacity(
+    capacity: usize,
+) -> (NotificationSender, NotificationReceiver) {
+    let shared = Arc::new(Shared {
+        items: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        pending: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        NotificationSender(shared.clone()),
+        NotificationReceiver(shared),
+    )
+}
+
+pub struct NotificationSender(Arc<Shared>);
+
+impl NotificationSender {
+    /// Queues the notification, returning the oldest one if it had to be dropped to make room.
+    ///
+    /// Fails if the receiving end is gone.
+    pub fn send(
+        &self,
+        notification: ClientNotification,
+    ) -> Result<Option<ClientNotification>, SendError<ClientNotification>> {
+        if !self.0.receiver_alive.load(Ordering::Acquire) {
+            return Err(SendError(notification));
+        }
+        let dropped = {
+            let mut items = self.0.items.lock();
+            let dropped = (items.len() >= self.0.capacity)
+                .then(|| items.pop_front())
+                .flatten();
+            items.push_back(notification);
+            dropped
+        };
+        self.0.pending.notify_one();
+        Ok(dropped)
+    }
+
+    /// Whether both senders deliver to the same receiver.
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Clone for NotificationSender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::AcqRel);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for NotificationSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.pending.notify_one();
+        }
+    }
+}
+
+impl std::fmt::Debug for NotificationSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotificationSender").finish_non_exhaustive()
+    }
+}
+
+pub struct NotificationReceiver(Arc<Shared>);
+
+impl NotificationReceiver {
+    /// Waits for the next notification, `None` once the pending ones are drained and all the
+    /// senders dropped.
+    pub async fn recv(&mut self) -> Option<ClientNotification> {
+        loop {
+            match self.try_recv() {
+                Ok(notification) => return Some(notification),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.0.pending.notified().await,
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<ClientNotification, TryRecvError> {
+        if let Some(notification) = self.0.items.lock().pop_front() {
+            return Ok(notification);
+        }
+        if self.0.senders.load(Ordering::Acquire) == 0 {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+}
+
+impl Drop for NotificationReceiver {
+    fn drop(&mut self) {
+        self.0.receiver_alive.store(false, Ordering::Release);
+    }
+}
+
+impl std::fmt::Debug for NotificationReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotificationReceiver")
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u8) -> ClientNotification {
+        ClientNotification::ContractEvent {
+            key: freenet_stdlib::prelude::ContractKey::from(
+                freenet_stdlib::prelude::ContractInstanceId::new([1; 32]),
+            ),
+            payload: vec![n],
+        }
+    }
+
+    fn payload(notification: Option<ClientNotification>) -> Option<u8> {
+        match notification? {
+            ClientNotification::ContractEvent { payload, .. } => Some(payload[0]),
+            other => panic!("unexpected notification: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_oldest_when_full() {
+        let (sender, mut receiver) = notification_channel_with_capacity(2);
+        assert!(sender.send(event(1)).unwrap().is_none());
+        assert!(sender.send(event(2)).unwrap().is_none());
+        assert_eq!(payload(sender.send(event(3)).unwrap()), Some(1));
+
+        let other = sender.clone();
+        drop(sender);
+        assert_eq!(payload(receiver.recv().await), Some(2));
+        assert!(other.send(event(4)).unwrap().is_none());
+        drop(other);
+
+        // the pending notifications are still delivered after all the senders are gone
+        assert_eq!(payload(receiver.recv().await), Some(3));
+        assert_eq!(payload(receiver.recv().await), Some(4));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn sending_fails_once_the_receiver_is_gone() {
+        let (sender, receiver) = notification_channel_with_capacity(2);
+        drop(receiver);
+        assert!(sender.send(event(1)).is_err());
+    }
+}
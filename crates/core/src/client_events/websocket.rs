@@ -29,7 +29,10 @@ use crate::{
     util::EncodingProtocol,
 };
 
-use super::{ClientError, ClientEventsProxy, ClientId, HostResult, OpenRequest};
+use super::{
+    notification_channel, ClientError, ClientEventsProxy, ClientId, ClientNotification,
+    ConnectivityEvent, NotificationReceiver, OpenRequest,
+};
 
 mod v1;
 
@@ -51,6 +54,36 @@ pub(crate) struct WebSocketProxy {
 
 const PARALLELISM: usize = 10; // TODO: get this from config, or whatever optimal way
 
+/// Prefix of the frames carrying the events emitted by contracts, followed by the key of the
+/// contract and the payload of the event encoded with bincode. It can't be mistaken for a
+/// host response frame, as a tag or offset it is out of range for either encoding.
+const CONTRACT_EVENT_MAGIC: [u8; 4] = *b"FNEV";
+
+/// Whether the client asked to receive the events emitted by the contracts it is subscribed
+/// to. Clients which don't know about the frames carrying them don't get any.
+#[derive(Clone, Copy)]
+struct ContractEvents(bool);
+
+fn contract_event_frame(key: &ContractKey, payload: &[u8]) -> bincode::Result<Vec<u8>> {
+    let mut frame = CONTRACT_EVENT_MAGIC.to_vec();
+    bincode::serialize_into(&mut frame, &(key, payload))?;
+    Ok(frame)
+}
+
+/// Prefix of the frames carrying the changes in the connectivity of the node with the network,
+/// followed by the event encoded with bincode. Out of range for the host responses as well.
+const CONNECTIVITY_EVENT_MAGIC: [u8; 4] = *b"FNCN";
+
+/// Whether the client asked to be told about the changes in the connectivity of the node.
+#[derive(Clone, Copy)]
+struct ConnectivityEvents(bool);
+
+fn connectivity_event_frame(event: &ConnectivityEvent) -> bincode::Result<Vec<u8>> {
+    let mut frame = CONNECTIVITY_EVENT_MAGIC.to_vec();
+    bincode::serialize_into(&mut frame, event)?;
+    Ok(frame)
+}
+
 impl WebSocketProxy {
     pub fn as_router(server_routing: Router) -> (Self, Router) {
         WebSocketProxy::as_router_v1(server_routing)
@@ -78,7 +111,7 @@ impl WebSocketProxy {
                 let open_req = match &*req {
                     ClientRequest::ContractOp(ContractRequest::Subscribe { key, .. }) => {
                         // intercept subscription messages because they require a callback subscription channel
-                        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+                        let (tx, rx) = notification_channel();
                         if let Some(ch) = self.response_channels.get(&client_id) {
                             ch.send(HostCallbackResult::SubscriptionChannel {
                                 key: *key,
@@ -142,12 +175,16 @@ impl headers::Header for EncodingProtocolExt {
 struct ConnectionInfo {
     auth_token: Option<AuthToken>,
     encoding_protocol: Option<EncodingProtocol>,
+    contract_events: Option<bool>,
+    connectivity_events: Option<bool>,
 }
 
 async fn connection_info(
     Query(ConnectionInfo {
         auth_token: auth_token_q,
         encoding_protocol,
+        contract_events,
+        connectivity_events,
     }): Query<ConnectionInfo>,
     mut req: axum::extract::Request,
     next: axum::middleware::Next,
@@ -200,6 +237,10 @@ async fn connection_info(
     );
     req.extensions_mut().insert(encoding_protoc);
     req.extensions_mut().insert(auth_token);
+    req.extensions_mut()
+        .insert(ContractEvents(contract_events.unwrap_or(false)));
+    req.extensions_mut()
+        .insert(ConnectivityEvents(connectivity_events.unwrap_or(false)));
 
     next.run(req).await
 }
@@ -208,11 +249,22 @@ async fn websocket_commands(
     ws: WebSocketUpgrade,
     Extension(auth_token): Extension<Option<AuthToken>>,
     Extension(encoding_protoc): Extension<EncodingProtocol>,
+    Extension(contract_events): Extension<ContractEvents>,
+    Extension(connectivity_events): Extension<ConnectivityEvents>,
     Extension(rs): Extension<WebSocketRequest>,
 ) -> axum::response::Response {
     let on_upgrade = move |ws: WebSocket| async move {
         tracing::debug!(protoc = ?ws.protocol(), "websocket connection established");
-        if let Err(error) = websocket_interface(rs.clone(), auth_token, encoding_protoc, ws).await {
+        if let Err(error) = websocket_interface(
+            rs.clone(),
+            auth_token,
+            encoding_protoc,
+            contract_events,
+            connectivity_events,
+            ws,
+        )
+        .await
+        {
             tracing::error!("{error}");
         }
     };
@@ -223,11 +275,13 @@ async fn websocket_interface(
     request_sender: WebSocketRequest,
     mut auth_token: Option<AuthToken>,
     encoding_protoc: EncodingProtocol,
+    contract_events: ContractEvents,
+    connectivity_events: ConnectivityEvents,
     ws: WebSocket,
 ) -> anyhow::Result<()> {
     let (mut response_rx, client_id) = new_client_connection(&request_sender).await?;
     let (mut server_sink, mut client_stream) = ws.split();
-    let contract_updates: Arc<Mutex<VecDeque<(_, mpsc::UnboundedReceiver<HostResult>)>>> =
+    let contract_updates: Arc<Mutex<VecDeque<(_, NotificationReceiver)>>> =
         Arc::new(Mutex::new(VecDeque::new()));
     loop {
         let contract_updates_cp = contract_updates.clone();
@@ -280,7 +334,7 @@ async fn websocket_interface(
         };
 
         tokio::select! { biased;
-            msg = async { process_host_response(response_rx.recv().await, client_id, encoding_protoc, &mut server_sink).await } => {
+            msg = async { process_host_response(response_rx.recv().await, client_id, encoding_protoc, connectivity_events, &mut server_sink).await } => {
                 let active_listeners = contract_updates.clone();
                 if let Some(NewSubscription { key, callback }) = msg? {
                     tracing::debug!(cli_id = %client_id, contract = %key, "added new notification listener");
@@ -307,7 +361,21 @@ async fn websocket_interface(
                 }
             }
             response = listeners_task => {
-                let response = response?;
+                let response = match response? {
+                    ClientNotification::Result(response) => response,
+                    ClientNotification::ContractEvent { key, payload } => {
+                        if !contract_events.0 {
+                            tracing::debug!(cli_id = %client_id, contract = %key, "client not taking contract events, dropping");
+                            continue;
+                        }
+                        tracing::debug!(cli_id = %client_id, contract = %key, "sending contract event");
+                        let frame = contract_event_frame(&key, &payload)?;
+                        server_sink.send(Message::Binary(frame)).await.inspect_err(|err| {
+                            tracing::debug!(err = %err, "error sending message to client");
+                        })?;
+                        continue;
+                    }
+                };
                 match &response {
                     Ok(res) => tracing::debug!(response = %res, cli_id = %client_id, "sending notification"),
                     Err(err) => tracing::debug!(response = %err, cli_id = %client_id, "sending notification error"),
@@ -347,7 +415,7 @@ async fn new_client_connection(
 
 struct NewSubscription {
     key: ContractKey,
-    callback: mpsc::UnboundedReceiver<HostResult>,
+    callback: NotificationReceiver,
 }
 
 async fn process_client_request(
@@ -411,6 +479,7 @@ async fn process_host_response(
     msg: Option<HostCallbackResult>,
     client_id: ClientId,
     encoding_protoc: EncodingProtocol,
+    connectivity_events: ConnectivityEvents,
     tx: &mut SplitSink<WebSocket, Message>,
 ) -> anyhow::Result<Option<NewSubscription>> {
     match msg {
@@ -456,6 +525,15 @@ async fn process_host_response(
             tracing::debug!(%cli_id, "new client registered");
             Ok(None)
         }
+        Some(HostCallbackResult::Connectivity { id, event }) => {
+            debug_assert_eq!(id, client_id);
+            if connectivity_events.0 {
+                tracing::debug!(cli_id = %id, ?event, "sending connectivity event");
+                tx.send(Message::Binary(connectivity_event_frame(&event)?))
+                    .await?;
+            }
+            Ok(None)
+        }
         None => {
             let result_error = bincode::serialize(&Err::<HostResponse, ClientError>(
                 ErrorKind::NodeUnavailable.into(),
@@ -512,4 +590,15 @@ impl ClientEventsProxy for WebSocketProxy {
         }
         .boxed()
     }
+
+    fn notify_connectivity(
+        &mut self,
+        event: ConnectivityEvent,
+    ) -> BoxFuture<Result<(), ClientError>> {
+        // connections whose channel is closed are dropped on the next response to them
+        for (id, ch) in &self.response_channels {
+            let _ = ch.send(HostCallbackResult::Connectivity { id: *id, event });
+        }
+        async { Ok(()) }.boxed()
+    }
 }
@@ -17,9 +17,16 @@ use pkcs8::DecodePublicKey;
 use serde::{Deserialize, Serialize};
 use tokio::runtime::Runtime;
 
-use crate::{dev_tool::PeerId, local_node::OperationMode, transport::TransportKeypair};
+use crate::{
+    dev_tool::PeerId,
+    local_node::OperationMode,
+    node::SeedSource,
+    transport::{NetworkId, TransportKeypair, TransportKind},
+};
 
+mod keystore;
 mod secret;
+pub(crate) use keystore::Keystore;
 pub use secret::*;
 
 /// Default maximum number of connections for the peer.
@@ -36,6 +43,45 @@ pub const DEFAULT_RANDOM_PEER_CONN_THRESHOLD: usize = 7;
 /// Default maximum number of hops to live for any operation
 /// (if it applies, e.g. connect requests).
 pub const DEFAULT_MAX_HOPS_TO_LIVE: usize = 10;
+/// Default maximum number of concurrent operations of the same type
+/// (e.g. get or put) a node will keep track of before rejecting new ones.
+pub const DEFAULT_MAX_CONCURRENT_OPS: usize = 1_000;
+/// Default number of peers a get request started at this node is sent to at once.
+pub const DEFAULT_GET_PARALLELISM: usize = 1;
+/// Default size, in bytes, above which messages carrying contract states are split
+/// in chunks for transfer.
+pub const DEFAULT_STATE_CHUNK_THRESHOLD: usize = 512 * 1024;
+/// Default size, in bytes, of the updated states above which only their summary is broadcast
+/// to subscribers, which then request the changes they are missing.
+pub const DEFAULT_UPDATE_SUMMARY_THRESHOLD: usize = 64 * 1024;
+/// Default serialized size, in bytes, below which messages are sent to peers uncompressed.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+/// Default reputation score below which peers are disconnected. Roughly two invalid messages,
+/// or eight timeouts, in a short span of time.
+pub const DEFAULT_REPUTATION_THRESHOLD: f64 = -2.0;
+/// Default time during which a peer this node disconnected from is not accepted again.
+pub const DEFAULT_CONNECTION_COOLDOWN: Duration = Duration::from_secs(120);
+/// Default time between the pings sent to each neighbour to check it is still alive.
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Default number of pings in a row a neighbour has to miss to be considered dead.
+pub const DEFAULT_KEEPALIVE_FAILURE_THRESHOLD: u32 = 3;
+/// Default time without any live neighbour after which a node joins the network again.
+pub const DEFAULT_DISCONNECTED_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default number of standby connections kept per distance band, none.
+pub const DEFAULT_STANDBY_CONNECTIONS: usize = 0;
+/// Default max upstream bandwidth, in bytes per second, of all the peer connections together.
+pub const DEFAULT_UPSTREAM_BANDWIDTH_LIMIT: usize = 1024 * 1024 * 10; // 10 MB/s
+/// Default max fraction of the upstream bandwidth a single peer may use, not capped.
+pub const DEFAULT_PEER_BANDWIDTH_SHARE: f64 = 1.0;
+/// Default bytes of contract states persisted, beyond which the least worth keeping are
+/// evicted.
+pub const DEFAULT_STATE_STORAGE_QUOTA: u64 = 1024 * 1024 * 1024;
+/// Default number of executors the calls to different contracts are executed concurrently on.
+pub const DEFAULT_CONTRACT_EXECUTORS: usize = 4;
+/// Default transports, plain UDP only.
+pub const DEFAULT_TRANSPORTS: &[TransportKind] = &[TransportKind::Udp];
+/// Number of threads dedicated to verifying the signatures of inbound messages.
+pub(crate) const DEFAULT_VERIFIER_THREADS: usize = 2;
 pub(crate) const OPERATION_TTL: Duration = Duration::from_secs(60);
 
 // Initialize the executor once.
@@ -51,6 +97,11 @@ pub struct ConfigArgs {
     #[clap(value_enum, env = "MODE")]
     pub mode: Option<OperationMode>,
 
+    /// Node profile preset, determines the defaults for connection, bandwidth
+    /// and operation limits. Default is desktop.
+    #[clap(long, value_enum, env = "NODE_PROFILE")]
+    pub profile: Option<NodeProfile>,
+
     #[clap(flatten)]
     pub ws_api: WebsocketApiArgs,
 
@@ -69,18 +120,29 @@ pub struct ConfigArgs {
     /// An arbitrary identifier for the node, mostly for debugging or testing purposes.
     #[clap(long)]
     pub id: Option<String>,
+
+    /// DNS names whose TXT records list gateways to join the network through.
+    #[clap(long = "seed-dns", env = "SEED_DNS", value_delimiter = ',')]
+    pub seed_dns: Vec<String>,
+
+    /// URLs of documents listing gateways to join the network through, served over HTTPS.
+    #[clap(long = "seed-url", env = "SEED_URL", value_delimiter = ',')]
+    pub seed_urls: Vec<String>,
 }
 
 impl Default for ConfigArgs {
     fn default() -> Self {
         Self {
             mode: Some(OperationMode::Network),
+            profile: None,
             network_listener: NetworkArgs {
                 address: Some(default_address()),
                 network_port: Some(default_network_port()),
                 public_address: None,
                 public_port: None,
                 is_gateway: false,
+                light_client: false,
+                network_name: None,
             },
             ws_api: WebsocketApiArgs {
                 address: Some(default_address()),
@@ -90,12 +152,14 @@ impl Default for ConfigArgs {
             log_level: Some(tracing::log::LevelFilter::Info),
             config_paths: Default::default(),
             id: None,
+            seed_dns: Vec::new(),
+            seed_urls: Vec::new(),
         }
     }
 }
 
 impl ConfigArgs {
-    fn read_config(dir: &PathBuf) -> std::io::Result<Option<Config>> {
+    pub(crate) fn read_config(dir: &PathBuf) -> std::io::Result<Option<Config>> {
         if !dir.exists() {
             return Ok(None);
         }
@@ -201,9 +265,21 @@ impl ConfigArgs {
         let should_persist = cfg.is_none();
 
         // merge the configuration from the file with the command line arguments
+        let mut limits = LimitsConfig::default();
+        let mut seeds: Vec<_> = self
+            .seed_dns
+            .drain(..)
+            .map(SeedSource::Dns)
+            .chain(self.seed_urls.drain(..).map(SeedSource::Https))
+            .collect();
         if let Some(cfg) = cfg {
+            limits = cfg.limits;
+            if seeds.is_empty() {
+                seeds = cfg.seeds;
+            }
             self.secrets.merge(cfg.secrets);
             self.mode.get_or_insert(cfg.mode);
+            self.profile.get_or_insert(cfg.profile);
             self.ws_api.address.get_or_insert(cfg.ws_api.address);
             self.ws_api.ws_api_port.get_or_insert(cfg.ws_api.port);
             self.log_level.get_or_insert(cfg.log_level);
@@ -266,6 +342,7 @@ impl ConfigArgs {
 
         let this = Config {
             mode,
+            profile: self.profile.unwrap_or_default(),
             peer_id,
             network_api: NetworkApiConfig {
                 address: self.network_listener.address.unwrap_or_else(|| match mode {
@@ -278,6 +355,12 @@ impl ConfigArgs {
                     .unwrap_or(default_network_port()),
                 public_address: self.network_listener.public_address,
                 public_port: self.network_listener.public_port,
+                network_id: self
+                    .network_listener
+                    .network_name
+                    .as_deref()
+                    .map(NetworkId::from_name)
+                    .unwrap_or_default(),
             },
             ws_api: WebsocketApiConfig {
                 address: self.ws_api.address.unwrap_or_else(|| match mode {
@@ -291,9 +374,12 @@ impl ConfigArgs {
             },
             secrets,
             log_level: self.log_level.unwrap_or(tracing::log::LevelFilter::Info),
+            limits,
+            seeds,
             config_paths: Arc::new(config_paths),
             gateways: gateways.gateways,
             is_gateway: self.network_listener.is_gateway,
+            is_light_client: self.network_listener.light_client,
         };
 
         fs::create_dir_all(this.config_dir())?;
@@ -359,6 +445,9 @@ mod serde_log_level_filter {
 pub struct Config {
     /// Node operation mode.
     pub mode: OperationMode,
+    /// Node profile preset.
+    #[serde(default)]
+    pub profile: NodeProfile,
     #[serde(flatten)]
     pub network_api: NetworkApiConfig,
     #[serde(flatten)]
@@ -367,6 +456,12 @@ pub struct Config {
     pub secrets: Secrets,
     #[serde(with = "serde_log_level_filter")]
     pub log_level: tracing::log::LevelFilter,
+    /// Limits which can be changed while the node runs.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Seed lists the gateways to join the network through are discovered from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seeds: Vec<SeedSource>,
     #[serde(flatten)]
     config_paths: Arc<ConfigPaths>,
     #[serde(skip)]
@@ -374,6 +469,8 @@ pub struct Config {
     #[serde(skip)]
     pub(crate) gateways: Vec<GatewayConfig>,
     pub(crate) is_gateway: bool,
+    #[serde(default)]
+    pub(crate) is_light_client: bool,
 }
 
 impl Config {
@@ -386,6 +483,56 @@ impl Config {
     }
 }
 
+/// Limits of the node which are applied again when the configuration file changes, without
+/// restarting the node. Unset values are left to the node profile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    #[serde(rename = "min-connections", skip_serializing_if = "Option::is_none")]
+    pub min_connections: Option<usize>,
+    #[serde(rename = "max-connections", skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+    /// Max bytes per second sent to all peers together.
+    #[serde(
+        rename = "upstream-bandwidth-limit",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub upstream_bandwidth_limit: Option<usize>,
+    /// Max fraction of the upstream bandwidth limit a single peer may use.
+    #[serde(
+        rename = "peer-bandwidth-share",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub peer_bandwidth_share: Option<f64>,
+}
+
+/// Presets for the node configuration, tailored to the environment the node runs in.
+///
+/// Any explicitly set value in the node configuration takes precedence over the preset.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeProfile {
+    /// A publicly reachable node other peers use to join the network.
+    Gateway,
+    /// A regular node running in a personal computer.
+    #[default]
+    Desktop,
+    /// A node running in a device with limited resources (bandwidth, memory...).
+    Constrained,
+    /// A node running as part of a simulated network, mainly for testing.
+    Simulation,
+}
+
+impl std::fmt::Display for NodeProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeProfile::Gateway => write!(f, "gateway"),
+            NodeProfile::Desktop => write!(f, "desktop"),
+            NodeProfile::Constrained => write!(f, "constrained"),
+            NodeProfile::Simulation => write!(f, "simulation"),
+        }
+    }
+}
+
 #[derive(clap::Parser, Debug, Default, Copy, Clone, Serialize, Deserialize)]
 pub struct NetworkArgs {
     /// Address to bind to for the network event listener, default is 0.0.0.0
@@ -418,6 +565,19 @@ pub struct NetworkArgs {
     /// If the node is a gateway, it will be able to accept connections from other nodes.
     #[arg(long)]
     pub is_gateway: bool,
+
+    /// Whether the node is a light client, for devices with limited resources.
+    /// A light client routes requests and serves its clients, but only keeps the states of
+    /// the contracts in memory, so it is never counted among the holders of a contract.
+    #[arg(long)]
+    #[serde(default)]
+    pub light_client: bool,
+
+    /// Name of the network to join, peers belonging to a different network are refused.
+    /// Default is the main network.
+    #[arg(long, env = "NETWORK_NAME")]
+    #[serde(rename = "network-name", skip_serializing_if = "Option::is_none")]
+    pub network_name: Option<String>,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -438,6 +598,10 @@ pub struct NetworkApiConfig {
 
     #[serde(rename = "public_port", skip_serializing_if = "Option::is_none")]
     pub public_port: Option<u16>,
+
+    /// Identifier of the network this node belongs to.
+    #[serde(default, rename = "network-id")]
+    pub network_id: NetworkId,
 }
 
 #[inline]
@@ -693,6 +857,14 @@ impl ConfigPaths {
         self
     }
 
+    pub fn client_storage_dir(&self, mode: OperationMode) -> PathBuf {
+        let client_storage_dir = self.data_dir.join("client_storage");
+        match mode {
+            OperationMode::Local => client_storage_dir.join("local"),
+            OperationMode::Network => client_storage_dir,
+        }
+    }
+
     pub fn event_log(&self, mode: OperationMode) -> PathBuf {
         match mode {
             OperationMode::Local => {
@@ -767,6 +939,11 @@ impl Config {
         self.config_paths.contracts_dir(self.mode)
     }
 
+    /// Directory the compiled contract modules are cached in.
+    pub fn compiled_modules_dir(&self) -> PathBuf {
+        self.contracts_dir().join("compiled")
+    }
+
     pub fn delegates_dir(&self) -> PathBuf {
         self.config_paths.delegates_dir(self.mode)
     }
@@ -775,6 +952,10 @@ impl Config {
         self.config_paths.secrets_dir(self.mode)
     }
 
+    pub fn client_storage_dir(&self) -> PathBuf {
+        self.config_paths.client_storage_dir(self.mode)
+    }
+
     pub fn event_log(&self) -> PathBuf {
         self.config_paths.event_log(self.mode)
     }
@@ -875,6 +1056,19 @@ pub fn set_logger(level: Option<tracing::level_filters::LevelFilter>, endpoint:
     }
 }
 
+/// Changes the level of the logs while the node runs, returns false if logging is not set up.
+pub(crate) fn set_log_level(level: tracing::level_filters::LevelFilter) -> bool {
+    #[cfg(feature = "trace")]
+    {
+        crate::tracing::tracer::set_log_level(level)
+    }
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = level;
+        false
+    }
+}
+
 async fn load_gateways_from_index(url: &str, pub_keys_dir: &Path) -> anyhow::Result<Gateways> {
     let response = reqwest::get(url).await?.error_for_status()?.text().await?;
     let mut gateways: Gateways = toml::from_str(&response)?;
@@ -933,6 +1127,24 @@ mod tests {
         let _: Config = toml::from_str(&serialized).unwrap();
     }
 
+    #[tokio::test]
+    async fn seed_sources_from_args() {
+        let args = ConfigArgs {
+            seed_dns: vec!["seeds.freenet.org".into()],
+            seed_urls: vec!["https://freenet.org/seeds".into()],
+            ..Default::default()
+        };
+        let cfg = args.build().await.unwrap();
+        let seeds = [
+            SeedSource::Dns("seeds.freenet.org".into()),
+            SeedSource::Https("https://freenet.org/seeds".into()),
+        ];
+        assert_eq!(cfg.seeds, seeds);
+        let serialized = toml::to_string(&cfg).unwrap();
+        let cfg: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(cfg.seeds, seeds);
+    }
+
     #[tokio::test]
     async fn test_load_gateways_from_index() {
         let server = Server::run();
@@ -0,0 +1,121 @@
+//! Persistence of the identity keypair of the node.
+//!
+//! The transport keypair identifies the node in the network, so a node generating a new one on
+//! every start shows up as a different peer each time. The keystore keeps the keypair in a
+//! PKCS#8 PEM file, encrypted with a passphrase if one is given, and generates it on the first
+//! start. If a rotation period is set, keys older than it are replaced on start, keeping the
+//! previous one next to the new one.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use pkcs8::DecodePrivateKey;
+
+use crate::transport::TransportKeypair;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Keystore {
+    pub path: PathBuf,
+    /// Passphrase the key is encrypted with at rest, stored unencrypted otherwise.
+    pub passphrase: Option<String>,
+    /// Age after which the key is replaced by a new one.
+    pub rotate_after: Option<Duration>,
+}
+
+impl Keystore {
+    /// Loads the keypair, generating and storing a new one if there is none yet or it is due
+    /// for rotation.
+    pub fn load_or_generate(&self) -> anyhow::Result<TransportKeypair> {
+        let created = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                tracing::info!(path = %self.path.display(), "Generating the node identity key");
+                return self.generate();
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let age = created.elapsed().unwrap_or_default();
+        if self.rotate_after.is_some_and(|period| age >= period) {
+            let previous = previous_key_path(&self.path);
+            tracing::info!(
+                path = %self.path.display(),
+                previous = %previous.display(),
+                "Rotating the node identity key"
+            );
+            fs::rename(&self.path, previous)?;
+            return self.generate();
+        }
+        self.load()
+    }
+
+    fn load(&self) -> anyhow::Result<TransportKeypair> {
+        let pem = fs::read_to_string(&self.path)?;
+        let key = match &self.passphrase {
+            Some(passphrase) => rsa::RsaPrivateKey::from_pkcs8_encrypted_pem(&pem, passphrase),
+            None => rsa::RsaPrivateKey::from_pkcs8_pem(&pem),
+        }
+        .with_context(|| format!("failed to read the key at {}", self.path.display()))?;
+        Ok(TransportKeypair::from_private_key(key))
+    }
+
+    fn generate(&self) -> anyhow::Result<TransportKeypair> {
+        let keypair = TransportKeypair::new();
+        let pem = match &self.passphrase {
+            Some(passphrase) => keypair.secret().to_encrypted_pkcs8_pem(passphrase)?,
+            None => keypair.secret().to_pkcs8_pem()?,
+        };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&pem))
+            .with_context(|| format!("failed to store the key at {}", self.path.display()))?;
+        Ok(keypair)
+    }
+}
+
+fn previous_key_path(path: &Path) -> PathBuf {
+    let mut previous = path.as_os_str().to_owned();
+    previous.push(".previous");
+    PathBuf::from(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_identity_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut keystore = Keystore {
+            path: dir.path().join("identity.pem"),
+            passphrase: Some("correct horse".into()),
+            rotate_after: None,
+        };
+        let generated = keystore.load_or_generate().unwrap();
+        let loaded = keystore.load_or_generate().unwrap();
+        assert_eq!(generated.public(), loaded.public());
+
+        keystore.passphrase = Some("wrong horse".into());
+        assert!(keystore.load_or_generate().is_err());
+
+        keystore.passphrase = Some("correct horse".into());
+        keystore.rotate_after = Some(Duration::ZERO);
+        let rotated = keystore.load_or_generate().unwrap();
+        assert_ne!(rotated.public(), generated.public());
+        assert!(previous_key_path(&keystore.path).exists());
+    }
+}
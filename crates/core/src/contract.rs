@@ -3,10 +3,13 @@
 //! Internally uses the wasm_runtime module to execute contract and/or delegate instructions.
 
 use either::Either;
-use freenet_stdlib::prelude::*;
+use freenet_stdlib::{client_api::ContractResponse, prelude::*};
+
+use crate::client_events::ClientNotification;
 
 mod executor;
 mod handler;
+mod pool;
 pub mod storages;
 
 pub(crate) use executor::{
@@ -15,142 +18,200 @@ pub(crate) use executor::{
 };
 pub(crate) use handler::{
     client_responses_channel, contract_handler_channel, in_memory::MemoryContractHandler,
-    ClientResponsesReceiver, ClientResponsesSender, ContractHandler, ContractHandlerChannel,
-    ContractHandlerEvent, NetworkContractHandler, SenderHalve, StoreResponse, WaitingResolution,
+    CachedContract, ClientResponsesReceiver, ClientResponsesSender, ContractHandler,
+    ContractHandlerChannel, ContractHandlerEvent, NetworkContractHandler, SenderHalve,
+    StoreResponse, WaitingResolution,
 };
 
-pub use executor::{Executor, ExecutorError, OperationMode};
+pub use executor::{CodeCollectionConfig, Executor, ExecutorError, OperationMode};
 
 use executor::ContractExecutor;
+use pool::ExecutorPool;
 use tracing::Instrument;
 
-pub(crate) async fn contract_handling<CH>(mut contract_handler: CH) -> Result<(), ContractError>
+pub(crate) async fn contract_handling<CH>(contract_handler: CH) -> Result<(), ContractError>
 where
     CH: ContractHandler + Send + 'static,
 {
+    let (mut channel, executors) = contract_handler.into_parts();
+    let mut pool = ExecutorPool::start(executors);
     loop {
-        let (id, event) = contract_handler.channel().recv_from_sender().await?;
-        tracing::debug!(%event, "Got contract handling event");
-        match event {
-            ContractHandlerEvent::GetQuery {
-                key,
-                return_contract_code,
-            } => {
-                match contract_handler
-                    .executor()
-                    .fetch_contract(key, return_contract_code)
-                    .instrument(tracing::info_span!("fetch_contract", %key, %return_contract_code))
-                    .await
-                {
-                    Ok((state, contract)) => {
-                        tracing::debug!(with_contract_code = %return_contract_code, has_contract = %contract.is_some(), "Fetched contract {key}");
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Ok(StoreResponse { state, contract }),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
-                    }
-                    Err(err) => {
-                        tracing::warn!("Error while executing get contract query: {err}");
-                        contract_handler
-                            .channel()
-                            .send_to_sender(
-                                id,
-                                ContractHandlerEvent::GetResponse {
-                                    key,
-                                    response: Err(err),
-                                },
-                            )
-                            .await
-                            .map_err(|error| {
-                                tracing::debug!(%error, "shutting down contract handler");
-                                error
-                            })?;
-                    }
+        tokio::select! {
+            event = channel.recv_from_sender() => {
+                let (id, event) = event?;
+                tracing::debug!(%event, "Got contract handling event");
+                if let Err(error) = pool.execute(id, event).await {
+                    tracing::error!(%error, "Failed to execute contract handling event");
+                    channel.abandon(id);
                 }
             }
-            ContractHandlerEvent::PutQuery {
-                key,
-                state,
-                related_contracts,
-                contract,
-            } => {
-                let put_result = contract_handler
-                    .executor()
-                    .upsert_contract_state(key, Either::Left(state), related_contracts, contract)
-                    .instrument(tracing::info_span!("upsert_contract_state", %key))
-                    .await;
-
-                contract_handler
-                    .channel()
-                    .send_to_sender(
-                        id,
-                        ContractHandlerEvent::PutResponse {
-                            new_value: put_result.map_err(Into::into),
-                        },
-                    )
-                    .await
-                    .map_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                        error
-                    })?;
+            Some((id, response)) = pool.next_response() => {
+                channel.send_to_sender(id, response).await.map_err(|error| {
+                    tracing::debug!(%error, "shutting down contract handler");
+                    error
+                })?;
             }
-            ContractHandlerEvent::UpdateQuery {
-                key,
-                data,
-                related_contracts,
-            } => {
-                let update_value: Either<WrappedState, StateDelta<'static>> = match data {
-                    freenet_stdlib::prelude::UpdateData::State(state) => {
-                        Either::Left(WrappedState::from(state.into_bytes()))
-                    }
-                    freenet_stdlib::prelude::UpdateData::Delta(delta) => Either::Right(delta),
-                    _ => unreachable!(),
-                };
-                let update_result = contract_handler
-                    .executor()
-                    .upsert_contract_state(key, update_value, related_contracts, None)
-                    .instrument(tracing::info_span!("upsert_contract_state", %key))
-                    .await;
+        }
+    }
+}
 
-                contract_handler
-                    .channel()
-                    .send_to_sender(
-                        id,
-                        ContractHandlerEvent::UpdateResponse {
-                            new_value: update_result.map_err(Into::into),
-                        },
-                    )
+/// Executes the call to a contract, returning the response to send back, if any.
+async fn execute<E: ContractExecutor>(
+    executor: &mut E,
+    event: ContractHandlerEvent,
+) -> Option<ContractHandlerEvent> {
+    match event {
+        ContractHandlerEvent::GetQuery {
+            key,
+            return_contract_code,
+        } => {
+            let response = executor
+                .fetch_contract(key, return_contract_code)
+                .instrument(tracing::info_span!("fetch_contract", %key, %return_contract_code))
+                .await
+                .map(|(state, contract)| {
+                    tracing::debug!(with_contract_code = %return_contract_code, has_contract = %contract.is_some(), "Fetched contract {key}");
+                    StoreResponse { state, contract }
+                })
+                .inspect_err(|err| {
+                    tracing::warn!("Error while executing get contract query: {err}");
+                });
+            Some(ContractHandlerEvent::GetResponse { key, response })
+        }
+        ContractHandlerEvent::PutQuery {
+            key,
+            state,
+            related_contracts,
+            contract,
+        } => {
+            let put_result = executor
+                .upsert_contract_state(key, Either::Left(state), related_contracts, contract)
+                .instrument(tracing::info_span!("upsert_contract_state", %key))
+                .await;
+            Some(ContractHandlerEvent::PutResponse {
+                new_value: put_result.map_err(Into::into),
+            })
+        }
+        ContractHandlerEvent::UpdateQuery {
+            key,
+            data,
+            related_contracts,
+        } => {
+            let update_value: Either<WrappedState, StateDelta<'static>> = match data {
+                freenet_stdlib::prelude::UpdateData::State(state) => {
+                    Either::Left(WrappedState::from(state.into_bytes()))
+                }
+                freenet_stdlib::prelude::UpdateData::Delta(delta) => Either::Right(delta),
+                _ => unreachable!(),
+            };
+            let update_result = executor
+                .upsert_contract_state(key, update_value, related_contracts, None)
+                .instrument(tracing::info_span!("upsert_contract_state", %key))
+                .await;
+            Some(ContractHandlerEvent::UpdateResponse {
+                new_value: update_result.map_err(Into::into),
+            })
+        }
+        ContractHandlerEvent::RegisterSubscriberListener {
+            key,
+            client_id,
+            summary,
+            subscriber_listener,
+        } => {
+            let registered = executor
+                .register_contract_notifier(
+                    key,
+                    client_id,
+                    subscriber_listener.clone(),
+                    summary.clone(),
+                )
+                .inspect_err(|err| {
+                    tracing::warn!("Error while registering subscriber listener: {err}");
+                });
+            // a client resuming a subscription gets all the changes since its last known summary,
+            // if there are any
+            if let (Ok(()), Some(summary)) = (registered, summary) {
+                let unchanged = executor
+                    .summarize_contract_state(key)
                     .await
-                    .map_err(|error| {
-                        tracing::debug!(%error, "shutting down contract handler");
-                        error
-                    })?;
-            }
-            ContractHandlerEvent::RegisterSubscriberListener {
-                key,
-                client_id,
-                summary,
-                subscriber_listener,
-            } => {
-                let _ = contract_handler
-                    .executor()
-                    .register_contract_notifier(key, client_id, subscriber_listener, summary)
-                    .inspect_err(|err| {
-                        tracing::warn!("Error while registering subscriber listener: {err}");
-                    });
+                    .is_ok_and(|current| current == summary);
+                if unchanged {
+                    return None;
+                }
+                match executor.state_delta_since(key, summary).await {
+                    Ok(update) => {
+                        let _ = subscriber_listener.send(ClientNotification::Result(Ok(
+                            ContractResponse::UpdateNotification { key, update }.into(),
+                        )));
+                    }
+                    Err(err) => {
+                        tracing::debug!(%key, "No local state to catch up subscriber: {err}");
+                    }
+                }
             }
-            _ => unreachable!(),
+            None
+        }
+        ContractHandlerEvent::SummaryQuery { key } => {
+            let summary = executor
+                .summarize_contract_state(key)
+                .instrument(tracing::info_span!("summarize_contract_state", %key))
+                .await;
+            Some(ContractHandlerEvent::SummaryResponse { key, summary })
+        }
+        ContractHandlerEvent::DeltaQuery { key, summary } => {
+            let delta = executor
+                .state_delta_since(key, summary)
+                .instrument(tracing::info_span!("state_delta_since", %key))
+                .await;
+            Some(ContractHandlerEvent::DeltaResponse { key, delta })
+        }
+        ContractHandlerEvent::CachedContractsQuery => {
+            let contracts = executor
+                .cached_contracts()
+                .instrument(tracing::info_span!("cached_contracts"))
+                .await;
+            Some(ContractHandlerEvent::CachedContractsResponse { contracts })
+        }
+        ContractHandlerEvent::IntegrityQuery { key } => {
+            let intact = executor
+                .verify_state(key)
+                .instrument(tracing::info_span!("verify_state", %key))
+                .await;
+            Some(ContractHandlerEvent::IntegrityResponse { key, intact })
+        }
+        ContractHandlerEvent::MigrateQuery {
+            from,
+            migration_delegate,
+            delegate_params,
+            attested_contract,
+        } => {
+            let new_state = executor
+                .migrate_contract(
+                    from,
+                    migration_delegate,
+                    delegate_params,
+                    attested_contract.as_ref(),
+                )
+                .instrument(tracing::info_span!("migrate_contract", %from))
+                .await;
+            Some(ContractHandlerEvent::MigrateResponse { from, new_state })
+        }
+        ContractHandlerEvent::DelegateQuery {
+            req,
+            attested_contract,
+        } => {
+            let key = req.key().clone();
+            let response = executor
+                .execute_delegate_request(req, attested_contract.as_ref())
+                .instrument(tracing::info_span!("execute_delegate_request", %key))
+                .await;
+            Some(ContractHandlerEvent::DelegateResponse { key, response })
+        }
+        ContractHandlerEvent::DelegateStatsQuery { key } => {
+            let stats = executor.delegate_stats(&key);
+            Some(ContractHandlerEvent::DelegateStatsResponse { key, stats })
         }
+        _ => unreachable!(),
     }
 }
 
@@ -158,6 +219,10 @@ where
 pub(crate) enum ContractError {
     #[error("handler channel dropped")]
     ChannelDropped(Box<ContractHandlerEvent>),
+    #[error("unexpected event for the contract handler: {0}")]
+    UnexpectedEvent(Box<ContractHandlerEvent>),
+    #[error("contract executor stopped")]
+    ExecutorStopped,
     #[error("contract {0} not found in storage")]
     ContractNotFound(ContractKey),
     #[error("{0}")]
@@ -1,48 +1,58 @@
 //! Contract executor.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::future::Future;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use blake3::traits::digest::generic_array::GenericArray;
+use dashmap::DashMap;
 use either::Either;
 use freenet_stdlib::client_api::{
-    ClientError as WsClientError, ClientRequest, ContractError as StdContractError,
-    ContractRequest, ContractResponse, DelegateError as StdDelegateError, DelegateRequest,
+    ClientRequest, ContractError as StdContractError, ContractRequest, ContractResponse,
+    DelegateError as StdDelegateError, DelegateRequest,
     HostResponse::{self, DelegateResponse},
     RequestError,
 };
 use freenet_stdlib::prelude::*;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self};
 
-use crate::config::Config;
+use crate::config::{Config, OPERATION_TTL};
 use crate::message::Transaction;
-use crate::node::OpManager;
+use crate::node::{NodeLifecycleEvent, OpManager};
 use crate::operations::get::GetResult;
 use crate::operations::{OpEnum, OpError};
+use crate::util::lru::LruCache;
 use crate::wasm_runtime::{
-    ContractRuntimeInterface, ContractStore, DelegateRuntimeInterface, DelegateStore, Runtime,
-    SecretsStore, StateStore, StateStoreError,
+    ContractExecError, ContractImports, ContractLimits, ContractRejection,
+    ContractRuntimeInterface, ContractStore, DelegateExecStats, DelegateLimits,
+    DelegateRuntimeInterface, DelegateStore, ModuleCache, Runtime, RuntimeResult, SecretsStore,
+    StateJournalLimits, StateQuota, StateStore, StateStoreError, StoredState,
+    DEFAULT_MODULE_CACHE_SIZE,
 };
 use crate::{
-    client_events::{ClientId, HostResult},
+    client_events::{ClientId, ClientNotification, NotificationSender},
     operations::{self, Operation},
 };
 
-use super::storages::Storage;
+use super::handler::CachedContract;
+use super::pool::Shard;
+use super::storages::{Storage, StorageBackend};
+use validation_cache::ValidationCache;
 
 pub(super) mod mock_runtime;
 pub(super) mod runtime;
+mod validation_cache;
 
 #[derive(Debug)]
 pub struct ExecutorError(Either<Box<RequestError>, anyhow::Error>);
 
 enum InnerOpError {
     Upsert(ContractKey),
+    Validate(ContractKey),
     Delegate(DelegateKey),
 }
 
@@ -62,6 +72,22 @@ impl ExecutorError {
         Self(Either::Left(Box::new(error.into())))
     }
 
+    /// Attempted to change the state of an immutable contract.
+    fn immutable_contract(key: ContractKey) -> Self {
+        Self::request(StdContractError::Update {
+            key,
+            cause: "the contract is immutable, its state can't be updated".into(),
+        })
+    }
+
+    /// The state an update resulted in was found invalid, so the update was rejected.
+    fn invalid_update(key: ContractKey) -> Self {
+        Self::request(StdContractError::Update {
+            key,
+            cause: "the resulting state is invalid, the update was rejected".into(),
+        })
+    }
+
     fn execution(
         outer_error: crate::wasm_runtime::ContractError,
         op: Option<InnerOpError>,
@@ -69,6 +95,57 @@ impl ExecutorError {
         use crate::wasm_runtime::RuntimeInnerError;
         let error = outer_error.deref();
 
+        // provisional: rejections reach clients as the cause of the error, until the stdlib
+        // has a typed variant to carry them
+        if let RuntimeInnerError::ContractExecError(ContractExecError::ContractError(e)) = error {
+            if let Some(rejection) = ContractRejection::from_contract_error(e) {
+                if let ContractRejection::MissingRelated(key) = rejection {
+                    return ExecutorError::request(StdContractError::MissingRelated { key });
+                }
+                match &op {
+                    Some(InnerOpError::Upsert(key)) => {
+                        return ExecutorError::request(StdContractError::Update {
+                            key: *key,
+                            cause: rejection.to_string().into(),
+                        })
+                    }
+                    Some(InnerOpError::Validate(key)) => {
+                        return ExecutorError::request(StdContractError::Put {
+                            key: *key,
+                            cause: rejection.to_string().into(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let RuntimeInnerError::ContractExecError(
+            e @ (ContractExecError::OutOfFuel { .. }
+            | ContractExecError::MemoryLimitExceeded { .. }
+            | ContractExecError::ForbiddenImport { .. }
+            | ContractExecError::UnsupportedAbi { .. }
+            | ContractExecError::CodeRejected { .. }),
+        ) = error
+        {
+            // the update or the new state are rejected, as the contract could not check them
+            match &op {
+                Some(InnerOpError::Upsert(key)) => {
+                    return ExecutorError::request(StdContractError::Update {
+                        key: *key,
+                        cause: e.to_string().into(),
+                    })
+                }
+                Some(InnerOpError::Validate(key)) => {
+                    return ExecutorError::request(StdContractError::Put {
+                        key: *key,
+                        cause: e.to_string().into(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
         if let RuntimeInnerError::ContractExecError(e) = error {
             if let Some(InnerOpError::Upsert(key)) = &op {
                 return ExecutorError::request(StdContractError::update_exec_error(*key, e));
@@ -125,6 +202,14 @@ impl ExecutorError {
         matches!(self.0, Either::Left(_))
     }
 
+    /// The error to report back to the client, if this is a request error.
+    pub(crate) fn request_error(&self) -> Option<&RequestError> {
+        match &self.0 {
+            Either::Left(err) => Some(err),
+            Either::Right(_) => None,
+        }
+    }
+
     pub fn unwrap_request(self) -> RequestError {
         match self.0 {
             Either::Left(err) => *err,
@@ -174,6 +259,33 @@ impl Display for OperationMode {
     }
 }
 
+/// Requests the executor can have waiting to be picked up by the event loop, further ones are
+/// rejected until it catches up.
+const PENDING_REQUESTS: usize = 32;
+/// Operations an executor can be waiting for the result of, further requests are rejected
+/// until some complete. Only the results of these are sent back, so no more than these can be
+/// waiting to be picked up by the executor.
+const PENDING_RESULTS: usize = 64;
+
+/// How often the contract code no longer referenced is collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodeCollectionConfig {
+    /// Interval between the collections.
+    pub interval: Duration,
+    /// Time contract code must stay unreferenced for before it is removed, so code stored
+    /// ahead of the state of its contract is not lost.
+    pub grace_period: Duration,
+}
+
+impl Default for CodeCollectionConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            grace_period: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
 pub struct ExecutorToEventLoopChannel<End: sealed::ChannelHalve> {
     op_manager: Arc<OpManager>,
     end: End,
@@ -185,25 +297,19 @@ pub(crate) fn executor_channel(
     ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
     ExecutorToEventLoopChannel<ExecutorHalve>,
 ) {
-    // todo: use sensible values for channel buf sizes based on number concurrent tasks running
-    // when we are able to suspend execution of a request while waiting for a callback
-    let (waiting_for_op_tx, waiting_for_op_rx) = mpsc::channel(1);
-    let (response_for_tx, response_for_rx) = mpsc::channel(1);
+    let (waiting_for_op_tx, waiting_for_op_rx) = mpsc::channel(PENDING_REQUESTS);
+    let routes = Routes::default();
 
     let listener_halve = ExecutorToEventLoopChannel {
         op_manager: op_manager.clone(),
         end: NetworkEventListenerHalve {
             waiting_for_op_rx,
-            response_for_tx,
+            routes: routes.clone(),
         },
     };
     let sender_halve = ExecutorToEventLoopChannel {
         op_manager: op_manager.clone(),
-        end: ExecutorHalve {
-            waiting_for_op_tx,
-            response_for_rx,
-            completed: HashMap::default(),
-        },
+        end: ExecutorHalve::new(waiting_for_op_tx, routes),
     };
     (listener_halve, sender_halve)
 }
@@ -224,39 +330,81 @@ impl ExecutorToEventLoopChannel<ExecutorHalve> {
         T: ComposeNetworkMessage<Op>,
         Op: Operation + Send + 'static,
     {
+        if self.end.awaiting.len() >= PENDING_RESULTS {
+            self.op_manager.contract_metrics.request_rejected();
+            anyhow::bail!("too many operations pending, request rejected");
+        }
         let op = message.initiate_op(&self.op_manager);
         let tx = *op.id();
-        self.end.waiting_for_op_tx.send(tx).await.inspect_err(|_| {
-            tracing::debug!("failed to send request to executor, channel closed");
-        })?;
-        <T as ComposeNetworkMessage<Op>>::resume_op(op, &self.op_manager)
-            .await
-            .map_err(|e| {
-                tracing::debug!("failed to resume operation: {e}");
-                e
-            })?;
+        self.end.routes.insert(tx, self.end.response_for_tx.clone());
+        self.end.awaiting.insert(tx);
+        match self.end.waiting_for_op_tx.try_send(tx) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.forget(&tx);
+                self.op_manager.contract_metrics.request_rejected();
+                anyhow::bail!("the event loop is overloaded, request rejected");
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                self.forget(&tx);
+                tracing::debug!("failed to send request to executor, channel closed");
+                anyhow::bail!("channel closed");
+            }
+        }
+        if let Err(e) = <T as ComposeNetworkMessage<Op>>::resume_op(op, &self.op_manager).await {
+            tracing::debug!("failed to resume operation: {e}");
+            self.forget(&tx);
+            return Err(e.into());
+        }
         Ok(tx)
     }
 
+    /// Waits for the result of the operation until it times out, discarding the results of
+    /// the operations given up on before.
     async fn receive_op_result<Op>(&mut self, transaction: Transaction) -> Result<Op, CallbackError>
     where
         Op: Operation + TryFrom<OpEnum, Error = OpError>,
     {
-        if let Some(result) = self.end.completed.remove(&transaction) {
-            return result.try_into().map_err(CallbackError::Conversion);
-        }
-        let op_result = self
-            .end
-            .response_for_rx
-            .recv()
-            .await
-            .ok_or_else(|| ExecutorError::other(anyhow::anyhow!("channel closed")))?;
-        if op_result.id() != &transaction {
-            self.end.completed.insert(*op_result.id(), op_result);
+        let waiting = tokio::time::timeout(OPERATION_TTL, async {
+            loop {
+                let op_result = self.end.response_for_rx.recv().await?;
+                if op_result.id() == &transaction {
+                    return Some(op_result);
+                }
+                // sent back before the executor gave up on it
+                tracing::debug!(tx = %op_result.id(), "Discarding the result of an operation no longer waited for");
+            }
+        })
+        .await;
+        self.forget(&transaction);
+        let Ok(op_result) = waiting else {
             return Err(CallbackError::MissingResult);
-        }
+        };
+        let op_result =
+            op_result.ok_or_else(|| ExecutorError::other(anyhow::anyhow!("channel closed")))?;
         op_result.try_into().map_err(CallbackError::Conversion)
     }
+
+    /// Splits the channel among a pool of executors. The result of each operation is routed
+    /// back to the executor which requested it.
+    pub(crate) fn split(self, executors: usize) -> Vec<Self> {
+        if executors <= 1 {
+            return vec![self];
+        }
+        let Self { op_manager, end } = self;
+        (0..executors)
+            .map(|_| Self {
+                op_manager: op_manager.clone(),
+                end: ExecutorHalve::new(end.waiting_for_op_tx.clone(), end.routes.clone()),
+            })
+            .collect()
+    }
+
+    /// Stops routing the result of the operation to this executor, once it gave up on it.
+    fn forget(&mut self, transaction: &Transaction) {
+        self.end.routes.remove(transaction);
+        self.end.awaiting.remove(transaction);
+    }
 }
 
 impl ExecutorToEventLoopChannel<NetworkEventListenerHalve> {
@@ -274,43 +422,70 @@ impl ExecutorToEventLoopChannel<NetworkEventListenerHalve> {
         ExecutorToEventLoopChannel {
             op_manager: self.op_manager.clone(),
             end: Callback {
-                response_for_tx: self.end.response_for_tx.clone(),
+                routes: self.end.routes.clone(),
             },
         }
     }
 }
 
 impl ExecutorToEventLoopChannel<Callback> {
-    pub async fn response(&mut self, result: OpEnum) {
-        if self.end.response_for_tx.send(result).await.is_err() {
-            tracing::debug!("failed to send response to executor, channel closed");
+    /// Sends the result of the operation back to the executor waiting for it, if any.
+    pub fn response(&self, result: OpEnum) {
+        let Some((_, executor)) = self.end.routes.remove(result.id()) else {
+            tracing::debug!(tx = %result.id(), "No executor waiting for the operation result");
+            self.op_manager.contract_metrics.result_dropped();
+            return;
+        };
+        if executor.send(result).is_err() {
+            tracing::debug!("Executor stopped before getting the operation result");
         }
     }
 }
 
 pub(crate) struct Callback {
-    /// sends the callback response to the executor
-    response_for_tx: mpsc::Sender<OpEnum>,
+    /// where the result of each operation must be sent back to
+    routes: Routes,
 }
 
 pub(crate) struct NetworkEventListenerHalve {
     /// this is the receiver end of the Executor halve, which will be sent from the executor
     /// when a callback is expected for a given transaction
     waiting_for_op_rx: mpsc::Receiver<Transaction>,
-    /// this is the sender end of the Executor halve receiver, which will communicate
-    /// back responses to the executor, it's cloned each tiome a new callback halve is created
-    response_for_tx: mpsc::Sender<OpEnum>,
+    /// executors waiting for the result of each operation, shared with the callback halves
+    /// created to send back the responses
+    routes: Routes,
 }
 
 pub struct ExecutorHalve {
     /// communicates the executor is waiting for a callback for a given transaction
     waiting_for_op_tx: mpsc::Sender<Transaction>,
     /// receives the callback response from the `process_message` task after completion
-    response_for_rx: mpsc::Receiver<OpEnum>,
-    /// stores the completed operations if they haven't been asked for yet in the executor
-    completed: HashMap<Transaction, OpEnum>,
+    response_for_rx: mpsc::UnboundedReceiver<OpEnum>,
+    /// where the results of the operations requested by this executor are routed to; holds no
+    /// more than the results of the operations awaited, so it is bounded by [`PENDING_RESULTS`]
+    response_for_tx: mpsc::UnboundedSender<OpEnum>,
+    /// operations requested whose result is being waited for
+    awaiting: HashSet<Transaction>,
+    /// executors waiting for the result of each operation, shared by the executors of a pool
+    routes: Routes,
+}
+
+impl ExecutorHalve {
+    fn new(waiting_for_op_tx: mpsc::Sender<Transaction>, routes: Routes) -> Self {
+        let (response_for_tx, response_for_rx) = mpsc::unbounded_channel();
+        Self {
+            waiting_for_op_tx,
+            response_for_rx,
+            response_for_tx,
+            awaiting: HashSet::new(),
+            routes,
+        }
+    }
 }
 
+/// Executors waiting for the result of an operation, by its transaction.
+type Routes = Arc<DashMap<Transaction, mpsc::UnboundedSender<OpEnum>>>;
+
 mod sealed {
     use super::{Callback, ExecutorHalve, NetworkEventListenerHalve};
     pub trait ChannelHalve {}
@@ -434,9 +609,116 @@ pub(crate) trait ContractExecutor: Send + 'static {
         &mut self,
         key: ContractKey,
         cli_id: ClientId,
-        notification_ch: tokio::sync::mpsc::UnboundedSender<HostResult>,
+        notification_ch: NotificationSender,
         summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>>;
+
+    /// Returns the changes in the contract state since the given summary, or the full state
+    /// if the changes cannot be derived from it.
+    fn state_delta_since(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> impl Future<Output = Result<UpdateData<'static>, ExecutorError>> + Send;
+
+    /// Returns the summary of the current contract state.
+    fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> impl Future<Output = Result<StateSummary<'static>, ExecutorError>> + Send;
+
+    /// Runs the state maintenance of the hosted contracts in the shard of this executor which
+    /// is due at the given time, since the unix epoch, returning the states which changed as a
+    /// result. Only the contracts this node is the closest holder of are maintained.
+    fn maintain_contracts(
+        &mut self,
+        now: Duration,
+        shard: Shard,
+    ) -> impl Future<Output = Vec<(ContractKey, WrappedState)>> + Send;
+
+    /// Contracts whose state is cached in this node, with their size, last access,
+    /// subscribers and whether the node is home for them.
+    fn cached_contracts(&mut self) -> impl Future<Output = Vec<CachedContract>> + Send;
+
+    /// Checks the stored state of the contract against the hash recorded when it was written,
+    /// and validates it again, quarantining it if either fails. A state found invalid is rolled
+    /// back instead to its latest valid version still journaled, if any. Returns whether it was
+    /// intact, which it is when there is none.
+    fn verify_state(
+        &mut self,
+        key: ContractKey,
+    ) -> impl Future<Output = Result<bool, ExecutorError>> + Send;
+
+    /// Migrates the state of a contract for a new version of its code: the delegate is handed
+    /// the state of the old one in an application message, on behalf of the attested contract
+    /// if the delegate was registered for it, and answers with the initial state of the new
+    /// one in another, which is returned. The new version is put along with it by its own
+    /// executor.
+    fn migrate_contract(
+        &mut self,
+        from: ContractKey,
+        migration_delegate: DelegateKey,
+        delegate_params: Parameters<'static>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> impl Future<Output = Result<WrappedState, ExecutorError>> + Send;
+
+    /// Executes the request to a delegate, registering or unregistering it, or handing it the
+    /// messages of an application, on behalf of the attested contract if any.
+    fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'static>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> impl Future<Output = Response> + Send;
+
+    /// Execution statistics of the delegate since the node started, if it was ever executed
+    /// by this executor.
+    fn delegate_stats(&self, key: &DelegateKey) -> Option<DelegateExecStats>;
+}
+
+/// Schedule of the periodic state maintenance of a contract.
+struct MaintenanceSchedule {
+    interval: Duration,
+    /// Time at which the maintenance is next due, since the unix epoch; set the first
+    /// time the schedule is checked.
+    next: Option<Duration>,
+}
+
+impl MaintenanceSchedule {
+    /// Whether the maintenance is due at the given time, in which case the next one
+    /// is scheduled.
+    fn is_due(&mut self, now: Duration) -> bool {
+        let next = *self.next.get_or_insert(now + self.interval);
+        if next > now {
+            return false;
+        }
+        self.next = Some(now + self.interval);
+        true
+    }
+}
+
+/// Max number of contracts whose immutability declaration is kept.
+const MAX_IMMUTABLE_DECLARATIONS: usize = 4096;
+/// Max size of the states of immutable contracts kept in memory.
+const MAX_IMMUTABLE_STATES_SIZE: usize = 64 * 1024 * 1024;
+
+/// State of an immutable contract, which is never modified once stored.
+#[derive(Clone)]
+struct ImmutableState {
+    state: WrappedState,
+    /// Hash of the content of the state, states received later for the contract are only
+    /// accepted if they match it.
+    hash: blake3::Hash,
+}
+
+impl ImmutableState {
+    fn new(state: WrappedState) -> Self {
+        let hash = blake3::hash(state.as_ref());
+        Self { state, hash }
+    }
+
+    fn matches(&self, state: &[u8]) -> bool {
+        blake3::hash(state) == self.hash
+    }
 }
 
 /// A WASM executor which will run any contracts, delegates, etc. registered.
@@ -449,11 +731,33 @@ pub struct Executor<R = Runtime> {
     runtime: R,
     pub state_store: StateStore<Storage>,
     /// Notification channels for any clients subscribed to updates for a given contract.
-    update_notifications: HashMap<ContractKey, Vec<(ClientId, mpsc::UnboundedSender<HostResult>)>>,
+    update_notifications: HashMap<ContractKey, Vec<(ClientId, NotificationSender)>>,
     /// Summaries of the state of all clients subscribed to a given contract.
     subscriber_summaries: HashMap<ContractKey, HashMap<ClientId, Option<StateSummary<'static>>>>,
+    /// Count of the clients subscribed to each contract through any of the executors sharing
+    /// the state store.
+    client_subscriptions: Arc<DashMap<ContractInstanceId, usize>>,
     /// Attested contract instances for a given delegate.
     delegate_attested_ids: HashMap<DelegateKey, Vec<ContractInstanceId>>,
+    /// Maintenance schedule of the hosted contracts, `None` for contracts which do not
+    /// declare any maintenance.
+    maintenance: HashMap<ContractKey, Option<MaintenanceSchedule>>,
+    /// Whether the maintenance of the contracts stored before the node started is tracked.
+    stored_maintenance_scheduled: bool,
+    /// Whether the most recently used contracts declared themselves immutable.
+    immutable: LruCache<ContractKey, bool>,
+    /// States of the most recently used immutable contracts, kept in memory once loaded since
+    /// they never change.
+    immutable_states: LruCache<ContractKey, ImmutableState>,
+    /// Whether this executor collects the unreferenced contract code, which only one of the
+    /// executors sharing the code store does.
+    collects_code: bool,
+    /// How often the unreferenced contract code is collected, when this executor does.
+    code_collection: CodeCollectionConfig,
+    /// Time since the unix epoch the unreferenced contract code was last collected.
+    last_code_collection: Option<Duration>,
+    /// Results of the recent validations, shared by the executors sharing the state store.
+    validation_cache: Arc<Mutex<ValidationCache>>,
 
     event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
 }
@@ -474,7 +778,16 @@ impl<R> Executor<R> {
             state_store,
             update_notifications: HashMap::default(),
             subscriber_summaries: HashMap::default(),
+            client_subscriptions: Arc::default(),
             delegate_attested_ids: HashMap::default(),
+            maintenance: HashMap::default(),
+            stored_maintenance_scheduled: false,
+            immutable: LruCache::new(MAX_IMMUTABLE_DECLARATIONS),
+            immutable_states: LruCache::new(MAX_IMMUTABLE_STATES_SIZE),
+            collects_code: true,
+            code_collection: CodeCollectionConfig::default(),
+            last_code_collection: None,
+            validation_cache: Arc::default(),
             event_loop_channel,
         })
     }
@@ -485,6 +798,7 @@ impl<R> Executor<R> {
 
     async fn get_stores(
         config: &Config,
+        backend: StorageBackend,
     ) -> Result<
         (
             ContractStore,
@@ -494,18 +808,107 @@ impl<R> Executor<R> {
         ),
         anyhow::Error,
     > {
-        const MAX_SIZE: i64 = 10 * 1024 * 1024;
         const MAX_MEM_CACHE: u32 = 10_000_000;
 
-        let state_store =
-            StateStore::new(Storage::new(&config.db_dir()).await?, MAX_MEM_CACHE).unwrap();
+        let storage = Storage::with_backend(backend, &config.db_dir()).await?;
+        // light clients keep no contract states besides the ones in memory
+        let state_store = if config.is_light_client {
+            StateStore::volatile(storage, MAX_MEM_CACHE)
+        } else {
+            StateStore::new(storage, MAX_MEM_CACHE)
+        }
+        .unwrap();
+        let (contract_store, delegate_store, secret_store) = Self::get_runtime_stores(config)?;
+        Ok((contract_store, delegate_store, secret_store, state_store))
+    }
+
+    /// Stores of the contracts, delegates and secrets the runtime works with.
+    fn get_runtime_stores(
+        config: &Config,
+    ) -> anyhow::Result<(ContractStore, DelegateStore, SecretsStore)> {
+        const MAX_SIZE: i64 = 10 * 1024 * 1024;
+
         let contract_store = ContractStore::new(config.contracts_dir(), MAX_SIZE)?;
 
         let delegate_store = DelegateStore::new(config.delegates_dir(), MAX_SIZE)?;
 
         let secret_store = SecretsStore::new(config.secrets_dir(), config.secrets.clone())?;
 
-        Ok((contract_store, delegate_store, secret_store, state_store))
+        Ok((contract_store, delegate_store, secret_store))
+    }
+
+    /// Checks the bytes of the stored state of the contract, quarantining it if they changed
+    /// since written.
+    async fn check_stored_state(
+        &mut self,
+        key: &ContractKey,
+    ) -> Result<StoredState, ExecutorError> {
+        let stored = self
+            .state_store
+            .verify(key)
+            .await
+            .map_err(ExecutorError::other)?;
+        if let StoredState::Corrupted = stored {
+            self.quarantine_state(key).await?;
+        }
+        Ok(stored)
+    }
+
+    /// Takes the state of the contract out of service, for it to be fetched anew.
+    async fn quarantine_state(&mut self, key: &ContractKey) -> Result<(), ExecutorError> {
+        tracing::warn!(contract = %key, "Quarantining corrupted contract state");
+        self.immutable_states.remove(key);
+        self.state_store
+            .quarantine(key)
+            .await
+            .map_err(ExecutorError::other)
+    }
+
+    /// Contracts among the given ones whose state is stored, largest first.
+    /// Whether this node maintains the state of the contract: the one closest to it out of
+    /// the peers around caching it, so they don't all run the maintenance and propagate
+    /// competing states. A node not part of a network always does.
+    fn is_maintainer(&self, key: &ContractKey) -> bool {
+        match &self.event_loop_channel {
+            Some(channel) => channel.op_manager.ring.is_closest_holder(key),
+            None => true,
+        }
+    }
+
+    async fn describe_cached(&self, ids: Vec<ContractInstanceId>) -> Vec<CachedContract> {
+        let sizes = match self
+            .state_store
+            .state_sizes(ids.into_iter().map(ContractKey::from))
+            .await
+        {
+            Ok(sizes) => sizes,
+            Err(err) => {
+                tracing::warn!("Failed reading the sizes of the stored states: {err}");
+                return vec![];
+            }
+        };
+        let mut cached = Vec::with_capacity(sizes.len());
+        for (key, size) in sizes {
+            let (subscribers, home) = match &self.event_loop_channel {
+                Some(channel) => {
+                    let ring = &channel.op_manager.ring;
+                    (
+                        ring.subscribers_of(&key).map_or(0, |subs| subs.len()),
+                        ring.home_contracts.contains(&key),
+                    )
+                }
+                None => (0, false),
+            };
+            cached.push(CachedContract {
+                key,
+                size,
+                idle: self.state_store.idle(&key),
+                subscribers,
+                home,
+            });
+        }
+        cached.sort_by(|a, b| b.size.cmp(&a.size));
+        cached
     }
 
     async fn op_request<Op, M>(&mut self, request: M) -> Result<Op::Result, ExecutorError>
@@ -527,19 +930,18 @@ impl<R> Executor<R> {
         // an answer back so we don't block the executor itself.
         // otherwise it may be possible to end up in a deadlock waiting for a tree of contract
         // dependencies to be resolved
-        let result = loop {
-            match ch.receive_op_result::<Op>(transaction).await {
-                Ok(result) => break result,
-                Err(CallbackError::MissingResult) => {
-                    tokio::time::sleep(Duration::from_secs(10)).await;
-                    continue;
-                }
-                Err(CallbackError::Conversion(err)) => {
-                    tracing::error!("expect message of one type but got an other: {err}");
-                    return Err(ExecutorError::other(err));
-                }
-                Err(CallbackError::Err(other)) => return Err(other),
+        let result = match ch.receive_op_result::<Op>(transaction).await {
+            Ok(result) => result,
+            Err(CallbackError::MissingResult) => {
+                return Err(ExecutorError::other(anyhow::anyhow!(
+                    "operation {transaction} timed out"
+                )));
             }
+            Err(CallbackError::Conversion(err)) => {
+                tracing::error!("expect message of one type but got an other: {err}");
+                return Err(ExecutorError::other(err));
+            }
+            Err(CallbackError::Err(other)) => return Err(other),
         };
         let result = <Op::Result>::try_from(result).map_err(|err| {
             tracing::debug!("didn't get result back: {err}");
@@ -547,4 +949,136 @@ impl<R> Executor<R> {
         })?;
         Ok(result)
     }
+
+    /// Drops the notifiers of the clients subscribed to the contract which went away, and the
+    /// subscription of the contract once no client is left.
+    fn drop_notifiers(&mut self, key: &ContractKey, gone: &[ClientId]) {
+        if gone.is_empty() {
+            return;
+        }
+        if let Some(notifiers) = self.update_notifications.get_mut(key) {
+            notifiers.retain(|(cli_id, _)| !gone.contains(cli_id));
+            if notifiers.is_empty() {
+                self.update_notifications.remove(key);
+            }
+        }
+        if let Some(summaries) = self.subscriber_summaries.get_mut(key) {
+            summaries.retain(|cli_id, _| !gone.contains(cli_id));
+            if summaries.is_empty() {
+                self.subscriber_summaries.remove(key);
+            }
+        }
+        if let dashmap::mapref::entry::Entry::Occupied(mut clients) =
+            self.client_subscriptions.entry(*key.id())
+        {
+            *clients.get_mut() = clients.get().saturating_sub(gone.len());
+            if *clients.get() == 0 {
+                clients.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::get::GetOp;
+
+    #[tokio::test]
+    async fn results_go_to_the_executor_waiting_for_them() -> anyhow::Result<()> {
+        let op_manager = Arc::new(
+            crate::test_utils::op_manager("results_go_to_the_executor_waiting_for_them").await?,
+        );
+        let (listener, executor) = executor_channel(op_manager.clone());
+        let mut executors = executor.split(2);
+
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let get = || operations::get::start_op(key, false);
+        let (first, second) = (get(), get());
+        let requested = [*first.id(), *second.id()];
+        // waiting for them as if requested, without running the operations
+        for (executor, tx) in executors.iter_mut().zip(requested) {
+            let results = executor.end.response_for_tx.clone();
+            executor.end.routes.insert(tx, results);
+            executor.end.awaiting.insert(tx);
+        }
+
+        let callback = listener.callback();
+        callback.response(OpEnum::Get(second));
+        callback.response(OpEnum::Get(first));
+        // no executor is waiting for it
+        callback.response(OpEnum::Get(get()));
+
+        for (executor, tx) in executors.iter_mut().zip(requested) {
+            let result: GetOp = executor.receive_op_result(tx).await?;
+            assert_eq!(result.id(), &tx);
+            assert!(executor.end.awaiting.is_empty());
+        }
+        assert!(executors[0].end.routes.is_empty());
+        assert_eq!(op_manager.contract_metrics.snapshot().dropped_results, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn maintenance_due_every_interval() {
+        let mut schedule = MaintenanceSchedule {
+            interval: Duration::from_secs(60),
+            next: None,
+        };
+        let start = Duration::from_secs(1_000);
+        // not due right after the contract is hosted
+        assert!(!schedule.is_due(start));
+        assert!(!schedule.is_due(start + Duration::from_secs(59)));
+        assert!(schedule.is_due(start + Duration::from_secs(60)));
+        assert!(!schedule.is_due(start + Duration::from_secs(61)));
+        // late checks schedule the next maintenance from the time it ran
+        assert!(schedule.is_due(start + Duration::from_secs(200)));
+        assert!(!schedule.is_due(start + Duration::from_secs(259)));
+        assert!(schedule.is_due(start + Duration::from_secs(260)));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_dropped_with_their_clients() -> anyhow::Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let contract_store = ContractStore::new(tmp_dir.path().join("contracts"), u16::MAX as i64)?;
+        let state_store =
+            StateStore::new(Storage::new(tmp_dir.path()).await?, u16::MAX as u32).unwrap();
+        let mut executor = Executor::new(
+            state_store,
+            || Ok(()),
+            OperationMode::Local,
+            mock_runtime::MockRuntime { contract_store },
+            None,
+        )
+        .await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let (first, second) = (ClientId::next(), ClientId::next());
+        for client in [first, second] {
+            let (tx, _) = crate::client_events::notification_channel();
+            executor
+                .update_notifications
+                .entry(key)
+                .or_default()
+                .push((client, tx));
+            executor
+                .subscriber_summaries
+                .entry(key)
+                .or_default()
+                .insert(client, None);
+            *executor.client_subscriptions.entry(*key.id()).or_default() += 1;
+        }
+
+        executor.drop_notifiers(&key, &[first]);
+        assert_eq!(executor.update_notifications[&key].len(), 1);
+        assert_eq!(
+            executor.client_subscriptions.get(key.id()).as_deref(),
+            Some(&1)
+        );
+
+        executor.drop_notifiers(&key, &[second]);
+        assert!(executor.update_notifications.is_empty());
+        assert!(executor.subscriber_summaries.is_empty());
+        assert!(executor.client_subscriptions.is_empty());
+        Ok(())
+    }
 }
@@ -1,5 +1,4 @@
 use super::*;
-use tokio::sync::mpsc::UnboundedSender;
 
 pub(crate) struct MockRuntime {
     pub contract_store: ContractStore,
@@ -39,7 +38,7 @@ impl Executor<MockRuntime> {
         &mut self,
         _id: ClientId,
         _req: ClientRequest<'_>,
-        _updates: Option<mpsc::UnboundedSender<Result<HostResponse, WsClientError>>>,
+        _updates: Option<NotificationSender>,
     ) -> Response {
         unreachable!()
     }
@@ -114,16 +113,87 @@ impl ContractExecutor for Executor<MockRuntime> {
         &mut self,
         _key: ContractKey,
         _cli_id: ClientId,
-        _notification_ch: UnboundedSender<HostResult>,
+        _notification_ch: NotificationSender,
         _summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>> {
         Ok(())
     }
+
+    async fn state_delta_since(
+        &mut self,
+        key: ContractKey,
+        _summary: StateSummary<'static>,
+    ) -> Result<UpdateData<'static>, ExecutorError> {
+        let state = self
+            .state_store
+            .get(&key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok(UpdateData::State(state.into()))
+    }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        let state = self
+            .state_store
+            .get(&key)
+            .await
+            .map_err(ExecutorError::other)?;
+        Ok(StateSummary::from(State::from(state).into_bytes()))
+    }
+
+    async fn maintain_contracts(
+        &mut self,
+        _now: Duration,
+        _shard: Shard,
+    ) -> Vec<(ContractKey, WrappedState)> {
+        vec![]
+    }
+
+    async fn cached_contracts(&mut self) -> Vec<CachedContract> {
+        let ids = self.runtime.contract_store.contracts();
+        self.describe_cached(ids).await
+    }
+
+    async fn verify_state(&mut self, key: ContractKey) -> Result<bool, ExecutorError> {
+        let stored = self.check_stored_state(&key).await?;
+        Ok(!matches!(stored, StoredState::Corrupted))
+    }
+
+    async fn migrate_contract(
+        &mut self,
+        _from: ContractKey,
+        migration_delegate: DelegateKey,
+        _delegate_params: Parameters<'static>,
+        _attested_contract: Option<&ContractInstanceId>,
+    ) -> Result<WrappedState, ExecutorError> {
+        Err(ExecutorError::other(anyhow::anyhow!(
+            "delegate `{migration_delegate}` cannot be executed by the mock runtime"
+        )))
+    }
+
+    async fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'static>,
+        _attested_contract: Option<&ContractInstanceId>,
+    ) -> Response {
+        Err(ExecutorError::other(anyhow::anyhow!(
+            "delegate `{}` cannot be executed by the mock runtime",
+            req.key()
+        )))
+    }
+
+    fn delegate_stats(&self, _key: &DelegateKey) -> Option<DelegateExecStats> {
+        None
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::contract::ContractHandlerEvent;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn local_node_handle() -> Result<(), Box<dyn std::error::Error>> {
@@ -152,4 +222,91 @@ mod test {
         assert_eq!(counter, 1);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn delegate_requests_answered_with_error() -> anyhow::Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let contract_store = ContractStore::new(tmp_dir.path().join("contracts"), u16::MAX as i64)?;
+        let state_store =
+            StateStore::new(Storage::new(tmp_dir.path()).await?, u16::MAX as u32).unwrap();
+        let mut executor = Executor::new(
+            state_store,
+            || Ok(()),
+            OperationMode::Local,
+            MockRuntime { contract_store },
+            None,
+        )
+        .await?;
+
+        let delegate = DelegateKey::new([1; 32], CodeHash::new([2; 32]));
+        let query = ContractHandlerEvent::DelegateQuery {
+            req: DelegateRequest::UnregisterDelegate(delegate.clone()),
+            attested_contract: None,
+        };
+        let Some(ContractHandlerEvent::DelegateResponse { key, response }) =
+            crate::contract::execute(&mut executor, query).await
+        else {
+            panic!("expected a delegate response");
+        };
+        assert_eq!(key, delegate);
+        assert!(response.is_err());
+
+        let query = ContractHandlerEvent::DelegateStatsQuery {
+            key: delegate.clone(),
+        };
+        let Some(ContractHandlerEvent::DelegateStatsResponse { key, stats }) =
+            crate::contract::execute(&mut executor, query).await
+        else {
+            panic!("expected a delegate stats response");
+        };
+        assert_eq!(key, delegate);
+        assert!(stats.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumed_subscriptions_only_notified_of_changes() -> anyhow::Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let contract_store = ContractStore::new(tmp_dir.path().join("contracts"), u16::MAX as i64)?;
+        let state_store =
+            StateStore::new(Storage::new(tmp_dir.path()).await?, u16::MAX as u32).unwrap();
+        let mut executor = Executor::new(
+            state_store,
+            || Ok(()),
+            OperationMode::Local,
+            MockRuntime { contract_store },
+            None,
+        )
+        .await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let state = WrappedState::new(vec![1, 2, 3]);
+        executor
+            .state_store
+            .store(key, state.clone(), Parameters::from(vec![]))
+            .await?;
+
+        let resume = |summary: Vec<u8>| {
+            let (sender, receiver) = crate::client_events::notification_channel();
+            let query = ContractHandlerEvent::RegisterSubscriberListener {
+                key,
+                client_id: ClientId::FIRST,
+                summary: Some(StateSummary::from(summary)),
+                subscriber_listener: sender,
+            };
+            (query, receiver)
+        };
+
+        let (query, mut receiver) = resume(state.as_ref().to_vec());
+        assert!(crate::contract::execute(&mut executor, query)
+            .await
+            .is_none());
+        assert!(receiver.try_recv().is_err());
+
+        let (query, mut receiver) = resume(vec![1]);
+        assert!(crate::contract::execute(&mut executor, query)
+            .await
+            .is_none());
+        assert!(receiver.try_recv().is_ok());
+        Ok(())
+    }
 }
@@ -1,5 +1,9 @@
 use super::*;
 
+/// Rounds of related contracts fetched for a contract call before giving up, as they may keep
+/// requesting others.
+const MAX_RELATED_ROUNDS: usize = 100;
+
 impl ContractExecutor for Executor<Runtime> {
     async fn fetch_contract(
         &mut self,
@@ -43,25 +47,34 @@ impl ContractExecutor for Executor<Runtime> {
             let code = code.ok_or_else(|| {
                 ExecutorError::request(StdContractError::MissingContract { key: key.into() })
             })?;
-            self.runtime
-                .contract_store
-                .store_contract(code.clone())
-                .map_err(ExecutorError::other)?;
+            self.check_and_store_contract(code.clone())?;
             true
         } else {
             false
         };
 
+        let immutable = self.is_immutable(&key, &params);
+        if immutable {
+            let Either::Left(incoming_state) = &update else {
+                return Err(ExecutorError::immutable_contract(key));
+            };
+            if let Some(stored) = self.immutable_state(&key).await? {
+                if !stored.matches(incoming_state.as_ref()) {
+                    return Err(ExecutorError::immutable_contract(key));
+                }
+                return Ok(stored.state.clone());
+            }
+        }
+
         let mut updates = match update {
             Either::Left(incoming_state) => {
                 let result = self
-                    .runtime
                     .validate_state(&key, &params, &incoming_state, &related_contracts)
                     .map_err(|err| {
                         if remove_if_fail {
                             let _ = self.runtime.contract_store.remove_contract(&key);
                         }
-                        ExecutorError::other(err)
+                        ExecutorError::execution(err, Some(InnerOpError::Validate(key)))
                     })?;
                 match result {
                     ValidateResult::Valid => {
@@ -69,6 +82,11 @@ impl ContractExecutor for Executor<Runtime> {
                             .store(key, incoming_state.clone(), params.clone())
                             .await
                             .map_err(ExecutorError::other)?;
+                        if immutable {
+                            // there is no update machinery to run for immutable contracts
+                            self.cache_immutable_state(key, incoming_state.clone());
+                            return Ok(incoming_state);
+                        }
                     }
                     ValidateResult::Invalid => {
                         return Err(ExecutorError::request(StdContractError::invalid_put(key)));
@@ -101,7 +119,7 @@ impl ContractExecutor for Executor<Runtime> {
                     key: key.into(),
                 }));
             }
-            Err(StateStoreError::Any(err)) => return Err(ExecutorError::other(err)),
+            Err(err) => return Err(ExecutorError::other(err)),
         };
 
         for (id, state) in related_contracts
@@ -129,6 +147,8 @@ impl ContractExecutor for Executor<Runtime> {
                 }));
             }
         };
+        self.send_emitted_events(&key);
+        self.schedule_maintenance(key, &params);
         Ok(updated_state)
     }
 
@@ -136,7 +156,7 @@ impl ContractExecutor for Executor<Runtime> {
         &mut self,
         key: ContractKey,
         cli_id: ClientId,
-        notification_ch: tokio::sync::mpsc::UnboundedSender<HostResult>,
+        notification_ch: NotificationSender,
         summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>> {
         let channels = self.update_notifications.entry(key).or_default();
@@ -151,6 +171,7 @@ impl ContractExecutor for Executor<Runtime> {
             }
         } else {
             channels.push((cli_id, notification_ch));
+            *self.client_subscriptions.entry(*key.id()).or_default() += 1;
         }
 
         if self
@@ -166,16 +187,237 @@ impl ContractExecutor for Executor<Runtime> {
         }
         Ok(())
     }
+
+    async fn state_delta_since(
+        &mut self,
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    ) -> Result<UpdateData<'static>, ExecutorError> {
+        let state = match self.state_store.get(&key).await {
+            Ok(s) => s,
+            Err(StateStoreError::MissingContract(_)) => {
+                return Err(ExecutorError::request(StdContractError::MissingContract {
+                    key: key.into(),
+                }));
+            }
+            Err(err) => return Err(ExecutorError::other(err)),
+        };
+        let Some(params) = self
+            .state_store
+            .get_params(&key)
+            .await
+            .map_err(ExecutorError::other)?
+        else {
+            return Ok(UpdateData::State(state.into()));
+        };
+        if let Some(delta) = self.state_store.journal().delta_since(&key, &summary) {
+            return Ok(UpdateData::Delta(delta));
+        }
+        match self
+            .runtime
+            .get_state_delta(&key, &params, &state, &summary)
+        {
+            Ok(delta) => {
+                let delta = delta.into_owned();
+                self.state_store
+                    .journal()
+                    .answered(&key, summary, delta.clone());
+                Ok(UpdateData::Delta(delta))
+            }
+            Err(error) => {
+                // the contract can't derive the changes from this summary, send the whole state
+                tracing::debug!(contract = %key, %error, "Failed to compute delta since summary");
+                Ok(UpdateData::State(state.into()))
+            }
+        }
+    }
+
+    async fn summarize_contract_state(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<StateSummary<'static>, ExecutorError> {
+        let state = match self.state_store.get(&key).await {
+            Ok(s) => s,
+            Err(StateStoreError::MissingContract(_)) => {
+                return Err(ExecutorError::request(StdContractError::MissingContract {
+                    key: key.into(),
+                }));
+            }
+            Err(err) => return Err(ExecutorError::other(err)),
+        };
+        let params = self
+            .state_store
+            .get_params(&key)
+            .await
+            .map_err(ExecutorError::other)?
+            .ok_or_else(|| {
+                ExecutorError::request(StdContractError::MissingContract { key: key.into() })
+            })?;
+        let summary = self
+            .runtime
+            .summarize_state(&key, &params, &state)
+            .map_err(|err| ExecutorError::execution(err, None))?;
+        self.state_store.journal().summarized(&key, summary.clone());
+        Ok(summary)
+    }
+
+    async fn maintain_contracts(
+        &mut self,
+        now: Duration,
+        shard: Shard,
+    ) -> Vec<(ContractKey, WrappedState)> {
+        if !self.stored_maintenance_scheduled {
+            self.schedule_stored_maintenance(shard).await;
+        }
+        let due: Vec<_> = self
+            .maintenance
+            .iter_mut()
+            .filter_map(|(key, schedule)| schedule.as_mut()?.is_due(now).then_some(*key))
+            .collect();
+        let mut updated = Vec::with_capacity(due.len());
+        for key in due {
+            if !self.is_maintainer(&key) {
+                tracing::debug!(contract = %key, "Closer peers maintain the contract state");
+                continue;
+            }
+            match self.maintain_contract(key).await {
+                Ok(Some(new_state)) => updated.push((key, new_state)),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::warn!(contract = %key, "Failed to maintain contract state: {err}");
+                }
+            }
+        }
+        self.collect_code_garbage(now).await;
+        updated
+    }
+
+    async fn cached_contracts(&mut self) -> Vec<CachedContract> {
+        let ids = self.runtime.contract_store.contracts();
+        self.describe_cached(ids).await
+    }
+
+    async fn verify_state(&mut self, key: ContractKey) -> Result<bool, ExecutorError> {
+        let (state, recorded) = match self.check_stored_state(&key).await? {
+            StoredState::Intact(state) => (state, true),
+            StoredState::Unrecorded(state) => (state, false),
+            StoredState::Missing => return Ok(true),
+            StoredState::Corrupted => return Ok(false),
+        };
+        let Some(params) = self
+            .state_store
+            .get_params(&key)
+            .await
+            .map_err(ExecutorError::other)?
+        else {
+            return Ok(true);
+        };
+        // the contract is run again, in case the state was stored without being validated; it
+        // is only taken out of service when found invalid with its related contracts at hand,
+        // failing to fetch them proves nothing
+        let result = self
+            .validate_resolving_related(&key, &params, &state)
+            .await?;
+        if let ValidateResult::Invalid = result {
+            if !self.roll_back_to_valid(&key, &params).await? {
+                self.quarantine_state(&key).await?;
+            }
+            return Ok(false);
+        }
+        if !recorded {
+            // only once valid, so the hash recorded is of bytes worth keeping
+            self.state_store
+                .record_digest(&key, state)
+                .await
+                .map_err(ExecutorError::other)?;
+        }
+        Ok(true)
+    }
+
+    async fn migrate_contract(
+        &mut self,
+        from: ContractKey,
+        migration_delegate: DelegateKey,
+        delegate_params: Parameters<'static>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Result<WrappedState, ExecutorError> {
+        let refused = |cause: String| {
+            ExecutorError::request(StdContractError::Update {
+                key: from,
+                cause: cause.into(),
+            })
+        };
+        let old_state = self
+            .state_store
+            .get(&from)
+            .await
+            .map_err(|_| refused(format!("no state of {from} stored to migrate")))?;
+        // only contracts the delegate was registered for are attested to it
+        let attested = attested_contract.and_then(|contract| {
+            self.delegate_attested_ids
+                .get(&migration_delegate)
+                .and_then(|contracts| contracts.iter().find(|c| *c == contract))
+        });
+        let inbound = vec![InboundDelegateMsg::ApplicationMessage(
+            ApplicationMessage::new(*from.id(), old_state.as_ref().to_vec()),
+        )];
+        let outbound = self
+            .runtime
+            .inbound_app_message(
+                &migration_delegate,
+                &delegate_params,
+                attested.map(|c| c.as_bytes()),
+                inbound,
+            )
+            .map_err(|err| {
+                ExecutorError::execution(
+                    err,
+                    Some(InnerOpError::Delegate(migration_delegate.clone())),
+                )
+            })?;
+        let new_state = outbound
+            .into_iter()
+            .find_map(|msg| match msg {
+                OutboundDelegateMsg::ApplicationMessage(msg) => {
+                    Some(WrappedState::new(msg.payload))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                refused(format!(
+                    "delegate {migration_delegate} returned no state migrated from {from}"
+                ))
+            })?;
+        tracing::info!(%from, "Migrated contract state");
+        Ok(new_state)
+    }
+
+    async fn execute_delegate_request(
+        &mut self,
+        req: DelegateRequest<'static>,
+        attested_contract: Option<&ContractInstanceId>,
+    ) -> Response {
+        self.delegate_request(req, attested_contract)
+    }
+
+    fn delegate_stats(&self, key: &DelegateKey) -> Option<DelegateExecStats> {
+        self.runtime.delegate_stats().get(key).cloned()
+    }
 }
 
 impl Executor<Runtime> {
     pub async fn from_config(
         config: Arc<Config>,
+        storage: StorageBackend,
         event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
     ) -> anyhow::Result<Self> {
         let (contract_store, delegate_store, secret_store, state_store) =
-            Self::get_stores(&config).await?;
-        let rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
+            Self::get_stores(&config, storage).await?;
+        let mut rt = Runtime::build(contract_store, delegate_store, secret_store, false).unwrap();
+        rt.set_module_cache(ModuleCache::persisted(
+            &config.compiled_modules_dir(),
+            DEFAULT_MODULE_CACHE_SIZE,
+        )?);
         Executor::new(
             state_store,
             move || {
@@ -189,11 +431,157 @@ impl Executor<Runtime> {
         .await
     }
 
+    /// Builds another executor to execute the calls of other contracts alongside this one, with
+    /// the same limits. Both share the state store, the compiled contract modules and the
+    /// contracts clients are subscribed to.
+    pub(crate) async fn fork(
+        &self,
+        config: &Config,
+        event_loop_channel: Option<ExecutorToEventLoopChannel<ExecutorHalve>>,
+    ) -> anyhow::Result<Self> {
+        let (contract_store, delegate_store, secret_store) = Self::get_runtime_stores(config)?;
+        let rt = self
+            .runtime
+            .fork(contract_store, delegate_store, secret_store)?;
+        let mut executor = Executor::new(
+            self.state_store.clone(),
+            || Ok(()),
+            self.mode,
+            rt,
+            event_loop_channel,
+        )
+        .await?;
+        executor.client_subscriptions = self.client_subscriptions.clone();
+        executor.collects_code = false;
+        executor.validation_cache = self.validation_cache.clone();
+        Ok(executor)
+    }
+
+    /// Sets the fuel, memory and secrets storage quotas delegates are executed under.
+    pub fn set_delegate_limits(&mut self, limits: DelegateLimits) {
+        self.runtime.set_delegate_limits(limits);
+    }
+
+    /// Sets the fuel, per function of their interface, the memory and the host functions
+    /// contracts are executed with.
+    pub fn set_contract_limits(&mut self, limits: ContractLimits) {
+        self.runtime.set_contract_limits(limits);
+    }
+
+    /// Host functions imported by each contract compiled by this executor, for auditing by
+    /// the node operator.
+    pub fn contract_imports(&self) -> Vec<ContractImports> {
+        self.runtime.contract_imports()
+    }
+
+    /// Removes the code no contract state stored, nor any subscription of peers or clients,
+    /// references anymore, once the grace period is over. Runs at most once per interval.
+    async fn collect_code_garbage(&mut self, now: Duration) {
+        if !self.collects_code
+            || self
+                .last_code_collection
+                .is_some_and(|last| now.saturating_sub(last) < self.code_collection.interval)
+        {
+            return;
+        }
+        self.last_code_collection = Some(now);
+        let mut referenced = HashSet::new();
+        for id in self.runtime.contract_store.contracts() {
+            if self.is_referenced(&ContractKey::from(id)).await {
+                referenced.insert(id);
+            }
+        }
+        match self
+            .runtime
+            .collect_code_garbage(&referenced, self.code_collection.grace_period, now)
+        {
+            Ok(removed) if !removed.is_empty() => {
+                tracing::info!(
+                    removed = removed.len(),
+                    "Collected unreferenced contract code"
+                );
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!("Failed collecting unreferenced contract code: {err}"),
+        }
+    }
+
+    async fn is_referenced(&self, key: &ContractKey) -> bool {
+        if self.client_subscriptions.contains_key(key.id()) {
+            return true;
+        }
+        if let Some(channel) = &self.event_loop_channel {
+            let ring = &channel.op_manager.ring;
+            if ring.is_seeding_contract(key)
+                || ring.home_contracts.contains(key)
+                || ring
+                    .subscribers_of(key)
+                    .is_some_and(|subs| !subs.is_empty())
+            {
+                return true;
+            }
+        }
+        // when it can't be told, the code is kept
+        self.state_store.contains(key).await.unwrap_or(true)
+    }
+
+    /// Validates the state, unless the same inputs were validated recently, in which case the
+    /// result of then is returned without running the contract.
+    fn validate_state(
+        &mut self,
+        key: &ContractKey,
+        params: &Parameters<'_>,
+        state: &WrappedState,
+        related: &RelatedContracts<'_>,
+    ) -> RuntimeResult<ValidateResult> {
+        let inputs = ValidationCache::inputs(key, params, state, related);
+        let cached = inputs.and_then(|inputs| self.validation_cache.lock().get(&inputs));
+        if let Some(channel) = &self.event_loop_channel {
+            channel
+                .op_manager
+                .contract_metrics
+                .validated(cached.is_some());
+        }
+        if let Some(result) = cached {
+            return Ok(result);
+        }
+        let result = self.runtime.validate_state(key, params, state, related)?;
+        if let Some(inputs) = inputs {
+            self.validation_cache.lock().insert(inputs, result.clone());
+        }
+        Ok(result)
+    }
+
+    /// Sets how often the unreferenced contract code is collected, and for how long it must
+    /// stay unreferenced before it is removed.
+    pub fn set_code_collection(&mut self, config: CodeCollectionConfig) {
+        self.code_collection = config;
+    }
+
+    /// Sets how much of the history of the contract states is journaled, shared by all the
+    /// executors forked from this one.
+    pub fn set_state_journal_limits(&mut self, limits: StateJournalLimits) {
+        self.state_store.set_journal_limits(limits);
+    }
+
+    /// Sets the bytes of contract states persisted beyond which the least worth keeping are
+    /// evicted. When part of the network, the states of the contracts this node is home for
+    /// are kept, and the ones with subscribers are favoured.
+    pub async fn set_state_quota(&mut self, max_bytes: u64) -> Result<(), StateStoreError> {
+        let mut quota = StateQuota::new(max_bytes);
+        if let Some(channel) = &self.event_loop_channel {
+            quota = quota
+                .with_policy(channel.op_manager.clone())
+                .with_clock(channel.op_manager.clock.clone());
+        }
+        self.state_store.set_quota(quota).await
+    }
+
     pub fn register_contract_notifier(
         &mut self,
         key: ContractKey,
         cli_id: ClientId,
-        notification_ch: tokio::sync::mpsc::UnboundedSender<HostResult>,
+        notification_ch: NotificationSender,
         summary: Option<StateSummary<'_>>,
     ) -> Result<(), Box<RequestError>> {
         let channels = self.update_notifications.entry(key).or_default();
@@ -208,6 +596,7 @@ impl Executor<Runtime> {
             }
         } else {
             channels.push((cli_id, notification_ch));
+            *self.client_subscriptions.entry(*key.id()).or_default() += 1;
         }
 
         if self
@@ -254,7 +643,7 @@ impl Executor<Runtime> {
         &mut self,
         id: ClientId,
         req: ClientRequest<'_>,
-        updates: Option<mpsc::UnboundedSender<Result<HostResponse, WsClientError>>>,
+        updates: Option<NotificationSender>,
     ) -> Response {
         match req {
             ClientRequest::ContractOp(op) => self.contract_requests(op, id, updates).await,
@@ -274,7 +663,7 @@ impl Executor<Runtime> {
         &mut self,
         req: ContractRequest<'_>,
         cli_id: ClientId,
-        updates: Option<mpsc::UnboundedSender<Result<HostResponse, WsClientError>>>,
+        updates: Option<NotificationSender>,
     ) -> Response {
         match req {
             ContractRequest::Put {
@@ -322,11 +711,10 @@ impl Executor<Runtime> {
                 cipher,
                 nonce,
             } => {
-                use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+                use chacha20poly1305::{Key, KeyInit, XChaCha20Poly1305, XNonce};
                 let key = delegate.key().clone();
-                let arr = GenericArray::from_slice(&cipher);
-                let cipher = XChaCha20Poly1305::new(arr);
-                let nonce = GenericArray::from_slice(&nonce).to_owned();
+                let cipher = XChaCha20Poly1305::new(Key::from_slice(&cipher));
+                let nonce = XNonce::from_slice(&nonce).to_owned();
                 tracing::debug!("registering delegate `{key}");
                 if let Some(contract) = attestaded_contract {
                     self.delegate_attested_ids
@@ -421,6 +809,15 @@ impl Executor<Runtime> {
         let params = contract.params();
 
         if self.get_local_contract(key.id()).await.is_ok() {
+            if self.is_immutable(&key, &params) {
+                // the same state can be put again, but not a different one
+                return match self.immutable_state(&key).await? {
+                    Some(stored) if stored.matches(state.as_ref()) => {
+                        Ok(ContractResponse::PutResponse { key }.into())
+                    }
+                    _ => Err(ExecutorError::immutable_contract(key)),
+                };
+            }
             // already existing contract, just try to merge states
             return self
                 .perform_contract_update(key, UpdateData::State(state.into()))
@@ -458,6 +855,9 @@ impl Executor<Runtime> {
                     })
                 })?
         };
+        if self.is_immutable(&key, &parameters) {
+            return Err(ExecutorError::immutable_contract(key));
+        }
 
         let current_state = self
             .state_store
@@ -489,6 +889,149 @@ impl Executor<Runtime> {
         Ok(ContractResponse::UpdateResponse { key, summary }.into())
     }
 
+    /// Whether the contract declared itself immutable in its package.
+    fn is_immutable(&mut self, key: &ContractKey, params: &Parameters<'_>) -> bool {
+        if let Some(immutable) = self.immutable.get(key) {
+            return *immutable;
+        }
+        match self.runtime.is_immutable(key, params) {
+            Ok(immutable) => {
+                self.immutable.insert(*key, immutable, 1);
+                immutable
+            }
+            Err(err) => {
+                tracing::debug!(contract = %key, "Failed to load immutability declaration: {err}");
+                false
+            }
+        }
+    }
+
+    /// The state of the immutable contract, if already stored.
+    async fn immutable_state(
+        &mut self,
+        key: &ContractKey,
+    ) -> Result<Option<ImmutableState>, ExecutorError> {
+        if let Some(stored) = self.immutable_states.get(key) {
+            return Ok(Some(stored.clone()));
+        }
+        match self.state_store.get(key).await {
+            Ok(state) => {
+                let stored = ImmutableState::new(state);
+                self.immutable_states
+                    .insert(*key, stored.clone(), stored.state.size());
+                Ok(Some(stored))
+            }
+            Err(StateStoreError::MissingContract(_)) => Ok(None),
+            Err(err) => Err(ExecutorError::other(err)),
+        }
+    }
+
+    /// Keeps the state in memory if the contract is immutable.
+    async fn cache_if_immutable(&mut self, key: &ContractKey, state: &WrappedState) {
+        let immutable = match self.immutable.get(key).copied() {
+            Some(immutable) => immutable,
+            None => match self.state_store.get_params(key).await {
+                Ok(Some(params)) => self.is_immutable(key, &params),
+                _ => false,
+            },
+        };
+        if immutable {
+            self.cache_immutable_state(*key, state.clone());
+        }
+    }
+
+    fn cache_immutable_state(&mut self, key: ContractKey, state: WrappedState) {
+        let size = state.size();
+        self.immutable_states
+            .insert(key, ImmutableState::new(state), size);
+    }
+
+    /// Starts tracking the maintenance schedule of the contract, if it declares one.
+    fn schedule_maintenance(&mut self, key: ContractKey, params: &Parameters<'_>) {
+        if self.maintenance.contains_key(&key) {
+            return;
+        }
+        match self.runtime.maintenance_interval(&key, params) {
+            Ok(interval) => {
+                let schedule = interval.map(|interval| MaintenanceSchedule {
+                    interval,
+                    next: None,
+                });
+                self.maintenance.insert(key, schedule);
+            }
+            Err(err) => {
+                tracing::debug!(contract = %key, "Failed to load maintenance interval: {err}");
+            }
+        }
+    }
+
+    /// Starts tracking the maintenance schedules of the contracts in the shard stored before
+    /// the node started, which would otherwise only be tracked once put or updated again.
+    async fn schedule_stored_maintenance(&mut self, shard: Shard) {
+        self.stored_maintenance_scheduled = true;
+        for id in self.runtime.contract_store.contracts() {
+            let key = ContractKey::from(id);
+            if !shard.owns(&key) || self.maintenance.contains_key(&key) {
+                continue;
+            }
+            match self.state_store.get_params(&key).await {
+                Ok(Some(params)) => self.schedule_maintenance(key, &params),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::debug!(contract = %key, "Failed to load contract parameters: {err}");
+                }
+            }
+        }
+    }
+
+    /// Runs the maintenance of the contract state, storing the resulting state if it changed
+    /// and is valid, and notifying the subscribed clients.
+    async fn maintain_contract(
+        &mut self,
+        key: ContractKey,
+    ) -> Result<Option<WrappedState>, ExecutorError> {
+        let state = self
+            .state_store
+            .get(&key)
+            .await
+            .map_err(ExecutorError::other)?;
+        let params = self
+            .state_store
+            .get_params(&key)
+            .await
+            .map_err(ExecutorError::other)?
+            .ok_or_else(|| {
+                ExecutorError::request(StdContractError::MissingContract { key: key.into() })
+            })?;
+        let UpdateModification { new_state, .. } = self
+            .runtime
+            .maintain_state(&key, &params, &state)
+            .map_err(|err| ExecutorError::execution(err, Some(InnerOpError::Upsert(key))))?;
+        let Some(new_state) = new_state else {
+            return Ok(None);
+        };
+        let new_state = WrappedState::new(new_state.into_bytes());
+        if new_state.as_ref() == state.as_ref() {
+            return Ok(None);
+        }
+        let ValidateResult::Valid = self
+            .validate_resolving_related(&key, &params, &new_state)
+            .await?
+        else {
+            return Err(ExecutorError::request(StdContractError::Update {
+                key,
+                cause: "invalid state after maintenance".into(),
+            }));
+        };
+        self.state_store
+            .update(&key, new_state.clone())
+            .await
+            .map_err(ExecutorError::other)?;
+        self.send_update_notification(&key, &params, &new_state)
+            .await?;
+        Ok(Some(new_state))
+    }
+
     /// Attempts to update the state with the provided updates.
     /// If there were no updates, it will return the current state.
     async fn attempt_state_update(
@@ -523,13 +1066,141 @@ impl Executor<Runtime> {
             }
         };
         let new_state = WrappedState::new(new_state.into_bytes());
-        self.state_store
-            .update(key, new_state.clone())
-            .await
-            .map_err(ExecutorError::other)?;
+        // the update may only be proven invalid by validating the state it results in, which
+        // is done before storing it, so an invalid state is never persisted
+        let result = self
+            .validate_resolving_related(key, parameters, &new_state)
+            .await?;
+        if let ValidateResult::Invalid = result {
+            return Err(ExecutorError::invalid_update(*key));
+        }
+        match updates {
+            [UpdateData::Delta(delta)] => self
+                .state_store
+                .update_by_delta(key, new_state.clone(), delta.clone().into_owned())
+                .await
+                .map_err(ExecutorError::other)?,
+            _ => self
+                .state_store
+                .update(key, new_state.clone())
+                .await
+                .map_err(ExecutorError::other)?,
+        }
         Ok(Either::Left(new_state))
     }
 
+    /// Validates the state of the contract, fetching the states of the related contracts the
+    /// validation requests.
+    async fn validate_resolving_related(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+        state: &WrappedState,
+    ) -> Result<ValidateResult, ExecutorError> {
+        let mut related_contracts = RelatedContracts::default();
+        for _ in 0..MAX_RELATED_ROUNDS {
+            let result = self
+                .validate_state(key, parameters, state, &related_contracts)
+                .map_err(|err| ExecutorError::execution(err, Some(InnerOpError::Upsert(*key))))?;
+            let ValidateResult::RequestRelated(related) = result else {
+                return Ok(result);
+            };
+            related_contracts.missing(related);
+            for (id, related) in related_contracts.update() {
+                if related.is_none() {
+                    let state = match self.local_state_or_from_network(id, false).await? {
+                        Either::Left(state) => state,
+                        Either::Right(result) => result.state,
+                    };
+                    *related = Some(state.into());
+                }
+            }
+        }
+        Err(ExecutorError::request(StdContractError::Update {
+            key: *key,
+            cause: "dependency cycle validating the state".into(),
+        }))
+    }
+
+    /// Rolls the state of the contract back to the latest version still in the journal which
+    /// is valid, returning whether there was one.
+    async fn roll_back_to_valid(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+    ) -> Result<bool, ExecutorError> {
+        let Some(current) = self.state_store.journal().version(key) else {
+            return Ok(false);
+        };
+        for version in (1..current).rev() {
+            let Some(state) = self.replay_state(key, parameters, version).await? else {
+                break;
+            };
+            let result = self
+                .validate_resolving_related(key, parameters, &state)
+                .await?;
+            if let ValidateResult::Valid = result {
+                self.state_store
+                    .update(key, state)
+                    .await
+                    .map_err(ExecutorError::other)?;
+                self.state_store.journal().roll_back(key, version);
+                tracing::info!(contract = %key, version, "Rolled back contract state");
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Rebuilds the state of the contract at a version still in the journal, by applying on
+    /// the snapshot preceding it the deltas since, along with the states of the related
+    /// contracts they request.
+    async fn replay_state(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+        version: u64,
+    ) -> Result<Option<WrappedState>, ExecutorError> {
+        let Some((mut state, deltas)) = self.state_store.journal().replay(key, version) else {
+            return Ok(None);
+        };
+        for delta in deltas {
+            let mut updates = vec![UpdateData::Delta(delta)];
+            for _ in 0..MAX_RELATED_ROUNDS {
+                let UpdateModification {
+                    new_state, related, ..
+                } = self
+                    .runtime
+                    .update_state(key, parameters, &state, &updates)
+                    .map_err(|err| {
+                        ExecutorError::execution(err, Some(InnerOpError::Upsert(*key)))
+                    })?;
+                if let Some(new_state) = new_state {
+                    state = WrappedState::new(new_state.into_bytes());
+                    break;
+                }
+                if related.is_empty() {
+                    break;
+                }
+                for RelatedContract {
+                    contract_instance_id: id,
+                    ..
+                } in related
+                {
+                    let related_state = match self.local_state_or_from_network(&id, false).await? {
+                        Either::Left(state) => state,
+                        Either::Right(result) => result.state,
+                    };
+                    updates.push(UpdateData::RelatedState {
+                        related_to: id,
+                        state: related_state.into(),
+                    });
+                }
+            }
+        }
+        Ok(Some(state))
+    }
+
     /// Given a contract and a series of delta updates, it will try to perform an update
     /// to the contract state and return the new state. If it fails to update the state,
     /// it will return an error.
@@ -549,13 +1220,8 @@ impl Executor<Runtime> {
                     .attempt_state_update(parameters, &current_state, &key, &updates)
                     .await?;
                 let missing = match state_update_res {
-                    Either::Left(new_state) => {
-                        self.state_store
-                            .update(&key, new_state.clone())
-                            .await
-                            .map_err(ExecutorError::other)?;
-                        break new_state;
-                    }
+                    // already stored by the attempt
+                    Either::Left(new_state) => break new_state,
                     Either::Right(missing) => missing,
                 };
                 // some required contracts are missing
@@ -638,8 +1304,14 @@ impl Executor<Runtime> {
             }
         }
 
+        if let Some(stored) = self.immutable_states.get(&key) {
+            return Ok((Some(stored.state.clone()), got_contract));
+        }
         match self.state_store.get(&key).await {
-            Ok(state) => Ok((Some(state), got_contract)),
+            Ok(state) => {
+                self.cache_if_immutable(&key, &state).await;
+                Ok((Some(state), got_contract))
+            }
             Err(StateStoreError::MissingContract(_)) => Ok((None, got_contract)),
             Err(err) => Err(ExecutorError::request(RequestError::from(
                 StdContractError::Get {
@@ -666,6 +1338,23 @@ impl Executor<Runtime> {
         Ok(State::from(state))
     }
 
+    /// Stores the code of a new contract, once it passes the static checks of the runtime.
+    fn check_and_store_contract(
+        &mut self,
+        contract: ContractContainer,
+    ) -> Result<(), ExecutorError> {
+        let key = contract.key();
+        if let ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) = &contract {
+            self.runtime
+                .check_contract_code(&key, contract_v1.code())
+                .map_err(|err| ExecutorError::execution(err, Some(InnerOpError::Validate(key))))?;
+        }
+        self.runtime
+            .contract_store
+            .store_contract(contract)
+            .map_err(ExecutorError::other)
+    }
+
     async fn verify_and_store_contract(
         &mut self,
         state: WrappedState,
@@ -688,14 +1377,10 @@ impl Executor<Runtime> {
 
         while iterations < DEPENDENCY_CYCLE_LIMIT_GUARD {
             if let Some(contract) = trying_contract.take() {
-                self.runtime
-                    .contract_store
-                    .store_contract(contract)
-                    .map_err(ExecutorError::other)?;
+                self.check_and_store_contract(contract)?;
             }
 
             let result = self
-                .runtime
                 .validate_state(
                     &trying_key,
                     &trying_params,
@@ -704,7 +1389,7 @@ impl Executor<Runtime> {
                 )
                 .map_err(|err| {
                     let _ = self.runtime.contract_store.remove_contract(&trying_key);
-                    ExecutorError::other(err)
+                    ExecutorError::execution(err, Some(InnerOpError::Validate(trying_key)))
                 })?;
 
             let is_valid = match result {
@@ -780,6 +1465,7 @@ impl Executor<Runtime> {
             let summaries = self.subscriber_summaries.get_mut(&key).unwrap();
             // in general there should be less than 32 failures
             let mut failures = Vec::with_capacity(32);
+            let mut dropped = 0;
             for (peer_key, notifier) in notifiers.iter() {
                 let peer_summary = summaries.get_mut(peer_key).unwrap();
                 let update = match peer_summary {
@@ -794,24 +1480,81 @@ impl Executor<Runtime> {
                         .into(),
                     None => UpdateData::State(State::from(new_state.as_ref()).into_owned()),
                 };
-                if let Err(err) =
-                    notifier.send(Ok(
-                        ContractResponse::UpdateNotification { key, update }.into()
-                    ))
-                {
-                    failures.push(*peer_key);
-                    tracing::error!(cli_id = %peer_key, "{err}");
-                } else {
-                    tracing::debug!(cli_id = %peer_key, contract = %key, "notified of update");
+                match notifier.send(ClientNotification::Result(Ok(
+                    ContractResponse::UpdateNotification { key, update }.into(),
+                ))) {
+                    Ok(oldest) => {
+                        dropped += u64::from(oldest.is_some());
+                        tracing::debug!(cli_id = %peer_key, contract = %key, "notified of update");
+                    }
+                    Err(err) => {
+                        failures.push(*peer_key);
+                        tracing::error!(cli_id = %peer_key, "{err}");
+                    }
                 }
             }
-            if !failures.is_empty() {
-                notifiers.retain(|(c, _)| !failures.contains(c));
-            }
+            self.notifications_dropped(&key, dropped);
+            self.drop_notifiers(&key, &failures);
         }
+        self.send_emitted_events(&key);
         Ok(())
     }
 
+    /// Delivers the events emitted by the contract while executing to the clients subscribed
+    /// to it, and to the applications subscribed to the lifecycle events of the node.
+    fn send_emitted_events(&mut self, key: &ContractKey) {
+        let events = self.runtime.take_emitted_events(key);
+        if events.is_empty() {
+            return;
+        }
+        if let Some(notifiers) = self.update_notifications.get(key) {
+            let mut failures = Vec::new();
+            let mut dropped = 0;
+            for (cli_id, notifier) in notifiers {
+                for payload in &events {
+                    let event = ClientNotification::ContractEvent {
+                        key: *key,
+                        payload: payload.clone(),
+                    };
+                    match notifier.send(event) {
+                        Ok(oldest) => dropped += u64::from(oldest.is_some()),
+                        Err(_) => {
+                            failures.push(*cli_id);
+                            break;
+                        }
+                    }
+                }
+            }
+            self.notifications_dropped(key, dropped);
+            self.drop_notifiers(key, &failures);
+        }
+        if let Some(channel) = &self.event_loop_channel {
+            for payload in events {
+                channel
+                    .op_manager
+                    .ring
+                    .lifecycle_events
+                    .emit(NodeLifecycleEvent::ContractEvent { key: *key, payload });
+            }
+        }
+        tracing::debug!(contract = %key, "notified of emitted events");
+    }
+
+    /// Accounts for the notifications of the contract dropped for the subscribed clients
+    /// falling behind.
+    fn notifications_dropped(&self, key: &ContractKey, dropped: u64) {
+        if dropped == 0 {
+            return;
+        }
+        tracing::debug!(contract = %key, %dropped, "clients falling behind, dropped their oldest notifications");
+        if let Some(channel) = &self.event_loop_channel {
+            channel
+                .op_manager
+                .contract_metrics
+                .notifications_dropped(dropped);
+        }
+    }
+
     async fn get_contract_locally(
         &self,
         key: &ContractKey,
@@ -0,0 +1,91 @@
+//! Cache of the results of validating contract states.
+//!
+//! The same states get validated over and over while they are replicated across the network,
+//! so the results of validating them are kept, keyed by a hash of the contract, its
+//! parameters, the state and the related contracts given. Validating the same inputs again
+//! returns the result kept instead of running the contract, which yields the same one since
+//! validation is deterministic. Only the most recent results are kept.
+
+use std::collections::{HashMap, VecDeque};
+
+use freenet_stdlib::prelude::*;
+
+/// Results kept, the oldest are dropped past it.
+const MAX_RESULTS: usize = 4096;
+
+/// Hash of the inputs of a validation.
+pub(super) type ValidationInputs = [u8; 32];
+
+#[derive(Default)]
+pub(super) struct ValidationCache {
+    results: HashMap<ValidationInputs, ValidateResult>,
+    /// Inputs of the results kept, oldest first.
+    order: VecDeque<ValidationInputs>,
+}
+
+impl ValidationCache {
+    /// Hash of the inputs of validating the state, if they can be hashed.
+    pub fn inputs(
+        key: &ContractKey,
+        params: &Parameters,
+        state: &WrappedState,
+        related: &RelatedContracts,
+    ) -> Option<ValidationInputs> {
+        let related = bincode::serialize(related).ok()?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(key.id().as_bytes());
+        for input in [params.as_ref(), state.as_ref(), &related] {
+            hasher.update(&(input.len() as u64).to_le_bytes());
+            hasher.update(input);
+        }
+        Some(*hasher.finalize().as_bytes())
+    }
+
+    pub fn get(&self, inputs: &ValidationInputs) -> Option<ValidateResult> {
+        self.results.get(inputs).cloned()
+    }
+
+    pub fn insert(&mut self, inputs: ValidationInputs, result: ValidateResult) {
+        if self.results.insert(inputs, result).is_some() {
+            return;
+        }
+        self.order.push_back(inputs);
+        if self.order.len() > MAX_RESULTS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_are_kept_for_the_same_inputs() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let params = Parameters::from(vec![1]);
+        let related = RelatedContracts::default();
+        let inputs = |byte| {
+            ValidationCache::inputs(&key, &params, &WrappedState::new(vec![byte]), &related)
+                .unwrap()
+        };
+        assert_eq!(inputs(0), inputs(0));
+        assert_ne!(inputs(0), inputs(1));
+
+        let mut cache = ValidationCache::default();
+        cache.insert(inputs(0), ValidateResult::Valid);
+        assert_eq!(cache.get(&inputs(0)), Some(ValidateResult::Valid));
+        assert_eq!(cache.get(&inputs(1)), None);
+
+        // the oldest results are dropped once over the limit
+        for n in 1..=MAX_RESULTS as u64 {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&n.to_le_bytes());
+            cache.insert(hash, ValidateResult::Invalid);
+        }
+        assert_eq!(cache.get(&inputs(0)), None);
+        assert_eq!(cache.results.len(), MAX_RESULTS);
+    }
+}
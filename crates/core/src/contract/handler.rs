@@ -5,20 +5,28 @@ use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
 use std::sync::Arc;
 use std::time::Duration;
 
+use freenet_stdlib::client_api::{DelegateRequest, HostResponse};
 use freenet_stdlib::prelude::*;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 use super::executor::{ExecutorHalve, ExecutorToEventLoopChannel};
+use super::storages::StorageBackend;
 use super::ExecutorError;
 use super::{
-    executor::{ContractExecutor, Executor},
+    executor::{CodeCollectionConfig, ContractExecutor, Executor},
     ContractError,
 };
-use crate::client_events::HostResult;
+use crate::client_events::{HostResult, NotificationSender};
 use crate::config::Config;
 use crate::message::Transaction;
-use crate::{client_events::ClientId, wasm_runtime::Runtime};
+use crate::util::time_source::Clock;
+use crate::{
+    client_events::ClientId,
+    wasm_runtime::{
+        ContractLimits, DelegateExecStats, DelegateLimits, Runtime, StateJournalLimits,
+    },
+};
 
 pub(crate) struct ClientResponsesReceiver(UnboundedReceiver<(ClientId, HostResult)>);
 
@@ -64,38 +72,73 @@ pub(crate) trait ContractHandler {
     where
         Self: Sized + 'static;
 
-    fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve>;
-
-    fn executor(&mut self) -> &mut Self::ContractExecutor;
+    /// The channel the contract calls are received from, and the executors they are spread
+    /// across.
+    fn into_parts(
+        self,
+    ) -> (
+        ContractHandlerChannel<ContractHandlerHalve>,
+        Vec<Self::ContractExecutor>,
+    );
 }
 
 pub(crate) struct NetworkContractHandler<R = Runtime> {
-    executor: Executor<R>,
+    executors: Vec<Executor<R>>,
     channel: ContractHandlerChannel<ContractHandlerHalve>,
 }
 
 impl ContractHandler for NetworkContractHandler<Runtime> {
-    type Builder = Arc<Config>;
+    type Builder = (
+        Arc<Config>,
+        ContractLimits,
+        DelegateLimits,
+        StorageBackend,
+        u64,
+        StateJournalLimits,
+        CodeCollectionConfig,
+        usize,
+    );
     type ContractExecutor = Executor<Runtime>;
 
     async fn build(
         channel: ContractHandlerChannel<ContractHandlerHalve>,
         executor_request_sender: ExecutorToEventLoopChannel<ExecutorHalve>,
-        config: Self::Builder,
+        (
+            config,
+            contract_limits,
+            delegate_limits,
+            storage,
+            state_quota,
+            state_journal,
+            code_collection,
+            pool_size,
+        ): Self::Builder,
     ) -> anyhow::Result<Self>
     where
         Self: Sized + 'static,
     {
-        let executor = Executor::from_config(config.clone(), Some(executor_request_sender)).await?;
-        Ok(Self { executor, channel })
-    }
-
-    fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
-        &mut self.channel
+        let mut channels = executor_request_sender.split(pool_size).into_iter();
+        let mut executor = Executor::from_config(config.clone(), storage, channels.next()).await?;
+        executor.set_contract_limits(contract_limits);
+        executor.set_delegate_limits(delegate_limits);
+        executor.set_state_quota(state_quota).await?;
+        executor.set_state_journal_limits(state_journal);
+        executor.set_code_collection(code_collection);
+        let mut executors = Vec::with_capacity(pool_size);
+        for channel in channels {
+            executors.push(executor.fork(&config, Some(channel)).await?);
+        }
+        executors.insert(0, executor);
+        Ok(Self { executors, channel })
     }
 
-    fn executor(&mut self) -> &mut Self::ContractExecutor {
-        &mut self.executor
+    fn into_parts(
+        self,
+    ) -> (
+        ContractHandlerChannel<ContractHandlerHalve>,
+        Vec<Self::ContractExecutor>,
+    ) {
+        (self.channel, self.executors)
     }
 }
 
@@ -113,21 +156,25 @@ impl ContractHandler for NetworkContractHandler<super::MockRuntime> {
         Self: Sized + 'static,
     {
         let executor = Executor::new_mock(&identifier, executor_request_sender).await?;
-        Ok(Self { executor, channel })
-    }
-
-    fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
-        &mut self.channel
+        Ok(Self {
+            executors: vec![executor],
+            channel,
+        })
     }
 
-    fn executor(&mut self) -> &mut Self::ContractExecutor {
-        &mut self.executor
+    fn into_parts(
+        self,
+    ) -> (
+        ContractHandlerChannel<ContractHandlerHalve>,
+        Vec<Self::ContractExecutor>,
+    ) {
+        (self.channel, self.executors)
     }
 }
 
-#[derive(Eq)]
+#[derive(Clone, Copy, Eq)]
 pub(crate) struct EventId {
-    id: u64,
+    pub(super) id: u64,
 }
 
 impl PartialEq for EventId {
@@ -224,6 +271,7 @@ impl ContractHandlerChannel<SenderHalve> {
     pub async fn send_to_handler(
         &self,
         ev: ContractHandlerEvent,
+        clock: &dyn Clock,
     ) -> Result<ContractHandlerEvent, ContractError> {
         let id = EV_ID.fetch_add(1, SeqCst);
         let (result, result_receiver) = tokio::sync::oneshot::channel();
@@ -231,9 +279,12 @@ impl ContractHandlerChannel<SenderHalve> {
             .event_sender
             .send(InternalCHEvent { ev, id, result })
             .map_err(|err| ContractError::ChannelDropped(Box::new(err.0.ev)))?;
-        match tokio::time::timeout(Self::CH_EV_RESPONSE_TIME_OUT, result_receiver).await {
-            Ok(Ok((_, res))) => Ok(res),
-            Ok(Err(_)) | Err(_) => Err(ContractError::NoEvHandlerResponse),
+        tokio::select! {
+            res = result_receiver => match res {
+                Ok((_, res)) => Ok(res),
+                Err(_) => Err(ContractError::NoEvHandlerResponse),
+            },
+            _ = clock.sleep(Self::CH_EV_RESPONSE_TIME_OUT) => Err(ContractError::NoEvHandlerResponse),
         }
     }
 
@@ -265,6 +316,11 @@ impl ContractHandlerChannel<ContractHandlerHalve> {
         }
     }
 
+    /// Gives up on answering the event, letting its sender know right away.
+    pub fn abandon(&mut self, id: EventId) {
+        self.end.waiting_response.remove(&id.id);
+    }
+
     pub async fn recv_from_sender(
         &mut self,
     ) -> Result<(EventId, ContractHandlerEvent), ContractError> {
@@ -282,6 +338,20 @@ pub(crate) struct StoreResponse {
     pub contract: Option<ContractContainer>,
 }
 
+/// A contract whose state is cached by the node, as reported for diagnostics.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct CachedContract {
+    pub key: ContractKey,
+    /// Bytes of its state.
+    pub size: u64,
+    /// Time since its state was last read or written, unknown if not since the node started.
+    pub idle: Option<Duration>,
+    /// Peers subscribed through this node to its updates.
+    pub subscribers: usize,
+    /// Whether the node is within its home region.
+    pub home: bool,
+}
+
 struct InternalCHEvent {
     ev: ContractHandlerEvent,
     id: u64,
@@ -326,7 +396,72 @@ pub(crate) enum ContractHandlerEvent {
         key: ContractKey,
         client_id: ClientId,
         summary: Option<StateSummary<'static>>,
-        subscriber_listener: UnboundedSender<HostResult>,
+        subscriber_listener: NotificationSender,
+    },
+    /// Summarize the current state of a contract in this node
+    SummaryQuery { key: ContractKey },
+    /// The response to a summary query
+    SummaryResponse {
+        key: ContractKey,
+        summary: Result<StateSummary<'static>, ExecutorError>,
+    },
+    /// Get the changes in the state of a contract since the given summary
+    DeltaQuery {
+        key: ContractKey,
+        summary: StateSummary<'static>,
+    },
+    /// The response to a delta query
+    DeltaResponse {
+        key: ContractKey,
+        delta: Result<UpdateData<'static>, ExecutorError>,
+    },
+    /// Run the state maintenance of the hosted contracts which is due at the given time
+    MaintenanceQuery { now: Duration },
+    /// The response to a maintenance query, with the contract states which changed
+    MaintenanceResponse {
+        updated: Vec<(ContractKey, WrappedState)>,
+    },
+    /// List the contracts whose state is cached in this node
+    CachedContractsQuery,
+    /// The response to a cached contracts query
+    CachedContractsResponse { contracts: Vec<CachedContract> },
+    /// Check the stored state of a contract has not been corrupted and is still valid
+    IntegrityQuery { key: ContractKey },
+    /// The response to an integrity query, whether the state was intact, if stored, or
+    /// quarantined to be fetched anew
+    IntegrityResponse {
+        key: ContractKey,
+        intact: Result<bool, ExecutorError>,
+    },
+    /// Migrate the state of a contract superseded by a new version of its code, through the
+    /// given delegate, on behalf of the contract the requester was attested for, if any
+    MigrateQuery {
+        from: ContractKey,
+        migration_delegate: DelegateKey,
+        delegate_params: Parameters<'static>,
+        attested_contract: Option<ContractInstanceId>,
+    },
+    /// The response to a migrate query, with the initial state of the new version
+    MigrateResponse {
+        from: ContractKey,
+        new_state: Result<WrappedState, ExecutorError>,
+    },
+    /// Execute a request to a delegate, on behalf of the contract the client was attested for
+    DelegateQuery {
+        req: DelegateRequest<'static>,
+        attested_contract: Option<ContractInstanceId>,
+    },
+    /// The response to a delegate request
+    DelegateResponse {
+        key: DelegateKey,
+        response: Result<HostResponse, ExecutorError>,
+    },
+    /// Get the execution statistics of a delegate since the node started
+    DelegateStatsQuery { key: DelegateKey },
+    /// The response to a delegate statistics query, if the delegate was ever executed
+    DelegateStatsResponse {
+        key: DelegateKey,
+        stats: Option<DelegateExecStats>,
     },
 }
 
@@ -390,6 +525,78 @@ impl std::fmt::Display for ContractHandlerEvent {
                     "register subscriber listener {{ {key}, client_id: {client_id} }}",
                 )
             }
+            ContractHandlerEvent::SummaryQuery { key } => {
+                write!(f, "summary query {{ {key} }}")
+            }
+            ContractHandlerEvent::SummaryResponse { key, summary } => match summary {
+                Ok(_) => write!(f, "summary query response {{ {key} }}"),
+                Err(e) => write!(f, "summary query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::DeltaQuery { key, .. } => {
+                write!(f, "delta query {{ {key} }}")
+            }
+            ContractHandlerEvent::DeltaResponse { key, delta } => match delta {
+                Ok(_) => write!(f, "delta query response {{ {key} }}"),
+                Err(e) => write!(f, "delta query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::MaintenanceQuery { .. } => {
+                write!(f, "maintenance query")
+            }
+            ContractHandlerEvent::MaintenanceResponse { updated } => {
+                write!(
+                    f,
+                    "maintenance query response {{ updated: {} }}",
+                    updated.len()
+                )
+            }
+            ContractHandlerEvent::CachedContractsQuery => {
+                write!(f, "cached contracts query")
+            }
+            ContractHandlerEvent::CachedContractsResponse { contracts } => {
+                write!(
+                    f,
+                    "cached contracts query response {{ contracts: {} }}",
+                    contracts.len()
+                )
+            }
+            ContractHandlerEvent::IntegrityQuery { key } => {
+                write!(f, "integrity query {{ {key} }}")
+            }
+            ContractHandlerEvent::IntegrityResponse { key, intact } => match intact {
+                Ok(intact) => write!(f, "integrity query response {{ {key}, intact: {intact} }}"),
+                Err(e) => write!(f, "integrity query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::MigrateQuery {
+                from,
+                migration_delegate,
+                ..
+            } => {
+                write!(
+                    f,
+                    "migrate query {{ from: {from}, delegate: {migration_delegate} }}"
+                )
+            }
+            ContractHandlerEvent::MigrateResponse { from, new_state } => match new_state {
+                Ok(_) => write!(f, "migrate query response {{ from: {from} }}"),
+                Err(e) => write!(f, "migrate query failed {{ from: {from}, {e} }}"),
+            },
+            ContractHandlerEvent::DelegateQuery { req, .. } => {
+                write!(f, "delegate query {{ {} }}", req.key())
+            }
+            ContractHandlerEvent::DelegateResponse { key, response } => match response {
+                Ok(_) => write!(f, "delegate query response {{ {key} }}"),
+                Err(e) => write!(f, "delegate query failed {{ {key}, {e} }}"),
+            },
+            ContractHandlerEvent::DelegateStatsQuery { key } => {
+                write!(f, "delegate stats query {{ {key} }}")
+            }
+            ContractHandlerEvent::DelegateStatsResponse { key, stats } => {
+                write!(
+                    f,
+                    "delegate stats query response {{ {key}, executed: {} }}",
+                    stats.is_some()
+                )
+            }
         }
     }
 }
@@ -400,6 +607,7 @@ pub mod test {
 
     use super::*;
     use crate::config::GlobalExecutor;
+    use crate::util::time_source::TokioClock;
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn channel_test() -> anyhow::Result<()> {
@@ -412,12 +620,15 @@ pub mod test {
 
         let h = GlobalExecutor::spawn(async move {
             send_halve
-                .send_to_handler(ContractHandlerEvent::PutQuery {
-                    key: contract.key(),
-                    state: vec![6, 7, 8].into(),
-                    related_contracts: RelatedContracts::default(),
-                    contract: Some(contract),
-                })
+                .send_to_handler(
+                    ContractHandlerEvent::PutQuery {
+                        key: contract.key(),
+                        state: vec![6, 7, 8].into(),
+                        related_contracts: RelatedContracts::default(),
+                        contract: Some(contract),
+                    },
+                    &TokioClock,
+                )
                 .await
         });
         let (id, ev) =
@@ -493,12 +704,13 @@ pub(super) mod in_memory {
             Ok(MemoryContractHandler::new(channel, executor_request_sender, &identifier).await)
         }
 
-        fn channel(&mut self) -> &mut ContractHandlerChannel<ContractHandlerHalve> {
-            &mut self.channel
-        }
-
-        fn executor(&mut self) -> &mut Self::ContractExecutor {
-            &mut self.runtime
+        fn into_parts(
+            self,
+        ) -> (
+            ContractHandlerChannel<ContractHandlerHalve>,
+            Vec<Self::ContractExecutor>,
+        ) {
+            (self.channel, vec![self.runtime])
         }
     }
 
@@ -0,0 +1,390 @@
+//! Pool of executors the contract calls are spread across.
+//!
+//! Every contract is assigned to one of the executors, which executes all of its calls one
+//! after the other, in the order they were received, while the calls of contracts assigned to
+//! other executors run concurrently. Maintenance is run by every executor for the contracts
+//! assigned to it, its [`Shard`], and answered once all are done. Queries about all the contracts are
+//! answered by the first executor, since all of them share the same storage, which is also the
+//! only one collecting the contract code no longer referenced. The requests to a
+//! delegate always go to the same executor too, which keeps the cipher it was registered with,
+//! as do the migrations of contract states run through it.
+//!
+//! Executors run on threads of their own, so the contracts being executed don't hold up the
+//! tasks of the node, and take a bounded number of calls: once an executor is behind, new calls
+//! wait for it to catch up. Calls no executor can take are answered right away, with an empty
+//! response for maintenance, or rejected.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use freenet_stdlib::prelude::*;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tracing::Instrument;
+
+use super::{
+    executor::ContractExecutor,
+    handler::{ContractHandlerEvent, EventId},
+    ContractError,
+};
+
+/// Calls an executor can have pending before new calls wait for it.
+const MAX_PENDING_JOBS: usize = 64;
+
+enum Job {
+    Call(EventId, ContractHandlerEvent),
+    Maintain { round: u64, now: Duration },
+}
+
+enum Done {
+    Call(EventId, ContractHandlerEvent),
+    Maintained {
+        round: u64,
+        updated: Vec<(ContractKey, WrappedState)>,
+    },
+}
+
+/// Maintenance query waiting for executors to finish.
+struct Maintenance {
+    id: EventId,
+    pending: usize,
+    updated: Vec<(ContractKey, WrappedState)>,
+}
+
+/// The contracts assigned to one of the executors of the pool.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Shard {
+    pub fn owns(&self, key: &ContractKey) -> bool {
+        slot(key.id(), self.count) == self.index
+    }
+}
+
+pub(super) struct ExecutorPool {
+    executors: Vec<Sender<Job>>,
+    done: UnboundedReceiver<Done>,
+    rounds: u64,
+    maintenance: HashMap<u64, Maintenance>,
+    /// Responses to send back without waiting for any executor.
+    answered: VecDeque<(EventId, ContractHandlerEvent)>,
+}
+
+impl ExecutorPool {
+    /// Starts a worker thread per executor, which stops once the pool is dropped.
+    pub fn start<E: ContractExecutor>(executors: Vec<E>) -> Self {
+        let (done_tx, done) = mpsc::unbounded_channel();
+        let count = executors.len();
+        let executors = executors
+            .into_iter()
+            .enumerate()
+            .map(|(worker, executor)| {
+                let (jobs_tx, jobs) = mpsc::channel(MAX_PENDING_JOBS);
+                let done_tx = done_tx.clone();
+                let shard = Shard {
+                    index: worker,
+                    count,
+                };
+                std::thread::Builder::new()
+                    .name(format!("contract-executor-{worker}"))
+                    .spawn(move || {
+                        let rt = tokio::runtime::Builder::new_current_thread()
+                            .enable_all()
+                            .build()
+                            .expect("failed to build contract executor runtime");
+                        rt.block_on(
+                            work(executor, shard, jobs, done_tx)
+                                .instrument(tracing::info_span!("contract_executor", worker)),
+                        );
+                    })
+                    .expect("failed to spawn contract executor thread");
+                jobs_tx
+            })
+            .collect();
+        Self {
+            executors,
+            done,
+            rounds: 0,
+            maintenance: HashMap::new(),
+            answered: VecDeque::new(),
+        }
+    }
+
+    /// Hands the event to the executor of its contract, or to all of them for maintenance,
+    /// waiting for them to have room for it. Fails if the event is not a query or the executor
+    /// of its contract stopped.
+    pub async fn execute(
+        &mut self,
+        id: EventId,
+        event: ContractHandlerEvent,
+    ) -> Result<(), ContractError> {
+        if let ContractHandlerEvent::MaintenanceQuery { now } = &event {
+            self.rounds += 1;
+            let round = self.rounds;
+            let mut pending = 0;
+            for executor in &self.executors {
+                if executor
+                    .send(Job::Maintain { round, now: *now })
+                    .await
+                    .is_ok()
+                {
+                    pending += 1;
+                }
+            }
+            if pending == 0 {
+                tracing::error!("All contract executors stopped, skipping maintenance");
+                let response = ContractHandlerEvent::MaintenanceResponse { updated: vec![] };
+                self.answered.push_back((id, response));
+                return Ok(());
+            }
+            self.maintenance.insert(
+                round,
+                Maintenance {
+                    id,
+                    pending,
+                    updated: vec![],
+                },
+            );
+            return Ok(());
+        }
+        let Some(executor) = self.executor_for(&event) else {
+            return Err(ContractError::UnexpectedEvent(Box::new(event)));
+        };
+        self.executors[executor]
+            .send(Job::Call(id, event))
+            .await
+            .map_err(|_| ContractError::ExecutorStopped)
+    }
+
+    /// Executor the call is handed to, `None` if the event is not a query.
+    fn executor_for(&self, event: &ContractHandlerEvent) -> Option<usize> {
+        let executor = match event {
+            // the states and the contracts stored are the same for all the executors
+            ContractHandlerEvent::CachedContractsQuery => 0,
+            ContractHandlerEvent::DelegateQuery { req, .. } => self.slot(req.key()),
+            // the statistics are kept by the executor running the delegate
+            ContractHandlerEvent::DelegateStatsQuery { key } => self.slot(key),
+            // the new version is then put by its own executor, as any other put
+            ContractHandlerEvent::MigrateQuery {
+                migration_delegate, ..
+            } => self.slot(migration_delegate),
+            ContractHandlerEvent::GetQuery { key, .. }
+            | ContractHandlerEvent::PutQuery { key, .. }
+            | ContractHandlerEvent::UpdateQuery { key, .. }
+            | ContractHandlerEvent::RegisterSubscriberListener { key, .. }
+            | ContractHandlerEvent::SummaryQuery { key }
+            | ContractHandlerEvent::DeltaQuery { key, .. }
+            | ContractHandlerEvent::IntegrityQuery { key } => self.executor_of(key),
+            _ => return None,
+        };
+        Some(executor)
+    }
+
+    /// Waits for the next response to send back. Cancel safe.
+    pub async fn next_response(&mut self) -> Option<(EventId, ContractHandlerEvent)> {
+        if let Some(answered) = self.answered.pop_front() {
+            return Some(answered);
+        }
+        loop {
+            match self.done.recv().await? {
+                Done::Call(id, response) => return Some((id, response)),
+                Done::Maintained { round, updated } => {
+                    let Some(maintenance) = self.maintenance.get_mut(&round) else {
+                        continue;
+                    };
+                    maintenance.updated.extend(updated);
+                    maintenance.pending -= 1;
+                    if maintenance.pending == 0 {
+                        let Maintenance { id, updated, .. } =
+                            self.maintenance.remove(&round).unwrap();
+                        return Some((id, ContractHandlerEvent::MaintenanceResponse { updated }));
+                    }
+                }
+            }
+        }
+    }
+
+    fn executor_of(&self, key: &ContractKey) -> usize {
+        self.slot(key.id())
+    }
+
+    fn slot(&self, value: &impl Hash) -> usize {
+        slot(value, self.executors.len())
+    }
+}
+
+fn slot(value: &impl Hash, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    (hasher.finish() % count as u64) as usize
+}
+
+async fn work<E: ContractExecutor>(
+    mut executor: E,
+    shard: Shard,
+    mut jobs: Receiver<Job>,
+    done: UnboundedSender<Done>,
+) {
+    while let Some(job) = jobs.recv().await {
+        let result = match job {
+            Job::Call(id, event) => match super::execute(&mut executor, event).await {
+                Some(response) => Done::Call(id, response),
+                None => continue,
+            },
+            Job::Maintain { round, now } => {
+                let updated = executor
+                    .maintain_contracts(now, shard)
+                    .instrument(tracing::info_span!("maintain_contracts"))
+                    .await;
+                Done::Maintained { round, updated }
+            }
+        };
+        if done.send(result).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::client_api::DelegateRequest;
+
+    use super::*;
+
+    fn pool(executors: usize) -> ExecutorPool {
+        let (done_tx, done) = mpsc::unbounded_channel::<Done>();
+        drop(done_tx);
+        ExecutorPool {
+            executors: (0..executors).map(|_| mpsc::channel(1).0).collect(),
+            done,
+            rounds: 0,
+            maintenance: HashMap::new(),
+            answered: VecDeque::new(),
+        }
+    }
+
+    #[test]
+    fn contracts_keep_their_executor() {
+        let pool = pool(4);
+        let keys: Vec<_> = (0..64u8)
+            .map(|byte| ContractKey::from(ContractInstanceId::new([byte; 32])))
+            .collect();
+        let assigned: Vec<_> = keys.iter().map(|key| pool.executor_of(key)).collect();
+        assert_eq!(
+            assigned,
+            keys.iter()
+                .map(|key| pool.executor_of(key))
+                .collect::<Vec<_>>()
+        );
+        // the contracts are spread across all the executors
+        for executor in 0..4 {
+            assert!(assigned.contains(&executor));
+        }
+    }
+
+    #[test]
+    fn shards_own_the_contracts_of_their_executor() {
+        let pool = pool(4);
+        for byte in 0..64u8 {
+            let key = ContractKey::from(ContractInstanceId::new([byte; 32]));
+            let owners: Vec<_> = (0..4)
+                .filter(|index| {
+                    Shard {
+                        index: *index,
+                        count: 4,
+                    }
+                    .owns(&key)
+                })
+                .collect();
+            assert_eq!(owners, vec![pool.executor_of(&key)]);
+        }
+    }
+
+    #[test]
+    fn migrated_states_are_put_by_the_executor_of_the_new_version() {
+        let pool = pool(64);
+        let delegate = DelegateKey::new([1; 32], CodeHash::new([2; 32]));
+        let migrate = ContractHandlerEvent::MigrateQuery {
+            from: ContractKey::from(ContractInstanceId::new([3; 32])),
+            migration_delegate: delegate.clone(),
+            delegate_params: Parameters::from(vec![]),
+            attested_contract: None,
+        };
+        assert_eq!(pool.executor_for(&migrate), Some(pool.slot(&delegate)));
+
+        // the new version is put as any other contract, by the executor its calls go to
+        let to = (0..=u8::MAX)
+            .map(|byte| ContractKey::from(ContractInstanceId::new([byte; 32])))
+            .find(|key| pool.executor_of(key) != pool.slot(&delegate))
+            .unwrap();
+        let put = ContractHandlerEvent::PutQuery {
+            key: to,
+            state: WrappedState::new(vec![]),
+            related_contracts: RelatedContracts::default(),
+            contract: None,
+        };
+        let update = ContractHandlerEvent::UpdateQuery {
+            key: to,
+            data: UpdateData::State(State::from(vec![])),
+            related_contracts: RelatedContracts::default(),
+        };
+        assert_eq!(pool.executor_for(&put), pool.executor_for(&update));
+        assert_ne!(pool.executor_for(&put), pool.executor_for(&migrate));
+    }
+
+    #[test]
+    fn delegate_requests_keep_their_executor() {
+        let pool = pool(64);
+        let delegate = DelegateKey::new([1; 32], CodeHash::new([2; 32]));
+        let messages = ContractHandlerEvent::DelegateQuery {
+            req: DelegateRequest::ApplicationMessages {
+                key: delegate.clone(),
+                params: Parameters::from(vec![]),
+                inbound: vec![],
+            },
+            attested_contract: Some(ContractInstanceId::new([3; 32])),
+        };
+        let unregister = ContractHandlerEvent::DelegateQuery {
+            req: DelegateRequest::UnregisterDelegate(delegate.clone()),
+            attested_contract: None,
+        };
+        assert_eq!(pool.executor_for(&messages), Some(pool.slot(&delegate)));
+        assert_eq!(pool.executor_for(&unregister), Some(pool.slot(&delegate)));
+    }
+
+    #[tokio::test]
+    async fn calls_no_executor_takes_are_answered() {
+        let mut pool = pool(4);
+        let id = EventId { id: 1 };
+        let response = ContractHandlerEvent::MaintenanceResponse { updated: vec![] };
+        assert!(matches!(
+            pool.execute(id, response).await,
+            Err(ContractError::UnexpectedEvent(_))
+        ));
+
+        // the executors of the test pool are all gone
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let summary = ContractHandlerEvent::SummaryQuery { key };
+        assert!(matches!(
+            pool.execute(id, summary).await,
+            Err(ContractError::ExecutorStopped)
+        ));
+
+        let maintenance = ContractHandlerEvent::MaintenanceQuery {
+            now: Duration::from_secs(1),
+        };
+        pool.execute(id, maintenance).await.unwrap();
+        let Some((answered, ContractHandlerEvent::MaintenanceResponse { updated })) =
+            pool.next_response().await
+        else {
+            panic!("expected a maintenance response");
+        };
+        assert!(answered == id);
+        assert!(updated.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use freenet_stdlib::prelude::*;
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::wasm_runtime::StateStorage;
+
+/// Length of the checksum each file starts with.
+const CHECKSUM_LEN: usize = blake3::OUT_LEN;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileSystemError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("corrupted file {0:?}, its contents do not match their checksum")]
+    Corrupted(PathBuf),
+}
+
+/// Keeps each state and its parameters in a file of their own, in the data directory.
+///
+/// Files are written next to their final path and then renamed over it, so a node stopping
+/// halfway through a write leaves the previous version in place. Each file starts with the
+/// checksum of its contents, checked when loading it.
+#[derive(Clone)]
+pub struct FileSystem {
+    dir: PathBuf,
+}
+
+impl FileSystem {
+    pub async fn new(data_dir: &Path) -> Result<Self, FileSystemError> {
+        let dir = data_dir.join("states");
+        tracing::info!("loading contract store from {dir:?}");
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &ContractKey, extension: &str) -> PathBuf {
+        let name = bs58::encode(key.as_bytes()).into_string();
+        self.dir.join(name).with_extension(extension)
+    }
+
+    async fn write(&self, path: PathBuf, contents: &[u8]) -> Result<(), FileSystemError> {
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(blake3::hash(contents).as_bytes()).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        fs::rename(tmp, path).await?;
+        Ok(())
+    }
+
+    async fn read(&self, path: PathBuf) -> Result<Option<Vec<u8>>, FileSystemError> {
+        let Some((contents, checksum)) = self.read_unchecked(&path).await? else {
+            return Ok(None);
+        };
+        if blake3::hash(&contents) != checksum {
+            tracing::error!(?path, "Contract store file is corrupted");
+            return Err(FileSystemError::Corrupted(path));
+        }
+        Ok(Some(contents))
+    }
+
+    /// Contents of the file along with the checksum it starts with, without checking them.
+    async fn read_unchecked(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(Vec<u8>, blake3::Hash)>, FileSystemError> {
+        let mut contents = match fs::read(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if contents.len() < CHECKSUM_LEN {
+            tracing::error!(?path, "Contract store file is corrupted");
+            return Err(FileSystemError::Corrupted(path.to_owned()));
+        }
+        let checksum: [u8; CHECKSUM_LEN] = contents[..CHECKSUM_LEN].try_into().unwrap();
+        contents.drain(..CHECKSUM_LEN);
+        Ok(Some((contents, blake3::Hash::from(checksum))))
+    }
+
+    async fn delete(&self, path: PathBuf) -> Result<(), FileSystemError> {
+        match fs::remove_file(path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StateStorage for FileSystem {
+    type Error = FileSystemError;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        self.write(self.path(&key, "state"), state.as_ref()).await
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        self.write(self.path(&key, "params"), params.as_ref()).await
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        let state = self.read(self.path(key, "state")).await?;
+        Ok(state.map(WrappedState::new))
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        let params = self.read(self.path(key, "params")).await?;
+        Ok(params.map(Parameters::from))
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        self.delete(self.path(key, "state")).await?;
+        self.delete(self.path(key, "params")).await
+    }
+
+    async fn state_sizes(&self) -> Result<Vec<(ContractKey, u64)>, Self::Error> {
+        let mut sizes = vec![];
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("state") {
+                continue;
+            }
+            let key = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .and_then(|name| bs58::decode(name).into_vec().ok())
+                .and_then(|bytes| super::key_from_bytes(&bytes));
+            let Some(key) = key else {
+                tracing::warn!(?path, "Unknown file in the contract store");
+                continue;
+            };
+            let size = entry.metadata().await?.len();
+            sizes.push((key, size.saturating_sub(CHECKSUM_LEN as u64)));
+        }
+        Ok(sizes)
+    }
+
+    async fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error> {
+        let state = self.read_unchecked(&self.path(key, "state")).await?;
+        Ok(state.map(|(state, checksum)| (WrappedState::new(state), Some(checksum))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn corrupted_files_are_detected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut storage = FileSystem::new(dir.path()).await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        assert!(storage.get(&key).await?.is_none());
+
+        storage
+            .store(key.clone(), WrappedState::new(vec![1; 64]))
+            .await?;
+        storage
+            .store(key.clone(), WrappedState::new(vec![2; 64]))
+            .await?;
+        let state = storage.get(&key).await?.unwrap();
+        assert_eq!(state.as_ref(), &[2; 64]);
+        let path = storage.path(&key, "state");
+        assert!(!path.with_extension("state.tmp").exists());
+
+        assert_eq!(storage.state_sizes().await?, [(key, 64)]);
+
+        let mut contents = std::fs::read(&path)?;
+        *contents.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, contents)?;
+        assert!(matches!(
+            storage.get(&key).await,
+            Err(FileSystemError::Corrupted(_))
+        ));
+        // still readable along with the checksum it was written with, to tell it changed
+        let (state, digest) = storage.get_with_digest(&key).await?.unwrap();
+        assert_eq!(digest, Some(blake3::hash(&[2; 64])));
+        assert_ne!(blake3::hash(state.as_ref()), blake3::hash(&[2; 64]));
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use freenet_stdlib::prelude::*;
+use parking_lot::RwLock;
+
+use crate::wasm_runtime::StateStorage;
+
+/// Keeps the states in memory, so they are lost once the node stops. Clones share the same
+/// states.
+#[derive(Default, Clone)]
+pub struct InMemory {
+    states: Arc<RwLock<HashMap<ContractKey, (WrappedState, Option<blake3::Hash>)>>>,
+    params: Arc<RwLock<HashMap<ContractKey, Parameters<'static>>>>,
+}
+
+impl StateStorage for InMemory {
+    type Error = Infallible;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        let digest = blake3::hash(state.as_ref());
+        self.states.write().insert(key, (state, Some(digest)));
+        Ok(())
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        self.params.write().insert(key, params);
+        Ok(())
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        Ok(self.states.read().get(key).map(|(state, _)| state.clone()))
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        Ok(self.params.read().get(key).cloned())
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        self.states.write().remove(key);
+        self.params.write().remove(key);
+        Ok(())
+    }
+
+    async fn state_sizes(&self) -> Result<Vec<(ContractKey, u64)>, Self::Error> {
+        Ok(self
+            .states
+            .read()
+            .iter()
+            .map(|(key, (state, _))| (*key, state.size() as u64))
+            .collect())
+    }
+
+    async fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error> {
+        Ok(self
+            .states
+            .read()
+            .get(key)
+            .map(|(state, digest)| (state.clone(), *digest)))
+    }
+}
+
+#[cfg(test)]
+impl InMemory {
+    /// Replaces the bytes of the state, keeping the hash recorded for the previous ones.
+    pub fn corrupt(&self, key: &ContractKey, state: WrappedState) {
+        if let Some((stored, _)) = self.states.write().get_mut(key) {
+            *stored = state;
+        }
+    }
+
+    /// Stores the state without recording its hash, like older versions of the node did.
+    pub fn store_without_digest(&self, key: ContractKey, state: WrappedState) {
+        self.states.write().insert(key, (state, None));
+    }
+}
@@ -1,3 +1,10 @@
+use std::path::Path;
+
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::wasm_runtime::StateStorage;
+
 /// State storage implementation based on the `sqlite`
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
@@ -5,7 +12,7 @@ pub mod sqlite;
 pub use sqlite::Pool as SqlitePool;
 
 #[cfg(all(feature = "sqlite", not(feature = "redb")))]
-pub type Storage = SqlitePool;
+type EmbeddedDb = SqlitePool;
 
 /// State storage implementation based on the [`redb`]
 #[cfg(feature = "redb")]
@@ -14,4 +21,146 @@ pub mod redb;
 use self::redb::ReDb;
 
 #[cfg(feature = "redb")]
-pub type Storage = ReDb;
+type EmbeddedDb = ReDb;
+
+/// State storage implementation based on a file per state
+pub mod filesystem;
+/// State storage implementation which keeps the states in memory
+pub mod memory;
+
+use self::{filesystem::FileSystem, memory::InMemory};
+
+/// Where the contract states are persisted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// Kept in memory only, lost once the node stops.
+    InMemory,
+    /// A file per state in the data directory.
+    FileSystem,
+    /// The embedded database the node was built with, `redb` or `sqlite`.
+    #[default]
+    Embedded,
+}
+
+/// Cloning it shares the same underlying storage.
+#[derive(Clone)]
+pub enum Storage {
+    InMemory(InMemory),
+    FileSystem(FileSystem),
+    #[cfg(any(feature = "redb", feature = "sqlite"))]
+    Embedded(EmbeddedDb),
+}
+
+impl Storage {
+    /// Opens the embedded database in the given directory.
+    pub async fn new(data_dir: &Path) -> anyhow::Result<Self> {
+        Self::with_backend(StorageBackend::Embedded, data_dir).await
+    }
+
+    pub async fn with_backend(backend: StorageBackend, data_dir: &Path) -> anyhow::Result<Self> {
+        let storage = match backend {
+            StorageBackend::InMemory => Self::InMemory(InMemory::default()),
+            StorageBackend::FileSystem => Self::FileSystem(FileSystem::new(data_dir).await?),
+            #[cfg(feature = "redb")]
+            StorageBackend::Embedded => Self::Embedded(ReDb::new(data_dir).await?),
+            #[cfg(all(feature = "sqlite", not(feature = "redb")))]
+            StorageBackend::Embedded => Self::Embedded(SqlitePool::new(Some(data_dir)).await?),
+            #[cfg(not(any(feature = "redb", feature = "sqlite")))]
+            StorageBackend::Embedded => {
+                anyhow::bail!("node built without an embedded database for the contract states")
+            }
+        };
+        Ok(storage)
+    }
+}
+
+impl StateStorage for Storage {
+    type Error = anyhow::Error;
+
+    async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        match self {
+            Self::InMemory(s) => s.store(key, state).await?,
+            Self::FileSystem(s) => s.store(key, state).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.store(key, state).await?,
+        }
+        Ok(())
+    }
+
+    async fn store_params(
+        &mut self,
+        key: ContractKey,
+        params: Parameters<'static>,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Self::InMemory(s) => s.store_params(key, params).await?,
+            Self::FileSystem(s) => s.store_params(key, params).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.store_params(key, params).await?,
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &ContractKey) -> Result<Option<WrappedState>, Self::Error> {
+        let state = match self {
+            Self::InMemory(s) => s.get(key).await?,
+            Self::FileSystem(s) => s.get(key).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.get(key).await?,
+        };
+        Ok(state)
+    }
+
+    async fn get_params<'a>(
+        &'a self,
+        key: &'a ContractKey,
+    ) -> Result<Option<Parameters<'static>>, Self::Error> {
+        let params = match self {
+            Self::InMemory(s) => s.get_params(key).await?,
+            Self::FileSystem(s) => s.get_params(key).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.get_params(key).await?,
+        };
+        Ok(params)
+    }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        match self {
+            Self::InMemory(s) => s.remove(key).await?,
+            Self::FileSystem(s) => s.remove(key).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.remove(key).await?,
+        }
+        Ok(())
+    }
+
+    async fn state_sizes(&self) -> Result<Vec<(ContractKey, u64)>, Self::Error> {
+        let sizes = match self {
+            Self::InMemory(s) => s.state_sizes().await?,
+            Self::FileSystem(s) => s.state_sizes().await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.state_sizes().await?,
+        };
+        Ok(sizes)
+    }
+
+    async fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error> {
+        let state = match self {
+            Self::InMemory(s) => s.get_with_digest(key).await?,
+            Self::FileSystem(s) => s.get_with_digest(key).await?,
+            #[cfg(any(feature = "redb", feature = "sqlite"))]
+            Self::Embedded(s) => s.get_with_digest(key).await?,
+        };
+        Ok(state)
+    }
+}
+
+/// Key of the contract a state is stored under, from its bytes.
+fn key_from_bytes(bytes: &[u8]) -> Option<ContractKey> {
+    let id: [u8; 32] = bytes.try_into().ok()?;
+    Some(ContractKey::from(ContractInstanceId::new(id)))
+}
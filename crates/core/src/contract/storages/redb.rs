@@ -1,21 +1,24 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 use freenet_stdlib::prelude::*;
-use redb::{Database, TableDefinition};
+use redb::{Database, ReadableTable, TableDefinition};
 
 use crate::wasm_runtime::StateStorage;
 
 const CONTRACT_PARAMS_TABLE: TableDefinition<&[u8], &[u8]> =
     TableDefinition::new("contract_params");
 const STATE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("state");
+/// Hashes of the states, written along with them.
+const STATE_DIGEST_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("state_digest");
 
-pub struct ReDb(Database);
+#[derive(Clone)]
+pub struct ReDb(Arc<Database>);
 
 impl ReDb {
     pub async fn new(data_dir: &Path) -> Result<Self, redb::Error> {
         let db_path = data_dir.join("db");
         tracing::info!("loading contract store from {db_path:?}");
-        match Database::create(db_path).map(Self) {
+        match Database::create(db_path).map(|db| Self(Arc::new(db))) {
             Ok(db) => {
                 let txn = db.0.begin_write()?;
                 {
@@ -28,6 +31,11 @@ impl ReDb {
                         tracing::error!(error = %e, "failed to open CONTRACT_PARAMS_TABLE");
                         e
                     })?;
+
+                    txn.open_table(STATE_DIGEST_TABLE).map_err(|e| {
+                        tracing::error!(error = %e, "failed to open STATE_DIGEST_TABLE");
+                        e
+                    })?;
                 }
                 txn.commit()?;
 
@@ -50,6 +58,11 @@ impl StateStorage for ReDb {
         {
             let mut tbl = txn.open_table(STATE_TABLE)?;
             tbl.insert(key.as_bytes(), state.as_ref())?;
+            let mut tbl = txn.open_table(STATE_DIGEST_TABLE)?;
+            tbl.insert(
+                key.as_bytes(),
+                blake3::hash(state.as_ref()).as_bytes().as_slice(),
+            )?;
         }
         txn.commit().map_err(Into::into)
     }
@@ -98,4 +111,45 @@ impl StateStorage for ReDb {
             None => Ok(None),
         }
     }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        let txn = self.0.begin_write()?;
+
+        {
+            txn.open_table(STATE_TABLE)?.remove(key.as_bytes())?;
+            txn.open_table(STATE_DIGEST_TABLE)?.remove(key.as_bytes())?;
+            txn.open_table(CONTRACT_PARAMS_TABLE)?
+                .remove(key.as_bytes())?;
+        }
+        txn.commit().map_err(Into::into)
+    }
+
+    async fn state_sizes(&self) -> Result<Vec<(ContractKey, u64)>, Self::Error> {
+        let txn = self.0.begin_read()?;
+        let tbl = txn.open_table(STATE_TABLE)?;
+        let mut sizes = vec![];
+        for entry in tbl.iter()? {
+            let (key, state) = entry?;
+            if let Some(key) = super::key_from_bytes(key.value()) {
+                sizes.push((key, state.value().len() as u64));
+            }
+        }
+        Ok(sizes)
+    }
+
+    async fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error> {
+        let txn = self.0.begin_read()?;
+        let Some(state) = txn.open_table(STATE_TABLE)?.get(key.as_bytes())? else {
+            return Ok(None);
+        };
+        let digest = txn
+            .open_table(STATE_DIGEST_TABLE)?
+            .get(key.as_bytes())?
+            .and_then(|digest| <[u8; blake3::OUT_LEN]>::try_from(digest.value()).ok())
+            .map(blake3::Hash::from);
+        Ok(Some((WrappedState::new(state.value().to_vec()), digest)))
+    }
 }
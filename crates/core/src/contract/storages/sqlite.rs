@@ -18,6 +18,14 @@ async fn create_contracts_table(pool: &SqlitePool) -> Result<(), SqlDbError> {
     )
     .execute(pool)
     .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS state_digests (
+            contract        BLOB PRIMARY KEY,
+            digest          BLOB NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
@@ -51,6 +59,7 @@ impl StateStorage for Pool {
     type Error = SqlDbError;
 
     async fn store(&mut self, key: ContractKey, state: WrappedState) -> Result<(), Self::Error> {
+        let mut txn = self.0.begin().await?;
         sqlx::query(
             "INSERT INTO states (contract, state) 
                      VALUES ($1, $2) 
@@ -59,8 +68,14 @@ impl StateStorage for Pool {
         )
         .bind(key.as_bytes())
         .bind(state.as_ref())
-        .execute(&self.0)
+        .execute(&mut *txn)
         .await?;
+        sqlx::query("INSERT OR REPLACE INTO state_digests (contract, digest) VALUES ($1, $2)")
+            .bind(key.as_bytes())
+            .bind(blake3::hash(state.as_ref()).as_bytes().as_slice())
+            .execute(&mut *txn)
+            .await?;
+        txn.commit().await?;
         Ok(())
     }
 
@@ -110,6 +125,56 @@ impl StateStorage for Pool {
             Err(_) => Err(SqlDbError::ContractNotFound),
         }
     }
+
+    async fn remove(&mut self, key: &ContractKey) -> Result<(), Self::Error> {
+        let mut txn = self.0.begin().await?;
+        sqlx::query("DELETE FROM states WHERE contract = ?")
+            .bind(key.as_bytes())
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM state_digests WHERE contract = ?")
+            .bind(key.as_bytes())
+            .execute(&mut *txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    async fn state_sizes(&self) -> Result<Vec<(ContractKey, u64)>, Self::Error> {
+        let rows = sqlx::query(
+            "SELECT contract, length(state) AS size FROM states WHERE state IS NOT NULL",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let key = super::key_from_bytes(row.get::<&[u8], _>("contract"))?;
+                Some((key, row.get::<i64, _>("size") as u64))
+            })
+            .collect())
+    }
+
+    async fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error> {
+        let row = sqlx::query(
+            "SELECT states.state, state_digests.digest FROM states
+                LEFT JOIN state_digests ON state_digests.contract = states.contract
+                WHERE states.contract = ? AND states.state IS NOT NULL",
+        )
+        .bind(key.as_bytes())
+        .fetch_optional(&self.0)
+        .await?;
+        Ok(row.map(|row| {
+            let digest = row
+                .get::<Option<Vec<u8>>, _>("digest")
+                .and_then(|digest| <[u8; blake3::OUT_LEN]>::try_from(digest.as_slice()).ok())
+                .map(blake3::Hash::from);
+            (WrappedState::new(row.get("state")), digest)
+        }))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -33,8 +33,10 @@ mod wasm_runtime;
 /// Exports to build a running local node.
 pub mod local_node {
     use super::*;
+    pub use contract::storages::StorageBackend;
     pub use contract::Executor;
     pub use contract::OperationMode;
+    pub use node::LocalNode;
 }
 
 /// Exports for the dev tool.
@@ -42,19 +44,42 @@ pub mod dev_tool {
     use super::*;
     pub use crate::config::Config;
     pub use client_events::{
-        test::MemoryEventsGen, test::NetworkEventGenerator, ClientEventsProxy, ClientId,
-        OpenRequest,
+        notification_channel, test::MemoryEventsGen, test::NetworkEventGenerator,
+        ClientEventsProxy, ClientId, ClientNotification, ConnectivityEvent, NotificationReceiver,
+        NotificationSender, OpenRequest,
+    };
+    pub use contract::{
+        storages::{Storage, StorageBackend},
+        CodeCollectionConfig, Executor, OperationMode,
     };
-    pub use contract::{storages::Storage, Executor, OperationMode};
     pub use flatbuffers;
     pub use message::Transaction;
     pub use node::{
-        testing_impl::{EventChain, NetworkPeer, NodeLabel, PeerMessage, PeerStatus, SimNetwork},
-        InitPeerNode, NodeConfig, PeerId,
+        testing_impl::{
+            EventChain, NetworkPeer, NodeLabel, PeerMessage, PeerStatus, SimNetwork, WireBreaks,
+            WireFeatures,
+        },
+        BootstrapConfig, GatewayServiceConfig, InitPeerNode, JoinQuota, LatencyHistogram,
+        MessagePolicingConfig, MessageQuota, MetricsReader, NodeConfig, NodeConfigBuilder,
+        NodeLifecycleEvent, NodeMetrics, OpMetrics, PeerId, PortMapping, RingProber, SeedSource,
+    };
+    pub use operations::probe::{ProbeResponse, ProbeVisit};
+    pub use ring::{
+        AcceptancePolicy, CapacityBased, CircuitBreakerConfig, ClockSkew, ConnectionCandidate,
+        CoverageGap, DistanceBased, DistanceBucket, EvictionReason, GatewaysAcceptAll, Location,
+        LocationAssignment, NeighbourInfo, NetworkStatus, PolicyChain, ReputationWeighted,
+        TopologyEvent, TopologyEventKind, TopologySnapshot, Verdict,
+    };
+    pub use transport::{
+        DualStack, NetworkId, TransportKeypair, TransportKind, TransportPublicKey,
+    };
+    pub use util::time_source::{Clock, TokioClock, VirtualClock};
+    pub use wasm_runtime::{
+        CodeRejection, ContractAbi, ContractCall, ContractImports, ContractLimits, ContractStore,
+        DelegateExecStats, DelegateLimits, DelegateStore, HostFunction, HostImport, Runtime,
+        SecretsStore, StateJournalLimits, StateStore, ABI_SECTION, IMMUTABLE_SECTION,
+        MAINTENANCE_SECTION,
     };
-    pub use ring::Location;
-    pub use transport::{TransportKeypair, TransportPublicKey};
-    pub use wasm_runtime::{ContractStore, DelegateStore, Runtime, SecretsStore, StateStore};
 }
 
 #[cfg(test)]
@@ -3,17 +3,22 @@
 use std::{
     borrow::{Borrow, Cow},
     fmt::Display,
-    time::{Duration, SystemTime},
+    net::SocketAddr,
+    time::Duration,
 };
 
-use freenet_stdlib::prelude::{ContractContainer, ContractKey, WrappedState};
+use freenet_stdlib::{
+    client_api::{ClientError, HostResponse},
+    prelude::{ContractContainer, ContractKey, WrappedState},
+};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
 use crate::{
-    node::PeerId,
+    node::{Codec, PeerId, ReaddressNotice, SignedEnvelope},
     operations::{
-        connect::ConnectMsg, get::GetMsg, put::PutMsg, subscribe::SubscribeMsg, update::UpdateMsg,
+        connect::ConnectMsg, get::GetMsg, probe::ProbeMsg, put::PutMsg, state_transfer::StateChunk,
+        subscribe::SubscribeMsg, update::UpdateMsg,
     },
     ring::{Location, PeerKeyLocation},
 };
@@ -53,29 +58,33 @@ impl Transaction {
             2 => TransactionType::Get,
             3 => TransactionType::Subscribe,
             4 => TransactionType::Update,
+            5 => TransactionType::Probe,
             _ => unsafe { std::hint::unreachable_unchecked() },
         }
     }
 
-    pub fn timed_out(&self) -> bool {
-        self.elapsed() >= crate::config::OPERATION_TTL
+    /// Whether the transaction outlived its TTL at the given time, since the unix epoch,
+    /// as read from the node clock.
+    pub fn timed_out(&self, now: Duration) -> bool {
+        self.elapsed(now) >= crate::config::OPERATION_TTL
     }
 
     #[cfg(feature = "trace-ot")]
-    pub fn started(&self) -> SystemTime {
-        SystemTime::UNIX_EPOCH + Duration::from_millis(self.id.timestamp_ms())
+    pub fn started(&self) -> std::time::SystemTime {
+        std::time::SystemTime::UNIX_EPOCH + Duration::from_millis(self.id.timestamp_ms())
     }
 
-    #[cfg(feature = "trace-ot")]
-    pub fn as_bytes(&self) -> [u8; 16] {
+    pub(crate) fn as_bytes(&self) -> [u8; 16] {
         self.id.0.to_le_bytes()
     }
 
-    fn elapsed(&self) -> Duration {
-        let current_unix_epoch_ts = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("now should be always be later than unix epoch")
-            .as_millis() as u64;
+    /// Creation time of the transaction, in milliseconds since the unix epoch.
+    pub(crate) fn timestamp_ms(&self) -> u64 {
+        self.id.timestamp_ms()
+    }
+
+    fn elapsed(&self, now: Duration) -> Duration {
+        let current_unix_epoch_ts = now.as_millis() as u64;
         let this_tx_creation = self.id.timestamp_ms();
         if current_unix_epoch_ts < this_tx_creation {
             Duration::new(0, 0)
@@ -89,9 +98,9 @@ impl Transaction {
     ///
     /// This will allow, for example, to compare against any older transactions,
     /// in order to remove them.
-    pub fn ttl_transaction() -> Self {
+    pub fn ttl_transaction(now: Duration) -> Self {
         let id = Ulid::new();
-        let ts = id.timestamp_ms();
+        let ts = now.as_millis() as u64;
         const TTL_MS: u64 = crate::config::OPERATION_TTL.as_millis() as u64;
         let ttl_epoch: u64 = ts - TTL_MS;
 
@@ -180,6 +189,7 @@ mod sealed_msg_type {
         Get = 2,
         Subscribe = 3,
         Update = 4,
+        Probe = 5,
     }
 
     impl TransactionType {
@@ -190,6 +200,7 @@ mod sealed_msg_type {
                 TransactionType::Get => "get",
                 TransactionType::Subscribe => "subscribe",
                 TransactionType::Update => "update",
+                TransactionType::Probe => "probe",
             }
         }
     }
@@ -223,7 +234,8 @@ mod sealed_msg_type {
         Put -> PutMsg,
         Get -> GetMsg,
         Subscribe -> SubscribeMsg,
-        Update -> UpdateMsg
+        Update -> UpdateMsg,
+        Probe -> ProbeMsg
     });
 }
 
@@ -240,6 +252,7 @@ pub(crate) enum NetMessage {
     V1(NetMessageV1),
 }
 
+// The variant tags are part of the wire format: new variants go at the end.
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) enum NetMessageV1 {
     Connect(ConnectMsg),
@@ -253,6 +266,141 @@ pub(crate) enum NetMessageV1 {
     },
     Update(UpdateMsg),
     Aborted(Transaction),
+    Probe(ProbeMsg),
+    /// The sender is leaving the network and the connection can be dropped.
+    Leaving {
+        transaction: Transaction,
+        from: PeerId,
+    },
+    /// Checks the target neighbour is still alive, the ping must be sent to the target
+    /// peer if it is not the receiver.
+    Ping {
+        transaction: Transaction,
+        target: PeerId,
+        from: PeerId,
+        nonce: u64,
+    },
+    /// Answer to a ping, carrying the nonce of the ping.
+    Pong {
+        transaction: Transaction,
+        from: PeerId,
+        nonce: u64,
+    },
+    /// The sender never caches contract states, so it is not counted among the holders of
+    /// the contracts close to it.
+    NonCaching {
+        transaction: Transaction,
+        from: PeerId,
+    },
+    /// The sender moved to a new location in the ring.
+    Relocated {
+        transaction: Transaction,
+        from: PeerId,
+        location: Location,
+    },
+    /// The peer moved to a new address, the notice must be relayed to the target peer
+    /// if it is not the receiver.
+    Readdress {
+        transaction: Transaction,
+        notice: ReaddressNotice,
+        target: PeerId,
+    },
+    /// Asks a gateway to introduce the sender to a peer it failed to connect to directly.
+    PunchRequest {
+        transaction: Transaction,
+        from: PeerKeyLocation,
+        target: PeerId,
+    },
+    /// Sent by a gateway to both ends of a punch request, so each connects to the other
+    /// at the same time and their NATs let the connection through.
+    PunchIntroduction {
+        transaction: Transaction,
+        target: PeerId,
+        peer: PeerKeyLocation,
+        via: PeerId,
+    },
+    /// A message between two peers which could not connect directly, relayed through
+    /// a gateway connected to both.
+    Relayed {
+        transaction: Transaction,
+        target: PeerId,
+        msg: Box<NetMessage>,
+    },
+    /// Codecs the sender can decompress, the receiver may compress the messages it sends
+    /// with any of them from then on.
+    CompressionOffer {
+        transaction: Transaction,
+        codecs: Vec<Codec>,
+    },
+    /// Asks the receiver which address it sees the sender at.
+    AddressProbe {
+        transaction: Transaction,
+    },
+    /// Answer to an address probe, with the address the prober is seen at.
+    ObservedAddress {
+        transaction: Transaction,
+        addr: SocketAddr,
+    },
+    /// Time of the clock of the sender, in milliseconds since the unix epoch, so the receiver
+    /// echoes it back along with the time of its own clock.
+    ClockProbe {
+        transaction: Transaction,
+        sent_at: u64,
+    },
+    /// Answer to a clock probe, with the time of the clock of the prober it was sent at and
+    /// the time of the clock of the sender on answering.
+    ClockReply {
+        transaction: Transaction,
+        sent_at: u64,
+        replied_at: u64,
+    },
+    /// Addresses the sender listens on, so it can be reached over either IP family.
+    AddressAdvertisement {
+        transaction: Transaction,
+        addrs: Vec<SocketAddr>,
+    },
+    /// A message compressed with a codec offered by the receiver.
+    Compressed {
+        transaction: Transaction,
+        codec: Codec,
+        payload: Vec<u8>,
+    },
+    /// Part of a message carrying a large contract state, split for transfer.
+    StateChunk(StateChunk),
+    /// Get requests for different contracts heading to the same peer, sent together.
+    GetBatch {
+        transaction: Transaction,
+        target: PeerKeyLocation,
+        requests: Vec<GetMsg>,
+    },
+    /// An operation message sealed by its sender, so it can't be forged or replayed by the
+    /// peers it passes through.
+    Authenticated(Box<SignedEnvelope>),
+    /// Sent to an address advertised by the receiver, which echoes the nonce back over the
+    /// connection to prove it can be reached at that address.
+    ReachabilityProbe {
+        transaction: Transaction,
+        nonce: u64,
+    },
+    /// Answer to a reachability probe, with its nonce.
+    ReachabilityAck {
+        transaction: Transaction,
+        nonce: u64,
+    },
+    /// Cancels a get request sent to the receiver, which stops relaying it and cancels it
+    /// further along its path. Sent by the requester once another of the requests it sent at
+    /// the same time was served. If the request subscribed and was already served, the
+    /// subscription it registered for the sender is dropped.
+    CancelGet {
+        transaction: Transaction,
+        key: ContractKey,
+        subscribe: bool,
+    },
+    /// Refuses an operation request, the receiver being at its max number of concurrent
+    /// operations, so the sender does not wait for the operation to time out.
+    Busy {
+        transaction: Transaction,
+    },
 }
 
 trait Versioned {
@@ -276,7 +424,31 @@ impl Versioned for NetMessageV1 {
             NetMessageV1::Subscribe(_) => semver::Version::new(1, 0, 0),
             NetMessageV1::Unsubscribed { .. } => semver::Version::new(1, 0, 0),
             NetMessageV1::Update(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::Probe(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::Leaving { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::NonCaching { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Relocated { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Ping { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Pong { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Readdress { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::PunchRequest { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::PunchIntroduction { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Relayed { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::CompressionOffer { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::AddressProbe { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::ObservedAddress { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::ClockProbe { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::ClockReply { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::AddressAdvertisement { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Compressed { .. } => semver::Version::new(1, 0, 0),
             NetMessageV1::Aborted(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::StateChunk(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::GetBatch { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Authenticated(_) => semver::Version::new(1, 0, 0),
+            NetMessageV1::ReachabilityProbe { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::ReachabilityAck { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::CancelGet { .. } => semver::Version::new(1, 0, 0),
+            NetMessageV1::Busy { .. } => semver::Version::new(1, 0, 0),
         }
     }
 }
@@ -293,6 +465,17 @@ pub(crate) trait InnerMessage: Into<NetMessage> {
     fn target(&self) -> Option<impl Borrow<PeerKeyLocation>>;
 
     fn requested_location(&self) -> Option<Location>;
+
+    /// Hops left for the message to be forwarded, if it is routed through the network.
+    fn hops_to_live(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether the message replies to a request sent from the receiving peer, so it is only
+    /// expected while the operation is in progress there.
+    fn is_response(&self) -> bool {
+        false
+    }
 }
 
 type RemainingChecks = Option<usize>;
@@ -316,6 +499,41 @@ pub(crate) enum NodeEvent {
     QueryConnections {
         callback: tokio::sync::mpsc::Sender<QueryResult>,
     },
+    /// The address of this peer changed, notify the connected peers so they can migrate
+    /// the connections to the new address.
+    AddressChanged {
+        new_addr: SocketAddr,
+    },
+    /// Time to check whether the address of this peer changed.
+    CheckAddress,
+    /// The addresses this peer can be reached at changed, advertise them again to the
+    /// connected peers.
+    AdvertiseAddresses,
+    /// This peer is in an over-crowded arc of the ring and should move to the given location.
+    Relocate {
+        location: Location,
+    },
+    /// A gateway introduced a peer this peer could not connect to directly, try connecting
+    /// to it at the same time as it does to punch through their NATs.
+    HolePunch {
+        peer: PeerKeyLocation,
+        via: PeerId,
+    },
+    /// All connections of this peer were lost for a while, join the network again.
+    Disconnected,
+    /// This peer is connected to the network again after having been disconnected.
+    Reconnected {
+        offline_for: Duration,
+    },
+    /// Change the quota of the messages of a transaction type received from each peer.
+    SetMessageQuota {
+        tx_type: TransactionType,
+        quota: crate::node::MessageQuota,
+    },
+    /// Gateways discovered from the seed lists while the node runs.
+    GatewaysDiscovered {
+        gateways: Vec<PeerKeyLocation>,
+    },
 }
 
 pub(crate) enum QueryResult {
@@ -325,6 +543,11 @@ pub(crate) enum QueryResult {
         state: WrappedState,
         contract: Option<ContractContainer>,
     },
+    /// The request was refused locally before reaching the network, e.g. because the
+    /// contract rejected the update.
+    Failed(ClientError),
+    /// The response of a delegate executed locally.
+    Delegate(HostResponse),
 }
 
 impl Display for NodeEvent {
@@ -345,6 +568,76 @@ impl Display for NodeEvent {
             NodeEvent::QueryConnections { .. } => {
                 write!(f, "QueryConnections")
             }
+            NodeEvent::AddressChanged { new_addr } => {
+                write!(f, "AddressChanged (to {new_addr})")
+            }
+            NodeEvent::CheckAddress => {
+                write!(f, "CheckAddress")
+            }
+            NodeEvent::AdvertiseAddresses => {
+                write!(f, "AdvertiseAddresses")
+            }
+            NodeEvent::Relocate { location } => {
+                write!(f, "Relocate (to {location})")
+            }
+            NodeEvent::HolePunch { peer, .. } => {
+                write!(f, "HolePunch (to {})", peer.peer)
+            }
+            NodeEvent::Disconnected => {
+                write!(f, "Disconnected")
+            }
+            NodeEvent::Reconnected { offline_for } => {
+                write!(f, "Reconnected (after {offline_for:?})")
+            }
+            NodeEvent::SetMessageQuota { tx_type, .. } => {
+                write!(f, "SetMessageQuota (of {tx_type})")
+            }
+            NodeEvent::GatewaysDiscovered { gateways } => {
+                write!(f, "GatewaysDiscovered ({} gateways)", gateways.len())
+            }
+        }
+    }
+}
+
+impl NetMessage {
+    /// Whether the message is about the connection with the peer or the node itself rather
+    /// than part of an operation, so it is cheap to process and must not wait behind operations.
+    pub(crate) fn is_control(&self) -> bool {
+        use NetMessageV1::*;
+        match self {
+            NetMessage::V1(msg) => match msg {
+                Connect(_)
+                | Put(_)
+                | Get(_)
+                | Subscribe(_)
+                | Update(_)
+                | Probe(_)
+                | StateChunk(_)
+                | GetBatch { .. }
+                | Authenticated(_)
+                | Compressed { .. } => false,
+                Relayed { msg, .. } => msg.is_control(),
+                Unsubscribed { .. }
+                | Aborted(_)
+                | Leaving { .. }
+                | Ping { .. }
+                | Pong { .. }
+                | NonCaching { .. }
+                | Relocated { .. }
+                | Readdress { .. }
+                | PunchRequest { .. }
+                | PunchIntroduction { .. }
+                | CompressionOffer { .. }
+                | AddressProbe { .. }
+                | ObservedAddress { .. }
+                | ClockProbe { .. }
+                | ClockReply { .. }
+                | AddressAdvertisement { .. }
+                | ReachabilityProbe { .. }
+                | ReachabilityAck { .. }
+                | CancelGet { .. }
+                | Busy { .. } => true,
+            },
         }
     }
 }
@@ -377,8 +670,32 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.id(),
             NetMessageV1::Subscribe(op) => op.id(),
             NetMessageV1::Update(op) => op.id(),
+            NetMessageV1::Probe(op) => op.id(),
             NetMessageV1::Aborted(tx) => tx,
             NetMessageV1::Unsubscribed { transaction, .. } => transaction,
+            NetMessageV1::Leaving { transaction, .. } => transaction,
+            NetMessageV1::NonCaching { transaction, .. } => transaction,
+            NetMessageV1::Relocated { transaction, .. } => transaction,
+            NetMessageV1::Ping { transaction, .. } => transaction,
+            NetMessageV1::Pong { transaction, .. } => transaction,
+            NetMessageV1::Readdress { transaction, .. } => transaction,
+            NetMessageV1::PunchRequest { transaction, .. } => transaction,
+            NetMessageV1::PunchIntroduction { transaction, .. } => transaction,
+            NetMessageV1::Relayed { transaction, .. } => transaction,
+            NetMessageV1::CompressionOffer { transaction, .. } => transaction,
+            NetMessageV1::AddressProbe { transaction } => transaction,
+            NetMessageV1::ObservedAddress { transaction, .. } => transaction,
+            NetMessageV1::ClockProbe { transaction, .. } => transaction,
+            NetMessageV1::ClockReply { transaction, .. } => transaction,
+            NetMessageV1::AddressAdvertisement { transaction, .. } => transaction,
+            NetMessageV1::Compressed { transaction, .. } => transaction,
+            NetMessageV1::StateChunk(chunk) => &chunk.id,
+            NetMessageV1::GetBatch { transaction, .. } => transaction,
+            NetMessageV1::Authenticated(envelope) => &envelope.transaction,
+            NetMessageV1::ReachabilityProbe { transaction, .. } => transaction,
+            NetMessageV1::ReachabilityAck { transaction, .. } => transaction,
+            NetMessageV1::CancelGet { transaction, .. } => transaction,
+            NetMessageV1::Busy { transaction } => transaction,
         }
     }
 
@@ -389,8 +706,32 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.target().as_ref().map(|b| b.borrow().clone()),
             NetMessageV1::Subscribe(op) => op.target().as_ref().map(|b| b.borrow().clone()),
             NetMessageV1::Update(op) => op.target().as_ref().map(|b| b.borrow().clone()),
+            NetMessageV1::Probe(op) => op.target().as_ref().map(|b| b.borrow().clone()),
             NetMessageV1::Aborted(_) => None,
             NetMessageV1::Unsubscribed { .. } => None,
+            NetMessageV1::Leaving { .. } => None,
+            NetMessageV1::NonCaching { .. } => None,
+            NetMessageV1::Relocated { .. } => None,
+            NetMessageV1::Ping { .. } => None,
+            NetMessageV1::Pong { .. } => None,
+            NetMessageV1::Readdress { .. } => None,
+            NetMessageV1::PunchRequest { .. } => None,
+            NetMessageV1::PunchIntroduction { .. } => None,
+            NetMessageV1::Relayed { .. } => None,
+            NetMessageV1::CompressionOffer { .. } => None,
+            NetMessageV1::AddressProbe { .. } => None,
+            NetMessageV1::ObservedAddress { .. } => None,
+            NetMessageV1::ClockProbe { .. } => None,
+            NetMessageV1::ClockReply { .. } => None,
+            NetMessageV1::AddressAdvertisement { .. } => None,
+            NetMessageV1::Compressed { .. } => None,
+            NetMessageV1::StateChunk(chunk) => Some(chunk.target.clone()),
+            NetMessageV1::GetBatch { target, .. } => Some(target.clone()),
+            NetMessageV1::Authenticated(_) => None,
+            NetMessageV1::ReachabilityProbe { .. } => None,
+            NetMessageV1::ReachabilityAck { .. } => None,
+            NetMessageV1::CancelGet { .. } => None,
+            NetMessageV1::Busy { .. } => None,
         }
     }
 
@@ -401,8 +742,32 @@ impl MessageStats for NetMessageV1 {
             NetMessageV1::Get(op) => op.requested_location(),
             NetMessageV1::Subscribe(op) => op.requested_location(),
             NetMessageV1::Update(op) => op.requested_location(),
+            NetMessageV1::Probe(op) => op.requested_location(),
             NetMessageV1::Aborted(_) => None,
             NetMessageV1::Unsubscribed { .. } => None,
+            NetMessageV1::Leaving { .. } => None,
+            NetMessageV1::NonCaching { .. } => None,
+            NetMessageV1::Relocated { .. } => None,
+            NetMessageV1::Ping { .. } => None,
+            NetMessageV1::Pong { .. } => None,
+            NetMessageV1::Readdress { .. } => None,
+            NetMessageV1::PunchRequest { .. } => None,
+            NetMessageV1::PunchIntroduction { .. } => None,
+            NetMessageV1::Relayed { .. } => None,
+            NetMessageV1::CompressionOffer { .. } => None,
+            NetMessageV1::AddressProbe { .. } => None,
+            NetMessageV1::ObservedAddress { .. } => None,
+            NetMessageV1::ClockProbe { .. } => None,
+            NetMessageV1::ClockReply { .. } => None,
+            NetMessageV1::AddressAdvertisement { .. } => None,
+            NetMessageV1::Compressed { .. } => None,
+            NetMessageV1::StateChunk(_) => None,
+            NetMessageV1::GetBatch { .. } => None,
+            NetMessageV1::Authenticated(_) => None,
+            NetMessageV1::ReachabilityProbe { .. } => None,
+            NetMessageV1::ReachabilityAck { .. } => None,
+            NetMessageV1::CancelGet { .. } => None,
+            NetMessageV1::Busy { .. } => None,
         }
     }
 }
@@ -418,10 +783,105 @@ impl Display for NetMessage {
                 Get(msg) => msg.fmt(f)?,
                 Subscribe(msg) => msg.fmt(f)?,
                 Update(msg) => msg.fmt(f)?,
+                Probe(msg) => msg.fmt(f)?,
                 Aborted(msg) => msg.fmt(f)?,
                 Unsubscribed { key, from, .. } => {
                     write!(f, "Unsubscribed {{  key: {}, from: {} }}", key, from)?;
                 }
+                Leaving { from, .. } => {
+                    write!(f, "Leaving {{ from: {from} }}")?;
+                }
+                NonCaching { from, .. } => {
+                    write!(f, "NonCaching {{ from: {from} }}")?;
+                }
+                Relocated { from, location, .. } => {
+                    write!(f, "Relocated {{ from: {from}, to: {location} }}")?;
+                }
+                Ping { target, from, .. } => {
+                    write!(f, "Ping {{ from: {from}, to: {target} }}")?;
+                }
+                Pong { from, .. } => {
+                    write!(f, "Pong {{ from: {from} }}")?;
+                }
+                Readdress { notice, .. } => {
+                    write!(
+                        f,
+                        "Readdress {{ from: {}, to: {} }}",
+                        notice.previous, notice.new_addr
+                    )?;
+                }
+                PunchRequest { from, target, .. } => {
+                    write!(f, "PunchRequest {{ from: {}, to: {target} }}", from.peer)?;
+                }
+                PunchIntroduction { target, peer, .. } => {
+                    write!(
+                        f,
+                        "PunchIntroduction {{ peer: {}, to: {target} }}",
+                        peer.peer
+                    )?;
+                }
+                Relayed { target, msg, .. } => {
+                    write!(f, "Relayed {{ to: {target}, msg: {msg} }}")?;
+                }
+                CompressionOffer { codecs, .. } => {
+                    write!(f, "CompressionOffer {{ codecs: {codecs:?} }}")?;
+                }
+                AddressProbe { .. } => {
+                    write!(f, "AddressProbe")?;
+                }
+                ObservedAddress { addr, .. } => {
+                    write!(f, "ObservedAddress {{ addr: {addr} }}")?;
+                }
+                ClockProbe { sent_at, .. } => {
+                    write!(f, "ClockProbe {{ sent_at: {sent_at} }}")?;
+                }
+                ClockReply {
+                    sent_at,
+                    replied_at,
+                    ..
+                } => {
+                    write!(
+                        f,
+                        "ClockReply {{ sent_at: {sent_at}, replied_at: {replied_at} }}"
+                    )?;
+                }
+                AddressAdvertisement { addrs, .. } => {
+                    write!(f, "AddressAdvertisement {{ addrs: {addrs:?} }}")?;
+                }
+                Compressed { codec, payload, .. } => {
+                    write!(
+                        f,
+                        "Compressed {{ codec: {codec:?}, size: {} }}",
+                        payload.len()
+                    )?;
+                }
+                StateChunk(chunk) => {
+                    write!(
+                        f,
+                        "StateChunk {{ id: {}, chunk: {}/{} }}",
+                        chunk.id, chunk.index, chunk.manifest.num_chunks
+                    )?;
+                }
+                GetBatch { requests, .. } => {
+                    write!(f, "GetBatch {{ requests: {} }}", requests.len())?;
+                }
+                Authenticated(envelope) => {
+                    write!(f, "Authenticated {{ from: {} }}", envelope.from)?;
+                }
+                ReachabilityProbe { nonce, .. } => {
+                    write!(f, "ReachabilityProbe {{ nonce: {nonce} }}")?;
+                }
+                ReachabilityAck { nonce, .. } => {
+                    write!(f, "ReachabilityAck {{ nonce: {nonce} }}")?;
+                }
+                CancelGet {
+                    transaction, key, ..
+                } => {
+                    write!(f, "CancelGet {{ tx: {transaction}, key: {key} }}")?;
+                }
+                Busy { transaction } => {
+                    write!(f, "Busy {{ tx: {transaction} }}")?;
+                }
             },
         };
         write!(f, "}}")
@@ -440,6 +900,7 @@ pub(crate) enum ConnectionResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::time_source::{Clock, TokioClock};
 
     #[test]
     fn pack_transaction_type() {
@@ -465,13 +926,114 @@ mod tests {
         );
     }
 
+    /// Peers running older releases rely on the bincode variant tags of the messages they know
+    /// about, so existing variants must never be reordered; new ones go at the end.
+    #[test]
+    fn wire_variant_tags_are_stable() {
+        // version tag (V1) followed by the message tag, both as little endian u32
+        let tags = |msg: NetMessageV1| {
+            let bytes = bincode::serialize(&NetMessage::V1(msg)).unwrap();
+            (
+                bytes[..4].to_vec(),
+                u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            )
+        };
+
+        let get = GetMsg::SeekNode {
+            id: Transaction::new::<GetMsg>(),
+            key: ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32])),
+            fetch_contract: false,
+            target: PeerKeyLocation::random(),
+            sender: PeerKeyLocation::random(),
+            htl: 10,
+            skip_list: vec![],
+        };
+        assert_eq!(tags(NetMessageV1::Get(get)), (vec![0, 0, 0, 0], 2));
+
+        let unsubscribed = NetMessageV1::Unsubscribed {
+            transaction: Transaction::new::<SubscribeMsg>(),
+            key: ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32])),
+            from: PeerKeyLocation::random().peer,
+        };
+        assert_eq!(tags(unsubscribed), (vec![0, 0, 0, 0], 4));
+
+        let tx = Transaction::new::<GetMsg>();
+        assert_eq!(tags(NetMessageV1::Aborted(tx)), (vec![0, 0, 0, 0], 6));
+        let bytes = bincode::serialize(&NetMessage::V1(NetMessageV1::Aborted(tx))).unwrap();
+        let NetMessage::V1(NetMessageV1::Aborted(decoded)) =
+            bincode::deserialize::<NetMessage>(&bytes).unwrap()
+        else {
+            panic!("unexpected message");
+        };
+        assert_eq!(decoded, tx);
+    }
+
+    #[test]
+    fn subscribing_gets_keep_seek_node_format() {
+        let seek = |subscribe| {
+            GetMsg::SeekNode {
+                id: Transaction::new::<GetMsg>(),
+                key: ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32])),
+                fetch_contract: true,
+                target: PeerKeyLocation::random(),
+                sender: PeerKeyLocation::random(),
+                htl: 10,
+                skip_list: vec![],
+            }
+            .subscribing(subscribe)
+        };
+        let plain = bincode::serialize(&seek(false)).unwrap();
+        let subscribing = bincode::serialize(&seek(true)).unwrap();
+        // the subscribing variant is appended after the existing ones, leaving their tags as is
+        assert_eq!(plain[..4], [1, 0, 0, 0]);
+        assert_eq!(subscribing[..4], [3, 0, 0, 0]);
+        assert_eq!(plain.len(), subscribing.len());
+        assert!(matches!(
+            bincode::deserialize(&subscribing).unwrap(),
+            GetMsg::SeekNodeAndSubscribe { htl: 10, .. }
+        ));
+    }
+
+    #[test]
+    fn control_messages() {
+        let tx = Transaction::new::<GetMsg>();
+        let ping = NetMessage::V1(NetMessageV1::Ping {
+            transaction: tx,
+            target: PeerId::random(),
+            from: PeerId::random(),
+            nonce: 1,
+        });
+        assert!(ping.is_control());
+        assert!(NetMessage::V1(NetMessageV1::Busy { transaction: tx }).is_control());
+
+        let get = NetMessage::from(GetMsg::SeekNode {
+            id: tx,
+            key: ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32])),
+            fetch_contract: false,
+            target: PeerKeyLocation::random(),
+            sender: PeerKeyLocation::random(),
+            htl: 10,
+            skip_list: vec![],
+        });
+        assert!(!get.is_control());
+        // relayed messages are whatever they carry
+        let relayed = NetMessage::V1(NetMessageV1::Relayed {
+            transaction: tx,
+            target: PeerId::random(),
+            msg: Box::new(get),
+        });
+        assert!(!relayed.is_control());
+    }
+
     #[test]
     fn get_ttl_cutoff_transaction() {
-        let ttl_tx = Transaction::ttl_transaction();
+        let now = TokioClock.unix_time();
+        let ttl_tx = Transaction::ttl_transaction(now);
         let original_tx = Transaction::new::<crate::operations::get::GetMsg>();
 
         assert!(original_tx > ttl_tx);
-        assert!(ttl_tx.timed_out());
+        assert!(ttl_tx.timed_out(now));
+        assert!(!original_tx.timed_out(now));
         assert!(
             original_tx.id.timestamp_ms() - ttl_tx.id.timestamp_ms()
                 >= crate::config::OPERATION_TTL.as_millis() as u64
@@ -15,36 +15,41 @@ use std::{
     hash::Hash,
     io::Read,
     net::{IpAddr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
     sync::Arc,
     time::Duration,
 };
 
 use anyhow::Context;
 use either::Either;
-use freenet_stdlib::{
-    client_api::{ClientRequest, ErrorKind},
-    prelude::ContractKey,
+use freenet_stdlib::prelude::{
+    ContractContainer, ContractInstanceId, ContractKey, DelegateKey, Parameters, RelatedContracts,
 };
 
 use rsa::pkcs8::DecodePublicKey;
 use serde::{Deserialize, Serialize};
-use tracing::Instrument;
 
+pub(crate) use self::client_transaction_tracker::ClientTransactionTracker;
 use self::p2p_impl::NodeP2P;
 use crate::{
-    client_events::{BoxedClient, ClientEventsProxy, ClientId, OpenRequest},
-    config::{Address, GatewayConfig, WebsocketApiConfig},
+    client_events::{BoxedClient, ClientId},
+    config::{Address, GatewayConfig, Keystore, NodeProfile, WebsocketApiConfig},
     contract::{
-        Callback, ClientResponsesSender, ContractError, ExecutorError, ExecutorToEventLoopChannel,
-        NetworkContractHandler,
+        storages::StorageBackend, Callback, CodeCollectionConfig, ContractError,
+        ExecutorToEventLoopChannel, NetworkContractHandler,
     },
     local_node::Executor,
-    message::{NetMessage, Transaction, TransactionType},
+    message::{NetMessage, NodeEvent, Transaction, TransactionType},
     operations::{
         connect::{self, ConnectOp},
-        get, put, subscribe, update, OpEnum, OpError, OpOutcome,
+        get,
+        probe::{self, ProbeResponse},
+        put, subscribe, update, OpEnum, OpError, OpOutcome,
+    },
+    ring::{
+        AcceptancePolicy, CircuitBreakerConfig, Location, LocationAssignment, NetworkStatus,
+        PeerKeyLocation, TopologyEvent, TopologySnapshot,
     },
-    ring::{Location, PeerKeyLocation},
     router::{RouteEvent, RouteOutcome},
     tracing::{EventRegister, NetEventLog, NetEventRegister},
 };
@@ -54,15 +59,46 @@ use crate::{
 };
 
 use crate::operations::handle_op_request;
-pub(crate) use network_bridge::{ConnectionError, EventLoopNotificationsSender, NetworkBridge};
+pub(crate) use network_bridge::{
+    authentication::SignedEnvelope,
+    compression::Codec,
+    hello::{Feature, PeerFeatures},
+    readdress::ReaddressNotice,
+    ConnectionError, EventLoopNotificationsSender, NetworkBridge,
+};
 
 use crate::topology::rate::Rate;
-use crate::transport::{TransportKeypair, TransportPublicKey};
+use crate::transport::{DualStack, NetworkId, TransportKeypair, TransportKind, TransportPublicKey};
+use crate::wasm_runtime::{ContractLimits, DelegateLimits, StateJournalLimits};
+use admin_api::AdminApiConfig;
+pub use bootstrap::{BootstrapConfig, SeedSource};
+pub(crate) use gateway::{Admission, GatewayService};
+pub use gateway::{GatewayServiceConfig, JoinQuota};
+pub(crate) use lifecycle::LifecycleEvents;
+pub use lifecycle::NodeLifecycleEvent;
+pub use local::LocalNode;
+pub use network_bridge::policing::{MessagePolicingConfig, MessageQuota};
+pub(crate) use op_dispatcher::{OpDispatcher, OpPriorities};
+pub use op_metrics::{LatencyHistogram, NodeMetrics, OpMetrics};
 pub(crate) use op_state_manager::{OpManager, OpNotAvailable};
-
+pub use port_mapping::PortMapping;
+
+mod admin_api;
+mod bootstrap;
+mod client_transaction_tracker;
+mod config_watch;
+mod gateway;
+mod lifecycle;
+mod local;
 mod network_bridge;
+mod network_map;
+mod op_dispatcher;
+mod op_metrics;
 mod op_state_manager;
 mod p2p_impl;
+mod port_mapping;
+mod prometheus;
+mod recent_transactions;
 pub(crate) mod testing_impl;
 
 pub struct Node(NodeP2P);
@@ -76,12 +112,68 @@ impl Node {
             .update_location(Some(location));
     }
 
+    /// Returns a handle to probe the ring from this node, usable while the node is running.
+    pub fn prober(&self) -> RingProber {
+        RingProber(self.0.op_manager.clone())
+    }
+
+    /// Returns a handle to read the operation metrics of this node, usable while the node is running.
+    pub fn metrics(&self) -> MetricsReader {
+        MetricsReader(self.0.op_manager.clone())
+    }
+
+    /// Returns a receiver notified whenever this node loses or regains connectivity with
+    /// the network, usable while the node is running.
+    pub fn network_status(&self) -> tokio::sync::watch::Receiver<NetworkStatus> {
+        self.0.op_manager.ring.network_status.subscribe()
+    }
+
+    /// Returns a receiver of the lifecycle events of this node, such as the connections
+    /// established and the operations failed, usable while the node is running.
+    pub fn events(&self) -> tokio::sync::broadcast::Receiver<NodeLifecycleEvent> {
+        self.0.op_manager.ring.lifecycle_events.subscribe()
+    }
+
     pub async fn run(self) -> anyhow::Result<()> {
         self.0.run_node().await?;
         Ok(())
     }
 }
 
+/// Measures the health of the ring by walking it from this node towards random locations.
+#[derive(Clone)]
+pub struct RingProber(Arc<OpManager>);
+
+impl RingProber {
+    /// Probes the ring towards a random location, returning the location and latency
+    /// of every peer in the path.
+    pub async fn probe(&self) -> anyhow::Result<ProbeResponse> {
+        let response = probe::probe(&self.0, Location::random()).await?;
+        Ok(response)
+    }
+
+    /// Returns the neighbourhood of this node, to check it has the expected small-world shape.
+    pub fn topology(&self) -> TopologySnapshot {
+        self.0.ring.topology_snapshot()
+    }
+
+    /// Returns the latest changes in the connections of this node, starting from the given
+    /// sequence number, to look into how its topology evolved.
+    pub fn topology_journal(&self, since: u64) -> Vec<TopologyEvent> {
+        self.0.ring.connection_manager.journal.since(since)
+    }
+}
+
+/// Reads the counters of the operations handled by a node.
+#[derive(Clone)]
+pub struct MetricsReader(Arc<OpManager>);
+
+impl MetricsReader {
+    pub fn snapshot(&self) -> NodeMetrics {
+        self.0.metrics()
+    }
+}
+
 /// When instancing a node you can either join an existing network or bootstrap a new network with a listener
 /// which will act as the initial provider. This initial peer will be listening at the provided port and assigned IP.
 /// If those are not free the instancing process will return an error.
@@ -106,6 +198,18 @@ pub struct NodeConfig {
     pub network_listener_ip: IpAddr,
     /// socket port to bind to the network listener.
     pub network_listener_port: u16,
+    /// Network this node belongs to, peers from other networks are refused.
+    pub network_id: NetworkId,
+    /// Transports to listen and dial peers on, from the most to the least preferred.
+    pub(crate) transports: Option<Vec<TransportKind>>,
+    /// Listener of the other IP family, besides the network listener IP.
+    pub(crate) dual_stack: DualStack,
+    /// Protocol the listener port is mapped on the router with, if any.
+    pub(crate) port_mapping: PortMapping,
+    /// Max bytes per second sent to all peers together, enforced by the transport.
+    pub(crate) upstream_bandwidth_limit: Option<usize>,
+    /// Max fraction of the upstream bandwidth limit a single peer may use.
+    pub(crate) peer_bandwidth_share: Option<f64>,
     pub(crate) peer_id: Option<PeerId>,
     pub(crate) config: Arc<Config>,
     /// At least one gateway is required for joining the network.
@@ -119,6 +223,81 @@ pub struct NodeConfig {
     pub(crate) min_number_conn: Option<usize>,
     pub(crate) max_upstream_bandwidth: Option<Rate>,
     pub(crate) max_downstream_bandwidth: Option<Rate>,
+    /// Max number of in-flight operations per transaction type, after which new
+    /// incoming requests of that type are rejected.
+    pub(crate) max_concurrent_ops: Option<usize>,
+    /// Number of peers the get requests started at this node are sent to at once.
+    pub(crate) get_parallelism: Option<usize>,
+    /// Contract in which the network-wide routing priors are published.
+    pub(crate) routing_priors_contract: Option<ContractKey>,
+    /// Size above which messages carrying contract states are transferred in chunks.
+    pub(crate) state_chunk_threshold: Option<usize>,
+    /// Size above which updated states are propagated to subscribers as a summary first.
+    pub(crate) update_summary_threshold: Option<usize>,
+    /// Size below which messages are sent to peers uncompressed.
+    pub(crate) compression_threshold: Option<usize>,
+    /// Reputation score below which peers are disconnected.
+    pub(crate) reputation_threshold: Option<f64>,
+    /// Time during which a peer this node disconnected from is not accepted again.
+    pub(crate) connection_cooldown: Option<Duration>,
+    /// Time between the pings sent to each neighbour to check it is still alive.
+    pub(crate) keepalive_interval: Option<Duration>,
+    /// Number of pings in a row a neighbour has to miss to be considered dead.
+    pub(crate) keepalive_failure_threshold: Option<u32>,
+    /// Thresholds at which requests stop being forwarded to the peers failing most of them.
+    pub(crate) circuit_breakers: CircuitBreakerConfig,
+    /// Time without any live neighbour after which the node joins the network again.
+    pub(crate) disconnected_timeout: Option<Duration>,
+    /// Idle connections kept per distance band, promoted when a connection is needed.
+    pub(crate) standby_connections: Option<usize>,
+    /// Decides which peers asking to connect are accepted, if not the default one.
+    #[serde(skip)]
+    pub(crate) acceptance_policy: Option<Arc<dyn AcceptancePolicy>>,
+    /// Weights of each transaction type when prioritizing the processing of inbound messages.
+    pub(crate) op_priorities: OpPriorities,
+    /// Fuel, memory and host functions contracts are executed with.
+    pub(crate) contract_limits: ContractLimits,
+    /// Executors the calls to different contracts are executed concurrently on.
+    pub(crate) contract_executors: Option<usize>,
+    /// Resource quotas delegates are executed under.
+    pub(crate) delegate_limits: DelegateLimits,
+    /// Where the contract states are persisted.
+    pub(crate) state_storage: StorageBackend,
+    /// Bytes of contract states persisted beyond which the least worth keeping are evicted.
+    pub(crate) state_storage_quota: Option<u64>,
+    /// How much of the history of the contract states is journaled.
+    pub(crate) state_journal: StateJournalLimits,
+    /// How often the contract code no longer referenced is collected.
+    pub(crate) code_collection: CodeCollectionConfig,
+    /// File to which anonymized snapshots of the network map are periodically written.
+    pub(crate) network_map_export: Option<PathBuf>,
+    /// File to which the changes in the topology around this node are appended.
+    pub(crate) topology_journal: Option<PathBuf>,
+    /// Local admin API to inspect and control the node at runtime, if enabled.
+    pub(crate) admin_api: Option<AdminApiConfig>,
+    /// Localhost port the metrics are served on in the Prometheus format, if enabled.
+    pub(crate) metrics_port: Option<u16>,
+    /// OpenTelemetry collector the spans of the operations are exported to, if enabled.
+    pub(crate) otlp_endpoint: Option<String>,
+    /// File the identity keypair of the node is persisted to, if not taken from the config.
+    pub(crate) identity_key_path: Option<PathBuf>,
+    /// Passphrase the persisted identity keypair is encrypted with.
+    #[serde(skip)]
+    pub(crate) identity_passphrase: Option<String>,
+    /// Age after which the persisted identity keypair is replaced on start.
+    pub(crate) identity_rotation: Option<Duration>,
+    /// How locations are assigned to the peers joining through this node.
+    pub(crate) location_assignment: LocationAssignment,
+    /// Behaviour of the node as a gateway, if it is one.
+    pub(crate) gateway_service: GatewayServiceConfig,
+    /// Rate limits of the messages received from peers and the bans of the abusive ones.
+    pub(crate) message_policing: MessagePolicingConfig,
+    /// Seed lists further gateways are discovered from on start.
+    pub(crate) bootstrap: BootstrapConfig,
+    /// File the messages received from peers are recorded to, if any.
+    pub(crate) message_recording: Option<PathBuf>,
+    /// Recording of the messages of a node fed to this one once started, if any.
+    pub(crate) message_replay: Option<PathBuf>,
 }
 
 impl NodeConfig {
@@ -151,7 +330,9 @@ impl NodeConfig {
         if let Some(peer_id) = &config.peer_id {
             tracing::info!("Node external address: {}", peer_id.addr);
         }
-        Ok(NodeConfig {
+        let profile = config.profile;
+        let seeds = config.seeds.clone();
+        let mut node_config = NodeConfig {
             should_connect: true,
             is_gateway: config.is_gateway,
             key_pair: config.transport_keypair().clone(),
@@ -159,15 +340,62 @@ impl NodeConfig {
             peer_id: config.peer_id.clone(),
             network_listener_ip: config.network_api.address,
             network_listener_port: config.network_api.port,
+            network_id: config.network_api.network_id,
+            transports: None,
+            dual_stack: DualStack::default(),
+            port_mapping: PortMapping::default(),
+            upstream_bandwidth_limit: config.limits.upstream_bandwidth_limit,
+            peer_bandwidth_share: config.limits.peer_bandwidth_share,
+            max_number_conn: config.limits.max_connections,
+            min_number_conn: config.limits.min_connections,
             config: Arc::new(config),
             location: None,
             max_hops_to_live: None,
             rnd_if_htl_above: None,
-            max_number_conn: None,
-            min_number_conn: None,
             max_upstream_bandwidth: None,
             max_downstream_bandwidth: None,
-        })
+            max_concurrent_ops: None,
+            get_parallelism: None,
+            routing_priors_contract: None,
+            state_chunk_threshold: None,
+            update_summary_threshold: None,
+            compression_threshold: None,
+            reputation_threshold: None,
+            connection_cooldown: None,
+            keepalive_interval: None,
+            keepalive_failure_threshold: None,
+            circuit_breakers: CircuitBreakerConfig::default(),
+            disconnected_timeout: None,
+            standby_connections: None,
+            acceptance_policy: None,
+            op_priorities: OpPriorities::default(),
+            contract_limits: ContractLimits::default(),
+            contract_executors: None,
+            delegate_limits: DelegateLimits::default(),
+            state_storage: StorageBackend::default(),
+            state_storage_quota: None,
+            state_journal: StateJournalLimits::default(),
+            code_collection: CodeCollectionConfig::default(),
+            network_map_export: None,
+            topology_journal: None,
+            admin_api: None,
+            metrics_port: None,
+            otlp_endpoint: None,
+            identity_key_path: None,
+            identity_passphrase: None,
+            identity_rotation: None,
+            location_assignment: LocationAssignment::default(),
+            gateway_service: GatewayServiceConfig::default(),
+            message_policing: MessagePolicingConfig::default(),
+            bootstrap: BootstrapConfig {
+                sources: seeds,
+                ..Default::default()
+            },
+            message_recording: None,
+            message_replay: None,
+        };
+        node_config.with_profile(profile);
+        Ok(node_config)
     }
 
     async fn parse_socket_addr(address: &Address) -> anyhow::Result<SocketAddr> {
@@ -207,14 +435,7 @@ impl NodeConfig {
             Address::HostAddress(addr) => return Ok(*addr),
         };
 
-        let (conf, opts) = hickory_resolver::system_conf::read_system_conf()?;
-        let resolver = hickory_resolver::TokioAsyncResolver::new(
-            conf,
-            opts,
-            hickory_resolver::name_server::GenericConnector::new(
-                hickory_resolver::name_server::TokioRuntimeProvider::new(),
-            ),
-        );
+        let resolver = Self::system_resolver()?;
 
         // only issue one query with .
         let hostname = if hostname.ends_with('.') {
@@ -233,10 +454,298 @@ impl NodeConfig {
         }
     }
 
+    /// DNS resolver configured like the one of the system.
+    fn system_resolver() -> anyhow::Result<hickory_resolver::TokioAsyncResolver> {
+        let (conf, opts) = hickory_resolver::system_conf::read_system_conf()?;
+        Ok(hickory_resolver::TokioAsyncResolver::new(
+            conf,
+            opts,
+            hickory_resolver::name_server::GenericConnector::new(
+                hickory_resolver::name_server::TokioRuntimeProvider::new(),
+            ),
+        ))
+    }
+
+    /// Starts building the configuration of a node from the layered [`Config`], with the
+    /// preset of its profile already applied.
+    pub async fn builder(config: Config) -> anyhow::Result<NodeConfigBuilder> {
+        Ok(NodeConfigBuilder {
+            config: NodeConfig::new(config).await?,
+        })
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// Applies the defaults of the given profile preset. Values which were previously
+    /// set explicitly are preserved.
+    pub fn with_profile(&mut self, profile: NodeProfile) -> &mut Self {
+        use crate::config::{
+            DEFAULT_MAX_CONCURRENT_OPS, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_HOPS_TO_LIVE,
+            DEFAULT_MIN_CONNECTIONS, DEFAULT_RANDOM_PEER_CONN_THRESHOLD,
+            DEFAULT_STATE_STORAGE_QUOTA,
+        };
+        match profile {
+            NodeProfile::Gateway => {
+                self.is_gateway = true;
+                self.max_concurrent_ops
+                    .get_or_insert(DEFAULT_MAX_CONCURRENT_OPS * 4);
+            }
+            NodeProfile::Desktop => {}
+            NodeProfile::Constrained => {
+                self.max_number_conn
+                    .get_or_insert(DEFAULT_MAX_CONNECTIONS / 2);
+                self.min_number_conn
+                    .get_or_insert(DEFAULT_MIN_CONNECTIONS / 2);
+                self.max_upstream_bandwidth
+                    .get_or_insert(Rate::new_per_second(100_000.0));
+                self.max_downstream_bandwidth
+                    .get_or_insert(Rate::new_per_second(100_000.0));
+                self.max_concurrent_ops
+                    .get_or_insert(DEFAULT_MAX_CONCURRENT_OPS / 10);
+                self.contract_executors.get_or_insert(1);
+                self.state_storage_quota
+                    .get_or_insert(DEFAULT_STATE_STORAGE_QUOTA / 8);
+            }
+            NodeProfile::Simulation => {
+                self.max_hops_to_live
+                    .get_or_insert(DEFAULT_MAX_HOPS_TO_LIVE);
+                self.rnd_if_htl_above
+                    .get_or_insert(DEFAULT_RANDOM_PEER_CONN_THRESHOLD);
+                self.max_number_conn.get_or_insert(DEFAULT_MAX_CONNECTIONS);
+                self.min_number_conn.get_or_insert(DEFAULT_MIN_CONNECTIONS);
+            }
+        }
+        self
+    }
+
+    /// Contract from which to load the routing priors used until the node has gathered
+    /// enough routing samples of its own.
+    pub fn with_routing_priors_contract(&mut self, key: ContractKey) -> &mut Self {
+        self.routing_priors_contract = Some(key);
+        self
+    }
+
+    pub fn with_key_pair(&mut self, key_pair: TransportKeypair) -> &mut Self {
+        self.key_pair = key_pair;
+        self
+    }
+
+    /// Address and port to bind to the network listener.
+    pub fn with_network_listener(&mut self, ip: IpAddr, port: u16) -> &mut Self {
+        self.network_listener_ip = ip;
+        self.network_listener_port = port;
+        self
+    }
+
+    /// Join the network with the given name instead of the main one.
+    pub fn with_network(&mut self, name: &str) -> &mut Self {
+        self.network_id = NetworkId::from_name(name);
+        self
+    }
+
+    /// Transports to listen on and dial peers over, from the most to the least preferred.
+    /// Peers are dialed over all of them at once, and reached afterwards over the most
+    /// preferred one they answered on. Only UDP is used by default.
+    pub fn with_transports(
+        &mut self,
+        transports: impl IntoIterator<Item = TransportKind>,
+    ) -> &mut Self {
+        self.transports = Some(transports.into_iter().collect());
+        self
+    }
+
+    /// Listener of the other IP family, besides the network listener IP. By default the node
+    /// listens on both families when the network listener IP is unspecified. Peers are
+    /// reached over the family they were last heard from.
+    pub fn with_dual_stack(&mut self, dual_stack: DualStack) -> &mut Self {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    /// Maps the listener port on the router of the local network with the given protocol, so
+    /// the node can be reached from the internet without configuring the router. The external
+    /// address of the mapping is advertised to the peers, and the mapping renewed while the
+    /// node runs. Disabled by default, and not done by gateways.
+    pub fn with_port_mapping(&mut self, port_mapping: PortMapping) -> &mut Self {
+        self.port_mapping = port_mapping;
+        self
+    }
+
+    /// Hard limit, in bytes per second, on the bandwidth used to send to all peers together.
+    /// The bandwidth is shared fairly among the peers with data waiting to be sent, so a large
+    /// transfer to one of them does not hold back the messages to the rest.
+    pub fn upstream_bandwidth_limit(&mut self, bytes_per_sec: usize) -> &mut Self {
+        self.upstream_bandwidth_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// Caps the bandwidth used to send to a single peer to the given fraction of the upstream
+    /// bandwidth limit, even if no other peer has data waiting to be sent.
+    pub fn peer_bandwidth_share(&mut self, share: f64) -> &mut Self {
+        self.peer_bandwidth_share = Some(share);
+        self
+    }
+
+    /// Checks that the configuration is consistent and the node can be built from it.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let (Some(min), Some(max)) = (self.min_number_conn, self.max_number_conn) {
+            if min > max {
+                anyhow::bail!(
+                    "min number of connections ({min}) is higher than the max number of connections ({max})"
+                );
+            }
+        }
+        if self.max_number_conn == Some(0) {
+            anyhow::bail!("max number of connections must be greater than zero");
+        }
+        if self.transports.as_ref().is_some_and(|t| t.is_empty()) {
+            anyhow::bail!("at least one transport must be enabled");
+        }
+        if self
+            .admin_api
+            .as_ref()
+            .is_some_and(|admin| admin.token.is_empty())
+        {
+            anyhow::bail!("admin API token must not be empty");
+        }
+        if self.identity_key_path.is_none()
+            && (self.identity_passphrase.is_some() || self.identity_rotation.is_some())
+        {
+            anyhow::bail!("identity passphrase and rotation require an identity keystore");
+        }
+        if self
+            .identity_passphrase
+            .as_ref()
+            .is_some_and(|passphrase| passphrase.is_empty())
+        {
+            anyhow::bail!("identity passphrase must not be empty");
+        }
+        if cfg!(not(feature = "trace-ot")) && self.otlp_endpoint.is_some() {
+            anyhow::bail!("exporting traces requires the trace-ot feature");
+        }
+        if self.upstream_bandwidth_limit == Some(0) {
+            anyhow::bail!("upstream bandwidth limit must be greater than zero");
+        }
+        if let Some(share) = self.peer_bandwidth_share {
+            if !(share > 0.0 && share <= 1.0) {
+                anyhow::bail!("peer bandwidth share must be within (0, 1]");
+            }
+        }
+        if self.max_hops_to_live == Some(0) {
+            anyhow::bail!("max hops to live must be greater than zero");
+        }
+        if let (Some(rnd_above), Some(max_htl)) = (self.rnd_if_htl_above, self.max_hops_to_live) {
+            if rnd_above > max_htl {
+                anyhow::bail!(
+                    "randomization threshold ({rnd_above}) is higher than the max hops to live ({max_htl})"
+                );
+            }
+        }
+        if self.max_concurrent_ops == Some(0) {
+            anyhow::bail!("max number of concurrent operations must be greater than zero");
+        }
+        if self.get_parallelism == Some(0) {
+            anyhow::bail!("get parallelism must be greater than zero");
+        }
+        if self.state_chunk_threshold == Some(0) {
+            anyhow::bail!("state chunk threshold must be greater than zero");
+        }
+        if self.update_summary_threshold == Some(0) {
+            anyhow::bail!("update summary threshold must be greater than zero");
+        }
+        if self.state_storage_quota == Some(0) {
+            anyhow::bail!("state storage quota must be greater than zero");
+        }
+        if self.contract_executors == Some(0) {
+            anyhow::bail!("contract executors must be greater than zero");
+        }
+        if let Some(threshold) = self.reputation_threshold {
+            if threshold.is_nan() || threshold >= 0.0 {
+                anyhow::bail!("reputation threshold must be lower than zero");
+            }
+        }
+        if self.keepalive_failure_threshold == Some(0) {
+            anyhow::bail!("keep-alive failure threshold must be greater than zero");
+        }
+        let breakers = &self.circuit_breakers;
+        if breakers.window == 0 || breakers.min_requests > breakers.window {
+            anyhow::bail!("circuit breaker window must hold at least the minimum requests");
+        }
+        if !(breakers.failure_rate > 0.0 && breakers.failure_rate <= 1.0) {
+            anyhow::bail!("circuit breaker failure rate must be between zero and one");
+        }
+        if breakers.open_for.is_zero() {
+            anyhow::bail!("circuit breaker open time must be greater than zero");
+        }
+        if let Some((tx_type, _)) = self.op_priorities.iter().find(|(_, weight)| *weight == 0) {
+            anyhow::bail!("priority weight of {tx_type} operations must be greater than zero");
+        }
+        if let Some((tx_type, _)) = self
+            .message_policing
+            .quotas()
+            .find(|(_, quota)| quota.burst == 0 || quota.refill_every.is_zero())
+        {
+            anyhow::bail!("message quota of {tx_type} operations must be greater than zero");
+        }
+        if self.message_policing.violations_to_ban == 0 {
+            anyhow::bail!("violations before banning a peer must be greater than zero");
+        }
+        if !self.bootstrap.sources.is_empty() {
+            for source in &self.bootstrap.sources {
+                source.validate()?;
+            }
+            if self.bootstrap.max_gateways == 0 {
+                anyhow::bail!("max gateways discovered from seeds must be greater than zero");
+            }
+            if self.bootstrap.health_check_timeout.is_zero() {
+                anyhow::bail!("gateway health check timeout must be greater than zero");
+            }
+            if self.bootstrap.refresh_interval.is_some_and(|i| i.is_zero()) {
+                anyhow::bail!("gateway seeds refresh interval must be greater than zero");
+            }
+        }
+        let contract_fuel = &self.contract_limits;
+        if [
+            contract_fuel.validate_fuel,
+            contract_fuel.update_fuel,
+            contract_fuel.summarize_fuel,
+            contract_fuel.delta_fuel,
+            contract_fuel.maintain_fuel,
+        ]
+        .contains(&0)
+        {
+            anyhow::bail!("contract fuel budgets must be greater than zero");
+        }
+        if self.contract_limits.max_memory == 0 {
+            anyhow::bail!("contract memory limit must be greater than zero");
+        }
+        if self.delegate_limits.max_fuel == 0 {
+            anyhow::bail!("delegate fuel quota must be greater than zero");
+        }
+        if self.delegate_limits.max_memory == 0 {
+            anyhow::bail!("delegate memory quota must be greater than zero");
+        }
+        if self.state_journal.max_deltas == 0 || self.state_journal.snapshot_interval == 0 {
+            anyhow::bail!(
+                "contract state journal deltas and snapshot interval must be greater than zero"
+            );
+        }
+        if self.code_collection.interval.is_zero() {
+            anyhow::bail!("contract code collection interval must be greater than zero");
+        }
+        if let DualStack::Address(ip) = self.dual_stack {
+            if ip.is_ipv4() == self.network_listener_ip.is_ipv4() {
+                anyhow::bail!("dual stack listener address must be of the other IP family");
+            }
+        }
+        if self.location.is_some() && self.peer_id.is_none() {
+            anyhow::bail!("a peer id is required when setting the location of the node");
+        }
+        Ok(())
+    }
+
     pub fn is_gateway(&mut self) -> &mut Self {
         self.is_gateway = true;
         self
@@ -271,6 +780,268 @@ impl NodeConfig {
         self
     }
 
+    /// Max number of concurrent operations of a given type (connect, get, put...)
+    /// the node will track before rejecting new ones.
+    pub fn max_concurrent_operations(&mut self, num: usize) -> &mut Self {
+        self.max_concurrent_ops = Some(num);
+        self
+    }
+
+    /// Number of peers the get requests started at this node are sent to at once, the best
+    /// candidates first. The first state returned is kept and the other requests cancelled,
+    /// trading bandwidth for tail latency.
+    pub fn get_parallelism(&mut self, peers: usize) -> &mut Self {
+        self.get_parallelism = Some(peers);
+        self
+    }
+
+    /// Size, in bytes, above which messages carrying contract states are split in
+    /// verifiable chunks for transfer.
+    pub fn state_chunk_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.state_chunk_threshold = Some(bytes);
+        self
+    }
+
+    /// Size, in bytes, of an updated state above which subscribers are sent only its summary,
+    /// and pull the changes they are missing on demand.
+    pub fn update_summary_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.update_summary_threshold = Some(bytes);
+        self
+    }
+
+    /// Serialized size, in bytes, below which messages are sent to peers uncompressed. Larger
+    /// ones are compressed for the peers which negotiated a codec supported by both ends.
+    pub fn compression_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.compression_threshold = Some(bytes);
+        self
+    }
+
+    /// Reputation score below which peers are disconnected, and not accepted again until their
+    /// score recovers. Peers are already avoided when routing at half this score.
+    ///
+    /// Scores start at zero, so the threshold must be negative.
+    pub fn reputation_threshold(&mut self, threshold: f64) -> &mut Self {
+        self.reputation_threshold = Some(threshold);
+        self
+    }
+
+    /// Time during which a peer this node disconnected from is refused, to avoid drop and
+    /// reconnect loops with the same peer. A zero interval disables the cooldown.
+    pub fn connection_cooldown(&mut self, interval: Duration) -> &mut Self {
+        self.connection_cooldown = Some(interval);
+        self
+    }
+
+    /// Time between the pings sent to each neighbour to check it is still alive. A zero
+    /// interval disables the pings, dead neighbours are then only noticed once sending to
+    /// them fails.
+    pub fn keepalive_interval(&mut self, interval: Duration) -> &mut Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Number of pings in a row a neighbour has to miss for its connection to be dropped
+    /// and replaced.
+    pub fn keepalive_failure_threshold(&mut self, failures: u32) -> &mut Self {
+        self.keepalive_failure_threshold = Some(failures);
+        self
+    }
+
+    /// Thresholds at which peers failing most of the requests forwarded to them are cut off,
+    /// and how long until they are probed again.
+    pub fn with_circuit_breakers(&mut self, config: CircuitBreakerConfig) -> &mut Self {
+        self.circuit_breakers = config;
+        self
+    }
+
+    /// Time without any live neighbour after which the node is considered cut off the network
+    /// and joins it again through the gateways. A zero timeout disables re-joining.
+    pub fn disconnected_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.disconnected_timeout = Some(timeout);
+        self
+    }
+
+    /// Number of connections kept idle per band of distance to this node, among the peers
+    /// offering to connect which were turned down, to be promoted right away when a new
+    /// connection around their location is needed instead of looking for one.
+    pub fn standby_connections(&mut self, per_band: usize) -> &mut Self {
+        self.standby_connections = Some(per_band);
+        self
+    }
+
+    /// Policy deciding which peers asking to connect are accepted, shaping the topology of
+    /// the network. By default peers are accepted based on their reputation, the number of
+    /// connections of this node and the request density it observes.
+    pub fn with_acceptance_policy(&mut self, policy: impl AcceptancePolicy) -> &mut Self {
+        self.acceptance_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Relative weight of the given transaction type when picking the next inbound message
+    /// to process under load; higher weights are processed ahead of lower ones.
+    pub(crate) fn operation_priority(
+        &mut self,
+        tx_type: TransactionType,
+        weight: u32,
+    ) -> &mut Self {
+        self.op_priorities.set(tx_type, weight);
+        self
+    }
+
+    /// Fuel contracts are executed with for each function of their interface. Contracts
+    /// running out of it are interrupted, and the update or state being checked is rejected.
+    pub fn contract_limits(&mut self, limits: ContractLimits) -> &mut Self {
+        self.contract_limits = limits;
+        self
+    }
+
+    /// Fuel, memory and secrets storage quotas delegates are executed under, independent
+    /// from the contract execution limits.
+    pub fn delegate_limits(&mut self, limits: DelegateLimits) -> &mut Self {
+        self.delegate_limits = limits;
+        self
+    }
+
+    /// Number of executors the calls to different contracts are executed concurrently on. The
+    /// calls to the same contract are always executed one after the other.
+    pub fn contract_executors(&mut self, executors: usize) -> &mut Self {
+        self.contract_executors = Some(executors);
+        self
+    }
+
+    /// Backend the contract states are persisted to, the embedded database by default.
+    pub fn state_storage(&mut self, backend: StorageBackend) -> &mut Self {
+        self.state_storage = backend;
+        self
+    }
+
+    /// Bytes of contract states persisted beyond which the least recently used are evicted,
+    /// favouring the ones with subscribers. States of the contracts this node is home for are
+    /// never evicted.
+    pub fn state_storage_quota(&mut self, bytes: u64) -> &mut Self {
+        self.state_storage_quota = Some(bytes);
+        self
+    }
+
+    /// Deltas and snapshots of the recent versions of the contract states kept in memory, to
+    /// answer for the changes since a recent summary and roll back updates found invalid.
+    pub fn state_journal_limits(&mut self, limits: StateJournalLimits) -> &mut Self {
+        self.state_journal = limits;
+        self
+    }
+
+    /// How often the contract code no state stored nor subscription references anymore is
+    /// collected, and for how long it must stay unreferenced before it is removed.
+    pub fn code_collection(&mut self, config: CodeCollectionConfig) -> &mut Self {
+        self.code_collection = config;
+        self
+    }
+
+    /// Periodically write an anonymized snapshot of the local view of the network topology
+    /// and routing statistics to the given file, for aggregate studies of the ring.
+    ///
+    /// Opt-in; no snapshots are written unless set.
+    pub fn export_network_map(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.network_map_export = Some(path.into());
+        self
+    }
+
+    /// Appends every change in the connections of this node to the given file, as JSON lines,
+    /// besides keeping the latest ones in memory.
+    pub fn topology_journal(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.topology_journal = Some(path.into());
+        self
+    }
+
+    /// Serves an admin API on the given localhost port, to inspect the peers, transactions and
+    /// contracts of the running node and to drop connections, look for new ones or shut it
+    /// down without a restart. Requests must carry the token as a bearer authorization header.
+    pub fn admin_api(&mut self, port: u16, token: impl Into<String>) -> &mut Self {
+        self.admin_api = Some(AdminApiConfig {
+            port,
+            token: token.into(),
+        });
+        self
+    }
+
+    /// Serves the metrics of the node in the Prometheus text format at `/metrics` on the
+    /// given localhost port.
+    pub fn metrics_endpoint(&mut self, port: u16) -> &mut Self {
+        self.metrics_port = Some(port);
+        self
+    }
+
+    /// Exports the spans of the operations handled by this node to the OpenTelemetry collector
+    /// at the endpoint, over OTLP/gRPC. Spans of the same operation recorded by different
+    /// nodes share its trace, so multi-hop operations can be followed in Jaeger or Tempo.
+    ///
+    /// Requires the `trace-ot` feature.
+    pub fn export_traces(&mut self, endpoint: impl Into<String>) -> &mut Self {
+        self.otlp_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Records the messages received from peers to the file, with the time they arrived at, so
+    /// they can be fed back into a node to reproduce a bug. See [`Self::replay_messages`] and
+    /// [`SimNetwork::with_replay`](crate::dev_tool::SimNetwork::with_replay).
+    pub fn record_messages(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.message_recording = Some(path.into());
+        self
+    }
+
+    /// Feeds the messages recorded by a node (see [`Self::record_messages`]) to this node once
+    /// started, as if received from its peers and with the same timing they were received with.
+    pub fn replay_messages(&mut self, recording: impl Into<PathBuf>) -> &mut Self {
+        self.message_replay = Some(recording.into());
+        self
+    }
+
+    /// Persists the identity keypair of the node to the file, so the node keeps the same
+    /// identity across restarts. The keypair is generated on the first start.
+    pub fn identity_keystore(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.identity_key_path = Some(path.into());
+        self
+    }
+
+    /// Encrypts the persisted identity keypair at rest with the passphrase.
+    pub fn identity_passphrase(&mut self, passphrase: impl Into<String>) -> &mut Self {
+        self.identity_passphrase = Some(passphrase.into());
+        self
+    }
+
+    /// Replaces the persisted identity keypair by a new one on start once it is older than
+    /// the period. The previous keypair is kept next to it, with a `.previous` suffix.
+    pub fn rotate_identity_after(&mut self, period: Duration) -> &mut Self {
+        self.identity_rotation = Some(period);
+        self
+    }
+
+    /// Strategy used to pick the location of the peers joining the network through this node.
+    pub fn with_location_assignment(&mut self, strategy: LocationAssignment) -> &mut Self {
+        self.location_assignment = strategy;
+        self
+    }
+
+    /// Configuration of the gateway service, only used if the node is a gateway.
+    pub fn with_gateway_service(&mut self, config: GatewayServiceConfig) -> &mut Self {
+        self.gateway_service = config;
+        self
+    }
+
+    /// Rate limits of the messages received from each peer, and how peers exceeding them
+    /// are banned.
+    pub fn with_message_policing(&mut self, config: MessagePolicingConfig) -> &mut Self {
+        self.message_policing = config;
+        self
+    }
+
+    /// Seed lists gateways are discovered from on start, in addition to the ones added with
+    /// [`Self::add_gateway()`].
+    pub fn with_bootstrap(&mut self, config: BootstrapConfig) -> &mut Self {
+        self.bootstrap = config;
+        self
+    }
+
     pub fn with_peer_id(&mut self, peer_id: PeerId) -> &mut Self {
         self.peer_id = Some(peer_id);
         self
@@ -289,34 +1060,109 @@ impl NodeConfig {
 
     /// Builds a node using the default backend connection manager.
     pub async fn build<const CLIENTS: usize>(
-        self,
+        mut self,
         clients: [BoxedClient; CLIENTS],
     ) -> anyhow::Result<Node> {
+        self.validate()?;
+        self.load_identity()?;
+        if !self.bootstrap.sources.is_empty() {
+            for gateway in bootstrap::discover_gateways(&self).await {
+                if self
+                    .gateways
+                    .iter()
+                    .all(|known| known.peer_id != gateway.peer_id)
+                {
+                    self.gateways.push(gateway);
+                }
+            }
+        }
         let event_register = {
             #[cfg(feature = "trace-ot")]
             {
                 use super::tracing::{CombinedRegister, OTEventRegister};
-                CombinedRegister::new([
-                    Box::new(EventRegister::new(self.config.event_log())),
-                    Box::new(OTEventRegister::new()),
-                ])
+                let ot_register = match &self.otlp_endpoint {
+                    Some(endpoint) => {
+                        OTEventRegister::with_otlp_exporter(endpoint, self.key_pair.public())?
+                    }
+                    None => OTEventRegister::new(),
+                };
+                CombinedRegister::new([Box::new(self.event_register()), Box::new(ot_register)])
             }
             #[cfg(not(feature = "trace-ot"))]
             {
-                EventRegister::new(self.config.event_log())
+                self.event_register()
             }
         };
-        let cfg = self.config.clone();
+        let ch_builder = (
+            self.config.clone(),
+            self.contract_limits.clone(),
+            self.delegate_limits,
+            self.state_storage,
+            self.state_storage_quota
+                .unwrap_or(crate::config::DEFAULT_STATE_STORAGE_QUOTA),
+            self.state_journal,
+            self.code_collection,
+            self.contract_executors
+                .unwrap_or(crate::config::DEFAULT_CONTRACT_EXECUTORS),
+        );
         let node = NodeP2P::build::<NetworkContractHandler, CLIENTS, _>(
             self,
             clients,
             event_register,
-            cfg,
+            ch_builder,
         )
         .await?;
         Ok(Node(node))
     }
 
+    /// Builds a node which runs offline: the network is never joined, and all the client
+    /// requests are served from the local storage and contract executor of the node.
+    pub async fn build_local<const CLIENTS: usize>(
+        self,
+        clients: [BoxedClient; CLIENTS],
+    ) -> anyhow::Result<LocalNode> {
+        self.validate()?;
+        let mut executor =
+            Executor::from_config(self.config.clone(), self.state_storage, None).await?;
+        executor.set_contract_limits(self.contract_limits.clone());
+        executor.set_delegate_limits(self.delegate_limits);
+        executor
+            .set_state_quota(
+                self.state_storage_quota
+                    .unwrap_or(crate::config::DEFAULT_STATE_STORAGE_QUOTA),
+            )
+            .await?;
+        executor.set_state_journal_limits(self.state_journal);
+        executor.set_code_collection(self.code_collection);
+        Ok(LocalNode::new(executor, clients))
+    }
+
+    /// Register of the events of the node to its event log.
+    pub(crate) fn event_register(&self) -> EventRegister {
+        let register = EventRegister::new(self.config.event_log());
+        match &self.message_recording {
+            Some(path) => register.record_messages(path.clone()),
+            None => register,
+        }
+    }
+
+    /// Replaces the keypair of the node by the one persisted in the identity keystore, if set.
+    fn load_identity(&mut self) -> anyhow::Result<()> {
+        let Some(path) = self.identity_key_path.clone() else {
+            return Ok(());
+        };
+        let keystore = Keystore {
+            path,
+            passphrase: self.identity_passphrase.clone(),
+            rotate_after: self.identity_rotation,
+        };
+        self.key_pair = keystore.load_or_generate()?;
+        if let Some(peer_id) = &mut self.peer_id {
+            peer_id.pub_key = self.key_pair.public().clone();
+        }
+        Ok(())
+    }
+
     pub fn get_peer_id(&self) -> Option<PeerId> {
         self.peer_id.clone()
     }
@@ -343,6 +1189,94 @@ impl NodeConfig {
     }
 }
 
+/// Builder of a [`NodeConfig`], which checks the configuration is consistent when built.
+///
+/// Settings without a dedicated method are set through [`NodeConfigBuilder::configure`].
+pub struct NodeConfigBuilder {
+    config: NodeConfig,
+}
+
+impl NodeConfigBuilder {
+    /// Applies the defaults of the given profile preset on top of the values set so far.
+    pub fn profile(mut self, profile: NodeProfile) -> Self {
+        self.config.with_profile(profile);
+        self
+    }
+
+    pub fn gateway(mut self) -> Self {
+        self.config.is_gateway();
+        self
+    }
+
+    pub fn should_connect(mut self, should_connect: bool) -> Self {
+        self.config.with_should_connect(should_connect);
+        self
+    }
+
+    pub fn key_pair(mut self, key_pair: TransportKeypair) -> Self {
+        self.config.with_key_pair(key_pair);
+        self
+    }
+
+    pub fn network_listener(mut self, ip: IpAddr, port: u16) -> Self {
+        self.config.with_network_listener(ip, port);
+        self
+    }
+
+    pub fn peer_id(mut self, peer_id: PeerId) -> Self {
+        self.config.with_peer_id(peer_id);
+        self
+    }
+
+    pub fn location(mut self, location: Location) -> Self {
+        self.config.with_location(location);
+        self
+    }
+
+    pub fn gateways(mut self, gateways: impl IntoIterator<Item = InitPeerNode>) -> Self {
+        for gateway in gateways {
+            self.config.add_gateway(gateway);
+        }
+        self
+    }
+
+    pub fn max_hops_to_live(mut self, num_hops: usize) -> Self {
+        self.config.max_hops_to_live(num_hops);
+        self
+    }
+
+    pub fn rnd_if_htl_above(mut self, num_hops: usize) -> Self {
+        self.config.rnd_if_htl_above(num_hops);
+        self
+    }
+
+    pub fn max_connections(mut self, num: usize) -> Self {
+        self.config.max_number_of_connections(num);
+        self
+    }
+
+    pub fn min_connections(mut self, num: usize) -> Self {
+        self.config.min_number_of_connections(num);
+        self
+    }
+
+    pub fn max_concurrent_operations(mut self, num: usize) -> Self {
+        self.config.max_concurrent_operations(num);
+        self
+    }
+
+    pub fn configure(mut self, f: impl FnOnce(&mut NodeConfig)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// Returns the configuration, or the first inconsistency found in it.
+    pub fn build(self) -> anyhow::Result<NodeConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
 /// Gateway node to use for joining the network.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct InitPeerNode {
@@ -361,13 +1295,23 @@ async fn report_result(
     op_result: Result<Option<OpEnum>, OpError>,
     op_manager: &OpManager,
     executor_callback: Option<ExecutorToEventLoopChannel<Callback>>,
-    client_req_handler_callback: Option<(ClientId, ClientResponsesSender)>,
+    client_tracker: Option<&ClientTransactionTracker>,
     event_listener: &mut dyn NetEventRegister,
 ) {
     match op_result {
         Ok(Some(op_res)) => {
-            if let Some((client_id, cb)) = client_req_handler_callback {
-                let _ = cb.send((client_id, op_res.to_host_result()));
+            op_manager.record_outcome(Some(op_res.id()), true);
+            if let Some(client_tracker) = client_tracker {
+                // a client subscribing through a get also gets the state it brought back
+                let notification = match &op_res {
+                    OpEnum::Get(op) => op.subscribed_state(),
+                    _ => None,
+                };
+                client_tracker.notify_result_with(
+                    op_res.id(),
+                    op_res.to_host_result(),
+                    notification,
+                );
             }
             // check operations.rs:handle_op_result to see what's the meaning of each state
             // in case more cases want to be handled when feeding information to the OpManager
@@ -411,22 +1355,39 @@ async fn report_result(
                 // }
                 OpOutcome::Incomplete | OpOutcome::Irrelevant => {}
             }
-            if let Some(mut cb) = executor_callback {
-                cb.response(op_res).await;
+            if let Some(cb) = executor_callback {
+                cb.response(op_res);
             }
         }
         Ok(None) => {
             tracing::debug!(?tx, "No operation result found, not sending response");
         }
         Err(err) => {
+            op_manager.record_outcome(tx.as_ref(), false);
+            op_manager
+                .ring
+                .lifecycle_events
+                .emit(NodeLifecycleEvent::OperationFailed {
+                    transaction: tx,
+                    error: err.to_string(),
+                });
             // just mark the operation as completed so no redundant messages are processed for this transaction anymore
             if let Some(tx) = tx {
                 op_manager.completed(tx);
+                if let Some(client_tracker) = client_tracker {
+                    client_tracker.notify_failure(&tx, &err);
+                }
             }
             #[cfg(any(debug_assertions, test))]
             {
                 use std::io::Write;
-                let OpError::InvalidStateTransition { tx, state, trace } = err else {
+                let OpError::InvalidTransition {
+                    tx,
+                    from,
+                    input,
+                    trace,
+                } = err
+                else {
                     tracing::error!("Finished transaction with error: {err}");
                     return;
                 };
@@ -447,7 +1408,7 @@ async fn report_result(
                     .get_peer_key()
                     .expect("Peer key not found");
                 let log = format!(
-                    "Transaction ({tx} @ {peer}) error trace:\n {trace} \nstate:\n {state:?}\n"
+                    "Transaction ({tx} @ {peer}) error trace:\n {trace} \nstate:\n {from}\ninput:\n {input}\n"
                 );
                 std::io::stderr().write_all(log.as_bytes()).unwrap();
             }
@@ -459,178 +1420,714 @@ async fn report_result(
     }
 }
 
-macro_rules! handle_op_not_available {
-    ($op_result:ident) => {
-        if let Err(OpError::OpNotAvailable(state)) = &$op_result {
-            match state {
-                OpNotAvailable::Running => {
-                    tracing::debug!("Operation still running");
-                    // TODO: do exponential backoff
-                    tokio::time::sleep(Duration::from_micros(1_000)).await;
-                    continue;
+macro_rules! handle_op_not_available {
+    ($op_result:ident, $msg:expr, $conn_manager:expr) => {
+        if let Err(OpError::OpNotAvailable(state)) = &$op_result {
+            match state {
+                OpNotAvailable::Running => {
+                    tracing::debug!("Operation still running");
+                    // TODO: do exponential backoff
+                    tokio::time::sleep(Duration::from_micros(1_000)).await;
+                    continue;
+                }
+                OpNotAvailable::Completed => {
+                    tracing::debug!("Operation already completed");
+                    return;
+                }
+                OpNotAvailable::Busy => {
+                    tracing::warn!("Operation rejected, too many concurrent operations");
+                    refuse_busy(*$msg.id(), op_sender($msg), $conn_manager).await;
+                    return;
+                }
+            }
+        }
+    };
+}
+
+async fn process_message<CB>(
+    msg: NetMessage,
+    op_manager: Arc<OpManager>,
+    conn_manager: CB,
+    event_listener: Box<dyn NetEventRegister>,
+    executor_callback: Option<ExecutorToEventLoopChannel<crate::contract::Callback>>,
+    client_tracker: ClientTransactionTracker,
+) where
+    CB: NetworkBridge,
+{
+    let tx = Some(*msg.id());
+    match msg {
+        NetMessage::V1(msg_v1) => {
+            process_message_v1(
+                tx,
+                msg_v1,
+                op_manager,
+                conn_manager,
+                event_listener,
+                executor_callback,
+                client_tracker,
+            )
+            .await
+        }
+    }
+}
+
+async fn process_message_v1<CB>(
+    tx: Option<Transaction>,
+    msg: NetMessageV1,
+    op_manager: Arc<OpManager>,
+    mut conn_manager: CB,
+    mut event_listener: Box<dyn NetEventRegister>,
+    executor_callback: Option<ExecutorToEventLoopChannel<crate::contract::Callback>>,
+    client_tracker: ClientTransactionTracker,
+) where
+    CB: NetworkBridge,
+{
+    event_listener
+        .register_events(NetEventLog::from_inbound_msg_v1(&msg, &op_manager))
+        .await;
+
+    if let NetMessageV1::GetBatch { .. } = msg {
+        return process_get_batch(
+            msg,
+            &op_manager,
+            &mut conn_manager,
+            &mut *event_listener,
+            &client_tracker,
+        )
+        .await;
+    }
+
+    const MAX_RETRIES: usize = 10usize;
+    for i in 0..MAX_RETRIES {
+        tracing::debug!(?tx, "Processing operation, iteration: {i}");
+        match msg {
+            NetMessageV1::Connect(ref op) => {
+                let op_result =
+                    handle_op_request::<connect::ConnectOp, _>(&op_manager, &mut conn_manager, op)
+                        .await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Put(ref op) => {
+                let op_result =
+                    handle_op_request::<put::PutOp, _>(&op_manager, &mut conn_manager, op).await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Get(ref op) => {
+                let op_result =
+                    handle_op_request::<get::GetOp, _>(&op_manager, &mut conn_manager, op).await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Subscribe(ref op) => {
+                let op_result = handle_op_request::<subscribe::SubscribeOp, _>(
+                    &op_manager,
+                    &mut conn_manager,
+                    op,
+                )
+                .await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Update(ref op) => {
+                let op_result =
+                    handle_op_request::<update::UpdateOp, _>(&op_manager, &mut conn_manager, op)
+                        .await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Probe(ref op) => {
+                let op_result =
+                    handle_op_request::<probe::ProbeOp, _>(&op_manager, &mut conn_manager, op)
+                        .await;
+                handle_op_not_available!(op_result, &msg, &mut conn_manager);
+                return report_result(
+                    tx,
+                    op_result,
+                    &op_manager,
+                    executor_callback,
+                    Some(&client_tracker),
+                    &mut *event_listener,
+                )
+                .await;
+            }
+            NetMessageV1::Unsubscribed {
+                ref key, ref from, ..
+            } => {
+                // only the peer this node is subscribed through can cut the subscription short
+                if !op_manager.is_subscribed_through(key, from) {
+                    tracing::debug!(%key, %from, "Ignoring unsubscription from a peer not subscribed through");
+                    break;
+                }
+                if let Err(error) = subscribe(op_manager, *key, None).await {
+                    tracing::error!(%error, "Failed to subscribe to contract");
+                }
+                break;
+            }
+            NetMessageV1::Leaving { ref from, .. } => {
+                tracing::debug!(%from, "Peer leaving the network");
+                op_manager.ring.prune_connection(from.clone()).await;
+                let _ = op_manager
+                    .notify_node_event(NodeEvent::DropConnection(from.clone()))
+                    .await;
+                break;
+            }
+            NetMessageV1::Ping {
+                transaction,
+                ref target,
+                ref from,
+                nonce,
+            } => {
+                ping_neighbour(
+                    transaction,
+                    target,
+                    from,
+                    nonce,
+                    &op_manager,
+                    &mut conn_manager,
+                )
+                .await;
+                break;
+            }
+            NetMessageV1::Pong {
+                ref from, nonce, ..
+            } => {
+                if !op_manager
+                    .ring
+                    .connection_manager
+                    .liveness
+                    .pong(from, nonce)
+                {
+                    tracing::debug!(%from, "Unexpected pong");
+                }
+                break;
+            }
+            NetMessageV1::NonCaching { ref from, .. } => {
+                tracing::debug!(%from, "Neighbour is a light client");
+                op_manager.ring.light_client_neighbour(from.clone());
+                break;
+            }
+            NetMessageV1::Relocated {
+                ref from, location, ..
+            } => {
+                if op_manager.ring.relocate_peer(from, location) {
+                    tracing::debug!(%from, %location, "Peer relocated");
+                }
+                break;
+            }
+            NetMessageV1::Readdress {
+                transaction,
+                ref notice,
+                ref target,
+            } => {
+                readdress_peer(transaction, notice, target, &op_manager, &mut conn_manager).await;
+                break;
+            }
+            NetMessageV1::PunchIntroduction {
+                ref peer, ref via, ..
+            } => {
+                let _ = op_manager
+                    .notify_node_event(NodeEvent::HolePunch {
+                        peer: peer.clone(),
+                        via: via.clone(),
+                    })
+                    .await;
+                break;
+            }
+            NetMessageV1::Busy { transaction } => {
+                handle_refused_op(transaction, &op_manager, &client_tracker);
+                break;
+            }
+            _ => break, // Exit the loop if no applicable message type is found
+        }
+    }
+}
+
+/// Tells the peer which sent an operation request refused for being at the max number of
+/// concurrent operations, so it does not wait for the operation to time out.
+async fn refuse_busy<CB>(
+    transaction: Transaction,
+    sender: Option<&PeerKeyLocation>,
+    conn_manager: &mut CB,
+) where
+    CB: NetworkBridge,
+{
+    let Some(sender) = sender else {
+        return;
+    };
+    let refusal = NetMessage::V1(NetMessageV1::Busy { transaction });
+    if let Err(error) = conn_manager.send(&sender.peer, refusal).await {
+        tracing::debug!(tx = %transaction, %error, peer = %sender.peer, "Failed refusing operation");
+    }
+}
+
+/// Peer which sent the operation message, to refuse it to. Connection requests are answered
+/// by the connect operation itself.
+fn op_sender(msg: &NetMessageV1) -> Option<&PeerKeyLocation> {
+    match msg {
+        NetMessageV1::Put(op) => op.sender(),
+        NetMessageV1::Get(op) => op.sender(),
+        NetMessageV1::Subscribe(op) => op.sender(),
+        NetMessageV1::Update(op) => op.sender(),
+        NetMessageV1::Probe(op) => op.sender(),
+        _ => None,
+    }
+}
+
+/// The peer an operation request was sent to refused it, the operation fails right away
+/// instead of waiting for it to time out.
+fn handle_refused_op(
+    tx: Transaction,
+    op_manager: &OpManager,
+    client_tracker: &ClientTransactionTracker,
+) {
+    if !op_manager.has_op(&tx) {
+        return;
+    }
+    // an operation being processed is left alone, it may have moved on already
+    if let Ok(Some(_)) = op_manager.pop(&tx) {
+        tracing::debug!(%tx, "Operation refused by a busy peer");
+        op_manager.record_outcome(Some(&tx), false);
+        op_manager.completed(tx);
+        client_tracker.notify_failure(&tx, &OpError::OpNotAvailable(OpNotAvailable::Busy));
+    }
+}
+
+/// Forwards a batch of get requests to the peer it targets or, if it is meant for this peer,
+/// processes each of the requests in it as if it had been received on its own.
+async fn process_get_batch<CB>(
+    msg: NetMessageV1,
+    op_manager: &OpManager,
+    conn_manager: &mut CB,
+    event_listener: &mut dyn NetEventRegister,
+    client_tracker: &ClientTransactionTracker,
+) where
+    CB: NetworkBridge,
+{
+    const MAX_RETRIES: usize = 10usize;
+    let NetMessageV1::GetBatch { target, .. } = &msg else {
+        return;
+    };
+    if op_manager.ring.connection_manager.get_peer_key().as_ref() != Some(&target.peer) {
+        let target = target.peer.clone();
+        if op_manager
+            .ring
+            .connection_manager
+            .peer_features
+            .supports(&target.addr, Feature::GetBatch)
+        {
+            if let Err(error) = conn_manager.send(&target, NetMessage::V1(msg)).await {
+                tracing::debug!(%error, %target, "Failed to send get batch");
+            }
+            return;
+        }
+        // peers which did not greet saying they take batches get the requests one by one
+        let NetMessageV1::GetBatch { requests, .. } = msg else {
+            return;
+        };
+        for request in requests {
+            if let Err(error) = conn_manager.send(&target, NetMessage::from(request)).await {
+                tracing::debug!(%error, %target, "Failed to send batched get request");
+            }
+        }
+        return;
+    }
+    let NetMessageV1::GetBatch { requests, .. } = msg else {
+        return;
+    };
+    if requests.len() > get::MAX_BATCH_SIZE {
+        tracing::warn!(
+            requests = requests.len(),
+            "Get batch over the max size, ignoring the requests past it"
+        );
+    }
+    'requests: for request in requests.iter().take(get::MAX_BATCH_SIZE) {
+        let tx = Some(*request.id());
+        for _ in 0..MAX_RETRIES {
+            let op_result =
+                handle_op_request::<get::GetOp, _>(op_manager, conn_manager, request).await;
+            match &op_result {
+                Err(OpError::OpNotAvailable(OpNotAvailable::Running)) => {
+                    tokio::time::sleep(Duration::from_micros(1_000)).await;
+                    continue;
+                }
+                Err(OpError::OpNotAvailable(OpNotAvailable::Busy)) => {
+                    refuse_busy(*request.id(), request.sender(), conn_manager).await;
+                    continue 'requests;
+                }
+                Err(OpError::OpNotAvailable(_)) => continue 'requests,
+                _ => {}
+            }
+            report_result(
+                tx,
+                op_result,
+                op_manager,
+                None,
+                Some(client_tracker),
+                event_listener,
+            )
+            .await;
+            continue 'requests;
+        }
+    }
+}
+
+/// Sends a ping to the target neighbour or, if it is meant for this peer, answers it.
+async fn ping_neighbour<CB>(
+    transaction: Transaction,
+    target: &PeerId,
+    from: &PeerId,
+    nonce: u64,
+    op_manager: &OpManager,
+    conn_manager: &mut CB,
+) where
+    CB: NetworkBridge,
+{
+    let (receiver, msg) =
+        if op_manager.ring.connection_manager.get_peer_key().as_ref() != Some(target) {
+            let ping = NetMessageV1::Ping {
+                transaction,
+                target: target.clone(),
+                from: from.clone(),
+                nonce,
+            };
+            (target, ping)
+        } else {
+            let pong = NetMessageV1::Pong {
+                transaction,
+                from: target.clone(),
+                nonce,
+            };
+            (from, pong)
+        };
+    if let Err(error) = conn_manager.send(receiver, NetMessage::V1(msg)).await {
+        tracing::debug!(%error, %receiver, "Failed to send keep-alive");
+    }
+}
+
+/// Migrates the connection with a peer which moved to a new address, relaying the notice
+/// if it was meant for some other peer.
+async fn readdress_peer<CB>(
+    transaction: Transaction,
+    notice: &ReaddressNotice,
+    target: &PeerId,
+    op_manager: &OpManager,
+    conn_manager: &mut CB,
+) where
+    CB: NetworkBridge,
+{
+    if op_manager.ring.connection_manager.get_peer_key().as_ref() != Some(target) {
+        let msg = NetMessage::V1(NetMessageV1::Readdress {
+            transaction,
+            notice: notice.clone(),
+            target: target.clone(),
+        });
+        if let Err(error) = conn_manager.send(target, msg).await {
+            tracing::debug!(%error, %target, "Failed to relay re-address notice");
+        }
+        return;
+    }
+
+    let moved = match notice
+        .verify(&op_manager.verifier, op_manager.clock.unix_time())
+        .await
+    {
+        Ok(moved) => moved,
+        Err(error) => {
+            tracing::warn!(%error, "Rejected re-address notice");
+            return;
+        }
+    };
+    if !op_manager
+        .ring
+        .readdress_peer(&notice.previous, moved.clone())
+    {
+        tracing::debug!(previous = %notice.previous, "No connection to migrate");
+        return;
+    }
+    tracing::info!(previous = %notice.previous, peer = %moved, "Peer moved to a new address");
+    let _ = op_manager
+        .notify_node_event(NodeEvent::DropConnection(notice.previous.clone()))
+        .await;
+
+    let (callback, mut result) = tokio::sync::mpsc::channel(1);
+    if op_manager
+        .notify_node_event(NodeEvent::ConnectPeer {
+            peer: moved.clone(),
+            tx: Transaction::new::<connect::ConnectMsg>(),
+            callback,
+            is_gw: false,
+        })
+        .await
+        .is_err()
+    {
+        return;
+    }
+    if !matches!(result.recv().await, Some(Ok(_))) {
+        tracing::debug!(peer = %moved, "Failed re-dialing moved peer");
+        op_manager.ring.prune_connection(moved).await;
+    }
+}
+
+/// Loads the network-wide routing priors published in the given contract and seeds the
+/// router with them, then keeps refreshing them from the latest state in the network.
+pub(crate) async fn load_routing_priors(op_manager: Arc<OpManager>, key: ContractKey) {
+    use crate::contract::{ContractHandlerEvent, StoreResponse};
+    use crate::router::RoutingPriors;
+
+    /// Time to wait for the contract to be fetched while there are no priors.
+    const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+    /// Time between refreshes of the priors once loaded.
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    loop {
+        let response = op_manager
+            .notify_contract_handler(ContractHandlerEvent::GetQuery {
+                key,
+                return_contract_code: false,
+            })
+            .await;
+        let mut interval = RETRY_INTERVAL;
+        if let Ok(ContractHandlerEvent::GetResponse {
+            response: Ok(StoreResponse {
+                state: Some(state), ..
+            }),
+            ..
+        }) = response
+        {
+            interval = REFRESH_INTERVAL;
+            match RoutingPriors::from_state(state.as_ref()) {
+                Ok(priors) => match op_manager.ring.router.write().set_priors(priors) {
+                    Ok(()) => tracing::info!(%key, "Loaded routing priors"),
+                    Err(error) => tracing::warn!(%key, %error, "Invalid routing priors"),
+                },
+                Err(error) => {
+                    tracing::warn!(%key, %error, "Invalid routing priors contract state");
+                }
+            }
+        }
+        // fetched ahead of the next round, which loads whatever state was stored by then
+        if op_manager.ring.open_connections() > 0 {
+            let get_op = get::start_op(key, true);
+            if let Err(error) = get::request_get(&op_manager, get_op, vec![]).await {
+                tracing::debug!(%key, %error, "Failed requesting routing priors contract");
+            }
+        }
+        op_manager.clock.sleep(interval).await;
+    }
+}
+
+/// Periodically runs the state maintenance of the hosted contracts which declare it, and
+/// propagates any resulting state changes to the network.
+pub(crate) async fn contract_maintenance(op_manager: Arc<OpManager>) {
+    use crate::contract::ContractHandlerEvent;
+
+    const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+    loop {
+        op_manager.clock.sleep(CHECK_INTERVAL).await;
+        let now = op_manager.clock.unix_time();
+        let updated = match op_manager
+            .notify_contract_handler(ContractHandlerEvent::MaintenanceQuery { now })
+            .await
+        {
+            Ok(ContractHandlerEvent::MaintenanceResponse { updated }) => updated,
+            Ok(_) => unreachable!("MaintenanceQuery always returns MaintenanceResponse"),
+            Err(ContractError::ChannelDropped(_)) => break,
+            Err(error) => {
+                tracing::debug!(%error, "Failed running contract maintenance");
+                continue;
+            }
+        };
+        for (key, state) in updated {
+            let op = update::start_op(key, state, RelatedContracts::default());
+            if let Err(error) = update::request_update(&op_manager, op).await {
+                tracing::debug!(%key, %error, "Failed propagating maintained contract state");
+            }
+        }
+    }
+}
+
+/// Periodically checks the contract states stored against the hashes recorded when written,
+/// and against their contracts, fetching anew from the network the ones found corrupted. The
+/// states are checked one at a time, with a pause in between, so other work is not held up.
+pub(crate) async fn state_integrity_checks(op_manager: Arc<OpManager>) {
+    use crate::contract::ContractHandlerEvent;
+
+    const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+    const PAUSE: Duration = Duration::from_millis(100);
+    loop {
+        op_manager.clock.sleep(CHECK_INTERVAL).await;
+        let contracts = match op_manager
+            .notify_contract_handler(ContractHandlerEvent::CachedContractsQuery)
+            .await
+        {
+            Ok(ContractHandlerEvent::CachedContractsResponse { contracts }) => contracts,
+            Ok(_) => unreachable!("CachedContractsQuery always returns CachedContractsResponse"),
+            Err(ContractError::ChannelDropped(_)) => break,
+            Err(error) => {
+                tracing::debug!(%error, "Failed listing the contract states stored");
+                continue;
+            }
+        };
+        for contract in contracts {
+            let key = contract.key;
+            match op_manager
+                .notify_contract_handler(ContractHandlerEvent::IntegrityQuery { key })
+                .await
+            {
+                Ok(ContractHandlerEvent::IntegrityResponse {
+                    intact: Ok(true), ..
+                }) => {}
+                Ok(ContractHandlerEvent::IntegrityResponse {
+                    intact: Ok(false), ..
+                }) => {
+                    if let Err(error) =
+                        get::request_get(&op_manager, get::start_op(key, true), vec![]).await
+                    {
+                        tracing::debug!(%key, %error, "Failed fetching a quarantined contract state");
+                    }
                 }
-                OpNotAvailable::Completed => {
-                    tracing::debug!("Operation already completed");
-                    return;
+                Ok(ContractHandlerEvent::IntegrityResponse {
+                    intact: Err(error), ..
+                }) => {
+                    tracing::debug!(%key, %error, "Failed checking the contract state");
+                }
+                Ok(_) => unreachable!("IntegrityQuery always returns IntegrityResponse"),
+                Err(ContractError::ChannelDropped(_)) => return,
+                Err(error) => {
+                    tracing::debug!(%key, %error, "Failed checking the contract state");
                 }
             }
-        }
-    };
-}
-
-async fn process_message<CB>(
-    msg: NetMessage,
-    op_manager: Arc<OpManager>,
-    conn_manager: CB,
-    event_listener: Box<dyn NetEventRegister>,
-    executor_callback: Option<ExecutorToEventLoopChannel<crate::contract::Callback>>,
-    client_req_handler_callback: Option<ClientResponsesSender>,
-    client_id: Option<ClientId>,
-) where
-    CB: NetworkBridge,
-{
-    let tx = Some(*msg.id());
-    match msg {
-        NetMessage::V1(msg_v1) => {
-            process_message_v1(
-                tx,
-                msg_v1,
-                op_manager,
-                conn_manager,
-                event_listener,
-                executor_callback,
-                client_req_handler_callback,
-                client_id,
-            )
-            .await
+            op_manager.clock.sleep(PAUSE).await;
         }
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn process_message_v1<CB>(
-    tx: Option<Transaction>,
-    msg: NetMessageV1,
-    op_manager: Arc<OpManager>,
-    mut conn_manager: CB,
-    mut event_listener: Box<dyn NetEventRegister>,
-    executor_callback: Option<ExecutorToEventLoopChannel<crate::contract::Callback>>,
-    client_req_handler_callback: Option<ClientResponsesSender>,
-    client_id: Option<ClientId>,
-) where
-    CB: NetworkBridge,
-{
-    let cli_req = client_id.zip(client_req_handler_callback);
-    event_listener
-        .register_events(NetEventLog::from_inbound_msg_v1(&msg, &op_manager))
-        .await;
+/// Fetches the latest state of the contracts this node becomes home for as the topology
+/// around it changes, since requests for them are now routed to this node.
+pub(crate) async fn home_contracts_sync(op_manager: Arc<OpManager>) {
+    use tokio::sync::broadcast::error::RecvError;
 
-    const MAX_RETRIES: usize = 10usize;
-    for i in 0..MAX_RETRIES {
-        tracing::debug!(?tx, "Processing operation, iteration: {i}");
-        match msg {
-            NetMessageV1::Connect(ref op) => {
-                let parent_span = tracing::Span::current();
-                let span = tracing::info_span!(
-                    parent: parent_span,
-                    "handle_connect_op_request",
-                    transaction = %msg.id(),
-                    tx_type = %msg.id().transaction_type()
+    let mut changes = op_manager.ring.home_contracts.subscribe();
+    loop {
+        let changes = match changes.recv().await {
+            Ok(changes) => changes,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::debug!(
+                    skipped,
+                    "Missed changes of the contracts this node is home for"
                 );
-                let op_result =
-                    handle_op_request::<connect::ConnectOp, _>(&op_manager, &mut conn_manager, op)
-                        .instrument(span)
-                        .await;
-                handle_op_not_available!(op_result);
-                return report_result(
-                    tx,
-                    op_result,
-                    &op_manager,
-                    executor_callback,
-                    cli_req,
-                    &mut *event_listener,
-                )
-                .await;
-            }
-            NetMessageV1::Put(ref op) => {
-                let op_result =
-                    handle_op_request::<put::PutOp, _>(&op_manager, &mut conn_manager, op).await;
-                handle_op_not_available!(op_result);
-                return report_result(
-                    tx,
-                    op_result,
-                    &op_manager,
-                    executor_callback,
-                    cli_req,
-                    &mut *event_listener,
-                )
-                .await;
-            }
-            NetMessageV1::Get(ref op) => {
-                let op_result =
-                    handle_op_request::<get::GetOp, _>(&op_manager, &mut conn_manager, op).await;
-                handle_op_not_available!(op_result);
-                return report_result(
-                    tx,
-                    op_result,
-                    &op_manager,
-                    executor_callback,
-                    cli_req,
-                    &mut *event_listener,
-                )
-                .await;
-            }
-            NetMessageV1::Subscribe(ref op) => {
-                let op_result = handle_op_request::<subscribe::SubscribeOp, _>(
-                    &op_manager,
-                    &mut conn_manager,
-                    op,
-                )
-                .await;
-                handle_op_not_available!(op_result);
-                return report_result(
-                    tx,
-                    op_result,
-                    &op_manager,
-                    executor_callback,
-                    cli_req,
-                    &mut *event_listener,
-                )
-                .await;
-            }
-            NetMessageV1::Update(ref op) => {
-                let op_result =
-                    handle_op_request::<update::UpdateOp, _>(&op_manager, &mut conn_manager, op)
-                        .await;
-                handle_op_not_available!(op_result);
-                return report_result(
-                    tx,
-                    op_result,
-                    &op_manager,
-                    executor_callback,
-                    cli_req,
-                    &mut *event_listener,
-                )
-                .await;
+                continue;
             }
-            NetMessageV1::Unsubscribed { ref key, .. } => {
-                if let Err(error) = subscribe(op_manager, *key, None).await {
-                    tracing::error!(%error, "Failed to subscribe to contract");
-                }
-                break;
+            Err(RecvError::Closed) => break,
+        };
+        for key in changes.gained {
+            if let Err(error) =
+                get::request_get(&op_manager, get::start_op(key, false), vec![]).await
+            {
+                tracing::debug!(%key, %error, "Failed fetching the state of a home contract");
             }
-            _ => break, // Exit the loop if no applicable message type is found
+        }
+        for key in changes.lost {
+            tracing::debug!(%key, "No longer home for contract");
         }
     }
 }
 
+/// Migrates the state of a contract superseded by a new version of its code, run through the
+/// given delegate, and puts the new version with the migrated state. The delegate runs on the
+/// executor it is assigned to, while the new version is stored by its own, as any other put.
+/// The put only reaches the network once the node stored the new version along with a state
+/// valid for it.
+pub(crate) async fn migrate_contract(
+    op_manager: &OpManager,
+    from: ContractKey,
+    to: ContractContainer,
+    migration_delegate: DelegateKey,
+    delegate_params: Parameters<'static>,
+    attested_contract: Option<ContractInstanceId>,
+) -> Result<ContractKey, OpError> {
+    use crate::contract::ContractHandlerEvent;
+
+    let response = op_manager
+        .notify_contract_handler(ContractHandlerEvent::MigrateQuery {
+            from,
+            migration_delegate,
+            delegate_params,
+            attested_contract,
+        })
+        .await?;
+    let ContractHandlerEvent::MigrateResponse { new_state, .. } = response else {
+        return Err(OpError::UnexpectedOpState);
+    };
+    let key = to.key();
+    let response = op_manager
+        .notify_contract_handler(ContractHandlerEvent::PutQuery {
+            key,
+            state: new_state?,
+            related_contracts: RelatedContracts::default(),
+            contract: Some(to.clone()),
+        })
+        .await?;
+    let ContractHandlerEvent::PutResponse { new_value } = response else {
+        return Err(OpError::UnexpectedOpState);
+    };
+    let op = put::start_op(
+        to,
+        RelatedContracts::default(),
+        new_value?,
+        op_manager.ring.max_hops_to_live,
+    );
+    put::request_put(op_manager, op).await?;
+    Ok(key)
+}
+
 /// Attempts to subscribe to a contract
 pub async fn subscribe(
     op_manager: Arc<OpManager>,
     key: ContractKey,
     client_id: Option<ClientId>,
 ) -> Result<Transaction, OpError> {
-    const TIMEOUT: Duration = Duration::from_secs(30);
     let op = subscribe::start_op(key);
     let id = op.id;
     if let Some(client_id) = client_id {
@@ -642,58 +2139,40 @@ pub async fn subscribe(
     // Initialize a subscribe op.
     match subscribe::request_subscribe(&op_manager, op).await {
         Err(OpError::ContractError(ContractError::ContractNotFound(key))) => {
-            tracing::info!(%key, "Trying to subscribe to a contract not present, requesting it first");
-            let get_op = get::start_op(key, true);
-            if let Err(error) = get::request_get(&op_manager, get_op, vec![]).await {
-                tracing::error!(%key, %error, "Failed getting the contract while previously trying to subscribe; bailing");
-                return Err(error);
-            }
+            tracing::info!(%key, "Trying to subscribe to a contract not present, getting it along");
+            get_and_subscribe(&op_manager, key, client_id).await
         }
         Err(err) => {
             tracing::error!("{}", err);
-            return Err(err);
-        }
-        Ok(()) => {
-            return Ok(id);
+            Err(err)
         }
+        Ok(()) => Ok(id),
     }
-    let timeout = tokio::time::timeout(TIMEOUT, async {
-        loop {
-            // just start a new op to check if contract is present
-            let op = subscribe::start_op(key);
-            match subscribe::request_subscribe(&op_manager, op).await {
-                Err(OpError::ContractError(ContractError::ContractNotFound(_))) => {
-                    tracing::warn!("Still waiting for {key} contract");
-                    tokio::time::sleep(Duration::from_secs(2)).await
-                }
-                Err(error) => {
-                    tracing::error!(%key, %error, "Error while subscribing to contract");
-                    break Err(error);
-                }
-                Ok(()) => {
-                    tracing::debug!(%key,
-                        "Got back the missing contract while subscribing"
-                    );
-                    tracing::debug!(%key, "Starting subscribe request");
-                    break Ok(id);
-                }
-            }
-        }
-    });
-    match timeout.await {
-        Err(_) => {
-            tracing::error!(%key, "Timeout while waiting for contract to start subscription");
-            Err(OpError::OpNotPresent(id))
-        }
-        Ok(Err(error)) => {
-            tracing::error!(%key, %error, "Error while subscribing to contract");
-            Err(error)
-        }
-        Ok(Ok(op_id)) => {
-            tracing::debug!(%key, "Started subscription to contract");
-            Ok(op_id)
-        }
+}
+
+/// Gets a contract and subscribes to it as a single operation, routed once through the
+/// network, instead of a get followed by a subscribe.
+///
+/// The subscription is registered by the peers returning the state; the client is answered
+/// with a subscription response followed by the state as an update notification.
+pub async fn get_and_subscribe(
+    op_manager: &OpManager,
+    key: ContractKey,
+    client_id: Option<ClientId>,
+) -> Result<Transaction, OpError> {
+    let op = get::start_op_and_subscribe(key, true);
+    let id = op.id;
+    if let Some(client_id) = client_id {
+        let _ = op_manager
+            .ch_outbound
+            .waiting_for_transaction_result(id, client_id)
+            .await;
     }
+    if let Err(error) = get::request_get(op_manager, op, vec![]).await {
+        tracing::error!(%key, %error, "Failed getting the contract to subscribe to");
+        return Err(error);
+    }
+    Ok(id)
 }
 
 async fn handle_aborted_op(
@@ -711,7 +2190,7 @@ async fn handle_aborted_op(
             Ok(Some(OpEnum::Connect(op)))
                 if op.has_backoff()
                     && op_manager.ring.open_connections()
-                        < op_manager.ring.connection_manager.min_connections =>
+                        < op_manager.ring.connection_manager.min_connections() =>
             {
                 let ConnectOp {
                     gateway, backoff, ..
@@ -726,7 +2205,7 @@ async fn handle_aborted_op(
                 if op_manager.ring.open_connections() == 0 && op_manager.ring.is_gateway() {
                     tracing::warn!("Retrying joining the ring with an other gateway");
                     if let Some(gateway) = gateways.iter().shuffle().next() {
-                        connect::join_ring_request(None, gateway, op_manager).await?
+                        connect::join_ring_request(None, gateway, op_manager).await?;
                     }
                 }
             }
@@ -851,8 +2330,9 @@ impl Display for PeerId {
 }
 
 pub async fn run_local_node(
-    mut executor: Executor,
+    executor: Executor,
     socket: WebsocketApiConfig,
+    client_storage_dir: PathBuf,
 ) -> anyhow::Result<()> {
     match socket.address {
         IpAddr::V4(ip) if !ip.is_loopback() => {
@@ -864,99 +2344,8 @@ pub async fn run_local_node(
         _ => {}
     }
 
-    let (mut gw, mut ws_proxy) = crate::server::serve_gateway_in(socket).await;
-
-    // TODO: use combinator instead
-    // let mut all_clients =
-    //    ClientEventsCombinator::new([Box::new(ws_handle), Box::new(http_handle)]);
-    enum Receiver {
-        Ws,
-        Gw,
-    }
-    let mut receiver;
-    loop {
-        let req = tokio::select! {
-            req = ws_proxy.recv() => {
-                receiver = Receiver::Ws;
-                req?
-            }
-            req = gw.recv() => {
-                receiver = Receiver::Gw;
-                req?
-            }
-        };
-        let OpenRequest {
-            client_id: id,
-            request,
-            notification_channel,
-            token,
-            ..
-        } = req;
-        tracing::trace!(cli_id = %id, "got request -> {request}");
-
-        let res = match *request {
-            ClientRequest::ContractOp(op) => {
-                executor
-                    .contract_requests(op, id, notification_channel)
-                    .await
-            }
-            ClientRequest::DelegateOp(op) => {
-                let attested_contract =
-                    token.and_then(|token| gw.attested_contracts.get(&token).map(|(t, _)| t));
-                executor.delegate_request(op, attested_contract)
-            }
-            ClientRequest::Disconnect { cause } => {
-                if let Some(cause) = cause {
-                    tracing::info!("disconnecting cause: {cause}");
-                }
-                // fixme: token must live for a bit to allow reconnections
-                if let Some(rm_token) = gw
-                    .attested_contracts
-                    .iter()
-                    .find_map(|(k, (_, eid))| (eid == &id).then(|| k.clone()))
-                {
-                    gw.attested_contracts.remove(&rm_token);
-                }
-                continue;
-            }
-            _ => Err(ExecutorError::other(anyhow::anyhow!("not supported"))),
-        };
-
-        match res {
-            Ok(res) => {
-                match receiver {
-                    Receiver::Ws => ws_proxy.send(id, Ok(res)).await?,
-                    Receiver::Gw => gw.send(id, Ok(res)).await?,
-                };
-            }
-            Err(err) if err.is_request() => {
-                let err = ErrorKind::RequestError(err.unwrap_request());
-                match receiver {
-                    Receiver::Ws => {
-                        ws_proxy.send(id, Err(err.into())).await?;
-                    }
-                    Receiver::Gw => {
-                        gw.send(id, Err(err.into())).await?;
-                    }
-                };
-            }
-            Err(err) => {
-                tracing::error!("{err}");
-                let err = Err(ErrorKind::Unhandled {
-                    cause: format!("{err}").into(),
-                }
-                .into());
-                match receiver {
-                    Receiver::Ws => {
-                        ws_proxy.send(id, err).await?;
-                    }
-                    Receiver::Gw => {
-                        gw.send(id, err).await?;
-                    }
-                };
-            }
-        }
-    }
+    let clients = crate::server::serve_gateway(socket, client_storage_dir).await?;
+    LocalNode::new(executor, clients).run().await
 }
 
 pub async fn run_network_node(mut node: Node) -> anyhow::Result<()> {
@@ -999,6 +2388,7 @@ mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr};
 
     use super::*;
+    use crate::test_utils::{node_config, RecordingBridge};
 
     #[tokio::test]
     async fn test_hostname_resolution() {
@@ -1028,4 +2418,213 @@ mod tests {
         let socket_addr = NodeConfig::parse_socket_addr(&addr).await.unwrap();
         assert_eq!(socket_addr.port(), 8080);
     }
+
+    #[tokio::test]
+    async fn test_node_config_validation() {
+        let mut node_config = node_config("test_node_config_validation").await.unwrap();
+        node_config.with_profile(NodeProfile::Constrained);
+        assert!(node_config.max_number_conn < Some(crate::config::DEFAULT_MAX_CONNECTIONS));
+        assert!(node_config.min_number_conn < Some(crate::config::DEFAULT_MIN_CONNECTIONS));
+        assert_eq!(node_config.contract_executors, Some(1));
+        assert!(node_config.state_storage_quota < Some(crate::config::DEFAULT_STATE_STORAGE_QUOTA));
+        assert!(node_config.validate().is_ok());
+
+        // explicitly set values take precedence over the preset
+        node_config
+            .max_number_of_connections(5)
+            .with_profile(NodeProfile::Simulation);
+        assert_eq!(node_config.max_number_conn, Some(5));
+        assert!(node_config.validate().is_err());
+
+        node_config.min_number_of_connections(1);
+        assert!(node_config.validate().is_ok());
+
+        // gateways may learn their public address later on
+        node_config.is_gateway();
+        assert!(node_config.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn builder_validates_the_config() -> anyhow::Result<()> {
+        async fn config() -> anyhow::Result<Config> {
+            let mut args = crate::config::ConfigArgs::default();
+            args.id = Some("builder_validates_the_config".to_owned());
+            args.build().await
+        }
+
+        let built = NodeConfig::builder(config().await?)
+            .await?
+            .profile(NodeProfile::Constrained)
+            .max_connections(4)
+            .build()?;
+        assert_eq!(built.max_number_conn, Some(4));
+        assert_eq!(built.contract_executors, Some(1));
+
+        // the preset keeps the minimum above the maximum set
+        let built = NodeConfig::builder(config().await?)
+            .await?
+            .max_connections(2)
+            .profile(NodeProfile::Constrained)
+            .build();
+        assert!(built.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn trace_export_requires_trace_ot() -> anyhow::Result<()> {
+        let mut node_config = node_config("trace_export_requires_trace_ot").await?;
+        node_config.with_profile(NodeProfile::Constrained);
+        assert!(node_config.validate().is_ok());
+
+        node_config.export_traces("http://localhost:4317");
+        assert_eq!(node_config.validate().is_ok(), cfg!(feature = "trace-ot"));
+        Ok(())
+    }
+
+    async fn busy_test_node(id: &str) -> anyhow::Result<Arc<OpManager>> {
+        let mut config = node_config(id).await?;
+        config.max_concurrent_operations(1);
+        let op_manager = OpManager::in_memory(&config).await?;
+        op_manager
+            .ring
+            .connection_manager
+            .try_set_peer_key(([127, 0, 0, 1], 10_000).into());
+        Ok(Arc::new(op_manager))
+    }
+
+    #[tokio::test]
+    async fn busy_peers_refuse_requests() -> anyhow::Result<()> {
+        let op_manager = busy_test_node("busy_peers_refuse_requests").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let running = get::start_op(key, false);
+        op_manager.push(running.id, OpEnum::Get(running)).await?;
+
+        let sender = PeerKeyLocation::random();
+        let transaction = Transaction::new::<get::GetMsg>();
+        let request = get::GetMsg::SeekNode {
+            id: transaction,
+            key,
+            fetch_contract: false,
+            target: op_manager.ring.connection_manager.own_location(),
+            sender: sender.clone(),
+            htl: 5,
+            skip_list: vec![],
+        };
+        let bridge = RecordingBridge::default();
+        let (_responses, responses_tx) = crate::contract::client_responses_channel();
+        process_message(
+            NetMessage::V1(NetMessageV1::Get(request)),
+            op_manager.clone(),
+            bridge.clone(),
+            Box::new(crate::tracing::TestEventListener::new().await),
+            None,
+            ClientTransactionTracker::new(responses_tx, op_manager.clock.clone()),
+        )
+        .await;
+
+        let sent = bridge.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, sender.peer);
+        assert!(matches!(
+            sent[0].1,
+            NetMessage::V1(NetMessageV1::Busy { transaction: refused }) if refused == transaction
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refused_operations_fail_right_away() -> anyhow::Result<()> {
+        let op_manager = busy_test_node("refused_operations_fail_right_away").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let op = get::start_op(key, false);
+        let transaction = op.id;
+        op_manager.push(transaction, OpEnum::Get(op)).await?;
+
+        let (mut responses, responses_tx) = crate::contract::client_responses_channel();
+        let client_tracker = ClientTransactionTracker::new(responses_tx, op_manager.clock.clone());
+        let client_id = ClientId::next();
+        client_tracker.track(transaction, client_id);
+        process_message(
+            NetMessage::V1(NetMessageV1::Busy { transaction }),
+            op_manager.clone(),
+            RecordingBridge::default(),
+            Box::new(crate::tracing::TestEventListener::new().await),
+            None,
+            client_tracker,
+        )
+        .await;
+
+        assert!(!op_manager.has_op(&transaction));
+        let (notified, result) = responses.try_recv().expect("client notified");
+        assert_eq!(notified, client_id);
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batches_go_one_by_one_to_peers_without_batches() -> anyhow::Result<()> {
+        let op_manager = busy_test_node("batches_go_one_by_one_to_peers_without_batches").await?;
+        let target = PeerKeyLocation::random();
+        let batch = || NetMessageV1::GetBatch {
+            transaction: Transaction::new::<get::GetMsg>(),
+            target: target.clone(),
+            requests: (0..3u8)
+                .map(|i| get::GetMsg::SeekNode {
+                    id: Transaction::new::<get::GetMsg>(),
+                    key: ContractKey::from(ContractInstanceId::new([i; 32])),
+                    fetch_contract: false,
+                    target: target.clone(),
+                    sender: PeerKeyLocation::random(),
+                    htl: 5,
+                    skip_list: vec![],
+                })
+                .collect(),
+        };
+        let (_responses, responses_tx) = crate::contract::client_responses_channel();
+        let client_tracker = ClientTransactionTracker::new(responses_tx, op_manager.clock.clone());
+        let bridge = RecordingBridge::default();
+        process_get_batch(
+            batch(),
+            &op_manager,
+            &mut bridge.clone(),
+            &mut crate::tracing::TestEventListener::new().await,
+            &client_tracker,
+        )
+        .await;
+        {
+            let sent = bridge.sent();
+            assert_eq!(sent.len(), 3);
+            assert!(sent.iter().all(|(peer, msg)| {
+                peer == &target.peer && matches!(msg, NetMessage::V1(NetMessageV1::Get(_)))
+            }));
+        }
+
+        let hello = network_bridge::hello::Hello {
+            protocol: crate::transport::PROTOC_VERSION,
+            min_protocol: crate::transport::MIN_PROTOC_VERSION,
+            role: network_bridge::hello::NodeRole::Regular,
+            features: vec![Feature::GetBatch],
+        };
+        op_manager
+            .ring
+            .connection_manager
+            .peer_features
+            .greeted(target.peer.addr, &hello);
+        let bridge = RecordingBridge::default();
+        process_get_batch(
+            batch(),
+            &op_manager,
+            &mut bridge.clone(),
+            &mut crate::tracing::TestEventListener::new().await,
+            &client_tracker,
+        )
+        .await;
+        let sent = bridge.sent();
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(
+            sent[0].1,
+            NetMessage::V1(NetMessageV1::GetBatch { .. })
+        ));
+        Ok(())
+    }
 }
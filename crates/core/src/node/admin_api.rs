@@ -0,0 +1,380 @@
+//! Local admin API to inspect and control a running node without restarting it.
+//!
+//! The API is served over HTTP on localhost only, and every request must carry the configured
+//! token as a bearer authorization header. It exposes the connected peers, the transactions
+//! in progress, the contracts seeded and cached by the node, the execution statistics of the
+//! delegates and its configuration, and lets operators drop a connection, look for a new one,
+//! change the rate limits of the messages received from peers, migrate the state of a contract
+//! to a new version of its code or shut the node down.
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use freenet_stdlib::prelude::{
+    CodeHash, ContractCode, ContractContainer, ContractKey, ContractWasmAPIVersion, DelegateKey,
+    Parameters, WrappedContract,
+};
+use headers::{
+    authorization::{Authorization, Bearer},
+    HeaderMapExt,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{op_dispatcher::TX_TYPES, MessageQuota, NodeConfig, OpManager};
+use crate::{
+    contract::{CachedContract, ContractHandlerEvent},
+    message::{NodeEvent, TransactionType},
+    ring::{Location, NeighbourInfo},
+    util::contract_key::CheckedKey,
+    wasm_runtime::DelegateExecStats,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct AdminApiConfig {
+    pub port: u16,
+    /// Token every request must carry.
+    pub token: String,
+}
+
+#[derive(Clone)]
+struct AdminState {
+    op_manager: Arc<OpManager>,
+    token: Arc<str>,
+    /// Configuration of the node, without its secrets.
+    config: Arc<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct OpenTransaction {
+    id: String,
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct ConnectParams {
+    /// Location to look for a peer around, a random one if not given.
+    location: Option<f64>,
+}
+
+/// Migration of the state of a contract to a new version of its code, binary fields in base58.
+#[derive(Deserialize)]
+struct MigrateContract {
+    /// Key of the contract to migrate the state from.
+    from: String,
+    /// Code of the contract to migrate the state to.
+    code: String,
+    #[serde(default)]
+    params: String,
+    /// Delegate transforming the old state into the new one, already registered in the node.
+    migration_delegate: String,
+    delegate_code_hash: String,
+    #[serde(default)]
+    delegate_params: String,
+}
+
+/// Configuration of the node to expose through the API, without its secrets.
+pub(crate) fn config_dump(config: &NodeConfig) -> anyhow::Result<serde_json::Value> {
+    let mut dump = serde_json::to_value(config)?;
+    if let Some(fields) = dump.as_object_mut() {
+        fields.remove("key_pair");
+        fields.remove("admin_api");
+    }
+    Ok(dump)
+}
+
+/// Serves the admin API on localhost until the node shuts down.
+pub(crate) async fn serve_admin_api(
+    op_manager: Arc<OpManager>,
+    admin: AdminApiConfig,
+    config: serde_json::Value,
+) {
+    let state = AdminState {
+        op_manager,
+        token: admin.token.into(),
+        config: Arc::new(config),
+    };
+    let router = Router::new()
+        .route("/peers", get(peers))
+        .route("/peers/:addr/disconnect", post(disconnect_peer))
+        .route("/transactions", get(transactions))
+        .route("/contracts", get(contracts))
+        .route("/contracts/cached", get(cached_contracts))
+        .route("/contracts/migrate", post(migrate_contract))
+        .route("/delegates/:key/:code_hash/stats", get(delegate_stats))
+        .route("/config", get(node_config))
+        .route("/connect", post(connect))
+        .route("/policing/quotas/:tx_type", post(set_message_quota))
+        .route("/shutdown", post(shutdown))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            authenticate,
+        ))
+        .with_state(state);
+
+    let socket = SocketAddr::from((Ipv4Addr::LOCALHOST, admin.port));
+    let served = async {
+        let listener = tokio::net::TcpListener::bind(socket).await?;
+        tracing::info!(%socket, "Admin API listening");
+        axum::serve(listener, router).await
+    };
+    if let Err(error) = served.await {
+        tracing::error!(%socket, %error, "Admin API stopped");
+    }
+}
+
+async fn authenticate(State(state): State<AdminState>, req: Request, next: Next) -> Response {
+    match req.headers().typed_get::<Authorization<Bearer>>() {
+        Some(auth) if token_matches(auth.token(), &state.token) => next.run(req).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Compares the tokens in constant time, so the response time leaks nothing about the token.
+fn token_matches(given: &str, expected: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), expected.as_bytes());
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn peers(State(state): State<AdminState>) -> Json<Vec<NeighbourInfo>> {
+    Json(state.op_manager.ring.topology_snapshot().neighbours)
+}
+
+async fn transactions(State(state): State<AdminState>) -> Json<Vec<OpenTransaction>> {
+    let open = state
+        .op_manager
+        .open_transactions()
+        .into_iter()
+        .map(|tx| OpenTransaction {
+            id: tx.to_string(),
+            kind: tx.transaction_type().to_string(),
+        })
+        .collect();
+    Json(open)
+}
+
+async fn contracts(State(state): State<AdminState>) -> Json<Vec<String>> {
+    let seeded = state
+        .op_manager
+        .ring
+        .seeded_contracts()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    Json(seeded)
+}
+
+async fn cached_contracts(
+    State(state): State<AdminState>,
+) -> Result<Json<Vec<CachedContract>>, (StatusCode, String)> {
+    let response = state
+        .op_manager
+        .notify_contract_handler(ContractHandlerEvent::CachedContractsQuery)
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+    match response {
+        ContractHandlerEvent::CachedContractsResponse { contracts } => Ok(Json(contracts)),
+        _ => unreachable!("CachedContractsQuery always returns CachedContractsResponse"),
+    }
+}
+
+async fn migrate_contract(
+    State(state): State<AdminState>,
+    Json(migration): Json<MigrateContract>,
+) -> Result<Json<String>, (StatusCode, String)> {
+    let bad_request = |field: &str| (StatusCode::BAD_REQUEST, format!("invalid {field}"));
+    let decode = |field, value: &str| {
+        bs58::decode(value)
+            .into_vec()
+            .map_err(|_| bad_request(field))
+    };
+    let hash = |field, value: &str| {
+        <[u8; 32]>::try_from(decode(field, value)?).map_err(|_| bad_request(field))
+    };
+
+    let from = migration
+        .from
+        .parse::<CheckedKey>()
+        .map(ContractKey::from)
+        .map_err(|_| bad_request("from"))?;
+    let to = ContractContainer::Wasm(ContractWasmAPIVersion::V1(WrappedContract::new(
+        Arc::new(ContractCode::from(decode("code", &migration.code)?)),
+        Parameters::from(decode("params", &migration.params)?),
+    )));
+    let migration_delegate = DelegateKey::new(
+        hash("migration_delegate", &migration.migration_delegate)?,
+        CodeHash::new(hash("delegate_code_hash", &migration.delegate_code_hash)?),
+    );
+    let delegate_params = Parameters::from(decode("delegate_params", &migration.delegate_params)?);
+
+    tracing::info!(%from, "Migrating contract state on operator request");
+    let key = super::migrate_contract(
+        &state.op_manager,
+        from,
+        to,
+        migration_delegate,
+        delegate_params,
+        None,
+    )
+    .await
+    .map_err(|error| (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()))?;
+    Ok(Json(key.to_string()))
+}
+
+/// Execution statistics of a delegate, with its key and code hash in base58.
+async fn delegate_stats(
+    State(state): State<AdminState>,
+    Path((key, code_hash)): Path<(String, String)>,
+) -> Result<Json<DelegateExecStats>, (StatusCode, String)> {
+    let hash = |field: &str, value: &str| {
+        bs58::decode(value)
+            .into_vec()
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("invalid {field}")))
+    };
+    let key = DelegateKey::new(
+        hash("key", &key)?,
+        CodeHash::new(hash("code_hash", &code_hash)?),
+    );
+    let response = state
+        .op_manager
+        .notify_contract_handler(ContractHandlerEvent::DelegateStatsQuery { key })
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+    match response {
+        ContractHandlerEvent::DelegateStatsResponse {
+            stats: Some(stats), ..
+        } => Ok(Json(stats)),
+        ContractHandlerEvent::DelegateStatsResponse { key, stats: None } => Err((
+            StatusCode::NOT_FOUND,
+            format!("delegate {key} not executed"),
+        )),
+        _ => unreachable!("DelegateStatsQuery always returns DelegateStatsResponse"),
+    }
+}
+
+async fn node_config(State(state): State<AdminState>) -> Json<serde_json::Value> {
+    Json(serde_json::Value::clone(&state.config))
+}
+
+async fn disconnect_peer(
+    State(state): State<AdminState>,
+    Path(addr): Path<SocketAddr>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let peer = state
+        .op_manager
+        .ring
+        .connection_manager
+        .connected_peers()
+        .find(|peer| peer.addr == addr)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("not connected to {addr}")))?;
+    tracing::info!(%peer, "Dropping connection on operator request");
+    notify(&state, NodeEvent::DropConnection(peer)).await
+}
+
+async fn connect(
+    State(state): State<AdminState>,
+    Query(params): Query<ConnectParams>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let location = match params.location {
+        Some(location) if (0.0..=1.0).contains(&location) => Location::new(location),
+        Some(location) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("invalid location: {location}"),
+            ))
+        }
+        None => Location::random(),
+    };
+    tracing::info!(%location, "Looking for a new connection on operator request");
+    state
+        .op_manager
+        .acquire_connection(location)
+        .await
+        .map_err(|error| (StatusCode::SERVICE_UNAVAILABLE, error.to_string()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Transaction type by its name, e.g. `get`.
+fn transaction_type(name: &str) -> Option<TransactionType> {
+    TX_TYPES
+        .into_iter()
+        .find(|tx_type| tx_type.description() == name)
+}
+
+async fn set_message_quota(
+    State(state): State<AdminState>,
+    Path(tx_type): Path<String>,
+    Json(quota): Json<MessageQuota>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let tx_type = transaction_type(&tx_type).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("unknown transaction type: {tx_type}"),
+        )
+    })?;
+    if quota.burst == 0 || quota.refill_every.is_zero() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the burst and refill period must be positive".into(),
+        ));
+    }
+    tracing::info!(%tx_type, "Changing message quota on operator request");
+    notify(&state, NodeEvent::SetMessageQuota { tx_type, quota }).await
+}
+
+async fn shutdown(State(state): State<AdminState>) -> Result<StatusCode, (StatusCode, String)> {
+    tracing::info!("Shutting down on operator request");
+    let event = NodeEvent::Disconnect {
+        cause: Some("shutdown requested by the operator".into()),
+    };
+    notify(&state, event).await
+}
+
+async fn notify(state: &AdminState, event: NodeEvent) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .op_manager
+        .notify_node_event(event)
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(token_matches("s3cret-token", "s3cret-token"));
+        assert!(!token_matches("s3cret-tokem", "s3cret-token"));
+        assert!(!token_matches("s3cret", "s3cret-token"));
+        assert!(!token_matches("", "s3cret-token"));
+    }
+
+    #[test]
+    fn transaction_types_by_name() {
+        assert_eq!(transaction_type("get"), Some(TransactionType::Get));
+        assert_eq!(
+            transaction_type("subscribe"),
+            Some(TransactionType::Subscribe)
+        );
+        assert_eq!(transaction_type("Get"), None);
+        assert_eq!(transaction_type("ping"), None);
+    }
+}
@@ -0,0 +1,404 @@
+//! Discovery of the gateways to join the network through from seed lists, so they don't have
+//! to be configured one by one.
+//!
+//! Seed lists are published either as the TXT records of a DNS name or as a document served
+//! over HTTPS, with a seed per record or line: the address of the gateway (`host:port`)
+//! followed by its public key, the base58 of its DER encoding. The gateways from all the
+//! sources are health-checked in parallel by opening a connection to them, and the ones which
+//! answered are ranked by how fast they did. The ranked list is cached on disk and used in
+//! place of the sources when none of them can be reached.
+//!
+//! While the node runs the seed lists are fetched again every refresh interval, and the
+//! gateways not known yet are handed to the event loop to join the network through.
+
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use rsa::pkcs8::DecodePublicKey;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use super::{InitPeerNode, NodeConfig, OpManager, PeerId};
+use crate::{
+    config::Address,
+    message::NodeEvent,
+    ring::{Location, PeerKeyLocation},
+    transport::{
+        create_connection_handler, AddressBook, BandwidthLimits, DualStack, NetworkId,
+        TrafficCounters, TransportKeypair, TransportKind, TransportPublicKey,
+    },
+};
+
+const BOOTSTRAP_SEEDS_FILE: &str = "bootstrap_seeds";
+
+/// Source of a list of gateways.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeedSource {
+    /// DNS name with a TXT record per gateway.
+    Dns(String),
+    /// URL of a document with a line per gateway, must be served over HTTPS.
+    Https(String),
+}
+
+impl SeedSource {
+    /// Checks the URL of an HTTPS source uses the `https` scheme, so the seed list can't be
+    /// tampered with on the way.
+    pub(super) fn validate(&self) -> anyhow::Result<()> {
+        if let SeedSource::Https(url) = self {
+            https_url(url)?;
+        }
+        Ok(())
+    }
+}
+
+fn https_url(url: &str) -> anyhow::Result<reqwest::Url> {
+    let url = reqwest::Url::parse(url)?;
+    if url.scheme() != "https" {
+        anyhow::bail!("gateway seeds must be served over HTTPS: {url}");
+    }
+    Ok(url)
+}
+
+/// Configuration of the discovery of gateways from seed lists.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootstrapConfig {
+    pub sources: Vec<SeedSource>,
+    /// Time a gateway has to accept the connection of the health check.
+    pub health_check_timeout: Duration,
+    /// Max number of gateways taken from the seed lists, the fastest ones first.
+    pub max_gateways: usize,
+    /// Time between the fetches of the seed lists while the node runs, only fetched on start
+    /// if not set.
+    pub refresh_interval: Option<Duration>,
+}
+
+impl Default for BootstrapConfig {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            health_check_timeout: Duration::from_secs(5),
+            max_gateways: 8,
+            refresh_interval: Some(Duration::from_secs(6 * 60 * 60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Seed {
+    addr: SocketAddr,
+    pub_key: TransportPublicKey,
+}
+
+/// Gateways discovered from the seed lists of the node, the fastest to answer first.
+pub(super) async fn discover_gateways(config: &NodeConfig) -> Vec<InitPeerNode> {
+    let bootstrap = &config.bootstrap;
+    let cache = config.config.db_dir().join(BOOTSTRAP_SEEDS_FILE);
+    let mut candidates = Vec::new();
+    for source in &bootstrap.sources {
+        match fetch(source).await {
+            Ok(seeds) => candidates.extend(seeds),
+            Err(error) => tracing::warn!(?source, %error, "Failed to fetch gateway seeds"),
+        }
+    }
+    if candidates.is_empty() {
+        candidates = load_cache(&cache);
+        tracing::info!(
+            gateways = candidates.len(),
+            "No gateway seeds fetched, using the cached ones"
+        );
+    }
+    let mut unique = HashSet::new();
+    candidates.retain(|seed| unique.insert(seed.clone()));
+
+    let transports = config
+        .transports
+        .clone()
+        .unwrap_or_else(|| crate::config::DEFAULT_TRANSPORTS.to_vec());
+    let round_trips = match health_check(
+        &candidates,
+        &transports,
+        config.network_id,
+        config.dual_stack,
+        bootstrap.health_check_timeout,
+    )
+    .await
+    {
+        Ok(round_trips) => round_trips,
+        Err(error) => {
+            tracing::warn!(%error, "Failed to health check the gateway seeds");
+            return Vec::new();
+        }
+    };
+    let ranked = rank(
+        candidates.into_iter().zip(round_trips),
+        bootstrap.max_gateways,
+    );
+    tracing::info!(gateways = ranked.len(), "Discovered gateways from seeds");
+    if !ranked.is_empty() {
+        if let Err(error) = persist(&cache, &ranked) {
+            tracing::warn!(%error, "Failed to cache the gateway seeds");
+        }
+    }
+    ranked
+        .into_iter()
+        .map(|seed| {
+            let location = Location::from_address(&seed.addr);
+            InitPeerNode::new(PeerId::new(seed.addr, seed.pub_key), location)
+        })
+        .collect()
+}
+
+/// Discovers the gateways from the seed lists every `interval` until the node stops.
+pub(super) async fn refresh_gateways(
+    op_manager: Arc<OpManager>,
+    config: NodeConfig,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick completes right away, and the gateways were discovered on start
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let gateways: Vec<_> = discover_gateways(&config)
+            .await
+            .into_iter()
+            .map(|gateway| PeerKeyLocation {
+                peer: gateway.peer_id,
+                location: Some(gateway.location),
+            })
+            .collect();
+        if gateways.is_empty() {
+            continue;
+        }
+        if op_manager
+            .notify_node_event(NodeEvent::GatewaysDiscovered { gateways })
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn fetch(source: &SeedSource) -> anyhow::Result<Vec<Seed>> {
+    let records: Vec<String> = match source {
+        SeedSource::Dns(name) => {
+            let resolver = NodeConfig::system_resolver()?;
+            // long records are split in several strings
+            resolver
+                .txt_lookup(name.as_str())
+                .await?
+                .iter()
+                .map(|txt| {
+                    txt.txt_data()
+                        .iter()
+                        .map(|chunk| String::from_utf8_lossy(chunk))
+                        .collect()
+                })
+                .collect()
+        }
+        SeedSource::Https(url) => {
+            // redirects to plain HTTP are refused as well
+            let client = reqwest::Client::builder().https_only(true).build()?;
+            client
+                .get(https_url(url)?)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?
+                .lines()
+                .map(str::to_owned)
+                .collect()
+        }
+    };
+    Ok(parse_records(&records).await)
+}
+
+async fn parse_records(records: &[String]) -> Vec<Seed> {
+    let mut seeds = Vec::new();
+    for record in records
+        .iter()
+        .map(|record| record.trim())
+        .filter(|record| !record.is_empty() && !record.starts_with('#'))
+    {
+        let Some((address, pub_key)) = parse_seed(record) else {
+            tracing::warn!(%record, "Invalid gateway seed, ignoring");
+            continue;
+        };
+        match NodeConfig::parse_socket_addr(&address).await {
+            Ok(addr) => seeds.push(Seed { addr, pub_key }),
+            Err(error) => tracing::warn!(%record, %error, "Failed to resolve gateway seed"),
+        }
+    }
+    seeds
+}
+
+fn parse_seed(record: &str) -> Option<(Address, TransportPublicKey)> {
+    let mut fields = record.split_whitespace();
+    let (address, pub_key) = (fields.next()?, fields.next()?);
+    if fields.next().is_some() {
+        return None;
+    }
+    let der = bs58::decode(pub_key).into_vec().ok()?;
+    let pub_key = rsa::RsaPublicKey::from_public_key_der(&der).ok()?;
+    let address = match address.parse() {
+        Ok(addr) => Address::HostAddress(addr),
+        Err(_) => Address::Hostname(address.to_owned()),
+    };
+    Some((address, pub_key.into()))
+}
+
+/// Time it took to connect to each seed, if it could be connected to in time.
+async fn health_check(
+    seeds: &[Seed],
+    transports: &[TransportKind],
+    network_id: NetworkId,
+    dual_stack: DualStack,
+    timeout: Duration,
+) -> anyhow::Result<Vec<Option<Duration>>> {
+    if seeds.is_empty() {
+        return Ok(Vec::new());
+    }
+    // a throwaway identity, so the checks are not tied to the identity of the node
+    let (_limits, limits) = watch::channel(BandwidthLimits::default());
+    let listen_host = Ipv4Addr::UNSPECIFIED.into();
+    let (outbound, _inbound) = create_connection_handler(
+        transports,
+        limits,
+        Arc::new(TrafficCounters::default()),
+        TransportKeypair::new(),
+        listen_host,
+        0,
+        dual_stack.secondary(listen_host),
+        false,
+        network_id,
+        AddressBook::default(),
+    )
+    .await?;
+    let checks = seeds.iter().map(|seed| {
+        let mut outbound = outbound.clone();
+        async move {
+            let start = Instant::now();
+            let connected = tokio::time::timeout(timeout, async {
+                outbound
+                    .connect(seed.pub_key.clone(), seed.addr)
+                    .await
+                    .await
+            })
+            .await;
+            match connected {
+                Ok(Ok(_)) => Some(start.elapsed()),
+                Ok(Err(error)) => {
+                    tracing::debug!(addr = %seed.addr, %error, "Gateway seed failed health check");
+                    None
+                }
+                Err(_) => {
+                    tracing::debug!(addr = %seed.addr, "Gateway seed health check timed out");
+                    None
+                }
+            }
+        }
+    });
+    Ok(join_all(checks).await)
+}
+
+/// The seeds which passed the health check, the fastest first.
+fn rank(checked: impl IntoIterator<Item = (Seed, Option<Duration>)>, max: usize) -> Vec<Seed> {
+    let mut healthy: Vec<_> = checked
+        .into_iter()
+        .filter_map(|(seed, round_trip)| Some((round_trip?, seed)))
+        .collect();
+    healthy.sort_by_key(|(round_trip, _)| *round_trip);
+    healthy
+        .into_iter()
+        .take(max)
+        .map(|(_, seed)| seed)
+        .collect()
+}
+
+fn load_cache(path: &Path) -> Vec<Seed> {
+    match std::fs::read(path) {
+        Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|error| {
+            tracing::warn!(%error, "Failed to load the cached gateway seeds, discarding");
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn persist(path: &Path, seeds: &[Seed]) -> std::io::Result<()> {
+    let bytes = bincode::serialize(seeds).map_err(std::io::Error::other)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use pkcs8::EncodePublicKey;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn seeds_are_parsed_ranked_and_cached() {
+        let encoded_key = || {
+            let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 256).unwrap();
+            let der = key.to_public_key().to_public_key_der().unwrap();
+            bs58::encode(der.as_bytes()).into_string()
+        };
+        let document = format!(
+            "# gateways of the network\n\
+             127.0.0.1:31337 {}\n\
+             127.0.0.2:31337 {}\n\
+             127.0.0.3:31337 not-a-key\n\
+             \n\
+             127.0.0.4:31337 {}\n",
+            encoded_key(),
+            encoded_key(),
+            encoded_key(),
+        );
+        let records: Vec<_> = document.lines().map(str::to_owned).collect();
+        let seeds = parse_records(&records).await;
+        let addrs: Vec<_> = seeds.iter().map(|seed| seed.addr.to_string()).collect();
+        assert_eq!(
+            addrs,
+            ["127.0.0.1:31337", "127.0.0.2:31337", "127.0.0.4:31337"]
+        );
+
+        let round_trips = [
+            Some(Duration::from_millis(80)),
+            None,
+            Some(Duration::from_millis(20)),
+        ];
+        let ranked = rank(seeds.iter().cloned().zip(round_trips), 8);
+        assert_eq!(ranked, [seeds[2].clone(), seeds[0].clone()]);
+        assert_eq!(rank(seeds.iter().cloned().zip(round_trips), 1).len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join(BOOTSTRAP_SEEDS_FILE);
+        assert!(load_cache(&cache).is_empty());
+        persist(&cache, &ranked).unwrap();
+        assert_eq!(load_cache(&cache), ranked);
+    }
+
+    #[tokio::test]
+    async fn seeds_only_fetched_over_https() {
+        let plain = SeedSource::Https("http://127.0.0.1:31337/seeds".into());
+        assert!(plain.validate().is_err());
+        let error = fetch(&plain).await.unwrap_err();
+        assert!(error.to_string().contains("HTTPS"), "{error}");
+        assert!(
+            SeedSource::Https("https://seeds.freenet.org/gateways".into())
+                .validate()
+                .is_ok()
+        );
+    }
+}
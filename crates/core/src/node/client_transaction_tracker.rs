@@ -0,0 +1,182 @@
+//! Bookkeeping of the transactions initiated on behalf of local clients.
+//!
+//! Once a client request is turned into a network operation the client is waiting for the
+//! outcome of the transaction; the tracker maps the transaction back to the client and routes
+//! the operation result, or the reason it failed, through the client responses channel.
+
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use freenet_stdlib::client_api::ErrorKind;
+
+use crate::{
+    client_events::{ClientId, HostResult},
+    contract::ClientResponsesSender,
+    message::Transaction,
+    operations::OpError,
+    util::time_source::SharedClock,
+};
+
+#[derive(Clone)]
+pub(crate) struct ClientTransactionTracker {
+    pending: Arc<DashMap<Transaction, ClientId>>,
+    responses: ClientResponsesSender,
+    clock: SharedClock,
+}
+
+impl ClientTransactionTracker {
+    pub fn new(responses: ClientResponsesSender, clock: SharedClock) -> Self {
+        Self {
+            pending: Arc::new(DashMap::new()),
+            responses,
+            clock,
+        }
+    }
+
+    /// Starts tracking a transaction initiated by a client. Transactions which timed out
+    /// in the meantime are dropped and their clients notified.
+    pub fn track(&self, tx: Transaction, client_id: ClientId) {
+        self.expire();
+        self.pending.insert(tx, client_id);
+    }
+
+    pub fn client_for(&self, tx: &Transaction) -> Option<ClientId> {
+        self.pending.get(tx).map(|client| *client)
+    }
+
+    /// Routes the result of a finished operation back to the client which initiated it, if any.
+    pub fn notify_result(&self, tx: &Transaction, result: HostResult) {
+        self.notify_result_with(tx, result, None);
+    }
+
+    /// Routes the result of a finished operation back to the client which initiated it, if any,
+    /// followed by the notification the operation produced for the client.
+    pub fn notify_result_with(
+        &self,
+        tx: &Transaction,
+        result: HostResult,
+        notification: Option<HostResult>,
+    ) {
+        if let Some((_, client_id)) = self.pending.remove(tx) {
+            tracing::debug!(%tx, %client_id, "Reporting operation result to client");
+            let _ = self.responses.send((client_id, result));
+            if let Some(notification) = notification {
+                let _ = self.responses.send((client_id, notification));
+            }
+        }
+    }
+
+    /// Notifies the client which initiated the transaction, if any, that the operation failed.
+    ///
+    /// Request errors, like a contract rejecting an update, are forwarded as they are so the
+    /// client gets the reason given by the contract.
+    pub fn notify_failure(&self, tx: &Transaction, error: &OpError) {
+        let request_error = match error {
+            OpError::ExecutorError(err) => err.request_error().cloned(),
+            _ => None,
+        };
+        let kind = request_error
+            .map(ErrorKind::RequestError)
+            .unwrap_or_else(|| ErrorKind::OperationError {
+                cause: format!("{error}").into(),
+            });
+        self.notify_result(tx, Err(kind.into()));
+    }
+
+    fn expire(&self) {
+        let now = self.clock.unix_time();
+        self.pending.retain(|tx, client_id| {
+            if !tx.timed_out(now) {
+                return true;
+            }
+            tracing::debug!(%tx, %client_id, "Client transaction timed out");
+            let _ = self.responses.send((
+                *client_id,
+                Err(ErrorKind::OperationError {
+                    cause: "operation timed out".into(),
+                }
+                .into()),
+            ));
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        contract::client_responses_channel, operations::put::PutMsg, util::time_source::TokioClock,
+    };
+
+    #[test]
+    fn route_results_to_client() {
+        let (mut responses, sender) = client_responses_channel();
+        let tracker = ClientTransactionTracker::new(sender, Arc::new(TokioClock));
+        let tx = Transaction::new::<PutMsg>();
+        let client_id = ClientId::next();
+        tracker.track(tx, client_id);
+        assert_eq!(tracker.client_for(&tx), Some(client_id));
+
+        tracker.notify_failure(&tx, &OpError::UnexpectedOpState);
+        let (notified, result) = responses.try_recv().expect("result sent");
+        assert_eq!(notified, client_id);
+        assert!(result.is_err());
+
+        // the transaction is no longer tracked once the client was notified
+        assert!(tracker.client_for(&tx).is_none());
+        tracker.notify_failure(&tx, &OpError::UnexpectedOpState);
+        assert!(responses.try_recv().is_err());
+    }
+
+    #[test]
+    fn forward_contract_rejections() {
+        use freenet_stdlib::client_api::{ContractError as StdContractError, RequestError};
+        use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+
+        let (mut responses, sender) = client_responses_channel();
+        let tracker = ClientTransactionTracker::new(sender, Arc::new(TokioClock));
+        let tx = Transaction::new::<PutMsg>();
+        tracker.track(tx, ClientId::next());
+
+        let rejection = RequestError::from(StdContractError::Update {
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            cause: "rejected by contract (code 3): sender blocked".into(),
+        });
+        tracker.notify_failure(&tx, &OpError::ExecutorError(rejection.into()));
+        let (_, result) = responses.try_recv().expect("result sent");
+        match result.unwrap_err().kind() {
+            ErrorKind::RequestError(RequestError::ContractError(StdContractError::Update {
+                cause,
+                ..
+            })) => assert!(cause.contains("sender blocked")),
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn notifications_follow_the_result() {
+        let (mut responses, sender) = client_responses_channel();
+        let tracker = ClientTransactionTracker::new(sender, Arc::new(TokioClock));
+        let tx = Transaction::new::<PutMsg>();
+        let client_id = ClientId::next();
+        tracker.track(tx, client_id);
+
+        let failure = |cause: &'static str| -> HostResult {
+            Err(ErrorKind::OperationError {
+                cause: cause.into(),
+            }
+            .into())
+        };
+        tracker.notify_result_with(&tx, failure("result"), Some(failure("notification")));
+        for expected in ["result", "notification"] {
+            let (notified, result) = responses.try_recv().expect("sent");
+            assert_eq!(notified, client_id);
+            match result.unwrap_err().kind() {
+                ErrorKind::OperationError { cause } => assert_eq!(cause, expected),
+                other => panic!("unexpected error: {other:?}"),
+            }
+        }
+        assert!(responses.try_recv().is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Applying the changes to the configuration file while the node runs.
+//!
+//! The configuration file is read again whenever it changes on disk or, on unix, when the
+//! node receives a SIGHUP. The settings which are safe to change at runtime, the log level
+//! and the connection and bandwidth limits, are applied right away. Changes to any other
+//! setting are reported as requiring a restart, and the node keeps running with the old value.
+
+use std::{sync::Arc, time::Duration};
+
+use notify::Watcher;
+use serde::Serialize;
+use tokio::sync::{mpsc, watch};
+
+use super::OpManager;
+use crate::{
+    config::{Config, ConfigArgs, LimitsConfig},
+    transport::BandwidthLimits,
+};
+
+/// Time given to the writes to the configuration file to settle before reading it.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Settings applied to the running node, changed since it started if the file was reloaded.
+struct Applied {
+    log_level: tracing::log::LevelFilter,
+    limits: LimitsConfig,
+}
+
+/// Reloads the configuration file on changes until the node shuts down.
+pub(crate) async fn watch_config(
+    op_manager: Arc<OpManager>,
+    running: Arc<Config>,
+    bandwidth: watch::Sender<BandwidthLimits>,
+) {
+    let config_dir = running.paths().config_dir();
+    let (reload_tx, mut reload_rx) = mpsc::channel(1);
+
+    let file_changed = reload_tx.clone();
+    let watcher =
+        notify::recommended_watcher(move |res: Result<notify::Event, notify::Error>| match res {
+            Ok(event) if is_config_change(&event) => {
+                let _ = file_changed.try_send(());
+            }
+            Ok(_) => {}
+            Err(error) => tracing::debug!(%error, "Failed watching the configuration file"),
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&config_dir, notify::RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+    let _watcher = match watcher {
+        Ok(watcher) => Some(watcher),
+        Err(error) => {
+            tracing::warn!(%error, dir = %config_dir.display(), "Not watching the configuration file for changes");
+            None
+        }
+    };
+
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            let reload_tx = reload_tx.clone();
+            crate::config::GlobalExecutor::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    if reload_tx.send(()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Err(error) => tracing::warn!(%error, "Not reloading the configuration on SIGHUP"),
+    }
+    drop(reload_tx);
+
+    let mut applied = Applied {
+        log_level: running.log_level,
+        limits: running.limits,
+    };
+    while reload_rx.recv().await.is_some() {
+        tokio::time::sleep(SETTLE_TIME).await;
+        while reload_rx.try_recv().is_ok() {}
+        let reloaded = match ConfigArgs::read_config(&config_dir) {
+            Ok(Some(reloaded)) => reloaded,
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::warn!(%error, "Failed reloading the configuration file");
+                continue;
+            }
+        };
+        tracing::info!("Configuration file reloaded");
+        if reloaded.log_level != applied.log_level {
+            if crate::config::set_log_level(to_level_filter(reloaded.log_level)) {
+                tracing::info!(log_level = %reloaded.log_level, "Log level changed");
+            }
+            applied.log_level = reloaded.log_level;
+        }
+        if reloaded.limits != applied.limits {
+            match apply_limits(&op_manager, &bandwidth, &reloaded.limits) {
+                Ok(()) => applied.limits = reloaded.limits,
+                Err(error) => tracing::warn!(%error, "Invalid limits in the configuration file"),
+            }
+        }
+        let restart_required = restart_required(&running, &reloaded);
+        if !restart_required.is_empty() {
+            tracing::warn!(
+                settings = ?restart_required,
+                "Changes to the configuration only applied after a restart"
+            );
+        }
+    }
+}
+
+fn is_config_change(event: &notify::Event) -> bool {
+    (event.kind.is_create() || event.kind.is_modify())
+        && event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("config"))
+        })
+}
+
+fn apply_limits(
+    op_manager: &OpManager,
+    bandwidth: &watch::Sender<BandwidthLimits>,
+    limits: &LimitsConfig,
+) -> anyhow::Result<()> {
+    let connection_manager = &op_manager.ring.connection_manager;
+    let connections = connection_limits(
+        (
+            connection_manager.min_connections(),
+            connection_manager.max_connections(),
+        ),
+        limits,
+    )?;
+    let bandwidth_limits = bandwidth_limits(*bandwidth.borrow(), limits)?;
+    if connections
+        != (
+            connection_manager.min_connections(),
+            connection_manager.max_connections(),
+        )
+    {
+        connection_manager.set_connection_limits(connections.0, connections.1);
+        tracing::info!(
+            min_connections = connections.0,
+            max_connections = connections.1,
+            "Connection limits changed"
+        );
+    }
+    bandwidth.send_if_modified(|current| {
+        let modified = current.total != bandwidth_limits.total
+            || current.remote_share != bandwidth_limits.remote_share;
+        *current = bandwidth_limits;
+        modified
+    });
+    Ok(())
+}
+
+/// Min and max number of connections after applying the limits set, if valid.
+fn connection_limits(
+    (min, max): (usize, usize),
+    limits: &LimitsConfig,
+) -> anyhow::Result<(usize, usize)> {
+    let min = limits.min_connections.unwrap_or(min);
+    let max = limits.max_connections.unwrap_or(max);
+    if max == 0 || min > max {
+        anyhow::bail!("min ({min}) and max ({max}) number of connections are inconsistent");
+    }
+    Ok((min, max))
+}
+
+/// Bandwidth limits after applying the limits set, if valid.
+fn bandwidth_limits(
+    current: BandwidthLimits,
+    limits: &LimitsConfig,
+) -> anyhow::Result<BandwidthLimits> {
+    let total = limits.upstream_bandwidth_limit.unwrap_or(current.total);
+    let remote_share = limits.peer_bandwidth_share.unwrap_or(current.remote_share);
+    if total == 0 {
+        anyhow::bail!("upstream bandwidth limit must be greater than zero");
+    }
+    if !(remote_share > 0.0 && remote_share <= 1.0) {
+        anyhow::bail!("peer bandwidth share must be within (0, 1]");
+    }
+    Ok(BandwidthLimits {
+        total,
+        remote_share,
+    })
+}
+
+/// Settings which changed in the file but cannot be applied without restarting the node.
+fn restart_required(running: &Config, reloaded: &Config) -> Vec<&'static str> {
+    fn differ<T: Serialize>(a: &T, b: &T) -> bool {
+        serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
+    }
+    let mut changed = Vec::new();
+    if running.mode != reloaded.mode {
+        changed.push("mode");
+    }
+    if running.profile != reloaded.profile {
+        changed.push("profile");
+    }
+    if running.is_gateway != reloaded.is_gateway {
+        changed.push("is_gateway");
+    }
+    if differ(&running.network_api, &reloaded.network_api) {
+        changed.push("network_api");
+    }
+    if differ(&running.ws_api, &reloaded.ws_api) {
+        changed.push("ws_api");
+    }
+    if running.secrets != reloaded.secrets {
+        changed.push("secrets");
+    }
+    if differ(&running.paths(), &reloaded.paths()) {
+        changed.push("paths");
+    }
+    changed
+}
+
+fn to_level_filter(level: tracing::log::LevelFilter) -> tracing::level_filters::LevelFilter {
+    use tracing::{level_filters::LevelFilter, log};
+    match level {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_limits_are_kept() {
+        let current = BandwidthLimits {
+            total: 1000,
+            remote_share: 0.5,
+        };
+        let limits = LimitsConfig {
+            max_connections: Some(30),
+            upstream_bandwidth_limit: Some(2000),
+            ..Default::default()
+        };
+        assert_eq!(connection_limits((10, 20), &limits).unwrap(), (10, 30));
+        let changed = bandwidth_limits(current, &limits).unwrap();
+        assert_eq!((changed.total, changed.remote_share), (2000, 0.5));
+
+        let inconsistent = LimitsConfig {
+            min_connections: Some(40),
+            ..limits
+        };
+        assert!(connection_limits((10, 20), &inconsistent).is_err());
+        let invalid_share = LimitsConfig {
+            peer_bandwidth_share: Some(1.5),
+            ..limits
+        };
+        assert!(bandwidth_limits(current, &invalid_share).is_err());
+    }
+}
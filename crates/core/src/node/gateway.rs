@@ -0,0 +1,207 @@
+//! Behaviour specific to gateways, the publicly reachable peers others join the network
+//! through: admitting the joiners and keeping statistics on the joins served.
+//!
+//! Locations are assigned to joiners by any peer they join through, gateway or not, see
+//! [`ConnectionManager::assign_location`].
+//!
+//! Regular peers don't run a gateway service at all.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use self::join_quota::JoinQuotas;
+use super::{OpManager, PeerId};
+use crate::ring::{ConnectionManager, Location};
+
+mod join_quota;
+pub use join_quota::JoinQuota;
+
+/// Configuration of the gateway service, only relevant for nodes running as gateways.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GatewayServiceConfig {
+    /// Interval at which the join statistics are reported.
+    pub stats_interval: Duration,
+    /// Joins admitted from a single IP address.
+    pub ip_join_quota: JoinQuota,
+    /// Joins admitted from all the IP addresses in a subnet.
+    pub subnet_join_quota: JoinQuota,
+}
+
+impl Default for GatewayServiceConfig {
+    fn default() -> Self {
+        Self {
+            stats_interval: Duration::from_secs(5 * 60),
+            ip_join_quota: JoinQuota::new(3, Duration::from_secs(20)),
+            subnet_join_quota: JoinQuota::new(16, Duration::from_secs(5)),
+        }
+    }
+}
+
+/// Outcome of a join request received by the gateway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Accepted,
+    Rejected,
+    /// Out of resources for joins, regardless of the joiner.
+    Busy,
+    /// The joiner exhausted the join quota of its address, and may try again after a while.
+    RateLimited {
+        retry_after: Duration,
+    },
+}
+
+/// Statistics of the joins served by the gateway since it started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GatewayStats {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub busy: usize,
+    pub rate_limited: usize,
+    /// Accepted joiners which completed the connection.
+    pub joined: usize,
+}
+
+#[derive(Default)]
+struct StatsCounters {
+    accepted: AtomicUsize,
+    rejected: AtomicUsize,
+    busy: AtomicUsize,
+    rate_limited: AtomicUsize,
+    joined: AtomicUsize,
+}
+
+#[derive(Clone)]
+pub(crate) struct GatewayService {
+    config: GatewayServiceConfig,
+    join_quotas: JoinQuotas,
+    stats: Arc<StatsCounters>,
+}
+
+impl GatewayService {
+    pub fn new(config: GatewayServiceConfig) -> Self {
+        Self {
+            config,
+            join_quotas: JoinQuotas::new(config.ip_join_quota, config.subnet_join_quota),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Decides whether the joiner, at the given location, is admitted as a direct connection
+    /// of this gateway. Admitted joiners have a connection spot reserved.
+    ///
+    /// Joins exceeding the quota of the joiner address are refused before considering them.
+    pub fn admit(
+        &self,
+        connection_manager: &ConnectionManager,
+        location: Location,
+        joiner: &PeerId,
+    ) -> Admission {
+        let (admission, counter) = if let Err(retry_after) = self
+            .join_quotas
+            .try_acquire(joiner.addr.ip(), connection_manager.clock.now())
+        {
+            tracing::debug!(%joiner, ?retry_after, "Join quota exhausted");
+            (
+                Admission::RateLimited { retry_after },
+                &self.stats.rate_limited,
+            )
+        } else if connection_manager.is_busy() {
+            (Admission::Busy, &self.stats.busy)
+        } else if connection_manager.should_accept(location, joiner) {
+            (Admission::Accepted, &self.stats.accepted)
+        } else {
+            (Admission::Rejected, &self.stats.rejected)
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        admission
+    }
+
+    /// Records the connection of a joiner.
+    pub fn joined(&self) {
+        self.stats.joined.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> GatewayStats {
+        GatewayStats {
+            accepted: self.stats.accepted.load(Ordering::Relaxed),
+            rejected: self.stats.rejected.load(Ordering::Relaxed),
+            busy: self.stats.busy.load(Ordering::Relaxed),
+            rate_limited: self.stats.rate_limited.load(Ordering::Relaxed),
+            joined: self.stats.joined.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Periodically reports the join statistics, for as long as the node runs.
+    pub async fn report_stats(self, op_manager: Arc<OpManager>) {
+        loop {
+            op_manager.clock.sleep(self.config.stats_interval).await;
+            let GatewayStats {
+                accepted,
+                rejected,
+                busy,
+                rate_limited,
+                joined,
+            } = self.stats();
+            tracing::info!(
+                accepted,
+                rejected,
+                busy,
+                rate_limited,
+                joined,
+                open_connections = op_manager.ring.open_connections(),
+                "Gateway join statistics"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportKeypair;
+
+    #[test]
+    fn admits_joins_until_busy() {
+        let connection_manager =
+            ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        connection_manager.update_location(Some(Location::new(0.5)));
+        let gateway = connection_manager
+            .gateway()
+            .expect("test connection managers run as gateways")
+            .clone();
+
+        // every admitted join reserves a connection spot, until running out of them
+        for _ in 0..connection_manager.max_connections() {
+            let joiner = PeerId::random();
+            let location = connection_manager.assign_location(&joiner.addr);
+            assert_eq!(
+                gateway.admit(&connection_manager, location, &joiner),
+                Admission::Accepted
+            );
+        }
+        let joiner = PeerId::random();
+        assert_eq!(
+            gateway.admit(&connection_manager, Location::new(0.1), &joiner),
+            Admission::Busy
+        );
+        gateway.joined();
+
+        assert_eq!(
+            gateway.stats(),
+            GatewayStats {
+                accepted: connection_manager.max_connections(),
+                rejected: 0,
+                busy: 1,
+                rate_limited: 0,
+                joined: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Rate limiting of the join requests received by a gateway, so a single host or network
+//! can't flood it with joins.
+//!
+//! Each source IP and each subnet (/24 for IPv4, /48 for IPv6) has a token bucket; a join
+//! consumes a token from both the bucket of its IP and the one of its subnet, and is refused
+//! while any of them is empty.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Token bucket parameters of a join quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinQuota {
+    /// Max number of joins admitted in a burst.
+    pub burst: u32,
+    /// Time it takes to recover one join of the burst.
+    pub refill_every: Duration,
+}
+
+impl JoinQuota {
+    pub const fn new(burst: u32, refill_every: Duration) -> Self {
+        Self {
+            burst,
+            refill_every,
+        }
+    }
+}
+
+/// Once tracking this many buckets, the ones already refilled are forgotten.
+const MAX_TRACKED_BUCKETS: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, quota: &JoinQuota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled = elapsed.as_secs_f64() / quota.refill_every.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(quota.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Time until a token is available again.
+    fn retry_after(&self, quota: &JoinQuota) -> Duration {
+        quota.refill_every.mul_f64((1.0 - self.tokens).max(0.0))
+    }
+
+    fn is_full(&self, quota: &JoinQuota) -> bool {
+        self.tokens >= quota.burst as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Ip(IpAddr),
+    Subnet(IpAddr),
+}
+
+impl Source {
+    fn subnet(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                Self::Subnet(IpAddr::from([a, b, c, 0]))
+            }
+            IpAddr::V6(ip) => {
+                let mut segments = ip.segments();
+                segments[3..].fill(0);
+                Self::Subnet(IpAddr::from(segments))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct JoinQuotas {
+    ip_quota: JoinQuota,
+    subnet_quota: JoinQuota,
+    buckets: Arc<Mutex<HashMap<Source, Bucket>>>,
+}
+
+impl JoinQuotas {
+    pub fn new(ip_quota: JoinQuota, subnet_quota: JoinQuota) -> Self {
+        Self {
+            ip_quota,
+            subnet_quota,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Consumes a join from the quotas of the given source IP, or returns the time after
+    /// which the source may try again if those are exhausted.
+    pub fn try_acquire(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            buckets.retain(|source, bucket| {
+                let quota = self.quota(source);
+                bucket.refill(quota, now);
+                !bucket.is_full(quota)
+            });
+        }
+
+        let sources = [Source::Ip(ip), Source::subnet(ip)];
+        let mut retry_after = Duration::ZERO;
+        for source in &sources {
+            let quota = self.quota(source);
+            let bucket = buckets.entry(*source).or_insert(Bucket {
+                tokens: quota.burst as f64,
+                last_refill: now,
+            });
+            bucket.refill(quota, now);
+            if bucket.tokens < 1.0 {
+                retry_after = retry_after.max(bucket.retry_after(quota));
+            }
+        }
+        if !retry_after.is_zero() {
+            return Err(retry_after);
+        }
+        for source in &sources {
+            if let Some(bucket) = buckets.get_mut(source) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    fn quota(&self, source: &Source) -> &JoinQuota {
+        match source {
+            Source::Ip(_) => &self.ip_quota,
+            Source::Subnet(_) => &self.subnet_quota,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_joins_per_ip_and_subnet() {
+        let quotas = JoinQuotas::new(
+            JoinQuota::new(2, Duration::from_secs(10)),
+            JoinQuota::new(3, Duration::from_secs(5)),
+        );
+        let now = Instant::now();
+        let ip = |last: u8| IpAddr::from([10, 0, 0, last]);
+
+        // the burst of a single IP is exhausted first
+        assert!(quotas.try_acquire(ip(1), now).is_ok());
+        assert!(quotas.try_acquire(ip(1), now).is_ok());
+        assert_eq!(quotas.try_acquire(ip(1), now), Err(Duration::from_secs(10)));
+
+        // other IPs in the subnet share the subnet burst
+        assert!(quotas.try_acquire(ip(2), now).is_ok());
+        assert_eq!(quotas.try_acquire(ip(3), now), Err(Duration::from_secs(5)));
+
+        // other subnets are not affected
+        assert!(quotas.try_acquire(IpAddr::from([10, 0, 1, 1]), now).is_ok());
+
+        // quotas recover over time
+        let later = now + Duration::from_secs(5);
+        assert!(quotas.try_acquire(ip(3), later).is_ok());
+        assert!(quotas.try_acquire(ip(1), later).is_err());
+        assert!(quotas
+            .try_acquire(ip(1), now + Duration::from_secs(15))
+            .is_ok());
+    }
+}
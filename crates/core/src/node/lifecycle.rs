@@ -0,0 +1,83 @@
+//! Lifecycle events of a running node, for applications embedding it.
+//!
+//! Events are broadcast to every subscriber as they happen. Subscribers which fall behind
+//! miss the oldest events rather than slowing the node down.
+
+use std::time::Duration;
+
+use freenet_stdlib::prelude::ContractKey;
+use tokio::sync::broadcast;
+
+use super::PeerId;
+use crate::{message::Transaction, ring::Location};
+
+/// Max number of events kept for subscribers which have not received them yet.
+const CAPACITY: usize = 256;
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum NodeLifecycleEvent {
+    /// Connected to one of the gateways this node joins the network through.
+    ConnectedToGateway { gateway: PeerId, location: Location },
+    /// A connection to a peer was established.
+    PeerJoined { peer: PeerId, location: Location },
+    /// A contract started being cached by this node.
+    ContractCached { key: ContractKey },
+    /// The state of a contract was evicted for going over the storage quota.
+    ContractEvicted { key: ContractKey, size: u64 },
+    /// A contract hosted by this node emitted an event while executing, which is not
+    /// reflected in its state.
+    ContractEvent { key: ContractKey, payload: Vec<u8> },
+    /// An operation finished with an error.
+    OperationFailed {
+        transaction: Option<Transaction>,
+        error: String,
+    },
+    /// All connections were lost for a while, the node is joining the network again.
+    Disconnected,
+    /// Connected to the network again after having been disconnected for the given time.
+    Reconnected { offline_for: Duration },
+}
+
+pub(crate) struct LifecycleEvents(broadcast::Sender<NodeLifecycleEvent>);
+
+impl LifecycleEvents {
+    pub fn new() -> Self {
+        Self(broadcast::Sender::new(CAPACITY))
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeLifecycleEvent> {
+        self.0.subscribe()
+    }
+
+    pub fn emit(&self, event: NodeLifecycleEvent) {
+        // no one may be subscribed, which is fine
+        let _ = self.0.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn subscribers_receive_events_emitted_after_subscribing() {
+        let events = LifecycleEvents::new();
+        events.emit(NodeLifecycleEvent::ContractCached {
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+        });
+
+        let mut subscriber = events.subscribe();
+        events.emit(NodeLifecycleEvent::OperationFailed {
+            transaction: None,
+            error: "timed out".into(),
+        });
+        assert!(matches!(
+            subscriber.recv().await.unwrap(),
+            NodeLifecycleEvent::OperationFailed { .. }
+        ));
+        assert!(subscriber.try_recv().is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Node running offline, without connecting to any peer.
+//!
+//! Contracts and delegates are run by the executor of the node, and their states kept in its
+//! local storage, so applications can be developed and tested without a network. The network
+//! connection manager is never started nor the network joined: every client request is served
+//! locally, e.g. subscribers are only notified of the updates made through this node.
+
+use freenet_stdlib::client_api::{ClientRequest, ErrorKind};
+
+use crate::{
+    client_events::{
+        combinator::ClientEventsCombinator, BoxedClient, ClientEventsProxy, OpenRequest,
+    },
+    contract::ExecutorError,
+    local_node::Executor,
+};
+
+/// A node serving the client API from its local storage and contract executor alone.
+///
+/// Built with [`NodeConfig::build_local`](super::NodeConfig::build_local).
+pub struct LocalNode {
+    executor: Executor,
+    clients: BoxedClient,
+}
+
+impl LocalNode {
+    pub(super) fn new<const CLIENTS: usize>(
+        executor: Executor,
+        clients: [BoxedClient; CLIENTS],
+    ) -> Self {
+        Self {
+            executor,
+            clients: Box::new(ClientEventsCombinator::new(clients)),
+        }
+    }
+
+    /// Serves the requests of the clients until they shut down.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        tracing::info!("Starting node in local mode");
+        loop {
+            let OpenRequest {
+                client_id,
+                request,
+                notification_channel,
+                attested_contract,
+                ..
+            } = match self.clients.recv().await {
+                Ok(request) => request,
+                Err(error) if matches!(error.kind(), ErrorKind::Shutdown) => {
+                    tracing::info!("Clients shut down, stopping local node");
+                    return Ok(());
+                }
+                // the clients which shut down are not listened to anymore
+                Err(error) => {
+                    tracing::debug!(%error, "client error");
+                    continue;
+                }
+            };
+            tracing::trace!(cli_id = %client_id, "got request -> {request}");
+
+            let res = match *request {
+                ClientRequest::ContractOp(op) => {
+                    self.executor
+                        .contract_requests(op, client_id, notification_channel)
+                        .await
+                }
+                ClientRequest::DelegateOp(op) => self
+                    .executor
+                    .delegate_request(op, attested_contract.as_ref()),
+                ClientRequest::Disconnect { cause } => {
+                    if let Some(cause) = cause {
+                        tracing::info!(cli_id = %client_id, "disconnecting cause: {cause}");
+                    }
+                    continue;
+                }
+                _ => Err(ExecutorError::other(anyhow::anyhow!(
+                    "not supported in local mode"
+                ))),
+            };
+            let res = match res {
+                Ok(res) => Ok(res),
+                Err(err) if err.is_request() => {
+                    Err(ErrorKind::RequestError(err.unwrap_request()).into())
+                }
+                Err(err) => {
+                    tracing::error!("{err}");
+                    Err(ErrorKind::Unhandled {
+                        cause: format!("{err}").into(),
+                    }
+                    .into())
+                }
+            };
+            self.clients.send(client_id, res).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use freenet_stdlib::client_api::{ClientError, HostResponse};
+    use futures::{future::BoxFuture, FutureExt};
+
+    use super::*;
+    use crate::client_events::ClientId;
+
+    /// Client whose connection to the node is closed.
+    struct ClosedClient;
+
+    impl ClientEventsProxy for ClosedClient {
+        fn recv(&mut self) -> BoxFuture<'_, Result<OpenRequest<'static>, ClientError>> {
+            async { Err(ErrorKind::ChannelClosed.into()) }.boxed()
+        }
+
+        fn send(
+            &mut self,
+            _id: ClientId,
+            _response: Result<HostResponse, ClientError>,
+        ) -> BoxFuture<'_, Result<(), ClientError>> {
+            async { Ok(()) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn closed_clients_stop_the_node() -> anyhow::Result<()> {
+        let config = crate::test_utils::node_config("closed_clients_stop_the_node").await?;
+        let clients: [BoxedClient; 2] = [Box::new(ClosedClient), Box::new(ClosedClient)];
+        let node = config.build_local(clients).await?;
+        tokio::time::timeout(Duration::from_secs(10), node.run()).await??;
+        Ok(())
+    }
+}
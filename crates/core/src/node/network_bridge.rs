@@ -10,9 +10,17 @@ use tokio::sync::mpsc::{self, Receiver, Sender};
 use super::PeerId;
 use crate::message::{NetMessage, NodeEvent};
 
+mod address_watch;
+pub(crate) mod authentication;
+pub(crate) mod compression;
 mod handshake;
+pub(crate) mod hello;
 pub(crate) mod in_memory;
+mod nat_traversal;
 pub(crate) mod p2p_protoc;
+pub(crate) mod policing;
+mod priority;
+pub(crate) mod readdress;
 
 pub(crate) type ConnResult<T> = std::result::Result<T, ConnectionError>;
 
@@ -0,0 +1,233 @@
+//! Detection of changes in the address of this peer.
+//!
+//! Home connections get a new address every now and then, and the peers connected to this one
+//! keep sending to the previous one until the connections time out. The address of the
+//! interface the routes of the host pick is checked periodically, and whenever it changes, as
+//! well as every once in a while regardless since the address the router is reached at can
+//! change on its own, or a peer connecting reports seeing this peer at another address, the
+//! neighbours are asked which address they see this peer at. Once enough of them agree on an
+//! address other than the one this peer is known by, the neighbours are sent a re-address
+//! notice. Neighbours which don't answer say nothing about the address, peers running older
+//! versions ignore the probes. If none of the neighbours which answered earlier probes answers
+//! after the local address changed, the connections were lost along with the previous address,
+//! and the network is joined again through the gateways.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// Time between checks of the address of the local interface.
+pub(super) const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Time after which the neighbours are probed even if the local address did not change.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Time the neighbours are given to answer a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Neighbours, on distinct hosts, which must agree on a new address for this peer to take it.
+const QUORUM: usize = 2;
+
+#[derive(Debug)]
+struct Probe {
+    sent_at: Instant,
+    /// Whether the probe was sent because the local address changed.
+    after_change: bool,
+    /// Neighbours the probe was sent to, by the address this peer knows them by.
+    probed: HashSet<SocketAddr>,
+    /// Address each neighbour which answered sees this peer at.
+    observed: HashMap<SocketAddr, SocketAddr>,
+}
+
+/// What to do after checking the address of this peer.
+#[derive(Debug, PartialEq)]
+pub(super) enum Check {
+    Idle,
+    /// Ask the neighbours which address they see this peer at.
+    Probe,
+    /// No neighbour answered the probe sent after the local address changed, so the
+    /// connections were lost.
+    Rejoin,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct AddressWatch {
+    /// Address of the interface the routes of the host picked last time.
+    local: Option<IpAddr>,
+    /// Whether the local address changed since the last probe.
+    changed: bool,
+    last_probe: Option<Instant>,
+    probe: Option<Probe>,
+    /// Neighbours which answered a probe, and so are expected to answer the next ones.
+    responsive: HashSet<SocketAddr>,
+}
+
+impl AddressWatch {
+    /// Checks the current address of the local interface against the previous one.
+    pub fn check(&mut self, local: Option<IpAddr>, now: Instant) -> Check {
+        if let Some(probe) = &self.probe {
+            if now.saturating_duration_since(probe.sent_at) < PROBE_TIMEOUT {
+                return Check::Idle;
+            }
+            let lost = probe.after_change
+                && probe.observed.is_empty()
+                && probe
+                    .probed
+                    .iter()
+                    .any(|neighbour| self.responsive.contains(neighbour));
+            self.probe = None;
+            if lost {
+                return Check::Rejoin;
+            }
+        }
+        let changed =
+            matches!((self.local, local), (Some(previous), Some(current)) if previous != current);
+        if changed {
+            tracing::info!(previous = ?self.local, ?local, "Local address changed");
+            self.changed = true;
+        }
+        // the interface may be down for a while in the middle of a change
+        if local.is_some() {
+            self.local = local;
+        }
+        let due = self.last_probe.map_or(true, |last| {
+            now.saturating_duration_since(last) >= PROBE_INTERVAL
+        });
+        if self.changed || due {
+            Check::Probe
+        } else {
+            Check::Idle
+        }
+    }
+
+    /// A peer reported seeing this peer at an address other than the one it is known by, the
+    /// neighbours are probed on the next check to find out whether it actually moved.
+    pub fn reported(&mut self) {
+        self.last_probe = None;
+    }
+
+    /// Records that the neighbours known by the given addresses were sent a probe.
+    pub fn probing(&mut self, neighbours: impl IntoIterator<Item = SocketAddr>, now: Instant) {
+        self.last_probe = Some(now);
+        let probed: HashSet<_> = neighbours.into_iter().collect();
+        self.responsive
+            .retain(|neighbour| probed.contains(neighbour));
+        self.probe = Some(Probe {
+            sent_at: now,
+            after_change: std::mem::take(&mut self.changed),
+            probed,
+            observed: HashMap::new(),
+        });
+    }
+
+    /// Records the address a probed neighbour sees this peer at, returning the new address of
+    /// this peer once enough neighbours agree on one other than the one it is known by.
+    pub fn observed(
+        &mut self,
+        neighbour: SocketAddr,
+        observed: SocketAddr,
+        own: SocketAddr,
+    ) -> Option<SocketAddr> {
+        let probe = self.probe.as_mut()?;
+        if !probe.probed.contains(&neighbour) {
+            return None;
+        }
+        probe.observed.insert(neighbour, observed);
+        self.responsive.insert(neighbour);
+        if observed == own {
+            return None;
+        }
+        // several connections with a single host count once
+        let agreeing = probe
+            .observed
+            .iter()
+            .filter(|(_, addr)| **addr == observed)
+            .map(|(neighbour, _)| neighbour.ip())
+            .collect::<HashSet<_>>()
+            .len();
+        if agreeing < QUORUM {
+            return None;
+        }
+        self.probe = None;
+        Some(observed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agreeing_neighbours_reveal_new_address() {
+        let mut watch = AddressWatch::default();
+        let now = Instant::now();
+        let local = Some("192.168.1.10".parse().unwrap());
+        let own: SocketAddr = "203.0.113.1:31337".parse().unwrap();
+        let moved: SocketAddr = "198.51.100.7:31337".parse().unwrap();
+        let neighbours: Vec<SocketAddr> = ["203.0.113.20:1000", "203.0.113.21:1000"]
+            .into_iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        // periodic probes may go unanswered, peers running older versions ignore them
+        assert_eq!(watch.check(local, now), Check::Probe);
+        watch.probing(neighbours.clone(), now);
+        assert_eq!(watch.check(local, now + PROBE_TIMEOUT), Check::Idle);
+        let now = now + PROBE_INTERVAL;
+        assert_eq!(watch.check(local, now), Check::Probe);
+        watch.probing(neighbours.clone(), now);
+        assert_eq!(watch.check(local, now), Check::Idle);
+
+        // a single neighbour is not trusted, nor peers which were not probed
+        assert_eq!(watch.observed(neighbours[0], moved, own), None);
+        assert_eq!(watch.observed(own, moved, own), None);
+        assert_eq!(watch.observed(neighbours[1], moved, own), Some(moved));
+
+        // once a peer reports another address, or the local address changes, the neighbours
+        // are probed again right away
+        let later = now + CHECK_INTERVAL;
+        assert_eq!(watch.check(local, later), Check::Idle);
+        watch.reported();
+        assert_eq!(watch.check(local, later), Check::Probe);
+        watch.probing(neighbours.clone(), later);
+        let later = later + PROBE_TIMEOUT;
+        assert_eq!(watch.check(local, later), Check::Idle);
+        let changed = Some("10.0.0.4".parse().unwrap());
+        assert_eq!(watch.check(changed, later), Check::Probe);
+        watch.probing(neighbours, later);
+
+        // nobody answering means the connections were lost with the previous address
+        assert_eq!(watch.check(changed, later + PROBE_TIMEOUT), Check::Rejoin);
+        assert_eq!(watch.check(changed, later + PROBE_TIMEOUT), Check::Idle);
+    }
+
+    #[test]
+    fn too_few_neighbours_are_not_trusted() {
+        let mut watch = AddressWatch::default();
+        let now = Instant::now();
+        let local = Some("192.168.1.10".parse().unwrap());
+        let own: SocketAddr = "203.0.113.1:31337".parse().unwrap();
+        let moved: SocketAddr = "198.51.100.7:31337".parse().unwrap();
+        let neighbour: SocketAddr = "203.0.113.20:1000".parse().unwrap();
+        let same_host: SocketAddr = "203.0.113.20:2000".parse().unwrap();
+
+        // a lone neighbour can't move this peer, nor a host with several connections
+        assert_eq!(watch.check(local, now), Check::Probe);
+        watch.probing([neighbour], now);
+        assert_eq!(watch.observed(neighbour, moved, own), None);
+        let now = now + PROBE_INTERVAL;
+        assert_eq!(watch.check(local, now), Check::Probe);
+        watch.probing([neighbour, same_host], now);
+        assert_eq!(watch.observed(neighbour, moved, own), None);
+        assert_eq!(watch.observed(same_host, moved, own), None);
+
+        // neighbours which never answered don't mean the connections were lost
+        let mut watch = AddressWatch::default();
+        assert_eq!(watch.check(local, now), Check::Probe);
+        watch.probing([neighbour], now);
+        let changed = Some("10.0.0.4".parse().unwrap());
+        let later = now + PROBE_TIMEOUT;
+        assert_eq!(watch.check(changed, later), Check::Probe);
+        watch.probing([neighbour], later);
+        assert_eq!(watch.check(changed, later + PROBE_TIMEOUT), Check::Idle);
+    }
+}
@@ -0,0 +1,410 @@
+//! Authentication of the operation messages exchanged between peers.
+//!
+//! The transport already authenticates the peer at the other end of each connection, but
+//! messages relayed through a gateway pass through a peer which could alter or forge them, and
+//! any peer on the path could send a message it captured again. Operation messages are sealed
+//! by their sender in an envelope signed with its transport key, naming the receiver and
+//! carrying a sequence number. Receivers verify the signature, that they are the intended
+//! receiver and that the sequence number was not seen before.
+//!
+//! Sequence numbers follow the clock of the sender, in microseconds since the unix epoch, so
+//! they keep increasing across restarts and envelopes older than `MAX_ENVELOPE_AGE` can be
+//! refused without remembering every sequence number ever seen. The window is widened by the
+//! estimated skew of the clock of the neighbour which sent the envelope, up to
+//! `MAX_CLOCK_TOLERANCE`.
+//!
+//! The signer of an envelope must be the peer named as the sender inside the message, and for
+//! messages which were not relayed, the peer at the other end of the connection.
+//!
+//! Peers which predate sealed messages can't open envelopes. Until every peer seals, messages
+//! to peers which did not greet saying they support them are sent as is, and unsealed messages
+//! are accepted from a peer until it sends a sealed one.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{MessageStats, NetMessage, NetMessageV1, Transaction},
+    node::PeerId,
+    operations::{
+        get::GetMsg, probe::ProbeMsg, put::PutMsg, subscribe::SubscribeMsg, update::UpdateMsg,
+    },
+    transport::{Overloaded, SignatureVerifier, TransportKeypair, TransportPublicKey},
+};
+
+/// Max difference between the sequence number of an envelope and the clock of the receiver
+/// for the envelope to be accepted, either way to allow for some clock skew.
+const MAX_ENVELOPE_AGE: Duration = Duration::from_secs(60);
+
+/// Max skew of the clock of a neighbour the window of accepted envelopes is widened by.
+const MAX_CLOCK_TOLERANCE: Duration = Duration::from_secs(30);
+
+/// Once tracking the sequence numbers of this many senders, the ones which did not send
+/// anything recently are forgotten.
+const MAX_TRACKED_SENDERS: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum AuthenticationError {
+    #[error("operation message without an authenticated envelope")]
+    Missing,
+    #[error("invalid signature in envelope from {0}")]
+    InvalidSignature(TransportPublicKey),
+    #[error("envelope from {0} meant for another peer")]
+    Misdirected(TransportPublicKey),
+    #[error("envelope from {0} expired")]
+    Expired(TransportPublicKey),
+    #[error("envelope from {0} replayed")]
+    Replayed(TransportPublicKey),
+    #[error("envelope from {0} sealing a message on behalf of another peer")]
+    Impersonation(TransportPublicKey),
+    #[error("envelope from {0} not verified: {1}")]
+    Overloaded(TransportPublicKey, Overloaded),
+}
+
+/// Whether the message belongs to an operation, and so must be sealed by its sender.
+///
+/// Connect messages are left out, since they are also exchanged with joiners during the
+/// handshake, before the envelope can be checked.
+pub(crate) fn requires_authentication(msg: &NetMessage) -> bool {
+    matches!(
+        msg,
+        NetMessage::V1(
+            NetMessageV1::Put(_)
+                | NetMessageV1::Get(_)
+                | NetMessageV1::Subscribe(_)
+                | NetMessageV1::Update(_)
+                | NetMessageV1::Probe(_)
+                | NetMessageV1::StateChunk(_)
+                | NetMessageV1::GetBatch { .. }
+        )
+    )
+}
+
+/// Peer the message names as its sender, which must be the peer sending it.
+///
+/// Only the messages which always name the peer sending them are covered, others name the
+/// peer the operation started at.
+pub(crate) fn embedded_sender(msg: &NetMessage) -> Option<&PeerId> {
+    let sender = match msg {
+        NetMessage::V1(NetMessageV1::Put(
+            PutMsg::SeekNode { sender, .. } | PutMsg::BroadcastTo { sender, .. },
+        ))
+        | NetMessage::V1(NetMessageV1::Get(GetMsg::SeekNode { sender, .. }))
+        | NetMessage::V1(NetMessageV1::Subscribe(SubscribeMsg::ReturnSub { sender, .. }))
+        | NetMessage::V1(NetMessageV1::Update(
+            UpdateMsg::SeekNode { sender, .. }
+            | UpdateMsg::BroadcastTo { sender, .. }
+            | UpdateMsg::BroadcastSummary { sender, .. },
+        ))
+        | NetMessage::V1(NetMessageV1::Probe(
+            ProbeMsg::SeekNode { sender, .. } | ProbeMsg::ReturnProbe { sender, .. },
+        )) => sender,
+        _ => return None,
+    };
+    Some(&sender.peer)
+}
+
+/// The message in the envelope, for peers which can't open envelopes.
+pub(crate) fn unsealed(msg: NetMessage) -> NetMessage {
+    match msg {
+        NetMessage::V1(NetMessageV1::Authenticated(envelope)) => {
+            bincode::deserialize(&envelope.payload).expect("sealed by this node")
+        }
+        msg => msg,
+    }
+}
+
+/// Message sealed by its sender for a given receiver.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SignedEnvelope {
+    /// Transaction of the sealed message, not covered by the signature.
+    pub transaction: Transaction,
+    pub from: TransportPublicKey,
+    to: TransportPublicKey,
+    sequence: u64,
+    /// The serialized message.
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl SignedEnvelope {
+    fn signed_bytes(
+        from: &TransportPublicKey,
+        to: &TransportPublicKey,
+        sequence: u64,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        bincode::serialize(&(from, to, sequence, payload)).expect("serializable")
+    }
+}
+
+/// Shared handle sealing the messages sent by this node and opening the ones it receives.
+#[derive(Clone)]
+pub(crate) struct MessageAuthenticator {
+    keypair: TransportKeypair,
+    last_sequence: Arc<AtomicU64>,
+    /// Sequence numbers seen from each sender which are recent enough to be accepted.
+    seen: Arc<Mutex<HashMap<TransportPublicKey, BTreeSet<u64>>>>,
+}
+
+impl MessageAuthenticator {
+    pub fn new(keypair: TransportKeypair) -> Self {
+        Self {
+            keypair,
+            last_sequence: Arc::default(),
+            seen: Arc::default(),
+        }
+    }
+
+    /// Seals the message for the receiver.
+    pub fn seal(&self, to: &TransportPublicKey, msg: NetMessage, now: Duration) -> NetMessage {
+        let now = now.as_micros() as u64;
+        let previous = self
+            .last_sequence
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+                Some((last + 1).max(now))
+            })
+            .expect("always updated");
+        let sequence = (previous + 1).max(now);
+        let from = self.keypair.public().clone();
+        let payload = bincode::serialize(&msg).expect("serializable");
+        let signature = self
+            .keypair
+            .sign(&SignedEnvelope::signed_bytes(&from, to, sequence, &payload));
+        NetMessage::V1(NetMessageV1::Authenticated(Box::new(SignedEnvelope {
+            transaction: *msg.id(),
+            from,
+            to: to.clone(),
+            sequence,
+            payload,
+            signature,
+        })))
+    }
+
+    /// Verifies the envelope was sealed by its sender for this node and was not received
+    /// before, returning the message in it.
+    pub async fn open(
+        &self,
+        envelope: SignedEnvelope,
+        verifier: &SignatureVerifier,
+        now: Duration,
+        tolerance: Duration,
+    ) -> Result<NetMessage, AuthenticationError> {
+        let SignedEnvelope {
+            from,
+            to,
+            sequence,
+            payload,
+            signature,
+            ..
+        } = envelope;
+        if &to != self.keypair.public() {
+            return Err(AuthenticationError::Misdirected(from));
+        }
+        let now = now.as_micros() as u64;
+        let max_age = (MAX_ENVELOPE_AGE + tolerance.min(MAX_CLOCK_TOLERANCE)).as_micros() as u64;
+        if sequence < now.saturating_sub(max_age) || sequence > now.saturating_add(max_age) {
+            return Err(AuthenticationError::Expired(from));
+        }
+        // sequence numbers are only forgotten past the widest window, so an envelope accepted
+        // thanks to the skew of a neighbour can't be replayed once the window shrinks
+        let oldest =
+            now.saturating_sub((MAX_ENVELOPE_AGE + MAX_CLOCK_TOLERANCE).as_micros() as u64);
+        if self.was_seen(&from, sequence) {
+            return Err(AuthenticationError::Replayed(from));
+        }
+        let signed = SignedEnvelope::signed_bytes(&from, &to, sequence, &payload);
+        match verifier.verify(&from, signed, signature).await {
+            Ok(true) => {}
+            Ok(false) => return Err(AuthenticationError::InvalidSignature(from)),
+            Err(overloaded) => return Err(AuthenticationError::Overloaded(from, overloaded)),
+        }
+        let msg: NetMessage = match bincode::deserialize(&payload) {
+            Ok(msg) if requires_authentication(&msg) => msg,
+            // signed by the sender but not something it should have sealed
+            _ => return Err(AuthenticationError::InvalidSignature(from)),
+        };
+        if embedded_sender(&msg).is_some_and(|sender| sender.pub_key != from) {
+            return Err(AuthenticationError::Impersonation(from));
+        }
+
+        // checked again, the same envelope may have been verified concurrently
+        let mut seen = self.seen.lock();
+        if seen.len() >= MAX_TRACKED_SENDERS {
+            seen.retain(|_, sequences| sequences.last().is_some_and(|last| *last >= oldest));
+        }
+        let sequences = seen.entry(from.clone()).or_default();
+        *sequences = sequences.split_off(&oldest);
+        if !sequences.insert(sequence) {
+            return Err(AuthenticationError::Replayed(from));
+        }
+        Ok(msg)
+    }
+
+    fn was_seen(&self, from: &TransportPublicKey, sequence: u64) -> bool {
+        self.seen
+            .lock()
+            .get(from)
+            .is_some_and(|sequences| sequences.contains(&sequence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring::{Location, PeerKeyLocation};
+
+    #[tokio::test]
+    async fn open_sealed_messages_once() {
+        let verifier = SignatureVerifier::new(1);
+        let sender = MessageAuthenticator::new(TransportKeypair::new());
+        let receiver_keypair = TransportKeypair::new();
+        let receiver = MessageAuthenticator::new(receiver_keypair.clone());
+        let now = Duration::from_secs(1_700_000_000);
+        let msg = || {
+            NetMessage::from(ProbeMsg::RequestProbe {
+                id: Transaction::new::<ProbeMsg>(),
+                destination: Location::random(),
+            })
+        };
+        let envelope = |msg: NetMessage| match msg {
+            NetMessage::V1(NetMessageV1::Authenticated(envelope)) => *envelope,
+            other => panic!("not sealed: {other}"),
+        };
+
+        let sealed = envelope(sender.seal(receiver_keypair.public(), msg(), now));
+        assert!(receiver
+            .open(
+                sealed.clone(),
+                &verifier,
+                now + Duration::from_secs(1),
+                Duration::ZERO
+            )
+            .await
+            .is_ok());
+        assert!(matches!(
+            receiver
+                .open(sealed.clone(), &verifier, now, Duration::ZERO)
+                .await,
+            Err(AuthenticationError::Replayed(_))
+        ));
+
+        let sealed = envelope(sender.seal(receiver_keypair.public(), msg(), now));
+        assert!(matches!(
+            receiver
+                .open(
+                    sealed.clone(),
+                    &verifier,
+                    now + MAX_ENVELOPE_AGE * 2,
+                    Duration::ZERO
+                )
+                .await,
+            Err(AuthenticationError::Expired(_))
+        ));
+
+        let mut tampered = sealed.clone();
+        tampered.sequence += 1;
+        assert!(matches!(
+            receiver
+                .open(tampered, &verifier, now, Duration::ZERO)
+                .await,
+            Err(AuthenticationError::InvalidSignature(_))
+        ));
+
+        // envelopes sealed for some other peer can't be replayed to this one
+        let other = envelope(sender.seal(TransportKeypair::new().public(), msg(), now));
+        assert!(matches!(
+            receiver.open(other, &verifier, now, Duration::ZERO).await,
+            Err(AuthenticationError::Misdirected(_))
+        ));
+
+        assert!(receiver
+            .open(sealed, &verifier, now, Duration::ZERO)
+            .await
+            .is_ok());
+
+        // the clock of the sender is behind, by more than the max age but within the estimated
+        // skew of its clock
+        let lagging = MessageAuthenticator::new(TransportKeypair::new());
+        let behind = now - MAX_ENVELOPE_AGE - Duration::from_secs(10);
+        let sealed = envelope(lagging.seal(receiver_keypair.public(), msg(), behind));
+        assert!(matches!(
+            receiver
+                .open(sealed.clone(), &verifier, now, Duration::ZERO)
+                .await,
+            Err(AuthenticationError::Expired(_))
+        ));
+        let skew = Duration::from_secs(15);
+        assert!(receiver
+            .open(sealed.clone(), &verifier, now, skew)
+            .await
+            .is_ok());
+        // and is still remembered once the window shrinks back
+        assert!(matches!(
+            receiver
+                .open(sealed, &verifier, now + Duration::from_secs(1), skew)
+                .await,
+            Err(AuthenticationError::Replayed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn signer_is_the_embedded_sender() {
+        let verifier = SignatureVerifier::new(1);
+        let sender_keypair = TransportKeypair::new();
+        let sender = MessageAuthenticator::new(sender_keypair.clone());
+        let receiver_keypair = TransportKeypair::new();
+        let receiver = MessageAuthenticator::new(receiver_keypair.clone());
+        let now = Duration::from_secs(1_700_000_000);
+        let return_probe = |sender: PeerId| {
+            NetMessage::from(ProbeMsg::ReturnProbe {
+                id: Transaction::new::<ProbeMsg>(),
+                target: PeerKeyLocation {
+                    peer: PeerId::new(
+                        ([127, 0, 0, 1], 2).into(),
+                        receiver_keypair.public().clone(),
+                    ),
+                    location: None,
+                },
+                sender: PeerKeyLocation {
+                    peer: sender,
+                    location: None,
+                },
+                visits: vec![],
+                round_trip: Duration::ZERO,
+            })
+        };
+        let own = PeerId::new(([127, 0, 0, 1], 1).into(), sender_keypair.public().clone());
+        let open = |msg: NetMessage| match msg {
+            NetMessage::V1(NetMessageV1::Authenticated(envelope)) => {
+                receiver.open(*envelope, &verifier, now, Duration::ZERO)
+            }
+            other => panic!("not sealed: {other}"),
+        };
+
+        let msg = return_probe(own.clone());
+        assert_eq!(embedded_sender(&msg), Some(&own));
+        let sealed = sender.seal(receiver_keypair.public(), msg, now);
+        assert!(open(sealed.clone()).await.is_ok());
+        assert!(matches!(
+            unsealed(sealed),
+            NetMessage::V1(NetMessageV1::Probe(ProbeMsg::ReturnProbe { .. }))
+        ));
+
+        let msg = return_probe(PeerId::random());
+        let sealed = sender.seal(receiver_keypair.public(), msg, now);
+        assert!(matches!(
+            open(sealed).await,
+            Err(AuthenticationError::Impersonation(_))
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! Transparent compression of the messages exchanged with other peers.
+//!
+//! Once the peer at the other end of a connection greets saying it supports compression, each
+//! end offers the codecs it can decompress, and the other end compresses the messages it sends
+//! with the first one it supports as well. Messages below the size threshold are sent as they
+//! are, since compressing them saves little and costs latency, and so are the ones which would
+//! not get any smaller. Peers which never send an offer keep receiving every message
+//! uncompressed.
+//!
+//! Codecs travel by their numeric id, so an offer from a newer peer listing codecs this peer
+//! does not know still decodes; those are skipped, and when no offered codec is known the
+//! messages are sent uncompressed.
+
+use std::io::{self, Read};
+
+use serde::{Deserialize, Serialize};
+
+use super::ConnectionError;
+use crate::{
+    message::{MessageStats, NetMessage, NetMessageV1, Transaction},
+    operations::connect::ConnectMsg,
+};
+
+/// Id of a compression codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Codec(u32);
+
+impl Codec {
+    pub const ZSTD: Self = Self(0);
+    pub const LZ4: Self = Self(1);
+}
+
+/// Codecs this peer can decompress, from the most to the least preferred.
+const SUPPORTED: &[Codec] = &[Codec::ZSTD, Codec::LZ4];
+const ZSTD_LEVEL: i32 = 3;
+/// Max size of a decompressed message, so a small payload cannot exhaust the memory.
+const MAX_DECOMPRESSED_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Compression state of a single peer connection.
+#[derive(Debug)]
+pub(super) struct ConnectionCompression {
+    /// Serialized size below which messages are not compressed.
+    threshold: usize,
+    offered: bool,
+    /// Codec to compress the messages with, once the peer offered one supported by this peer.
+    codec: Option<Codec>,
+}
+
+impl ConnectionCompression {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            offered: false,
+            codec: None,
+        }
+    }
+
+    /// Offer of the codecs this peer can decompress, unless already sent over the connection.
+    pub fn offer(&mut self) -> Option<NetMessage> {
+        if std::mem::replace(&mut self.offered, true) {
+            return None;
+        }
+        Some(NetMessage::V1(NetMessageV1::CompressionOffer {
+            transaction: Transaction::new::<ConnectMsg>(),
+            codecs: SUPPORTED.to_vec(),
+        }))
+    }
+
+    /// The peer offered the codecs it can decompress.
+    pub fn accept_offer(&mut self, codecs: &[Codec]) {
+        self.codec = codecs
+            .iter()
+            .copied()
+            .find(|codec| SUPPORTED.contains(codec));
+    }
+
+    /// Compresses the message if the peer accepts compressed messages and it is large enough.
+    pub async fn compress(&self, msg: NetMessage) -> NetMessage {
+        let Some(codec) = self.codec else {
+            return msg;
+        };
+        let Ok(size) = bincode::serialized_size(&msg) else {
+            return msg;
+        };
+        if size < self.threshold as u64 {
+            return msg;
+        }
+        let Ok(serialized) = bincode::serialize(&msg) else {
+            return msg;
+        };
+        match tokio::task::spawn_blocking(move || compress(codec, &serialized)).await {
+            Ok(Ok(payload)) if (payload.len() as u64) < size => {
+                NetMessage::V1(NetMessageV1::Compressed {
+                    transaction: *msg.id(),
+                    codec,
+                    payload,
+                })
+            }
+            Ok(Ok(_)) => msg,
+            Ok(Err(error)) => {
+                tracing::debug!(%error, "Failed compressing message");
+                msg
+            }
+            Err(error) => {
+                tracing::warn!(%error, "Compression task failed");
+                msg
+            }
+        }
+    }
+}
+
+fn compress(codec: Codec, serialized: &[u8]) -> io::Result<Vec<u8>> {
+    match codec {
+        Codec::ZSTD => zstd::encode_all(serialized, ZSTD_LEVEL),
+        Codec::LZ4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            io::Write::write_all(&mut encoder, serialized)?;
+            encoder.finish().map_err(io::Error::other)
+        }
+        _ => Err(unsupported(codec)),
+    }
+}
+
+fn unsupported(codec: Codec) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("unsupported codec {}", codec.0),
+    )
+}
+
+/// Decompresses a message received compressed with the codec.
+pub(super) async fn decompress(
+    codec: Codec,
+    payload: Vec<u8>,
+) -> Result<NetMessage, ConnectionError> {
+    tokio::task::spawn_blocking(move || {
+        let decoder: Box<dyn Read> = match codec {
+            Codec::ZSTD => Box::new(zstd::Decoder::new(payload.as_slice())?),
+            Codec::LZ4 => Box::new(lz4_flex::frame::FrameDecoder::new(payload.as_slice())),
+            _ => return Err(unsupported(codec).into()),
+        };
+        let msg: NetMessage = bincode::deserialize_from(decoder.take(MAX_DECOMPRESSED_SIZE))
+            .map_err(|err| ConnectionError::Serialization(Some(err)))?;
+        if let NetMessage::V1(NetMessageV1::Compressed { .. }) = msg {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "nested compressed message").into(),
+            );
+        }
+        Ok(msg)
+    })
+    .await
+    .map_err(|err| ConnectionError::IOError(format!("decompression task failed: {err}")))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(size: usize) -> NetMessage {
+        NetMessage::V1(NetMessageV1::CompressionOffer {
+            transaction: Transaction::new::<ConnectMsg>(),
+            codecs: vec![Codec::ZSTD; size],
+        })
+    }
+
+    #[tokio::test]
+    async fn compress_above_threshold_once_offered() {
+        let mut compression = ConnectionCompression::new(1024);
+        assert!(compression.offer().is_some());
+        assert!(compression.offer().is_none());
+
+        // nothing is compressed until the peer offers a codec
+        assert!(matches!(
+            compression.compress(message(4096)).await,
+            NetMessage::V1(NetMessageV1::CompressionOffer { .. })
+        ));
+
+        compression.accept_offer(&[Codec::ZSTD]);
+        let small = compression.compress(message(16)).await;
+        assert!(matches!(
+            small,
+            NetMessage::V1(NetMessageV1::CompressionOffer { .. })
+        ));
+        let large = message(4096);
+        let serialized = bincode::serialize(&large).unwrap();
+        let NetMessage::V1(NetMessageV1::Compressed { codec, payload, .. }) =
+            compression.compress(large).await
+        else {
+            panic!("large message not compressed");
+        };
+        assert!(payload.len() < serialized.len());
+        let decompressed = decompress(codec, payload).await.unwrap();
+        assert_eq!(bincode::serialize(&decompressed).unwrap(), serialized);
+    }
+
+    #[tokio::test]
+    async fn negotiate_by_id() {
+        // codecs unknown to this peer are skipped in the offer
+        let mut compression = ConnectionCompression::new(0);
+        compression.accept_offer(&[Codec(u32::MAX), Codec::LZ4]);
+        let msg = message(4096);
+        let serialized = bincode::serialize(&msg).unwrap();
+        let NetMessage::V1(NetMessageV1::Compressed { codec, payload, .. }) =
+            compression.compress(msg).await
+        else {
+            panic!("message not compressed");
+        };
+        assert_eq!(codec, Codec::LZ4);
+        let decompressed = decompress(codec, payload).await.unwrap();
+        assert_eq!(bincode::serialize(&decompressed).unwrap(), serialized);
+
+        // ...and without any known codec the messages go uncompressed
+        compression.accept_offer(&[Codec(u32::MAX)]);
+        assert!(matches!(
+            compression.compress(message(4096)).await,
+            NetMessage::V1(NetMessageV1::CompressionOffer { .. })
+        ));
+        assert!(decompress(Codec(u32::MAX), vec![0; 16]).await.is_err());
+
+        // an offer from a peer with codecs added later still decodes
+        let offer = NetMessage::V1(NetMessageV1::CompressionOffer {
+            transaction: Transaction::new::<ConnectMsg>(),
+            codecs: vec![Codec(7), Codec::ZSTD],
+        });
+        let decoded: NetMessage =
+            bincode::deserialize(&bincode::serialize(&offer).unwrap()).unwrap();
+        let NetMessage::V1(NetMessageV1::CompressionOffer { codecs, .. }) = decoded else {
+            panic!("offer not decoded");
+        };
+        assert_eq!(codecs, vec![Codec(7), Codec::ZSTD]);
+    }
+}
@@ -14,7 +14,7 @@ use tokio::sync::mpsc::{self};
 use crate::{
     dev_tool::{Location, PeerId, Transaction},
     message::{InnerMessage, NetMessage, NetMessageV1},
-    node::NetworkBridge,
+    node::{network_bridge::hello::understood_by_legacy_peers, Admission, NetworkBridge},
     operations::connect::{
         forward_conn, ConnectMsg, ConnectOp, ConnectRequest, ConnectResponse, ConnectState,
         ConnectivityInfo, ForwardParams,
@@ -250,6 +250,11 @@ impl HandshakeHandler {
                         }
                         Some(Ok(InternalEvent::FinishedOutboundConnProcess(tracker))) => {
                             self.connecting.remove(&tracker.gw_peer.peer.addr);
+                            if let Some(retry_after) = tracker.gw_retry_after {
+                                self.connection_manager.backoff_peer(&tracker.gw_peer.peer, retry_after);
+                            } else if tracker.gw_busy {
+                                self.connection_manager.backoff_busy_peer(&tracker.gw_peer.peer);
+                            }
                             // at this point we are done checking all the accepts inbound from a transient gw conn
                             tracing::debug!(at=?tracker.gw_conn.my_address(), gw=%tracker.gw_conn.remote_addr(), "Done checking, connection not accepted by gw, dropping connection");
                             Ok(Event::OutboundGatewayConnectionRejected { peer_id: tracker.gw_peer.peer })
@@ -315,9 +320,45 @@ impl HandshakeHandler {
                     match event {
                         InternalEvent::InboundGwJoinRequest(mut req) => {
                             let remote = req.conn.remote_addr();
-                            let location = Location::from_address(&remote);
-                            let should_accept = self.connection_manager.should_accept(location, &req.joiner);
-                            if should_accept {
+                            let location = self.connection_manager.assign_location(&remote);
+                            let admission = match self.connection_manager.gateway() {
+                                Some(gateway) => gateway.admit(&self.connection_manager, location, &req.joiner),
+                                // only gateways take unsolicited inbound connections
+                                None => Admission::Rejected,
+                            };
+                            if let Admission::Busy | Admission::RateLimited { .. } = admission {
+                                let InboundGwJoinRequest { mut conn, id, joiner, .. } = req;
+                                let acceptor = self.connection_manager.own_location();
+                                let response = match admission {
+                                    // joiners which predate busy replies would fail decoding them
+                                    _ if !conn.remote_greets() => {
+                                        tracing::debug!(at=?conn.my_address(), from=%remote, "Refusing join of a legacy joiner");
+                                        ConnectResponse::AcceptedBy { accepted: false, acceptor, joiner: joiner.clone() }
+                                    }
+                                    Admission::RateLimited { retry_after } => {
+                                        tracing::debug!(at=?conn.my_address(), from=%remote, ?retry_after, "Join quota exhausted, replying rate limited");
+                                        ConnectResponse::RateLimited { acceptor, joiner: joiner.clone(), retry_after }
+                                    }
+                                    _ => {
+                                        tracing::debug!(at=?conn.my_address(), from=%remote, "Out of resources for joins, replying busy");
+                                        ConnectResponse::Busy { acceptor, joiner: joiner.clone() }
+                                    }
+                                };
+                                let busy_msg = NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
+                                    id,
+                                    sender: self.connection_manager.own_location(),
+                                    target: PeerKeyLocation {
+                                        peer: joiner.clone(),
+                                        location: Some(location),
+                                    },
+                                    msg: response,
+                                }));
+                                conn.send(busy_msg).await?;
+                                self.outbound_messages.remove(&remote);
+                                self.connecting.remove(&remote);
+                                return Ok(Event::InboundConnectionRejected { peer_id: joiner });
+                            }
+                            if admission == Admission::Accepted {
                                 let accepted_msg = NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
                                     id: req.id,
                                     sender: self.connection_manager.own_location(),
@@ -349,7 +390,7 @@ impl HandshakeHandler {
                                     };
 
                                     let my_peer_id = self.connection_manager.own_location();
-                                    let joiner_loc = Location::from_address(&conn.remote_addr());
+                                    let joiner_loc = self.connection_manager.assign_location(&conn.remote_addr());
                                     let joiner_pk_loc = PeerKeyLocation {
                                         peer: joiner.clone(),
                                         location: Some(joiner_loc),
@@ -481,7 +522,7 @@ impl HandshakeHandler {
             msg: parking_lot::Mutex::new(None),
         };
 
-        let joiner_loc = Location::from_address(&conn.remote_addr());
+        let joiner_loc = self.connection_manager.assign_location(&conn.remote_addr());
         let joiner_pk_loc = PeerKeyLocation {
             peer: transaction.joiner.clone(),
             location: Some(joiner_loc),
@@ -572,14 +613,14 @@ impl HandshakeHandler {
         } else {
             let mut send_to_remote = None;
             if let NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
-                msg: ConnectResponse::AcceptedBy { joiner, .. },
+                msg: response,
                 ..
             })) = &op
             {
                 // this may be a reply message from a downstream peer to which it was forwarded previously
                 // for a transient connection, in this case we must send this message to the proper
                 // gw_transient_peer_conn future that is waiting for it
-                send_to_remote = Some(joiner.addr);
+                send_to_remote = Some(response.joiner().addr);
             }
 
             if let Some(remote) = send_to_remote {
@@ -663,6 +704,8 @@ impl HandshakeHandler {
                     gw_conn: conn,
                     gw_accepted: false,
                     gw_accepted_processed: false,
+                    gw_busy: false,
+                    gw_retry_after: None,
                     remaining_checks: max_hops_to_live,
                     accepted: 0,
                     total_checks: max_hops_to_live,
@@ -740,6 +783,10 @@ struct AcceptedTracker {
     gw_conn: PeerConnection,
     gw_accepted_processed: bool,
     gw_accepted: bool,
+    /// Whether the gateway replied it is out of resources for joins.
+    gw_busy: bool,
+    /// Time to wait before joining through the gateway again, if it rate limited this peer.
+    gw_retry_after: Option<Duration>,
     /// Remaining checks to be made, at max total_checks
     remaining_checks: usize,
     /// At max this will be total_checks
@@ -862,6 +909,47 @@ async fn check_remaining_hops(mut tracker: AcceptedTracker) -> OutboundConnResul
                     continue;
                 }
             }
+            NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
+                msg: ConnectResponse::Busy { acceptor, .. },
+                ..
+            })) => {
+                tracker.remaining_checks -= 1;
+                if acceptor.peer.addr == tracker.gw_conn.remote_addr() {
+                    // the gateway doesn't forward the join while busy, so there is nothing left to wait for
+                    tracing::debug!(
+                        at = ?tracker.gw_conn.my_address(),
+                        from = %tracker.gw_conn.remote_addr(),
+                        "Gateway is busy"
+                    );
+                    tracker.gw_accepted_processed = true;
+                    tracker.gw_busy = true;
+                    return Ok(InternalEvent::FinishedOutboundConnProcess(tracker));
+                }
+                continue;
+            }
+            NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
+                msg:
+                    ConnectResponse::RateLimited {
+                        acceptor,
+                        retry_after,
+                        ..
+                    },
+                ..
+            })) => {
+                tracker.remaining_checks -= 1;
+                if acceptor.peer.addr == tracker.gw_conn.remote_addr() {
+                    tracing::debug!(
+                        at = ?tracker.gw_conn.my_address(),
+                        from = %tracker.gw_conn.remote_addr(),
+                        ?retry_after,
+                        "Rate limited by gateway"
+                    );
+                    tracker.gw_accepted_processed = true;
+                    tracker.gw_retry_after = Some(retry_after);
+                    return Ok(InternalEvent::FinishedOutboundConnProcess(tracker));
+                }
+                continue;
+            }
             NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Request {
                 msg: ConnectRequest::FindOptimalPeer { .. },
                 ..
@@ -880,6 +968,15 @@ async fn check_remaining_hops(mut tracker: AcceptedTracker) -> OutboundConnResul
     Ok(InternalEvent::FinishedOutboundConnProcess(tracker))
 }
 
+/// Sends the message unless the joiner predates it and would fail decoding it.
+async fn send_understood(conn: &mut PeerConnection, msg: NetMessage) -> Result<(), TransportError> {
+    if !conn.remote_greets() && !understood_by_legacy_peers(&msg) {
+        tracing::debug!(to=%conn.remote_addr(), %msg, "Not sending message unknown to a legacy joiner");
+        return Ok(());
+    }
+    conn.send(msg).await
+}
+
 /// Handles communication with a potentially transient peer connection.
 /// Used primarily by gateways to manage connections in the process of joining the network.
 async fn gw_peer_connection_listener(
@@ -892,9 +989,7 @@ async fn gw_peer_connection_listener(
                 let Some(msg) = msg else { break Err(HandshakeError::ConnectionClosed(conn.remote_addr())); };
 
                 tracing::debug!(at=?conn.my_address(), from=%conn.remote_addr() ,"Sending message to peer. Msg: {msg}");
-                        conn
-                            .send(msg)
-                            .await?;
+                send_understood(&mut conn, msg).await?;
             }
             msg = conn.recv() => {
                 let Ok(msg) = msg.map_err(|error| {
@@ -981,12 +1076,12 @@ async fn gw_transient_peer_conn(
                     Ok(Some(msg)) => {
                         if matches!(
                             msg,
-                            NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response { msg: ConnectResponse::AcceptedBy { .. }, .. }))
+                            NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response { .. }))
                         ) {
                             let NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
                                 id,
                                 target,
-                                msg: ConnectResponse::AcceptedBy { accepted, acceptor, joiner },
+                                msg: response,
                                 ..
                             })) = msg else {
                                 unreachable!()
@@ -996,21 +1091,17 @@ async fn gw_transient_peer_conn(
                             let msg = NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
                                 id,
                                 sender: target,
-                                target: acceptor.clone(),
-                                msg: ConnectResponse::AcceptedBy {
-                                    accepted,
-                                    acceptor,
-                                    joiner,
-                                },
+                                target: response.acceptor().clone(),
+                                msg: response,
                             }));
-                            conn.send(msg).await?;
+                            send_understood(&mut conn, msg).await?;
                             if info.decrement_check() {
                                 break Ok((InternalEvent::DropInboundConnection(conn.remote_addr()), outbound));
                             } else {
                                 continue;
                             }
                         }
-                        conn.send(msg).await?;
+                        send_understood(&mut conn, msg).await?;
                     }
                     Ok(None) => {
                         tracing::debug!("Outbound channel closed");
@@ -1342,8 +1433,7 @@ mod tests {
         let (mut handler, mut test) = config_handler(addr, Some(vec![existing_conn]));
 
         // Configure the handler to reject connections by setting max_connections to 1
-        handler.connection_manager.max_connections = 1;
-        handler.connection_manager.min_connections = 1;
+        handler.connection_manager.set_connection_limits(1, 1);
 
         let remote_addr = ([127, 0, 0, 1], 10002).into();
 
@@ -1381,6 +1471,54 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_gateway_inbound_conn_busy() -> anyhow::Result<()> {
+        let addr: SocketAddr = ([127, 0, 0, 1], 10000).into();
+        let (mut handler, mut test) = config_handler(addr, None);
+
+        // Saturate the gateway with open transactions so it is out of budget for joins
+        handler
+            .connection_manager
+            .load_hints
+            .set_local(crate::transport::LoadHint {
+                queue_depth: crate::transport::QueueDepth::Saturated,
+                error_rate: 0,
+            });
+
+        let remote_addr = ([127, 0, 0, 1], 10001).into();
+        let test_controller = async {
+            let pub_key = TransportKeypair::new().public().clone();
+            test.transport.new_conn(remote_addr).await;
+            test.transport
+                .establish_inbound_conn(remote_addr, pub_key, None)
+                .await;
+            let msg = test.transport.recv_outbound_msg().await?;
+            assert!(matches!(
+                msg,
+                NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
+                    msg: ConnectResponse::Busy { .. },
+                    ..
+                }))
+            ));
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let gw_inbound = async {
+            let event =
+                tokio::time::timeout(Duration::from_secs(1), handler.wait_for_events()).await??;
+            match event {
+                Event::InboundConnectionRejected { peer_id } => {
+                    assert_eq!(peer_id.addr, remote_addr);
+                    Ok(())
+                }
+                other => Err(anyhow!("Unexpected event: {:?}", other)),
+            }
+        };
+
+        futures::try_join!(test_controller, gw_inbound)?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_peer_to_gw_outbound_conn() -> anyhow::Result<()> {
         let addr = ([127, 0, 0, 1], 10000).into();
@@ -1500,8 +1638,7 @@ mod tests {
         let (mut gw_handler, mut gw_test) = config_handler(gw_addr, None);
 
         // the gw only will accept one connection
-        gw_handler.connection_manager.max_connections = 1;
-        gw_handler.connection_manager.min_connections = 1;
+        gw_handler.connection_manager.set_connection_limits(1, 1);
 
         let peer_key = TransportKeypair::new();
         let joiner_key = TransportKeypair::new();
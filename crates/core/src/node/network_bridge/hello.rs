@@ -0,0 +1,428 @@
+//! Greeting exchanged by the ends of a connection once it is set up.
+//!
+//! Each end tells the other the range of versions of the wire protocol it speaks, whether it
+//! is a gateway, and the optional features it supports. Peers without a version in common are
+//! disconnected as soon as they greet, with an error saying why, instead of failing halfway
+//! through an operation at a message one of them does not understand. Optional messages, like
+//! compression offers, are only sent to the peers which said they support them.
+//!
+//! The greeting is not a network message: the layout of those changes from one version of the
+//! protocol to the next, while any version must be able to read the greeting. It is sent as a
+//! frame of its own, starting with a magic prefix which can't be the start of a network
+//! message, followed by the fields of [`HelloFrame`]. It goes ahead of any other message this
+//! node sends once the peer is past the handshake, which is when the peer first sends
+//! something over the connection.
+//!
+//! Nodes which predate the greeting fail on any frame they can't decode, so it is only sent
+//! to peers which said they understand it in their intro packet, or which greeted first. Peers
+//! which do neither are legacy peers, and are only sent the messages they know about.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{NetMessage, NetMessageV1},
+    operations::{
+        connect::{ConnectMsg, ConnectResponse},
+        get::GetMsg,
+        update::UpdateMsg,
+    },
+    transport::{MIN_PROTOC_VERSION, PROTOC_VERSION},
+};
+
+/// Start of a greeting frame. Network messages start with the version of the message enum,
+/// as a little endian u32, which is never this large.
+const HELLO_MAGIC: [u8; 4] = *b"FNHI";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeRole {
+    Gateway,
+    Regular,
+}
+
+/// Optional features a peer supports on top of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Feature {
+    /// Compressed messages, with the codecs negotiated with a compression offer.
+    Compression,
+    /// Estimation of the skew between the clocks of the ends.
+    ClockSync,
+    /// Listening on both IPv4 and IPv6.
+    DualStack,
+    /// Connections through NATs punched with the help of a gateway.
+    HolePunching,
+    /// Large states split in chunks.
+    StateChunks,
+    /// Get requests batched in a single message.
+    GetBatch,
+    /// Operation messages sealed by their sender.
+    SealedMessages,
+    /// Load hints carried in the keep-alive packets.
+    LoadHints,
+    /// A feature of a newer version of the protocol.
+    Unknown,
+}
+
+impl Feature {
+    /// Identifier of the feature in the greeting frame. Identifiers are never reused.
+    fn id(self) -> u8 {
+        match self {
+            Feature::Unknown => 0,
+            Feature::Compression => 1,
+            Feature::ClockSync => 2,
+            Feature::DualStack => 3,
+            Feature::HolePunching => 4,
+            Feature::StateChunks => 5,
+            Feature::GetBatch => 6,
+            Feature::SealedMessages => 7,
+            Feature::LoadHints => 8,
+        }
+    }
+
+    fn from_id(id: u8) -> Self {
+        match id {
+            1 => Feature::Compression,
+            2 => Feature::ClockSync,
+            3 => Feature::DualStack,
+            4 => Feature::HolePunching,
+            5 => Feature::StateChunks,
+            6 => Feature::GetBatch,
+            7 => Feature::SealedMessages,
+            8 => Feature::LoadHints,
+            _ => Feature::Unknown,
+        }
+    }
+}
+
+/// Features supported by this node.
+const FEATURES: &[Feature] = &[
+    Feature::Compression,
+    Feature::ClockSync,
+    Feature::DualStack,
+    Feature::HolePunching,
+    Feature::StateChunks,
+    Feature::GetBatch,
+    Feature::SealedMessages,
+    Feature::LoadHints,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Hello {
+    /// Newest version of the wire protocol the sender speaks.
+    pub protocol: u16,
+    /// Oldest version of the wire protocol the sender speaks.
+    pub min_protocol: u16,
+    pub role: NodeRole,
+    pub features: Vec<Feature>,
+}
+
+/// Layout of the greeting frame, as encoded by bincode. Fields may only be added at the end,
+/// older versions ignore them.
+#[derive(Serialize, Deserialize)]
+struct HelloFrame {
+    magic: [u8; 4],
+    protocol: u16,
+    min_protocol: u16,
+    gateway: bool,
+    features: Vec<u8>,
+}
+
+impl Hello {
+    fn local(role: NodeRole) -> Self {
+        Self {
+            protocol: PROTOC_VERSION,
+            min_protocol: MIN_PROTOC_VERSION,
+            role,
+            features: FEATURES.to_vec(),
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    fn to_frame(&self) -> Vec<u8> {
+        bincode::serialize(&HelloFrame {
+            magic: HELLO_MAGIC,
+            protocol: self.protocol,
+            min_protocol: self.min_protocol,
+            gateway: self.role == NodeRole::Gateway,
+            features: self.features.iter().map(|feature| feature.id()).collect(),
+        })
+        .expect("serializable")
+    }
+
+    /// The greeting in the frame, unless the frame is not a greeting.
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        if !frame.starts_with(&HELLO_MAGIC) {
+            return None;
+        }
+        let frame: HelloFrame = bincode::deserialize(frame).ok()?;
+        Some(Self {
+            protocol: frame.protocol,
+            min_protocol: frame.min_protocol,
+            role: if frame.gateway {
+                NodeRole::Gateway
+            } else {
+                NodeRole::Regular
+            },
+            features: frame.features.into_iter().map(Feature::from_id).collect(),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Incompatible {
+    #[error(
+        "peer speaks protocol versions {min_theirs} to {theirs}, none of {min_ours} to {ours}"
+    )]
+    ProtocolVersion {
+        ours: u16,
+        min_ours: u16,
+        theirs: u16,
+        min_theirs: u16,
+    },
+    #[error("invalid greeting")]
+    Invalid,
+}
+
+/// Whether a peer which predates the greeting can decode the message, which is the case for
+/// the messages of the first version of the protocol.
+pub(crate) fn understood_by_legacy_peers(msg: &NetMessage) -> bool {
+    let NetMessage::V1(msg) = msg;
+    match msg {
+        NetMessageV1::Connect(ConnectMsg::Response {
+            msg: ConnectResponse::Busy { .. } | ConnectResponse::RateLimited { .. },
+            ..
+        }) => false,
+        NetMessageV1::Get(GetMsg::SeekNodeAndSubscribe { .. }) => false,
+        NetMessageV1::Update(
+            UpdateMsg::BroadcastSummary { .. }
+            | UpdateMsg::RequestBody { .. }
+            | UpdateMsg::ReturnBody { .. },
+        ) => false,
+        NetMessageV1::Connect(_)
+        | NetMessageV1::Put(_)
+        | NetMessageV1::Get(_)
+        | NetMessageV1::Subscribe(_)
+        | NetMessageV1::Unsubscribed { .. }
+        | NetMessageV1::Update(_)
+        | NetMessageV1::Aborted(_) => true,
+        _ => false,
+    }
+}
+
+/// Features of the connected peers which greeted this node, by their address. Peers which
+/// did not greet support none.
+#[derive(Clone, Default)]
+pub(crate) struct PeerFeatures(Arc<DashMap<SocketAddr, Vec<Feature>>>);
+
+impl PeerFeatures {
+    pub fn greeted(&self, addr: SocketAddr, hello: &Hello) {
+        self.0.insert(addr, hello.features.clone());
+    }
+
+    pub fn supports(&self, addr: &SocketAddr, feature: Feature) -> bool {
+        self.0
+            .get(addr)
+            .is_some_and(|features| features.contains(&feature))
+    }
+
+    /// The peer moved to a new address, keeping the connection and so its greeting.
+    pub fn readdress(&self, previous: &SocketAddr, moved: SocketAddr) {
+        if let Some((_, features)) = self.0.remove(previous) {
+            self.0.insert(moved, features);
+        }
+    }
+
+    pub fn forget(&self, addr: &SocketAddr) {
+        self.0.remove(addr);
+    }
+}
+
+/// Greeting state of a single peer connection.
+#[derive(Debug)]
+pub(super) struct Greeting {
+    role: NodeRole,
+    /// Whether the peer said it understands greetings while connecting.
+    remote_greets: bool,
+    sent: bool,
+    /// Greeting of the peer, once received.
+    remote: Option<Hello>,
+    /// Whether the peer sent any sealed message.
+    remote_seals: bool,
+}
+
+impl Greeting {
+    pub fn new(role: NodeRole, remote_greets: bool) -> Self {
+        Self {
+            role,
+            remote_greets,
+            sent: false,
+            remote: None,
+            remote_seals: false,
+        }
+    }
+
+    /// Greeting frame of this peer, unless already sent over the connection or the peer is not
+    /// known to understand it yet.
+    pub fn greet(&mut self) -> Option<Vec<u8>> {
+        if self.is_legacy() || std::mem::replace(&mut self.sent, true) {
+            return None;
+        }
+        Some(Hello::local(self.role).to_frame())
+    }
+
+    /// Whether the peer neither said it understands greetings nor greeted, so it may predate
+    /// them and is only sent the messages [understood by legacy peers](understood_by_legacy_peers).
+    pub fn is_legacy(&self) -> bool {
+        !self.remote_greets && self.remote.is_none()
+    }
+
+    /// Checks whether the peer which sent the greeting can talk to this one, returning the
+    /// newest version of the protocol both speak.
+    pub fn greeted(&mut self, remote: &Hello) -> Result<u16, Incompatible> {
+        if remote.min_protocol > remote.protocol {
+            return Err(Incompatible::Invalid);
+        }
+        if remote.protocol < MIN_PROTOC_VERSION || remote.min_protocol > PROTOC_VERSION {
+            return Err(Incompatible::ProtocolVersion {
+                ours: PROTOC_VERSION,
+                min_ours: MIN_PROTOC_VERSION,
+                theirs: remote.protocol,
+                min_theirs: remote.min_protocol,
+            });
+        }
+        self.remote = Some(remote.clone());
+        Ok(remote.protocol.min(PROTOC_VERSION))
+    }
+
+    /// Whether the peer greeted saying it supports the feature.
+    pub fn peer_supports(&self, feature: Feature) -> bool {
+        self.remote
+            .as_ref()
+            .is_some_and(|remote| remote.supports(feature))
+    }
+
+    /// The peer sent a sealed message, and so is expected to seal every message from then on.
+    pub fn peer_sealed(&mut self) {
+        self.remote_seals = true;
+    }
+
+    pub fn peer_seals(&self) -> bool {
+        self.remote_seals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::Transaction, node::PeerId, ring::PeerKeyLocation};
+
+    #[test]
+    fn incompatible_versions_are_rejected() {
+        let mut greeting = Greeting::new(NodeRole::Regular, true);
+        let frame = greeting.greet().expect("greeting");
+        assert!(greeting.greet().is_none());
+        let hello = Hello::from_frame(&frame).unwrap();
+        assert_eq!(hello, Hello::local(NodeRole::Regular));
+        assert!(hello.supports(Feature::Compression));
+        assert_eq!(greeting.greeted(&hello).unwrap(), PROTOC_VERSION);
+
+        // newer peers still speaking this version
+        let newer = Hello {
+            protocol: PROTOC_VERSION + 3,
+            min_protocol: PROTOC_VERSION,
+            ..hello.clone()
+        };
+        assert_eq!(greeting.greeted(&newer).unwrap(), PROTOC_VERSION);
+        let too_new = Hello {
+            protocol: PROTOC_VERSION + 3,
+            min_protocol: PROTOC_VERSION + 1,
+            ..hello.clone()
+        };
+        assert!(matches!(
+            greeting.greeted(&too_new),
+            Err(Incompatible::ProtocolVersion { .. })
+        ));
+        let too_old = Hello {
+            protocol: MIN_PROTOC_VERSION - 1,
+            min_protocol: 0,
+            ..hello.clone()
+        };
+        assert!(matches!(
+            greeting.greeted(&too_old),
+            Err(Incompatible::ProtocolVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn legacy_peers_are_not_greeted() {
+        let mut greeting = Greeting::new(NodeRole::Regular, false);
+        assert!(greeting.is_legacy());
+        assert!(greeting.greet().is_none());
+
+        // peers which greet first are answered
+        let hello = Hello::local(NodeRole::Gateway);
+        greeting.greeted(&hello).unwrap();
+        assert!(!greeting.is_legacy());
+        assert!(greeting.greet().is_some());
+        assert!(greeting.greet().is_none());
+
+        let features = PeerFeatures::default();
+        let addr = ([127, 0, 0, 1], 10_000).into();
+        assert!(!features.supports(&addr, Feature::StateChunks));
+        features.greeted(addr, &hello);
+        assert!(features.supports(&addr, Feature::StateChunks));
+        features.forget(&addr);
+        assert!(!features.supports(&addr, Feature::StateChunks));
+    }
+
+    #[test]
+    fn legacy_peers_only_get_baseline_messages() {
+        let tx = Transaction::new::<ConnectMsg>();
+        assert!(understood_by_legacy_peers(&NetMessage::V1(
+            NetMessageV1::Aborted(tx)
+        )));
+        let busy = NetMessage::V1(NetMessageV1::Connect(ConnectMsg::Response {
+            id: tx,
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            msg: ConnectResponse::Busy {
+                acceptor: PeerKeyLocation::random(),
+                joiner: PeerId::random(),
+            },
+        }));
+        assert!(!understood_by_legacy_peers(&busy));
+        assert!(!understood_by_legacy_peers(&NetMessage::V1(
+            NetMessageV1::AddressProbe { transaction: tx }
+        )));
+    }
+
+    #[test]
+    fn greeting_frame_is_fixed() {
+        let hello = Hello::local(NodeRole::Gateway);
+        let frame = hello.to_frame();
+        assert_eq!(frame[..4], HELLO_MAGIC);
+        assert_eq!(frame[4..6], PROTOC_VERSION.to_le_bytes());
+        assert_eq!(frame[6..8], MIN_PROTOC_VERSION.to_le_bytes());
+
+        // features and fields added by newer versions are ignored
+        let mut newer = frame.clone();
+        let features = newer.len() - 1;
+        newer[features] = 200;
+        newer.extend_from_slice(b"some new field");
+        let decoded = Hello::from_frame(&newer).unwrap();
+        assert_eq!(decoded.role, NodeRole::Gateway);
+        assert_eq!(decoded.features.last(), Some(&Feature::Unknown));
+
+        // network messages are never mistaken for greetings
+        let msg = bincode::serialize(&NetMessage::V1(NetMessageV1::AddressProbe {
+            transaction: Transaction::new::<ConnectMsg>(),
+        }))
+        .unwrap();
+        assert!(Hello::from_frame(&msg).is_none());
+    }
+}
@@ -7,6 +7,8 @@ use std::{
 };
 
 use crossbeam::channel::{self, Receiver, Sender};
+use dashmap::DashMap;
+use futures::StreamExt;
 use once_cell::sync::OnceCell;
 use rand::{prelude::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use tokio::sync::Mutex;
@@ -14,9 +16,13 @@ use tokio::sync::Mutex;
 use super::{ConnectionError, NetworkBridge, PeerId};
 use crate::{
     config::GlobalExecutor,
-    message::NetMessage,
-    node::{testing_impl::NetworkBridgeExt, NetEventRegister, OpManager},
-    tracing::NetEventLog,
+    message::{MessageStats, NetMessage},
+    node::{
+        testing_impl::{NetworkBridgeExt, WireBreaks, WireFeatures},
+        NetEventRegister, OpManager,
+    },
+    tracing::{replay_messages, NetEventLog, RecordedMessage},
+    transport::NetworkId,
 };
 
 #[derive(Clone)]
@@ -28,13 +34,21 @@ pub(in crate::node) struct MemoryConnManager {
 }
 
 impl MemoryConnManager {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         peer: PeerId,
+        network_id: NetworkId,
         log_register: impl NetEventRegister,
         op_manager: Arc<OpManager>,
         add_noise: bool,
+        wire_features: WireFeatures,
+        wire_breaks: WireBreaks,
+        replay: Vec<RecordedMessage>,
     ) -> Self {
-        let transport = InMemoryTransport::new(peer, add_noise);
+        WIRE_FEATURES
+            .get_or_init(DashMap::new)
+            .insert(peer.clone(), wire_features);
+        let transport = InMemoryTransport::new(peer, network_id, add_noise);
         let msg_queue = Arc::new(Mutex::new(Vec::new()));
 
         let msg_queue_cp = msg_queue.clone();
@@ -45,12 +59,43 @@ impl MemoryConnManager {
                 let Some(msg) = transport_cp.msg_stack_queue.lock().await.pop() else {
                     continue;
                 };
+                if msg.network != network_id {
+                    tracing::warn!(
+                        from = %msg.origin,
+                        network = %msg.network,
+                        "Dropping message from a peer belonging to a different network"
+                    );
+                    continue;
+                }
                 let msg_data: NetMessage =
-                    bincode::deserialize_from(Cursor::new(msg.data)).unwrap();
+                    bincode::deserialize_from(Cursor::new(&msg.data)).unwrap();
+                if !wire_features.decodes(&msg.data, &msg_data) {
+                    tracing::error!(
+                        from = %msg.origin,
+                        tx = %msg_data.id(),
+                        "Dropping message not supported by this peer: {msg_data}"
+                    );
+                    wire_breaks.record();
+                    continue;
+                }
                 msg_queue_cp.lock().await.push(msg_data);
             }
         });
 
+        if !replay.is_empty() {
+            let msg_queue = msg_queue.clone();
+            let replayed = replay_messages(replay, op_manager.clock.clone());
+            GlobalExecutor::spawn(async move {
+                futures::pin_mut!(replayed);
+                while let Some(msg) = replayed.next().await {
+                    tracing::debug!(tx = %msg.id(), "Replaying recorded message");
+                    // the queue is popped from the back, keep the replayed ones in order
+                    msg_queue.lock().await.insert(0, msg);
+                }
+                tracing::info!("Finished replaying recorded messages");
+            });
+        }
+
         Self {
             transport,
             log_register: Arc::new(log_register),
@@ -65,6 +110,15 @@ impl NetworkBridge for MemoryConnManager {
         self.log_register
             .register_events(NetEventLog::from_outbound_msg(&msg, &self.op_manager.ring))
             .await;
+        // as the greeting would over real connections, peers are only sent what they understand
+        if WIRE_FEATURES
+            .get()
+            .and_then(|features| features.get(target).map(|f| *f))
+            .is_some_and(|features| !features.receives(&msg))
+        {
+            tracing::debug!(%target, %msg, "Not sending message unknown to a legacy peer");
+            return Ok(());
+        }
         self.op_manager.sending_transaction(target, &msg);
         let msg = bincode::serialize(&msg)?;
         self.transport.send(target.clone(), msg);
@@ -94,15 +148,20 @@ impl NetworkBridgeExt for MemoryConnManager {
 struct MessageOnTransit {
     origin: PeerId,
     target: PeerId,
+    network: NetworkId,
     data: Vec<u8>,
 }
 
 static NETWORK_WIRES: OnceCell<(Sender<MessageOnTransit>, Receiver<MessageOnTransit>)> =
     OnceCell::new();
 
+/// Wire features each of the simulated peers runs with.
+static WIRE_FEATURES: OnceCell<DashMap<PeerId, WireFeatures>> = OnceCell::new();
+
 #[derive(Clone, Debug)]
 struct InMemoryTransport {
     interface_peer: PeerId,
+    network_id: NetworkId,
     /// received messages per each peer awaiting processing
     msg_stack_queue: Arc<Mutex<Vec<MessageOnTransit>>>,
     /// all messages 'traversing' the network at a given time
@@ -110,7 +169,7 @@ struct InMemoryTransport {
 }
 
 impl InMemoryTransport {
-    fn new(interface_peer: PeerId, add_noise: bool) -> Self {
+    fn new(interface_peer: PeerId, network_id: NetworkId, add_noise: bool) -> Self {
         let msg_stack_queue = Arc::new(Mutex::new(Vec::new()));
         let (network_tx, network_rx) = NETWORK_WIRES.get_or_init(crossbeam::channel::unbounded);
 
@@ -175,6 +234,7 @@ impl InMemoryTransport {
 
         Self {
             interface_peer,
+            network_id,
             msg_stack_queue,
             network: network_tx.clone(),
         }
@@ -184,6 +244,7 @@ impl InMemoryTransport {
         let send_res = self.network.send(MessageOnTransit {
             origin: self.interface_peer.clone(),
             target: peer,
+            network: self.network_id,
             data: message,
         });
         if let Err(channel::SendError(_)) = send_res {
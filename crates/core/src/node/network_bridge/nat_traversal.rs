@@ -0,0 +1,333 @@
+//! Coordination of NAT traversal between peers which cannot reach each other directly.
+//!
+//! Most peers sit behind a NAT which drops packets from addresses it did not send to first, so
+//! a connection between two of them fails unless both ends send to each other at the same time.
+//! When connecting to a peer fails, this peer asks a gateway it is connected to for an
+//! introduction: if the gateway is connected to the other peer too, it tells each end about
+//! the other and both connect simultaneously, punching a hole through their NATs. If punching
+//! keeps failing the peers fall back to exchanging their messages relayed through the gateway.
+//!
+//! Gateways only relay messages between peers they introduced to each other, up to a rate
+//! limit, so they can't be used as an open relay. Peers only act on introductions from the
+//! gateway they asked for one or, if they did not ask, from one of their gateways.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{node::PeerId, ring::Location};
+
+/// Introductions requested before falling back to relaying.
+const MAX_PUNCH_ATTEMPTS: usize = 3;
+/// Time after which a traversal without news from the gateway is given up.
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Messages relayed per second between a pair of peers introduced by this gateway.
+const MAX_RELAYED_PER_SEC: u32 = 100;
+/// Pairs of introduced peers remembered, past which the least recently introduced ones are
+/// forgotten.
+const MAX_BROKERED: usize = 1024;
+
+#[derive(Debug)]
+enum Traversal {
+    Punching {
+        /// Gateway brokering the introduction.
+        via: PeerId,
+        /// Location of the peer, once introduced.
+        location: Option<Location>,
+        /// Whether this peer requested the introduction, and so is the one retrying it.
+        initiator: bool,
+        attempts: usize,
+        since: Instant,
+    },
+    Relayed {
+        via: PeerId,
+    },
+}
+
+/// What to do after an attempt at punching through the NAT failed.
+#[derive(Debug, PartialEq)]
+pub(super) enum NextStep {
+    /// Request a new introduction from the gateway.
+    Retry { via: PeerId },
+    /// Wait for the other end to request a new introduction.
+    Wait,
+    /// Give up punching and relay the messages through the gateway.
+    Relay {
+        via: PeerId,
+        location: Option<Location>,
+    },
+}
+
+/// Pair of peers introduced to each other by this gateway.
+#[derive(Debug)]
+struct Brokered {
+    introduced_at: Instant,
+    /// Start of the current rate limit window, and the messages relayed within it.
+    window: Instant,
+    relayed: u32,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct NatTraversal {
+    peers: HashMap<PeerId, Traversal>,
+    /// Pairs of peers introduced by this gateway, keyed with the lower peer first.
+    brokered: HashMap<(PeerId, PeerId), Brokered>,
+}
+
+fn pair(a: &PeerId, b: &PeerId) -> (PeerId, PeerId) {
+    if a <= b {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
+
+impl NatTraversal {
+    /// Starts traversing the NAT of the peer through the gateway, returns false if it is
+    /// already in progress or the peer is already relayed.
+    pub fn start(&mut self, peer: PeerId, via: PeerId, now: Instant) -> bool {
+        match self.peers.get(&peer) {
+            Some(Traversal::Relayed { .. }) => return false,
+            Some(Traversal::Punching { since, .. })
+                if now.saturating_duration_since(*since) < PUNCH_TIMEOUT =>
+            {
+                return false
+            }
+            _ => {}
+        }
+        self.peers.insert(
+            peer,
+            Traversal::Punching {
+                via,
+                location: None,
+                initiator: true,
+                attempts: 0,
+                since: now,
+            },
+        );
+        true
+    }
+
+    /// The gateway introduced the peer, returns whether to punch through towards it.
+    ///
+    /// Introductions to a peer this node asked for one are only accepted from the gateway
+    /// asked, unsolicited ones only if the gateway is trusted to broker them.
+    pub fn introduced(
+        &mut self,
+        peer: PeerId,
+        location: Option<Location>,
+        via: PeerId,
+        trusted: bool,
+        now: Instant,
+    ) -> bool {
+        match self.peers.get_mut(&peer) {
+            Some(Traversal::Relayed { .. }) => false,
+            Some(Traversal::Punching { via: asked, .. }) if *asked != via => false,
+            Some(Traversal::Punching {
+                location: known,
+                since,
+                ..
+            }) => {
+                *known = location.or(*known);
+                *since = now;
+                true
+            }
+            None if !trusted => false,
+            None => {
+                self.peers.insert(
+                    peer,
+                    Traversal::Punching {
+                        via,
+                        location,
+                        initiator: false,
+                        attempts: 0,
+                        since: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Connecting to the peer failed, returns what to do next if traversing its NAT.
+    pub fn punch_failed(&mut self, peer: &PeerId) -> Option<NextStep> {
+        let Traversal::Punching {
+            via,
+            location,
+            initiator,
+            attempts,
+            ..
+        } = self.peers.get_mut(peer)?
+        else {
+            return None;
+        };
+        *attempts += 1;
+        if *attempts < MAX_PUNCH_ATTEMPTS {
+            return Some(if *initiator {
+                NextStep::Retry { via: via.clone() }
+            } else {
+                NextStep::Wait
+            });
+        }
+        let (via, location) = (via.clone(), *location);
+        self.peers
+            .insert(peer.clone(), Traversal::Relayed { via: via.clone() });
+        Some(NextStep::Relay { via, location })
+    }
+
+    /// Connected to the peer, returns its location if the connection punched through its NAT.
+    pub fn established(&mut self, peer: &PeerId) -> Option<Location> {
+        match self.peers.remove(peer)? {
+            Traversal::Punching { location, .. } => location,
+            Traversal::Relayed { .. } => None,
+        }
+    }
+
+    /// Gateway relaying the messages to the peer, if any.
+    pub fn relay(&self, peer: &PeerId) -> Option<&PeerId> {
+        match self.peers.get(peer)? {
+            Traversal::Relayed { via } => Some(via),
+            Traversal::Punching { .. } => None,
+        }
+    }
+
+    /// This gateway introduced the peers to each other, so it relays their messages if they
+    /// fail to connect.
+    pub fn brokered(&mut self, a: &PeerId, b: &PeerId, now: Instant) {
+        let key = pair(a, b);
+        if self.brokered.len() >= MAX_BROKERED && !self.brokered.contains_key(&key) {
+            let oldest = self
+                .brokered
+                .iter()
+                .min_by_key(|(_, brokered)| brokered.introduced_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.brokered.remove(&oldest);
+            }
+        }
+        self.brokered
+            .entry(key)
+            .and_modify(|brokered| brokered.introduced_at = now)
+            .or_insert(Brokered {
+                introduced_at: now,
+                window: now,
+                relayed: 0,
+            });
+    }
+
+    /// Whether to relay a message between the peers: only if this gateway introduced them
+    /// and they are within the rate limit.
+    pub fn may_relay(&mut self, from: &PeerId, to: &PeerId, now: Instant) -> bool {
+        let Some(brokered) = self.brokered.get_mut(&pair(from, to)) else {
+            return false;
+        };
+        if now.saturating_duration_since(brokered.window) >= Duration::from_secs(1) {
+            brokered.window = now;
+            brokered.relayed = 0;
+        }
+        if brokered.relayed >= MAX_RELAYED_PER_SEC {
+            return false;
+        }
+        brokered.relayed += 1;
+        true
+    }
+
+    /// Forgets the traversals towards the disconnected peer, and the ones it brokered.
+    pub fn forget(&mut self, disconnected: &PeerId) {
+        self.peers.retain(|peer, traversal| {
+            let via = match traversal {
+                Traversal::Punching { via, .. } | Traversal::Relayed { via } => via,
+            };
+            peer != disconnected && via != disconnected
+        });
+        self.brokered
+            .retain(|(a, b), _| a != disconnected && b != disconnected);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn punch_then_relay() {
+        let mut traversal = NatTraversal::default();
+        let gateway = PeerId::random();
+        let peer = PeerId::random();
+        let location = Some(Location::new(0.3));
+        let now = Instant::now();
+
+        assert!(traversal.start(peer.clone(), gateway.clone(), now));
+        assert!(!traversal.start(peer.clone(), gateway.clone(), now));
+        assert!(traversal.introduced(peer.clone(), location, gateway.clone(), false, now));
+        for _ in 1..MAX_PUNCH_ATTEMPTS {
+            assert_eq!(
+                traversal.punch_failed(&peer),
+                Some(NextStep::Retry {
+                    via: gateway.clone()
+                })
+            );
+        }
+        assert_eq!(
+            traversal.punch_failed(&peer),
+            Some(NextStep::Relay {
+                via: gateway.clone(),
+                location
+            })
+        );
+        assert_eq!(traversal.relay(&peer), Some(&gateway));
+        assert!(!traversal.start(peer.clone(), gateway.clone(), now));
+        assert!(!traversal.introduced(peer.clone(), location, gateway.clone(), false, now));
+
+        // losing the gateway drops the relay
+        traversal.forget(&gateway);
+        assert!(traversal.relay(&peer).is_none());
+        assert_eq!(traversal.punch_failed(&peer), None);
+
+        // the introduced end waits for the initiator to retry
+        let other = PeerId::random();
+        assert!(!traversal.introduced(other.clone(), location, gateway.clone(), false, now));
+        assert!(traversal.introduced(other.clone(), location, gateway.clone(), true, now));
+        assert_eq!(traversal.punch_failed(&other), Some(NextStep::Wait));
+        assert_eq!(traversal.established(&other), location);
+        assert_eq!(traversal.established(&other), None);
+
+        // stale traversals are started over
+        assert!(traversal.start(other.clone(), gateway.clone(), now));
+        assert!(traversal.start(other, gateway, now + PUNCH_TIMEOUT));
+    }
+
+    #[test]
+    fn introductions_only_from_the_gateway_asked() {
+        let mut traversal = NatTraversal::default();
+        let (gateway, impostor, peer) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        assert!(traversal.start(peer.clone(), gateway.clone(), now));
+        assert!(!traversal.introduced(peer.clone(), None, impostor, true, now));
+        assert!(traversal.introduced(peer, None, gateway, false, now));
+    }
+
+    #[test]
+    fn relay_only_brokered_peers() {
+        let mut traversal = NatTraversal::default();
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        assert!(!traversal.may_relay(&a, &b, now));
+
+        traversal.brokered(&a, &b, now);
+        assert!(traversal.may_relay(&a, &b, now));
+        assert!(traversal.may_relay(&b, &a, now));
+        assert!(!traversal.may_relay(&a, &c, now));
+
+        // rate limited
+        for _ in 2..MAX_RELAYED_PER_SEC {
+            assert!(traversal.may_relay(&a, &b, now));
+        }
+        assert!(!traversal.may_relay(&a, &b, now));
+        assert!(traversal.may_relay(&a, &b, now + Duration::from_secs(1)));
+
+        traversal.forget(&b);
+        assert!(!traversal.may_relay(&a, &b, now + Duration::from_secs(2)));
+    }
+}
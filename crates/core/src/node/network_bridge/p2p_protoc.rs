@@ -8,24 +8,37 @@ use futures::{FutureExt, StreamExt};
 use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
+use std::time::Duration;
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
-use tokio::net::UdpSocket;
 use tokio::select;
-use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::mpsc::{self, Receiver};
 use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tracing::Instrument;
 
-use crate::dev_tool::Location;
+use crate::node::network_bridge::address_watch::{self, AddressWatch, Check};
+use crate::node::network_bridge::authentication::{
+    embedded_sender, requires_authentication, unsealed, AuthenticationError, MessageAuthenticator,
+    SignedEnvelope,
+};
+use crate::node::network_bridge::compression::{decompress, ConnectionCompression};
 use crate::node::network_bridge::handshake::{
     EstablishConnection, Event as HandshakeEvent, ForwardInfo, HandshakeError, HandshakeHandler,
     OutboundMessage,
 };
-use crate::node::PeerId;
+use crate::node::network_bridge::hello::{
+    understood_by_legacy_peers, Feature, Greeting, Hello, NodeRole, PeerFeatures,
+};
+use crate::node::network_bridge::nat_traversal::{NatTraversal, NextStep};
+use crate::node::network_bridge::policing::{MessagePolicing, Policed};
+use crate::node::network_bridge::priority::{self, Bulk, PriorityReceiver, PrioritySender};
+use crate::node::{NodeLifecycleEvent, PeerId, ReaddressNotice};
 use crate::transport::{
-    create_connection_handler, PeerConnection, TransportError, TransportKeypair,
+    create_connection_handler, AddressBook, BandwidthLimits, NetworkId, PeerConnection,
+    TransportError, TransportKeypair, TransportKind,
 };
 use crate::{
     client_events::ClientId,
@@ -35,26 +48,49 @@ use crate::{
         NetworkEventListenerHalve, WaitingResolution,
     },
     message::{MessageStats, NetMessage, NodeEvent, Transaction},
-    node::{handle_aborted_op, process_message, NetEventRegister, NodeConfig, OpManager},
-    ring::PeerKeyLocation,
+    node::{
+        handle_aborted_op, port_mapping::PortMappings, process_message, ClientTransactionTracker,
+        NetEventRegister, NodeConfig, OpManager, PortMapping,
+    },
+    operations::{
+        connect::{self, ConnectMsg},
+        get, leave, relocate,
+        state_transfer::{self, StateTransfers},
+    },
+    ring::{ClockSkews, PeerKeyLocation, ReputationSignal},
     tracing::NetEventLog,
+    util::time_source::SharedClock,
 };
 
-type P2pBridgeEvent = Either<(PeerId, Box<NetMessage>), NodeEvent>;
+/// Outbound messages carry the span they were sent from, so the transmission is logged
+/// within the scope of the operation that originated it.
+type OutboundBridgeMessage = (PeerId, Box<NetMessage>, tracing::Span);
+type P2pBridgeEvent = Either<OutboundBridgeMessage, NodeEvent>;
+
+impl Bulk for OutboundBridgeMessage {
+    fn is_bulk(&self) -> bool {
+        self.1.is_bulk()
+    }
+}
 
 #[derive(Clone)]
 pub(crate) struct P2pBridge {
     accepted_peers: Arc<DashSet<PeerId>>,
-    ev_listener_tx: Sender<P2pBridgeEvent>,
+    /// Outbound messages of bulk transfers queue apart, so they don't hold back the rest.
+    ev_listener_tx: PrioritySender<OutboundBridgeMessage, NodeEvent>,
     op_manager: Arc<OpManager>,
     log_register: Arc<dyn NetEventRegister>,
+    state_chunk_threshold: usize,
+    authenticator: MessageAuthenticator,
 }
 
 impl P2pBridge {
     fn new<EL>(
-        sender: Sender<P2pBridgeEvent>,
+        sender: PrioritySender<OutboundBridgeMessage, NodeEvent>,
         op_manager: Arc<OpManager>,
         event_register: EL,
+        state_chunk_threshold: usize,
+        authenticator: MessageAuthenticator,
     ) -> Self
     where
         EL: NetEventRegister,
@@ -64,6 +100,8 @@ impl P2pBridge {
             ev_listener_tx: sender,
             op_manager,
             log_register: Arc::new(event_register),
+            state_chunk_threshold,
+            authenticator,
         }
     }
 }
@@ -89,27 +127,73 @@ impl NetworkBridge for P2pBridge {
             .register_events(NetEventLog::from_outbound_msg(&msg, &self.op_manager.ring))
             .await;
         self.op_manager.sending_transaction(target, &msg);
-        self.ev_listener_tx
-            .send(Left((target.clone(), Box::new(msg))))
-            .await
-            .map_err(|_| ConnectionError::SendNotCompleted(target.clone()))?;
+        // sealed for the peer the event loop will deliver the message to
+        let target = &msg
+            .target()
+            .map(|t| t.peer)
+            .unwrap_or_else(|| target.clone());
+        let connection_manager = &self.op_manager.ring.connection_manager;
+        // peers which can't put chunks back together get the whole state
+        let chunk_threshold = if connection_manager
+            .peer_features
+            .supports(&target.addr, Feature::StateChunks)
+        {
+            self.state_chunk_threshold
+        } else {
+            usize::MAX
+        };
+        let messages = match state_transfer::split_message(msg, chunk_threshold) {
+            Ok(Left(msg)) => vec![msg],
+            Ok(Right(chunks)) => chunks,
+            Err(error) => {
+                tracing::error!(%error, "Failed to split message in chunks");
+                return Err(ConnectionError::SendNotCompleted(target.clone()));
+            }
+        };
+        for msg in messages {
+            let msg = if requires_authentication(&msg) {
+                self.authenticator
+                    .seal(&target.pub_key, msg, self.op_manager.clock.unix_time())
+            } else {
+                msg
+            };
+            self.ev_listener_tx
+                .send(Left((
+                    target.clone(),
+                    Box::new(msg),
+                    tracing::Span::current(),
+                )))
+                .await
+                .map_err(|_| ConnectionError::SendNotCompleted(target.clone()))?;
+        }
         Ok(())
     }
 }
 
-type PeerConnChannelSender = Sender<Either<NetMessage, ConnEvent>>;
-type PeerConnChannelRecv = Receiver<Either<NetMessage, ConnEvent>>;
+type PeerConnChannelSender = PrioritySender<NetMessage, ConnEvent>;
+type PeerConnChannelRecv = PriorityReceiver<NetMessage, ConnEvent>;
 
 pub(in crate::node) struct P2pConnManager {
     pub(in crate::node) gateways: Vec<PeerKeyLocation>,
     pub(in crate::node) bridge: P2pBridge,
-    conn_bridge_rx: Receiver<P2pBridgeEvent>,
+    conn_bridge_rx: PriorityReceiver<OutboundBridgeMessage, NodeEvent>,
     event_listener: Box<dyn NetEventRegister>,
     connections: HashMap<PeerId, PeerConnChannelSender>,
     key_pair: TransportKeypair,
     listening_ip: IpAddr,
     listening_port: u16,
+    /// Listener IP of the other family, if listening on both.
+    secondary_listening_ip: Option<IpAddr>,
+    /// Addresses of both IP families of this node and its peers.
+    addresses: AddressBook,
+    port_mapping: PortMapping,
     is_gateway: bool,
+    network_id: NetworkId,
+    transports: Vec<TransportKind>,
+    /// Upstream bandwidth limits of the transport, which may change while the node runs.
+    pub(in crate::node) bandwidth_limits: watch::Sender<BandwidthLimits>,
+    compression_threshold: usize,
+    policing: MessagePolicing,
 }
 
 impl P2pConnManager {
@@ -121,8 +205,21 @@ impl P2pConnManager {
         let listen_port = config.network_listener_port;
         let listener_ip = config.network_listener_ip;
 
-        let (tx_bridge_cmd, rx_bridge_cmd) = mpsc::channel(100);
-        let bridge = P2pBridge::new(tx_bridge_cmd, op_manager, event_listener.clone());
+        let (tx_bridge_cmd, rx_bridge_cmd) = priority::channel(100);
+        let policing = MessagePolicing::new(
+            config.message_policing,
+            op_manager.ring.connection_manager.bans.clone(),
+            op_manager.policing.clone(),
+        );
+        let bridge = P2pBridge::new(
+            tx_bridge_cmd,
+            op_manager,
+            event_listener.clone(),
+            config
+                .state_chunk_threshold
+                .unwrap_or(crate::config::DEFAULT_STATE_CHUNK_THRESHOLD),
+            MessageAuthenticator::new(config.key_pair.clone()),
+        );
 
         let gateways = config.get_gateways()?;
         let key_pair = config.key_pair.clone();
@@ -135,7 +232,28 @@ impl P2pConnManager {
             key_pair,
             listening_ip: listener_ip,
             listening_port: listen_port,
+            secondary_listening_ip: config.dual_stack.secondary(listener_ip),
+            addresses: AddressBook::default(),
+            port_mapping: config.port_mapping,
             is_gateway: config.is_gateway,
+            network_id: config.network_id,
+            transports: config
+                .transports
+                .clone()
+                .unwrap_or_else(|| crate::config::DEFAULT_TRANSPORTS.to_vec()),
+            bandwidth_limits: watch::channel(BandwidthLimits {
+                total: config
+                    .upstream_bandwidth_limit
+                    .unwrap_or(crate::config::DEFAULT_UPSTREAM_BANDWIDTH_LIMIT),
+                remote_share: config
+                    .peer_bandwidth_share
+                    .unwrap_or(crate::config::DEFAULT_PEER_BANDWIDTH_SHARE),
+            })
+            .0,
+            compression_threshold: config
+                .compression_threshold
+                .unwrap_or(crate::config::DEFAULT_COMPRESSION_THRESHOLD),
+            policing,
         })
     }
 
@@ -149,18 +267,52 @@ impl P2pConnManager {
         cli_response_sender: ClientResponsesSender,
         mut node_controller: Receiver<NodeEvent>,
     ) -> anyhow::Result<()> {
-        tracing::info!(%self.listening_port, %self.listening_ip, %self.is_gateway, key = %self.key_pair.public(), "Opening network listener");
+        tracing::info!(%self.listening_port, %self.listening_ip, %self.is_gateway, transports = ?self.transports, key = %self.key_pair.public(), "Opening network listener");
 
-        let mut state = EventListenerState::new();
+        let mut state = EventListenerState::new(cli_response_sender, op_manager.clock.clone());
 
-        let (outbound_conn_handler, inbound_conn_handler) = create_connection_handler::<UdpSocket>(
+        let (outbound_conn_handler, inbound_conn_handler) = create_connection_handler(
+            &self.transports,
+            self.bandwidth_limits.subscribe(),
+            op_manager.traffic.clone(),
             self.key_pair.clone(),
             self.listening_ip,
             self.listening_port,
+            self.secondary_listening_ip,
             self.is_gateway,
+            self.network_id,
+            self.addresses.clone(),
         )
         .await?;
 
+        // gateways are expected to be reachable already
+        let port_mappings =
+            (!self.is_gateway && self.port_mapping != PortMapping::Disabled).then(|| {
+                PortMappings::start(
+                    self.port_mapping,
+                    self.addresses.clone(),
+                    op_manager.clone(),
+                )
+            });
+
+        if !self.is_gateway {
+            let op_manager = self.bridge.op_manager.clone();
+            GlobalExecutor::spawn(async move {
+                let mut interval = tokio::time::interval(address_watch::CHECK_INTERVAL);
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                loop {
+                    interval.tick().await;
+                    if op_manager
+                        .notify_node_event(NodeEvent::CheckAddress)
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+
         let (mut handshake_handler, establish_connection, outbound_message) = HandshakeHandler::new(
             inbound_conn_handler,
             outbound_conn_handler.clone(),
@@ -184,43 +336,69 @@ impl P2pConnManager {
                 EventResult::Continue => continue,
                 EventResult::Event(event) => {
                     match event {
-                        ConnEvent::InboundMessage(msg) => {
+                        ConnEvent::InboundMessage { msg, from } => {
                             self.handle_inbound_message(
                                 msg,
+                                from,
                                 &outbound_message,
                                 &op_manager,
                                 &mut state,
                                 &executor_listener,
-                                &cli_response_sender,
                             )
                             .await?;
                         }
-                        ConnEvent::OutboundMessage(NetMessage::V1(NetMessageV1::Aborted(tx))) => {
+                        ConnEvent::OutboundMessage {
+                            msg: NetMessage::V1(NetMessageV1::Aborted(tx)),
+                            ..
+                        } => {
                             // TODO: handle aborted transaction as internal message
                             tracing::error!(%tx, "Aborted transaction");
                         }
-                        ConnEvent::OutboundMessage(msg) => {
-                            let Some(target_peer) = msg.target() else {
-                                let id = *msg.id();
-                                tracing::error!(%id, %msg, "Target peer not set, must be set for connection outbound message");
-                                self.bridge.op_manager.completed(id);
-                                continue;
-                            };
-                            tracing::debug!(%target_peer, %msg, "Sending message to peer");
-                            match self.connections.get(&target_peer.peer) {
-                                Some(peer_connection) => {
-                                    if let Err(e) = peer_connection.send(Left(msg)).await {
-                                        tracing::error!("Failed to send message to peer: {}", e);
+                        ConnEvent::OutboundMessage { target, msg, span } => {
+                            // messages which are not part of an operation (e.g. notifications)
+                            // don't carry a target, use the peer they were sent to
+                            let target_peer = msg.target().map(|t| t.peer).unwrap_or(target);
+                            async {
+                                tracing::debug!(%target_peer, %msg, "Sending message to peer");
+                                match self.connections.get(&target_peer) {
+                                    Some(peer_connection) => {
+                                        if let Err(e) = peer_connection.send(Left(msg)).await {
+                                            tracing::error!(
+                                                "Failed to send message to peer: {}",
+                                                e
+                                            );
+                                        }
                                     }
-                                }
-                                None => {
-                                    tracing::error!(
-                                        id = %msg.id(),
-                                        target = %target_peer.peer,
-                                        "No existing outbound connection to forward the message"
-                                    );
+                                    None => match state
+                                        .nat_traversal
+                                        .relay(&target_peer)
+                                        .and_then(|via| self.connections.get(via))
+                                    {
+                                        Some(relay) => {
+                                            let msg = NetMessage::V1(NetMessageV1::Relayed {
+                                                transaction: *msg.id(),
+                                                target: target_peer.clone(),
+                                                msg: Box::new(msg),
+                                            });
+                                            if let Err(e) = relay.send(Left(msg)).await {
+                                                tracing::error!(
+                                                    "Failed to relay message to peer: {}",
+                                                    e
+                                                );
+                                            }
+                                        }
+                                        None => {
+                                            tracing::error!(
+                                                id = %msg.id(),
+                                                target = %target_peer,
+                                                "No existing outbound connection to forward the message"
+                                            );
+                                        }
+                                    },
                                 }
                             }
+                            .instrument(span)
+                            .await;
                         }
 
                         ConnEvent::HandshakeAction(action) => {
@@ -261,6 +439,80 @@ impl P2pConnManager {
                                 let connections = self.connections.keys().cloned().collect();
                                 callback.send(QueryResult::Connections(connections)).await?;
                             }
+                            NodeEvent::AddressChanged { new_addr } => {
+                                self.handle_address_changed(new_addr).await;
+                            }
+                            NodeEvent::CheckAddress if !state.leaving => {
+                                self.check_address(&mut state).await?;
+                            }
+                            NodeEvent::CheckAddress => {}
+                            NodeEvent::AdvertiseAddresses => {
+                                self.advertise_addresses().await;
+                            }
+                            NodeEvent::Relocate { location } if !state.leaving => {
+                                let op_manager = op_manager.clone();
+                                let bridge = self.bridge.clone();
+                                GlobalExecutor::spawn(async move {
+                                    if let Err(error) =
+                                        relocate::relocate(&op_manager, &bridge, location).await
+                                    {
+                                        tracing::warn!(%error, "Failed relocating");
+                                    }
+                                });
+                            }
+                            NodeEvent::Relocate { .. } => {}
+                            NodeEvent::HolePunch { peer, via } => {
+                                self.handle_hole_punch(
+                                    peer,
+                                    via,
+                                    &establish_connection,
+                                    &mut state,
+                                )
+                                .await?;
+                            }
+                            NodeEvent::Disconnected if !state.leaving => {
+                                op_manager
+                                    .ring
+                                    .lifecycle_events
+                                    .emit(NodeLifecycleEvent::Disconnected);
+                                tracing::info!("Joining the network again through the gateways");
+                                if let Err(error) = connect::initial_join_procedure(
+                                    op_manager.clone(),
+                                    &self.gateways,
+                                )
+                                .await
+                                {
+                                    tracing::error!(%error, "Failed joining the network again");
+                                }
+                            }
+                            NodeEvent::Disconnected => {}
+                            NodeEvent::Reconnected { offline_for } => {
+                                tracing::info!(?offline_for, "Connected to the network again");
+                                op_manager
+                                    .ring
+                                    .lifecycle_events
+                                    .emit(NodeLifecycleEvent::Reconnected { offline_for });
+                            }
+                            NodeEvent::SetMessageQuota { tx_type, quota } => {
+                                self.policing.set_quota(tx_type, quota);
+                            }
+                            NodeEvent::GatewaysDiscovered { gateways } => {
+                                self.add_gateways(gateways);
+                            }
+                            NodeEvent::Disconnect { cause } if !state.leaving => {
+                                tracing::info!("Leaving the network");
+                                state.leaving = true;
+                                let op_manager = op_manager.clone();
+                                let bridge = self.bridge.clone();
+                                GlobalExecutor::spawn(async move {
+                                    if let Err(error) = leave::leave(&op_manager, &bridge).await {
+                                        tracing::warn!(%error, "Failed leaving the network gracefully");
+                                    }
+                                    let _ = op_manager
+                                        .notify_node_event(NodeEvent::Disconnect { cause })
+                                        .await;
+                                });
+                            }
                             NodeEvent::Disconnect { cause } => {
                                 tracing::info!(
                                     "Disconnecting from network{}",
@@ -273,6 +525,9 @@ impl P2pConnManager {
                 }
             }
         }
+        if let Some(port_mappings) = port_mappings {
+            port_mappings.stop().await;
+        }
         Ok(())
     }
 
@@ -289,6 +544,22 @@ impl P2pConnManager {
             msg = state.peer_connections.next(), if !state.peer_connections.is_empty() => {
                 self.handle_peer_connection_msg(msg, state).await
             }
+            Some((msg, from)) = state.authenticating.next(), if !state.authenticating.is_empty() => {
+                match msg {
+                    Ok(msg) => self.accept_inbound(msg, from).await,
+                    // not the fault of the sender, the message is dropped like any other
+                    // arriving while this node is overloaded
+                    Err(error @ AuthenticationError::Overloaded(..)) => {
+                        tracing::debug!(?from, %error, "Discarding message");
+                        EventResult::Continue
+                    }
+                    Err(error) => {
+                        tracing::warn!(?from, %error, "Discarding unauthenticated message");
+                        self.report_invalid_message(from.as_ref());
+                        EventResult::Continue
+                    }
+                }
+            }
             msg = notification_channel.0.recv() => {
                 self.handle_notification_msg(msg).await
             }
@@ -313,29 +584,120 @@ impl P2pConnManager {
     async fn handle_inbound_message(
         &self,
         msg: NetMessage,
+        from: Option<PeerId>,
         outbound_message: &OutboundMessage,
         op_manager: &Arc<OpManager>,
         state: &mut EventListenerState,
         executor_listener: &ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
-        cli_response_sender: &ClientResponsesSender,
     ) -> anyhow::Result<()> {
         match msg {
             NetMessage::V1(NetMessageV1::Aborted(tx)) => {
                 handle_aborted_op(tx, op_manager, &self.gateways).await?;
             }
+            // busy refusals carry no sender, so they are only taken from the peers the
+            // operation is waiting on, otherwise any peer could fail the operations of others
+            NetMessage::V1(NetMessageV1::Busy { transaction })
+                if !from.as_ref().is_some_and(|from| {
+                    op_manager
+                        .ring
+                        .live_tx_tracker
+                        .peers_of(&transaction)
+                        .contains(from)
+                }) =>
+            {
+                tracing::debug!(tx = %transaction, ?from, "Ignoring busy refusal from a peer not asked");
+            }
+            NetMessage::V1(NetMessageV1::CancelGet {
+                transaction,
+                key,
+                subscribe,
+            }) => {
+                if let Some(from) = &from {
+                    get::cancel_request(
+                        op_manager,
+                        &self.bridge,
+                        (transaction, key, subscribe),
+                        from,
+                    )
+                    .await?;
+                }
+            }
+            NetMessage::V1(NetMessageV1::PunchRequest {
+                transaction,
+                from: requester,
+                target,
+            }) => {
+                self.introduce_peers(transaction, requester, target, state)
+                    .await;
+            }
+            NetMessage::V1(NetMessageV1::Relayed { target, msg, .. })
+                if op_manager.ring.connection_manager.get_peer_key().as_ref() != Some(&target) =>
+            {
+                let may_relay = from.as_ref().is_some_and(|from| {
+                    state
+                        .nat_traversal
+                        .may_relay(from, &target, op_manager.clock.now())
+                });
+                match self.connections.get(&target).filter(|_| may_relay) {
+                    Some(peer) => {
+                        let msg = NetMessage::V1(NetMessageV1::Relayed {
+                            transaction: *msg.id(),
+                            target: target.clone(),
+                            msg,
+                        });
+                        peer.send(Left(msg)).await?;
+                    }
+                    None => tracing::debug!(
+                        ?from,
+                        %target,
+                        "Not relaying the message, peers not introduced by this node"
+                    ),
+                }
+            }
+            NetMessage::V1(NetMessageV1::Relayed { msg, .. }) => match *msg {
+                // the skew of the clock of the sender is unknown; relayed messages come from
+                // peers recent enough to punch holes, which always seal them
+                NetMessage::V1(NetMessageV1::Authenticated(envelope)) => {
+                    self.authenticate(*envelope, None, Duration::ZERO, state);
+                }
+                msg if requires_authentication(&msg) => {
+                    tracing::warn!(%msg, "Discarding unsealed relayed message");
+                }
+                msg => {
+                    self.process_message(msg, op_manager, executor_listener, state)
+                        .await;
+                }
+            },
             msg => {
                 if let Some(addr) = state.transient_conn.get(msg.id()) {
                     // Forward message to transient joiner
                     outbound_message.send_to(*addr, msg).await?;
                 } else {
-                    self.process_message(
-                        msg,
-                        op_manager,
-                        executor_listener,
-                        cli_response_sender,
-                        state,
-                    )
-                    .await;
+                    let msg = match msg {
+                        NetMessage::V1(NetMessageV1::StateChunk(chunk)) => {
+                            // only taken straight from the peer sending the state, so a
+                            // transfer can't be tampered with by others
+                            let Some(from) = from else {
+                                tracing::debug!(tx = %chunk.id, "Discarding state chunk of unknown sender");
+                                return Ok(());
+                            };
+                            match state.state_transfers.receive(
+                                from,
+                                chunk,
+                                op_manager.clock.unix_time(),
+                            ) {
+                                Ok(Some(msg)) => msg,
+                                Ok(None) => return Ok(()),
+                                Err(error) => {
+                                    tracing::warn!(%error, "Discarding state chunk");
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        msg => msg,
+                    };
+                    self.process_message(msg, op_manager, executor_listener, state)
+                        .await;
                 }
             }
         }
@@ -347,34 +709,36 @@ impl P2pConnManager {
         msg: NetMessage,
         op_manager: &Arc<OpManager>,
         executor_listener: &ExecutorToEventLoopChannel<NetworkEventListenerHalve>,
-        cli_response_sender: &ClientResponsesSender,
         state: &mut EventListenerState,
     ) {
         let executor_callback = state
             .pending_from_executor
             .remove(msg.id())
             .then(|| executor_listener.callback());
-        let pending_client_req = state.tx_to_client.get(msg.id()).copied();
-        let client_req_handler_callback = pending_client_req.map(|_| cli_response_sender.clone());
 
+        let tx_type = msg.id().transaction_type();
         let span = tracing::info_span!(
             "process_network_message",
             transaction = %msg.id(),
-            tx_type = %msg.id().transaction_type()
+            %tx_type
         );
 
-        GlobalExecutor::spawn(
-            process_message(
-                msg,
-                op_manager.clone(),
-                self.bridge.clone(),
-                self.event_listener.trait_clone(),
-                executor_callback,
-                client_req_handler_callback,
-                pending_client_req,
-            )
-            .instrument(span),
-        );
+        let skips_lanes = msg.is_control() || op_manager.has_op(msg.id());
+        let job = process_message(
+            msg,
+            op_manager.clone(),
+            self.bridge.clone(),
+            self.event_listener.trait_clone(),
+            executor_callback,
+            state.client_tracker.clone(),
+        )
+        .instrument(span)
+        .boxed();
+        if skips_lanes {
+            op_manager.dispatcher.dispatch_now(job);
+        } else {
+            op_manager.dispatcher.dispatch(tx_type, job);
+        }
     }
 
     async fn handle_connect_peer(
@@ -411,25 +775,29 @@ impl P2pConnManager {
         match event {
             HandshakeEvent::InboundConnection {
                 id,
-                conn,
+                mut conn,
                 joiner,
                 op,
                 forward_info,
             } => {
-                let (tx, rx) = mpsc::channel(1);
+                // room for the light client announcement ahead of the first message
+                let (tx, rx) = priority::channel(2);
+                self.announce_light_client(&tx).await;
                 self.connections.insert(joiner.clone(), tx);
                 let was_reserved = {
                     // this is an unexpected inbound request at a gateway so it didn't have a reserved spot
                     false
                 };
+                let location = self
+                    .bridge
+                    .op_manager
+                    .ring
+                    .connection_manager
+                    .assign_location(&joiner.addr);
                 self.bridge
                     .op_manager
                     .ring
-                    .add_connection(
-                        Location::from_address(&joiner.addr),
-                        joiner.clone(),
-                        was_reserved,
-                    )
+                    .add_connection(location, joiner.clone(), was_reserved)
                     .await;
                 if let Some(op) = op {
                     self.bridge
@@ -437,7 +805,37 @@ impl P2pConnManager {
                         .push(id, crate::operations::OpEnum::Connect(op))
                         .await?;
                 }
-                let task = peer_connection_listener(rx, conn).boxed();
+                conn.exchange_load_hints(
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .connection_manager
+                        .load_hints
+                        .clone(),
+                );
+                let compression = ConnectionCompression::new(self.compression_threshold);
+                let greeting = Greeting::new(self.role(), conn.remote_greets());
+                let task = peer_connection_listener(
+                    rx,
+                    conn,
+                    compression,
+                    greeting,
+                    self.addresses.clone(),
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .connection_manager
+                        .clock_skews
+                        .clone(),
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .connection_manager
+                        .peer_features
+                        .clone(),
+                    self.bridge.op_manager.clock.clone(),
+                )
+                .boxed();
                 state.peer_connections.push(task);
 
                 if let Some(ForwardInfo {
@@ -492,6 +890,7 @@ impl P2pConnManager {
                 if let Some(mut r) = state.awaiting_connection.remove(&peer_id.addr) {
                     r.send_result(Err(error)).await?;
                 }
+                self.traverse_nat(peer_id, state).await?;
             }
             HandshakeEvent::RemoveTransaction(tx) => {
                 state.transient_conn.remove(&tx);
@@ -522,10 +921,11 @@ impl P2pConnManager {
     async fn handle_successful_connection(
         &mut self,
         peer_id: PeerId,
-        connection: PeerConnection,
+        mut connection: PeerConnection,
         state: &mut EventListenerState,
         remaining_checks: Option<usize>,
     ) -> anyhow::Result<()> {
+        let punched_at = state.nat_traversal.established(&peer_id);
         if let Some(mut cb) = state.awaiting_connection.remove(&peer_id.addr) {
             let peer_id = if let Some(peer_id) = self
                 .bridge
@@ -543,16 +943,388 @@ impl P2pConnManager {
                 PeerId::new(self_addr, key)
             };
             let _ = cb.send_result(Ok((peer_id, remaining_checks))).await;
+        } else if let Some(location) = punched_at {
+            tracing::info!(%peer_id, "Connection punched through the NAT");
+            self.bridge
+                .op_manager
+                .ring
+                .add_connection(location, peer_id.clone(), false)
+                .await;
         } else {
             tracing::warn!(%peer_id, "No callback for connection established");
         }
-        let (tx, rx) = mpsc::channel(10);
+        // the remote peer reports the address it sees for this peer, which changes
+        // if this peer moved to a different network
+        let observed_addr = connection.my_address();
+        let (tx, rx) = priority::channel(10);
+        self.announce_light_client(&tx).await;
         self.connections.insert(peer_id.clone(), tx);
-        let task = peer_connection_listener(rx, connection).boxed();
+        connection.exchange_load_hints(
+            self.bridge
+                .op_manager
+                .ring
+                .connection_manager
+                .load_hints
+                .clone(),
+        );
+        let compression = ConnectionCompression::new(self.compression_threshold);
+        let greeting = Greeting::new(self.role(), connection.remote_greets());
+        let task = peer_connection_listener(
+            rx,
+            connection,
+            compression,
+            greeting,
+            self.addresses.clone(),
+            self.bridge
+                .op_manager
+                .ring
+                .connection_manager
+                .clock_skews
+                .clone(),
+            self.bridge
+                .op_manager
+                .ring
+                .connection_manager
+                .peer_features
+                .clone(),
+            self.bridge.op_manager.clock.clone(),
+        )
+        .boxed();
         state.peer_connections.push(task);
+        let own = self
+            .bridge
+            .op_manager
+            .ring
+            .connection_manager
+            .get_peer_key();
+        if let (Some(observed), Some(own)) = (observed_addr, own) {
+            if !self.is_gateway && observed != own.addr {
+                // a single peer is not trusted with the address of this one, the neighbours
+                // are asked about it instead
+                state.address_watch.reported();
+            }
+        }
+        Ok(())
+    }
+
+    fn role(&self) -> NodeRole {
+        if self.is_gateway {
+            NodeRole::Gateway
+        } else {
+            NodeRole::Regular
+        }
+    }
+
+    /// Lets a new neighbour know this node is a light client, so it does not count this node
+    /// among the holders of the contracts close to it.
+    async fn announce_light_client(&self, conn: &PeerConnChannelSender) {
+        let ring = &self.bridge.op_manager.ring;
+        if !ring.is_light_client() {
+            return;
+        }
+        let Some(from) = ring.connection_manager.get_peer_key() else {
+            return;
+        };
+        let msg = NetMessage::V1(NetMessageV1::NonCaching {
+            transaction: Transaction::new::<ConnectMsg>(),
+            from,
+        });
+        if let Err(error) = conn.send(Left(msg)).await {
+            tracing::debug!(%error, "Failed to announce this node is a light client");
+        }
+    }
+
+    /// Connects to a peer introduced by a gateway, at the same time as the peer connects to
+    /// this one.
+    async fn handle_hole_punch(
+        &mut self,
+        peer: PeerKeyLocation,
+        via: PeerId,
+        establish_connection: &EstablishConnection,
+        state: &mut EventListenerState,
+    ) -> anyhow::Result<()> {
+        // unsolicited introductions are only trusted from the gateways of this node
+        let trusted = self.gateways.iter().any(|gw| gw.peer == via);
+        if self.connections.contains_key(&peer.peer)
+            || !state.nat_traversal.introduced(
+                peer.peer.clone(),
+                peer.location,
+                via,
+                trusted,
+                self.bridge.op_manager.clock.now(),
+            )
+        {
+            return Ok(());
+        }
+        tracing::debug!(peer = %peer.peer, "Punching through the NAT");
+        establish_connection
+            .establish_conn(peer.peer, Transaction::new::<ConnectMsg>(), false)
+            .await
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Introduces two peers behind NATs to each other, so both connect to the other at the same
+    /// time. Only gateways broker introductions, and only between peers connected to them; the
+    /// messages of the peers introduced are relayed if they fail to connect.
+    async fn introduce_peers(
+        &self,
+        transaction: Transaction,
+        from: PeerKeyLocation,
+        target: PeerId,
+        state: &mut EventListenerState,
+    ) {
+        let connection_manager = &self.bridge.op_manager.ring.connection_manager;
+        let (true, Some(via)) = (
+            self.bridge.op_manager.ring.is_gateway(),
+            connection_manager.get_peer_key(),
+        ) else {
+            tracing::debug!(from = %from.peer, "Ignoring punch request, not a gateway");
+            return;
+        };
+        let (Some(location), true) = (
+            connection_manager.location_of(&target),
+            self.connections.contains_key(&from.peer),
+        ) else {
+            tracing::debug!(from = %from.peer, %target, "Not connected to the peers to introduce");
+            return;
+        };
+        if ![&from.peer, &target].into_iter().all(|peer| {
+            connection_manager
+                .peer_features
+                .supports(&peer.addr, Feature::HolePunching)
+        }) {
+            tracing::debug!(from = %from.peer, %target, "Not introducing peers which do not punch holes");
+            return;
+        }
+        state
+            .nat_traversal
+            .brokered(&from.peer, &target, self.bridge.op_manager.clock.now());
+        let introductions = [
+            (target.clone(), from.clone()),
+            (
+                from.peer,
+                PeerKeyLocation {
+                    peer: target,
+                    location: Some(location),
+                },
+            ),
+        ];
+        for (receiver, peer) in introductions {
+            let Some(conn) = self.connections.get(&receiver) else {
+                tracing::debug!(%receiver, "No connection to send the punch introduction");
+                continue;
+            };
+
+            let msg = NetMessage::V1(NetMessageV1::PunchIntroduction {
+                transaction,
+                target: receiver.clone(),
+                peer,
+                via: via.clone(),
+            });
+            if let Err(error) = conn.send(Left(msg)).await {
+                tracing::debug!(%error, %receiver, "Failed to send punch introduction");
+            }
+        }
+    }
+
+    /// After failing to connect to a peer, asks a gateway to broker a simultaneous connection
+    /// attempt with it, eventually falling back to relaying the messages through the gateway.
+    async fn traverse_nat(
+        &mut self,
+        peer: PeerId,
+        state: &mut EventListenerState,
+    ) -> anyhow::Result<()> {
+        if self.is_gateway || self.gateways.iter().any(|gw| gw.peer == peer) {
+            return Ok(());
+        }
+        let via = match state.nat_traversal.punch_failed(&peer) {
+            Some(NextStep::Retry { via }) => via,
+            Some(NextStep::Wait) => return Ok(()),
+            Some(NextStep::Relay { via, location }) => {
+                tracing::info!(%peer, %via, "Failed punching through the NAT, relaying through the gateway");
+                if let Some(location) = location {
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .add_connection(location, peer, false)
+                        .await;
+                }
+                return Ok(());
+            }
+            None => {
+                let Some(via) = self
+                    .gateways
+                    .iter()
+                    .find(|gw| self.connections.contains_key(&gw.peer))
+                    .map(|gw| gw.peer.clone())
+                else {
+                    return Ok(());
+                };
+                if !state.nat_traversal.start(
+                    peer.clone(),
+                    via.clone(),
+                    self.bridge.op_manager.clock.now(),
+                ) {
+                    return Ok(());
+                }
+                via
+            }
+        };
+        let Some(gateway) = self.connections.get(&via) else {
+            return Ok(());
+        };
+        if !self
+            .bridge
+            .op_manager
+            .ring
+            .connection_manager
+            .peer_features
+            .supports(&via.addr, Feature::HolePunching)
+        {
+            tracing::debug!(%via, "Gateway does not broker punches through NATs");
+            return Ok(());
+        }
+        tracing::debug!(%peer, %via, "Requesting introduction to punch through the NAT");
+        let msg = NetMessage::V1(NetMessageV1::PunchRequest {
+            transaction: Transaction::new::<ConnectMsg>(),
+            from: self
+                .bridge
+                .op_manager
+                .ring
+                .connection_manager
+                .own_location(),
+            target: peer,
+        });
+        gateway.send(Left(msg)).await?;
         Ok(())
     }
 
+    /// Adds the gateways not known yet to those the network is joined again through.
+    fn add_gateways(&mut self, gateways: Vec<PeerKeyLocation>) {
+        for gateway in gateways {
+            if self.gateways.iter().all(|known| known.peer != gateway.peer) {
+                tracing::info!(gateway = %gateway.peer, "Discovered a new gateway");
+                self.gateways.push(gateway);
+            }
+        }
+    }
+
+    /// Advertises the addresses of this node again to the connected peers which understand
+    /// advertisements, since the ones they were advertised when connecting changed.
+    async fn advertise_addresses(&self) {
+        let peer_features = &self.bridge.op_manager.ring.connection_manager.peer_features;
+        for (peer, conn) in &self.connections {
+            if !peer_features.supports(&peer.addr, Feature::DualStack) {
+                continue;
+            }
+            if let Some(addrs) = self.addresses.advertisement_for(peer.addr) {
+                let msg = NetMessage::V1(NetMessageV1::AddressAdvertisement {
+                    transaction: Transaction::new::<ConnectMsg>(),
+                    addrs,
+                });
+                if conn.send(Left(msg)).await.is_err() {
+                    tracing::debug!(%peer, "Connection closed before advertising the addresses");
+                }
+            }
+        }
+    }
+
+    /// Notifies the connected peers that this peer moved to a new address, so they migrate
+    /// their connections instead of dropping them.
+    async fn handle_address_changed(&self, new_addr: SocketAddr) {
+        let op_manager = &self.bridge.op_manager;
+        let connection_manager = &op_manager.ring.connection_manager;
+        let Some(previous) = connection_manager.get_peer_key() else {
+            return;
+        };
+        if previous.addr == new_addr {
+            return;
+        }
+        tracing::info!(%previous, %new_addr, "Address changed, migrating connections");
+        connection_manager.update_peer_addr(new_addr);
+        let notice = ReaddressNotice::new(
+            &self.key_pair,
+            previous,
+            new_addr,
+            op_manager.clock.unix_time(),
+        );
+        // peers without a connection which survived the change are reached through a gateway
+        let relay = self
+            .gateways
+            .iter()
+            .find_map(|gw| self.connections.get(&gw.peer));
+        for target in connection_manager.connected_peers() {
+            let Some(conn) = self.connections.get(&target).or(relay) else {
+                tracing::warn!(%target, "No connection to send the re-address notice");
+                continue;
+            };
+            let msg = NetMessage::V1(NetMessageV1::Readdress {
+                transaction: Transaction::new::<ConnectMsg>(),
+                notice: notice.clone(),
+                target: target.clone(),
+            });
+            if let Err(error) = conn.send(Left(msg)).await {
+                tracing::debug!(%error, %target, "Failed to send re-address notice");
+            }
+        }
+    }
+
+    /// Checks whether the address of this peer may have changed, probing the neighbours for
+    /// the address they see this peer at if so.
+    async fn check_address(&self, state: &mut EventListenerState) -> anyhow::Result<()> {
+        let now = self.bridge.op_manager.clock.now();
+        match state.address_watch.check(self.addresses.local_ip(), now) {
+            Check::Idle => {}
+            Check::Rejoin => {
+                tracing::info!("Connections lost with the previous address, joining again");
+                connect::initial_join_procedure(self.bridge.op_manager.clone(), &self.gateways)
+                    .await?;
+            }
+            // probed once connected
+            Check::Probe if self.connections.is_empty() => {}
+            Check::Probe => {
+                tracing::debug!(
+                    neighbours = self.connections.len(),
+                    "Probing the address this peer is seen at"
+                );
+                for conn in self.connections.values() {
+                    let msg = NetMessage::V1(NetMessageV1::AddressProbe {
+                        transaction: Transaction::new::<ConnectMsg>(),
+                    });
+                    if let Err(error) = conn.send(Left(msg)).await {
+                        tracing::debug!(%error, "Failed to send address probe");
+                    }
+                }
+                state
+                    .address_watch
+                    .probing(self.connections.keys().map(|peer| peer.addr), now);
+            }
+        }
+        Ok(())
+    }
+
+    /// A neighbour answered the address probe, the neighbours are notified once enough of them
+    /// agree this peer moved to a new address.
+    async fn handle_observed_address(
+        &self,
+        neighbour: SocketAddr,
+        observed: SocketAddr,
+        state: &mut EventListenerState,
+    ) {
+        let Some(own) = self
+            .bridge
+            .op_manager
+            .ring
+            .connection_manager
+            .get_peer_key()
+        else {
+            return;
+        };
+        if let Some(new_addr) = state.address_watch.observed(neighbour, observed, own.addr) {
+            self.handle_address_changed(new_addr).await;
+        }
+    }
+
     async fn handle_peer_connection_msg(
         &mut self,
         msg: Option<Result<PeerConnectionInbound, TransportError>>,
@@ -560,12 +1332,102 @@ impl P2pConnManager {
     ) -> EventResult {
         match msg {
             Some(Ok(peer_conn)) => {
-                let task = peer_connection_listener(peer_conn.rx, peer_conn.conn).boxed();
+                let remote_addr = peer_conn.conn.remote_addr();
+                let peer = self
+                    .connections
+                    .keys()
+                    .find_map(|k| (k.addr == remote_addr).then(|| k.clone()));
+                let policed = peer_conn.msg.as_ref().map_or(Policed::Accepted, |msg| {
+                    self.policing.police(
+                        remote_addr,
+                        peer.as_ref(),
+                        msg.id().transaction_type(),
+                        self.bridge.op_manager.clock.now(),
+                    )
+                });
+                if policed == Policed::Banned {
+                    // the listener is not polled anymore, so the connection is closed
+                    self.policing.forget(&remote_addr);
+                    self.addresses.connection_closed(&remote_addr);
+                    if let Some(peer) = peer {
+                        self.bridge
+                            .op_manager
+                            .ring
+                            .prune_connection(peer.clone())
+                            .await;
+                        self.connections.remove(&peer);
+                        state.nat_traversal.forget(&peer);
+                    }
+                    return EventResult::Continue;
+                }
+                let peer_seals = peer_conn.greeting.peer_seals();
+                let clock_tolerance = self
+                    .bridge
+                    .op_manager
+                    .ring
+                    .connection_manager
+                    .clock_skews
+                    .get(&remote_addr)
+                    .map(|skew| skew.tolerance())
+                    .unwrap_or_default();
+                let task = peer_connection_listener(
+                    peer_conn.rx,
+                    peer_conn.conn,
+                    peer_conn.compression,
+                    peer_conn.greeting,
+                    self.addresses.clone(),
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .connection_manager
+                        .clock_skews
+                        .clone(),
+                    self.bridge
+                        .op_manager
+                        .ring
+                        .connection_manager
+                        .peer_features
+                        .clone(),
+                    self.bridge.op_manager.clock.clone(),
+                )
+                .boxed();
                 state.peer_connections.push(task);
-                EventResult::Event(ConnEvent::InboundMessage(peer_conn.msg))
+                match peer_conn.msg {
+                    Ok(_) if policed == Policed::Throttled => EventResult::Continue,
+                    Ok(NetMessage::V1(NetMessageV1::ObservedAddress { addr, .. })) => {
+                        self.handle_observed_address(remote_addr, addr, state).await;
+                        EventResult::Continue
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::Authenticated(envelope))) => match peer {
+                        Some(peer) if envelope.from == peer.pub_key => {
+                            self.authenticate(*envelope, Some(peer), clock_tolerance, state);
+                            EventResult::Continue
+                        }
+                        peer => {
+                            tracing::warn!(from = %remote_addr, "Discarding message sealed by another peer");
+                            self.report_invalid_message(peer.as_ref());
+                            EventResult::Continue
+                        }
+                    },
+                    // unsealed messages are only accepted from peers which never sealed one,
+                    // while the network moves to sealed messages
+                    Ok(msg) if requires_authentication(&msg) && peer_seals => {
+                        tracing::warn!(from = %remote_addr, error = %AuthenticationError::Missing, "Discarding unauthenticated message");
+                        self.report_invalid_message(peer.as_ref());
+                        EventResult::Continue
+                    }
+                    Ok(msg) => self.accept_inbound(msg, peer).await,
+                    Err(error) => {
+                        tracing::warn!(from = %remote_addr, %error, "Received invalid message from peer");
+                        self.report_invalid_message(peer.as_ref());
+                        EventResult::Continue
+                    }
+                }
             }
             Some(Err(err)) => {
                 if let TransportError::ConnectionClosed(socket_addr) = err {
+                    self.policing.forget(&socket_addr);
+                    self.addresses.connection_closed(&socket_addr);
                     if let Some(peer) = self
                         .connections
                         .keys()
@@ -578,6 +1440,7 @@ impl P2pConnManager {
                             .prune_connection(peer.clone())
                             .await;
                         self.connections.remove(&peer);
+                        state.nat_traversal.forget(&peer);
                     }
                 }
                 EventResult::Continue
@@ -589,12 +1452,68 @@ impl P2pConnManager {
         }
     }
 
+    fn report_invalid_message(&self, peer: Option<&PeerId>) {
+        if let Some(peer) = peer {
+            let op_manager = &self.bridge.op_manager;
+            op_manager.ring.connection_manager.peer_reputation.report(
+                peer,
+                ReputationSignal::InvalidMessage,
+                op_manager.clock.now(),
+            );
+        }
+    }
+
+    /// Opens the envelope of an operation message received from a peer, off the event loop.
+    /// The message is handled once verified, as received from the given connected peer, or as
+    /// relayed if none. The envelope may be off the local clock by the given tolerance, besides
+    /// the max age of envelopes.
+    fn authenticate(
+        &self,
+        envelope: SignedEnvelope,
+        from: Option<PeerId>,
+        tolerance: Duration,
+        state: &mut EventListenerState,
+    ) {
+        let authenticator = self.bridge.authenticator.clone();
+        let verifier = self.bridge.op_manager.verifier.clone();
+        let now = self.bridge.op_manager.clock.unix_time();
+        state.authenticating.push(
+            async move {
+                let msg = authenticator
+                    .open(envelope, &verifier, now, tolerance)
+                    .await;
+                (msg, from)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Handles a message received from a peer, unless it was sent on behalf of another one.
+    async fn accept_inbound(&self, msg: NetMessage, from: Option<PeerId>) -> EventResult {
+        let own_peer = self
+            .bridge
+            .op_manager
+            .ring
+            .connection_manager
+            .get_peer_key();
+        // relayed messages were opened already, checking their sender is the signer
+        let impersonating = from.is_some()
+            && embedded_sender(&msg).is_some_and(|sender| Some(sender) != from.as_ref());
+        if impersonating || !sent_by_connection_peer(&msg, from.as_ref(), own_peer.as_ref()) {
+            tracing::warn!(?from, %msg, "Discarding message sent on behalf of another peer");
+            self.report_invalid_message(from.as_ref());
+            return EventResult::Continue;
+        }
+        self.event_listener.register_inbound(&msg).await;
+        EventResult::Event(ConnEvent::InboundMessage { msg, from })
+    }
+
     async fn handle_notification_msg(
         &self,
         msg: Option<Either<NetMessage, NodeEvent>>,
     ) -> EventResult {
         match msg {
-            Some(Left(msg)) => EventResult::Event(ConnEvent::InboundMessage(msg)),
+            Some(Left(msg)) => EventResult::Event(ConnEvent::InboundMessage { msg, from: None }),
             Some(Right(action)) => EventResult::Event(ConnEvent::NodeAction(action)),
             None => EventResult::Continue,
         }
@@ -602,7 +1521,11 @@ impl P2pConnManager {
 
     async fn handle_bridge_msg(&self, msg: Option<P2pBridgeEvent>) -> EventResult {
         match msg {
-            Some(Left((_, msg))) => EventResult::Event(ConnEvent::OutboundMessage(*msg)),
+            Some(Left((target, msg, span))) => EventResult::Event(ConnEvent::OutboundMessage {
+                target,
+                msg: *msg,
+                span,
+            }),
             Some(Right(action)) => EventResult::Event(ConnEvent::NodeAction(action)),
             None => EventResult::Event(ConnEvent::ClosedChannel),
         }
@@ -633,7 +1556,7 @@ impl P2pConnManager {
         }) else {
             return EventResult::Continue;
         };
-        state.tx_to_client.insert(transaction, client_id);
+        state.client_tracker.track(transaction, client_id);
         EventResult::Continue
     }
 
@@ -692,20 +1615,35 @@ impl ConnectResultSender for mpsc::Sender<Result<(PeerId, Option<usize>), ()>> {
 struct EventListenerState {
     peer_connections:
         FuturesUnordered<BoxFuture<'static, Result<PeerConnectionInbound, TransportError>>>,
+    /// Sealed messages being opened, with the connected peer they were received from.
+    authenticating: FuturesUnordered<
+        BoxFuture<'static, (Result<NetMessage, AuthenticationError>, Option<PeerId>)>,
+    >,
     pending_from_executor: HashSet<Transaction>,
-    tx_to_client: HashMap<Transaction, ClientId>,
+    client_tracker: ClientTransactionTracker,
     transient_conn: HashMap<Transaction, SocketAddr>,
+    state_transfers: StateTransfers,
     awaiting_connection: HashMap<SocketAddr, Box<dyn ConnectResultSender>>,
+    nat_traversal: NatTraversal,
+    /// Changes in the address of this peer.
+    address_watch: AddressWatch,
+    /// Whether the node is leaving the network.
+    leaving: bool,
 }
 
 impl EventListenerState {
-    fn new() -> Self {
+    fn new(cli_response_sender: ClientResponsesSender, clock: SharedClock) -> Self {
         Self {
             peer_connections: FuturesUnordered::new(),
+            authenticating: FuturesUnordered::new(),
             pending_from_executor: HashSet::new(),
-            tx_to_client: HashMap::new(),
+            client_tracker: ClientTransactionTracker::new(cli_response_sender, clock),
             transient_conn: HashMap::new(),
+            state_transfers: StateTransfers::default(),
             awaiting_connection: HashMap::new(),
+            nat_traversal: NatTraversal::default(),
+            address_watch: AddressWatch::default(),
+            leaving: false,
         }
     }
 }
@@ -717,8 +1655,16 @@ enum EventResult {
 
 #[derive(Debug)]
 enum ConnEvent {
-    InboundMessage(NetMessage),
-    OutboundMessage(NetMessage),
+    InboundMessage {
+        msg: NetMessage,
+        /// Connected peer the message was received from, if not sent by this node.
+        from: Option<PeerId>,
+    },
+    OutboundMessage {
+        target: PeerId,
+        msg: NetMessage,
+        span: tracing::Span,
+    },
     HandshakeAction(HandshakeEvent),
     NodeAction(NodeEvent),
     ClosedChannel,
@@ -736,13 +1682,22 @@ struct PeerConnectionInbound {
     conn: PeerConnection,
     /// Receiver for inbound messages for the peer connection
     rx: Receiver<Either<NetMessage, ConnEvent>>,
-    msg: NetMessage,
+    compression: ConnectionCompression,
+    greeting: Greeting,
+    msg: Result<NetMessage, ConnectionError>,
 }
 
 async fn peer_connection_listener(
     mut rx: PeerConnChannelRecv,
     mut conn: PeerConnection,
+    mut compression: ConnectionCompression,
+    mut greeting: Greeting,
+    addresses: AddressBook,
+    clock_skews: ClockSkews,
+    peer_features: PeerFeatures,
+    clock: SharedClock,
 ) -> Result<PeerConnectionInbound, TransportError> {
+    let now_ms = || clock.unix_time().as_millis() as u64;
     loop {
         tokio::select! {
             msg = rx.recv() => {
@@ -750,6 +1705,16 @@ async fn peer_connection_listener(
                 match msg {
                     Left(msg) => {
                         tracing::debug!(to=%conn.remote_addr() ,"Sending message to peer. Msg: {msg}");
+                        let msg = if greeting.peer_supports(Feature::SealedMessages) {
+                            msg
+                        } else {
+                            unsealed(msg)
+                        };
+                        if greeting.is_legacy() && !understood_by_legacy_peers(&msg) {
+                            tracing::debug!(to=%conn.remote_addr(), %msg, "Not sending message unknown to a legacy peer");
+                            continue;
+                        }
+                        let msg = compression.compress(msg).await;
                         conn
                             .send(msg)
                             .await?;
@@ -773,17 +1738,241 @@ async fn peer_connection_listener(
                 }) else {
                      break Err(TransportError::ConnectionClosed(conn.remote_addr()));
                 };
-                let net_message = decode_msg(&msg).unwrap();
-                tracing::debug!(from=%conn.remote_addr() ,"Received message from peer. Msg: {net_message}");
-                break Ok(PeerConnectionInbound { conn, rx, msg: net_message });
+                // greeted once the peer sends something rather than on connecting, so the greeting
+                // does not reach a peer still going through the handshake
+                if let Some(hello) = greeting.greet() {
+                    conn.send_frame(hello).await?;
+                }
+                if let Some(hello) = Hello::from_frame(&msg) {
+                    if let Err(error) = greeting.greeted(&hello) {
+                        tracing::error!(from=%conn.remote_addr(), %error, "Incompatible peer, closing the connection");
+                        break Err(TransportError::ConnectionClosed(conn.remote_addr()));
+                    }
+                    tracing::debug!(from=%conn.remote_addr(), protocol=%hello.protocol, role=?hello.role, "Peer greeted");
+                    peer_features.greeted(conn.remote_addr(), &hello);
+                    // peers which greet first are answered, whether they said they greet or not
+                    if let Some(hello) = greeting.greet() {
+                        conn.send_frame(hello).await?;
+                    }
+                    if hello.supports(Feature::Compression) {
+                        if let Some(offer) = compression.offer() {
+                            conn.send(offer).await?;
+                        }
+                    }
+                    // peers predating dual stack support fail decoding advertisements
+                    if hello.supports(Feature::DualStack) {
+                        if let Some(addrs) = addresses.advertisement_for(conn.remote_addr()) {
+                            conn.send(NetMessage::V1(NetMessageV1::AddressAdvertisement {
+                                transaction: Transaction::new::<ConnectMsg>(),
+                                addrs,
+                            }))
+                            .await?;
+                        }
+                    }
+                    // peers predating load hints fail decoding keep-alives carrying them
+                    if hello.supports(Feature::LoadHints) {
+                        conn.send_load_hints();
+                    }
+                    if hello.supports(Feature::ClockSync) {
+                        let sent_at = now_ms();
+                        clock_skews.probed(conn.remote_addr(), sent_at);
+                        conn.send(NetMessage::V1(NetMessageV1::ClockProbe {
+                            transaction: Transaction::new::<ConnectMsg>(),
+                            sent_at,
+                        }))
+                        .await?;
+                    }
+                    continue;
+                }
+                let net_message = match decode_msg(&msg) {
+                    Ok(NetMessage::V1(NetMessageV1::CompressionOffer { codecs, .. })) => {
+                        tracing::debug!(from=%conn.remote_addr(), ?codecs, "Peer offered compression");
+                        compression.accept_offer(&codecs);
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::AddressProbe { transaction })) => {
+                        conn.send(NetMessage::V1(NetMessageV1::ObservedAddress {
+                            transaction,
+                            addr: conn.remote_addr(),
+                        }))
+                        .await?;
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::ClockProbe { transaction, sent_at })) => {
+                        conn.send(NetMessage::V1(NetMessageV1::ClockReply {
+                            transaction,
+                            sent_at,
+                            replied_at: now_ms(),
+                        }))
+                        .await?;
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::ClockReply { sent_at, replied_at, .. })) => {
+                        clock_skews.record(conn.remote_addr(), sent_at, replied_at, now_ms());
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::AddressAdvertisement { addrs, .. })) => {
+                        tracing::debug!(from=%conn.remote_addr(), ?addrs, "Peer advertised its addresses");
+                        // keyed by the address of this connection, so a peer can only advertise
+                        // its own addresses, and only used once it answers from the new one
+                        if let Some((alternative, nonce)) = addresses.advertised(conn.remote_addr(), &addrs) {
+                            conn.probe_address(alternative, NetMessage::V1(NetMessageV1::ReachabilityProbe {
+                                transaction: Transaction::new::<ConnectMsg>(),
+                                nonce,
+                            }))
+                            .await?;
+                        }
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::ReachabilityProbe { transaction, nonce })) => {
+                        conn.send(NetMessage::V1(NetMessageV1::ReachabilityAck { transaction, nonce }))
+                            .await?;
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::ReachabilityAck { nonce, .. })) => {
+                        addresses.confirmed(conn.remote_addr(), nonce);
+                        continue;
+                    }
+                    Ok(NetMessage::V1(NetMessageV1::Compressed { codec, payload, .. })) => {
+                        decompress(codec, payload).await
+                    }
+                    other => other,
+                };
+                if let Ok(net_message) = &net_message {
+                    if matches!(net_message, NetMessage::V1(NetMessageV1::Authenticated(_))) {
+                        greeting.peer_sealed();
+                    }
+                    tracing::debug!(from=%conn.remote_addr() ,"Received message from peer. Msg: {net_message}");
+                }
+                break Ok(PeerConnectionInbound { conn, rx, compression, greeting, msg: net_message });
             }
         }
     }
 }
 
+/// Whether a message about the peer which sent it was received over the connection with that
+/// same peer, so no peer can act on behalf of another one.
+///
+/// Pings are only answered if meant for this peer, relaying them would let a peer direct the
+/// pongs at a third one.
+fn sent_by_connection_peer(
+    msg: &NetMessage,
+    conn_peer: Option<&PeerId>,
+    own: Option<&PeerId>,
+) -> bool {
+    let from = match msg {
+        NetMessage::V1(NetMessageV1::Ping { target, .. }) if Some(target) != own => return false,
+        NetMessage::V1(NetMessageV1::Leaving { from, .. })
+        | NetMessage::V1(NetMessageV1::Unsubscribed { from, .. })
+        | NetMessage::V1(NetMessageV1::Relocated { from, .. })
+        | NetMessage::V1(NetMessageV1::NonCaching { from, .. })
+        | NetMessage::V1(NetMessageV1::Ping { from, .. })
+        | NetMessage::V1(NetMessageV1::Pong { from, .. })
+        | NetMessage::V1(NetMessageV1::PunchIntroduction { via: from, .. }) => from,
+        NetMessage::V1(NetMessageV1::PunchRequest { from, .. }) => &from.peer,
+        _ => return true,
+    };
+    conn_peer == Some(from)
+}
+
 #[inline(always)]
 fn decode_msg(data: &[u8]) -> Result<NetMessage, ConnectionError> {
     bincode::deserialize(data).map_err(|err| ConnectionError::Serialization(Some(err)))
 }
 
 // TODO: add testing for the network loop, now it should be possible to do since we don't depend upon having real connections
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_messages_on_behalf_of_other_peers() {
+        let (sender, other) = (PeerId::random(), PeerId::random());
+        let leaving = |from: &PeerId| {
+            NetMessage::V1(NetMessageV1::Leaving {
+                transaction: Transaction::new::<ConnectMsg>(),
+                from: from.clone(),
+            })
+        };
+        assert!(sent_by_connection_peer(
+            &leaving(&sender),
+            Some(&sender),
+            None
+        ));
+        assert!(!sent_by_connection_peer(
+            &leaving(&other),
+            Some(&sender),
+            None
+        ));
+        // not yet known as a connected peer
+        assert!(!sent_by_connection_peer(&leaving(&sender), None, None));
+
+        let relocated = NetMessage::V1(NetMessageV1::Relocated {
+            transaction: Transaction::new::<ConnectMsg>(),
+            from: other.clone(),
+            location: crate::ring::Location::random(),
+        });
+        assert!(!sent_by_connection_peer(&relocated, Some(&sender), None));
+
+        let unsubscribed = NetMessage::V1(NetMessageV1::Unsubscribed {
+            transaction: Transaction::new::<ConnectMsg>(),
+            key: freenet_stdlib::prelude::ContractKey::from(
+                freenet_stdlib::prelude::ContractInstanceId::new([1; 32]),
+            ),
+            from: other.clone(),
+        });
+        assert!(!sent_by_connection_peer(&unsubscribed, Some(&sender), None));
+
+        let non_caching = NetMessage::V1(NetMessageV1::NonCaching {
+            transaction: Transaction::new::<ConnectMsg>(),
+            from: other.clone(),
+        });
+        assert!(!sent_by_connection_peer(&non_caching, Some(&sender), None));
+
+        let own = PeerId::random();
+        let ping = |target: &PeerId, from: &PeerId| {
+            NetMessage::V1(NetMessageV1::Ping {
+                transaction: Transaction::new::<ConnectMsg>(),
+                target: target.clone(),
+                from: from.clone(),
+                nonce: 1,
+            })
+        };
+        assert!(sent_by_connection_peer(
+            &ping(&own, &sender),
+            Some(&sender),
+            Some(&own)
+        ));
+        // pongs would be sent to the peer named as the sender
+        assert!(!sent_by_connection_peer(
+            &ping(&own, &other),
+            Some(&sender),
+            Some(&own)
+        ));
+        // pings are not relayed to other peers
+        assert!(!sent_by_connection_peer(
+            &ping(&other, &sender),
+            Some(&sender),
+            Some(&own)
+        ));
+
+        // introductions are only taken from the gateway brokering them
+        let introduction = NetMessage::V1(NetMessageV1::PunchIntroduction {
+            transaction: Transaction::new::<ConnectMsg>(),
+            target: own.clone(),
+            peer: PeerKeyLocation::random(),
+            via: other.clone(),
+        });
+        assert!(!sent_by_connection_peer(
+            &introduction,
+            Some(&sender),
+            Some(&own)
+        ));
+        assert!(sent_by_connection_peer(
+            &introduction,
+            Some(&other),
+            Some(&own)
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+//! Policing of the messages received from peers, so a single peer can't flood this node.
+//!
+//! Each connection has a token bucket per transaction type, and messages received while the
+//! bucket of their type is empty are dropped. Dropped messages count as violations; a peer
+//! piling up too many violations within a short window is disconnected and banned, and the
+//! ban list escalates repeated bans to a permanent one.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::TransactionType,
+    node::{op_dispatcher::TX_TYPES, PeerId},
+    ring::{Ban, BanList},
+};
+
+/// Token bucket parameters of the messages of a transaction type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageQuota {
+    /// Max number of messages admitted in a burst.
+    pub burst: u32,
+    /// Time it takes to recover one message of the burst.
+    pub refill_every: Duration,
+}
+
+impl MessageQuota {
+    pub const fn new(burst: u32, refill_every: Duration) -> Self {
+        Self {
+            burst,
+            refill_every,
+        }
+    }
+}
+
+/// Configuration of the policing of the messages received from peers.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MessagePolicingConfig {
+    quotas: [MessageQuota; TX_TYPES.len()],
+    /// Messages dropped within the violation window after which the peer is banned.
+    pub violations_to_ban: u32,
+    pub violation_window: Duration,
+    /// Time a peer stays banned the first times it is banned.
+    pub ban_duration: Duration,
+    /// Temporary bans after which a peer is banned permanently.
+    pub bans_to_permanent: u32,
+}
+
+impl MessagePolicingConfig {
+    /// Sets the quota of the messages of the given transaction type received from each peer.
+    pub fn set_quota(&mut self, tx_type: TransactionType, quota: MessageQuota) {
+        self.quotas[tx_type as usize] = quota;
+    }
+
+    pub fn quota(&self, tx_type: TransactionType) -> MessageQuota {
+        self.quotas[tx_type as usize]
+    }
+
+    pub(crate) fn quotas(&self) -> impl Iterator<Item = (TransactionType, MessageQuota)> + '_ {
+        TX_TYPES
+            .into_iter()
+            .map(|tx_type| (tx_type, self.quota(tx_type)))
+    }
+}
+
+impl Default for MessagePolicingConfig {
+    fn default() -> Self {
+        let mut config = Self {
+            quotas: [MessageQuota::new(200, Duration::from_millis(10)); TX_TYPES.len()],
+            violations_to_ban: 100,
+            violation_window: Duration::from_secs(10),
+            ban_duration: Duration::from_secs(60 * 60),
+            bans_to_permanent: 3,
+        };
+        // topology maintenance is far less chatty than contract operations
+        config.set_quota(
+            TransactionType::Connect,
+            MessageQuota::new(20, Duration::from_millis(100)),
+        );
+        config.set_quota(
+            TransactionType::Probe,
+            MessageQuota::new(10, Duration::from_millis(500)),
+        );
+        config
+    }
+}
+
+/// Totals of the policing of this node since it started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PolicingStats {
+    pub throttled: u64,
+    pub temporary_bans: u64,
+    pub permanent_bans: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PolicingCounters {
+    throttled: AtomicU64,
+    temporary_bans: AtomicU64,
+    permanent_bans: AtomicU64,
+}
+
+impl PolicingCounters {
+    pub fn snapshot(&self) -> PolicingStats {
+        PolicingStats {
+            throttled: self.throttled.load(Ordering::Relaxed),
+            temporary_bans: self.temporary_bans.load(Ordering::Relaxed),
+            permanent_bans: self.permanent_bans.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What to do with a message received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Policed {
+    Accepted,
+    /// The quota of the message type is exhausted, the message is dropped.
+    Throttled,
+    /// The peer was banned, and the connection to it must be dropped.
+    Banned,
+}
+
+/// Once tracking this many connections, the ones in good standing are forgotten.
+const MAX_TRACKED_CONNECTIONS: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, quota: &MessageQuota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refilled = elapsed.as_secs_f64() / quota.refill_every.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(quota.burst as f64);
+        self.last_refill = now;
+    }
+}
+
+struct Record {
+    buckets: [Bucket; TX_TYPES.len()],
+    violations: u32,
+    window_start: Instant,
+}
+
+pub(super) struct MessagePolicing {
+    config: MessagePolicingConfig,
+    records: HashMap<SocketAddr, Record>,
+    bans: BanList,
+    counters: Arc<PolicingCounters>,
+}
+
+impl MessagePolicing {
+    pub fn new(
+        config: MessagePolicingConfig,
+        bans: BanList,
+        counters: Arc<PolicingCounters>,
+    ) -> Self {
+        Self {
+            config,
+            records: HashMap::new(),
+            bans,
+            counters,
+        }
+    }
+
+    /// Polices a message of the given type received over the connection to the address.
+    ///
+    /// Peers which did not complete the handshake yet have their messages throttled, but
+    /// can't be banned until their identity is known.
+    pub fn police(
+        &mut self,
+        remote: SocketAddr,
+        peer: Option<&PeerId>,
+        tx_type: TransactionType,
+        now: Instant,
+    ) -> Policed {
+        if self.records.len() >= MAX_TRACKED_CONNECTIONS {
+            self.records.retain(|_, record| record.violations > 0);
+        }
+        let config = &self.config;
+        let record = self.records.entry(remote).or_insert_with(|| Record {
+            buckets: TX_TYPES.map(|tx_type| Bucket {
+                tokens: config.quota(tx_type).burst as f64,
+                last_refill: now,
+            }),
+            violations: 0,
+            window_start: now,
+        });
+        let bucket = &mut record.buckets[tx_type as usize];
+        bucket.refill(&config.quota(tx_type), now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Policed::Accepted;
+        }
+
+        self.counters.throttled.fetch_add(1, Ordering::Relaxed);
+        if now.saturating_duration_since(record.window_start) > config.violation_window {
+            record.violations = 0;
+            record.window_start = now;
+        }
+        record.violations += 1;
+        let Some(peer) = peer.filter(|_| record.violations >= config.violations_to_ban) else {
+            tracing::trace!(%remote, %tx_type, "Throttling message");
+            return Policed::Throttled;
+        };
+
+        self.records.remove(&remote);
+        let ban = self.bans.ban(&peer.pub_key, now);
+        let counter = match ban {
+            Ban::Temporary => &self.counters.temporary_bans,
+            Ban::Permanent => &self.counters.permanent_bans,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(%peer, ?ban, "Banning peer flooding this node with messages");
+        Policed::Banned
+    }
+
+    /// Changes the quota of a message type, the buckets of the connections already tracked
+    /// are capped to the new burst the next time they are refilled.
+    pub fn set_quota(&mut self, tx_type: TransactionType, quota: MessageQuota) {
+        tracing::info!(%tx_type, ?quota, "Message quota changed");
+        self.config.set_quota(tx_type, quota);
+    }
+
+    /// Forgets the record of a closed connection.
+    pub fn forget(&mut self, remote: &SocketAddr) {
+        self.records.remove(remote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floods_are_throttled_then_banned() {
+        let mut config = MessagePolicingConfig {
+            violations_to_ban: 2,
+            bans_to_permanent: 1,
+            ..Default::default()
+        };
+        config.set_quota(
+            TransactionType::Get,
+            MessageQuota::new(2, Duration::from_secs(1)),
+        );
+        let counters = Arc::new(PolicingCounters::default());
+        let bans = BanList::in_memory(config.ban_duration, config.bans_to_permanent);
+        let mut policing = MessagePolicing::new(config, bans.clone(), counters.clone());
+        let peer = PeerId::random();
+        let now = Instant::now();
+        let mut police = |tx_type, at| policing.police(peer.addr, Some(&peer), tx_type, at);
+
+        assert_eq!(police(TransactionType::Get, now), Policed::Accepted);
+        assert_eq!(police(TransactionType::Get, now), Policed::Accepted);
+        assert_eq!(police(TransactionType::Get, now), Policed::Throttled);
+        // other message types have their own quota
+        assert_eq!(police(TransactionType::Put, now), Policed::Accepted);
+
+        // violations outside of the window are forgiven
+        let later = now + config.violation_window * 2;
+        assert_eq!(police(TransactionType::Get, later), Policed::Accepted);
+        assert_eq!(police(TransactionType::Get, later), Policed::Accepted);
+        assert_eq!(police(TransactionType::Get, later), Policed::Throttled);
+        assert_eq!(police(TransactionType::Get, later), Policed::Banned);
+        assert!(bans.is_banned(&peer.pub_key, later));
+
+        for _ in 0..4 {
+            police(TransactionType::Get, later);
+        }
+        assert_eq!(
+            counters.snapshot(),
+            PolicingStats {
+                throttled: 5,
+                temporary_bans: 1,
+                permanent_bans: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn quotas_changed_at_runtime() {
+        let config = MessagePolicingConfig::default();
+        let bans = BanList::in_memory(config.ban_duration, config.bans_to_permanent);
+        let mut policing = MessagePolicing::new(config, bans, Arc::default());
+        let peer = PeerId::random();
+        let now = Instant::now();
+        assert_eq!(
+            policing.police(peer.addr, Some(&peer), TransactionType::Get, now),
+            Policed::Accepted
+        );
+
+        // the connections already tracked get the new burst on their next refill
+        policing.set_quota(
+            TransactionType::Get,
+            MessageQuota::new(1, Duration::from_secs(60)),
+        );
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            policing.police(peer.addr, Some(&peer), TransactionType::Get, later),
+            Policed::Accepted
+        );
+        assert_eq!(
+            policing.police(peer.addr, Some(&peer), TransactionType::Get, later),
+            Policed::Throttled
+        );
+    }
+}
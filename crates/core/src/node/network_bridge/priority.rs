@@ -0,0 +1,136 @@
+//! Channels of the messages to send over a peer connection, with control messages going
+//! ahead of bulk transfers.
+//!
+//! Large contract states are sent as many chunks, which used to queue in the same channel as
+//! the small messages driving the operations, holding them back until the whole transfer was
+//! through. Bulk messages now go through a channel of their own, and the listener of the
+//! connection always sends the pending control messages before the next bulk one, so a
+//! transfer only takes the bandwidth the control messages leave. The messages the operations
+//! hand to the event loop to send go through the same kind of channels, so the chunks of a
+//! transfer don't hold back the messages to other peers either.
+
+use either::Either::{self, Left};
+use tokio::sync::mpsc::{self, error::SendError};
+
+use crate::message::{NetMessage, NetMessageV1};
+
+/// Messages larger than this are sent as bulk, once serialized.
+const BULK_THRESHOLD: u64 = 16 * 1024;
+/// Bulk messages waiting to be sent to a single peer.
+const BULK_CAPACITY: usize = 64;
+
+/// Message which may be part of a bulk transfer rather than a control message.
+pub(super) trait Bulk {
+    fn is_bulk(&self) -> bool;
+}
+
+impl Bulk for NetMessage {
+    fn is_bulk(&self) -> bool {
+        match self {
+            NetMessage::V1(NetMessageV1::StateChunk(_)) => true,
+            // sealed messages can only be told apart by their size
+            msg => bincode::serialized_size(msg).is_ok_and(|size| size > BULK_THRESHOLD),
+        }
+    }
+}
+
+/// Channels to the receiver, holding up to `capacity` control messages and events, besides
+/// the bulk messages.
+pub(super) fn channel<M, T>(capacity: usize) -> (PrioritySender<M, T>, PriorityReceiver<M, T>) {
+    let (control, control_rx) = mpsc::channel(capacity);
+    let (bulk, bulk_rx) = mpsc::channel(BULK_CAPACITY);
+    (
+        PrioritySender { control, bulk },
+        PriorityReceiver {
+            control: control_rx,
+            bulk: bulk_rx,
+        },
+    )
+}
+
+pub(super) struct PrioritySender<M, T> {
+    control: mpsc::Sender<Either<M, T>>,
+    bulk: mpsc::Sender<M>,
+}
+
+impl<M, T> Clone for PrioritySender<M, T> {
+    fn clone(&self) -> Self {
+        Self {
+            control: self.control.clone(),
+            bulk: self.bulk.clone(),
+        }
+    }
+}
+
+impl<M: Bulk, T> PrioritySender<M, T> {
+    pub async fn send(&self, msg: Either<M, T>) -> Result<(), SendError<Either<M, T>>> {
+        match msg {
+            Left(msg) if msg.is_bulk() => self
+                .bulk
+                .send(msg)
+                .await
+                .map_err(|SendError(msg)| SendError(Left(msg))),
+            msg => self.control.send(msg).await,
+        }
+    }
+}
+
+pub(super) struct PriorityReceiver<M, T> {
+    control: mpsc::Receiver<Either<M, T>>,
+    bulk: mpsc::Receiver<M>,
+}
+
+impl<M, T> PriorityReceiver<M, T> {
+    /// The next control message or event if any is pending, otherwise the next bulk message.
+    /// Returns `None` once the senders are dropped.
+    pub async fn recv(&mut self) -> Option<Either<M, T>> {
+        tokio::select! {
+            biased;
+            msg = self.control.recv() => msg,
+            Some(msg) = self.bulk.recv() => Some(Left(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use either::Either::Right;
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::{message::Transaction, operations::connect::ConnectMsg};
+
+    #[tokio::test]
+    async fn control_messages_go_ahead_of_bulk() {
+        let (sender, mut receiver) = channel::<NetMessage, ()>(10);
+        let small = NetMessage::V1(NetMessageV1::Aborted(Transaction::new::<ConnectMsg>()));
+        let large = || {
+            NetMessage::V1(NetMessageV1::AddressAdvertisement {
+                transaction: Transaction::new::<ConnectMsg>(),
+                addrs: vec!["127.0.0.1:1".parse().unwrap(); 2048],
+            })
+        };
+        let (first_bulk, second_bulk) = (large(), large());
+        let (first_id, second_id, small_id) = (*first_bulk.id(), *second_bulk.id(), *small.id());
+        assert!(first_bulk.is_bulk() && !small.is_bulk());
+
+        sender.send(Left(first_bulk)).await.unwrap();
+        sender.send(Left(second_bulk)).await.unwrap();
+        sender.send(Left(small)).await.unwrap();
+        sender.send(Right(())).await.unwrap();
+
+        let mut next = || match receiver.recv().now_or_never().flatten() {
+            Some(Left(msg)) => Some(Some(*msg.id())),
+            Some(Right(())) => Some(None),
+            None => None,
+        };
+        assert_eq!(next(), Some(Some(small_id)));
+        assert_eq!(next(), Some(None));
+        assert_eq!(next(), Some(Some(first_id)));
+        assert_eq!(next(), Some(Some(second_id)));
+        assert_eq!(next(), None);
+
+        drop(sender);
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Migration of peer connections across network address changes.
+//!
+//! A peer whose address changed (e.g. after switching networks) issues a notice signed with its
+//! transport key and sends it to each of its neighbours, over a connection which survived the
+//! change or relayed through a gateway. Neighbours verify the notice, relink the connection and
+//! the state tied to the previous address to the new one and re-dial the peer, so it doesn't
+//! have to join the network again.
+
+use std::{net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::PeerId,
+    transport::{Overloaded, SignatureVerifier, TransportKeypair},
+};
+
+/// Max age of a notice for it to be accepted, so old notices cannot be replayed.
+const MAX_NOTICE_AGE: Duration = Duration::from_secs(60);
+
+/// Max time a notice can be issued ahead of the clock of the receiver, allowing for some clock
+/// skew, so notices dated in the future cannot be replayed for longer.
+const MAX_CLOCK_TOLERANCE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ReaddressError {
+    #[error("invalid signature in re-address notice from {0}")]
+    InvalidSignature(PeerId),
+    #[error("re-address notice from {0} expired")]
+    Expired(PeerId),
+    #[error("re-address notice from {0} not verified: {1}")]
+    Overloaded(PeerId, Overloaded),
+}
+
+/// Notice of a peer moving to a new address, signed with the peer transport key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ReaddressNotice {
+    pub previous: PeerId,
+    pub new_addr: SocketAddr,
+    /// Time the notice was issued at, in milliseconds since the unix epoch.
+    issued_at: u64,
+    signature: Vec<u8>,
+}
+
+impl ReaddressNotice {
+    pub fn new(
+        keypair: &TransportKeypair,
+        previous: PeerId,
+        new_addr: SocketAddr,
+        now: Duration,
+    ) -> Self {
+        let issued_at = now.as_millis() as u64;
+        let signature = keypair.sign(&Self::signed_bytes(&previous, new_addr, issued_at));
+        Self {
+            previous,
+            new_addr,
+            issued_at,
+            signature,
+        }
+    }
+
+    /// Verifies the notice was issued by the peer recently enough, returning the new id
+    /// of the peer.
+    pub async fn verify(
+        &self,
+        verifier: &SignatureVerifier,
+        now: Duration,
+    ) -> Result<PeerId, ReaddressError> {
+        let issued_at = Duration::from_millis(self.issued_at);
+        if now.saturating_sub(issued_at) > MAX_NOTICE_AGE || issued_at > now + MAX_CLOCK_TOLERANCE {
+            return Err(ReaddressError::Expired(self.previous.clone()));
+        }
+        let signed = Self::signed_bytes(&self.previous, self.new_addr, self.issued_at);
+        match verifier
+            .verify(&self.previous.pub_key, signed, self.signature.clone())
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return Err(ReaddressError::InvalidSignature(self.previous.clone())),
+            Err(overloaded) => {
+                return Err(ReaddressError::Overloaded(
+                    self.previous.clone(),
+                    overloaded,
+                ))
+            }
+        }
+        Ok(PeerId::new(self.new_addr, self.previous.pub_key.clone()))
+    }
+
+    fn signed_bytes(previous: &PeerId, new_addr: SocketAddr, issued_at: u64) -> Vec<u8> {
+        bincode::serialize(&(previous, new_addr, issued_at)).expect("serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_notice() {
+        let verifier = SignatureVerifier::new(1);
+        let keypair = TransportKeypair::new();
+        let previous = PeerId::new(([10, 0, 0, 1], 4000).into(), keypair.public().clone());
+        let new_addr: SocketAddr = ([192, 168, 1, 7], 4000).into();
+        let now = Duration::from_secs(1_700_000_000);
+
+        let notice = ReaddressNotice::new(&keypair, previous.clone(), new_addr, now);
+        let moved = notice
+            .verify(&verifier, now + Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(moved.addr, new_addr);
+        assert_eq!(moved.pub_key, previous.pub_key);
+
+        assert!(matches!(
+            notice.verify(&verifier, now + MAX_NOTICE_AGE * 2).await,
+            Err(ReaddressError::Expired(_))
+        ));
+
+        // nor are notices dated too far ahead
+        let ahead = ReaddressNotice::new(
+            &keypair,
+            previous.clone(),
+            new_addr,
+            now + MAX_CLOCK_TOLERANCE * 2,
+        );
+        assert!(matches!(
+            ahead.verify(&verifier, now).await,
+            Err(ReaddressError::Expired(_))
+        ));
+        let skewed = ReaddressNotice::new(
+            &keypair,
+            previous.clone(),
+            new_addr,
+            now + MAX_CLOCK_TOLERANCE / 2,
+        );
+        assert!(skewed.verify(&verifier, now).await.is_ok());
+
+        let mut tampered = notice.clone();
+        tampered.new_addr = ([172, 16, 0, 3], 4000).into();
+        assert!(matches!(
+            tampered.verify(&verifier, now).await,
+            Err(ReaddressError::InvalidSignature(_))
+        ));
+
+        // notices signed by some other key are rejected
+        let forged = ReaddressNotice::new(&TransportKeypair::new(), previous, new_addr, now);
+        assert!(matches!(
+            forged.verify(&verifier, now).await,
+            Err(ReaddressError::InvalidSignature(_))
+        ));
+    }
+}
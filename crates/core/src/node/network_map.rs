@@ -0,0 +1,126 @@
+//! Opt-in export of anonymized snapshots of the local view of the network, so the small-world
+//! properties of the ring can be studied in aggregate.
+//!
+//! Snapshots never include peer identities or addresses. Not even the node's own location
+//! is included, since for gateways it is derived from their address; neighbours are only
+//! described by their (rounded) ring distance to this node, and timestamps are truncated
+//! to the hour.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::OpManager;
+use crate::{ring::Location, router::RoutingPriors};
+
+/// Version of the snapshot schema, bumped on incompatible changes.
+const SCHEMA_VERSION: u32 = 1;
+
+const EXPORT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Granularity of the exported timestamps.
+const TIME_GRANULARITY: Duration = Duration::from_secs(60 * 60);
+
+/// Distances are rounded to this many decimals.
+const DISTANCE_DECIMALS: i32 = 4;
+
+/// Distances at which the routing estimation curves are sampled.
+const SAMPLED_DISTANCES: [f64; 11] = [0.0, 0.05, 0.1, 0.15, 0.2, 0.25, 0.3, 0.35, 0.4, 0.45, 0.5];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct NetworkMapSnapshot {
+    pub schema_version: u32,
+    /// Start of the hour the snapshot was taken in, in seconds since the unix epoch.
+    pub hour: u64,
+    pub is_gateway: bool,
+    /// Ring distance to each of the neighbours, in ascending order.
+    pub neighbour_distances: Vec<f64>,
+    /// Routing estimations built from the requests routed by this node, if enough of them
+    /// have been routed yet.
+    pub routing: Option<RoutingPriors>,
+}
+
+impl NetworkMapSnapshot {
+    fn new(
+        now: Duration,
+        is_gateway: bool,
+        own_location: Option<Location>,
+        neighbours: impl IntoIterator<Item = Location>,
+        routing: Option<RoutingPriors>,
+    ) -> Self {
+        let granularity = TIME_GRANULARITY.as_secs();
+        let scale = 10f64.powi(DISTANCE_DECIMALS);
+        let mut neighbour_distances: Vec<_> = own_location
+            .map(|own| {
+                neighbours
+                    .into_iter()
+                    .map(|loc| (own.distance(loc).as_f64() * scale).round() / scale)
+                    .collect()
+            })
+            .unwrap_or_default();
+        neighbour_distances.sort_by(f64::total_cmp);
+        Self {
+            schema_version: SCHEMA_VERSION,
+            hour: now.as_secs() / granularity * granularity,
+            is_gateway,
+            neighbour_distances,
+            routing,
+        }
+    }
+}
+
+/// Periodically writes a snapshot of the network map to the given file, replacing the
+/// previous one.
+pub(crate) async fn export_network_map(op_manager: Arc<OpManager>, path: PathBuf) {
+    loop {
+        op_manager.clock.sleep(EXPORT_INTERVAL).await;
+        let ring = &op_manager.ring;
+        let snapshot = NetworkMapSnapshot::new(
+            op_manager.clock.unix_time(),
+            ring.is_gateway(),
+            ring.connection_manager.own_location().location,
+            ring.connection_manager.neighbour_locations(),
+            ring.router.read().local_estimates(&SAMPLED_DISTANCES),
+        );
+        if let Err(error) = write_snapshot(&path, &snapshot) {
+            tracing::warn!(path = %path.display(), %error, "Failed exporting network map");
+        }
+    }
+}
+
+fn write_snapshot(path: &Path, snapshot: &NetworkMapSnapshot) -> anyhow::Result<()> {
+    // write to a temporary file first so readers never see a partial snapshot
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(snapshot)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_is_anonymized() {
+        let now = Duration::from_secs(3 * 3600 + 1234);
+        let neighbours = [
+            Location::new(0.9),
+            Location::new(0.3),
+            Location::new(0.123456),
+        ];
+        let snapshot =
+            NetworkMapSnapshot::new(now, false, Some(Location::new(0.1)), neighbours, None);
+        assert_eq!(snapshot.hour, 3 * 3600);
+        assert_eq!(snapshot.neighbour_distances, [0.0235, 0.2, 0.2]);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: NetworkMapSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.neighbour_distances, snapshot.neighbour_distances);
+        // only distances make it to the snapshot, not the locations themselves
+        assert!(!json.contains("0.9") && !json.contains("0.3"));
+    }
+}
@@ -0,0 +1,256 @@
+//! Prioritized dispatching of inbound operation messages.
+//!
+//! Messages are queued in a lane per transaction type and a bounded number of them are
+//! processed at a time. When several lanes have messages waiting, lanes are picked in
+//! proportion to their weights, so under load user facing operations (get, put...) are
+//! processed ahead of background topology maintenance (connect, probe) without starving it.
+//! Each lane holds a bounded number of messages, further ones of its type are dropped until it
+//! drains, so a flood of one type of message neither exhausts the memory nor holds up the rest.
+//!
+//! Control messages (liveness checks, departures, refusals...) and the messages of operations
+//! already in flight skip the lanes: they keep live peers and ongoing operations from being
+//! dropped under load, and they don't add new work.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, Semaphore};
+
+use crate::{config::GlobalExecutor, message::TransactionType};
+
+/// Max number of messages being processed at the same time.
+const PROCESSING_SLOTS: usize = 64;
+/// Max number of messages waiting to be processed per transaction type.
+const LANE_CAPACITY: usize = 1024;
+
+pub(super) const TX_TYPES: [TransactionType; 6] = [
+    TransactionType::Connect,
+    TransactionType::Put,
+    TransactionType::Get,
+    TransactionType::Subscribe,
+    TransactionType::Update,
+    TransactionType::Probe,
+];
+
+/// Relative weight of each transaction type when picking the next message to process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct OpPriorities([u32; TX_TYPES.len()]);
+
+impl OpPriorities {
+    pub fn set(&mut self, tx_type: TransactionType, weight: u32) {
+        self.0[tx_type as usize] = weight;
+    }
+
+    pub fn get(&self, tx_type: TransactionType) -> u32 {
+        self.0[tx_type as usize]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TransactionType, u32)> + '_ {
+        TX_TYPES
+            .into_iter()
+            .map(|tx_type| (tx_type, self.get(tx_type)))
+    }
+}
+
+impl Default for OpPriorities {
+    fn default() -> Self {
+        let mut priorities = Self([1; TX_TYPES.len()]);
+        priorities.set(TransactionType::Get, 8);
+        priorities.set(TransactionType::Put, 8);
+        priorities.set(TransactionType::Update, 4);
+        priorities.set(TransactionType::Subscribe, 4);
+        priorities
+    }
+}
+
+/// Queues of pending items per transaction type, popped in weighted round robin order.
+struct PriorityLanes<T> {
+    lanes: [VecDeque<T>; TX_TYPES.len()],
+    /// Running credit of each lane, the non-empty lane with the most credit is picked next.
+    credit: [i64; TX_TYPES.len()],
+    priorities: OpPriorities,
+}
+
+impl<T> PriorityLanes<T> {
+    fn new(priorities: OpPriorities) -> Self {
+        Self {
+            lanes: Default::default(),
+            credit: [0; TX_TYPES.len()],
+            priorities,
+        }
+    }
+
+    /// Queues the item, giving it back if its lane is full.
+    fn push(&mut self, tx_type: TransactionType, item: T) -> Result<(), T> {
+        let lane = &mut self.lanes[tx_type as usize];
+        if lane.len() >= LANE_CAPACITY {
+            return Err(item);
+        }
+        lane.push_back(item);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let mut total = 0;
+        let mut next = None;
+        for (idx, tx_type) in TX_TYPES.into_iter().enumerate() {
+            if self.lanes[idx].is_empty() {
+                continue;
+            }
+            let weight = self.priorities.get(tx_type) as i64;
+            self.credit[idx] += weight;
+            total += weight;
+            if next.map_or(true, |n: usize| self.credit[idx] > self.credit[n]) {
+                next = Some(idx);
+            }
+        }
+        let next = next?;
+        self.credit[next] -= total;
+        if self.lanes[next].len() == 1 {
+            // the lane is drained, so it doesn't carry credit over while idle
+            self.credit[next] = 0;
+        }
+        self.lanes[next].pop_front()
+    }
+}
+
+/// Handle to the prioritized queue in front of operation processing.
+pub(crate) struct OpDispatcher {
+    lanes: Arc<Mutex<PriorityLanes<BoxFuture<'static, ()>>>>,
+    pending: Arc<Notify>,
+}
+
+impl OpDispatcher {
+    pub fn new(priorities: OpPriorities) -> Self {
+        let lanes = Arc::new(Mutex::new(PriorityLanes::new(priorities)));
+        let pending = Arc::new(Notify::new());
+        GlobalExecutor::spawn(dispatch_task(lanes.clone(), pending.clone()));
+        Self { lanes, pending }
+    }
+
+    /// Queues the processing of a message of the given transaction type, dropping it if too
+    /// many of that type are waiting already.
+    pub fn dispatch(&self, tx_type: TransactionType, job: BoxFuture<'static, ()>) {
+        if self.lanes.lock().push(tx_type, job).is_err() {
+            tracing::warn!(%tx_type, "Too many messages waiting to be processed, dropping message");
+            return;
+        }
+        self.pending.notify_one();
+    }
+
+    /// Processes a message right away, without waiting in the lanes nor taking a processing
+    /// slot.
+    pub fn dispatch_now(&self, job: BoxFuture<'static, ()>) {
+        GlobalExecutor::spawn(job);
+    }
+}
+
+async fn dispatch_task(
+    lanes: Arc<Mutex<PriorityLanes<BoxFuture<'static, ()>>>>,
+    pending: Arc<Notify>,
+) {
+    let slots = Arc::new(Semaphore::new(PROCESSING_SLOTS));
+    loop {
+        let Ok(slot) = slots.clone().acquire_owned().await else {
+            break;
+        };
+        let job = loop {
+            if let Some(job) = lanes.lock().pop() {
+                break job;
+            }
+            pending.notified().await;
+        };
+        GlobalExecutor::spawn(async move {
+            job.await;
+            drop(slot);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_lanes() {
+        let mut lanes = PriorityLanes::new(OpPriorities::default());
+        for i in 0..20 {
+            assert!(lanes
+                .push(TransactionType::Connect, (TransactionType::Connect, i))
+                .is_ok());
+            assert!(lanes
+                .push(TransactionType::Get, (TransactionType::Get, i))
+                .is_ok());
+        }
+
+        // gets go ahead of connects, in proportion to their weights
+        let first: Vec<_> = (0..9).map(|_| lanes.pop().unwrap().0).collect();
+        let connects = first
+            .iter()
+            .filter(|ty| **ty == TransactionType::Connect)
+            .count();
+        assert_eq!(connects, 1);
+
+        // connects are not starved, and each lane keeps its order
+        let mut rest = vec![];
+        while let Some(item) = lanes.pop() {
+            rest.push(item);
+        }
+        assert_eq!(rest.len(), 31);
+        let connects: Vec<_> = rest
+            .iter()
+            .filter(|(ty, _)| *ty == TransactionType::Connect)
+            .map(|(_, i)| *i)
+            .collect();
+        assert_eq!(connects, (1..20).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn messages_skipping_the_lanes_are_never_dropped() {
+        let dispatcher = OpDispatcher::new(OpPriorities::default());
+        // hold all the processing slots and fill the connect lane
+        let blocked = Arc::new(Notify::new());
+        for _ in 0..PROCESSING_SLOTS + LANE_CAPACITY {
+            let blocked = blocked.clone();
+            dispatcher.dispatch(
+                TransactionType::Connect,
+                Box::pin(async move { blocked.notified().await }),
+            );
+        }
+        let (processed, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for i in 0..10 {
+            let processed = processed.clone();
+            dispatcher.dispatch_now(Box::pin(async move {
+                processed.send(i).unwrap();
+            }));
+        }
+        let mut received = vec![];
+        for _ in 0..10 {
+            let i = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv())
+                .await
+                .expect("processed while the lanes are full");
+            received.extend(i);
+        }
+        received.sort();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn full_lanes_reject_items() {
+        let mut lanes = PriorityLanes::new(OpPriorities::default());
+        for i in 0..LANE_CAPACITY {
+            assert!(lanes.push(TransactionType::Probe, i).is_ok());
+        }
+        assert_eq!(
+            lanes.push(TransactionType::Probe, LANE_CAPACITY),
+            Err(LANE_CAPACITY)
+        );
+        // other lanes still take items
+        assert!(lanes.push(TransactionType::Get, 0).is_ok());
+        lanes.pop();
+        lanes.pop();
+        assert!(lanes.push(TransactionType::Probe, LANE_CAPACITY).is_ok());
+    }
+}
@@ -0,0 +1,318 @@
+//! Counters and latency histograms for the operations handled by this node.
+//!
+//! Every operation is accounted for once at the node where it is first seen: either when it
+//! is started locally or when the first message of a remote operation arrives. Latencies are
+//! measured from the creation time of the transaction, so for operations relayed by this
+//! node they include the time spent upstream.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::message::{Transaction, TransactionType};
+
+/// Upper bounds of the latency histogram buckets, in milliseconds. Latencies above the last
+/// bound are accounted in an extra overflow bucket.
+const LATENCY_BUCKETS_MS: [u64; 10] = [10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000];
+
+const NUM_TX_TYPES: usize = 6;
+
+#[derive(Default)]
+struct TypeCounters {
+    started: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    latency: LatencyCounters,
+}
+
+impl TypeCounters {
+    fn snapshot(&self) -> OpMetrics {
+        OpMetrics {
+            started: self.started.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+/// Index of the latency histogram bucket the latency is accounted in.
+fn latency_bucket(elapsed: Duration) -> usize {
+    let elapsed_ms = elapsed.as_millis() as u64;
+    LATENCY_BUCKETS_MS
+        .iter()
+        .position(|bound| elapsed_ms <= *bound)
+        .unwrap_or(LATENCY_BUCKETS_MS.len())
+}
+
+#[derive(Default)]
+struct LatencyCounters {
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    /// Sum of all the latencies recorded, in microseconds.
+    sum_us: AtomicU64,
+}
+
+impl LatencyCounters {
+    fn record(&self, elapsed: Duration) {
+        self.buckets[latency_bucket(elapsed)].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(
+            u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        let buckets = LATENCY_BUCKETS_MS
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .chain(std::iter::once(Duration::MAX))
+            .zip(self.buckets.iter().map(|c| c.load(Ordering::Relaxed)))
+            .collect();
+        LatencyHistogram {
+            buckets,
+            sum: Duration::from_micros(self.sum_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Live counters updated by the operation manager.
+#[derive(Default)]
+pub(crate) struct OpCounters {
+    per_type: [TypeCounters; NUM_TX_TYPES],
+}
+
+impl OpCounters {
+    fn counters(&self, tx: &Transaction) -> &TypeCounters {
+        &self.per_type[tx.transaction_type() as usize]
+    }
+
+    pub fn started(&self, tx: &Transaction) {
+        self.counters(tx).started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a finished operation, `now` being the time since the unix epoch.
+    pub fn finished(&self, tx: &Transaction, success: bool, now: Duration) {
+        let counters = self.counters(tx);
+        if success {
+            counters.completed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counters.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        let elapsed = now.saturating_sub(Duration::from_millis(tx.timestamp_ms()));
+        counters.latency.record(elapsed);
+    }
+
+    pub fn timed_out(&self, tx: &Transaction) {
+        self.counters(tx).timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> NodeMetrics {
+        let of = |tx_type: TransactionType| self.per_type[tx_type as usize].snapshot();
+        NodeMetrics {
+            connect: of(TransactionType::Connect),
+            put: of(TransactionType::Put),
+            get: of(TransactionType::Get),
+            subscribe: of(TransactionType::Subscribe),
+            update: of(TransactionType::Update),
+            probe: of(TransactionType::Probe),
+        }
+    }
+}
+
+/// Live counters of the requests sent to the contract handler.
+#[derive(Default)]
+pub(crate) struct ContractCounters {
+    requests: AtomicU64,
+    failed: AtomicU64,
+    latency: LatencyCounters,
+    rejected_requests: AtomicU64,
+    dropped_results: AtomicU64,
+    dropped_notifications: AtomicU64,
+    evicted_states: AtomicU64,
+    evicted_bytes: AtomicU64,
+    validations_cached: AtomicU64,
+    validations_run: AtomicU64,
+}
+
+impl ContractCounters {
+    pub fn finished(&self, success: bool, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency.record(elapsed);
+    }
+
+    /// A request of the executor was rejected for the event loop falling behind, or for the
+    /// executor waiting for too many operations already.
+    pub fn request_rejected(&self) {
+        self.rejected_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The result of an operation came back once the executor had given up waiting for it.
+    pub fn result_dropped(&self) {
+        self.dropped_results.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The oldest notifications pending for some clients were dropped for the clients falling
+    /// behind.
+    pub fn notifications_dropped(&self, count: u64) {
+        self.dropped_notifications
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// A contract state was evicted for going over the storage quota.
+    pub fn state_evicted(&self, size: u64) {
+        self.evicted_states.fetch_add(1, Ordering::Relaxed);
+        self.evicted_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// A contract state was validated, either by running the contract or from the result of
+    /// validating the same inputs before.
+    pub fn validated(&self, cached: bool) {
+        if cached {
+            self.validations_cached.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.validations_run.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ContractMetrics {
+        ContractMetrics {
+            requests: self.requests.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            latency: self.latency.snapshot(),
+            rejected_requests: self.rejected_requests.load(Ordering::Relaxed),
+            dropped_results: self.dropped_results.load(Ordering::Relaxed),
+            dropped_notifications: self.dropped_notifications.load(Ordering::Relaxed),
+            evicted_states: self.evicted_states.load(Ordering::Relaxed),
+            evicted_bytes: self.evicted_bytes.load(Ordering::Relaxed),
+            validations_cached: self.validations_cached.load(Ordering::Relaxed),
+            validations_run: self.validations_run.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the requests handled by the contract handler since the node started.
+#[derive(Debug, Clone)]
+pub(crate) struct ContractMetrics {
+    pub requests: u64,
+    pub failed: u64,
+    pub latency: LatencyHistogram,
+    /// Requests of the executor to the event loop rejected for the event loop or the executor
+    /// falling behind.
+    pub rejected_requests: u64,
+    /// Operation results which came back once the executor had given up waiting for them.
+    pub dropped_results: u64,
+    /// Notifications dropped on their way to the clients for the clients falling behind.
+    pub dropped_notifications: u64,
+    /// Contract states evicted for going over the storage quota.
+    pub evicted_states: u64,
+    pub evicted_bytes: u64,
+    /// Contract state validations answered from the results of validating the same inputs.
+    pub validations_cached: u64,
+    /// Contract state validations which ran the contract.
+    pub validations_run: u64,
+}
+
+/// Snapshot of the operation metrics of a node since it started.
+#[derive(Debug, Clone)]
+pub struct NodeMetrics {
+    pub connect: OpMetrics,
+    pub put: OpMetrics,
+    pub get: OpMetrics,
+    pub subscribe: OpMetrics,
+    pub update: OpMetrics,
+    pub probe: OpMetrics,
+}
+
+/// Counters for a single type of operation.
+#[derive(Debug, Clone)]
+pub struct OpMetrics {
+    /// Includes the operations relayed by this node, which may finish elsewhere.
+    pub started: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub timed_out: u64,
+    /// Latency of the finished operations, successful or not.
+    pub latency: LatencyHistogram,
+}
+
+impl OpMetrics {
+    /// Proportion of the operations which ended up completing successfully, out of those
+    /// which already came to an end. `None` if none has finished yet.
+    pub fn success_rate(&self) -> Option<f64> {
+        let finished = self.completed + self.failed + self.timed_out;
+        if finished == 0 {
+            return None;
+        }
+        Some(self.completed as f64 / finished as f64)
+    }
+}
+
+/// Distribution of the latencies of finished operations.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    /// Number of samples per bucket, each bucket identified by its (inclusive) upper bound.
+    pub buckets: Vec<(Duration, u64)>,
+    /// Sum of all the latencies in the histogram.
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|(_, n)| n).sum()
+    }
+
+    /// Upper bound of the bucket containing the given quantile, `Duration::MAX` if it falls
+    /// in the overflow bucket. `None` if there are no samples.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let target = ((count as f64 * q.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut acc = 0;
+        for (bound, n) in &self.buckets {
+            acc += n;
+            if acc >= target {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::get::GetMsg;
+
+    #[test]
+    fn get_success_rate_and_latency() {
+        let counters = OpCounters::default();
+        let txs: Vec<_> = (0..4).map(|_| Transaction::new::<GetMsg>()).collect();
+        for tx in &txs {
+            counters.started(tx);
+        }
+        let created = Duration::from_millis(txs[0].timestamp_ms());
+        counters.finished(&txs[0], true, created + Duration::from_millis(5));
+        counters.finished(&txs[1], true, created + Duration::from_millis(200));
+        counters.finished(&txs[2], false, created + Duration::from_secs(60));
+        counters.timed_out(&txs[3]);
+
+        let metrics = counters.snapshot();
+        assert_eq!(metrics.put.started, 0);
+        let get = metrics.get;
+        assert_eq!(get.started, 4);
+        assert_eq!((get.completed, get.failed, get.timed_out), (2, 1, 1));
+        assert_eq!(get.success_rate(), Some(0.5));
+        assert_eq!(get.latency.count(), 3);
+        assert_eq!(get.latency.sum, Duration::from_millis(60_205));
+        assert_eq!(get.latency.quantile(0.5), Some(Duration::from_millis(250)));
+        assert_eq!(get.latency.quantile(1.0), Some(Duration::MAX));
+    }
+}
@@ -1,7 +1,16 @@
-use std::{cmp::Reverse, collections::BTreeSet, sync::Arc, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use dashmap::{DashMap, DashSet};
 use either::Either;
+use freenet_stdlib::prelude::ContractKey;
 use tracing::Instrument;
 
 use crate::{
@@ -9,13 +18,26 @@ use crate::{
     contract::{ContractError, ContractHandlerChannel, ContractHandlerEvent, SenderHalve},
     message::{MessageStats, NetMessage, NodeEvent, Transaction, TransactionType},
     operations::{
-        connect::ConnectOp, get::GetOp, put::PutOp, subscribe::SubscribeOp, update::UpdateOp,
+        connect::ConnectOp,
+        get::{GetOp, RedundancyMetrics, RedundantGets},
+        probe::ProbeOp,
+        put::PutOp,
+        subscribe::SubscribeOp,
+        update::UpdateOp,
         OpEnum, OpError,
     },
-    ring::{ConnectionManager, LiveTransactionTracker, Ring},
+    ring::{ConnectionManager, LiveTransactionTracker, Location, ReputationSignal, Ring},
+    transport::{LoadHint, QueueDepth, SignatureVerifier, TrafficCounters},
+    util::time_source::SharedClock,
+    wasm_runtime::{Eviction, Retention, RetentionPolicy},
 };
 
-use super::{network_bridge::EventLoopNotificationsSender, NetEventRegister, NodeConfig, PeerId};
+use super::{
+    network_bridge::{policing::PolicingCounters, EventLoopNotificationsSender},
+    op_metrics::{ContractCounters, ContractMetrics, OpCounters},
+    recent_transactions::RecentTransactions,
+    NetEventRegister, NodeConfig, NodeMetrics, OpDispatcher, PeerId,
+};
 
 #[cfg(debug_assertions)]
 macro_rules! check_id_op {
@@ -32,17 +54,78 @@ pub(crate) enum OpNotAvailable {
     Running,
     #[error("operation completed")]
     Completed,
+    #[error("max number of concurrent operations reached")]
+    Busy,
 }
 
-#[derive(Default)]
 struct Ops {
     connect: DashMap<Transaction, ConnectOp>,
     put: DashMap<Transaction, PutOp>,
     get: DashMap<Transaction, GetOp>,
     subscribe: DashMap<Transaction, SubscribeOp>,
     update: DashMap<Transaction, UpdateOp>,
+    probe: DashMap<Transaction, ProbeOp>,
     completed: DashSet<Transaction>,
     under_progress: DashSet<Transaction>,
+    /// Transactions for which a request has been routed through this node.
+    seen_requests: DashSet<Transaction>,
+    /// Persisted digest of the completed transactions, to filter out replays after a restart.
+    recent: RecentTransactions,
+    /// Operations finished at this node since the load hint was last refreshed.
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    /// Lifetime operation metrics, exposed to the node operator.
+    metrics: OpCounters,
+    /// Get requests started at this node which were sent to several peers at once.
+    redundant_gets: RedundantGets,
+}
+
+impl Ops {
+    /// Number of operations of the given type currently being tracked, including the ones
+    /// being processed.
+    fn pending(&self, tx_type: TransactionType) -> usize {
+        let processing = self
+            .under_progress
+            .iter()
+            .filter(|tx| tx.transaction_type() == tx_type)
+            .count();
+        processing
+            + match tx_type {
+                TransactionType::Connect => self.connect.len(),
+                TransactionType::Put => self.put.len(),
+                TransactionType::Get => self.get.len(),
+                TransactionType::Subscribe => self.subscribe.len(),
+                TransactionType::Update => self.update.len(),
+                TransactionType::Probe => self.probe.len(),
+            }
+    }
+
+    /// Summarizes the current load of the node, resetting the operation outcome counters.
+    fn load_hint(&self, max_concurrent_ops: usize) -> LoadHint {
+        let in_progress = [
+            TransactionType::Connect,
+            TransactionType::Put,
+            TransactionType::Get,
+            TransactionType::Subscribe,
+            TransactionType::Update,
+            TransactionType::Probe,
+        ]
+        .into_iter()
+        .map(|tx_type| self.pending(tx_type))
+        .max()
+        .unwrap_or_default();
+        let succeeded = self.succeeded.swap(0, Ordering::AcqRel);
+        let failed = self.failed.swap(0, Ordering::AcqRel);
+        let error_rate = if failed == 0 {
+            0
+        } else {
+            (failed * 100 / (succeeded + failed)) as u8
+        };
+        LoadHint {
+            queue_depth: QueueDepth::from_usage(in_progress, max_concurrent_ops),
+            error_rate,
+        }
+    }
 }
 
 /// Thread safe and friendly data structure to maintain state of the different operations
@@ -53,6 +136,23 @@ pub(crate) struct OpManager {
     to_event_listener: EventLoopNotificationsSender,
     pub ch_outbound: ContractHandlerChannel<SenderHalve>,
     new_transactions: tokio::sync::mpsc::Sender<Transaction>,
+    /// Max number of concurrent operations per transaction type.
+    max_concurrent_ops: usize,
+    /// Size of the updated states above which only their summary is broadcast.
+    pub update_summary_threshold: usize,
+    /// Number of peers the get requests started at this node are sent to at once.
+    pub get_parallelism: usize,
+    /// Clock all the timeout logic of the node is based on.
+    pub clock: SharedClock,
+    /// Prioritized queue in front of the processing of inbound messages.
+    pub dispatcher: OpDispatcher,
+    /// Pool verifying the signatures of inbound messages off the executor threads.
+    pub verifier: SignatureVerifier,
+    /// Traffic over the transport sockets of this node.
+    pub traffic: Arc<TrafficCounters>,
+    /// Messages throttled and peers banned by the policing of inbound messages.
+    pub policing: Arc<PolicingCounters>,
+    pub contract_metrics: ContractCounters,
 }
 
 impl OpManager {
@@ -62,15 +162,39 @@ impl OpManager {
         config: &NodeConfig,
         event_register: ER,
         connection_manager: ConnectionManager,
+        clock: SharedClock,
     ) -> anyhow::Result<Self> {
         let ring = Ring::new(
             config,
             notification_channel.clone(),
             event_register.clone(),
-            config.is_gateway,
             connection_manager,
         )?;
-        let ops = Arc::new(Ops::default());
+        let ops = Arc::new(Ops {
+            connect: DashMap::default(),
+            put: DashMap::default(),
+            get: DashMap::default(),
+            subscribe: DashMap::default(),
+            update: DashMap::default(),
+            probe: DashMap::default(),
+            completed: DashSet::default(),
+            under_progress: DashSet::default(),
+            seen_requests: DashSet::default(),
+            recent: RecentTransactions::load(&config.config.db_dir(), clock.unix_time()),
+            succeeded: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            metrics: OpCounters::default(),
+            redundant_gets: RedundantGets::default(),
+        });
+        let max_concurrent_ops = config
+            .max_concurrent_ops
+            .unwrap_or(crate::config::DEFAULT_MAX_CONCURRENT_OPS);
+        let get_parallelism = config
+            .get_parallelism
+            .unwrap_or(crate::config::DEFAULT_GET_PARALLELISM);
+        let update_summary_threshold = config
+            .update_summary_threshold
+            .unwrap_or(crate::config::DEFAULT_UPDATE_SUMMARY_THRESHOLD);
 
         let (new_transactions, rx) = tokio::sync::mpsc::channel(100);
         let current_span = tracing::Span::current();
@@ -83,7 +207,11 @@ impl OpManager {
             garbage_cleanup_task(
                 rx,
                 ops.clone(),
+                notification_channel.clone(),
                 ring.live_tx_tracker.clone(),
+                ring.connection_manager.clone(),
+                max_concurrent_ops,
+                clock.clone(),
                 event_register,
             )
             .instrument(garbage_span),
@@ -95,9 +223,34 @@ impl OpManager {
             to_event_listener: notification_channel,
             ch_outbound,
             new_transactions,
+            max_concurrent_ops,
+            update_summary_threshold,
+            get_parallelism,
+            clock,
+            dispatcher: OpDispatcher::new(config.op_priorities),
+            verifier: SignatureVerifier::new(crate::config::DEFAULT_VERIFIER_THREADS),
+            traffic: Arc::default(),
+            policing: Arc::default(),
+            contract_metrics: ContractCounters::default(),
         })
     }
 
+    /// Operation manager of a node which is not part of any network, for tests.
+    #[cfg(test)]
+    pub(crate) async fn in_memory(config: &NodeConfig) -> anyhow::Result<Self> {
+        let (_, notification_tx) = super::network_bridge::event_loop_notification_channel();
+        let (ops_ch_channel, _, _) = crate::contract::contract_handler_channel();
+        let clock: SharedClock = Arc::new(crate::util::time_source::TokioClock);
+        Self::new(
+            notification_tx,
+            ops_ch_channel,
+            config,
+            crate::tracing::TestEventListener::new().await,
+            ConnectionManager::new(config, clock.clone()),
+            clock,
+        )
+    }
+
     /// An early, fast path, return for communicating back changes of on-going operations
     /// in the node to the main message handler, without any transmission in the network whatsoever.
     ///
@@ -112,6 +265,15 @@ impl OpManager {
             .map_err(Into::into)
     }
 
+    /// Hands a message which is not tied to the state of any operation at this node to the
+    /// main message handler, e.g. to forward it to the peer it targets.
+    pub async fn notify_message(&self, msg: NetMessage) -> Result<(), OpError> {
+        self.to_event_listener
+            .send(Either::Left(msg))
+            .await
+            .map_err(Into::into)
+    }
+
     // An early, fast path, return for communicating events in the node to the main message handler,
     // without any transmission in the network whatsoever and avoiding any state transition.
     //
@@ -129,15 +291,27 @@ impl OpManager {
         &self,
         msg: ContractHandlerEvent,
     ) -> Result<ContractHandlerEvent, ContractError> {
-        self.ch_outbound.send_to_handler(msg).await
+        let started = self.clock.unix_time();
+        let result = self.ch_outbound.send_to_handler(msg, &*self.clock).await;
+        self.contract_metrics.finished(
+            result.is_ok(),
+            self.clock.unix_time().saturating_sub(started),
+        );
+        result
     }
 
     pub async fn push(&self, id: Transaction, op: OpEnum) -> Result<(), OpError> {
         if let Some(tx) = self.ops.under_progress.remove(&id) {
-            if tx.timed_out() {
+            if tx.timed_out(self.clock.unix_time()) {
                 self.ops.completed.insert(tx);
                 return Ok(());
             }
+        } else if self.at_capacity(id.transaction_type()) {
+            // a brand new operation started at this node, reject it if we are over the limits
+            tracing::warn!(tx = %id, "Max number of concurrent operations reached, rejecting");
+            return Err(OpNotAvailable::Busy.into());
+        } else if !self.has_op(&id) {
+            self.ops.metrics.started(&id);
         }
         self.new_transactions.send(id).await?;
         match op {
@@ -166,6 +340,11 @@ impl OpManager {
                 check_id_op!(id.transaction_type(), TransactionType::Update);
                 self.ops.update.insert(id, op);
             }
+            OpEnum::Probe(op) => {
+                #[cfg(debug_assertions)]
+                check_id_op!(id.transaction_type(), TransactionType::Probe);
+                self.ops.probe.insert(id, op);
+            }
         }
         Ok(())
     }
@@ -175,7 +354,7 @@ impl OpManager {
             return Err(OpNotAvailable::Completed);
         }
         if self.ops.under_progress.contains(id) {
-            if id.timed_out() {
+            if id.timed_out(self.clock.unix_time()) {
                 self.ops.completed.insert(*id);
                 return Err(OpNotAvailable::Completed);
             }
@@ -202,13 +381,134 @@ impl OpManager {
                 .remove(id)
                 .map(|(_k, v)| v)
                 .map(OpEnum::Update),
+            TransactionType::Probe => self
+                .ops
+                .probe
+                .remove(id)
+                .map(|(_k, v)| v)
+                .map(OpEnum::Probe),
         };
+        if op.is_none() && id.timed_out(self.clock.unix_time()) {
+            // a late message for a transaction which already expired across the network
+            tracing::debug!(tx = %id, "Ignoring timed out transaction");
+            self.ops.completed.insert(*id);
+            return Err(OpNotAvailable::Completed);
+        }
+        if op.is_none() && self.ops.recent.contains(id, self.clock.unix_time()) {
+            // a new transaction which was already completed before, probably replayed
+            tracing::debug!(tx = %id, "Ignoring already completed transaction");
+            self.ops.completed.insert(*id);
+            return Err(OpNotAvailable::Completed);
+        }
+        if op.is_none() && self.at_capacity(id.transaction_type()) {
+            // new inbound operation, apply backpressure and drop it, any further
+            // messages for this transaction will be filtered out
+            tracing::warn!(tx = %id, "Max number of concurrent operations reached, rejecting");
+            self.ops.completed.insert(*id);
+            return Err(OpNotAvailable::Busy);
+        }
+        if op.is_none() {
+            self.ops.metrics.started(id);
+        }
         self.ops.under_progress.insert(*id);
         Ok(op)
     }
 
+    /// Whether there is an operation in progress at this node for the transaction, either
+    /// waiting for its next message or being processed.
+    pub fn has_op(&self, id: &Transaction) -> bool {
+        if self.ops.under_progress.contains(id) {
+            return true;
+        }
+        match id.transaction_type() {
+            TransactionType::Connect => self.ops.connect.contains_key(id),
+            TransactionType::Put => self.ops.put.contains_key(id),
+            TransactionType::Get => self.ops.get.contains_key(id),
+            TransactionType::Subscribe => self.ops.subscribe.contains_key(id),
+            TransactionType::Update => self.ops.update.contains_key(id),
+            TransactionType::Probe => self.ops.probe.contains_key(id),
+        }
+    }
+
+    /// Transactions of the operations in progress at this node, waiting for their next
+    /// message or being processed.
+    pub fn open_transactions(&self) -> Vec<Transaction> {
+        let ops = &self.ops;
+        let mut open: BTreeSet<Transaction> =
+            ops.under_progress.iter().map(|tx| *tx.key()).collect();
+        open.extend(ops.connect.iter().map(|op| *op.key()));
+        open.extend(ops.put.iter().map(|op| *op.key()));
+        open.extend(ops.get.iter().map(|op| *op.key()));
+        open.extend(ops.subscribe.iter().map(|op| *op.key()));
+        open.extend(ops.update.iter().map(|op| *op.key()));
+        open.extend(ops.probe.iter().map(|op| *op.key()));
+        open.into_iter().collect()
+    }
+
+    /// Looks for a new connection close to the location, as the connection maintenance does
+    /// when the ring needs one.
+    pub async fn acquire_connection(&self, location: Location) -> anyhow::Result<()> {
+        let Some(own) = self.ring.connection_manager.get_peer_key() else {
+            anyhow::bail!("not joined the network yet");
+        };
+        self.ring
+            .acquire_new(location, &[&own], &self.to_event_listener)
+            .await?;
+        Ok(())
+    }
+
+    /// Whether the max number of concurrent operations for the given type has been reached.
+    fn at_capacity(&self, tx_type: TransactionType) -> bool {
+        self.ops.pending(tx_type) >= self.max_concurrent_ops
+    }
+
+    /// Records that a request for the transaction has been routed through this node.
+    ///
+    /// Returns `false` if the request had already been seen before, e.g. because it came
+    /// back to this node following a cycle in the ring.
+    pub fn first_visit(&self, id: Transaction) -> bool {
+        self.ops.seen_requests.insert(id)
+    }
+
+    /// Records the outcome of an operation finished at this node, reported to the
+    /// connected peers as part of the node load hint.
+    pub fn record_outcome(&self, tx: Option<&Transaction>, success: bool) {
+        let counter = if success {
+            &self.ops.succeeded
+        } else {
+            &self.ops.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(tx) = tx {
+            self.ops
+                .metrics
+                .finished(tx, success, self.clock.unix_time());
+        }
+    }
+
+    /// Snapshot of the metrics of the operations handled by this node.
+    pub fn metrics(&self) -> NodeMetrics {
+        self.ops.metrics.snapshot()
+    }
+
+    /// Get requests started at this node which were sent to several peers at once.
+    pub fn redundant_gets(&self) -> &RedundantGets {
+        &self.ops.redundant_gets
+    }
+
+    /// Snapshot of the get requests this node sent redundantly.
+    pub fn redundancy_metrics(&self) -> RedundancyMetrics {
+        self.ops.redundant_gets.snapshot()
+    }
+
+    /// Snapshot of the metrics of the requests to the contract handler of this node.
+    pub fn contract_metrics(&self) -> ContractMetrics {
+        self.contract_metrics.snapshot()
+    }
+
     pub fn completed(&self, id: Transaction) {
         self.ring.live_tx_tracker.remove_finished_transaction(id);
+        self.ops.recent.insert(&id);
         self.ops.completed.insert(id);
     }
 
@@ -225,15 +525,32 @@ impl OpManager {
     }
 }
 
+impl RetentionPolicy for OpManager {
+    fn retention(&self, key: &ContractKey) -> Retention {
+        Retention {
+            home: self.ring.home_contracts.contains(key),
+            subscribers: self.ring.subscribers_of(key).map_or(0, |subs| subs.len()),
+        }
+    }
+
+    fn evicted(&self, eviction: &Eviction) {
+        self.contract_metrics.state_evicted(eviction.size);
+        self.ring.contract_evicted(&eviction.key, eviction.size);
+    }
+}
+
 async fn garbage_cleanup_task<ER: NetEventRegister>(
     mut new_transactions: tokio::sync::mpsc::Receiver<Transaction>,
     ops: Arc<Ops>,
+    notifications: EventLoopNotificationsSender,
     live_tx_tracker: LiveTransactionTracker,
+    connection_manager: ConnectionManager,
+    max_concurrent_ops: usize,
+    clock: SharedClock,
     mut event_register: ER,
 ) {
     const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
-    let mut tick = tokio::time::interval(CLEANUP_INTERVAL);
-    tick.tick().await;
+    let mut next_cleanup = clock.sleep(CLEANUP_INTERVAL);
 
     let mut ttl_set = BTreeSet::new();
 
@@ -245,7 +562,26 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                     ttl_set.insert(Reverse(tx));
                 }
             }
-            _ = tick.tick() => {
+            _ = &mut next_cleanup => {
+                next_cleanup = clock.sleep(CLEANUP_INTERVAL);
+                let now = clock.unix_time();
+                if let Err(error) = ops.recent.persist(now) {
+                    tracing::warn!(%error, "Failed to persist recent transactions digest");
+                }
+                ops.seen_requests.retain(|tx| !tx.timed_out(now));
+                connection_manager
+                    .load_hints
+                    .set_local(ops.load_hint(max_concurrent_ops));
+                // gets are only sent once the peer key is known
+                if connection_manager.get_peer_key().is_some() {
+                    let own = connection_manager.own_location();
+                    for failure in ops.redundant_gets.stalled(&own, clock.now()) {
+                        if notifications.send(Either::Left(failure)).await.is_err() {
+                            tracing::debug!("Event loop closed, not retrying stalled gets");
+                            break;
+                        }
+                    }
+                }
                 let mut old_missing = std::mem::replace(&mut delayed, Vec::with_capacity(200));
                 for tx in old_missing.drain(..) {
                     if let Some(tx) = ops.completed.remove(&tx) {
@@ -262,12 +598,22 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                         TransactionType::Get => ops.get.remove(&tx).is_none(),
                         TransactionType::Subscribe => ops.subscribe.remove(&tx).is_none(),
                         TransactionType::Update => ops.update.remove(&tx).is_none(),
+                        TransactionType::Probe => ops.probe.remove(&tx).is_none(),
                     };
-                    let timed_out = tx.timed_out();
+                    let timed_out = tx.timed_out(now);
                     if still_waiting && !timed_out {
                         delayed.push(tx);
                     } else {
                         if still_waiting && timed_out {
+                            ops.metrics.timed_out(&tx);
+                            for peer in live_tx_tracker.peers_of(&tx) {
+                                connection_manager
+                                    .peer_reputation
+                                    .report(&peer, ReputationSignal::Timeout, clock.now());
+                                connection_manager
+                                .circuit_breakers
+                                .record_failure(&peer, clock.now());
+                            }
                             ops.under_progress.remove(&tx);
                             ops.completed.remove(&tx);
                         }
@@ -276,12 +622,13 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                 }
 
                 // notice the use of reverse so the older transactions are removed instead of the newer ones
-                let older_than: Reverse<Transaction> = Reverse(Transaction::ttl_transaction());
+                let older_than: Reverse<Transaction> = Reverse(Transaction::ttl_transaction(now));
                 for Reverse(tx) in ttl_set.split_off(&older_than).into_iter() {
                     if ops.under_progress.contains(&tx) {
                         delayed.push(tx);
                         continue;
                     }
+                    ops.redundant_gets.forget(&tx);
                     if let Some(tx) = ops.completed.remove(&tx) {
                         if cfg!(feature = "trace-ot") {
                             event_register.notify_of_time_out(tx).await;
@@ -296,8 +643,18 @@ async fn garbage_cleanup_task<ER: NetEventRegister>(
                         TransactionType::Get => ops.get.remove(&tx).is_some(),
                         TransactionType::Subscribe => ops.subscribe.remove(&tx).is_some(),
                         TransactionType::Update => ops.update.remove(&tx).is_some(),
+                        TransactionType::Probe => ops.probe.remove(&tx).is_some(),
                     };
                     if removed {
+                        ops.metrics.timed_out(&tx);
+                        for peer in live_tx_tracker.peers_of(&tx) {
+                            connection_manager
+                                    .peer_reputation
+                                    .report(&peer, ReputationSignal::Timeout, clock.now());
+                            connection_manager
+                                .circuit_breakers
+                                .record_failure(&peer, clock.now());
+                        }
                         live_tx_tracker.remove_finished_transaction(tx);
                     }
                 }
@@ -19,6 +19,7 @@ use crate::{
     message::NodeEvent,
     node::NodeConfig,
     operations::connect,
+    util::time_source::TokioClock,
 };
 
 use super::OpManager;
@@ -70,13 +71,15 @@ impl NodeP2P {
         let (ch_outbound, ch_inbound, wait_for_event) = contract::contract_handler_channel();
         let (client_responses, cli_response_sender) = contract::client_responses_channel();
 
-        let connection_manager = ConnectionManager::new(&config);
+        let clock = Arc::new(TokioClock);
+        let connection_manager = ConnectionManager::new(&config, clock.clone());
         let op_manager = Arc::new(OpManager::new(
             notification_tx,
             ch_outbound,
             &config,
             event_register.clone(),
             connection_manager,
+            clock,
         )?);
         let (executor_listener, executor_sender) = contract::executor_channel(op_manager.clone());
         let contract_handler = CH::build(ch_inbound, executor_sender, ch_builder)
@@ -87,6 +90,82 @@ impl NodeP2P {
             P2pConnManager::build(&config, op_manager.clone(), event_register).await?;
 
         let parent_span = tracing::Span::current();
+        if let Some(key) = config.routing_priors_contract {
+            GlobalExecutor::spawn(
+                super::load_routing_priors(op_manager.clone(), key)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "routing_priors")),
+            );
+        }
+        if let Some(path) = config.network_map_export.clone() {
+            GlobalExecutor::spawn(
+                super::network_map::export_network_map(op_manager.clone(), path)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "network_map")),
+            );
+        }
+        if let Some(admin) = config.admin_api.clone() {
+            let dump = super::admin_api::config_dump(&config)?;
+            GlobalExecutor::spawn(
+                super::admin_api::serve_admin_api(op_manager.clone(), admin, dump)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "admin_api")),
+            );
+        }
+        GlobalExecutor::spawn(
+            super::config_watch::watch_config(
+                op_manager.clone(),
+                config.config.clone(),
+                conn_manager.bandwidth_limits.clone(),
+            )
+            .instrument(tracing::info_span!(parent: parent_span.clone(), "config_watch")),
+        );
+        if let Some(interval) = config
+            .bootstrap
+            .refresh_interval
+            .filter(|_| !config.bootstrap.sources.is_empty())
+        {
+            GlobalExecutor::spawn(
+                super::bootstrap::refresh_gateways(op_manager.clone(), config.clone(), interval)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "bootstrap")),
+            );
+        }
+        if let Some(recording) = &config.message_replay {
+            let messages = crate::tracing::read_recording(recording)?;
+            tracing::info!(
+                messages = messages.len(),
+                "Loaded recorded messages to replay"
+            );
+            GlobalExecutor::spawn(
+                crate::tracing::replay_into(op_manager.clone(), messages)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "replay")),
+            );
+        }
+        if let Some(port) = config.metrics_port {
+            GlobalExecutor::spawn(
+                super::prometheus::serve_metrics(op_manager.clone(), port)
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "metrics")),
+            );
+        }
+        if let Some(gateway) = op_manager.ring.connection_manager.gateway().cloned() {
+            GlobalExecutor::spawn(
+                gateway
+                    .report_stats(op_manager.clone())
+                    .instrument(tracing::info_span!(parent: parent_span.clone(), "gateway_stats")),
+            );
+        }
+        GlobalExecutor::spawn(
+            super::contract_maintenance(op_manager.clone()).instrument(
+                tracing::info_span!(parent: parent_span.clone(), "contract_maintenance"),
+            ),
+        );
+        GlobalExecutor::spawn(
+            super::state_integrity_checks(op_manager.clone()).instrument(
+                tracing::info_span!(parent: parent_span.clone(), "state_integrity_checks"),
+            ),
+        );
+        GlobalExecutor::spawn(
+            super::home_contracts_sync(op_manager.clone()).instrument(
+                tracing::info_span!(parent: parent_span.clone(), "home_contracts_sync"),
+            ),
+        );
         GlobalExecutor::spawn(
             contract::contract_handling(contract_handler)
                 .instrument(tracing::info_span!(parent: parent_span.clone(), "contract_handling")),
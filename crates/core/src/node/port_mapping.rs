@@ -0,0 +1,754 @@
+//! Mapping of the port of the node on the router of the local network, so peers behind a
+//! home router can be reached without configuring the router by hand.
+//!
+//! The port is mapped over NAT-PMP, asking the default gateway of the host, or over UPnP,
+//! asking the internet gateway device found with SSDP. The external address of the mapping is
+//! advertised to the peers along with the other addresses of the node, again to the connected
+//! ones whenever it changes, and the mapping is renewed halfway through its lease for as long
+//! as the node runs, then removed from the router. IPv6 addresses are not translated, but the
+//! firewall of the router may still drop the packets of peers: over UPnP a pinhole is opened
+//! in it for the IPv6 listener too.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::{net::UdpSocket, sync::watch, task::JoinHandle};
+
+use super::OpManager;
+use crate::{
+    config::GlobalExecutor,
+    message::NodeEvent,
+    transport::{reachable_at, AddressBook},
+};
+
+/// Lease asked for the mapping, which is renewed halfway through it.
+const MAPPING_LEASE: Duration = Duration::from_secs(60 * 60);
+/// Shortest time the mapping is renewed after, however short the lease granted by the router.
+const MIN_RENEW_AFTER: Duration = Duration::from_secs(60);
+/// Time after which mapping the port is attempted again once it failed.
+const RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+/// Time the router has to answer the removal of the mappings once the node stops.
+const UNMAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+const NAT_PMP_PORT: u16 = 5351;
+/// Requests are sent again after this long without an answer, doubling it every time.
+const NAT_PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const NAT_PMP_ATTEMPTS: u32 = 4;
+
+const SSDP_ADDR: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900));
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+const WAN_SERVICES: &[&str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+const PINHOLE_SERVICES: &[&str] = &["urn:schemas-upnp-org:service:WANIPv6FirewallControl:"];
+
+/// Protocols the port of the node is mapped on the router with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortMapping {
+    #[default]
+    Disabled,
+    NatPmp,
+    Upnp,
+    /// NAT-PMP, falling back to UPnP if the gateway does not support it.
+    Auto,
+}
+
+/// Port mapped on the router.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mapping {
+    external: SocketAddr,
+    lease: Duration,
+    /// How the port was mapped, so the mapping is removed the same way.
+    with: MappedWith,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MappedWith {
+    NatPmp {
+        gateway: SocketAddr,
+        internal_port: u16,
+    },
+    Upnp(UpnpService),
+    /// Pinhole in the IPv6 firewall of the router, the port is reached at the same address.
+    UpnpPinhole {
+        service: UpnpService,
+        id: String,
+    },
+}
+
+impl Mapping {
+    fn renew_after(&self) -> Duration {
+        (self.lease / 2).max(MIN_RENEW_AFTER)
+    }
+}
+
+/// Ports of the node mapped on the router, kept while the node runs.
+pub(crate) struct PortMappings {
+    stop: watch::Sender<()>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl PortMappings {
+    /// Maps the ports of the sockets in the address book on the router, recording the
+    /// external address of the IPv4 one in it.
+    pub fn start(
+        protocol: PortMapping,
+        addresses: AddressBook,
+        op_manager: Arc<OpManager>,
+    ) -> Self {
+        let (stop, stopped) = watch::channel(());
+        let bound = addresses.bound();
+        let v4 = bound.iter().find(|addr| addr.is_ipv4());
+        // NAT-PMP has no say over the IPv6 firewall of the router
+        let v6 = bound
+            .iter()
+            .find(|addr| addr.is_ipv6())
+            .filter(|_| matches!(protocol, PortMapping::Upnp | PortMapping::Auto));
+        if v4.is_none() && v6.is_none() {
+            tracing::warn!(
+                ?protocol,
+                "No listener the port can be mapped for, not mapping"
+            );
+        }
+        let tasks = v4
+            .into_iter()
+            .chain(v6)
+            .map(|bound| {
+                GlobalExecutor::spawn(maintain_port_mapping(
+                    protocol,
+                    *bound,
+                    addresses.clone(),
+                    op_manager.clone(),
+                    stopped.clone(),
+                ))
+            })
+            .collect();
+        Self { stop, tasks }
+    }
+
+    /// Removes the mappings from the router, waiting a few seconds at most for it to answer.
+    pub async fn stop(self) {
+        drop(self.stop);
+        if tokio::time::timeout(UNMAP_TIMEOUT, join_all(self.tasks))
+            .await
+            .is_err()
+        {
+            tracing::warn!("Timed out removing the port mappings from the router");
+        }
+    }
+}
+
+/// Maps the port of the socket bound to the given address on the router and keeps the mapping
+/// renewed until stopped, then removes it.
+async fn maintain_port_mapping(
+    protocol: PortMapping,
+    bound: SocketAddr,
+    addresses: AddressBook,
+    op_manager: Arc<OpManager>,
+    mut stopped: watch::Receiver<()>,
+) {
+    let mut mapping = None;
+    loop {
+        let renew_after = match renew(protocol, bound, mapping.as_ref()).await {
+            Ok(renewed) => {
+                tracing::info!(external = %renewed.external, ?protocol, "Port mapped on the router");
+                let renew_after = renewed.renew_after();
+                mapping = Some(renewed);
+                renew_after
+            }
+            Err(error) => {
+                tracing::warn!(%error, ?protocol, %bound, "Failed to map port on the router");
+                mapping = None;
+                RETRY_AFTER
+            }
+        };
+        // peers only know of the external address as it was when they connected
+        if bound.is_ipv4() && addresses.set_mapped(mapping.as_ref().map(|m| m.external)) {
+            let _ = op_manager
+                .notify_node_event(NodeEvent::AdvertiseAddresses)
+                .await;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(renew_after) => {}
+            _ = stopped.changed() => break,
+        }
+    }
+    if let Some(mapping) = mapping {
+        match unmap(&mapping).await {
+            Ok(()) => tracing::info!(external = %mapping.external, "Port mapping removed"),
+            Err(error) => tracing::warn!(%error, "Failed to remove port mapping from the router"),
+        }
+    }
+}
+
+/// Maps the port of the socket bound to the given address, renewing the current mapping if
+/// there is one.
+async fn renew(
+    protocol: PortMapping,
+    bound: SocketAddr,
+    current: Option<&Mapping>,
+) -> anyhow::Result<Mapping> {
+    if bound.is_ipv4() {
+        // asking again for the same port renews the mapping
+        return map_port(protocol, bound.port()).await;
+    }
+    let internal = reachable_at(bound).context("no global IPv6 address")?;
+    match current {
+        Some(pinhole) if pinhole.external == internal => match renew_pinhole(pinhole).await {
+            Ok(renewed) => Ok(renewed),
+            Err(error) => {
+                tracing::debug!(%error, "Failed to renew pinhole, opening it again");
+                open_pinhole(internal).await
+            }
+        },
+        Some(stale) => {
+            // the address of the host changed, the pinhole is of no use anymore
+            if let Err(error) = unmap(stale).await {
+                tracing::debug!(%error, "Failed to close pinhole of the previous address");
+            }
+            open_pinhole(internal).await
+        }
+        None => open_pinhole(internal).await,
+    }
+}
+
+async fn map_port(protocol: PortMapping, internal_port: u16) -> anyhow::Result<Mapping> {
+    let nat_pmp = || async {
+        let gateway = default_gateway().await.context("default gateway unknown")?;
+        nat_pmp_map(SocketAddr::new(gateway.into(), NAT_PMP_PORT), internal_port).await
+    };
+    match protocol {
+        PortMapping::Disabled => bail!("port mapping disabled"),
+        PortMapping::NatPmp => nat_pmp().await,
+        PortMapping::Upnp => upnp_map(internal_port).await,
+        PortMapping::Auto => match nat_pmp().await {
+            Ok(mapping) => Ok(mapping),
+            Err(error) => {
+                tracing::debug!(%error, "NAT-PMP not available, trying UPnP");
+                upnp_map(internal_port).await
+            }
+        },
+    }
+}
+
+/// Removes the mapping from the router.
+async fn unmap(mapping: &Mapping) -> anyhow::Result<()> {
+    match &mapping.with {
+        MappedWith::NatPmp {
+            gateway,
+            internal_port,
+        } => {
+            // a zero lease for any external port deletes the mapping
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+            nat_pmp_request_mapping(&socket, *gateway, *internal_port, 0, Duration::ZERO).await?;
+        }
+        MappedWith::Upnp(service) => {
+            let port = mapping.external.port().to_string();
+            soap_request(
+                service,
+                "DeletePortMapping",
+                &[
+                    ("NewRemoteHost", ""),
+                    ("NewExternalPort", &port),
+                    ("NewProtocol", "UDP"),
+                ],
+            )
+            .await?;
+        }
+        MappedWith::UpnpPinhole { service, id } => {
+            soap_request(service, "DeletePinhole", &[("UniqueID", id)]).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the NAT-PMP request to the gateway until it answers with a response to the opcode.
+async fn nat_pmp_request(
+    socket: &UdpSocket,
+    gateway: SocketAddr,
+    request: &[u8],
+    response: &mut [u8],
+) -> anyhow::Result<usize> {
+    let mut timeout = NAT_PMP_INITIAL_TIMEOUT;
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send_to(request, gateway).await?;
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(response)).await
+            else {
+                break;
+            };
+            let (size, from) = received?;
+            // responses carry the opcode of the request plus 128
+            if from == gateway && size >= 4 && response[0] == 0 && response[1] == request[1] + 128 {
+                let result = u16::from_be_bytes([response[2], response[3]]);
+                if result != 0 {
+                    bail!("NAT-PMP request refused with result code {result}");
+                }
+                return Ok(size);
+            }
+        }
+        timeout *= 2;
+    }
+    bail!("no NAT-PMP answer from {gateway}")
+}
+
+async fn nat_pmp_map(gateway: SocketAddr, internal_port: u16) -> anyhow::Result<Mapping> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let mut response = [0; 16];
+
+    let size = nat_pmp_request(&socket, gateway, &[0, 0], &mut response).await?;
+    if size < 12 {
+        bail!("short NAT-PMP external address response");
+    }
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let (external_port, lease) = nat_pmp_request_mapping(
+        &socket,
+        gateway,
+        internal_port,
+        internal_port,
+        MAPPING_LEASE,
+    )
+    .await?;
+    Ok(Mapping {
+        external: SocketAddr::new(external_ip.into(), external_port),
+        lease,
+        with: MappedWith::NatPmp {
+            gateway,
+            internal_port,
+        },
+    })
+}
+
+/// Asks the gateway to map the UDP port for the given lease, returning the external port and
+/// the lease granted.
+async fn nat_pmp_request_mapping(
+    socket: &UdpSocket,
+    gateway: SocketAddr,
+    internal_port: u16,
+    external_port: u16,
+    lease: Duration,
+) -> anyhow::Result<(u16, Duration)> {
+    let mut request = [0; 12];
+    request[1] = 1; // map UDP
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&(lease.as_secs() as u32).to_be_bytes());
+    let mut response = [0; 16];
+    let size = nat_pmp_request(socket, gateway, &request, &mut response).await?;
+    if size < 16 {
+        bail!("short NAT-PMP mapping response");
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lease = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok((external_port, Duration::from_secs(lease.into())))
+}
+
+/// Default IPv4 gateway of the host, read from the routing table.
+#[cfg(target_os = "linux")]
+async fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = tokio::fs::read_to_string("/proc/net/route").await.ok()?;
+    routes.lines().skip(1).find_map(|route| {
+        let fields: Vec<_> = route.split_whitespace().collect();
+        let (destination, gateway) = (fields.get(1)?, fields.get(2)?);
+        if *destination != "00000000" {
+            return None;
+        }
+        // in the byte order of the host
+        let gateway = u32::from_str_radix(gateway, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+/// Default IPv4 gateway of the host, as reported by `route`.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+async fn default_gateway() -> Option<Ipv4Addr> {
+    let output = tokio::process::Command::new("route")
+        .args(["-n", "get", "default"])
+        .output()
+        .await
+        .ok()?;
+    bsd_route_gateway(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Default IPv4 gateway of the host, as reported by `route`.
+#[cfg(windows)]
+async fn default_gateway() -> Option<Ipv4Addr> {
+    let output = tokio::process::Command::new("route")
+        .args(["print", "-4", "0.0.0.0"])
+        .output()
+        .await
+        .ok()?;
+    windows_route_gateway(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+    windows
+)))]
+async fn default_gateway() -> Option<Ipv4Addr> {
+    None
+}
+
+/// Gateway in the output of `route -n get default`.
+#[cfg(any(
+    test,
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn bsd_route_gateway(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim() != "gateway" {
+            return None;
+        }
+        value.trim().parse().ok()
+    })
+}
+
+/// Gateway of the default route in the output of `route print -4 0.0.0.0`.
+#[cfg(any(test, windows))]
+fn windows_route_gateway(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            // routes on-link have no gateway
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Service of the internet gateway device of the local network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UpnpService {
+    service_type: String,
+    control_url: reqwest::Url,
+    /// Location of the description of the device.
+    location: reqwest::Url,
+}
+
+/// First service of one of the given types in the internet gateway device.
+async fn upnp_service(service_types: &[&str]) -> anyhow::Result<UpnpService> {
+    let location = ssdp_discover().await?;
+    let description = reqwest::get(location.clone())
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let (service_type, control_url) = find_service(&description, service_types)
+        .with_context(|| format!("none of {service_types:?} in the gateway"))?;
+    Ok(UpnpService {
+        service_type: service_type.to_owned(),
+        control_url: location.join(control_url)?,
+        location,
+    })
+}
+
+async fn upnp_map(internal_port: u16) -> anyhow::Result<Mapping> {
+    let service = upnp_service(WAN_SERVICES).await?;
+
+    // the address of this host on the interface towards the gateway
+    let gateway_host = service
+        .location
+        .host_str()
+        .context("gateway location without host")?;
+    let gateway_port = service.location.port_or_known_default().unwrap_or(80);
+    let probe = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    probe.connect((gateway_host, gateway_port)).await?;
+    let internal_ip = probe.local_addr()?.ip();
+
+    let response = soap_request(&service, "GetExternalIPAddress", &[]).await?;
+    let external_ip: IpAddr = xml_text(&response, "NewExternalIPAddress")
+        .context("no external address in the gateway response")?
+        .trim()
+        .parse()?;
+    let port = internal_port.to_string();
+    let lease = MAPPING_LEASE.as_secs().to_string();
+    soap_request(
+        &service,
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", ""),
+            ("NewExternalPort", &port),
+            ("NewProtocol", "UDP"),
+            ("NewInternalPort", &port),
+            ("NewInternalClient", &internal_ip.to_string()),
+            ("NewEnabled", "1"),
+            ("NewPortMappingDescription", "freenet"),
+            ("NewLeaseDuration", &lease),
+        ],
+    )
+    .await?;
+    Ok(Mapping {
+        external: SocketAddr::new(external_ip, internal_port),
+        lease: MAPPING_LEASE,
+        with: MappedWith::Upnp(service),
+    })
+}
+
+/// Opens a pinhole in the IPv6 firewall of the router for packets from any peer to the given
+/// address.
+async fn open_pinhole(internal: SocketAddr) -> anyhow::Result<Mapping> {
+    let service = upnp_service(PINHOLE_SERVICES).await?;
+    let port = internal.port().to_string();
+    let lease = MAPPING_LEASE.as_secs().to_string();
+    let response = soap_request(
+        &service,
+        "AddPinhole",
+        &[
+            // any remote host and port
+            ("RemoteHost", ""),
+            ("RemotePort", "0"),
+            ("InternalClient", &internal.ip().to_string()),
+            ("InternalPort", &port),
+            ("Protocol", "17"), // UDP
+            ("LeaseTime", &lease),
+        ],
+    )
+    .await?;
+    let id = xml_text(&response, "UniqueID")
+        .context("no pinhole id in the gateway response")?
+        .trim()
+        .to_owned();
+    Ok(Mapping {
+        external: internal,
+        lease: MAPPING_LEASE,
+        with: MappedWith::UpnpPinhole { service, id },
+    })
+}
+
+async fn renew_pinhole(pinhole: &Mapping) -> anyhow::Result<Mapping> {
+    let MappedWith::UpnpPinhole { service, id } = &pinhole.with else {
+        bail!("not a pinhole");
+    };
+    let lease = MAPPING_LEASE.as_secs().to_string();
+    soap_request(
+        service,
+        "UpdatePinhole",
+        &[("UniqueID", id), ("NewLeaseTime", &lease)],
+    )
+    .await?;
+    Ok(Mapping {
+        lease: MAPPING_LEASE,
+        ..pinhole.clone()
+    })
+}
+
+/// Location of the description of the internet gateway device of the local network.
+async fn ssdp_discover() -> anyhow::Result<reqwest::Url> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let search = "M-SEARCH * HTTP/1.1\r\n\
+                  HOST: 239.255.255.250:1900\r\n\
+                  MAN: \"ssdp:discover\"\r\n\
+                  MX: 2\r\n\
+                  ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+    socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+    let mut buf = [0; 2048];
+    let deadline = tokio::time::Instant::now() + SSDP_TIMEOUT;
+    loop {
+        let (size, _) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf))
+            .await
+            .context("no internet gateway device answered")??;
+        let response = String::from_utf8_lossy(&buf[..size]);
+        let location = response.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_owned())
+        });
+        if let Some(location) = location {
+            return Ok(reqwest::Url::parse(&location)?);
+        }
+    }
+}
+
+/// Type and control URL of the first service of one of the given types in the description of
+/// the device.
+fn find_service<'a>(description: &'a str, service_types: &[&str]) -> Option<(&'a str, &'a str)> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service_type = xml_text(service, "serviceType")?.trim();
+        service_types
+            .iter()
+            .any(|wanted| service_type.starts_with(wanted))
+            .then_some(())?;
+        Some((service_type, xml_text(service, "controlURL")?.trim()))
+    })
+}
+
+async fn soap_request(
+    service: &UpnpService,
+    action: &str,
+    args: &[(&str, &str)],
+) -> anyhow::Result<String> {
+    let service_type = &service.service_type;
+    let args: String = args
+        .iter()
+        .map(|(name, value)| format!("<{name}>{value}</{name}>"))
+        .collect();
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service_type}\">{args}</u:{action}></s:Body>\
+         </s:Envelope>"
+    );
+    let response = reqwest::Client::new()
+        .post(service.control_url.clone())
+        .header("Content-Type", "text/xml; charset=\"utf-8\"")
+        .header("SOAPAction", format!("\"{service_type}#{action}\""))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(response)
+}
+
+/// Text of the first element with the given tag.
+fn xml_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(&xml[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn map_and_remove_port_over_nat_pmp() {
+        let gateway = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let gateway_addr = gateway.local_addr().unwrap();
+        let (requests, mut requested) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0; 16];
+            loop {
+                let (size, from) = gateway.recv_from(&mut buf).await.unwrap();
+                requests.send(buf[..size].to_vec()).unwrap();
+                let mut response = vec![0, buf[1] + 128, 0, 0, 0, 0, 0, 1];
+                match buf[1] {
+                    0 => response.extend([203, 0, 113, 9]),
+                    1 => {
+                        // the internal port, mapped to external port 40000 for 30 minutes
+                        // unless removed
+                        let lease = u32::from_be_bytes(buf[8..12].try_into().unwrap());
+                        response.extend(&buf[4..6]);
+                        if lease == 0 {
+                            response.extend([0; 6]);
+                        } else {
+                            response.extend(40000u16.to_be_bytes());
+                            response.extend(1800u32.to_be_bytes());
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+                gateway.send_to(&response, from).await.unwrap();
+            }
+        });
+
+        let mapping = nat_pmp_map(gateway_addr, 31337).await.unwrap();
+        assert_eq!(
+            mapping,
+            Mapping {
+                external: "203.0.113.9:40000".parse().unwrap(),
+                lease: Duration::from_secs(1800),
+                with: MappedWith::NatPmp {
+                    gateway: gateway_addr,
+                    internal_port: 31337,
+                },
+            }
+        );
+        while requested.try_recv().is_ok() {}
+
+        unmap(&mapping).await.unwrap();
+        let removal = requested.recv().await.unwrap();
+        assert_eq!(removal[1], 1);
+        assert_eq!(removal[4..6], 31337u16.to_be_bytes());
+        // any external port, for no time
+        assert_eq!(removal[6..12], [0; 6]);
+    }
+
+    #[test]
+    fn renew_short_leases_at_min_interval() {
+        let mapping = |lease| Mapping {
+            external: "203.0.113.9:40000".parse().unwrap(),
+            lease,
+            with: MappedWith::NatPmp {
+                gateway: "192.168.1.1:5351".parse().unwrap(),
+                internal_port: 31337,
+            },
+        };
+        assert_eq!(mapping(Duration::ZERO).renew_after(), MIN_RENEW_AFTER);
+        assert_eq!(
+            mapping(Duration::from_secs(1)).renew_after(),
+            MIN_RENEW_AFTER
+        );
+        assert_eq!(mapping(MAPPING_LEASE).renew_after(), MAPPING_LEASE / 2);
+    }
+
+    #[test]
+    fn default_gateway_from_route_output() {
+        let bsd = "   route to: default\n\
+                   destination: default\n\
+                   mask: default\n\
+                   gateway: 192.168.1.1\n\
+                   interface: en0\n";
+        assert_eq!(bsd_route_gateway(bsd), Some(Ipv4Addr::new(192, 168, 1, 1)));
+
+        let windows = "IPv4 Route Table\n\
+            ===========================================================================\n\
+            Active Routes:\n\
+            Network Destination        Netmask          Gateway       Interface  Metric\n\
+            \x20         0.0.0.0          0.0.0.0         On-link     10.8.0.2     281\n\
+            \x20         0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.100     25\n\
+            ===========================================================================\n";
+        assert_eq!(
+            windows_route_gateway(windows),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(windows_route_gateway("Persistent Routes:\n  None\n"), None);
+    }
+
+    #[test]
+    fn find_wan_service_of_device() {
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+            <controlURL>/ctl/L3F</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:2</serviceType>\
+            <controlURL>/ctl/IPConn</controlURL></service>\
+            </serviceList></device></root>";
+        assert_eq!(
+            find_service(description, WAN_SERVICES),
+            Some((
+                "urn:schemas-upnp-org:service:WANIPConnection:2",
+                "/ctl/IPConn"
+            ))
+        );
+        assert_eq!(find_service(description, PINHOLE_SERVICES), None);
+    }
+}
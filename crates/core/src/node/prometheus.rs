@@ -0,0 +1,393 @@
+//! Metrics of the node in the Prometheus text format, served on localhost for scraping.
+//!
+//! The metrics cover the connections and contracts of the ring, the operations handled by the
+//! node, the requests to the contract handler, the traffic over the transport sockets and the
+//! policing of the messages received from peers. They are rendered from the live counters on
+//! every scrape, so nothing is kept between scrapes.
+
+use std::{
+    fmt::Write,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+
+use super::{LatencyHistogram, OpManager};
+use crate::ring::NetworkStatus;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves the metrics of the node on localhost until the node shuts down.
+pub(crate) async fn serve_metrics(op_manager: Arc<OpManager>, port: u16) {
+    let router = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(op_manager);
+    let socket = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let served = async {
+        let listener = tokio::net::TcpListener::bind(socket).await?;
+        tracing::info!(%socket, "Metrics endpoint listening");
+        axum::serve(listener, router).await
+    };
+    if let Err(error) = served.await {
+        tracing::error!(%socket, %error, "Metrics endpoint stopped");
+    }
+}
+
+async fn metrics(State(op_manager): State<Arc<OpManager>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], render(&op_manager))
+}
+
+fn render(op_manager: &OpManager) -> String {
+    let mut out = Exposition::default();
+    let ring = &op_manager.ring;
+    let connection_manager = &ring.connection_manager;
+
+    out.header(
+        "freenet_ring_connections",
+        "gauge",
+        "Open connections with other peers.",
+    );
+    out.sample("freenet_ring_connections", &[], ring.open_connections());
+    out.header(
+        "freenet_ring_min_connections",
+        "gauge",
+        "Min number of connections sought.",
+    );
+    out.sample(
+        "freenet_ring_min_connections",
+        &[],
+        connection_manager.min_connections(),
+    );
+    out.header(
+        "freenet_ring_max_connections",
+        "gauge",
+        "Max number of connections allowed.",
+    );
+    out.sample(
+        "freenet_ring_max_connections",
+        &[],
+        connection_manager.max_connections(),
+    );
+    out.header(
+        "freenet_ring_seeded_contracts",
+        "gauge",
+        "Contracts seeded by this node.",
+    );
+    out.sample(
+        "freenet_ring_seeded_contracts",
+        &[],
+        ring.seeded_contracts().len(),
+    );
+    out.header(
+        "freenet_network_connected",
+        "gauge",
+        "Whether the node is connected to the network.",
+    );
+    let connected = *ring.network_status.borrow() == NetworkStatus::Connected;
+    out.sample("freenet_network_connected", &[], u8::from(connected));
+
+    let ops = op_manager.metrics();
+    out.header(
+        "freenet_operations_total",
+        "counter",
+        "Operations by type and outcome.",
+    );
+    let per_type = [
+        ("connect", &ops.connect),
+        ("put", &ops.put),
+        ("get", &ops.get),
+        ("subscribe", &ops.subscribe),
+        ("update", &ops.update),
+        ("probe", &ops.probe),
+    ];
+    for (op, metrics) in per_type {
+        for (outcome, count) in [
+            ("started", metrics.started),
+            ("completed", metrics.completed),
+            ("failed", metrics.failed),
+            ("timed_out", metrics.timed_out),
+        ] {
+            out.sample(
+                "freenet_operations_total",
+                &[("type", op), ("outcome", outcome)],
+                count,
+            );
+        }
+    }
+    out.header(
+        "freenet_operation_latency_seconds",
+        "histogram",
+        "Latency of the finished operations.",
+    );
+    for (op, metrics) in per_type {
+        out.histogram(
+            "freenet_operation_latency_seconds",
+            &[("type", op)],
+            &metrics.latency,
+        );
+    }
+
+    let contracts = op_manager.contract_metrics();
+    out.header(
+        "freenet_contract_requests_total",
+        "counter",
+        "Requests to the contract handler.",
+    );
+    out.sample("freenet_contract_requests_total", &[], contracts.requests);
+    out.header(
+        "freenet_contract_requests_failed_total",
+        "counter",
+        "Requests to the contract handler which failed.",
+    );
+    out.sample(
+        "freenet_contract_requests_failed_total",
+        &[],
+        contracts.failed,
+    );
+    out.header(
+        "freenet_contract_request_latency_seconds",
+        "histogram",
+        "Latency of the requests to the contract handler.",
+    );
+    out.histogram(
+        "freenet_contract_request_latency_seconds",
+        &[],
+        &contracts.latency,
+    );
+    out.header(
+        "freenet_executor_channel_overflows_total",
+        "counter",
+        "Messages between the executor and the event loop dropped for the receiving end falling behind.",
+    );
+    for (kind, count) in [
+        ("rejected_request", contracts.rejected_requests),
+        ("dropped_result", contracts.dropped_results),
+    ] {
+        out.sample(
+            "freenet_executor_channel_overflows_total",
+            &[("kind", kind)],
+            count,
+        );
+    }
+    out.header(
+        "freenet_client_notifications_dropped_total",
+        "counter",
+        "Notifications dropped on their way to the clients subscribed to contracts for the clients falling behind.",
+    );
+    out.sample(
+        "freenet_client_notifications_dropped_total",
+        &[],
+        contracts.dropped_notifications,
+    );
+    out.header(
+        "freenet_contract_states_evicted_total",
+        "counter",
+        "Contract states evicted for going over the storage quota.",
+    );
+    out.sample(
+        "freenet_contract_states_evicted_total",
+        &[],
+        contracts.evicted_states,
+    );
+    out.header(
+        "freenet_contract_state_evicted_bytes_total",
+        "counter",
+        "Bytes of the contract states evicted for going over the storage quota.",
+    );
+    out.sample(
+        "freenet_contract_state_evicted_bytes_total",
+        &[],
+        contracts.evicted_bytes,
+    );
+    out.header(
+        "freenet_contract_validations_total",
+        "counter",
+        "Contract state validations, by whether the result of validating the same inputs was reused.",
+    );
+    for (source, count) in [
+        ("cached", contracts.validations_cached),
+        ("run", contracts.validations_run),
+    ] {
+        out.sample(
+            "freenet_contract_validations_total",
+            &[("source", source)],
+            count,
+        );
+    }
+
+    let redundancy = op_manager.redundancy_metrics();
+    out.header(
+        "freenet_redundant_get_requests_total",
+        "counter",
+        "Get requests sent besides the one to the best candidate, and what came of them.",
+    );
+    for (outcome, count) in [
+        ("dispatched", redundancy.dispatched),
+        ("cancelled", redundancy.cancelled),
+        ("wasted", redundancy.wasted),
+    ] {
+        out.sample(
+            "freenet_redundant_get_requests_total",
+            &[("outcome", outcome)],
+            count,
+        );
+    }
+
+    let traffic = op_manager.traffic.snapshot();
+    out.header(
+        "freenet_transport_packets_total",
+        "counter",
+        "Packets sent and received over the transports.",
+    );
+    out.sample(
+        "freenet_transport_packets_total",
+        &[("direction", "sent")],
+        traffic.packets_sent,
+    );
+    out.sample(
+        "freenet_transport_packets_total",
+        &[("direction", "received")],
+        traffic.packets_received,
+    );
+    out.header(
+        "freenet_transport_bytes_total",
+        "counter",
+        "Bytes sent and received over the transports.",
+    );
+    out.sample(
+        "freenet_transport_bytes_total",
+        &[("direction", "sent")],
+        traffic.bytes_sent,
+    );
+    out.sample(
+        "freenet_transport_bytes_total",
+        &[("direction", "received")],
+        traffic.bytes_received,
+    );
+
+    let policing = op_manager.policing.snapshot();
+    out.header(
+        "freenet_policing_throttled_messages_total",
+        "counter",
+        "Messages from peers dropped for exceeding their rate limit.",
+    );
+    out.sample(
+        "freenet_policing_throttled_messages_total",
+        &[],
+        policing.throttled,
+    );
+    out.header(
+        "freenet_policing_bans_total",
+        "counter",
+        "Peers banned for flooding this node, by kind of ban.",
+    );
+    out.sample(
+        "freenet_policing_bans_total",
+        &[("kind", "temporary")],
+        policing.temporary_bans,
+    );
+    out.sample(
+        "freenet_policing_bans_total",
+        &[("kind", "permanent")],
+        policing.permanent_bans,
+    );
+
+    if let Some(gateway) = connection_manager.gateway() {
+        let stats = gateway.stats();
+        out.header(
+            "freenet_gateway_joins_total",
+            "counter",
+            "Join requests served by this gateway, by outcome.",
+        );
+        for (outcome, count) in [
+            ("accepted", stats.accepted),
+            ("rejected", stats.rejected),
+            ("busy", stats.busy),
+            ("rate_limited", stats.rate_limited),
+            ("joined", stats.joined),
+        ] {
+            out.sample(
+                "freenet_gateway_joins_total",
+                &[("outcome", outcome)],
+                count,
+            );
+        }
+    }
+    out.0
+}
+
+/// Builder of a document in the Prometheus text exposition format.
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            self.0.push('{');
+            for (i, (label, label_value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.0.push(',');
+                }
+                let _ = write!(self.0, "{label}=\"{label_value}\"");
+            }
+            self.0.push('}');
+        }
+        let _ = writeln!(self.0, " {value}");
+    }
+
+    /// Cumulative buckets of the histogram, the overflow bucket being `+Inf`, followed by
+    /// the sum and count of the samples.
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &LatencyHistogram) {
+        let bucket = format!("{name}_bucket");
+        let mut cumulative = 0;
+        for (bound, count) in &histogram.buckets {
+            cumulative += count;
+            let le = if *bound == std::time::Duration::MAX {
+                "+Inf".to_string()
+            } else {
+                bound.as_secs_f64().to_string()
+            };
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            self.sample(&bucket, &bucket_labels, cumulative);
+        }
+        self.sample(&format!("{name}_sum"), labels, histogram.sum.as_secs_f64());
+        self.sample(&format!("{name}_count"), labels, histogram.count());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = LatencyHistogram {
+            buckets: vec![
+                (Duration::from_millis(10), 2),
+                (Duration::from_millis(500), 1),
+                (Duration::MAX, 3),
+            ],
+            sum: Duration::from_millis(95_250),
+        };
+        let mut out = Exposition::default();
+        out.histogram("latency_seconds", &[("type", "get")], &histogram);
+        assert_eq!(
+            out.0,
+            "latency_seconds_bucket{type=\"get\",le=\"0.01\"} 2\n\
+             latency_seconds_bucket{type=\"get\",le=\"0.5\"} 3\n\
+             latency_seconds_bucket{type=\"get\",le=\"+Inf\"} 6\n\
+             latency_seconds_sum{type=\"get\"} 95.25\n\
+             latency_seconds_count{type=\"get\"} 6\n"
+        );
+    }
+}
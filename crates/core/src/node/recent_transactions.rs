@@ -0,0 +1,194 @@
+//! Compact digest of the transactions recently completed by this node.
+//!
+//! The in-memory set of completed transactions is lost on restart, so the digest is
+//! persisted to disk in order to reject replayed transactions right after boot. Transactions
+//! are tracked in bloom filters bucketed by their creation time; buckets are dropped once
+//! all the transactions in them have timed out, from then on the transactions are rejected by
+//! the creation time embedded in them.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::OPERATION_TTL, message::Transaction};
+
+const DIGEST_FILE: &str = "recent_transactions";
+const BUCKET_SPAN: Duration = Duration::from_secs(10);
+const FILTER_BITS: usize = 1 << 17;
+const NUM_HASHES: u64 = 4;
+
+#[derive(Serialize, Deserialize)]
+struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn new() -> Self {
+        Self {
+            bits: vec![0; FILTER_BITS / 64],
+        }
+    }
+
+    fn positions(tx: &Transaction) -> impl Iterator<Item = usize> {
+        let hash = blake3::hash(&tx.as_bytes());
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes"));
+        (0..NUM_HASHES)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % FILTER_BITS as u64) as usize)
+    }
+
+    fn insert(&mut self, tx: &Transaction) {
+        for pos in Self::positions(tx) {
+            self.bits[pos / 64] |= 1 << (pos % 64);
+        }
+    }
+
+    fn contains(&self, tx: &Transaction) -> bool {
+        Self::positions(tx).all(|pos| self.bits[pos / 64] & (1 << (pos % 64)) != 0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Buckets {
+    /// Filters indexed by the start of the time span they cover, in milliseconds since the unix epoch.
+    filters: BTreeMap<u64, BloomFilter>,
+}
+
+impl Buckets {
+    fn bucket(tx: &Transaction) -> u64 {
+        let span = BUCKET_SPAN.as_millis() as u64;
+        tx.timestamp_ms() / span * span
+    }
+
+    /// Removes the buckets in which all transactions have already timed out.
+    fn expire(&mut self, now_ms: u64) {
+        let cutoff = now_ms.saturating_sub((OPERATION_TTL + BUCKET_SPAN).as_millis() as u64);
+        self.filters = self.filters.split_off(&cutoff);
+    }
+}
+
+/// Digest of the recently completed transactions which survives node restarts.
+pub(crate) struct RecentTransactions {
+    buckets: Mutex<Buckets>,
+    path: PathBuf,
+    dirty: std::sync::atomic::AtomicBool,
+}
+
+impl RecentTransactions {
+    /// Loads the digest persisted in the given directory, if any, as of the given time since
+    /// the unix epoch.
+    pub fn load(dir: &Path, now: Duration) -> Self {
+        let path = dir.join(DIGEST_FILE);
+        let mut buckets = match std::fs::read(&path) {
+            Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|error| {
+                tracing::warn!(%error, "Failed to load recent transactions digest, discarding");
+                Buckets::default()
+            }),
+            Err(_) => Buckets::default(),
+        };
+        buckets.expire(now.as_millis() as u64);
+        Self {
+            buckets: Mutex::new(buckets),
+            path,
+            dirty: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    pub fn insert(&self, tx: &Transaction) {
+        self.buckets
+            .lock()
+            .filters
+            .entry(Buckets::bucket(tx))
+            .or_insert_with(BloomFilter::new)
+            .insert(tx);
+        self.dirty.store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    /// Whether the transaction was (probably) completed already, as of the given time since the
+    /// unix epoch; may report false positives. Transactions older than the span covered by the
+    /// digest are assumed to be completed, judging by the creation time embedded in them, since
+    /// they may have been dropped from it or completed before the digest was persisted.
+    pub fn contains(&self, tx: &Transaction, now: Duration) -> bool {
+        if tx.timed_out(now) {
+            return true;
+        }
+        self.buckets
+            .lock()
+            .filters
+            .get(&Buckets::bucket(tx))
+            .map(|filter| filter.contains(tx))
+            .unwrap_or(false)
+    }
+
+    /// Drops the expired buckets and writes the digest to disk if it changed since the last time.
+    pub fn persist(&self, now: Duration) -> std::io::Result<()> {
+        if !self.dirty.swap(false, std::sync::atomic::Ordering::AcqRel) {
+            return Ok(());
+        }
+        let bytes = {
+            let mut buckets = self.buckets.lock();
+            buckets.expire(now.as_millis() as u64);
+            bincode::serialize(&*buckets).map_err(std::io::Error::other)?
+        };
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::get::GetMsg;
+    use crate::util::time_source::{Clock, TokioClock};
+
+    #[test]
+    fn persisted_across_restarts() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let completed: Vec<_> = (0..100).map(|_| Transaction::new::<GetMsg>()).collect();
+        let now = TokioClock.unix_time();
+        let recent = RecentTransactions::load(dir.path(), now);
+        for tx in &completed {
+            recent.insert(tx);
+        }
+        recent.persist(now)?;
+
+        let recent = RecentTransactions::load(dir.path(), now);
+        assert!(completed.iter().all(|tx| recent.contains(tx, now)));
+        assert!(!recent.contains(&Transaction::new::<GetMsg>(), now));
+        Ok(())
+    }
+
+    #[test]
+    fn old_transactions_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = TokioClock.unix_time();
+        let tx = Transaction::new::<GetMsg>();
+        // e.g. a node which was down for longer than the span of the digest
+        let recent = RecentTransactions::load(dir.path(), now + OPERATION_TTL + BUCKET_SPAN * 2);
+        assert!(recent.contains(&tx, now + OPERATION_TTL + BUCKET_SPAN * 2));
+        assert!(!recent.contains(&tx, now));
+    }
+
+    #[test]
+    fn expire_old_buckets() {
+        let tx = Transaction::new::<GetMsg>();
+        let mut buckets = Buckets::default();
+        buckets
+            .filters
+            .entry(Buckets::bucket(&tx))
+            .or_insert_with(BloomFilter::new)
+            .insert(&tx);
+        let now = TokioClock.unix_time();
+        buckets.expire(now.as_millis() as u64);
+        assert_eq!(buckets.filters.len(), 1);
+        buckets.expire((now + OPERATION_TTL + BUCKET_SPAN * 2).as_millis() as u64);
+        assert!(buckets.filters.is_empty());
+    }
+}
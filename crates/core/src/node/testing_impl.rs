@@ -2,8 +2,12 @@ use std::{
     collections::{HashMap, HashSet},
     net::Ipv6Addr,
     num::NonZeroUsize,
+    path::Path,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -27,21 +31,24 @@ use crate::{
         WaitingResolution,
     },
     dev_tool::TransportKeypair,
-    message::{MessageStats, NetMessage, NetMessageV1, NodeEvent, Transaction},
+    message::{MessageStats, NetMessage, NetMessageV1, NodeEvent},
     node::{InitPeerNode, NetEventRegister, NodeConfig},
-    operations::connect,
+    operations::{connect, get::GetMsg, relocate, update::UpdateMsg},
     ring::{Distance, Location, PeerKeyLocation},
-    tracing::TestEventListener,
+    tracing::{read_recording, RecordedMessage, TestEventListener},
     transport::TransportPublicKey,
+    util::time_source::{SharedClock, TokioClock, VirtualClock},
 };
 
+mod baseline_wire;
 mod in_memory;
 mod network;
 
 pub use self::network::{NetworkPeer, PeerMessage, PeerStatus};
 
 use super::{
-    network_bridge::EventLoopNotificationsReceiver, ConnectionError, NetworkBridge, PeerId,
+    network_bridge::{hello::understood_by_legacy_peers, EventLoopNotificationsReceiver},
+    ConnectionError, NetworkBridge, NodeLifecycleEvent, PeerId,
 };
 
 pub(crate) type EventId = u32;
@@ -108,6 +115,104 @@ impl<'a> From<&'a str> for NodeLabel {
     }
 }
 
+/// Optional wire features a simulated peer runs with.
+///
+/// Allows simulating peers running older releases alongside current ones. Peers without every
+/// feature decode the messages they receive with a frozen copy of the wire format of the first
+/// release, extended with the messages of the features they have; messages they fail to
+/// decode are dropped and accounted as wire breaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WireFeatures {
+    /// Large contract states are transferred in chunks.
+    pub state_chunks: bool,
+    /// Large updates are propagated to subscribers as summaries.
+    pub update_summaries: bool,
+    /// Get requests heading to the same peer are sent together.
+    pub get_batches: bool,
+    /// Gets can subscribe to the contract along the way.
+    pub get_and_subscribe: bool,
+}
+
+impl WireFeatures {
+    /// All the features supported by this release.
+    pub const CURRENT: Self = Self {
+        state_chunks: true,
+        update_summaries: true,
+        get_batches: true,
+        get_and_subscribe: true,
+    };
+
+    /// The features supported by the first release of the V1 protocol.
+    pub const BASELINE: Self = Self {
+        state_chunks: false,
+        update_summaries: false,
+        get_batches: false,
+        get_and_subscribe: false,
+    };
+
+    fn apply(&self, config: &mut NodeConfig) {
+        if !self.state_chunks {
+            config.state_chunk_threshold(usize::MAX);
+        }
+        if !self.update_summaries {
+            config.update_summary_threshold(usize::MAX);
+        }
+    }
+
+    /// Whether a peer with these features would be sent the message over a real connection,
+    /// where the greeting tells which messages the peer understands.
+    pub(crate) fn receives(&self, msg: &NetMessage) -> bool {
+        *self == Self::CURRENT || understood_by_legacy_peers(msg) || self.has_feature_for(msg)
+    }
+
+    /// Whether a peer with these features can decode the serialized message.
+    pub(crate) fn decodes(&self, data: &[u8], msg: &NetMessage) -> bool {
+        *self == Self::CURRENT || baseline_wire::decodes(data) || self.has_feature_for(msg)
+    }
+
+    /// Whether the message belongs to one of the features of the peer.
+    fn has_feature_for(&self, msg: &NetMessage) -> bool {
+        let NetMessage::V1(msg) = msg;
+        match msg {
+            NetMessageV1::StateChunk(_) => self.state_chunks,
+            NetMessageV1::GetBatch { requests, .. } => {
+                self.get_batches
+                    && (self.get_and_subscribe
+                        || !requests
+                            .iter()
+                            .any(|req| matches!(req, GetMsg::SeekNodeAndSubscribe { .. })))
+            }
+            NetMessageV1::Get(GetMsg::SeekNodeAndSubscribe { .. }) => self.get_and_subscribe,
+            NetMessageV1::Update(
+                UpdateMsg::BroadcastSummary { .. }
+                | UpdateMsg::RequestBody { .. }
+                | UpdateMsg::ReturnBody { .. },
+            ) => self.update_summaries,
+            _ => false,
+        }
+    }
+}
+
+/// Number of messages dropped by simulated peers because they required wire features the
+/// receiver lacks.
+#[derive(Clone, Default)]
+pub struct WireBreaks(Arc<AtomicUsize>);
+
+impl WireBreaks {
+    pub(crate) fn record(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fails if any message could not be understood by the peer it was sent to.
+    pub fn check(&self) -> anyhow::Result<()> {
+        let breaks = self.0.load(Ordering::Relaxed);
+        if breaks > 0 {
+            anyhow::bail!("{breaks} messages were sent to peers not supporting them");
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct GatewayConfig {
     label: NodeLabel,
@@ -271,6 +376,11 @@ pub(super) struct Builder<ER> {
     event_register: ER,
     contracts: Vec<(ContractContainer, WrappedState, bool)>,
     contract_subscribers: HashMap<ContractKey, Vec<PeerKeyLocation>>,
+    clock: SharedClock,
+    wire_features: WireFeatures,
+    wire_breaks: WireBreaks,
+    /// Recorded messages fed to the node as if received from its peers.
+    replay: Vec<RecordedMessage>,
 }
 
 impl<ER: NetEventRegister> Builder<ER> {
@@ -288,6 +398,10 @@ impl<ER: NetEventRegister> Builder<ER> {
             event_register,
             contracts: Vec::new(),
             contract_subscribers: HashMap::new(),
+            clock: Arc::new(TokioClock),
+            wire_features: WireFeatures::CURRENT,
+            wire_breaks: WireBreaks::default(),
+            replay: Vec::new(),
         }
     }
 }
@@ -310,6 +424,7 @@ pub struct SimNetwork {
     min_connections: usize,
     start_backoff: Duration,
     add_noise: bool,
+    wire_breaks: WireBreaks,
 }
 
 impl SimNetwork {
@@ -343,6 +458,7 @@ impl SimNetwork {
             min_connections,
             start_backoff: Duration::from_millis(1),
             add_noise: false,
+            wire_breaks: WireBreaks::default(),
         };
         net.config_gateways(
             gateways
@@ -371,6 +487,78 @@ impl SimNetwork {
         self.clean_up_tmp_dirs = false;
     }
 
+    /// Drives the timeouts of all the peers with a shared virtual clock, which only moves
+    /// forward when advanced through the returned handle.
+    ///
+    /// Must be called before starting the network.
+    pub fn with_virtual_clock(&mut self) -> VirtualClock {
+        let clock = VirtualClock::new();
+        let builders = self
+            .gateways
+            .iter_mut()
+            .map(|(builder, _)| builder)
+            .chain(self.nodes.iter_mut().map(|(builder, _)| builder));
+        for builder in builders {
+            builder.clock = Arc::new(clock.clone());
+        }
+        clock
+    }
+
+    /// Runs the last `count` regular nodes with the given wire features, to check that
+    /// peers running older releases can still join the ring and take part in operations.
+    ///
+    /// Must be called before starting the network.
+    pub fn with_legacy_nodes(&mut self, count: usize, features: WireFeatures) {
+        assert!(count <= self.nodes.len(), "not enough nodes to downgrade");
+        let first = self.nodes.len() - count;
+        for (builder, label) in &mut self.nodes[first..] {
+            tracing::debug!(peer = %label, ?features, "running with legacy wire features");
+            features.apply(&mut builder.config);
+            builder.wire_features = features;
+        }
+    }
+
+    /// Feeds the messages recorded by a node (see [`NodeConfig::record_messages`]) to the
+    /// peer with the label, with the same timing they were received with. Combined with
+    /// [`Self::with_virtual_clock`] the replay is deterministic.
+    ///
+    /// Must be called before starting the network.
+    pub fn with_replay(&mut self, label: &NodeLabel, recording: &Path) -> anyhow::Result<()> {
+        let builder = self.builder_mut(label)?;
+        builder.replay = read_recording(recording)?;
+        tracing::info!(peer = %label, messages = builder.replay.len(), "Loaded recorded messages to replay");
+        Ok(())
+    }
+
+    /// Records the messages the peer with the label receives to the file, so they can be
+    /// replayed with [`Self::with_replay`] or [`NodeConfig::replay_messages`].
+    ///
+    /// Must be called before starting the network.
+    pub fn with_recording(&mut self, label: &NodeLabel, path: &Path) -> anyhow::Result<()> {
+        let builder = self.builder_mut(label)?;
+        builder.event_register = builder.event_register.record_messages(path.to_owned());
+        Ok(())
+    }
+
+    fn builder_mut(&mut self, label: &NodeLabel) -> anyhow::Result<&mut Builder<DefaultRegistry>> {
+        self.gateways
+            .iter_mut()
+            .map(|(builder, config)| (builder, &config.label))
+            .chain(
+                self.nodes
+                    .iter_mut()
+                    .map(|(builder, label)| (builder, &*label)),
+            )
+            .find_map(|(builder, peer)| (peer == label).then_some(builder))
+            .ok_or_else(|| anyhow::anyhow!("no peer {label} in the network"))
+    }
+
+    /// Handle to the count of messages peers received without supporting them, usable
+    /// after the network has started.
+    pub fn wire_breaks(&self) -> WireBreaks {
+        self.wire_breaks.clone()
+    }
+
     async fn config_gateways(&mut self, num: NonZeroUsize) {
         info!("Building {} gateways", num);
         let mut configs = Vec::with_capacity(num.into());
@@ -384,20 +572,20 @@ impl SimNetwork {
             let mut config_args = ConfigArgs::default();
             config_args.id = Some(format!("{label}"));
             // TODO: it may be unnecessary use config_args.build() for the simulation. Related with the TODO in Config line 238
-            let mut config = NodeConfig::new(config_args.build().await.unwrap())
+            let config = NodeConfig::builder(config_args.build().await.unwrap())
                 .await
-                .unwrap();
-            config.key_pair = keypair;
-            config.network_listener_ip = Ipv6Addr::LOCALHOST.into();
-            config.network_listener_port = port;
-            config.with_peer_id(id.clone());
-            config
-                .with_location(location)
+                .unwrap()
+                .gateway()
+                .key_pair(keypair)
+                .network_listener(Ipv6Addr::LOCALHOST.into(), port)
+                .peer_id(id.clone())
+                .location(location)
                 .max_hops_to_live(self.ring_max_htl)
-                .max_number_of_connections(self.max_connections)
-                .min_number_of_connections(self.min_connections)
-                .is_gateway()
-                .rnd_if_htl_above(self.rnd_if_htl_above);
+                .max_connections(self.max_connections)
+                .min_connections(self.min_connections)
+                .rnd_if_htl_above(self.rnd_if_htl_above)
+                .build()
+                .unwrap();
             self.event_listener
                 .add_node(label.clone(), config.key_pair.public().clone());
             configs.push((
@@ -433,12 +621,13 @@ impl SimNetwork {
                     self.event_listener.clone()
                 }
             };
-            let gateway = Builder::build(
+            let mut gateway = Builder::build(
                 this_node,
                 event_listener,
                 format!("{}-{label}", self.name, label = this_config.label),
                 self.add_noise,
             );
+            gateway.wire_breaks = self.wire_breaks.clone();
             self.gateways.push((gateway, this_config));
         }
     }
@@ -457,20 +646,22 @@ impl SimNetwork {
 
             let mut config_args = ConfigArgs::default();
             config_args.id = Some(format!("{label}"));
-            let mut config = NodeConfig::new(config_args.build().await.unwrap())
-                .await
-                .unwrap();
-            for GatewayConfig { id, location, .. } in &gateways {
-                config.add_gateway(InitPeerNode::new(id.clone(), *location));
-            }
             let port = crate::util::get_free_port().unwrap();
-            config.network_listener_port = port;
-            config.network_listener_ip = Ipv6Addr::LOCALHOST.into();
-            config.key_pair = crate::transport::TransportKeypair::new();
-            config
+            let config = NodeConfig::builder(config_args.build().await.unwrap())
+                .await
+                .unwrap()
+                .gateways(
+                    gateways
+                        .iter()
+                        .map(|gw| InitPeerNode::new(gw.id.clone(), gw.location)),
+                )
+                .key_pair(crate::transport::TransportKeypair::new())
+                .network_listener(Ipv6Addr::LOCALHOST.into(), port)
                 .max_hops_to_live(self.ring_max_htl)
                 .rnd_if_htl_above(self.rnd_if_htl_above)
-                .max_number_of_connections(self.max_connections);
+                .max_connections(self.max_connections)
+                .build()
+                .unwrap();
 
             self.event_listener
                 .add_node(label.clone(), config.key_pair.public().clone());
@@ -489,12 +680,13 @@ impl SimNetwork {
                     self.event_listener.clone()
                 }
             };
-            let node = Builder::build(
+            let mut node = Builder::build(
                 config,
                 event_listener,
                 format!("{}-{label}", self.name),
                 self.add_noise,
             );
+            node.wire_breaks = self.wire_breaks.clone();
             self.nodes.push((node, label));
         }
     }
@@ -831,12 +1023,18 @@ where
     NB: NetworkBridge + NetworkBridgeExt,
     UsrEv: ClientEventsProxy + Send + 'static,
 {
-    // todo: this two containers need to be clean up on transaction time-out
+    // todo: this container needs to be clean up on transaction time-out
     let mut pending_from_executor = HashSet::new();
-    let mut tx_to_client: HashMap<Transaction, crate::client_events::ClientId> = HashMap::new();
+    let client_tracker =
+        super::ClientTransactionTracker::new(cli_response_sender, op_manager.clock.clone());
     loop {
         let msg = tokio::select! {
-            msg = conn_manager.recv() => { msg.map(Either::Left) }
+            msg = conn_manager.recv() => {
+                if let Ok(msg) = &msg {
+                    event_register.register_inbound(msg).await;
+                }
+                msg.map(Either::Left)
+            }
             msg = notification_channel.recv() => {
                 if let Some(msg) = msg {
                     Ok(msg)
@@ -853,7 +1051,7 @@ where
             }
             event_id = wait_for_event.relay_transaction_result_to_client() => {
                 if let Ok((client_id, transaction)) = event_id {
-                   tx_to_client.insert(transaction, client_id);
+                   client_tracker.track(transaction, client_id);
                 }
                 continue;
             }
@@ -898,6 +1096,51 @@ where
                 NodeEvent::QueryConnections { .. } => {
                     unimplemented!()
                 }
+                NodeEvent::AddressChanged { .. } | NodeEvent::CheckAddress => {
+                    // in-memory peers are not bound to a network address
+                    continue;
+                }
+                NodeEvent::Relocate { location } => {
+                    let op_manager = op_manager.clone();
+                    let conn_manager = conn_manager.clone();
+                    GlobalExecutor::spawn(async move {
+                        if let Err(error) =
+                            relocate::relocate(&op_manager, &conn_manager, location).await
+                        {
+                            tracing::warn!(%error, "Failed relocating");
+                        }
+                    });
+                    continue;
+                }
+                NodeEvent::HolePunch { .. } => {
+                    // in-memory peers can always reach each other
+                    continue;
+                }
+                NodeEvent::Disconnected => {
+                    op_manager
+                        .ring
+                        .lifecycle_events
+                        .emit(NodeLifecycleEvent::Disconnected);
+                    tracing::info!(peer = %peer_key, "Joining the network again");
+                    if let Err(error) =
+                        connect::initial_join_procedure(op_manager.clone(), &gateways).await
+                    {
+                        tracing::error!(peer = %peer_key, %error, "Failed joining the network again");
+                    }
+                    continue;
+                }
+                NodeEvent::Reconnected { offline_for } => {
+                    op_manager
+                        .ring
+                        .lifecycle_events
+                        .emit(NodeLifecycleEvent::Reconnected { offline_for });
+                    continue;
+                }
+                NodeEvent::SetMessageQuota { .. }
+                | NodeEvent::GatewaysDiscovered { .. }
+                | NodeEvent::AdvertiseAddresses => {
+                    continue;
+                }
             },
             Err(err) => {
                 super::report_result(
@@ -913,7 +1156,7 @@ where
             }
         };
 
-        let op_manager = op_manager.clone();
+        let tx_type = msg.id().transaction_type();
         let event_listener = event_register.trait_clone();
 
         let span = {
@@ -924,14 +1167,14 @@ where
                         parent: parent_span.clone(),
                         "process_network_message",
                         peer = %peer_key, transaction = %msg.id(),
-                        tx_type = %msg.id().transaction_type()
+                        %tx_type
                     )
                 })
                 .unwrap_or_else(|| {
                     tracing::info_span!(
                         "process_network_message",
                         peer = %peer_key, transaction = %msg.id(),
-                        tx_type = %msg.id().transaction_type()
+                        %tx_type
                     )
                 })
         };
@@ -939,23 +1182,21 @@ where
         let executor_callback = pending_from_executor
             .remove(msg.id())
             .then(|| executor_listener.callback());
-        let pending_client_req = tx_to_client.get(msg.id()).copied();
-        let client_req_handler_callback = if pending_client_req.is_some() {
-            Some(cli_response_sender.clone())
-        } else {
-            None
-        };
 
+        let skips_lanes = msg.is_control() || op_manager.has_op(msg.id());
         let msg = super::process_message(
             msg,
-            op_manager,
+            op_manager.clone(),
             conn_manager.clone(),
             event_listener,
             executor_callback,
-            client_req_handler_callback,
-            pending_client_req,
+            client_tracker.clone(),
         )
         .instrument(span);
-        GlobalExecutor::spawn(msg);
+        if skips_lanes {
+            op_manager.dispatcher.dispatch_now(Box::pin(msg));
+        } else {
+            op_manager.dispatcher.dispatch(tx_type, Box::pin(msg));
+        }
     }
 }
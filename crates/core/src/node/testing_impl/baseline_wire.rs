@@ -0,0 +1,306 @@
+//! Frozen copy of the wire format of the first release of the V1 protocol.
+//!
+//! Simulated legacy peers decode the messages they receive with these types rather than with
+//! the current ones, so a message a real peer of that release would fail to decode is caught
+//! as a wire break even if the current types still accept it. These types must never change;
+//! the leaf types they reuse are the ones whose layout did not change since that release.
+#![allow(dead_code)]
+
+use freenet_stdlib::prelude::{
+    ContractContainer, ContractKey, RelatedContracts, StateSummary, WrappedState,
+};
+use serde::Deserialize;
+
+use crate::{
+    contract::StoreResponse,
+    message::Transaction,
+    node::PeerId,
+    ring::{Location, PeerKeyLocation},
+    transport::TransportPublicKey,
+};
+
+/// Whether a peer of the first release of the V1 protocol can decode the message.
+pub(super) fn decodes(data: &[u8]) -> bool {
+    bincode::deserialize::<NetMessage>(data).is_ok()
+}
+
+#[derive(Deserialize)]
+enum NetMessage {
+    V1(NetMessageV1),
+}
+
+#[derive(Deserialize)]
+enum NetMessageV1 {
+    Connect(ConnectMsg),
+    Put(PutMsg),
+    Get(GetMsg),
+    Subscribe(SubscribeMsg),
+    Unsubscribed {
+        transaction: Transaction,
+        key: ContractKey,
+        from: PeerId,
+    },
+    Update(UpdateMsg),
+    Aborted(Transaction),
+}
+
+#[derive(Deserialize)]
+enum ConnectMsg {
+    Request {
+        id: Transaction,
+        target: PeerKeyLocation,
+        msg: ConnectRequest,
+    },
+    Response {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        msg: ConnectResponse,
+    },
+    Connected {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+    },
+}
+
+#[derive(Deserialize)]
+enum ConnectRequest {
+    StartJoinReq {
+        joiner: Option<PeerId>,
+        joiner_key: TransportPublicKey,
+        hops_to_live: usize,
+        max_hops_to_live: usize,
+        skip_list: Vec<PeerId>,
+    },
+    FindOptimalPeer {
+        query_target: PeerKeyLocation,
+        ideal_location: Location,
+        joiner: PeerKeyLocation,
+        max_hops_to_live: usize,
+        skip_list: Vec<PeerId>,
+    },
+    CheckConnectivity {
+        sender: PeerKeyLocation,
+        joiner: PeerKeyLocation,
+        hops_to_live: usize,
+        max_hops_to_live: usize,
+        skip_list: Vec<PeerId>,
+    },
+    CleanConnection {
+        joiner: PeerKeyLocation,
+    },
+}
+
+#[derive(Deserialize)]
+enum ConnectResponse {
+    AcceptedBy {
+        accepted: bool,
+        acceptor: PeerKeyLocation,
+        joiner: PeerId,
+    },
+}
+
+#[derive(Deserialize)]
+enum PutMsg {
+    RequestPut {
+        id: Transaction,
+        contract: ContractContainer,
+        #[serde(deserialize_with = "RelatedContracts::deser_related_contracts")]
+        related_contracts: RelatedContracts<'static>,
+        value: WrappedState,
+        htl: usize,
+        target: PeerKeyLocation,
+    },
+    AwaitPut {
+        id: Transaction,
+    },
+    PutForward {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        contract: ContractContainer,
+        new_value: WrappedState,
+        htl: usize,
+        skip_list: Vec<PeerId>,
+    },
+    SuccessfulPut {
+        id: Transaction,
+        target: PeerKeyLocation,
+        key: ContractKey,
+        sender: PeerKeyLocation,
+    },
+    SeekNode {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        value: WrappedState,
+        contract: ContractContainer,
+        #[serde(deserialize_with = "RelatedContracts::deser_related_contracts")]
+        related_contracts: RelatedContracts<'static>,
+        htl: usize,
+    },
+    Broadcasting {
+        id: Transaction,
+        broadcasted_to: usize,
+        broadcast_to: Vec<PeerKeyLocation>,
+        key: ContractKey,
+        new_value: WrappedState,
+        contract: ContractContainer,
+        upstream: PeerKeyLocation,
+        sender: PeerKeyLocation,
+    },
+    BroadcastTo {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        key: ContractKey,
+        new_value: WrappedState,
+        contract: ContractContainer,
+        target: PeerKeyLocation,
+    },
+}
+
+#[derive(Deserialize)]
+enum GetMsg {
+    RequestGet {
+        id: Transaction,
+        target: PeerKeyLocation,
+        key: ContractKey,
+        fetch_contract: bool,
+        skip_list: Vec<PeerId>,
+    },
+    SeekNode {
+        id: Transaction,
+        key: ContractKey,
+        fetch_contract: bool,
+        target: PeerKeyLocation,
+        sender: PeerKeyLocation,
+        htl: usize,
+        skip_list: Vec<PeerId>,
+    },
+    ReturnGet {
+        id: Transaction,
+        key: ContractKey,
+        value: StoreResponse,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        skip_list: Vec<PeerId>,
+    },
+}
+
+#[derive(Deserialize)]
+enum SubscribeMsg {
+    FetchRouting {
+        id: Transaction,
+        target: PeerKeyLocation,
+    },
+    RequestSub {
+        id: Transaction,
+        key: ContractKey,
+        target: PeerKeyLocation,
+    },
+    SeekNode {
+        id: Transaction,
+        key: ContractKey,
+        target: PeerKeyLocation,
+        subscriber: PeerKeyLocation,
+        skip_list: Vec<PeerId>,
+        htl: usize,
+        retries: usize,
+    },
+    ReturnSub {
+        id: Transaction,
+        key: ContractKey,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        subscribed: bool,
+    },
+}
+
+#[derive(Deserialize)]
+enum UpdateMsg {
+    RequestUpdate {
+        id: Transaction,
+        key: ContractKey,
+        target: PeerKeyLocation,
+        #[serde(deserialize_with = "RelatedContracts::deser_related_contracts")]
+        related_contracts: RelatedContracts<'static>,
+        value: WrappedState,
+    },
+    SuccessfulUpdate {
+        id: Transaction,
+        target: PeerKeyLocation,
+        #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+        summary: StateSummary<'static>,
+    },
+    AwaitUpdate {
+        id: Transaction,
+    },
+    SeekNode {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        target: PeerKeyLocation,
+        value: WrappedState,
+        key: ContractKey,
+        #[serde(deserialize_with = "RelatedContracts::deser_related_contracts")]
+        related_contracts: RelatedContracts<'static>,
+    },
+    Broadcasting {
+        id: Transaction,
+        broadcasted_to: usize,
+        broadcast_to: Vec<PeerKeyLocation>,
+        key: ContractKey,
+        new_value: WrappedState,
+        upstream: PeerKeyLocation,
+    },
+    BroadcastTo {
+        id: Transaction,
+        sender: PeerKeyLocation,
+        key: ContractKey,
+        new_value: WrappedState,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{NetMessage, NetMessageV1},
+        operations::connect::{self, ConnectMsg},
+    };
+
+    fn encoded(msg: NetMessageV1) -> Vec<u8> {
+        bincode::serialize(&NetMessage::V1(msg)).unwrap()
+    }
+
+    #[test]
+    fn only_messages_of_the_first_release_decode() {
+        let tx = Transaction::new::<ConnectMsg>();
+        assert!(decodes(&encoded(NetMessageV1::Aborted(tx))));
+        assert!(decodes(&encoded(NetMessageV1::Connect(
+            ConnectMsg::Connected {
+                id: tx,
+                sender: PeerKeyLocation::random(),
+                target: PeerKeyLocation::random(),
+            }
+        ))));
+
+        assert!(!decodes(&encoded(NetMessageV1::Pong {
+            transaction: tx,
+            from: PeerId::random(),
+            nonce: 1,
+        })));
+        // variants appended to the messages of operations are not known either
+        assert!(!decodes(&encoded(NetMessageV1::Connect(
+            ConnectMsg::Response {
+                id: tx,
+                sender: PeerKeyLocation::random(),
+                target: PeerKeyLocation::random(),
+                msg: connect::ConnectResponse::Busy {
+                    acceptor: PeerKeyLocation::random(),
+                    joiner: PeerId::random(),
+                },
+            }
+        ))));
+    }
+}
@@ -27,19 +27,21 @@ impl<ER> Builder<ER> {
         UsrEv: ClientEventsProxy + Send + 'static,
         ER: NetEventRegister + Clone,
     {
+        self.config.validate()?;
         let gateways = self.config.get_gateways()?;
 
         let (notification_channel, notification_tx) = event_loop_notification_channel();
         let (ops_ch_channel, ch_channel, wait_for_event) = contract::contract_handler_channel();
 
         let _guard = parent_span.enter();
-        let connection_manager = ConnectionManager::new(&self.config);
+        let connection_manager = ConnectionManager::new(&self.config, self.clock.clone());
         let op_manager = Arc::new(OpManager::new(
             notification_tx,
             ops_ch_channel,
             &self.config,
             self.event_register.clone(),
             connection_manager.clone(),
+            self.clock.clone(),
         )?);
         std::mem::drop(_guard);
         let (executor_listener, executor_sender) = executor_channel(op_manager.clone());
@@ -53,9 +55,13 @@ impl<ER> Builder<ER> {
                 ([127, 0, 0, 1], 0).into(),
                 self.config.key_pair.public().clone(),
             ),
+            self.config.network_id,
             self.event_register.clone(),
             op_manager.clone(),
             self.add_noise,
+            self.wire_features,
+            self.wire_breaks,
+            self.replay,
         );
 
         GlobalExecutor::spawn(
@@ -3,7 +3,6 @@ use crate::contract::MemoryContractHandler;
 use crate::dev_tool::TransportPublicKey;
 use crate::node::p2p_impl::NodeP2P;
 use crate::node::Node;
-use crate::tracing::EventRegister;
 use anyhow::Error;
 use futures::SinkExt;
 use serde::{Deserialize, Serialize};
@@ -73,13 +72,13 @@ impl NetworkPeer {
             {
                 use crate::tracing::OTEventRegister;
                 crate::tracing::CombinedRegister::new([
-                    Box::new(EventRegister::new(self.config.config.event_log())),
+                    Box::new(self.config.event_register()),
                     Box::new(OTEventRegister::new()),
                 ])
             }
             #[cfg(not(feature = "trace-ot"))]
             {
-                EventRegister::new(self.config.config.event_log())
+                self.config.event_register()
             }
         };
         let node = NodeP2P::build::<MemoryContractHandler, CLIENTS, _>(
@@ -5,6 +5,7 @@ use std::{pin::Pin, time::Duration};
 use freenet_stdlib::prelude::ContractKey;
 use futures::Future;
 use tokio::sync::mpsc::error::SendError;
+use tracing::Instrument;
 
 use crate::{
     client_events::HostResult,
@@ -16,7 +17,11 @@ use crate::{
 
 pub(crate) mod connect;
 pub(crate) mod get;
+pub(crate) mod leave;
+pub(crate) mod probe;
 pub(crate) mod put;
+pub(crate) mod relocate;
+pub(crate) mod state_transfer;
 pub(crate) mod subscribe;
 pub(crate) mod update;
 
@@ -66,15 +71,37 @@ where
     Op: Operation,
     NB: NetworkBridge,
 {
-    let sender;
     let tx = *msg.id();
-    let result = {
-        let OpInitialization { sender: s, op } = Op::load_or_init(op_manager, msg).await?;
-        sender = s;
-        op.process_message(network_bridge, op_manager, msg).await
-    };
+    async {
+        if msg.is_response() && !op_manager.has_op(&tx) {
+            // a retransmitted or late response, there is no operation left for it to drive
+            // and initializing a new one from it would only end up in an invalid state
+            tracing::debug!("Ignoring response for an operation not in progress");
+            op_manager.redundant_gets().late_response(&tx);
+            return Err(OpNotAvailable::Completed.into());
+        }
+        let OpInitialization { sender, op } = Op::load_or_init(op_manager, msg).await?;
+        let result = op.process_message(network_bridge, op_manager, msg).await;
+        handle_op_result(op_manager, network_bridge, result, tx, sender).await
+    }
+    .instrument(op_span(op_manager, msg))
+    .await
+}
 
-    handle_op_result(op_manager, network_bridge, result, tx, sender).await
+/// Span scoping the processing of an operation message at this peer, including the messages
+/// sent as a result, so the logs of every peer involved in a transaction can be correlated.
+fn op_span<M: InnerMessage>(op_manager: &OpManager, msg: &M) -> tracing::Span {
+    let tx = msg.id();
+    let span = tracing::info_span!(
+        "op",
+        tx = %tx,
+        tx_type = %tx.transaction_type(),
+        hop = tracing::field::Empty
+    );
+    if let Some(htl) = msg.hops_to_live() {
+        span.record("hop", op_manager.ring.max_hops_to_live.saturating_sub(htl));
+    }
+    span
 }
 
 #[inline(always)]
@@ -160,6 +187,7 @@ pub(crate) enum OpEnum {
     Get(get::GetOp),
     Subscribe(subscribe::SubscribeOp),
     Update(update::UpdateOp),
+    Probe(probe::ProbeOp),
 }
 
 impl OpEnum {
@@ -170,6 +198,7 @@ impl OpEnum {
             OpEnum::Get(op) => op,
             OpEnum::Subscribe(op) => op,
             OpEnum::Update(op) => op,
+            OpEnum::Probe(op) => op,
         } {
             pub fn id(&self) -> &Transaction;
             pub fn outcome(&self) -> OpOutcome;
@@ -205,6 +234,7 @@ try_from_op_enum!(
     TransactionType::Subscribe
 );
 try_from_op_enum!(OpEnum::Update, update::UpdateOp, TransactionType::Update);
+try_from_op_enum!(OpEnum::Probe, probe::ProbeOp, TransactionType::Probe);
 
 pub(crate) enum OpOutcome<'a> {
     /// An op which involves a contract completed successfully.
@@ -243,11 +273,15 @@ pub(crate) enum OpError {
 
     #[error("unexpected operation state")]
     UnexpectedOpState,
-    #[error("cannot perform a state transition from the current state with the provided input (tx: {tx})")]
-    InvalidStateTransition {
+    #[error(
+        "cannot perform a state transition from state `{from}` with input `{input}` (tx: {tx})"
+    )]
+    InvalidTransition {
         tx: Transaction,
-        #[cfg(debug_assertions)]
-        state: Option<Box<dyn std::fmt::Debug + Send + Sync>>,
+        /// The state the operation was in when the input was received.
+        from: String,
+        /// The input that triggered the invalid transition.
+        input: String,
         #[cfg(debug_assertions)]
         trace: StdTrace,
     },
@@ -270,28 +304,19 @@ pub(crate) enum OpError {
 }
 
 impl OpError {
-    pub fn invalid_transition(tx: Transaction) -> Self {
-        Self::InvalidStateTransition {
-            tx,
-            #[cfg(debug_assertions)]
-            state: None,
-            #[cfg(debug_assertions)]
-            trace: StdTrace::force_capture(),
-        }
-    }
-
-    pub fn invalid_transition_with_state(
+    /// An invalid transition for the operation, keeping track of the state the operation
+    /// was in (if any) and the offending input.
+    pub fn invalid_transition<S: std::fmt::Display>(
         tx: Transaction,
-        state: Box<dyn std::fmt::Debug + Send + Sync>,
+        from: Option<&S>,
+        input: impl std::fmt::Display,
     ) -> Self {
-        #[cfg(not(debug_assertions))]
-        {
-            let _ = state;
-        }
-        Self::InvalidStateTransition {
+        Self::InvalidTransition {
             tx,
-            #[cfg(debug_assertions)]
-            state: Some(state),
+            from: from
+                .map(|state| state.to_string())
+                .unwrap_or_else(|| "None".to_owned()),
+            input: input.to_string(),
             #[cfg(debug_assertions)]
             trace: StdTrace::force_capture(),
         }
@@ -304,7 +329,16 @@ impl<T> From<SendError<T>> for OpError {
     }
 }
 
-/// If the contract is not found, it will try to get it first if the `try_get` parameter is set.
+/// Hops to live for a request being forwarded to the next hop.
+///
+/// The received hops to live are capped to `max_htl`, so peers cannot make requests travel
+/// further than allowed. Returns `None` if the request must not be forwarded any further.
+pub(crate) fn forward_htl(htl: usize, max_htl: usize) -> Option<usize> {
+    htl.min(max_htl).checked_sub(1)
+}
+
+/// If the contract is not found, it will get it along with the subscription if the `try_get`
+/// parameter is set.
 async fn start_subscription_request(
     op_manager: &OpManager,
     key: ContractKey,
@@ -318,8 +352,8 @@ async fn start_subscription_request(
             return;
         }
         if let OpError::ContractError(ContractError::ContractNotFound(key)) = &error {
-            tracing::debug!(%key, "Contract not found, trying to get it first");
-            let get_op = get::start_op(*key, true);
+            tracing::debug!(%key, "Contract not found, getting it along with the subscription");
+            let get_op = get::start_op_and_subscribe(*key, true);
             if let Err(error) = get::request_get(op_manager, get_op, skip_list).await {
                 tracing::warn!(%error, "Error getting contract");
             }
@@ -344,3 +378,119 @@ async fn has_contract(op_manager: &OpManager, key: ContractKey) -> Result<bool,
         _ => Ok(false),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+    use crate::test_utils::{op_manager, RecordingBridge};
+
+    /// Writer keeping the formatted logs in memory.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_scoped_in_transaction_span() -> anyhow::Result<()> {
+        let op_manager = op_manager("logs_scoped_in_transaction_span").await?;
+
+        let tx = Transaction::new::<get::GetMsg>();
+        let routed = get::GetMsg::SeekNode {
+            id: tx,
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            fetch_contract: false,
+            target: PeerKeyLocation::random(),
+            sender: PeerKeyLocation::random(),
+            htl: op_manager.ring.max_hops_to_live - 2,
+            skip_list: vec![],
+        };
+        let response = get::GetMsg::ReturnGet {
+            id: tx,
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            value: crate::contract::StoreResponse {
+                state: None,
+                contract: None,
+            },
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            skip_list: vec![],
+        };
+
+        let logs = LogBuffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            op_span(&op_manager, &routed).in_scope(|| tracing::info!("forwarding"));
+            op_span(&op_manager, &response).in_scope(|| tracing::info!("returning"));
+        });
+
+        let logs = String::from_utf8(logs.0.lock().clone())?;
+        let mut lines = logs.lines();
+        let tx_type = tx.transaction_type();
+        let routed = lines.next().expect("routed message logged");
+        assert!(
+            routed.contains(&format!("op{{tx={tx} tx_type={tx_type} hop=2}}: ")),
+            "{routed}"
+        );
+        assert!(routed.ends_with("forwarding"));
+        let response = lines.next().expect("response logged");
+        assert!(
+            response.contains(&format!("op{{tx={tx} tx_type={tx_type}}}: ")),
+            "{response}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn late_responses_ignored() -> anyhow::Result<()> {
+        let op_manager = op_manager("late_responses_ignored").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        // a response for an operation this node never started or already finished
+        let tx = Transaction::new::<get::GetMsg>();
+        let response = get::GetMsg::ReturnGet {
+            id: tx,
+            key,
+            value: crate::contract::StoreResponse {
+                state: None,
+                contract: None,
+            },
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            skip_list: vec![],
+        };
+        let mut bridge = RecordingBridge::default();
+        let result = handle_op_request::<get::GetOp, _>(&op_manager, &mut bridge, &response).await;
+        assert!(matches!(
+            result,
+            Err(OpError::OpNotAvailable(OpNotAvailable::Completed))
+        ));
+        assert!(!op_manager.has_op(&tx));
+        assert!(bridge.sent().is_empty());
+
+        // responses are only expected while the operation is in progress
+        let op = get::start_op(key, false);
+        let tx = op.id;
+        assert!(!op_manager.has_op(&tx));
+        op_manager.push(tx, OpEnum::Get(op)).await?;
+        assert!(op_manager.has_op(&tx));
+        Ok(())
+    }
+}
@@ -17,7 +17,7 @@ use crate::router::Router;
 use crate::transport::TransportPublicKey;
 use crate::{
     message::{InnerMessage, NetMessage, Transaction},
-    node::{NetworkBridge, OpManager, PeerId},
+    node::{NetworkBridge, NodeLifecycleEvent, OpManager, PeerId},
     operations::OpEnum,
     ring::PeerKeyLocation,
     util::ExponentialBackoff,
@@ -193,6 +193,8 @@ impl Operation for ConnectOp {
                                 desirable_peer = %desirable_peer.peer,
                                 "Found a desirable peer to connect to",
                             );
+                            // the request may come back to this node if there is a cycle in the ring
+                            op_manager.first_visit(*id);
                             let msg = create_forward_message(
                                 *id,
                                 &own_loc,
@@ -267,6 +269,53 @@ impl Operation for ConnectOp {
                         std::process::exit(1);
                     }
                     let this_peer = op_manager.ring.connection_manager.own_location();
+                    if !op_manager.first_visit(*id) {
+                        tracing::debug!(
+                            tx = %id,
+                            at = %this_peer.peer,
+                            joiner = %joiner.peer,
+                            "Connectivity check already seen by this peer, rejecting"
+                        );
+                        return build_op_result(
+                            self.id,
+                            self.state,
+                            Some(ConnectMsg::Response {
+                                id: *id,
+                                sender: this_peer.clone(),
+                                msg: ConnectResponse::AcceptedBy {
+                                    accepted: false,
+                                    acceptor: this_peer,
+                                    joiner: joiner.peer.clone(),
+                                },
+                                target: sender.clone(),
+                            }),
+                            self.gateway,
+                            self.backoff,
+                        );
+                    }
+                    if op_manager.ring.connection_manager.is_busy() {
+                        tracing::debug!(
+                            tx = %id,
+                            at = %this_peer.peer,
+                            joiner = %joiner.peer,
+                            "Out of resources for joins, replying busy"
+                        );
+                        return build_op_result(
+                            self.id,
+                            None,
+                            Some(ConnectMsg::Response {
+                                id: *id,
+                                sender: this_peer.clone(),
+                                msg: ConnectResponse::Busy {
+                                    acceptor: this_peer,
+                                    joiner: joiner.peer.clone(),
+                                },
+                                target: sender.clone(),
+                            }),
+                            self.gateway,
+                            self.backoff,
+                        );
+                    }
                     let joiner_loc = joiner
                         .location
                         .expect("should be already set at the p2p bridge level");
@@ -364,13 +413,10 @@ impl Operation for ConnectOp {
                     id,
                     sender,
                     target,
-                    msg:
-                        ConnectResponse::AcceptedBy {
-                            accepted,
-                            acceptor,
-                            joiner,
-                        },
+                    msg: response,
                 } => {
+                    let accepted = response.accepted();
+                    let acceptor = response.acceptor();
                     tracing::debug!(
                         tx = %id,
                         at = %target.peer,
@@ -389,7 +435,7 @@ impl Operation for ConnectOp {
                             assert!(info.remaining_connections > 0);
                             let remaining_connetions = info.remaining_connections.saturating_sub(1);
 
-                            if *accepted {
+                            if accepted {
                                 tracing::debug!(
                                     tx = %id,
                                     at = %this_peer_id,
@@ -414,6 +460,17 @@ impl Operation for ConnectOp {
                                     rejected_peer = %acceptor.peer,
                                     "Connection rejected",
                                 );
+                                match response {
+                                    ConnectResponse::Busy { .. } => op_manager
+                                        .ring
+                                        .connection_manager
+                                        .backoff_busy_peer(&acceptor.peer),
+                                    ConnectResponse::RateLimited { retry_after, .. } => op_manager
+                                        .ring
+                                        .connection_manager
+                                        .backoff_peer(&acceptor.peer, *retry_after),
+                                    ConnectResponse::AcceptedBy { .. } => {}
+                                }
                             }
 
                             let your_location: Location =
@@ -429,6 +486,9 @@ impl Operation for ConnectOp {
                                 .connection_manager
                                 .update_location(target.location);
 
+                            // the next state is built from the updated info, not the one the
+                            // response was received in
+                            info.remaining_connections = remaining_connetions;
                             if remaining_connetions == 0 {
                                 tracing::debug!(
                                     tx = %id,
@@ -477,15 +537,10 @@ impl Operation for ConnectOp {
                                         requester: requester.clone(),
                                     }));
                             }
-                            let response = ConnectResponse::AcceptedBy {
-                                accepted: *accepted,
-                                acceptor: acceptor.clone(),
-                                joiner: joiner.clone(),
-                            };
                             return_msg = Some(ConnectMsg::Response {
                                 id: *id,
                                 sender: target.clone(),
-                                msg: response,
+                                msg: response.clone(),
                                 target: requester.clone(),
                             });
                         }
@@ -543,7 +598,13 @@ impl Operation for ConnectOp {
                         }
                     }
                 }
-                _ => return Err(OpError::UnexpectedOpState),
+                _ => {
+                    return Err(OpError::invalid_transition(
+                        self.id,
+                        self.state.as_ref(),
+                        input,
+                    ))
+                }
             }
 
             build_op_result(self.id, new_state, return_msg, self.gateway, self.backoff)
@@ -642,11 +703,44 @@ pub(crate) struct NewConnectionInfo {
 }
 
 impl ConnectState {
-    fn try_unwrap_connecting(self) -> Result<ConnectionInfo, OpError> {
+    fn try_unwrap_connecting(self, tx: Transaction) -> Result<ConnectionInfo, OpError> {
         if let Self::ConnectingToNode(conn_info) = self {
             Ok(conn_info)
         } else {
-            Err(OpError::UnexpectedOpState)
+            Err(OpError::invalid_transition(
+                tx,
+                Some(&self),
+                "ConnectRequest",
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectState::Initializing => write!(f, "Initializing"),
+            ConnectState::ConnectingToNode(info) => write!(
+                f,
+                "ConnectingToNode(gateway: {}, accepted_by: {}, remaining_connections: {})",
+                info.gateway.peer,
+                info.accepted_by.len(),
+                info.remaining_connections
+            ),
+            ConnectState::AwaitingConnectivity(info) => write!(
+                f,
+                "AwaitingConnectivity(requester: {}, remaining_checks: {})",
+                info.requester.peer, info.remaining_checks
+            ),
+            ConnectState::AwaitingConnectionAcquisition => {
+                write!(f, "AwaitingConnectionAcquisition")
+            }
+            ConnectState::AwaitingNewConnection(info) => write!(
+                f,
+                "AwaitingNewConnection(remaining_connections: {})",
+                info.remaining_connetions
+            ),
+            ConnectState::Connected => write!(f, "Connected"),
         }
     }
 }
@@ -658,6 +752,15 @@ impl ConnectState {
 ///   (to gateways or regular peers) will be treated as regular connections.
 ///
 /// - is_gateway: Whether this peer is a gateway or not.
+///
+/// The neighbours of the previous sessions are dialed directly first, and the node only joins
+/// through the gateways if none of them could be reached. Connecting through the gateways is
+/// retried until a first connection is established; if the node loses all its connections later
+/// on the procedure is started again by the event loop.
+/// Join attempts fired per gateway connection needed, so a flaky gateway does not hold up the
+/// join; the surplus attempts are cancelled once enough connections are established.
+const ATTEMPTS_PER_CONNECTION: usize = 2;
+
 pub(crate) async fn initial_join_procedure(
     op_manager: Arc<OpManager>,
     gateways: &[PeerKeyLocation],
@@ -667,54 +770,185 @@ pub(crate) async fn initial_join_procedure(
         let max_potential_conns_per_gw = op_manager.ring.max_hops_to_live;
         // e.g. 10 gateways and htl 5 -> only need 2 connections in parallel
         let needed_to_cover_max =
-            op_manager.ring.connection_manager.max_connections / max_potential_conns_per_gw;
+            op_manager.ring.connection_manager.max_connections() / max_potential_conns_per_gw;
         gateways.iter().take(needed_to_cover_max).count().max(1)
     };
     let gateways = gateways.to_vec();
     tokio::task::spawn(async move {
-        if gateways.is_empty() {
+        let reconnected = reconnect_known_peers(&op_manager).await;
+        if reconnected > 0 {
+            tracing::info!(
+                neighbours = reconnected,
+                "Reconnected to the neighbours of the previous session"
+            );
+        }
+        if gateways.is_empty() && op_manager.ring.open_connections() == 0 {
             tracing::warn!("No gateways available, aborting join procedure");
             return;
         }
-        loop {
-            if op_manager.ring.open_connections() == 0 {
-                tracing::info!(
-                    "Attempting to connect to {} gateways in parallel",
-                    number_of_parallel_connections
-                );
-                for gateway in op_manager
-                    .ring
-                    .is_not_connected(gateways.iter())
-                    .shuffle()
-                    .take(number_of_parallel_connections)
-                {
-                    tracing::info!(%gateway, "Attempting connection to gateway");
-                    if let Err(error) = join_ring_request(None, gateway, &op_manager).await {
-                        if !matches!(
-                            error,
-                            OpError::ConnError(crate::node::ConnectionError::UnwantedConnection)
-                        ) {
-                            tracing::error!(%error, "Failed while attempting connection to gateway");
+        // join attempts left running by previous rounds
+        let mut in_flight: Vec<(Transaction, &PeerKeyLocation)> = vec![];
+        while op_manager.ring.open_connections() == 0 {
+            in_flight.retain(|(tx, _)| op_manager.has_op(tx));
+            let max_attempts = (number_of_parallel_connections * ATTEMPTS_PER_CONNECTION)
+                .saturating_sub(in_flight.len());
+            tracing::info!(
+                "Attempting to connect to {} gateways in parallel",
+                number_of_parallel_connections
+            );
+            let attempts = op_manager
+                .ring
+                .is_not_connected(gateways.iter().filter(|gateway| {
+                    // skip gateways which recently replied they are busy or are being joined
+                    // already
+                    !op_manager
+                        .ring
+                        .connection_manager
+                        .is_backing_off(&gateway.peer)
+                        && !in_flight.iter().any(|(_, joining)| joining.peer == gateway.peer)
+                }))
+                .shuffle()
+                .take(max_attempts)
+                .map(|gateway| {
+                    let op_manager = &op_manager;
+                    async move {
+                        tracing::info!(%gateway, "Attempting connection to gateway");
+                        match join_ring_request(None, gateway, op_manager).await {
+                            Ok(tx) => Some((tx, gateway)),
+                            Err(error) => {
+                                if !matches!(
+                                    error,
+                                    OpError::ConnError(
+                                        crate::node::ConnectionError::UnwantedConnection
+                                    )
+                                ) {
+                                    tracing::error!(%error, "Failed while attempting connection to gateway");
+                                }
+                                None
+                            }
                         }
                     }
-                }
-            }
+                });
+            let started = futures::future::join_all(attempts).await;
+            in_flight.extend(started.into_iter().flatten());
             #[cfg(debug_assertions)]
             const WAIT_TIME: u64 = 15;
             #[cfg(not(debug_assertions))]
             const WAIT_TIME: u64 = 3;
-            tokio::time::sleep(Duration::from_secs(WAIT_TIME)).await;
+            keep_first_gateway_connections(
+                &op_manager,
+                &mut in_flight,
+                number_of_parallel_connections,
+                Duration::from_secs(WAIT_TIME),
+            )
+            .await;
         }
     });
     Ok(())
 }
 
+/// Dials the most recently seen neighbours of the previous sessions which this node is not
+/// connected to, waiting for them to answer, and returns how many were connected to. If this
+/// node has not taken a place in the ring yet, it takes the one it had in the previous session.
+async fn reconnect_known_peers(op_manager: &OpManager) -> usize {
+    /// Time to wait for the known peers to answer before falling back to the gateways.
+    const MAX_WAIT: Duration = Duration::from_secs(5);
+
+    let connection_manager = &op_manager.ring.connection_manager;
+    let known = connection_manager
+        .known_peers
+        .recent(connection_manager.min_connections());
+    let attempts = known
+        .into_iter()
+        .filter(|known| {
+            connection_manager.location_of(&known.peer).is_none()
+                && !connection_manager.is_backing_off(&known.peer)
+        })
+        .map(|known| async move {
+            let (callback, mut result) = tokio::sync::mpsc::channel(1);
+            op_manager
+                .notify_node_event(NodeEvent::ConnectPeer {
+                    peer: known.peer.clone(),
+                    tx: Transaction::new::<ConnectMsg>(),
+                    callback,
+                    is_gw: false,
+                })
+                .await
+                .ok()?;
+            match tokio::time::timeout(MAX_WAIT, result.recv()).await {
+                Ok(Some(Ok((own, _)))) => Some((known, own)),
+                _ => {
+                    tracing::debug!(peer = %known.peer, "Failed reconnecting to known peer");
+                    None
+                }
+            }
+        });
+    let mut reconnected = 0;
+    for (known, own) in futures::future::join_all(attempts)
+        .await
+        .into_iter()
+        .flatten()
+    {
+        if connection_manager.try_set_peer_key(own.addr).is_none() {
+            connection_manager.update_location(connection_manager.known_peers.location());
+        }
+        op_manager
+            .ring
+            .add_connection(known.location, known.peer, false)
+            .await;
+        reconnected += 1;
+    }
+    reconnected
+}
+
+/// Waits up to `max_wait` for the parallel join attempts against the gateways to succeed and,
+/// once the first `required` gateway connections are established, cancels the attempts still
+/// pending and returns.
+///
+/// If not enough connections are established in time, the pending attempts are left to
+/// run their course, and are kept in `pending`.
+async fn keep_first_gateway_connections(
+    op_manager: &OpManager,
+    pending: &mut Vec<(Transaction, &PeerKeyLocation)>,
+    required: usize,
+    max_wait: Duration,
+) {
+    const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+    let attempted = pending.len();
+    let required = required.min(attempted);
+    let started = tokio::time::Instant::now();
+    while started.elapsed() < max_wait {
+        tokio::time::sleep(CHECK_INTERVAL.min(max_wait)).await;
+        pending.retain(|(_, gateway)| {
+            op_manager
+                .ring
+                .is_not_connected(std::iter::once(*gateway))
+                .next()
+                .is_some()
+        });
+        let connected = attempted - pending.len();
+        if attempted > 0 && connected >= required {
+            for (tx, gateway) in pending.drain(..) {
+                tracing::debug!(
+                    %tx,
+                    gateway = %gateway.peer,
+                    "Enough gateway connections established, cancelling join attempt"
+                );
+                // any further messages for this join attempt will be ignored
+                op_manager.completed(tx);
+            }
+            return;
+        }
+    }
+}
+
 #[tracing::instrument(fields(peer = %op_manager.ring.connection_manager.pub_key), skip_all)]
 pub(crate) async fn join_ring_request(
     backoff: Option<ExponentialBackoff>,
     gateway: &PeerKeyLocation,
     op_manager: &OpManager,
-) -> Result<(), OpError> {
+) -> Result<Transaction, OpError> {
     use crate::node::ConnectionError;
     if !op_manager.ring.connection_manager.should_accept(
         gateway.location.ok_or_else(|| {
@@ -742,7 +976,7 @@ pub(crate) async fn join_ring_request(
                 // if connections where established the peer should incrementally acquire more over time
                 return Err(OpError::MaxRetriesExceeded(tx_id, tx_id.transaction_type()));
             } else {
-                return Ok(());
+                return Ok(tx_id);
             }
         }
         // on first run the backoff will be initialized at the `initial_request` function
@@ -751,7 +985,7 @@ pub(crate) async fn join_ring_request(
         op.backoff = Some(backoff);
     }
     connect_request(tx_id, op_manager, op).await?;
-    Ok(())
+    Ok(tx_id)
 }
 
 fn initial_request(
@@ -791,7 +1025,7 @@ async fn connect_request(
     let ConnectOp {
         id, state, backoff, ..
     } = join_op;
-    let ConnectionInfo { gateway, .. } = state.expect("infallible").try_unwrap_connecting()?;
+    let ConnectionInfo { gateway, .. } = state.expect("infallible").try_unwrap_connecting(id)?;
 
     tracing::info!(
         tx = %id,
@@ -818,6 +1052,13 @@ async fn connect_request(
                     true,
                 )
                 .await;
+            op_manager
+                .ring
+                .lifecycle_events
+                .emit(NodeLifecycleEvent::ConnectedToGateway {
+                    gateway: gateway.peer.clone(),
+                    location: gateway.location.expect("location not found"),
+                });
             let Some(remaining_connetions) = remaining_checks else {
                 tracing::error!(tx = %id, "Failed to connect to gateway, missing remaining checks");
                 return Err(OpError::ConnError(
@@ -880,14 +1121,14 @@ where
         req_peer,
         joiner,
     } = params;
-    if left_htl == 0 {
+    let Some(next_htl) = super::forward_htl(left_htl, max_htl) else {
         tracing::debug!(
             tx = %id,
             joiner = %joiner.peer,
             "Couldn't forward connect petition, no hops left or enough connections",
         );
         return Ok(None);
-    }
+    };
 
     if connection_manager.num_connections() == 0 {
         tracing::warn!(
@@ -918,7 +1159,7 @@ where
                 &req_peer,
                 &joiner,
                 &target_peer,
-                left_htl,
+                next_htl,
                 max_htl,
                 skip_list,
             );
@@ -945,7 +1186,12 @@ fn select_forward_target(
             joiner = %joiner.peer,
             "Randomly selecting peer to forward connect request",
         );
-        connection_manager.random_peer(|p| !skip_list.contains(p))
+        match joiner.location {
+            Some(location) => {
+                connection_manager.random_peer_towards(location, |p| !skip_list.contains(p))
+            }
+            None => connection_manager.random_peer(|p| !skip_list.contains(p)),
+        }
     } else {
         tracing::debug!(
             tx = %id,
@@ -978,7 +1224,7 @@ fn create_forward_message(
         msg: ConnectRequest::CheckConnectivity {
             sender: request_peer.clone(),
             joiner: joiner.clone(),
-            hops_to_live: hops_to_live.saturating_sub(1), // decrement the hops to live for the next hop
+            hops_to_live,
             max_hops_to_live,
             skip_list,
         },
@@ -1057,6 +1303,10 @@ mod messages {
         fn requested_location(&self) -> Option<Location> {
             self.target().and_then(|pkloc| pkloc.borrow().location)
         }
+
+        fn is_response(&self) -> bool {
+            matches!(self, Self::Response { .. })
+        }
     }
 
     impl ConnectMsg {
@@ -1102,6 +1352,24 @@ mod messages {
                     f,
                     "AcceptedBy(id: {id}, target: {target}, accepted: {accepted}, acceptor: {acceptor})"
                 ),
+                Self::Response {
+                    target,
+                    msg: ConnectResponse::Busy { acceptor, .. },
+                    ..
+                } => write!(f, "Busy(id: {id}, target: {target}, acceptor: {acceptor})"),
+                Self::Response {
+                    target,
+                    msg:
+                        ConnectResponse::RateLimited {
+                            acceptor,
+                            retry_after,
+                            ..
+                        },
+                    ..
+                } => write!(
+                    f,
+                    "RateLimited(id: {id}, target: {target}, acceptor: {acceptor}, retry_after: {retry_after:?})"
+                ),
                 Self::Connected { .. } => write!(f, "Connected(id: {id})"),
                 ConnectMsg::Request { id, target, .. } => write!(f, "Request(id: {id}, target: {target})"),
             }
@@ -1150,5 +1418,119 @@ mod messages {
             acceptor: PeerKeyLocation,
             joiner: PeerId,
         },
+        /// The peer is out of resources to take part in joins, the joiner should back off
+        /// from it for a while.
+        Busy {
+            acceptor: PeerKeyLocation,
+            joiner: PeerId,
+        },
+        /// The gateway refused the join because the joiner address exhausted its join quota,
+        /// it can be retried after the given time.
+        RateLimited {
+            acceptor: PeerKeyLocation,
+            joiner: PeerId,
+            retry_after: Duration,
+        },
+    }
+
+    impl ConnectResponse {
+        pub fn accepted(&self) -> bool {
+            matches!(self, Self::AcceptedBy { accepted: true, .. })
+        }
+
+        pub fn acceptor(&self) -> &PeerKeyLocation {
+            match self {
+                Self::AcceptedBy { acceptor, .. }
+                | Self::Busy { acceptor, .. }
+                | Self::RateLimited { acceptor, .. } => acceptor,
+            }
+        }
+
+        pub fn joiner(&self) -> &PeerId {
+            match self {
+                Self::AcceptedBy { joiner, .. }
+                | Self::Busy { joiner, .. }
+                | Self::RateLimited { joiner, .. } => joiner,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::OpNotAvailable;
+
+    #[tokio::test]
+    async fn pending_gateway_joins_cancelled_once_connected() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("pending_gateway_joins_cancelled_once_connected").await?;
+        op_manager
+            .ring
+            .connection_manager
+            .try_set_peer_key(PeerId::random().addr);
+        let (connected, pending) = (PeerKeyLocation::random(), PeerKeyLocation::random());
+        let (connected_tx, pending_tx) = (
+            Transaction::new::<ConnectMsg>(),
+            Transaction::new::<ConnectMsg>(),
+        );
+
+        // no gateway connection is established in time, the attempts are left running
+        let max_wait = Duration::from_millis(500);
+        let started = std::time::Instant::now();
+        let mut in_flight = vec![(connected_tx, &connected), (pending_tx, &pending)];
+        keep_first_gateway_connections(&op_manager, &mut in_flight, 1, max_wait).await;
+        assert!(started.elapsed() >= max_wait);
+        assert_eq!(in_flight.len(), 2);
+        assert!(!matches!(
+            op_manager.pop(&pending_tx),
+            Err(OpNotAvailable::Completed)
+        ));
+
+        // once the first gateway is connected the other attempt is cancelled right away
+        op_manager.ring.connection_manager.add_connection(
+            Location::random(),
+            connected.peer.clone(),
+            false,
+        );
+        let max_wait = Duration::from_secs(60);
+        let started = std::time::Instant::now();
+        keep_first_gateway_connections(&op_manager, &mut in_flight, 1, max_wait).await;
+        assert!(started.elapsed() < max_wait);
+        assert!(in_flight.is_empty());
+        assert!(matches!(
+            op_manager.pop(&pending_tx),
+            Err(OpNotAvailable::Completed)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_transitions_report_state_and_input() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("connect_invalid_transitions_report_state_and_input")
+                .await?;
+
+        let tx = Transaction::new::<ConnectMsg>();
+        let op = ConnectOp {
+            id: tx,
+            state: Some(ConnectState::Connected),
+            gateway: None,
+            backoff: None,
+        };
+        let error = connect_request(tx, &op_manager, op).await.unwrap_err();
+        let OpError::InvalidTransition {
+            tx: failed,
+            from,
+            input,
+            ..
+        } = &error
+        else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(*failed, tx);
+        assert_eq!(from, "Connected");
+        assert_eq!(input, "ConnectRequest");
+        Ok(())
     }
 }
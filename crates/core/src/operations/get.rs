@@ -1,15 +1,18 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::pin::Pin;
-use std::{future::Future, time::Instant};
+use std::{future::Future, sync::Arc, time::Instant};
 
 use freenet_stdlib::client_api::{ErrorKind, HostResponse};
 use freenet_stdlib::prelude::*;
 
+use tokio::sync::mpsc;
+
 use crate::client_events::HostResult;
 use crate::{
     contract::{ContractHandlerEvent, StoreResponse},
-    message::{InnerMessage, NetMessage, Transaction},
-    node::{NetworkBridge, OpManager, PeerId},
+    message::{InnerMessage, NetMessage, NetMessageV1, Transaction, TransactionType},
+    node::{NetworkBridge, OpManager, OpNotAvailable, PeerId},
     operations::{OpInitialization, Operation},
     ring::{Location, PeerKeyLocation, RingError},
 };
@@ -17,18 +20,35 @@ use crate::{
 use super::{OpEnum, OpError, OpOutcome, OperationResult};
 
 pub(crate) use self::messages::GetMsg;
+pub(crate) use self::redundancy::{RedundancyMetrics, RedundantGets};
+
+mod redundancy;
 
 /// Maximum number of retries to get values.
 const MAX_RETRIES: usize = 10;
 
+/// Max number of get requests sent together, and processed from a batch received.
+pub(crate) const MAX_BATCH_SIZE: usize = 32;
+
 pub(crate) fn start_op(key: ContractKey, fetch_contract: bool) -> GetOp {
+    new_op(key, fetch_contract, false)
+}
+
+/// Gets the contract and subscribes to it in a single operation: the request is routed once,
+/// and the peers returning the state along the path register the subscription as they do.
+pub(crate) fn start_op_and_subscribe(key: ContractKey, fetch_contract: bool) -> GetOp {
+    new_op(key, fetch_contract, true)
+}
+
+fn new_op(key: ContractKey, fetch_contract: bool, subscribe: bool) -> GetOp {
     let contract_location = Location::from(&key);
     let id = Transaction::new::<GetMsg>();
-    tracing::debug!(tx = %id, "Requesting get contract {key} @ loc({contract_location})");
+    tracing::debug!(tx = %id, subscribe, "Requesting get contract {key} @ loc({contract_location})");
     let state = Some(GetState::PrepareRequest {
         key,
         id,
         fetch_contract,
+        subscribe,
     });
     GetOp {
         id,
@@ -63,7 +83,11 @@ pub(crate) async fn request_get(
             *id,
         )
     } else {
-        return Err(OpError::UnexpectedOpState);
+        return Err(OpError::invalid_transition(
+            get_op.id,
+            get_op.state.as_ref(),
+            "RequestGet",
+        ));
     };
     tracing::debug!(
         tx = %id,
@@ -76,15 +100,25 @@ pub(crate) async fn request_get(
             fetch_contract,
             key,
             id,
-            ..
+            subscribe,
         }) => {
             let new_state = Some(GetState::AwaitingResponse {
                 retries: 0,
                 fetch_contract,
+                subscribe,
                 requester: None,
                 current_hop: op_manager.ring.max_hops_to_live,
             });
 
+            let redundant = redundant_requests(
+                op_manager,
+                id,
+                key,
+                (fetch_contract, subscribe),
+                &skip_list,
+                &target,
+            );
+
             let msg = GetMsg::RequestGet {
                 id,
                 key,
@@ -106,12 +140,276 @@ pub(crate) async fn request_get(
             op_manager
                 .notify_op_change(NetMessage::from(msg), OpEnum::Get(op))
                 .await?;
+
+            for (target, request) in redundant {
+                // goes through this node event loop, which forwards it to the target, one by one if
+                // the target does not take batches
+                let batch = NetMessage::V1(NetMessageV1::GetBatch {
+                    transaction: Transaction::new::<GetMsg>(),
+                    target,
+                    requests: vec![request],
+                });
+                op_manager.notify_message(batch).await?;
+            }
+        }
+        _ => {
+            return Err(OpError::invalid_transition(
+                get_op.id,
+                get_op.state.as_ref(),
+                "RequestGet",
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Stops relaying a get request the peer which sent it cancelled, once another of the requests
+/// it sent at the same time was served, and cancels it at the peers it was relayed to. If the
+/// request subscribing to the contract was already served, the subscription it registered for
+/// the peer is dropped instead.
+///
+/// Cancellations from any other peer are ignored, the requests sent at the same time share
+/// their transaction and may meet along the way.
+pub(crate) async fn cancel_request<NB: NetworkBridge>(
+    op_manager: &OpManager,
+    network_bridge: &NB,
+    (id, key, subscribe): (Transaction, ContractKey, bool),
+    from: &PeerId,
+) -> Result<(), OpError> {
+    if id.transaction_type() != TransactionType::Get {
+        return Ok(());
+    }
+    match op_manager.pop(&id) {
+        Ok(Some(OpEnum::Get(op))) if op.requested_by(from) => {
+            tracing::debug!(tx = %id, %from, "Get request cancelled by the requester");
+            let relayed_to = op_manager.ring.live_tx_tracker.peers_of(&id);
+            op_manager.completed(id);
+            for peer in relayed_to.iter().filter(|peer| *peer != from) {
+                let cancel = NetMessage::V1(NetMessageV1::CancelGet {
+                    transaction: id,
+                    key,
+                    subscribe,
+                });
+                if let Err(error) = network_bridge.send(peer, cancel).await {
+                    tracing::debug!(tx = %id, %peer, %error, "Failed cancelling get request");
+                }
+            }
         }
-        _ => return Err(OpError::invalid_transition(get_op.id)),
+        Ok(Some(op)) => op_manager.push(id, op).await?,
+        Ok(None) | Err(OpNotAvailable::Completed) if subscribe => {
+            if op_manager.ring.remove_subscriber(&key, from) {
+                tracing::debug!(tx = %id, %key, %from, "Subscription of cancelled get dropped");
+            }
+        }
+        _ => {}
     }
     Ok(())
 }
 
+/// Requests for the get to send at the same time as the one to the target, to the next best
+/// candidates up to the get parallelism. They are recorded so the first one served cancels the
+/// others.
+fn redundant_requests(
+    op_manager: &OpManager,
+    id: Transaction,
+    key: ContractKey,
+    (fetch_contract, subscribe): (bool, bool),
+    skip_list: &[PeerId],
+    target: &PeerKeyLocation,
+) -> Vec<(PeerKeyLocation, GetMsg)> {
+    let own_loc = op_manager.ring.connection_manager.own_location();
+    let mut skip_list = skip_list.to_vec();
+    skip_list.extend([own_loc.peer.clone(), target.peer.clone()]);
+    let mut targets = vec![];
+    while targets.len() + 1 < op_manager.get_parallelism {
+        let Some(next) = op_manager
+            .ring
+            .closest_potentially_caching(&key, skip_list.as_slice())
+        else {
+            break;
+        };
+        skip_list.push(next.peer.clone());
+        targets.push(next);
+    }
+    if targets.is_empty() {
+        return vec![];
+    }
+    let peers = std::iter::once(target).chain(&targets);
+    op_manager
+        .redundant_gets()
+        .dispatched(id, key, peers.map(|peer| peer.peer.clone()).collect());
+    targets
+        .into_iter()
+        .map(|target| {
+            tracing::debug!(tx = %id, %key, target = %target.peer, "Seek contract redundantly");
+            let request = GetMsg::SeekNode {
+                id,
+                key,
+                fetch_contract,
+                target: target.clone(),
+                sender: own_loc.clone(),
+                htl: op_manager.ring.max_hops_to_live,
+                skip_list: skip_list.clone(),
+            }
+            .subscribing(subscribe);
+            (target, request)
+        })
+        .collect()
+}
+
+/// Requests several contracts at once. Every get is routed on its own towards its contract,
+/// but the requests heading to the same peer travel together in a single message.
+///
+/// Each get succeeds or fails on its own, a failed one does not hold back the rest. Results
+/// are reported per transaction as they arrive.
+pub(crate) async fn request_get_batch(op_manager: &OpManager, get_ops: Vec<GetOp>) {
+    let mut per_target: HashMap<PeerKeyLocation, Vec<GetMsg>> = HashMap::new();
+    for get_op in get_ops {
+        let id = get_op.id;
+        match prepare_batched_request(op_manager, get_op).await {
+            Ok(requests) => {
+                for (target, request) in requests {
+                    per_target.entry(target).or_default().push(request);
+                }
+            }
+            Err(error) => tracing::error!(tx = %id, %error, "Failed requesting contract"),
+        }
+    }
+    for (target, mut requests) in per_target {
+        while !requests.is_empty() {
+            let rest = requests.split_off(requests.len().min(MAX_BATCH_SIZE));
+            // goes through this node event loop, which forwards it to the target, one by one if
+            // the target does not take batches
+            let batch = NetMessage::V1(NetMessageV1::GetBatch {
+                transaction: Transaction::new::<GetMsg>(),
+                target: target.clone(),
+                requests,
+            });
+            if let Err(error) = op_manager.notify_message(batch).await {
+                tracing::error!(target = %target.peer, %error, "Failed sending get batch");
+            }
+            requests = rest;
+        }
+    }
+}
+
+/// Starts awaiting the response to a get sent in a batch, returning the requests to send for
+/// it along with their targets.
+async fn prepare_batched_request(
+    op_manager: &OpManager,
+    get_op: GetOp,
+) -> Result<Vec<(PeerKeyLocation, GetMsg)>, OpError> {
+    let Some(GetState::PrepareRequest {
+        key,
+        id,
+        fetch_contract,
+        subscribe,
+    }) = get_op.state
+    else {
+        return Err(OpError::invalid_transition(
+            get_op.id,
+            get_op.state.as_ref(),
+            "RequestGetBatch",
+        ));
+    };
+    let own_loc = op_manager.ring.connection_manager.own_location();
+    let htl = op_manager.ring.max_hops_to_live;
+    let target = op_manager
+        .ring
+        .closest_potentially_caching(&key, &[])
+        .into_iter()
+        .next()
+        .ok_or(RingError::EmptyRing)?;
+    tracing::debug!(tx = %id, %key, target = %target.peer, "Seek contract in batch");
+    // the request may come back to this node if there is a cycle in the ring
+    op_manager.first_visit(id);
+    let op = GetOp {
+        id,
+        state: Some(GetState::AwaitingResponse {
+            retries: 0,
+            fetch_contract,
+            subscribe,
+            requester: None,
+            current_hop: htl,
+        }),
+        result: None,
+        stats: get_op.stats.map(|mut s| {
+            s.next_peer = Some(target.clone());
+            s
+        }),
+    };
+    op_manager.push(id, OpEnum::Get(op)).await?;
+    let mut requests = redundant_requests(
+        op_manager,
+        id,
+        key,
+        (fetch_contract, subscribe),
+        &[],
+        &target,
+    );
+    requests.push((
+        target.clone(),
+        GetMsg::SeekNode {
+            id,
+            key,
+            fetch_contract,
+            target,
+            sender: own_loc.clone(),
+            htl,
+            skip_list: vec![own_loc.peer],
+        }
+        .subscribing(subscribe),
+    ));
+    Ok(requests)
+}
+
+/// Sends the get requests started by local clients; the ones started while a previous request
+/// was being sent are sent together as a batch.
+#[derive(Clone)]
+pub(crate) struct GetBatcher(mpsc::Sender<GetOp>);
+
+impl GetBatcher {
+    pub fn new(op_manager: Arc<OpManager>) -> (Self, impl Future<Output = ()>) {
+        let (tx, rx) = mpsc::channel(MAX_BATCH_SIZE * 4);
+        (Self(tx), batch_gets(op_manager, rx))
+    }
+
+    pub async fn request_get(&self, get_op: GetOp) -> Result<(), OpError> {
+        self.0
+            .send(get_op)
+            .await
+            .map_err(|_| OpError::NotificationError)
+    }
+}
+
+async fn batch_gets(op_manager: Arc<OpManager>, mut requests: mpsc::Receiver<GetOp>) {
+    while let Some(first) = requests.recv().await {
+        let mut batch = next_batch(first, &mut requests);
+        if batch.len() == 1 {
+            let get_op = batch.remove(0);
+            let id = get_op.id;
+            if let Err(error) = request_get(&op_manager, get_op, vec![]).await {
+                tracing::error!(tx = %id, %error, "Failed requesting contract");
+            }
+        } else {
+            request_get_batch(&op_manager, batch).await;
+        }
+    }
+}
+
+/// The batch of the requests already waiting to be sent along with the first one, without
+/// waiting for any more to come.
+fn next_batch(first: GetOp, requests: &mut mpsc::Receiver<GetOp>) -> Vec<GetOp> {
+    let mut batch = vec![first];
+    while batch.len() < MAX_BATCH_SIZE {
+        let Ok(get_op) = requests.try_recv() else {
+            break;
+        };
+        batch.push(get_op);
+    }
+    batch
+}
+
 #[derive(Debug)]
 enum GetState {
     /// A new petition for a get op.
@@ -121,12 +419,15 @@ enum GetState {
         key: ContractKey,
         id: Transaction,
         fetch_contract: bool,
+        subscribe: bool,
     },
     /// Awaiting response from petition.
     AwaitingResponse {
         /// If specified the peer waiting for the response upstream
         requester: Option<PeerKeyLocation>,
         fetch_contract: bool,
+        /// Whether the subscription to the contract is registered along with the get.
+        subscribe: bool,
         retries: usize,
         current_hop: usize,
     },
@@ -140,20 +441,22 @@ impl Display for GetState {
                 key,
                 id,
                 fetch_contract,
+                subscribe,
             } => {
                 write!(
                     f,
-                    "PrepareRequest(key: {}, id: {}, fetch_contract: {})",
-                    key, id, fetch_contract
+                    "PrepareRequest(key: {}, id: {}, fetch_contract: {}, subscribe: {})",
+                    key, id, fetch_contract, subscribe
                 )
             }
             GetState::AwaitingResponse {
                 requester,
                 fetch_contract,
+                subscribe,
                 retries,
                 current_hop,
             } => {
-                write!(f, "AwaitingResponse(requester: {:?}, fetch_contract: {}, retries: {}, current_hop: {})", requester, fetch_contract, retries, current_hop)
+                write!(f, "AwaitingResponse(requester: {:?}, fetch_contract: {}, subscribe: {}, retries: {}, current_hop: {})", requester, fetch_contract, subscribe, retries, current_hop)
             }
         }
     }
@@ -174,6 +477,8 @@ pub(crate) struct GetResult {
     key: ContractKey,
     pub state: WrappedState,
     pub contract: Option<ContractContainer>,
+    /// For gets subscribing to the contract, whether the subscription was registered.
+    subscribed: Option<bool>,
 }
 
 impl TryFrom<GetOp> for GetResult {
@@ -182,7 +487,11 @@ impl TryFrom<GetOp> for GetResult {
     fn try_from(value: GetOp) -> Result<Self, Self::Error> {
         match value.result {
             Some(r) => Ok(r),
-            _ => Err(OpError::UnexpectedOpState),
+            _ => Err(OpError::invalid_transition(
+                value.id,
+                value.state.as_ref(),
+                "TakeResult",
+            )),
         }
     }
 }
@@ -230,12 +539,34 @@ impl GetOp {
         self.result.is_some()
     }
 
+    /// Whether this node relays the request on behalf of the peer.
+    fn requested_by(&self, peer: &PeerId) -> bool {
+        matches!(
+            &self.state,
+            Some(GetState::AwaitingResponse {
+                requester: Some(requester),
+                ..
+            }) if &requester.peer == peer
+        )
+    }
+
     pub(super) fn to_host_result(&self) -> HostResult {
         match &self.result {
+            Some(GetResult {
+                key,
+                subscribed: Some(subscribed),
+                ..
+            }) => Ok(HostResponse::ContractResponse(
+                freenet_stdlib::client_api::ContractResponse::SubscribeResponse {
+                    key: *key,
+                    subscribed: *subscribed,
+                },
+            )),
             Some(GetResult {
                 key,
                 state,
                 contract,
+                ..
             }) => Ok(HostResponse::ContractResponse(
                 freenet_stdlib::client_api::ContractResponse::GetResponse {
                     key: *key,
@@ -249,6 +580,26 @@ impl GetOp {
             .into()),
         }
     }
+
+    /// The state of the contract subscribed to, delivered to the client as an update
+    /// notification following the subscription response.
+    pub(crate) fn subscribed_state(&self) -> Option<HostResult> {
+        let GetResult {
+            key,
+            state,
+            subscribed: Some(true),
+            ..
+        } = self.result.as_ref()?
+        else {
+            return None;
+        };
+        Some(Ok(HostResponse::ContractResponse(
+            freenet_stdlib::client_api::ContractResponse::UpdateNotification {
+                key: *key,
+                update: UpdateData::State(State::from(state.as_ref()).into_owned()),
+            },
+        )))
+    }
 }
 
 impl Operation for GetOp {
@@ -295,7 +646,7 @@ impl Operation for GetOp {
 
     fn process_message<'a, NB: NetworkBridge>(
         self,
-        _conn_manager: &'a mut NB,
+        conn_manager: &'a mut NB,
         op_manager: &'a OpManager,
         input: &'a Self::Message,
     ) -> Pin<Box<dyn Future<Output = Result<OperationResult, OpError>> + Send + 'a>> {
@@ -319,6 +670,8 @@ impl Operation for GetOp {
                         Some(GetState::AwaitingResponse { .. })
                     ));
                     tracing::info!(tx = %id, %key, target = %target.peer, "Seek contract");
+                    // the request may come back to this node if there is a cycle in the ring
+                    op_manager.first_visit(*id);
                     new_state = self.state;
                     stats = Some(Box::new(GetStats {
                         contract_location: Location::from(key),
@@ -329,15 +682,25 @@ impl Operation for GetOp {
                     let own_loc = op_manager.ring.connection_manager.own_location();
                     let mut new_skip_list = skip_list.clone();
                     new_skip_list.push(own_loc.peer.clone());
-                    return_msg = Some(GetMsg::SeekNode {
-                        key: *key,
-                        id: *id,
-                        target: target.clone(),
-                        sender: own_loc.clone(),
-                        fetch_contract: *fetch_contract,
-                        htl: op_manager.ring.max_hops_to_live,
-                        skip_list: new_skip_list,
-                    });
+                    let subscribe = matches!(
+                        self.state,
+                        Some(GetState::AwaitingResponse {
+                            subscribe: true,
+                            ..
+                        })
+                    );
+                    return_msg = Some(
+                        GetMsg::SeekNode {
+                            key: *key,
+                            id: *id,
+                            target: target.clone(),
+                            sender: own_loc.clone(),
+                            fetch_contract: *fetch_contract,
+                            htl: op_manager.ring.max_hops_to_live,
+                            skip_list: new_skip_list,
+                        }
+                        .subscribing(subscribe),
+                    );
                 }
                 GetMsg::SeekNode {
                     key,
@@ -347,11 +710,21 @@ impl Operation for GetOp {
                     target,
                     htl,
                     skip_list,
+                }
+                | GetMsg::SeekNodeAndSubscribe {
+                    key,
+                    id,
+                    fetch_contract,
+                    sender,
+                    target,
+                    htl,
+                    skip_list,
                 } => {
                     let htl = *htl;
                     let id = *id;
                     let key: ContractKey = *key;
                     let fetch_contract = *fetch_contract;
+                    let subscribe = matches!(input, GetMsg::SeekNodeAndSubscribe { .. });
                     let this_peer = target.clone();
 
                     if let Some(s) = stats.as_mut() {
@@ -361,6 +734,32 @@ impl Operation for GetOp {
                     let mut new_skip_list = skip_list.clone();
                     new_skip_list.push(this_peer.clone().peer);
 
+                    if !op_manager.first_visit(id) {
+                        tracing::debug!(
+                            tx = %id,
+                            %key,
+                            sender = %sender.peer,
+                            "Get request already seen by this peer, returning back to sender",
+                        );
+                        return build_op_result(
+                            id,
+                            self.state,
+                            Some(GetMsg::ReturnGet {
+                                id,
+                                key,
+                                value: StoreResponse {
+                                    state: None,
+                                    contract: None,
+                                },
+                                sender: this_peer,
+                                target: sender.clone(),
+                                skip_list: new_skip_list,
+                            }),
+                            None,
+                            stats,
+                        );
+                    }
+
                     let get_result = op_manager
                         .notify_contract_handler(ContractHandlerEvent::GetQuery {
                             key,
@@ -388,7 +787,7 @@ impl Operation for GetOp {
                             return try_forward_or_return(
                                 id,
                                 key,
-                                (htl, fetch_contract),
+                                (htl, fetch_contract, subscribe),
                                 (this_peer, sender.clone()),
                                 skip_list,
                                 op_manager,
@@ -404,6 +803,9 @@ impl Operation for GetOp {
                         Some(GetState::AwaitingResponse { requester, .. }) => {
                             if let Some(requester) = requester {
                                 new_state = None;
+                                if subscribe {
+                                    register_subscriber(op_manager, id, &key, requester.clone());
+                                }
                                 tracing::debug!(tx = %id, "Returning contract {} to {}", key, sender.peer);
                                 return_msg = Some(GetMsg::ReturnGet {
                                     id,
@@ -428,6 +830,9 @@ impl Operation for GetOp {
                         }
                         Some(GetState::ReceivedRequest) => {
                             new_state = None;
+                            if subscribe {
+                                register_subscriber(op_manager, id, &key, sender.clone());
+                            }
                             tracing::debug!(tx = %id, "Returning contract {} to {}", key, sender.peer);
                             return_msg = Some(GetMsg::ReturnGet {
                                 id,
@@ -441,7 +846,13 @@ impl Operation for GetOp {
                                 skip_list: skip_list.clone(),
                             });
                         }
-                        _ => return Err(OpError::invalid_transition(self.id)),
+                        _ => {
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ))
+                        }
                     }
                 }
                 GetMsg::ReturnGet {
@@ -465,12 +876,33 @@ impl Operation for GetOp {
                     match self.state {
                         Some(GetState::AwaitingResponse {
                             fetch_contract,
+                            subscribe,
                             retries,
                             requester,
                             current_hop,
                         }) => {
                             // todo: register in the stats for the outcome of the op that failed to get a response from this peer
-                            if retries < MAX_RETRIES {
+                            if requester.is_none()
+                                && op_manager.redundant_gets().failed(
+                                    id,
+                                    &sender.peer,
+                                    op_manager.clock.now(),
+                                )
+                            {
+                                tracing::debug!(
+                                    tx = %id,
+                                    %key,
+                                    "Waiting for the other requests sent for the contract"
+                                );
+                                return_msg = None;
+                                new_state = Some(GetState::AwaitingResponse {
+                                    retries,
+                                    fetch_contract,
+                                    subscribe,
+                                    requester,
+                                    current_hop,
+                                });
+                            } else if retries < MAX_RETRIES {
                                 // no response received from this peer, so skip it in the next iteration
                                 let mut new_skip_list = skip_list.clone();
                                 new_skip_list.push(target.peer.clone());
@@ -480,15 +912,18 @@ impl Operation for GetOp {
                                     .into_iter()
                                     .next()
                                 {
-                                    return_msg = Some(GetMsg::SeekNode {
-                                        id: *id,
-                                        key: *key,
-                                        target,
-                                        sender: this_peer.clone(),
-                                        fetch_contract,
-                                        htl: current_hop,
-                                        skip_list: new_skip_list.clone(),
-                                    });
+                                    return_msg = Some(
+                                        GetMsg::SeekNode {
+                                            id: *id,
+                                            key: *key,
+                                            target,
+                                            sender: this_peer.clone(),
+                                            fetch_contract,
+                                            htl: current_hop,
+                                            skip_list: new_skip_list.clone(),
+                                        }
+                                        .subscribing(subscribe),
+                                    );
                                 } else if let Some(requester_peer) = requester.clone() {
                                     tracing::warn!(
                                         tx = %id,
@@ -519,11 +954,13 @@ impl Operation for GetOp {
                                         key: *key,
                                         state: WrappedState::new(vec![]),
                                         contract: None,
+                                        subscribed: subscribe.then_some(false),
                                     });
                                 }
                                 new_state = Some(GetState::AwaitingResponse {
                                     retries: retries + 1,
                                     fetch_contract,
+                                    subscribe,
                                     requester,
                                     current_hop,
                                 });
@@ -563,6 +1000,7 @@ impl Operation for GetOp {
                                     new_state = Some(GetState::AwaitingResponse {
                                         retries: retries + 1,
                                         fetch_contract,
+                                        subscribe,
                                         requester,
                                         current_hop,
                                     });
@@ -570,6 +1008,7 @@ impl Operation for GetOp {
                                         key: *key,
                                         state: WrappedState::new(vec![]),
                                         contract: None,
+                                        subscribed: subscribe.then_some(false),
                                     });
                                 }
                             }
@@ -589,7 +1028,13 @@ impl Operation for GetOp {
                                 skip_list: skip_list.clone(),
                             });
                         }
-                        _ => return Err(OpError::invalid_transition(self.id)),
+                        _ => {
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ))
+                        }
                     };
                 }
                 GetMsg::ReturnGet {
@@ -621,7 +1066,11 @@ impl Operation for GetOp {
                     {
                         requester.clone()
                     } else {
-                        return Err(OpError::UnexpectedOpState);
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
                     };
 
                     // received a response with a contract value
@@ -677,8 +1126,17 @@ impl Operation for GetOp {
                             ..
                         })
                     );
+                    let subscribe = matches!(
+                        self.state,
+                        Some(GetState::AwaitingResponse {
+                            subscribe: true,
+                            ..
+                        })
+                    );
                     let should_subscribe = op_manager.ring.should_seed(&key);
-                    let should_put = is_original_requester || should_subscribe;
+                    // peers along the path of a subscribing get join the subscription tree,
+                    // so they cache the contract to relay its updates
+                    let should_put = is_original_requester || should_subscribe || subscribe;
 
                     if should_put {
                         let res = op_manager
@@ -693,7 +1151,7 @@ impl Operation for GetOp {
                             ContractHandlerEvent::PutResponse { new_value: Ok(_) } => {
                                 let is_subscribed_contract =
                                     op_manager.ring.is_seeding_contract(&key);
-                                if !is_subscribed_contract && should_subscribe {
+                                if !is_subscribed_contract && should_subscribe && !subscribe {
                                     tracing::debug!(tx = %id, %key, peer = %op_manager.ring.connection_manager.get_peer_key().unwrap(), "Contract not cached @ peer, caching");
                                     let mut new_skip_list = skip_list.clone();
                                     new_skip_list.push(sender.peer.clone());
@@ -754,17 +1212,37 @@ impl Operation for GetOp {
                         }
                     }
 
+                    if subscribe {
+                        // the provider, upstream, already registered this peer as subscriber
+                        register_subscriber(op_manager, id, &key, sender.clone());
+                        if let Some(requester) = requester {
+                            register_subscriber(op_manager, id, &key, requester);
+                        }
+                    }
+
                     match self.state {
                         Some(GetState::AwaitingResponse {
                             requester: None, ..
                         }) => {
                             tracing::info!(tx = %id, %key, "Get response received for contract at original requester");
+                            for peer in op_manager.redundant_gets().served(&id, &sender.peer) {
+                                tracing::debug!(tx = %id, %peer, "Cancelling redundant get request");
+                                let cancel = NetMessage::V1(NetMessageV1::CancelGet {
+                                    transaction: id,
+                                    key,
+                                    subscribe,
+                                });
+                                if let Err(error) = conn_manager.send(&peer, cancel).await {
+                                    tracing::debug!(tx = %id, %peer, %error, "Failed cancelling get request");
+                                }
+                            }
                             new_state = None;
                             return_msg = None;
                             result = Some(GetResult {
                                 key,
                                 state: value.clone(),
                                 contract: contract.clone(),
+                                subscribed: subscribe.then_some(true),
                             });
                         }
                         Some(GetState::AwaitingResponse {
@@ -789,6 +1267,7 @@ impl Operation for GetOp {
                                 key,
                                 state: value.clone(),
                                 contract: contract.clone(),
+                                subscribed: None,
                             });
                         }
                         Some(GetState::ReceivedRequest) => {
@@ -806,13 +1285,13 @@ impl Operation for GetOp {
                                 skip_list: skip_list.clone(),
                             });
                         }
-                        Some(other) => {
-                            return Err(OpError::invalid_transition_with_state(
+                        _ => {
+                            return Err(OpError::invalid_transition(
                                 self.id,
-                                Box::new(other),
+                                self.state.as_ref(),
+                                input,
                             ))
                         }
-                        None => return Err(OpError::invalid_transition(self.id)),
                     };
                 }
             }
@@ -841,10 +1320,25 @@ fn build_op_result(
     })
 }
 
+fn register_subscriber(
+    op_manager: &OpManager,
+    id: Transaction,
+    key: &ContractKey,
+    subscriber: PeerKeyLocation,
+) {
+    if op_manager
+        .ring
+        .add_subscriber(key, subscriber.clone())
+        .is_err()
+    {
+        tracing::debug!(tx = %id, %key, subscriber = %subscriber.peer, "Max number of subscribers reached for contract");
+    }
+}
+
 async fn try_forward_or_return(
     id: Transaction,
     key: ContractKey,
-    (htl, fetch_contract): (usize, bool),
+    (htl, fetch_contract, subscribe): (usize, bool, bool),
     (this_peer, sender): (PeerKeyLocation, PeerKeyLocation),
     skip_list: &[PeerId],
     op_manager: &OpManager,
@@ -860,9 +1354,9 @@ async fn try_forward_or_return(
     let mut new_skip_list = skip_list.to_vec();
     new_skip_list.push(this_peer.peer.clone());
 
-    let new_htl = htl - 1;
+    let new_htl = super::forward_htl(htl, op_manager.ring.max_hops_to_live);
 
-    let new_target = if new_htl == 0 {
+    let new_target = if new_htl.is_none() {
         tracing::warn!(
             tx = %id,
             sender = %sender.peer,
@@ -887,7 +1381,7 @@ async fn try_forward_or_return(
         }
     };
 
-    if let Some(target) = new_target {
+    if let (Some(target), Some(new_htl)) = (new_target, new_htl) {
         tracing::debug!(
             tx = %id,
             "Forwarding get request to {}",
@@ -899,17 +1393,21 @@ async fn try_forward_or_return(
                 requester: Some(sender),
                 retries: 0,
                 fetch_contract,
+                subscribe,
                 current_hop: new_htl,
             }),
-            Some(GetMsg::SeekNode {
-                id,
-                key,
-                fetch_contract,
-                sender: this_peer,
-                target,
-                htl: new_htl,
-                skip_list: new_skip_list,
-            }),
+            Some(
+                GetMsg::SeekNode {
+                    id,
+                    key,
+                    fetch_contract,
+                    sender: this_peer,
+                    target,
+                    htl: new_htl,
+                    skip_list: new_skip_list,
+                }
+                .subscribing(subscribe),
+            ),
             None,
             stats,
         )
@@ -973,6 +1471,16 @@ mod messages {
             target: PeerKeyLocation,
             skip_list: Vec<PeerId>,
         },
+        /// A `SeekNode` which also subscribes to the contract at the peers returning its state.
+        SeekNodeAndSubscribe {
+            id: Transaction,
+            key: ContractKey,
+            fetch_contract: bool,
+            target: PeerKeyLocation,
+            sender: PeerKeyLocation,
+            htl: usize,
+            skip_list: Vec<PeerId>,
+        },
     }
 
     impl InnerMessage for GetMsg {
@@ -981,6 +1489,7 @@ mod messages {
                 Self::RequestGet { id, .. } => id,
                 Self::SeekNode { id, .. } => id,
                 Self::ReturnGet { id, .. } => id,
+                Self::SeekNodeAndSubscribe { id, .. } => id,
             }
         }
 
@@ -989,6 +1498,7 @@ mod messages {
                 Self::SeekNode { target, .. } => Some(target),
                 Self::RequestGet { target, .. } => Some(target),
                 Self::ReturnGet { target, .. } => Some(target),
+                Self::SeekNodeAndSubscribe { target, .. } => Some(target),
             }
         }
 
@@ -997,17 +1507,57 @@ mod messages {
                 GetMsg::RequestGet { key, .. } => Some(Location::from(key.id())),
                 GetMsg::SeekNode { key, .. } => Some(Location::from(key.id())),
                 GetMsg::ReturnGet { key, .. } => Some(Location::from(key.id())),
+                GetMsg::SeekNodeAndSubscribe { key, .. } => Some(Location::from(key.id())),
             }
         }
+
+        fn hops_to_live(&self) -> Option<usize> {
+            match self {
+                GetMsg::SeekNode { htl, .. } | GetMsg::SeekNodeAndSubscribe { htl, .. } => {
+                    Some(*htl)
+                }
+                _ => None,
+            }
+        }
+
+        fn is_response(&self) -> bool {
+            matches!(self, GetMsg::ReturnGet { .. })
+        }
     }
 
     impl GetMsg {
         pub fn sender(&self) -> Option<&PeerKeyLocation> {
             match self {
-                Self::SeekNode { target, .. } => Some(target),
+                Self::SeekNode { target, .. } | Self::SeekNodeAndSubscribe { target, .. } => {
+                    Some(target)
+                }
                 _ => None,
             }
         }
+
+        /// Turns a `SeekNode` into a `SeekNodeAndSubscribe` if `subscribe` is set.
+        pub fn subscribing(self, subscribe: bool) -> Self {
+            match self {
+                Self::SeekNode {
+                    id,
+                    key,
+                    fetch_contract,
+                    target,
+                    sender,
+                    htl,
+                    skip_list,
+                } if subscribe => Self::SeekNodeAndSubscribe {
+                    id,
+                    key,
+                    fetch_contract,
+                    target,
+                    sender,
+                    htl,
+                    skip_list,
+                },
+                msg => msg,
+            }
+        }
     }
 
     impl Display for GetMsg {
@@ -1017,7 +1567,156 @@ mod messages {
                 Self::RequestGet { .. } => write!(f, "RequestGet(id: {id})"),
                 Self::SeekNode { .. } => write!(f, "SeekNode(id: {id})"),
                 Self::ReturnGet { .. } => write!(f, "ReturnGet(id: {id})"),
+                Self::SeekNodeAndSubscribe { .. } => write!(f, "SeekNodeAndSubscribe(id: {id})"),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::client_api::ContractResponse;
+
+    use super::*;
+
+    fn finished(subscribed: Option<bool>) -> GetOp {
+        GetOp {
+            id: Transaction::new::<GetMsg>(),
+            state: None,
+            result: Some(GetResult {
+                key: ContractKey::from(ContractInstanceId::new([1; 32])),
+                state: WrappedState::new(vec![1, 2, 3]),
+                contract: None,
+                subscribed,
+            }),
+            stats: None,
+        }
+    }
+
+    #[test]
+    fn subscribing_gets_answer_as_subscriptions() {
+        let op = finished(Some(true));
+        assert!(matches!(
+            op.to_host_result(),
+            Ok(HostResponse::ContractResponse(
+                ContractResponse::SubscribeResponse {
+                    subscribed: true,
+                    ..
+                }
+            ))
+        ));
+        match op.subscribed_state() {
+            Some(Ok(HostResponse::ContractResponse(ContractResponse::UpdateNotification {
+                update: UpdateData::State(state),
+                ..
+            }))) => assert_eq!(state.as_ref(), &[1, 2, 3]),
+            other => panic!("unexpected notification: {other:?}"),
+        }
+
+        let failed = finished(Some(false));
+        assert!(matches!(
+            failed.to_host_result(),
+            Ok(HostResponse::ContractResponse(
+                ContractResponse::SubscribeResponse {
+                    subscribed: false,
+                    ..
+                }
+            ))
+        ));
+        assert!(failed.subscribed_state().is_none());
+
+        let plain = finished(None);
+        assert!(matches!(
+            plain.to_host_result(),
+            Ok(HostResponse::ContractResponse(
+                ContractResponse::GetResponse { .. }
+            ))
+        ));
+        assert!(plain.subscribed_state().is_none());
+    }
+
+    #[tokio::test]
+    async fn ready_requests_are_batched_without_waiting() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let (tx, mut rx) = mpsc::channel(MAX_BATCH_SIZE * 2);
+        // a lone request is sent right away
+        assert_eq!(next_batch(start_op(key, false), &mut rx).len(), 1);
+
+        for _ in 0..MAX_BATCH_SIZE + 2 {
+            tx.try_send(start_op(key, false)).unwrap();
+        }
+        assert_eq!(
+            next_batch(start_op(key, false), &mut rx).len(),
+            MAX_BATCH_SIZE
+        );
+        assert_eq!(next_batch(start_op(key, false), &mut rx).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_do_not_hold_back_the_batch() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("failed_requests_do_not_hold_back_the_batch").await?;
+        let conn_manager = &op_manager.ring.connection_manager;
+        conn_manager.try_set_peer_key(([127, 0, 0, 1], 10_000).into());
+        conn_manager.add_connection(Location::random(), PeerId::random(), false);
+
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let first = start_op(key, false);
+        let invalid = GetOp {
+            id: Transaction::new::<GetMsg>(),
+            state: Some(GetState::ReceivedRequest),
+            result: None,
+            stats: None,
+        };
+        let last = start_op(key, false);
+        let ids = [first.id, invalid.id, last.id];
+        request_get_batch(&op_manager, vec![first, invalid, last]).await;
+        assert!(op_manager.has_op(&ids[0]));
+        assert!(!op_manager.has_op(&ids[1]));
+        assert!(op_manager.has_op(&ids[2]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requests_are_forwarded_while_hops_are_left() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("requests_are_forwarded_while_hops_are_left").await?;
+        let conn_manager = &op_manager.ring.connection_manager;
+        conn_manager.try_set_peer_key(([127, 0, 0, 1], 10_000).into());
+        conn_manager.add_connection(Location::random(), PeerId::random(), false);
+        let this_peer = conn_manager.own_location();
+        let sender = PeerKeyLocation {
+            peer: PeerId::random(),
+            location: Some(Location::random()),
+        };
+
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let forward = |htl| {
+            try_forward_or_return(
+                Transaction::new::<GetMsg>(),
+                key,
+                (htl, false, false),
+                (this_peer.clone(), sender.clone()),
+                &[],
+                &op_manager,
+                None,
+            )
+        };
+
+        // the last hop left is still taken
+        let result = forward(1).await?;
+        assert!(matches!(
+            result.return_msg,
+            Some(NetMessage::V1(NetMessageV1::Get(GetMsg::SeekNode {
+                htl: 0,
+                ..
+            })))
+        ));
+        let result = forward(0).await?;
+        assert!(matches!(
+            result.return_msg,
+            Some(NetMessage::V1(NetMessageV1::Get(GetMsg::ReturnGet { .. })))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! Bookkeeping of the get requests sent to several peers at once.
+//!
+//! With a get parallelism above one the requester sends the request to the best few candidates
+//! at the same time and keeps the first state returned, trading bandwidth for tail latency.
+//! The other requests are then cancelled along the paths they took, and the responses still
+//! arriving for them are discarded. The work wasted this way is accounted for, so it can be
+//! weighed against the latency gained.
+//!
+//! Gets subscribing to the contract are sent redundantly too. The peers which served a
+//! cancelled one drop the subscription it registered, so a single path stays subscribed.
+//!
+//! Once a request failed the requester waits for the others, but only for a while: requests
+//! still pending after that are failed in their place, so the get is retried rather than left
+//! to time out when the last of them is lost.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use freenet_stdlib::prelude::ContractKey;
+
+use super::GetMsg;
+use crate::{
+    contract::StoreResponse,
+    message::{NetMessage, Transaction},
+    node::PeerId,
+    ring::PeerKeyLocation,
+};
+
+struct Dispatch {
+    key: ContractKey,
+    /// Peers the request was sent to.
+    targets: Vec<PeerId>,
+    /// Peers the request was sent to which did not respond yet.
+    pending: Vec<PeerId>,
+    /// Whether one of the requests was already served.
+    served: bool,
+    /// Since when the requester waits for the pending requests, after another one failed.
+    waiting_since: Option<Instant>,
+}
+
+#[derive(Default)]
+pub(crate) struct RedundantGets {
+    requests: DashMap<Transaction, Dispatch>,
+    dispatched: AtomicU64,
+    cancelled: AtomicU64,
+    wasted: AtomicU64,
+}
+
+impl RedundantGets {
+    /// Time the requester waits for the pending requests once another one failed.
+    const WAIT_FOR_PENDING: Duration = Duration::from_secs(10);
+
+    /// Records a get request sent to all the peers at once.
+    pub fn dispatched(&self, tx: Transaction, key: ContractKey, targets: Vec<PeerId>) {
+        let extra = targets.len().saturating_sub(1) as u64;
+        self.dispatched.fetch_add(extra, Ordering::Relaxed);
+        self.requests.insert(
+            tx,
+            Dispatch {
+                key,
+                pending: targets.clone(),
+                targets,
+                served: false,
+                waiting_since: None,
+            },
+        );
+    }
+
+    /// Records that the request sent to the peer could not be served. Returns whether other
+    /// requests for the transaction are still pending, in which case the requester waits for
+    /// them rather than retrying.
+    pub fn failed(&self, tx: &Transaction, peer: &PeerId, now: Instant) -> bool {
+        let Some(mut dispatch) = self.requests.get_mut(tx) else {
+            return false;
+        };
+        dispatch.pending.retain(|pending| pending != peer);
+        let waiting = !dispatch.served && !dispatch.pending.is_empty();
+        if waiting {
+            dispatch.waiting_since.get_or_insert(now);
+        }
+        if dispatch.pending.is_empty() {
+            std::mem::drop(dispatch);
+            self.requests.remove(tx);
+        }
+        waiting
+    }
+
+    /// Failures, addressed to this node, standing in for the requests the requester waited on
+    /// for too long. Processing them retries the get once none is left pending.
+    pub fn stalled(&self, own: &PeerKeyLocation, now: Instant) -> Vec<NetMessage> {
+        let mut failures = vec![];
+        for mut dispatch in self.requests.iter_mut() {
+            let tx = *dispatch.key();
+            let dispatch = dispatch.value_mut();
+            if dispatch.served
+                || !dispatch
+                    .waiting_since
+                    .is_some_and(|since| since + Self::WAIT_FOR_PENDING <= now)
+            {
+                continue;
+            }
+            dispatch.waiting_since = None;
+            for peer in &dispatch.pending {
+                tracing::debug!(%tx, %peer, "Giving up on get request");
+                failures.push(NetMessage::from(GetMsg::ReturnGet {
+                    id: tx,
+                    key: dispatch.key,
+                    value: StoreResponse {
+                        state: None,
+                        contract: None,
+                    },
+                    sender: PeerKeyLocation {
+                        peer: peer.clone(),
+                        location: None,
+                    },
+                    target: own.clone(),
+                    skip_list: dispatch.targets.clone(),
+                }));
+            }
+        }
+        failures
+    }
+
+    /// Records that the request sent to the peer was served, returning the peers the other
+    /// requests are still pending at, to be cancelled.
+    pub fn served(&self, tx: &Transaction, peer: &PeerId) -> Vec<PeerId> {
+        let Some(mut dispatch) = self.requests.get_mut(tx) else {
+            return vec![];
+        };
+        dispatch.served = true;
+        dispatch.pending.retain(|pending| pending != peer);
+        let cancelled = dispatch.pending.clone();
+        self.cancelled
+            .fetch_add(cancelled.len() as u64, Ordering::Relaxed);
+        cancelled
+    }
+
+    /// Accounts for a response which arrived once the operation was over, if it answered a
+    /// request made redundant by another one being served first.
+    pub fn late_response(&self, tx: &Transaction) {
+        if self
+            .requests
+            .get(tx)
+            .is_some_and(|dispatch| dispatch.served)
+        {
+            self.wasted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Stops tracking the transaction, once it expired.
+    pub fn forget(&self, tx: &Transaction) {
+        self.requests.remove(tx);
+    }
+
+    pub fn snapshot(&self) -> RedundancyMetrics {
+        RedundancyMetrics {
+            dispatched: self.dispatched.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            wasted: self.wasted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the get requests sent redundantly since the node started.
+#[derive(Debug, Clone)]
+pub(crate) struct RedundancyMetrics {
+    /// Requests sent besides the one to the best candidate.
+    pub dispatched: u64,
+    /// Requests cancelled because another one was served first.
+    pub cancelled: u64,
+    /// Responses discarded because another request was served first.
+    pub wasted: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::get::GetMsg;
+
+    fn key() -> ContractKey {
+        ContractKey::from(freenet_stdlib::prelude::ContractInstanceId::new([1; 32]))
+    }
+
+    #[test]
+    fn first_served_request_cancels_the_others() {
+        let gets = RedundantGets::default();
+        let (first, second, third) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        // a failed request waits for the others, the last one failing lets the op retry
+        let failing = Transaction::new::<GetMsg>();
+        gets.dispatched(failing, key(), vec![first.clone(), second.clone()]);
+        assert!(gets.failed(&failing, &first, now));
+        assert!(!gets.failed(&failing, &second, now));
+        assert!(!gets.failed(&failing, &first, now));
+
+        let served = Transaction::new::<GetMsg>();
+        gets.dispatched(
+            served,
+            key(),
+            vec![first.clone(), second.clone(), third.clone()],
+        );
+        assert!(gets.failed(&served, &first, now));
+        assert_eq!(gets.served(&served, &third), vec![second]);
+        gets.late_response(&served);
+        gets.forget(&served);
+        gets.late_response(&served);
+
+        let metrics = gets.snapshot();
+        assert_eq!(metrics.dispatched, 3);
+        assert_eq!(metrics.cancelled, 1);
+        assert_eq!(metrics.wasted, 1);
+    }
+
+    #[test]
+    fn stalled_requests_are_failed_in_their_place() {
+        let gets = RedundantGets::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let own = PeerKeyLocation::random();
+        let now = Instant::now();
+
+        let tx = Transaction::new::<GetMsg>();
+        gets.dispatched(tx, key(), vec![first.clone(), second.clone()]);
+        // nothing is given up on while no request failed
+        let later = now + RedundantGets::WAIT_FOR_PENDING;
+        assert!(gets.stalled(&own, later).is_empty());
+
+        assert!(gets.failed(&tx, &first, now));
+        assert!(gets.stalled(&own, now).is_empty());
+        let failures = gets.stalled(&own, later);
+        let [NetMessage::V1(crate::message::NetMessageV1::Get(GetMsg::ReturnGet {
+            id,
+            value: StoreResponse { state: None, .. },
+            sender,
+            target,
+            skip_list,
+            ..
+        }))] = failures.as_slice()
+        else {
+            panic!("expected a single failure, got {failures:?}");
+        };
+        assert_eq!(id, &tx);
+        assert_eq!(sender.peer, second);
+        assert_eq!(target, &own);
+        assert_eq!(skip_list, &vec![first, second.clone()]);
+        // and only once
+        assert!(gets.stalled(&own, later).is_empty());
+
+        // processing the failure lets the op retry
+        assert!(!gets.failed(&tx, &second, later));
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_is_torn_down() -> anyhow::Result<()> {
+        use crate::{
+            message::NetMessageV1,
+            operations::{
+                get::{cancel_request, GetOp, GetState},
+                OpEnum,
+            },
+            test_utils::RecordingBridge,
+        };
+
+        let op_manager = crate::test_utils::op_manager("cancelled_request_is_torn_down").await?;
+        let bridge = RecordingBridge::default();
+
+        // the redundant request relayed by this node on behalf of the requester, to the next hop
+        let (requester, next_hop) = (PeerKeyLocation::random(), PeerId::random());
+        let tx = Transaction::new::<GetMsg>();
+        let relayed = GetOp {
+            id: tx,
+            state: Some(GetState::AwaitingResponse {
+                requester: Some(requester.clone()),
+                fetch_contract: false,
+                subscribe: true,
+                retries: 0,
+                current_hop: 10,
+            }),
+            result: None,
+            stats: None,
+        };
+        op_manager.push(tx, OpEnum::Get(relayed)).await?;
+        op_manager
+            .ring
+            .live_tx_tracker
+            .add_transaction(next_hop.clone(), tx);
+
+        // only the requester can cancel it
+        let cancel = (tx, key(), true);
+        cancel_request(&op_manager, &bridge, cancel, &PeerId::random()).await?;
+        assert!(op_manager.has_op(&tx));
+        assert!(bridge.sent().is_empty());
+        cancel_request(&op_manager, &bridge, cancel, &requester.peer).await?;
+        assert!(!op_manager.has_op(&tx));
+        assert!(op_manager.pop(&tx).is_err());
+        // and it is cancelled further along
+        assert!(matches!(
+            bridge.sent().as_slice(),
+            [(peer, NetMessage::V1(NetMessageV1::CancelGet { transaction, subscribe: true, .. }))]
+                if peer == &next_hop && transaction == &tx
+        ));
+
+        // once served, the subscription registered for the requester is dropped instead
+        op_manager
+            .ring
+            .add_subscriber(&key(), requester.clone())
+            .unwrap();
+        cancel_request(&op_manager, &bridge, cancel, &requester.peer).await?;
+        assert!(!op_manager
+            .ring
+            .subscribers_of(&key())
+            .is_some_and(|subs| subs.contains(&requester)));
+        assert_eq!(bridge.sent().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Graceful leave of the network.
+//!
+//! Before disconnecting, a node hands off the contracts it is the closest holder of to the
+//! next closest peers, lets the subscribers of the contracts it seeds know so they can
+//! subscribe through some other peer, and notifies its neighbours so they can drop the
+//! connection right away instead of waiting for it to time out.
+
+use std::time::Duration;
+
+use freenet_stdlib::prelude::*;
+
+use super::{put, OpError};
+use crate::{
+    contract::{ContractHandlerEvent, StoreResponse},
+    message::{NetMessage, NetMessageV1, Transaction},
+    node::{NetworkBridge, OpManager},
+    operations::connect::ConnectMsg,
+    ring::Location,
+};
+
+/// Time given to the contract hand offs to complete before disconnecting.
+const HANDOFF_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Leaves the network gracefully.
+pub(crate) async fn leave<NB: NetworkBridge>(
+    op_manager: &OpManager,
+    network_bridge: &NB,
+) -> Result<(), OpError> {
+    let own_location = op_manager.ring.connection_manager.own_location();
+    let this_peer = own_location.peer.clone();
+
+    let mut handoffs = 0;
+    for key in op_manager.ring.seeded_contracts() {
+        if is_closest_holder(op_manager, own_location.location, &key) {
+            match handoff_contract(op_manager, key).await {
+                Ok(()) => handoffs += 1,
+                Err(error) => tracing::warn!(%key, %error, "Failed handing off contract"),
+            }
+        }
+        let subscribers = op_manager
+            .ring
+            .subscribers_of(&key)
+            .map(|subs| subs.value().clone())
+            .unwrap_or_default();
+        for subscriber in subscribers {
+            let notice = NetMessage::V1(NetMessageV1::Unsubscribed {
+                transaction: Transaction::new::<ConnectMsg>(),
+                key,
+                from: this_peer.clone(),
+            });
+            // the rest of the subscribers are still told when one of them can't be
+            if let Err(error) = network_bridge.send(&subscriber.peer, notice).await {
+                tracing::debug!(%key, subscriber = %subscriber.peer, %error, "Failed notifying subscriber of the leave");
+            }
+        }
+    }
+    if handoffs > 0 {
+        tracing::info!(%handoffs, "Handing off contracts before leaving");
+        op_manager.clock.sleep(HANDOFF_GRACE_PERIOD).await;
+    }
+
+    // so the node can reconnect to its current neighbours when it comes back
+    op_manager.ring.persist_known_peers().await;
+    for peer in op_manager.ring.connection_manager.connected_peers() {
+        let notice = NetMessage::V1(NetMessageV1::Leaving {
+            transaction: Transaction::new::<ConnectMsg>(),
+            from: this_peer.clone(),
+        });
+        if let Err(error) = network_bridge.send(&peer, notice).await {
+            tracing::debug!(%peer, %error, "Failed notifying neighbour of the leave");
+        }
+    }
+    Ok(())
+}
+
+/// Whether this peer is closer to the contract than any of its neighbours caching contracts.
+fn is_closest_holder(op_manager: &OpManager, own: Option<Location>, key: &ContractKey) -> bool {
+    let contract_location = Location::from(key);
+    let Some(own) = own else {
+        return false;
+    };
+    let light_clients = op_manager.ring.connection_manager.light_client_neighbours();
+    match op_manager
+        .ring
+        .closest_to_location(contract_location, &light_clients)
+        .and_then(|closest| closest.location)
+    {
+        Some(closest) => own.distance(contract_location) < closest.distance(contract_location),
+        None => true,
+    }
+}
+
+/// Puts the contract and its current state in the next closest peers, other than light clients
+/// which would not keep it.
+async fn handoff_contract(op_manager: &OpManager, key: ContractKey) -> Result<(), OpError> {
+    let response = op_manager
+        .notify_contract_handler(ContractHandlerEvent::GetQuery {
+            key,
+            return_contract_code: true,
+        })
+        .await?;
+    let ContractHandlerEvent::GetResponse {
+        response:
+            Ok(StoreResponse {
+                state: Some(state),
+                contract: Some(contract),
+            }),
+        ..
+    } = response
+    else {
+        return Err(OpError::UnexpectedOpState);
+    };
+    let put_op = put::start_op(
+        contract,
+        RelatedContracts::default(),
+        state,
+        op_manager.ring.max_hops_to_live,
+    );
+    let light_clients = op_manager.ring.connection_manager.light_client_neighbours();
+    put::request_put_skipping(op_manager, put_op, light_clients).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::PeerId;
+
+    #[tokio::test]
+    async fn light_clients_do_not_hold_contracts() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("light_clients_do_not_hold_contracts").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let contract_location = Location::from(&key);
+        let own = Location::new((contract_location.as_f64() + 0.1) % 1.0);
+
+        let neighbour = PeerId::random();
+        let connection_manager = &op_manager.ring.connection_manager;
+        connection_manager.add_connection(contract_location, neighbour.clone(), false);
+        assert!(!is_closest_holder(&op_manager, Some(own), &key));
+
+        // a light client closer to the contract would not keep it
+        connection_manager.light_client_neighbour(neighbour);
+        assert!(is_closest_holder(&op_manager, Some(own), &key));
+        Ok(())
+    }
+}
@@ -0,0 +1,610 @@
+//! Probe operation, used to measure the health of the ring.
+//!
+//! A probe walks the ring greedily towards a random location, each peer in the path recording
+//! its location. On the way back each peer measures the round trip time to the rest of the
+//! path, which allows attributing to every hop the latency it added to the probe.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use freenet_stdlib::client_api::ErrorKind;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use super::{OpEnum, OpError, OpInitialization, OpOutcome, Operation, OperationResult};
+use crate::{
+    client_events::HostResult,
+    message::{InnerMessage, NetMessage, Transaction},
+    node::{NetworkBridge, OpManager, PeerId},
+    ring::{Location, PeerKeyLocation, RingError},
+};
+
+pub(crate) use self::messages::ProbeMsg;
+
+/// Max number of peers a probe visits, peers stop walking probes which already visited as many.
+const MAX_VISITS: usize = 64;
+
+/// A peer visited by a probe.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeVisit {
+    pub hop: usize,
+    /// Location of the peer, if it had one assigned.
+    pub location: Option<Location>,
+    /// Latency added by this hop, including the link from the previous peer in the path.
+    pub latency: Duration,
+}
+
+/// Path followed by a probe walking the ring towards the given destination.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResponse {
+    pub destination: Location,
+    pub visits: Vec<ProbeVisit>,
+}
+
+impl ProbeResponse {
+    /// Round trip time of the whole probe.
+    pub fn total_latency(&self) -> Duration {
+        self.visits.iter().map(|visit| visit.latency).sum()
+    }
+}
+
+enum ProbeState {
+    /// Prepare the request to probe the ring.
+    PrepareRequest {
+        destination: Location,
+        result: oneshot::Sender<ProbeResponse>,
+    },
+    /// Received a request to probe the ring.
+    ReceivedRequest,
+    /// The probe was forwarded and this peer is awaiting for the rest of the path.
+    AwaitingResponse {
+        destination: Location,
+        /// Position of the next peer in the path among the visits.
+        next_hop: usize,
+        forwarded_at: Instant,
+        upstream: Option<PeerKeyLocation>,
+        result: Option<oneshot::Sender<ProbeResponse>>,
+    },
+    /// The probe came back to the peer which requested it.
+    Finished,
+}
+
+impl std::fmt::Display for ProbeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeState::PrepareRequest { destination, .. } => {
+                write!(f, "PrepareRequest(destination: {destination})")
+            }
+            ProbeState::ReceivedRequest => write!(f, "ReceivedRequest"),
+            ProbeState::AwaitingResponse { next_hop, .. } => {
+                write!(f, "AwaitingResponse(next_hop: {next_hop})")
+            }
+            ProbeState::Finished => write!(f, "Finished"),
+        }
+    }
+}
+
+pub(crate) struct ProbeOp {
+    pub id: Transaction,
+    state: Option<ProbeState>,
+}
+
+impl ProbeOp {
+    pub(super) fn outcome(&self) -> OpOutcome {
+        OpOutcome::Irrelevant
+    }
+
+    pub(super) fn finalized(&self) -> bool {
+        matches!(self.state, Some(ProbeState::Finished))
+    }
+
+    pub(super) fn to_host_result(&self) -> HostResult {
+        Err(ErrorKind::OperationError {
+            cause: "probe results are only reported through the node API".into(),
+        }
+        .into())
+    }
+}
+
+pub(crate) fn start_op(destination: Location) -> (ProbeOp, oneshot::Receiver<ProbeResponse>) {
+    let id = Transaction::new::<ProbeMsg>();
+    let (result, rx) = oneshot::channel();
+    let state = Some(ProbeState::PrepareRequest {
+        destination,
+        result,
+    });
+    (ProbeOp { id, state }, rx)
+}
+
+/// Request to probe the ring towards the operation destination.
+pub(crate) async fn request_probe(
+    op_manager: &OpManager,
+    probe_op: ProbeOp,
+) -> Result<(), OpError> {
+    let ProbeOp { id, state } = probe_op;
+    let Some(ProbeState::PrepareRequest {
+        destination,
+        result,
+    }) = state
+    else {
+        return Err(OpError::invalid_transition(
+            id,
+            state.as_ref(),
+            "RequestProbe",
+        ));
+    };
+    if op_manager.ring.open_connections() == 0 {
+        return Err(RingError::EmptyRing.into());
+    }
+    let op = ProbeOp {
+        id,
+        state: Some(ProbeState::AwaitingResponse {
+            destination,
+            next_hop: 0,
+            forwarded_at: op_manager.clock.now(),
+            upstream: None,
+            result: Some(result),
+        }),
+    };
+    op_manager
+        .notify_op_change(
+            NetMessage::from(ProbeMsg::RequestProbe { id, destination }),
+            OpEnum::Probe(op),
+        )
+        .await
+}
+
+/// Probes the ring towards the given location, awaiting for the probe to complete.
+pub(crate) async fn probe(
+    op_manager: &OpManager,
+    destination: Location,
+) -> Result<ProbeResponse, OpError> {
+    let (op, result) = start_op(destination);
+    let id = op.id;
+    request_probe(op_manager, op).await?;
+    // the sender is dropped if the operation fails or times out
+    result.await.map_err(|_| OpError::OpNotPresent(id))
+}
+
+/// Attributes to the next hop in the path the latency it added to the probe, given the
+/// round trip time measured by this peer and the one measured by the next hop.
+fn record_latency(
+    visits: &mut [ProbeVisit],
+    next_hop: usize,
+    round_trip: Duration,
+    downstream_round_trip: Duration,
+) {
+    if let Some(visit) = visits.get_mut(next_hop) {
+        visit.latency = round_trip.saturating_sub(downstream_round_trip);
+    }
+}
+
+impl Operation for ProbeOp {
+    type Message = ProbeMsg;
+    type Result = ProbeResponse;
+
+    async fn load_or_init<'a>(
+        op_manager: &'a OpManager,
+        msg: &'a Self::Message,
+    ) -> Result<OpInitialization<Self>, OpError> {
+        let sender = msg.sender().map(|sender| sender.peer.clone());
+        let id = *msg.id();
+
+        match op_manager.pop(msg.id()) {
+            Ok(Some(OpEnum::Probe(probe_op))) => Ok(OpInitialization {
+                op: probe_op,
+                sender,
+            }),
+            Ok(Some(op)) => {
+                let _ = op_manager.push(id, op).await;
+                Err(OpError::OpNotPresent(id))
+            }
+            Ok(None) => Ok(OpInitialization {
+                op: Self {
+                    state: Some(ProbeState::ReceivedRequest),
+                    id,
+                },
+                sender,
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn id(&self) -> &Transaction {
+        &self.id
+    }
+
+    fn process_message<'a, NB: NetworkBridge>(
+        self,
+        _conn_manager: &'a mut NB,
+        op_manager: &'a OpManager,
+        input: &'a Self::Message,
+    ) -> Pin<Box<dyn Future<Output = Result<OperationResult, OpError>> + Send + 'a>> {
+        Box::pin(async move {
+            match input {
+                ProbeMsg::RequestProbe { id, destination } => {
+                    // fast tracked from the request_probe func
+                    let Some(ProbeState::AwaitingResponse {
+                        upstream: None,
+                        result,
+                        ..
+                    }) = self.state
+                    else {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
+                    };
+                    let this_peer = op_manager.ring.connection_manager.own_location();
+                    let skip_list = vec![this_peer.peer.clone()];
+                    let target = op_manager
+                        .ring
+                        .closest_to_location(*destination, &skip_list)
+                        .ok_or(RingError::EmptyRing)?;
+                    build_op_result(
+                        *id,
+                        Some(ProbeState::AwaitingResponse {
+                            destination: *destination,
+                            next_hop: 0,
+                            forwarded_at: op_manager.clock.now(),
+                            upstream: None,
+                            result,
+                        }),
+                        Some(ProbeMsg::SeekNode {
+                            id: *id,
+                            destination: *destination,
+                            target,
+                            sender: this_peer,
+                            visits: vec![],
+                            skip_list,
+                            htl: op_manager.ring.max_hops_to_live,
+                        }),
+                    )
+                }
+                ProbeMsg::SeekNode {
+                    id,
+                    destination,
+                    sender,
+                    visits,
+                    skip_list,
+                    htl,
+                    ..
+                } => {
+                    if !matches!(self.state, Some(ProbeState::ReceivedRequest)) {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
+                    }
+                    let this_peer = op_manager.ring.connection_manager.own_location();
+                    let mut visits = visits.clone();
+                    visits.truncate(MAX_VISITS - 1);
+                    visits.push(ProbeVisit {
+                        hop: visits.len(),
+                        location: this_peer.location,
+                        latency: Duration::ZERO,
+                    });
+
+                    let mut skip_list = skip_list.clone();
+                    skip_list.push(this_peer.peer.clone());
+                    // keep walking only while there is a peer closer to the destination
+                    let next_htl = super::forward_htl(*htl, op_manager.ring.max_hops_to_live)
+                        .filter(|_| visits.len() < MAX_VISITS);
+                    let next = next_htl
+                        .and_then(|_| {
+                            op_manager
+                                .ring
+                                .closest_to_location(*destination, &skip_list)
+                        })
+                        .filter(|next| match (next.location, this_peer.location) {
+                            (Some(next), Some(own)) => {
+                                next.distance(destination) < own.distance(destination)
+                            }
+                            _ => true,
+                        });
+
+                    let (Some(next), Some(next_htl)) = (next, next_htl) else {
+                        tracing::debug!(tx = %id, hops = visits.len(), "Probe reached its end");
+                        return Ok(OperationResult {
+                            return_msg: Some(NetMessage::from(ProbeMsg::ReturnProbe {
+                                id: *id,
+                                target: sender.clone(),
+                                sender: this_peer,
+                                visits,
+                                round_trip: Duration::ZERO,
+                            })),
+                            state: None,
+                        });
+                    };
+
+                    tracing::debug!(tx = %id, next = %next.peer, "Forwarding probe");
+                    let next_hop = visits.len();
+                    build_op_result(
+                        *id,
+                        Some(ProbeState::AwaitingResponse {
+                            destination: *destination,
+                            next_hop,
+                            forwarded_at: op_manager.clock.now(),
+                            upstream: Some(sender.clone()),
+                            result: None,
+                        }),
+                        Some(ProbeMsg::SeekNode {
+                            id: *id,
+                            destination: *destination,
+                            target: next,
+                            sender: this_peer,
+                            visits,
+                            skip_list,
+                            htl: next_htl,
+                        }),
+                    )
+                }
+                ProbeMsg::ReturnProbe {
+                    id,
+                    target,
+                    visits,
+                    round_trip: downstream_round_trip,
+                    ..
+                } => {
+                    let Some(ProbeState::AwaitingResponse {
+                        destination,
+                        next_hop,
+                        forwarded_at,
+                        upstream,
+                        result,
+                    }) = self.state
+                    else {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
+                    };
+                    let round_trip = op_manager
+                        .clock
+                        .now()
+                        .saturating_duration_since(forwarded_at);
+                    let mut visits = visits.clone();
+                    visits.truncate(MAX_VISITS);
+                    record_latency(&mut visits, next_hop, round_trip, *downstream_round_trip);
+
+                    if let Some(upstream) = upstream {
+                        return build_op_result(
+                            *id,
+                            None,
+                            Some(ProbeMsg::ReturnProbe {
+                                id: *id,
+                                target: upstream,
+                                sender: target.clone(),
+                                visits,
+                                round_trip,
+                            }),
+                        );
+                    }
+
+                    tracing::info!(tx = %id, hops = visits.len(), ?round_trip, "Probe completed");
+                    if let Some(result) = result {
+                        let _ = result.send(ProbeResponse {
+                            destination,
+                            visits,
+                        });
+                    }
+                    build_op_result(*id, Some(ProbeState::Finished), None)
+                }
+            }
+        })
+    }
+}
+
+fn build_op_result(
+    id: Transaction,
+    state: Option<ProbeState>,
+    msg: Option<ProbeMsg>,
+) -> Result<OperationResult, OpError> {
+    let output_op = state.map(|state| ProbeOp {
+        id,
+        state: Some(state),
+    });
+    Ok(OperationResult {
+        return_msg: msg.map(NetMessage::from),
+        state: output_op.map(OpEnum::Probe),
+    })
+}
+
+mod messages {
+    use std::{borrow::Borrow, fmt::Display};
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub(crate) enum ProbeMsg {
+        RequestProbe {
+            id: Transaction,
+            destination: Location,
+        },
+        SeekNode {
+            id: Transaction,
+            destination: Location,
+            target: PeerKeyLocation,
+            sender: PeerKeyLocation,
+            visits: Vec<ProbeVisit>,
+            skip_list: Vec<PeerId>,
+            htl: usize,
+        },
+        ReturnProbe {
+            id: Transaction,
+            target: PeerKeyLocation,
+            sender: PeerKeyLocation,
+            visits: Vec<ProbeVisit>,
+            /// Round trip time measured by the sender to the rest of the path.
+            round_trip: Duration,
+        },
+    }
+
+    impl InnerMessage for ProbeMsg {
+        fn id(&self) -> &Transaction {
+            match self {
+                Self::RequestProbe { id, .. } => id,
+                Self::SeekNode { id, .. } => id,
+                Self::ReturnProbe { id, .. } => id,
+            }
+        }
+
+        fn target(&self) -> Option<impl Borrow<PeerKeyLocation>> {
+            match self {
+                Self::SeekNode { target, .. } => Some(target),
+                Self::ReturnProbe { target, .. } => Some(target),
+                Self::RequestProbe { .. } => None,
+            }
+        }
+
+        fn requested_location(&self) -> Option<Location> {
+            // probes target random locations, don't count them as demand for that location
+            None
+        }
+
+        fn hops_to_live(&self) -> Option<usize> {
+            match self {
+                Self::SeekNode { htl, .. } => Some(*htl),
+                _ => None,
+            }
+        }
+
+        fn is_response(&self) -> bool {
+            matches!(self, Self::ReturnProbe { .. })
+        }
+    }
+
+    impl ProbeMsg {
+        pub fn sender(&self) -> Option<&PeerKeyLocation> {
+            match self {
+                Self::SeekNode { sender, .. } => Some(sender),
+                Self::ReturnProbe { sender, .. } => Some(sender),
+                Self::RequestProbe { .. } => None,
+            }
+        }
+    }
+
+    impl Display for ProbeMsg {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let id = self.id();
+            match self {
+                Self::RequestProbe { .. } => write!(f, "RequestProbe(id: {id})"),
+                Self::SeekNode { .. } => write!(f, "SeekNode(id: {id})"),
+                Self::ReturnProbe { .. } => write!(f, "ReturnProbe(id: {id})"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_latency_per_hop() {
+        let mut visits: Vec<_> = (0..3)
+            .map(|hop| ProbeVisit {
+                hop,
+                location: Some(Location::random()),
+                latency: Duration::ZERO,
+            })
+            .collect();
+        // round trips as measured walking back from the end of the path
+        record_latency(&mut visits, 2, Duration::from_millis(30), Duration::ZERO);
+        record_latency(
+            &mut visits,
+            1,
+            Duration::from_millis(50),
+            Duration::from_millis(30),
+        );
+        record_latency(
+            &mut visits,
+            0,
+            Duration::from_millis(90),
+            Duration::from_millis(50),
+        );
+        let latencies: Vec<_> = visits.iter().map(|v| v.latency.as_millis()).collect();
+        assert_eq!(latencies, [40, 20, 30]);
+
+        let response = ProbeResponse {
+            destination: Location::random(),
+            visits,
+        };
+        assert_eq!(response.total_latency(), Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn invalid_transitions_report_state_and_input() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("invalid_transitions_report_state_and_input").await?;
+
+        let op = ProbeOp {
+            id: Transaction::new::<ProbeMsg>(),
+            state: Some(ProbeState::ReceivedRequest),
+        };
+        let tx = op.id;
+        let error = request_probe(&op_manager, op).await.unwrap_err();
+        let OpError::InvalidTransition {
+            tx: failed,
+            from,
+            input,
+            ..
+        } = &error
+        else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(*failed, tx);
+        assert_eq!(from, "ReceivedRequest");
+        assert_eq!(input, "RequestProbe");
+        assert!(error
+            .to_string()
+            .contains("from state `ReceivedRequest` with input `RequestProbe`"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finalized_once_back_at_the_requester() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("probe_finalized_once_back_at_the_requester").await?;
+        let (result, response) = oneshot::channel();
+        let op = ProbeOp {
+            id: Transaction::new::<ProbeMsg>(),
+            state: Some(ProbeState::AwaitingResponse {
+                destination: Location::random(),
+                next_hop: 0,
+                forwarded_at: op_manager.clock.now(),
+                upstream: None,
+                result: Some(result),
+            }),
+        };
+        // a path longer than any probe may visit
+        let visits = (0..MAX_VISITS * 2)
+            .map(|hop| ProbeVisit {
+                hop,
+                location: None,
+                latency: Duration::ZERO,
+            })
+            .collect();
+        let msg = ProbeMsg::ReturnProbe {
+            id: op.id,
+            target: PeerKeyLocation::random(),
+            sender: PeerKeyLocation::random(),
+            visits,
+            round_trip: Duration::ZERO,
+        };
+
+        let mut bridge = crate::test_utils::RecordingBridge::default();
+        let result = op.process_message(&mut bridge, &op_manager, &msg).await?;
+        assert!(result.return_msg.is_none());
+        let Some(OpEnum::Probe(op)) = result.state else {
+            panic!("expected the probe to be kept as finished");
+        };
+        assert!(op.finalized());
+        assert_eq!(response.await?.visits.len(), MAX_VISITS);
+        Ok(())
+    }
+}
@@ -217,7 +217,9 @@ impl Operation for PutOp {
                         );
                     }
 
-                    let last_hop = if let Some(new_htl) = htl.checked_sub(1) {
+                    let last_hop = if let Some(new_htl) =
+                        super::forward_htl(*htl, op_manager.ring.max_hops_to_live)
+                    {
                         // forward changes in the contract to nodes closer to the contract location, if possible
                         forward_put(
                             op_manager,
@@ -422,7 +424,13 @@ impl Operation for PutOp {
                                 return_msg = None;
                             }
                         }
-                        _ => return Err(OpError::invalid_transition(self.id)),
+                        _ => {
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ))
+                        }
                     };
                 }
                 PutMsg::PutForward {
@@ -460,7 +468,9 @@ impl Operation for PutOp {
                     }
 
                     // if successful, forward to the next closest peers (if any)
-                    let last_hop = if let Some(new_htl) = htl.checked_sub(1) {
+                    let last_hop = if let Some(new_htl) =
+                        super::forward_htl(*htl, op_manager.ring.max_hops_to_live)
+                    {
                         let mut new_skip_list = skip_list.clone();
                         new_skip_list.push(sender.peer.clone());
                         // only hop forward if there are closer peers
@@ -552,7 +562,13 @@ impl Operation for PutOp {
                         Err(err) => return Err(err),
                     }
                 }
-                _ => return Err(OpError::UnexpectedOpState),
+                _ => {
+                    return Err(OpError::invalid_transition(
+                        self.id,
+                        self.state.as_ref(),
+                        input,
+                    ))
+                }
             }
 
             build_op_result(self.id, new_state, return_msg, stats)
@@ -649,7 +665,7 @@ async fn try_to_broadcast(
                 });
             }
         }
-        _ => return Err(OpError::invalid_transition(id)),
+        _ => return Err(OpError::invalid_transition(id, state.as_ref(), "Broadcast")),
     };
 
     Ok((new_state, return_msg))
@@ -699,22 +715,53 @@ pub enum PutState {
     },
 }
 
+impl std::fmt::Display for PutState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PutState::ReceivedRequest => write!(f, "ReceivedRequest"),
+            PutState::PrepareRequest { contract, htl, .. } => {
+                write!(f, "PrepareRequest(key: {}, htl: {})", contract.key(), htl)
+            }
+            PutState::AwaitingResponse { key, upstream } => {
+                write!(f, "AwaitingResponse(key: {key}, upstream: {upstream:?})")
+            }
+            PutState::BroadcastOngoing => write!(f, "BroadcastOngoing"),
+            PutState::Finished { key } => write!(f, "Finished(key: {key})"),
+        }
+    }
+}
+
 /// Request to insert/update a value into a contract.
-pub(crate) async fn request_put(op_manager: &OpManager, mut put_op: PutOp) -> Result<(), OpError> {
+pub(crate) async fn request_put(op_manager: &OpManager, put_op: PutOp) -> Result<(), OpError> {
+    request_put_skipping(op_manager, put_op, vec![]).await
+}
+
+/// Request to insert/update a value into a contract, never sent first to the peers in the
+/// skip list.
+pub(crate) async fn request_put_skipping(
+    op_manager: &OpManager,
+    mut put_op: PutOp,
+    mut skip_list: Vec<PeerId>,
+) -> Result<(), OpError> {
     let key = if let Some(PutState::PrepareRequest { contract, .. }) = &put_op.state {
         contract.key()
     } else {
-        return Err(OpError::UnexpectedOpState);
+        return Err(OpError::invalid_transition(
+            put_op.id,
+            put_op.state.as_ref(),
+            "RequestPut",
+        ));
     };
 
     let sender = op_manager.ring.connection_manager.own_location();
+    skip_list.push(sender.peer.clone());
 
     // the initial request must provide:
     // - a peer as close as possible to the contract location
     // - and the value to put
     let target = op_manager
         .ring
-        .closest_potentially_caching(&key, [&sender.peer].as_slice())
+        .closest_potentially_caching(&key, skip_list.as_slice())
         .into_iter()
         .next()
         .ok_or(RingError::EmptyRing)?;
@@ -755,7 +802,13 @@ pub(crate) async fn request_put(op_manager: &OpManager, mut put_op: PutOp) -> Re
                 .notify_op_change(NetMessage::from(msg), OpEnum::Put(op))
                 .await?;
         }
-        _ => return Err(OpError::invalid_transition(put_op.id)),
+        _ => {
+            return Err(OpError::invalid_transition(
+                put_op.id,
+                put_op.state.as_ref(),
+                "RequestPut",
+            ))
+        }
     };
 
     Ok(())
@@ -953,6 +1006,19 @@ mod messages {
                 _ => None,
             }
         }
+
+        fn hops_to_live(&self) -> Option<usize> {
+            match self {
+                Self::RequestPut { htl, .. } => Some(*htl),
+                Self::PutForward { htl, .. } => Some(*htl),
+                Self::SeekNode { htl, .. } => Some(*htl),
+                _ => None,
+            }
+        }
+
+        fn is_response(&self) -> bool {
+            matches!(self, Self::SuccessfulPut { .. })
+        }
     }
 
     impl PutMsg {
@@ -0,0 +1,68 @@
+//! Relocation of a node to a new location in the ring.
+//!
+//! A node which ended up in an over-crowded arc of the ring moves to a sparser one. It lets
+//! its neighbours know its new location so they route accordingly, stops seeding the
+//! contracts now too far away, letting their subscribers know so they can subscribe through
+//! some other peer, and subscribes again to the contracts it keeps seeding so updates reach
+//! it through the peers closest to its new location.
+
+use super::{subscribe, OpError};
+use crate::{
+    message::{NetMessage, NetMessageV1, Transaction},
+    node::{NetworkBridge, OpManager},
+    operations::connect::ConnectMsg,
+    ring::Location,
+};
+
+/// Moves this node to the given location.
+pub(crate) async fn relocate<NB: NetworkBridge>(
+    op_manager: &OpManager,
+    network_bridge: &NB,
+    location: Location,
+) -> Result<(), OpError> {
+    let own_location = op_manager.ring.connection_manager.own_location();
+    let this_peer = own_location.peer.clone();
+    let dropped = op_manager.ring.relocate(location);
+    tracing::info!(
+        previous = ?own_location.location,
+        %location,
+        dropped = dropped.len(),
+        "Relocated in the ring"
+    );
+
+    for peer in op_manager.ring.connection_manager.connected_peers() {
+        network_bridge
+            .send(
+                &peer,
+                NetMessage::V1(NetMessageV1::Relocated {
+                    transaction: Transaction::new::<ConnectMsg>(),
+                    from: this_peer.clone(),
+                    location,
+                }),
+            )
+            .await?;
+    }
+
+    for (key, subscribers) in dropped {
+        for subscriber in subscribers {
+            network_bridge
+                .send(
+                    &subscriber.peer,
+                    NetMessage::V1(NetMessageV1::Unsubscribed {
+                        transaction: Transaction::new::<ConnectMsg>(),
+                        key,
+                        from: this_peer.clone(),
+                    }),
+                )
+                .await?;
+        }
+    }
+
+    for key in op_manager.ring.seeded_contracts() {
+        let op = subscribe::start_op(key);
+        if let Err(error) = subscribe::request_subscribe(op_manager, op).await {
+            tracing::debug!(%key, %error, "Failed subscribing again after relocating");
+        }
+    }
+    Ok(())
+}
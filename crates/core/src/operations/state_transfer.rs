@@ -0,0 +1,421 @@
+//! Chunked transfer of operation messages carrying large contract states.
+//!
+//! Messages above the configured threshold are split into sequenced chunks. Chunks are the
+//! leaves of a hash tree; each one travels with the tree root and the proof needed to verify
+//! it, so the receiving side can verify every chunk as it arrives and reassemble the original
+//! message once all of them have been received, regardless of the order.
+
+use std::{collections::HashMap, time::Duration};
+
+use either::Either;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{MessageStats, NetMessage, NetMessageV1, Transaction},
+    node::PeerId,
+    ring::PeerKeyLocation,
+};
+
+type Hash = [u8; 32];
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Max size of a reassembled message, so a peer can't make this node reserve memory for a
+/// transfer larger than any state it would accept.
+const MAX_TRANSFER_LEN: u64 = 32 * 1024 * 1024;
+/// Max number of transfers a single peer may have ongoing at once, so a peer can't make this
+/// node hold buffers for many transfers it never completes.
+const MAX_TRANSFERS_PER_PEER: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TransferError {
+    #[error("chunk {index} of transfer {id} failed verification")]
+    InvalidChunk { id: Transaction, index: u32 },
+    #[error("transfer {0} manifest doesn't match the previously received chunks")]
+    ManifestMismatch(Transaction),
+    #[error("transfer {id} manifest is invalid or above the max size ({total_len} bytes)")]
+    InvalidManifest { id: Transaction, total_len: u64 },
+    #[error("transfer {0} was started by another peer")]
+    ForeignChunk(Transaction),
+    #[error("too many ongoing transfers from {0}")]
+    TooManyTransfers(PeerId),
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+}
+
+/// Describes the whole transferred message, shared by all its chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TransferManifest {
+    /// Root of the hash tree built over the chunks.
+    pub root: Hash,
+    pub total_len: u64,
+    pub num_chunks: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StateChunk {
+    pub id: Transaction,
+    pub target: PeerKeyLocation,
+    pub manifest: TransferManifest,
+    pub index: u32,
+    /// Sibling hashes from the chunk leaf up to the root.
+    pub proof: Vec<Hash>,
+    pub data: Vec<u8>,
+}
+
+impl TransferManifest {
+    /// Whether the manifest describes a transfer of at most `max_len` bytes, split in as many
+    /// chunks as needed for its length.
+    fn is_valid(&self, max_len: u64) -> bool {
+        self.total_len > 0
+            && self.total_len <= max_len
+            && u64::from(self.num_chunks) == self.total_len.div_ceil(CHUNK_SIZE as u64)
+    }
+
+    /// Length the chunk at the index must have.
+    fn chunk_len(&self, index: u32) -> u64 {
+        let start = u64::from(index) * CHUNK_SIZE as u64;
+        (self.total_len - start).min(CHUNK_SIZE as u64)
+    }
+}
+
+impl StateChunk {
+    fn verify(&self) -> bool {
+        let mut hash = leaf_hash(&self.data);
+        let mut index = self.index;
+        for sibling in &self.proof {
+            hash = if index % 2 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+            index /= 2;
+        }
+        hash == self.manifest.root
+    }
+}
+
+fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[0]);
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[1]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Builds all the levels of the hash tree, from the leaves to the root.
+fn hash_tree(leaves: Vec<Hash>) -> Vec<Vec<Hash>> {
+    let mut levels = vec![leaves];
+    while levels.last().map(Vec::len).unwrap_or_default() > 1 {
+        let next = levels
+            .last()
+            .expect("at least one level")
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                // odd node out is promoted paired with itself
+                [single] => node_hash(single, single),
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn proof(levels: &[Vec<Hash>], mut index: usize) -> Vec<Hash> {
+    let mut proof = Vec::with_capacity(levels.len().saturating_sub(1));
+    for level in &levels[..levels.len() - 1] {
+        let sibling = index ^ 1;
+        proof.push(*level.get(sibling).unwrap_or(&level[index]));
+        index /= 2;
+    }
+    proof
+}
+
+/// Splits the message into chunks if its serialized size is above the threshold.
+pub(crate) fn split_message(
+    msg: NetMessage,
+    threshold: usize,
+) -> Result<Either<NetMessage, Vec<NetMessage>>, TransferError> {
+    let Some(target) = msg.target() else {
+        return Ok(Either::Left(msg));
+    };
+    if bincode::serialized_size(&msg)? as usize <= threshold {
+        return Ok(Either::Left(msg));
+    }
+    let id = *msg.id();
+    let bytes = bincode::serialize(&msg)?;
+    let chunks: Vec<_> = bytes.chunks(CHUNK_SIZE).collect();
+    let levels = hash_tree(chunks.iter().map(|c| leaf_hash(c)).collect());
+    let manifest = TransferManifest {
+        root: levels.last().expect("at least one level")[0],
+        total_len: bytes.len() as u64,
+        num_chunks: chunks.len() as u32,
+    };
+    tracing::debug!(tx = %id, len = bytes.len(), chunks = chunks.len(), "Splitting message in chunks");
+    let messages = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            NetMessage::V1(NetMessageV1::StateChunk(StateChunk {
+                id,
+                target: target.clone(),
+                manifest,
+                index: index as u32,
+                proof: proof(&levels, index),
+                data: data.to_vec(),
+            }))
+        })
+        .collect();
+    Ok(Either::Right(messages))
+}
+
+struct PendingTransfer {
+    manifest: TransferManifest,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+}
+
+/// Transfers being received by this node, keyed by the peer sending them and transaction.
+pub(crate) struct StateTransfers {
+    pending: HashMap<(PeerId, Transaction), PendingTransfer>,
+    /// Max size of the messages reassembled.
+    max_len: u64,
+}
+
+impl Default for StateTransfers {
+    fn default() -> Self {
+        Self::new(MAX_TRANSFER_LEN)
+    }
+}
+
+impl StateTransfers {
+    pub fn new(max_len: u64) -> Self {
+        Self {
+            pending: HashMap::new(),
+            max_len,
+        }
+    }
+
+    /// Verifies and stores the chunk sent by the peer, returning the original message once all
+    /// the chunks of the transfer have been received. Only the peer which sent the first chunk
+    /// of a transfer may send the rest.
+    pub fn receive(
+        &mut self,
+        from: PeerId,
+        chunk: StateChunk,
+        now: Duration,
+    ) -> Result<Option<NetMessage>, TransferError> {
+        self.pending.retain(|(_, tx), _| !tx.timed_out(now));
+        if !chunk.manifest.is_valid(self.max_len) {
+            return Err(TransferError::InvalidManifest {
+                id: chunk.id,
+                total_len: chunk.manifest.total_len,
+            });
+        }
+        if chunk.index >= chunk.manifest.num_chunks
+            || chunk.data.len() as u64 != chunk.manifest.chunk_len(chunk.index)
+            || !chunk.verify()
+        {
+            return Err(TransferError::InvalidChunk {
+                id: chunk.id,
+                index: chunk.index,
+            });
+        }
+        let key = (from, chunk.id);
+        if !self.pending.contains_key(&key) {
+            if self.pending.keys().any(|(_, tx)| *tx == chunk.id) {
+                return Err(TransferError::ForeignChunk(chunk.id));
+            }
+            let ongoing = self
+                .pending
+                .keys()
+                .filter(|(peer, _)| *peer == key.0)
+                .count();
+            if ongoing >= MAX_TRANSFERS_PER_PEER {
+                return Err(TransferError::TooManyTransfers(key.0));
+            }
+        }
+        let transfer = self
+            .pending
+            .entry(key.clone())
+            .or_insert_with(|| PendingTransfer {
+                manifest: chunk.manifest,
+                chunks: vec![None; chunk.manifest.num_chunks as usize],
+                received: 0,
+            });
+        if transfer.manifest != chunk.manifest {
+            return Err(TransferError::ManifestMismatch(chunk.id));
+        }
+        // the manifest was checked against the max size, so is the number of chunks
+        let slot = &mut transfer.chunks[chunk.index as usize];
+        if slot.is_none() {
+            *slot = Some(chunk.data);
+            transfer.received += 1;
+        }
+        if transfer.received < transfer.manifest.num_chunks {
+            return Ok(None);
+        }
+
+        let transfer = self.pending.remove(&key).expect("transfer present");
+        // every chunk was checked to be of the length the manifest describes
+        let mut bytes = Vec::with_capacity(transfer.manifest.total_len as usize);
+        for data in transfer.chunks.into_iter().flatten() {
+            bytes.extend(data);
+        }
+        Ok(Some(bincode::deserialize(&bytes)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::{ContractInstanceId, ContractKey, WrappedState};
+
+    use super::*;
+    use crate::{
+        operations::get::GetMsg,
+        util::time_source::{Clock, TokioClock},
+    };
+
+    fn large_message(len: usize) -> NetMessage {
+        let id = Transaction::new::<GetMsg>();
+        NetMessage::V1(NetMessageV1::Get(GetMsg::ReturnGet {
+            id,
+            key: ContractKey::from(ContractInstanceId::new([1; 32])),
+            value: crate::contract::StoreResponse {
+                state: Some(WrappedState::new(vec![7u8; len])),
+                contract: None,
+            },
+            sender: PeerKeyLocation::random(),
+            target: PeerKeyLocation::random(),
+            skip_list: vec![],
+        }))
+    }
+
+    #[test]
+    fn reassemble_out_of_order() -> Result<(), TransferError> {
+        let msg = large_message(CHUNK_SIZE * 5 + 10);
+        let id = *msg.id();
+        let Either::Right(mut chunks) = split_message(msg, CHUNK_SIZE)? else {
+            panic!("message should be chunked");
+        };
+        assert_eq!(chunks.len(), 6);
+        chunks.reverse();
+
+        let mut transfers = StateTransfers::default();
+        let now = TokioClock.unix_time();
+        let sender = PeerId::random();
+        let last = chunks.pop().unwrap();
+        for chunk in chunks {
+            let NetMessage::V1(NetMessageV1::StateChunk(chunk)) = chunk else {
+                panic!("expected chunk");
+            };
+            assert!(transfers.receive(sender.clone(), chunk, now)?.is_none());
+        }
+        let NetMessage::V1(NetMessageV1::StateChunk(last)) = last else {
+            panic!("expected chunk");
+        };
+        let msg = transfers
+            .receive(sender, last, now)?
+            .expect("message reassembled");
+        assert_eq!(*msg.id(), id);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_tampered_chunk() -> Result<(), TransferError> {
+        let Either::Right(mut chunks) = split_message(large_message(CHUNK_SIZE * 3), CHUNK_SIZE)?
+        else {
+            panic!("message should be chunked");
+        };
+        let NetMessage::V1(NetMessageV1::StateChunk(mut chunk)) = chunks.remove(1) else {
+            panic!("expected chunk");
+        };
+        chunk.data[0] ^= 1;
+        let mut transfers = StateTransfers::default();
+        assert!(matches!(
+            transfers.receive(PeerId::random(), chunk, TokioClock.unix_time()),
+            Err(TransferError::InvalidChunk { index: 1, .. })
+        ));
+
+        let small = large_message(10);
+        assert!(split_message(small, CHUNK_SIZE)?.is_left());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_oversized_manifest() -> Result<(), TransferError> {
+        let Either::Right(mut chunks) = split_message(large_message(CHUNK_SIZE * 3), CHUNK_SIZE)?
+        else {
+            panic!("message should be chunked");
+        };
+        let NetMessage::V1(NetMessageV1::StateChunk(chunk)) = chunks.remove(0) else {
+            panic!("expected chunk");
+        };
+        let now = TokioClock.unix_time();
+
+        let mut transfers = StateTransfers::new(CHUNK_SIZE as u64);
+        assert!(matches!(
+            transfers.receive(PeerId::random(), chunk, now),
+            Err(TransferError::InvalidManifest { .. })
+        ));
+
+        let NetMessage::V1(NetMessageV1::StateChunk(mut chunk)) = chunks.remove(0) else {
+            panic!("expected chunk");
+        };
+        chunk.manifest.num_chunks = u32::MAX;
+        let mut transfers = StateTransfers::default();
+        assert!(matches!(
+            transfers.receive(PeerId::random(), chunk, now),
+            Err(TransferError::InvalidManifest { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn transfers_are_bound_to_their_sender() -> Result<(), TransferError> {
+        let chunk = |msg| -> Result<StateChunk, TransferError> {
+            let Either::Right(mut chunks) = split_message(msg, CHUNK_SIZE)? else {
+                panic!("message should be chunked");
+            };
+            let NetMessage::V1(NetMessageV1::StateChunk(chunk)) = chunks.remove(0) else {
+                panic!("expected chunk");
+            };
+            Ok(chunk)
+        };
+        let now = TokioClock.unix_time();
+        let (sender, other) = (PeerId::random(), PeerId::random());
+        let mut transfers = StateTransfers::default();
+
+        let first = chunk(large_message(CHUNK_SIZE * 2))?;
+        let id = first.id;
+        let mut foreign = chunk(large_message(CHUNK_SIZE * 3))?;
+        foreign.id = id;
+        assert!(transfers.receive(sender.clone(), first, now)?.is_none());
+        assert!(matches!(
+            transfers.receive(other.clone(), foreign, now),
+            Err(TransferError::ForeignChunk(tx)) if tx == id
+        ));
+
+        for _ in 1..MAX_TRANSFERS_PER_PEER {
+            let next = chunk(large_message(CHUNK_SIZE * 2))?;
+            assert!(transfers.receive(sender.clone(), next, now)?.is_none());
+        }
+        let over = chunk(large_message(CHUNK_SIZE * 2))?;
+        assert!(matches!(
+            transfers.receive(sender, over, now),
+            Err(TransferError::TooManyTransfers(_))
+        ));
+        // other peers have their own allowance
+        let next = chunk(large_message(CHUNK_SIZE * 2))?;
+        assert!(transfers.receive(other, next, now)?.is_none());
+        Ok(())
+    }
+}
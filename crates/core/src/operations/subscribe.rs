@@ -41,6 +41,26 @@ enum SubscribeState {
     },
 }
 
+impl std::fmt::Display for SubscribeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubscribeState::PrepareRequest { id, key } => {
+                write!(f, "PrepareRequest(id: {id}, key: {key})")
+            }
+            SubscribeState::ReceivedRequest => write!(f, "ReceivedRequest"),
+            SubscribeState::AwaitingResponse {
+                retries,
+                current_hop,
+                ..
+            } => write!(
+                f,
+                "AwaitingResponse(retries: {retries}, current_hop: {current_hop})"
+            ),
+            SubscribeState::Completed { key } => write!(f, "Completed(key: {key})"),
+        }
+    }
+}
+
 pub(crate) struct SubscribeResult {}
 
 impl TryFrom<SubscribeOp> for SubscribeResult {
@@ -50,7 +70,11 @@ impl TryFrom<SubscribeOp> for SubscribeResult {
         if let Some(SubscribeState::Completed { .. }) = value.state {
             Ok(SubscribeResult {})
         } else {
-            Err(OpError::UnexpectedOpState)
+            Err(OpError::invalid_transition(
+                value.id,
+                value.state.as_ref(),
+                "TakeResult",
+            ))
         }
     }
 }
@@ -84,7 +108,11 @@ pub(crate) async fn request_subscribe(
             *id,
         )
     } else {
-        return Err(OpError::UnexpectedOpState);
+        return Err(OpError::invalid_transition(
+            sub_op.id,
+            sub_op.state.as_ref(),
+            "RequestSub",
+        ));
     };
 
     match sub_op.state {
@@ -104,7 +132,13 @@ pub(crate) async fn request_subscribe(
                 .notify_op_change(NetMessage::from(msg), OpEnum::Subscribe(op))
                 .await?;
         }
-        _ => return Err(OpError::invalid_transition(sub_op.id)),
+        _ => {
+            return Err(OpError::invalid_transition(
+                sub_op.id,
+                sub_op.state.as_ref(),
+                "RequestSub",
+            ))
+        }
     }
 
     Ok(())
@@ -247,12 +281,12 @@ impl Operation for SubscribeOp {
                             tracing::warn!(tx = %id, %key, "No target peer found while trying getting contract");
                             return Ok(return_not_subbed());
                         };
-                        let new_htl = htl - 1;
-
-                        if new_htl == 0 {
+                        let Some(new_htl) =
+                            super::forward_htl(*htl, op_manager.ring.max_hops_to_live)
+                        else {
                             tracing::debug!(tx = %id, %key, "Max number of hops reached while trying to get contract");
                             return Ok(return_not_subbed());
-                        }
+                        };
 
                         let mut new_skip_list = skip_list.clone();
                         new_skip_list.push(target.peer.clone());
@@ -307,7 +341,13 @@ impl Operation for SubscribeOp {
                                 subscribed: true,
                             });
                         }
-                        _ => return Err(OpError::invalid_transition(self.id)),
+                        _ => {
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ))
+                        }
                     }
                 }
                 SubscribeMsg::ReturnSub {
@@ -366,7 +406,13 @@ impl Operation for SubscribeOp {
                                 ));
                             }
                         }
-                        _ => return Err(OpError::invalid_transition(self.id)),
+                        _ => {
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ))
+                        }
                     }
                 }
                 SubscribeMsg::ReturnSub {
@@ -406,11 +452,21 @@ impl Operation for SubscribeOp {
                             return_msg = None;
                         }
                     }
-                    _other => {
-                        return Err(OpError::invalid_transition(self.id));
+                    _ => {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
                     }
                 },
-                _ => return Err(OpError::UnexpectedOpState),
+                _ => {
+                    return Err(OpError::invalid_transition(
+                        self.id,
+                        self.state.as_ref(),
+                        input,
+                    ))
+                }
             }
 
             build_op_result(self.id, new_state, return_msg)
@@ -493,6 +549,17 @@ mod messages {
                 _ => None,
             }
         }
+
+        fn hops_to_live(&self) -> Option<usize> {
+            match self {
+                Self::SeekNode { htl, .. } => Some(*htl),
+                _ => None,
+            }
+        }
+
+        fn is_response(&self) -> bool {
+            matches!(self, Self::ReturnSub { .. })
+        }
     }
 
     impl SubscribeMsg {
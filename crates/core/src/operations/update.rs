@@ -11,7 +11,7 @@ use crate::{
     node::{NetworkBridge, OpManager, PeerId},
 };
 
-pub(crate) use self::messages::UpdateMsg;
+pub(crate) use self::messages::{UpdateBody, UpdateMsg};
 
 pub(crate) struct UpdateOp {
     pub id: Transaction,
@@ -58,7 +58,11 @@ impl TryFrom<UpdateOp> for UpdateResult {
         if matches!(op.state, None | Some(UpdateState::Finished { .. })) {
             Ok(UpdateResult {})
         } else {
-            Err(OpError::UnexpectedOpState)
+            Err(OpError::invalid_transition(
+                op.id,
+                op.state.as_ref(),
+                "TakeResult",
+            ))
         }
     }
 }
@@ -173,8 +177,13 @@ impl Operation for UpdateOp {
 
                     if is_subscribed_contract {
                         tracing::debug!("Peer is subscribed to contract. About to update it");
-                        update_contract(op_manager, *key, value.clone(), related_contracts.clone())
-                            .await?;
+                        update_contract(
+                            op_manager,
+                            *key,
+                            UpdateData::State(State::from(value.clone())),
+                            related_contracts.clone(),
+                        )
+                        .await?;
                         tracing::debug!(
                             tx = %id,
                             "Successfully updated a value for contract {} @ {:?} - update",
@@ -216,13 +225,18 @@ impl Operation for UpdateOp {
                         return Err(OpError::StatePushed);
                     };
 
+                    if !op_manager.is_subscribed_through(key, &sender.peer) {
+                        tracing::debug!(tx = %id, %key, from = %sender.peer, "Dropping update of a contract not subscribed to - BroadcastTo");
+                        return build_op_result(self.id, None, None, stats);
+                    }
+
                     let target = op_manager.ring.connection_manager.own_location();
 
                     tracing::debug!("Attempting contract value update - BroadcastTo - update");
                     let new_value = update_contract(
                         op_manager,
                         *key,
-                        new_value.clone(),
+                        UpdateData::State(State::from(new_value.clone())),
                         RelatedContracts::default(),
                     )
                     .await?;
@@ -266,14 +280,31 @@ impl Operation for UpdateOp {
                     let sender = op_manager.ring.connection_manager.own_location();
                     let mut broadcasted_to = *broadcasted_to;
 
+                    // large states are announced by their summary, subscribers pull the changes
+                    // they are missing from this peer
+                    let announced_summary =
+                        if new_value.size() > op_manager.update_summary_threshold {
+                            Some(summarize_contract(op_manager, *key).await?)
+                        } else {
+                            None
+                        };
+
                     let mut broadcasting = Vec::with_capacity(broadcast_to.len());
 
                     for peer in broadcast_to.iter() {
-                        let msg = UpdateMsg::BroadcastTo {
-                            id: *id,
-                            key: *key,
-                            new_value: new_value.clone(),
-                            sender: sender.clone(),
+                        let msg = match &announced_summary {
+                            Some(summary) => UpdateMsg::BroadcastSummary {
+                                id: *id,
+                                key: *key,
+                                summary: summary.clone(),
+                                sender: sender.clone(),
+                            },
+                            None => UpdateMsg::BroadcastTo {
+                                id: *id,
+                                key: *key,
+                                new_value: new_value.clone(),
+                                sender: sender.clone(),
+                            },
                         };
                         let f = conn_manager.send(&peer.peer, msg.into());
                         broadcasting.push(f);
@@ -320,7 +351,126 @@ impl Operation for UpdateOp {
                         summary,
                     });
 
-                    new_state = None;
+                    new_state = announced_summary.map(|_| UpdateState::ServingBodies { key: *key });
+                }
+                UpdateMsg::BroadcastSummary {
+                    id,
+                    key,
+                    summary,
+                    sender,
+                } => match self.state {
+                    Some(UpdateState::AwaitingResponse { .. }) => {
+                        tracing::debug!("Trying to broadcast to a peer that was the initiator of the op because it received the client request, or is in the middle of a seek node process");
+                        return Err(OpError::StatePushed);
+                    }
+                    Some(UpdateState::AwaitingBody { .. } | UpdateState::ServingBodies { .. }) => {
+                        // this peer is already catching up with, or serving, this update
+                        new_state = self.state;
+                        return_msg = None;
+                    }
+                    _ if !op_manager.is_subscribed_through(key, &sender.peer) => {
+                        tracing::debug!(tx = %id, %key, from = %sender.peer, "Dropping update of a contract not subscribed to - BroadcastSummary");
+                        new_state = None;
+                        return_msg = None;
+                    }
+                    _ => {
+                        let local_summary = summarize_contract(op_manager, *key).await?;
+                        if &local_summary == summary {
+                            tracing::debug!(tx = %id, %key, "Contract state already up to date - BroadcastSummary");
+                            new_state = None;
+                            return_msg = None;
+                        } else {
+                            tracing::debug!(tx = %id, %key, from = %sender.peer, "Requesting updated contract state - BroadcastSummary");
+                            new_state = Some(UpdateState::AwaitingBody { key: *key });
+                            return_msg = Some(UpdateMsg::RequestBody {
+                                id: *id,
+                                key: *key,
+                                summary: local_summary,
+                                sender: op_manager.ring.connection_manager.own_location(),
+                                target: sender.clone(),
+                            });
+                        }
+                    }
+                },
+                UpdateMsg::RequestBody {
+                    id,
+                    key,
+                    summary,
+                    sender,
+                    ..
+                } => {
+                    let Some(UpdateState::ServingBodies { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
+                    };
+                    let body = match op_manager
+                        .notify_contract_handler(ContractHandlerEvent::DeltaQuery {
+                            key: *key,
+                            summary: summary.clone(),
+                        })
+                        .await?
+                    {
+                        ContractHandlerEvent::DeltaResponse { delta, .. } => {
+                            UpdateBody::try_from(delta?)?
+                        }
+                        _ => return Err(OpError::UnexpectedOpState),
+                    };
+                    tracing::debug!(tx = %id, %key, to = %sender.peer, "Sending updated contract state - RequestBody");
+                    return_msg = Some(UpdateMsg::ReturnBody {
+                        id: *id,
+                        key: *key,
+                        body,
+                        sender: op_manager.ring.connection_manager.own_location(),
+                        target: sender.clone(),
+                    });
+                    // keep serving the rest of subscribers
+                    new_state = self.state;
+                }
+                UpdateMsg::ReturnBody {
+                    id,
+                    key,
+                    body,
+                    sender,
+                    ..
+                } => {
+                    let Some(UpdateState::AwaitingBody { .. }) = self.state else {
+                        return Err(OpError::invalid_transition(
+                            self.id,
+                            self.state.as_ref(),
+                            input,
+                        ));
+                    };
+                    let new_value = update_contract(
+                        op_manager,
+                        *key,
+                        body.clone().into(),
+                        RelatedContracts::default(),
+                    )
+                    .await?;
+                    tracing::debug!(tx = %id, %key, "Contract successfully updated - ReturnBody");
+
+                    let broadcast_to = op_manager.get_broadcast_targets_update(key, &sender.peer);
+                    match try_to_broadcast(
+                        *id,
+                        false,
+                        op_manager,
+                        Some(UpdateState::ReceivedRequest),
+                        (broadcast_to, sender.clone()),
+                        *key,
+                        new_value,
+                        true,
+                    )
+                    .await
+                    {
+                        Ok((state, msg)) => {
+                            new_state = state;
+                            return_msg = msg;
+                        }
+                        Err(err) => return Err(err),
+                    }
                 }
                 UpdateMsg::SuccessfulUpdate { id, summary, .. } => {
                     match self.state {
@@ -347,17 +497,27 @@ impl Operation for UpdateOp {
                                 return_msg = None;
                             }
                         }
+                        Some(UpdateState::ServingBodies { .. }) => {
+                            // a subscriber finished relaying the update, keep serving the rest
+                            new_state = self.state;
+                            return_msg = None;
+                        }
                         _ => {
-                            tracing::error!(
-                                state = ?self.state,
-                                "invalid transition in UpdateMsg::SuccessfulUpdate -> match self.state"
-                            );
-
-                            return Err(OpError::invalid_transition(self.id));
+                            return Err(OpError::invalid_transition(
+                                self.id,
+                                self.state.as_ref(),
+                                input,
+                            ));
                         }
                     };
                 }
-                _ => return Err(OpError::UnexpectedOpState),
+                _ => {
+                    return Err(OpError::invalid_transition(
+                        self.id,
+                        self.state.as_ref(),
+                        input,
+                    ))
+                }
             }
 
             build_op_result(self.id, new_state, return_msg, stats)
@@ -438,7 +598,7 @@ async fn try_to_broadcast(
                 });
             }
         }
-        _ => return Err(OpError::invalid_transition(id)),
+        _ => return Err(OpError::invalid_transition(id, state.as_ref(), "Broadcast")),
     };
 
     Ok((new_state, return_msg))
@@ -464,6 +624,14 @@ impl OpManager {
 
         subscribers
     }
+
+    /// Whether updates of the contract broadcast by the peer are expected, because this peer
+    /// is subscribed to the contract through it.
+    pub(crate) fn is_subscribed_through(&self, key: &ContractKey, sender: &PeerId) -> bool {
+        self.ring
+            .subscribers_of(key)
+            .is_some_and(|subs| subs.iter().any(|pk| &pk.peer == sender))
+    }
 }
 
 fn build_op_result(
@@ -493,13 +661,25 @@ fn build_op_result(
     })
 }
 
+async fn summarize_contract(
+    op_manager: &OpManager,
+    key: ContractKey,
+) -> Result<StateSummary<'static>, OpError> {
+    match op_manager
+        .notify_contract_handler(ContractHandlerEvent::SummaryQuery { key })
+        .await?
+    {
+        ContractHandlerEvent::SummaryResponse { summary, .. } => Ok(summary?),
+        _ => Err(OpError::UnexpectedOpState),
+    }
+}
+
 async fn update_contract(
     op_manager: &OpManager,
     key: ContractKey,
-    state: WrappedState,
+    update_data: UpdateData<'static>,
     related_contracts: RelatedContracts<'static>,
 ) -> Result<WrappedState, OpError> {
-    let update_data = UpdateData::State(State::from(state));
     match op_manager
         .notify_contract_handler(ContractHandlerEvent::UpdateQuery {
             key,
@@ -512,10 +692,10 @@ async fn update_contract(
             new_value: Ok(new_val),
         }) => Ok(new_val),
         Ok(ContractHandlerEvent::UpdateResponse {
-            new_value: Err(_err),
+            new_value: Err(err),
         }) => {
-            // return Err(OpError::from(ContractError::StorageError(err)));
-            todo!("not a valid value update, notify back to requester")
+            // the contract rejected the update, the reason is reported back to the requester
+            Err(err.into())
         }
         Err(err) => Err(err.into()),
         Ok(_) => Err(OpError::UnexpectedOpState),
@@ -555,7 +735,11 @@ pub(crate) async fn request_update(
     let key = if let Some(UpdateState::PrepareRequest { key, .. }) = &update_op.state {
         key
     } else {
-        return Err(OpError::UnexpectedOpState);
+        return Err(OpError::invalid_transition(
+            update_op.id,
+            update_op.state.as_ref(),
+            "RequestUpdate",
+        ));
     };
 
     let sender = op_manager.ring.connection_manager.own_location();
@@ -617,7 +801,13 @@ pub(crate) async fn request_update(
                 .notify_op_change(NetMessage::from(msg), OpEnum::Update(op))
                 .await?;
         }
-        _ => return Err(OpError::invalid_transition(update_op.id)),
+        _ => {
+            return Err(OpError::invalid_transition(
+                update_op.id,
+                update_op.state.as_ref(),
+                "RequestUpdate",
+            ))
+        }
     };
 
     Ok(())
@@ -626,11 +816,14 @@ pub(crate) async fn request_update(
 mod messages {
     use std::{borrow::Borrow, fmt::Display};
 
-    use freenet_stdlib::prelude::{ContractKey, RelatedContracts, StateSummary, WrappedState};
+    use freenet_stdlib::prelude::{
+        ContractKey, RelatedContracts, State, StateDelta, StateSummary, UpdateData, WrappedState,
+    };
     use serde::{Deserialize, Serialize};
 
     use crate::{
         message::{InnerMessage, Transaction},
+        operations::OpError,
         ring::{Location, PeerKeyLocation},
     };
 
@@ -680,6 +873,61 @@ mod messages {
             key: ContractKey,
             new_value: WrappedState,
         },
+        /// Announces a change to a peer through the summary of the new state, used instead
+        /// of `BroadcastTo` for large states.
+        BroadcastSummary {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            key: ContractKey,
+            #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+            summary: StateSummary<'static>,
+        },
+        /// Requests the changes since the given summary from the peer which announced them.
+        RequestBody {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            key: ContractKey,
+            #[serde(deserialize_with = "StateSummary::deser_state_summary")]
+            summary: StateSummary<'static>,
+        },
+        /// The changes requested by a peer after a summary announcement.
+        ReturnBody {
+            id: Transaction,
+            sender: PeerKeyLocation,
+            target: PeerKeyLocation,
+            key: ContractKey,
+            body: UpdateBody,
+        },
+    }
+
+    /// Changes pulled by a subscriber, either a delta over its current state or the whole
+    /// state if the delta couldn't be computed.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(crate) enum UpdateBody {
+        State(WrappedState),
+        Delta(Vec<u8>),
+    }
+
+    impl TryFrom<UpdateData<'static>> for UpdateBody {
+        type Error = OpError;
+
+        fn try_from(data: UpdateData<'static>) -> Result<Self, Self::Error> {
+            match data {
+                UpdateData::State(state) => Ok(Self::State(WrappedState::new(state.into_bytes()))),
+                UpdateData::Delta(delta) => Ok(Self::Delta(delta.into_bytes())),
+                _ => Err(OpError::UnexpectedOpState),
+            }
+        }
+    }
+
+    impl From<UpdateBody> for UpdateData<'static> {
+        fn from(body: UpdateBody) -> Self {
+            match body {
+                UpdateBody::State(state) => UpdateData::State(State::from(state)),
+                UpdateBody::Delta(delta) => UpdateData::Delta(StateDelta::from(delta)),
+            }
+        }
     }
 
     impl InnerMessage for UpdateMsg {
@@ -691,6 +939,9 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => id,
                 UpdateMsg::Broadcasting { id, .. } => id,
                 UpdateMsg::BroadcastTo { id, .. } => id,
+                UpdateMsg::BroadcastSummary { id, .. } => id,
+                UpdateMsg::RequestBody { id, .. } => id,
+                UpdateMsg::ReturnBody { id, .. } => id,
             }
         }
 
@@ -699,6 +950,8 @@ mod messages {
                 UpdateMsg::RequestUpdate { target, .. } => Some(target),
                 UpdateMsg::SuccessfulUpdate { target, .. } => Some(target),
                 UpdateMsg::SeekNode { target, .. } => Some(target),
+                UpdateMsg::RequestBody { target, .. } => Some(target),
+                UpdateMsg::ReturnBody { target, .. } => Some(target),
                 _ => None,
             }
         }
@@ -709,9 +962,17 @@ mod messages {
                 UpdateMsg::SeekNode { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::Broadcasting { key, .. } => Some(Location::from(key.id())),
                 UpdateMsg::BroadcastTo { key, .. } => Some(Location::from(key.id())),
+                UpdateMsg::BroadcastSummary { key, .. } => Some(Location::from(key.id())),
                 _ => None,
             }
         }
+
+        fn is_response(&self) -> bool {
+            matches!(
+                self,
+                UpdateMsg::SuccessfulUpdate { .. } | UpdateMsg::ReturnBody { .. }
+            )
+        }
     }
 
     impl UpdateMsg {
@@ -719,6 +980,9 @@ mod messages {
             match self {
                 Self::SeekNode { sender, .. } => Some(sender),
                 Self::BroadcastTo { sender, .. } => Some(sender),
+                Self::BroadcastSummary { sender, .. } => Some(sender),
+                Self::RequestBody { sender, .. } => Some(sender),
+                Self::ReturnBody { sender, .. } => Some(sender),
                 _ => None,
             }
         }
@@ -733,6 +997,9 @@ mod messages {
                 UpdateMsg::SeekNode { id, .. } => write!(f, "SeekNode(id: {id})"),
                 UpdateMsg::Broadcasting { id, .. } => write!(f, "Broadcasting(id: {id})"),
                 UpdateMsg::BroadcastTo { id, .. } => write!(f, "BroadcastTo(id: {id})"),
+                UpdateMsg::BroadcastSummary { id, .. } => write!(f, "BroadcastSummary(id: {id})"),
+                UpdateMsg::RequestBody { id, .. } => write!(f, "RequestBody(id: {id})"),
+                UpdateMsg::ReturnBody { id, .. } => write!(f, "ReturnBody(id: {id})"),
             }
         }
     }
@@ -755,4 +1022,171 @@ pub enum UpdateState {
         value: WrappedState,
     },
     BroadcastOngoing,
+    /// Requested the changes announced through a summary.
+    AwaitingBody {
+        key: ContractKey,
+    },
+    /// Announced a change through its summary and serving the changes to subscribers.
+    ServingBodies {
+        key: ContractKey,
+    },
+}
+
+impl std::fmt::Display for UpdateState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateState::ReceivedRequest => write!(f, "ReceivedRequest"),
+            UpdateState::AwaitingResponse { key, upstream } => {
+                write!(f, "AwaitingResponse(key: {key}, upstream: {upstream:?})")
+            }
+            UpdateState::Finished { key, .. } => write!(f, "Finished(key: {key})"),
+            UpdateState::PrepareRequest { key, .. } => write!(f, "PrepareRequest(key: {key})"),
+            UpdateState::BroadcastOngoing => write!(f, "BroadcastOngoing"),
+            UpdateState::AwaitingBody { key } => write!(f, "AwaitingBody(key: {key})"),
+            UpdateState::ServingBodies { key } => write!(f, "ServingBodies(key: {key})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::RecordingBridge;
+
+    async fn test_op_manager(id: &str) -> anyhow::Result<OpManager> {
+        let op_manager = crate::test_utils::op_manager(id).await?;
+        op_manager
+            .ring
+            .connection_manager
+            .try_set_peer_key(([127, 0, 0, 1], 10_000).into());
+        Ok(op_manager)
+    }
+
+    fn update_op(state: UpdateState) -> UpdateOp {
+        UpdateOp {
+            id: Transaction::new::<UpdateMsg>(),
+            state: Some(state),
+            stats: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn updates_are_expected_only_from_subscriptions() -> anyhow::Result<()> {
+        let op_manager =
+            crate::test_utils::op_manager("updates_are_expected_only_from_subscriptions").await?;
+
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let provider = PeerKeyLocation::random();
+        assert!(!op_manager.is_subscribed_through(&key, &provider.peer));
+
+        op_manager
+            .ring
+            .add_subscriber(&key, provider.clone())
+            .unwrap();
+        assert!(op_manager.is_subscribed_through(&key, &provider.peer));
+        assert!(!op_manager.is_subscribed_through(&key, &PeerId::random()));
+        let other = ContractKey::from(ContractInstanceId::new([2; 32]));
+        assert!(!op_manager.is_subscribed_through(&other, &provider.peer));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn repeated_summaries_ignored_while_pulling_changes() -> anyhow::Result<()> {
+        let op_manager =
+            test_op_manager("repeated_summaries_ignored_while_pulling_changes").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let op = update_op(UpdateState::AwaitingBody { key });
+        let announcement = UpdateMsg::BroadcastSummary {
+            id: op.id,
+            key,
+            summary: StateSummary::from(vec![1, 2, 3]),
+            sender: PeerKeyLocation::random(),
+        };
+
+        let mut bridge = RecordingBridge::default();
+        let result = op
+            .process_message(&mut bridge, &op_manager, &announcement)
+            .await?;
+        assert!(result.return_msg.is_none());
+        assert!(matches!(
+            result.state,
+            Some(OpEnum::Update(UpdateOp {
+                state: Some(UpdateState::AwaitingBody { .. }),
+                ..
+            }))
+        ));
+        assert!(bridge.sent().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bodies_served_until_the_transaction_expires() -> anyhow::Result<()> {
+        let op_manager = test_op_manager("bodies_served_until_the_transaction_expires").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let op = update_op(UpdateState::ServingBodies { key });
+        let relayed = UpdateMsg::SuccessfulUpdate {
+            id: op.id,
+            target: op_manager.ring.connection_manager.own_location(),
+            summary: StateSummary::from(vec![1, 2, 3]),
+        };
+
+        let mut bridge = RecordingBridge::default();
+        let result = op
+            .process_message(&mut bridge, &op_manager, &relayed)
+            .await?;
+        assert!(result.return_msg.is_none());
+        assert!(matches!(
+            result.state,
+            Some(OpEnum::Update(UpdateOp {
+                state: Some(UpdateState::ServingBodies { .. }),
+                ..
+            }))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bodies_only_served_after_announcing_a_summary() -> anyhow::Result<()> {
+        let op_manager = test_op_manager("bodies_only_served_after_announcing_a_summary").await?;
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let request = |op: &UpdateOp| UpdateMsg::RequestBody {
+            id: op.id,
+            sender: PeerKeyLocation::random(),
+            target: op_manager.ring.connection_manager.own_location(),
+            key,
+            summary: StateSummary::from(vec![1, 2, 3]),
+        };
+
+        let mut bridge = RecordingBridge::default();
+        let op = update_op(UpdateState::AwaitingBody { key });
+        let msg = request(&op);
+        let error = op
+            .process_message(&mut bridge, &op_manager, &msg)
+            .await
+            .err()
+            .expect("not serving bodies");
+        let OpError::InvalidTransition { from, input, .. } = &error else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(from, &format!("AwaitingBody(key: {key})"));
+        assert!(input.starts_with("RequestBody"));
+
+        // a returned body is only applied by subscribers which requested it
+        let op = update_op(UpdateState::ReceivedRequest);
+        let body = UpdateMsg::ReturnBody {
+            id: op.id,
+            sender: PeerKeyLocation::random(),
+            target: op_manager.ring.connection_manager.own_location(),
+            key,
+            body: UpdateBody::Delta(vec![1]),
+        };
+        let error = op
+            .process_message(&mut bridge, &op_manager, &body)
+            .await
+            .err()
+            .expect("no body requested");
+        assert!(matches!(error, OpError::InvalidTransition { .. }));
+        assert!(bridge.sent().is_empty());
+        Ok(())
+    }
 }
@@ -30,7 +30,7 @@ use serde::{Deserialize, Serialize};
 use tokio::sync;
 use tracing::Instrument;
 
-use crate::message::TransactionType;
+use crate::message::{NetMessage, NetMessageV1, TransactionType};
 use crate::topology::rate::Rate;
 use crate::topology::TopologyAdjustment;
 use crate::tracing::{NetEventLog, NetEventRegister};
@@ -39,13 +39,44 @@ use crate::util::Contains;
 use crate::{
     config::GlobalExecutor,
     message::Transaction,
-    node::{self, EventLoopNotificationsSender, NodeConfig, PeerId},
+    node::{
+        self, EventLoopNotificationsSender, LifecycleEvents, NodeConfig, NodeLifecycleEvent, PeerId,
+    },
     operations::connect,
-    router::Router,
+    router::{RouteOutcome, Router},
 };
 
+mod acceptance;
+mod ban_list;
+mod circuit_breaker;
+mod clock_skew;
 mod connection_manager;
 pub(crate) use connection_manager::ConnectionManager;
+mod cooldown;
+mod home_contracts;
+mod journal;
+pub(crate) use home_contracts::HomeContracts;
+mod known_peers;
+mod liveness;
+mod location_assignment;
+pub use location_assignment::LocationAssignment;
+mod network_health;
+mod relocation;
+mod reputation;
+mod standby;
+pub(crate) use ban_list::{Ban, BanList};
+pub(crate) use clock_skew::ClockSkews;
+pub(crate) use reputation::ReputationSignal;
+mod topology_snapshot;
+pub use acceptance::{
+    AcceptancePolicy, CapacityBased, ConnectionCandidate, DistanceBased, GatewaysAcceptAll,
+    PolicyChain, ReputationWeighted, Verdict,
+};
+pub use circuit_breaker::CircuitBreakerConfig;
+pub use clock_skew::ClockSkew;
+pub use journal::{EvictionReason, TopologyEvent, TopologyEventKind};
+pub use network_health::NetworkStatus;
+pub use topology_snapshot::{CoverageGap, DistanceBucket, NeighbourInfo, TopologySnapshot};
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
@@ -96,8 +127,8 @@ pub(crate) struct Connection {
     open_at: Instant,
 }
 
-#[cfg(test)]
 impl Connection {
+    #[cfg(test)]
     pub fn new(peer: PeerId, location: Location) -> Self {
         Connection {
             location: PeerKeyLocation {
@@ -163,10 +194,25 @@ impl LiveTransactionTracker {
         )
     }
 
+    /// Peers the transaction was sent to and which did not respond yet.
+    pub fn peers_of(&self, tx: &Transaction) -> Vec<PeerId> {
+        self.tx_per_peer
+            .iter()
+            .filter(|entry| entry.value().contains(tx))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
     fn prune_transactions_from_peer(&self, peer: &PeerId) {
         self.tx_per_peer.remove(peer);
     }
 
+    fn readdress_peer(&self, previous: &PeerId, moved: PeerId) {
+        if let Some((_, txs)) = self.tx_per_peer.remove(previous) {
+            self.tx_per_peer.entry(moved).or_default().extend(txs);
+        }
+    }
+
     fn has_live_connection(&self, peer: &PeerId) -> bool {
         self.tx_per_peer.contains_key(peer)
     }
@@ -212,14 +258,18 @@ pub(crate) struct Ring {
     subscribers: DashMap<ContractKey, Vec<PeerKeyLocation>>,
     /// Contracts this peer is seeding.
     seeding_contract: DashMap<ContractKey, Score>,
+    /// Connectivity of this node with the rest of the network, watched by clients.
+    pub network_status: sync::watch::Sender<NetworkStatus>,
+    /// Lifecycle events of this node, subscribed to by embedding applications.
+    pub lifecycle_events: LifecycleEvents,
+    /// Cached contracts this node is among the closest peers to.
+    pub home_contracts: HomeContracts,
+    /// Whether this node is a light client, which never seeds contracts.
+    light_client: bool,
     // A peer which has been blacklisted to perform actions regarding a given contract.
     // todo: add blacklist
     // contract_blacklist: Arc<DashMap<ContractKey, Vec<Blacklisted>>>,
     event_register: Box<dyn NetEventRegister>,
-    /// Whether this peer is a gateway or not. This will affect behavior of the node when acquiring
-    /// and dropping connections.
-    #[allow(unused)]
-    is_gateway: bool,
 }
 
 // /// A data type that represents the fact that a peer has been blacklisted
@@ -257,11 +307,16 @@ impl Ring {
     /// Min number of seeding contracts.
     const MIN_SEEDING_CONTRACTS: usize = Self::MAX_SEEDING_CONTRACTS / 4;
 
+    /// Max distance to a contract for it to be seeded once the min number of seeding contracts is reached.
+    const CACHING_DISTANCE: f64 = 0.05;
+
+    /// Times slower than predicted a peer has to start responding to count as underperforming.
+    const UNDERPERFORMANCE_FACTOR: f64 = 3.0;
+
     pub fn new<ER: NetEventRegister + Clone>(
         config: &NodeConfig,
         event_loop_notifier: EventLoopNotificationsSender,
         event_register: ER,
-        is_gateway: bool,
         connection_manager: ConnectionManager,
     ) -> anyhow::Result<Arc<Self>> {
         let (live_tx_tracker, missing_candidate_rx) = LiveTransactionTracker::new();
@@ -283,8 +338,11 @@ impl Ring {
             subscribers: DashMap::new(),
             seeding_contract: DashMap::new(),
             live_tx_tracker: live_tx_tracker.clone(),
+            network_status: sync::watch::Sender::new(NetworkStatus::Connecting),
+            lifecycle_events: LifecycleEvents::new(),
+            home_contracts: HomeContracts::new(home_contracts::HOME_REPLICAS),
+            light_client: config.config.is_light_client,
             event_register: Box::new(event_register),
-            is_gateway,
         };
 
         if let Some(loc) = config.location {
@@ -302,6 +360,23 @@ impl Ring {
             tracing::info_span!(parent: current_span, "connection_maintenance")
         };
 
+        if ring.connection_manager.liveness.is_enabled() {
+            GlobalExecutor::spawn(
+                ring.clone()
+                    .keepalive(event_loop_notifier.clone())
+                    .instrument(span.clone()),
+            );
+        }
+        let disconnected_timeout = config
+            .disconnected_timeout
+            .unwrap_or(crate::config::DEFAULT_DISCONNECTED_TIMEOUT);
+        if !disconnected_timeout.is_zero() {
+            GlobalExecutor::spawn(
+                ring.clone()
+                    .network_health_watchdog(event_loop_notifier.clone(), disconnected_timeout)
+                    .instrument(span.clone()),
+            );
+        }
         GlobalExecutor::spawn(
             ring.clone()
                 .connection_maintenance(event_loop_notifier, live_tx_tracker, missing_candidate_rx)
@@ -312,13 +387,36 @@ impl Ring {
     }
 
     pub fn is_gateway(&self) -> bool {
-        self.is_gateway
+        self.connection_manager.gateway().is_some()
+    }
+
+    pub fn is_light_client(&self) -> bool {
+        self.light_client
     }
 
     pub fn open_connections(&self) -> usize {
         self.connection_manager.get_open_connections()
     }
 
+    /// Returns the current neighbours of this node and how they are spread along the ring.
+    pub fn topology_snapshot(&self) -> TopologySnapshot {
+        let own_location = self
+            .connection_manager
+            .get_peer_key()
+            .and_then(|_| self.connection_manager.own_location().location);
+        let neighbours = self
+            .connection_manager
+            .get_connections_by_location()
+            .into_values()
+            .flatten()
+            .map(|conn| conn.location);
+        let mut snapshot = TopologySnapshot::new(own_location, neighbours);
+        for neighbour in &mut snapshot.neighbours {
+            neighbour.clock_skew = self.connection_manager.clock_skews.get(&neighbour.address);
+        }
+        snapshot
+    }
+
     async fn refresh_router<ER: NetEventRegister>(router: Arc<RwLock<Router>>, register: ER) {
         let mut interval = tokio::time::interval(Duration::from_secs(60 * 5));
         interval.tick().await;
@@ -334,15 +432,23 @@ impl Ring {
                 .expect("todo: propagate this to main thread");
             if !history.is_empty() {
                 let router_ref = &mut *router.write();
+                let priors = router_ref.priors().cloned();
                 *router_ref = Router::new(&history);
+                if let Some(priors) = priors {
+                    if let Err(error) = router_ref.set_priors(priors) {
+                        tracing::warn!(%error, "Failed to restore routing priors");
+                    }
+                }
             }
         }
     }
 
     /// Return if a contract is within appropiate seeding distance.
     pub fn should_seed(&self, key: &ContractKey) -> bool {
-        const CACHING_DISTANCE: f64 = 0.05;
-        let caching_distance = Distance::new(CACHING_DISTANCE);
+        if self.light_client {
+            return false;
+        }
+        let caching_distance = Distance::new(Self::CACHING_DISTANCE);
         if self.seeding_contract.len() < Self::MIN_SEEDING_CONTRACTS {
             return true;
         }
@@ -389,10 +495,47 @@ impl Ring {
             }
         }
 
-        self.seeding_contract.insert(key, seed_score);
+        if self.seeding_contract.insert(key, seed_score).is_none() {
+            self.lifecycle_events
+                .emit(NodeLifecycleEvent::ContractCached { key });
+        }
+        self.refresh_home_contracts();
         (contract_to_drop, old_subscribers)
     }
 
+    /// Re-evaluates which of the seeded contracts this node is home for, after either the
+    /// contracts or the topology around this node changed.
+    fn refresh_home_contracts(&self) {
+        let Some(own) = self.connection_manager.own_location().location else {
+            return;
+        };
+        // light clients are not counted among the closest holders, since they won't cache them
+        let neighbours = self.connection_manager.caching_neighbour_locations();
+        let changes = self
+            .home_contracts
+            .refresh(own, &neighbours, self.seeded_contracts());
+        if !changes.is_empty() {
+            tracing::debug!(
+                gained = changes.gained.len(),
+                lost = changes.lost.len(),
+                "Contracts this node is home for changed"
+            );
+        }
+    }
+
+    /// Whether no other caching neighbour is closer to the contract than this node. A node
+    /// which has no location yet is not connected to anyone else caching it.
+    pub fn is_closest_holder(&self, key: &ContractKey) -> bool {
+        if self.connection_manager.get_peer_key().is_none() {
+            return true;
+        }
+        let Some(own) = self.connection_manager.own_location().location else {
+            return true;
+        };
+        let neighbours = self.connection_manager.caching_neighbour_locations();
+        home_contracts::is_among_closest(own, &neighbours, Location::from(key), 1)
+    }
+
     fn calculate_seed_score(&self, key: &ContractKey) -> Score {
         let location = self
             .connection_manager
@@ -405,6 +548,87 @@ impl Ring {
         Score(score)
     }
 
+    /// Moves this peer to a new location in the ring, re-scoring the seeded contracts from it.
+    ///
+    /// Returns the contracts no longer within seeding distance, which stop being seeded,
+    /// along with their subscribers.
+    pub fn relocate(&self, location: Location) -> Vec<(ContractKey, Vec<PeerKeyLocation>)> {
+        self.connection_manager.update_location(Some(location));
+        for mut entry in self.seeding_contract.iter_mut() {
+            let score = self.calculate_seed_score(entry.key());
+            *entry.value_mut() = score;
+        }
+
+        let min_score = 0.5 - Self::CACHING_DISTANCE;
+        let mut too_far: Vec<_> = self
+            .seeding_contract
+            .iter()
+            .filter(|entry| entry.value().0 < min_score)
+            .map(|entry| (*entry.key(), entry.value().0))
+            .collect();
+        too_far.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        let droppable = self
+            .seeding_contract
+            .len()
+            .saturating_sub(Self::MIN_SEEDING_CONTRACTS);
+        let dropped = too_far
+            .into_iter()
+            .take(droppable)
+            .map(|(key, _)| {
+                self.seeding_contract.remove(&key);
+                let subscribers = self
+                    .subscribers
+                    .remove(&key)
+                    .map(|(_, subs)| subs)
+                    .unwrap_or_default();
+                (key, subscribers)
+            })
+            .collect();
+        self.refresh_home_contracts();
+        dropped
+    }
+
+    /// Records the new location of a neighbour which relocated, returns false if there is
+    /// no connection with the peer.
+    pub fn relocate_peer(&self, peer: &PeerId, location: Location) -> bool {
+        if !self.connection_manager.relocate_peer(peer, location) {
+            return false;
+        }
+        self.subscribers.alter_all(|_, mut subs| {
+            for sub in subs.iter_mut().filter(|sub| &sub.peer == peer) {
+                sub.location = Some(location);
+            }
+            subs.sort();
+            subs
+        });
+        self.refresh_home_contracts();
+        true
+    }
+
+    /// Records that the neighbour is a light client, so it is no longer counted among the
+    /// holders of the contracts close to it.
+    pub fn light_client_neighbour(&self, peer: PeerId) {
+        self.connection_manager.light_client_neighbour(peer);
+        self.refresh_home_contracts();
+    }
+
+    /// Stops seeding a contract whose state was evicted from the storage, dropping its
+    /// subscribers.
+    pub fn contract_evicted(&self, key: &ContractKey, size: u64) {
+        self.seeding_contract.remove(key);
+        self.subscribers.remove(key);
+        self.lifecycle_events
+            .emit(NodeLifecycleEvent::ContractEvicted { key: *key, size });
+    }
+
+    /// Contracts this node is currently seeding.
+    pub fn seeded_contracts(&self) -> Vec<ContractKey> {
+        self.seeding_contract
+            .iter()
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
     /// Whether this node already is seeding to this contract or not.
     #[inline]
     pub fn is_seeding_contract(&self, key: &ContractKey) -> bool {
@@ -427,10 +651,38 @@ impl Ring {
         tracing::info!(%peer, this = ?self.connection_manager.get_peer_key(), %was_reserved, "Adding connection to peer");
         self.connection_manager
             .add_connection(loc, peer.clone(), was_reserved);
+        self.lifecycle_events.emit(NodeLifecycleEvent::PeerJoined {
+            peer: peer.clone(),
+            location: loc,
+        });
         self.event_register
             .register_events(Either::Left(NetEventLog::connected(self, peer, loc)))
             .await;
-        self.refresh_density_request_cache()
+        self.refresh_density_request_cache();
+        self.refresh_home_contracts();
+    }
+
+    /// Refreshes when the currently connected peers were last seen and the location of this
+    /// node, and persists them.
+    pub async fn persist_known_peers(&self) {
+        let known_peers = &self.connection_manager.known_peers;
+        let now = self.connection_manager.clock.unix_time();
+        if self.connection_manager.get_peer_key().is_some() {
+            if let Some(location) = self.connection_manager.own_location().location {
+                known_peers.record_location(location);
+            }
+        }
+        let connected = self
+            .connection_manager
+            .get_connections_by_location()
+            .into_iter()
+            .flat_map(|(location, conns)| conns.into_iter().map(move |conn| (conn, location)));
+        for (conn, location) in connected {
+            known_peers.record(conn.location.peer, location, now);
+        }
+        if let Err(error) = known_peers.persist(now).await {
+            tracing::warn!(%error, "Failed to persist known peers");
+        }
     }
 
     fn refresh_density_request_cache(&self) {
@@ -467,6 +719,36 @@ impl Ring {
     }
 
     pub fn routing_finished(&self, event: crate::router::RouteEvent) {
+        let breakers = &self.connection_manager.circuit_breakers;
+        let now = self.connection_manager.clock.now();
+        let signal = match event.outcome {
+            RouteOutcome::Failure => {
+                breakers.record_failure(&event.peer.peer, now);
+                ReputationSignal::FailedTransaction
+            }
+            RouteOutcome::Success {
+                time_to_response_start,
+                ..
+            } => {
+                breakers.record_success(&event.peer.peer, now);
+                let expected = self
+                    .router
+                    .read()
+                    .expected_response_start(&event.peer, event.contract_location);
+                match expected {
+                    Some(expected)
+                        if time_to_response_start.as_secs_f64()
+                            > expected * Self::UNDERPERFORMANCE_FACTOR =>
+                    {
+                        ReputationSignal::Underperformed
+                    }
+                    _ => ReputationSignal::Succeeded,
+                }
+            }
+        };
+        self.connection_manager
+            .peer_reputation
+            .report(&event.peer.peer, signal, now);
         self.connection_manager
             .topology_manager
             .write()
@@ -498,6 +780,16 @@ impl Ring {
         Ok(())
     }
 
+    /// Removes the peer from the subscribers of the contract, returning whether it was one.
+    pub fn remove_subscriber(&self, contract: &ContractKey, peer: &PeerId) -> bool {
+        let Some(mut subs) = self.subscribers.get_mut(contract) else {
+            return false;
+        };
+        let before = subs.len();
+        subs.retain(|sub| &sub.peer != peer);
+        before != subs.len()
+    }
+
     pub fn subscribers_of(
         &self,
         contract: &ContractKey,
@@ -520,18 +812,43 @@ impl Ring {
                 subs
             });
         }
+        self.refresh_home_contracts();
         self.event_register
             .register_events(Either::Left(NetEventLog::disconnected(self, &peer)))
             .await;
     }
 
+    /// Relinks the connection with a peer which moved to a new address, and any state tied
+    /// to its previous address, to the new one.
+    ///
+    /// Returns false if there is no connection with the previous peer, or its key does not
+    /// match the key of the moved peer.
+    pub fn readdress_peer(&self, previous: &PeerId, moved: PeerId) -> bool {
+        if !self.connection_manager.readdress(previous, moved.clone()) {
+            return false;
+        }
+        self.live_tx_tracker.readdress_peer(previous, moved.clone());
+        self.subscribers.alter_all(|_, mut subs| {
+            for sub in subs.iter_mut().filter(|sub| &sub.peer == previous) {
+                sub.peer = moved.clone();
+            }
+            subs.sort();
+            subs
+        });
+        true
+    }
+
+    /// Peers with an open circuit are skipped, unless due a probe.
     pub fn closest_to_location(
         &self,
         location: Location,
         skip_list: &[PeerId],
     ) -> Option<PeerKeyLocation> {
         use rand::seq::SliceRandom;
-        self.connection_manager
+        let now = self.connection_manager.clock.now();
+        let breakers = &self.connection_manager.circuit_breakers;
+        let selected = self
+            .connection_manager
             .get_connections_by_location()
             .iter()
             .sorted_by(|(loc_a, _), (loc_b, _)| {
@@ -540,14 +857,17 @@ impl Ring {
             .find_map(|(_, conns)| {
                 for _ in 0..conns.len() {
                     let conn = conns.choose(&mut rand::thread_rng()).unwrap();
-                    let selected =
-                        (!skip_list.contains(&conn.location.peer)).then_some(conn.location.clone());
+                    let selected = (!skip_list.contains(&conn.location.peer)
+                        && breakers.allows(&conn.location.peer, now))
+                    .then_some(conn.location.clone());
                     if selected.is_some() {
                         return selected;
                     }
                 }
                 None
-            })
+            })?;
+        breakers.forwarding(&selected.peer, now);
+        Some(selected)
     }
 
     async fn connection_maintenance(
@@ -562,11 +882,18 @@ impl Ring {
         const CONNECTION_AGE_THRESOLD: Duration = Duration::from_secs(5);
         const CHECK_TICK_DURATION: Duration = Duration::from_secs(10);
         const REGENERATE_DENSITY_MAP_INTERVAL: Duration = Duration::from_secs(60);
+        const SMALL_WORLD_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
         let mut check_interval = tokio::time::interval(CHECK_TICK_DURATION);
         check_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
         let mut refresh_density_map = tokio::time::interval(REGENERATE_DENSITY_MAP_INTERVAL);
         refresh_density_map.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut small_world_check = tokio::time::interval(SMALL_WORLD_CHECK_INTERVAL);
+        small_world_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        // skip the immediate first tick, the ring is not settled yet
+        small_world_check.tick().await;
+        let mut small_world_due = false;
+        let mut last_relocation: Option<Instant> = None;
 
         let mut missing = BTreeMap::new();
 
@@ -647,21 +974,97 @@ impl Ring {
                     .collect()
             };
 
-            let adjustment = self
+            // disconnect from the peers which misbehaved too much
+            let misbehaving: Vec<_> = self
+                .connection_manager
+                .get_connections_by_location()
+                .into_values()
+                .flatten()
+                .map(|conn| conn.location.peer)
+                .filter(|peer| {
+                    self.connection_manager
+                        .peer_reputation
+                        .is_below_threshold(peer, self.connection_manager.clock.now())
+                })
+                .collect();
+            for peer in misbehaving {
+                tracing::info!(
+                    %peer,
+                    reputation = self.connection_manager.reputation(&peer),
+                    "Dropping connection to peer with low reputation"
+                );
+                self.connection_manager.journal.record(
+                    TopologyEventKind::evicted(&peer, EvictionReason::LowReputation),
+                    self.connection_manager.clock.unix_time(),
+                );
+                notifier
+                    .send(Either::Right(crate::message::NodeEvent::DropConnection(
+                        peer,
+                    )))
+                    .await
+                    .map_err(|error| {
+                        tracing::debug!(?error, "Shutting down connection maintenance task");
+                        error
+                    })?;
+            }
+
+            // drop the connections evicted to make room for better placed ones, or above
+            // the max number of connections
+            for peer in self.connection_manager.take_evictions() {
+                notifier
+                    .send(Either::Right(crate::message::NodeEvent::DropConnection(
+                        peer,
+                    )))
+                    .await
+                    .map_err(|error| {
+                        tracing::debug!(?error, "Shutting down connection maintenance task");
+                        error
+                    })?;
+            }
+
+            let own_location = self.connection_manager.own_location().location;
+            let mut adjustment = self
                 .connection_manager
                 .topology_manager
                 .write()
-                .adjust_topology(
-                    &neighbor_locations,
-                    &self.connection_manager.own_location().location,
-                    Instant::now(),
-                );
+                .adjust_topology(&neighbor_locations, &own_location, Instant::now());
+            // once resource usage is within bounds, periodically reshape the connections
+            // towards the small-world distribution
+            let check_small_world = std::mem::take(&mut small_world_due)
+                && pending_conn_adds.is_empty()
+                && matches!(adjustment, TopologyAdjustment::NoChange);
+            let check_relocation = check_small_world
+                && !self.is_gateway()
+                && last_relocation
+                    .filter(|at| {
+                        self.connection_manager
+                            .clock
+                            .now()
+                            .saturating_duration_since(*at)
+                            < relocation::MIN_RELOCATION_INTERVAL
+                    })
+                    .is_none();
+            if let (true, Some(own_location)) = (check_small_world, own_location) {
+                adjustment = self
+                    .connection_manager
+                    .topology_manager
+                    .read()
+                    .adjust_small_world(
+                        &self.connection_manager.get_connections_by_location(),
+                        &neighbor_locations,
+                        own_location,
+                    );
+            }
             match adjustment {
                 TopologyAdjustment::AddConnections(target_locs) => {
                     pending_conn_adds.extend(target_locs);
                 }
                 TopologyAdjustment::RemoveConnections(mut should_disconnect_peers) => {
                     for peer in should_disconnect_peers.drain(..) {
+                        self.connection_manager.journal.record(
+                            TopologyEventKind::evicted(&peer.peer, EvictionReason::Topology),
+                            self.connection_manager.clock.unix_time(),
+                        );
                         notifier
                             .send(Either::Right(crate::message::NodeEvent::DropConnection(
                                 peer.peer,
@@ -679,23 +1082,167 @@ impl Ring {
                 TopologyAdjustment::NoChange => {}
             }
 
+            // move out of over-crowded arcs of the ring
+            if let (true, Some(own_location)) = (check_relocation, own_location) {
+                let neighbours = self
+                    .connection_manager
+                    .get_connections_by_location()
+                    .into_iter()
+                    .filter(|(_, conns)| !conns.is_empty())
+                    .map(|(loc, _)| loc);
+                if let Some(location) =
+                    relocation::relocation_target(own_location, neighbours, rand::random())
+                {
+                    last_relocation = Some(self.connection_manager.clock.now());
+                    notifier
+                        .send(Either::Right(crate::message::NodeEvent::Relocate {
+                            location,
+                        }))
+                        .await
+                        .map_err(|error| {
+                            tracing::debug!(?error, "Shutting down connection maintenance task");
+                            error
+                        })?;
+                }
+            }
+
             tokio::select! {
               _ = refresh_density_map.tick() => {
                 self.refresh_density_request_cache();
+                self.persist_known_peers().await;
+              }
+              _ = small_world_check.tick() => {
+                small_world_due = true;
               }
               _ = check_interval.tick() => {}
             }
         }
     }
 
+    /// Periodically pings the neighbours of this node, dropping the connections with the ones
+    /// which stopped answering and looking for new connections to replace them.
+    async fn keepalive(
+        self: Arc<Self>,
+        notifier: EventLoopNotificationsSender,
+    ) -> anyhow::Result<()> {
+        let liveness = self.connection_manager.liveness.clone();
+        let mut interval = tokio::time::interval(liveness.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let Some(this_peer) = self.connection_manager.get_peer_key() else {
+                continue;
+            };
+            let round = liveness.next_round(self.connection_manager.connected_peers());
+            for (target, nonce) in round.ping {
+                let msg = NetMessage::V1(NetMessageV1::Ping {
+                    transaction: Transaction::new::<connect::ConnectMsg>(),
+                    target,
+                    from: this_peer.clone(),
+                    nonce,
+                });
+                notifier.send(Either::Left(msg)).await.map_err(|error| {
+                    tracing::debug!(?error, "Shutting down keep-alive task");
+                    error
+                })?;
+            }
+            for peer in round.dead {
+                let location = self
+                    .connection_manager
+                    .location_for_peer
+                    .read()
+                    .get(&peer)
+                    .copied();
+                tracing::info!(%peer, "Dropping connection to unresponsive peer");
+                self.connection_manager.journal.record(
+                    TopologyEventKind::evicted(&peer, EvictionReason::Unresponsive),
+                    self.connection_manager.clock.unix_time(),
+                );
+                notifier
+                    .send(Either::Right(crate::message::NodeEvent::DropConnection(
+                        peer.clone(),
+                    )))
+                    .await
+                    .map_err(|error| {
+                        tracing::debug!(?error, "Shutting down keep-alive task");
+                        error
+                    })?;
+                if let Some(location) = location {
+                    self.acquire_new(location, &[&this_peer, &peer], &notifier)
+                        .await?;
+                }
+            }
+        }
+    }
+
+    /// Watches the number of live neighbours, letting the event loop know when this node
+    /// is cut off the network, so it joins again, and when it is back.
+    async fn network_health_watchdog(
+        self: Arc<Self>,
+        notifier: EventLoopNotificationsSender,
+        grace_period: Duration,
+    ) -> anyhow::Result<()> {
+        use crate::message::NodeEvent;
+        use network_health::{HealthChange, PartitionDetector};
+
+        const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+        let mut detector = PartitionDetector::new(grace_period);
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let Some(change) =
+                detector.observe(self.open_connections(), self.connection_manager.clock.now())
+            else {
+                continue;
+            };
+            let event = match change {
+                HealthChange::Joined => {
+                    self.network_status.send_replace(NetworkStatus::Connected);
+                    continue;
+                }
+                HealthChange::Disconnected => {
+                    tracing::warn!(?grace_period, "No live neighbours left, disconnected");
+                    self.network_status
+                        .send_replace(NetworkStatus::Disconnected);
+                    NodeEvent::Disconnected
+                }
+                HealthChange::Reconnected { offline_for } => {
+                    tracing::info!(?offline_for, "Reconnected to the network");
+                    self.network_status.send_replace(NetworkStatus::Connected);
+                    NodeEvent::Reconnected { offline_for }
+                }
+            };
+            notifier.send(Either::Right(event)).await.map_err(|error| {
+                tracing::debug!(?error, "Shutting down network health watchdog");
+                error
+            })?;
+        }
+    }
+
     #[tracing::instrument(level = "debug", skip(self, notifier), fields(peer = %self.connection_manager.pub_key))]
-    async fn acquire_new(
+    pub(crate) async fn acquire_new(
         &self,
         ideal_location: Location,
         skip_list: &[&PeerId],
         notifier: &EventLoopNotificationsSender,
     ) -> anyhow::Result<Option<Transaction>> {
         use crate::message::InnerMessage;
+        // gateway and standby slots can leave more connections open than the max
+        let missing_connections = self
+            .connection_manager
+            .max_connections()
+            .saturating_sub(self.open_connections());
+        if missing_connections == 0 {
+            tracing::debug!(%ideal_location, "Not acquiring new connections, already at the max");
+            return Ok(None);
+        }
+        if let Some((peer, location)) = self.connection_manager.promote_standby(ideal_location) {
+            tracing::debug!(%peer, %location, %ideal_location, "Promoting standby connection");
+            self.add_connection(location, peer, false).await;
+            return Ok(None);
+        }
         let query_target = {
             let router = self.router.read();
             if let Some(t) =
@@ -714,7 +1261,6 @@ impl Ring {
             %ideal_location,
             "Adding new connections"
         );
-        let missing_connections = self.connection_manager.max_connections - self.open_connections();
         let connected = self.connection_manager.connected_peers();
         let msg = connect::ConnectMsg::Request {
             id: Transaction::new::<connect::ConnectMsg>(),
@@ -0,0 +1,215 @@
+//! Policies deciding whether a peer asking to connect to this node is accepted.
+//!
+//! Which connections a node accepts shapes the topology of the network, so deployments may
+//! want to tune it, e.g. to let gateways take in as many newcomers as they can. A policy looks
+//! at the candidate peer and at the connections of this node, and either settles the decision
+//! or abstains, leaving it to the next policy in a [`PolicyChain`]. When every policy abstains
+//! the node falls back to comparing the candidate against the request density it observes.
+//!
+//! Some checks are not up to the policies: peers already connected, peers recently dropped
+//! and peers at a location some neighbour already has are always rejected.
+
+use std::{fmt::Debug, sync::Arc};
+
+use super::Location;
+use crate::node::PeerId;
+
+/// A peer asking to connect to this node, along with the state of this node.
+#[derive(Debug, Clone)]
+pub struct ConnectionCandidate<'a> {
+    pub peer: &'a PeerId,
+    pub location: Location,
+    pub own_location: Location,
+    /// Locations of the current neighbours of this node.
+    pub neighbours: &'a [Location],
+    /// Open connections of this node, plus the ones being established.
+    pub connections: usize,
+    pub min_connections: usize,
+    pub max_connections: usize,
+    /// Reputation of the candidate; zero is neutral and negative scores denote misbehaviour.
+    pub reputation: f64,
+    /// Reputation below which peers are disconnected.
+    pub reputation_threshold: f64,
+    /// Whether this node is a gateway.
+    pub is_gateway: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Reject,
+    /// Accept only if a connection contributing less to the coverage of the ring can be
+    /// evicted to make room for the candidate.
+    Replace,
+    /// Leave the decision to the next policy.
+    Abstain,
+}
+
+pub trait AcceptancePolicy: Debug + Send + Sync + 'static {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict;
+}
+
+impl<P: AcceptancePolicy + ?Sized> AcceptancePolicy for Arc<P> {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        (**self).evaluate(candidate)
+    }
+}
+
+/// Policies evaluated in order, the first one not abstaining settles the decision.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyChain(Vec<Arc<dyn AcceptancePolicy>>);
+
+impl PolicyChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, policy: impl AcceptancePolicy) -> Self {
+        self.0.push(Arc::new(policy));
+        self
+    }
+}
+
+impl AcceptancePolicy for PolicyChain {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        self.0
+            .iter()
+            .map(|policy| policy.evaluate(candidate))
+            .find(|verdict| *verdict != Verdict::Abstain)
+            .unwrap_or(Verdict::Abstain)
+    }
+}
+
+/// The policy used unless configured otherwise.
+pub(crate) fn default_policy() -> PolicyChain {
+    PolicyChain::new()
+        .then(ReputationWeighted)
+        .then(CapacityBased)
+}
+
+/// Rejects misbehaving peers: those below the reputation threshold always, and those
+/// halfway there once this node has the min number of connections.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReputationWeighted;
+
+impl AcceptancePolicy for ReputationWeighted {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        if candidate.reputation < candidate.reputation_threshold {
+            return Verdict::Reject;
+        }
+        if candidate.connections >= candidate.min_connections
+            && candidate.reputation < candidate.reputation_threshold / 2.0
+        {
+            return Verdict::Reject;
+        }
+        Verdict::Abstain
+    }
+}
+
+/// Accepts any peer until this node has the min number of connections, and only replaces
+/// worse placed connections once it has the max number.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CapacityBased;
+
+impl AcceptancePolicy for CapacityBased {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        if candidate.connections < candidate.min_connections {
+            Verdict::Accept
+        } else if candidate.connections >= candidate.max_connections {
+            Verdict::Replace
+        } else {
+            Verdict::Abstain
+        }
+    }
+}
+
+/// Rejects peers close to a current neighbour relative to their distance to this node,
+/// since that neighbour already covers their part of the ring.
+#[derive(Debug, Clone, Copy)]
+pub struct DistanceBased {
+    /// Min distance to the closest neighbour, as a fraction of the distance to this node.
+    pub min_spacing: f64,
+}
+
+impl Default for DistanceBased {
+    fn default() -> Self {
+        Self { min_spacing: 0.1 }
+    }
+}
+
+impl AcceptancePolicy for DistanceBased {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        let distance = candidate.own_location.distance(candidate.location).as_f64();
+        let covered = candidate.neighbours.iter().any(|neighbour| {
+            neighbour.distance(candidate.location).as_f64() < distance * self.min_spacing
+        });
+        if covered {
+            Verdict::Reject
+        } else {
+            Verdict::Abstain
+        }
+    }
+}
+
+/// Gateways accept every peer they have room for, so newcomers can always join through them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GatewaysAcceptAll;
+
+impl AcceptancePolicy for GatewaysAcceptAll {
+    fn evaluate(&self, candidate: &ConnectionCandidate<'_>) -> Verdict {
+        match (
+            candidate.is_gateway,
+            candidate.connections < candidate.max_connections,
+        ) {
+            (false, _) => Verdict::Abstain,
+            (true, true) => Verdict::Accept,
+            (true, false) => Verdict::Replace,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_verdict_wins() {
+        let peer = PeerId::random();
+        let neighbours = [Location::new(0.5), Location::new(0.9)];
+        let candidate = |location, connections, reputation| ConnectionCandidate {
+            peer: &peer,
+            location: Location::new(location),
+            own_location: Location::new(0.1),
+            neighbours: &neighbours,
+            connections,
+            min_connections: 2,
+            max_connections: 4,
+            reputation,
+            reputation_threshold: -2.0,
+            is_gateway: false,
+        };
+
+        let policy = default_policy();
+        assert_eq!(policy.evaluate(&candidate(0.3, 1, -1.5)), Verdict::Accept);
+        assert_eq!(policy.evaluate(&candidate(0.3, 1, -2.5)), Verdict::Reject);
+        assert_eq!(policy.evaluate(&candidate(0.3, 2, -1.5)), Verdict::Reject);
+        assert_eq!(policy.evaluate(&candidate(0.3, 2, 0.0)), Verdict::Abstain);
+        assert_eq!(policy.evaluate(&candidate(0.3, 4, 0.0)), Verdict::Replace);
+
+        // 0.52 is 0.42 away from this node but just 0.02 away from the neighbour at 0.5
+        let policy = default_policy().then(DistanceBased::default());
+        assert_eq!(policy.evaluate(&candidate(0.52, 3, 0.0)), Verdict::Reject);
+        assert_eq!(policy.evaluate(&candidate(0.3, 3, 0.0)), Verdict::Abstain);
+
+        let policy = PolicyChain::new()
+            .then(GatewaysAcceptAll)
+            .then(CapacityBased);
+        let gateway = |connections| ConnectionCandidate {
+            is_gateway: true,
+            ..candidate(0.3, connections, 0.0)
+        };
+        assert_eq!(policy.evaluate(&gateway(3)), Verdict::Accept);
+        assert_eq!(policy.evaluate(&gateway(4)), Verdict::Replace);
+        assert_eq!(policy.evaluate(&candidate(0.3, 3, 0.0)), Verdict::Abstain);
+    }
+}
@@ -0,0 +1,144 @@
+//! Peers banned for abusing this node.
+//!
+//! Peers are banned by the message policing of the event loop once they keep flooding this
+//! node with messages despite being throttled. The first bans of a peer are temporary; a peer
+//! banned too many times is banned permanently, and permanent bans are persisted to disk so
+//! they survive restarts. Bans are tied to the public key of the peer rather than its address,
+//! which it could easily change.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::transport::TransportPublicKey;
+
+const BANNED_PEERS_FILE: &str = "banned_peers";
+
+/// Ban issued to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ban {
+    Temporary,
+    Permanent,
+}
+
+#[derive(Default)]
+struct Bans {
+    temporary: HashMap<TransportPublicKey, Instant>,
+    /// Number of temporary bans issued to each peer since this node started.
+    strikes: HashMap<TransportPublicKey, u32>,
+    permanent: HashSet<TransportPublicKey>,
+}
+
+/// Shared handle to the peers banned by this node.
+#[derive(Clone)]
+pub(crate) struct BanList {
+    ban_duration: Duration,
+    /// Temporary bans after which a peer is banned permanently.
+    bans_to_permanent: u32,
+    bans: Arc<Mutex<Bans>>,
+    /// Not persisted if unset.
+    path: Option<Arc<PathBuf>>,
+}
+
+impl BanList {
+    /// Loads the peers permanently banned in the given directory, if any.
+    pub fn load(dir: &Path, ban_duration: Duration, bans_to_permanent: u32) -> Self {
+        let path = dir.join(BANNED_PEERS_FILE);
+        let permanent: Vec<TransportPublicKey> = match std::fs::read(&path) {
+            Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|error| {
+                tracing::warn!(%error, "Failed to load banned peers, discarding");
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            ban_duration,
+            bans_to_permanent,
+            bans: Arc::new(Mutex::new(Bans {
+                permanent: permanent.into_iter().collect(),
+                ..Default::default()
+            })),
+            path: Some(Arc::new(path)),
+        }
+    }
+
+    /// A ban list which is not persisted.
+    #[cfg(test)]
+    pub fn in_memory(ban_duration: Duration, bans_to_permanent: u32) -> Self {
+        Self {
+            ban_duration,
+            bans_to_permanent,
+            bans: Arc::default(),
+            path: None,
+        }
+    }
+
+    /// Bans the peer, permanently if it was already banned too many times.
+    pub fn ban(&self, peer: &TransportPublicKey, now: Instant) -> Ban {
+        let mut bans = self.bans.lock();
+        bans.temporary.retain(|_, until| *until > now);
+        let strikes = bans.strikes.entry(peer.clone()).or_default();
+        *strikes += 1;
+        if *strikes <= self.bans_to_permanent {
+            bans.temporary.insert(peer.clone(), now + self.ban_duration);
+            return Ban::Temporary;
+        }
+        bans.strikes.remove(peer);
+        bans.permanent.insert(peer.clone());
+        let permanent: Vec<_> = bans.permanent.iter().cloned().collect();
+        drop(bans);
+        if let Err(error) = self.persist(&permanent) {
+            tracing::warn!(%error, "Failed to persist banned peers");
+        }
+        Ban::Permanent
+    }
+
+    /// Whether the peer is banned and should not be connected to.
+    pub fn is_banned(&self, peer: &TransportPublicKey, now: Instant) -> bool {
+        let bans = self.bans.lock();
+        bans.permanent.contains(peer) || bans.temporary.get(peer).is_some_and(|until| *until > now)
+    }
+
+    fn persist(&self, permanent: &[TransportPublicKey]) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let bytes = bincode::serialize(permanent).map_err(std::io::Error::other)?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(tmp, path.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::PeerId;
+
+    #[test]
+    fn bans_escalate_to_permanent() {
+        let dir = tempfile::tempdir().unwrap();
+        let bans = BanList::load(dir.path(), Duration::from_secs(60), 1);
+        let peer = PeerId::random().pub_key;
+        let now = Instant::now();
+        assert!(!bans.is_banned(&peer, now));
+
+        assert_eq!(bans.ban(&peer, now), Ban::Temporary);
+        assert!(bans.is_banned(&peer, now + Duration::from_secs(59)));
+        assert!(!bans.is_banned(&peer, now + Duration::from_secs(60)));
+        assert!(!bans.is_banned(&PeerId::random().pub_key, now));
+
+        let later = now + Duration::from_secs(120);
+        assert_eq!(bans.ban(&peer, later), Ban::Permanent);
+        assert!(bans.is_banned(&peer, later + Duration::from_secs(3600)));
+
+        // permanent bans survive restarts
+        let bans = BanList::load(dir.path(), Duration::from_secs(60), 1);
+        assert!(bans.is_banned(&peer, later));
+    }
+}
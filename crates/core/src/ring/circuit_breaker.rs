@@ -0,0 +1,206 @@
+//! Circuit breakers of the peers requests are forwarded to.
+//!
+//! A peer failing most of the requests forwarded to it keeps being picked by the router for a
+//! while, since its reputation only drops gradually. Once most of the recent requests to a peer
+//! fail its circuit opens and no requests are forwarded to it, until after a while a single
+//! probe request is let through: if it succeeds the circuit closes again, otherwise it stays
+//! open for another while.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::node::PeerId;
+
+/// Thresholds at which the circuits of the peers open and are probed again.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Number of recent requests the failure rate of a peer is computed over.
+    pub window: usize,
+    /// Requests a peer must have been sent before its circuit can open.
+    pub min_requests: usize,
+    /// Rate of failed requests, between zero and one, at which the circuit of a peer opens.
+    pub failure_rate: f64,
+    /// Time a circuit stays open before a probe is let through, and a probe is given to
+    /// complete before another one is.
+    pub open_for: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            window: 20,
+            min_requests: 10,
+            failure_rate: 0.5,
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+enum Circuit {
+    /// Requests are forwarded to the peer, keeping track of how the recent ones went.
+    Closed { outcomes: VecDeque<bool> },
+    /// No requests are forwarded to the peer until the time.
+    Open { until: Instant },
+    /// A probe request was forwarded to the peer, at the time.
+    HalfOpen { probed: Instant },
+}
+
+impl Circuit {
+    fn closed(window: usize) -> Self {
+        Self::Closed {
+            outcomes: VecDeque::with_capacity(window),
+        }
+    }
+}
+
+/// Shared handle to the circuit breakers of the peers requests are forwarded to.
+#[derive(Clone)]
+pub(crate) struct CircuitBreakers {
+    config: CircuitBreakerConfig,
+    circuits: Arc<RwLock<HashMap<PeerId, Circuit>>>,
+}
+
+impl CircuitBreakers {
+    /// Number of peers tracked above which the healthy ones are forgotten.
+    const MAX_TRACKED: usize = 1024;
+
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            circuits: Arc::default(),
+        }
+    }
+
+    /// Records that a request forwarded to the peer was served.
+    pub fn record_success(&self, peer: &PeerId, now: Instant) {
+        self.record_at(peer, true, now);
+    }
+
+    /// Records that a request forwarded to the peer failed or timed out.
+    pub fn record_failure(&self, peer: &PeerId, now: Instant) {
+        self.record_at(peer, false, now);
+    }
+
+    fn record_at(&self, peer: &PeerId, succeeded: bool, now: Instant) {
+        let mut circuits = self.circuits.write();
+        if circuits.len() >= Self::MAX_TRACKED && !circuits.contains_key(peer) {
+            circuits.retain(|_, circuit| {
+                !matches!(circuit, Circuit::Closed { outcomes } if outcomes.iter().all(|ok| *ok))
+            });
+        }
+        let config = &self.config;
+        let circuit = circuits
+            .entry(peer.clone())
+            .or_insert_with(|| Circuit::closed(config.window));
+        match circuit {
+            Circuit::Closed { outcomes } => {
+                if outcomes.len() == config.window {
+                    outcomes.pop_front();
+                }
+                outcomes.push_back(succeeded);
+                let failed = outcomes.iter().filter(|ok| !**ok).count();
+                if outcomes.len() >= config.min_requests
+                    && failed as f64 >= outcomes.len() as f64 * config.failure_rate
+                {
+                    tracing::info!(
+                        %peer,
+                        failed,
+                        requests = outcomes.len(),
+                        "Peer failing most requests, opening its circuit"
+                    );
+                    *circuit = Circuit::Open {
+                        until: now + config.open_for,
+                    };
+                }
+            }
+            Circuit::HalfOpen { .. } if succeeded => {
+                tracing::info!(%peer, "Probe to peer succeeded, closing its circuit");
+                *circuit = Circuit::closed(config.window);
+            }
+            Circuit::HalfOpen { .. } => {
+                tracing::debug!(%peer, "Probe to peer failed, keeping its circuit open");
+                *circuit = Circuit::Open {
+                    until: now + config.open_for,
+                };
+            }
+            // outcomes of requests forwarded before the circuit opened
+            Circuit::Open { .. } => {}
+        }
+    }
+
+    /// Whether requests can be forwarded to the peer, either because its circuit is closed or
+    /// because it is due a probe.
+    pub fn allows(&self, peer: &PeerId, now: Instant) -> bool {
+        match self.circuits.read().get(peer) {
+            None | Some(Circuit::Closed { .. }) => true,
+            Some(Circuit::Open { until }) => *until <= now,
+            Some(Circuit::HalfOpen { probed }) => *probed + self.config.open_for <= now,
+        }
+    }
+
+    /// Records that a request is being forwarded to the peer, which is the probe if its
+    /// circuit is not closed.
+    pub fn forwarding(&self, peer: &PeerId, now: Instant) {
+        let mut circuits = self.circuits.write();
+        if let Some(circuit @ (Circuit::Open { .. } | Circuit::HalfOpen { .. })) =
+            circuits.get_mut(peer)
+        {
+            tracing::debug!(%peer, "Probing peer with an open circuit");
+            *circuit = Circuit::HalfOpen { probed: now };
+        }
+    }
+
+    pub fn readdress(&self, previous: &PeerId, moved: PeerId) {
+        let mut circuits = self.circuits.write();
+        if let Some(circuit) = circuits.remove(previous) {
+            circuits.insert(moved, circuit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failing_peers_are_cut_off_and_probed() {
+        let config = CircuitBreakerConfig::default();
+        let breakers = CircuitBreakers::new(config);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        // a few failures among successes don't open the circuit
+        for i in 0..config.window {
+            breakers.record_at(&peer, i % 3 != 0, now);
+        }
+        assert!(breakers.allows(&peer, now));
+
+        for _ in 0..config.window / 2 {
+            breakers.record_at(&peer, false, now);
+        }
+        assert!(!breakers.allows(&peer, now));
+        assert!(breakers.allows(&PeerId::random(), now));
+
+        // a single probe is let through once the circuit was open for a while
+        let later = now + config.open_for;
+        assert!(breakers.allows(&peer, later));
+        breakers.forwarding(&peer, later);
+        assert!(!breakers.allows(&peer, later));
+
+        // a failed probe keeps the circuit open, a successful one closes it
+        breakers.record_at(&peer, false, later);
+        assert!(!breakers.allows(&peer, later));
+        let later = later + config.open_for;
+        breakers.forwarding(&peer, later);
+        breakers.record_at(&peer, true, later);
+        assert!(breakers.allows(&peer, later));
+        breakers.record_at(&peer, false, later);
+        assert!(breakers.allows(&peer, later));
+    }
+}
@@ -0,0 +1,166 @@
+//! Estimation of the difference between the clock of this node and the ones of its neighbours.
+//!
+//! Right after connecting each end sends the other the time of its clock, which is echoed back
+//! along with the time of the clock of the receiver, as in NTP. Assuming both legs of the round
+//! trip take the same time, the remote clock read the echoed time half way through the round
+//! trip, which gives the skew between both clocks give or take half the round trip. Only
+//! answers to the last probe sent to the neighbour are taken into account, so a neighbour
+//! can't make up the time the probe was sent at.
+//!
+//! The skew of a neighbour widens the window within which the messages it sealed are accepted.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// Skew above which the clock of a neighbour is reported as off.
+const NOTABLE_SKEW: Duration = Duration::from_secs(5);
+
+/// Estimated skew between the clock of a neighbour and the one of this node.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// Time the clock of the neighbour is ahead of the one of this node, negative if behind.
+    pub offset_ms: i64,
+    /// Max error of the offset, half the round trip of the exchange it was estimated from.
+    pub uncertainty_ms: u64,
+}
+
+impl ClockSkew {
+    /// Window around the local time within which falls a time read from the clock of the
+    /// neighbour at the same instant.
+    pub fn tolerance(&self) -> Duration {
+        Duration::from_millis(
+            self.offset_ms
+                .unsigned_abs()
+                .saturating_add(self.uncertainty_ms),
+        )
+    }
+
+    /// Skew estimated from an exchange, unless the local clock went backwards during it or
+    /// the times can't be represented.
+    fn estimate(sent_at: u64, remote_at: u64, received_at: u64) -> Option<Self> {
+        let round_trip = received_at.checked_sub(sent_at)?;
+        let midpoint = i64::try_from(sent_at.checked_add(round_trip / 2)?).ok()?;
+        Some(ClockSkew {
+            offset_ms: i64::try_from(remote_at).ok()?.checked_sub(midpoint)?,
+            uncertainty_ms: round_trip.div_ceil(2),
+        })
+    }
+}
+
+/// Clock skews of the neighbours of this node, by their address.
+#[derive(Clone, Default)]
+pub(crate) struct ClockSkews {
+    skews: Arc<DashMap<SocketAddr, ClockSkew>>,
+    /// Local time the last probe was sent to each neighbour at, until answered.
+    probes: Arc<DashMap<SocketAddr, u64>>,
+}
+
+impl ClockSkews {
+    /// Records a probe sent to the neighbour at the given address at the given local time.
+    pub fn probed(&self, addr: SocketAddr, sent_at: u64) {
+        self.probes.insert(addr, sent_at);
+    }
+
+    /// Records the answer of the neighbour at the given address to the last probe sent to it:
+    /// the local time the probe was sent at, the time of the clock of the neighbour on
+    /// answering, and the local time the answer was received at.
+    pub fn record(&self, addr: SocketAddr, sent_at: u64, remote_at: u64, received_at: u64) {
+        if self
+            .probes
+            .remove_if(&addr, |_, probe| *probe == sent_at)
+            .is_none()
+        {
+            tracing::debug!(%addr, "Discarding clock reply to no outstanding probe");
+            return;
+        }
+        let Some(skew) = ClockSkew::estimate(sent_at, remote_at, received_at) else {
+            tracing::debug!(%addr, "Discarding meaningless clock sample");
+            return;
+        };
+        if skew.tolerance() > NOTABLE_SKEW {
+            tracing::warn!(%addr, ?skew, "Clock of the peer is off");
+        } else {
+            tracing::debug!(%addr, ?skew, "Estimated clock skew of the peer");
+        }
+        self.skews.insert(addr, skew);
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<ClockSkew> {
+        self.skews.get(addr).map(|skew| *skew)
+    }
+
+    pub fn forget(&self, addr: &SocketAddr) {
+        self.skews.remove(addr);
+        self.probes.remove(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skew_from_round_trip() {
+        let skews = ClockSkews::default();
+        let ahead: SocketAddr = "203.0.113.1:31337".parse().unwrap();
+        let behind: SocketAddr = "203.0.113.2:31337".parse().unwrap();
+
+        // answered half way through a 100ms round trip, by a clock one second ahead
+        skews.probed(ahead, 10_000);
+        skews.record(ahead, 10_000, 11_050, 10_100);
+        let skew = skews.get(&ahead).unwrap();
+        assert_eq!(skew.offset_ms, 1_000);
+        assert_eq!(skew.uncertainty_ms, 50);
+        assert_eq!(skew.tolerance(), Duration::from_millis(1_050));
+
+        skews.probed(behind, 10_000);
+        skews.record(behind, 10_000, 7_001, 10_002);
+        assert_eq!(skews.get(&behind).unwrap().offset_ms, -3_000);
+
+        // samples taken while the local clock went backwards are meaningless
+        skews.forget(&behind);
+        skews.probed(behind, 10_000);
+        skews.record(behind, 10_000, 7_001, 9_000);
+        assert_eq!(skews.get(&behind), None);
+    }
+
+    #[test]
+    fn only_replies_to_outstanding_probes() {
+        let skews = ClockSkews::default();
+        let peer: SocketAddr = "203.0.113.1:31337".parse().unwrap();
+
+        // never probed
+        skews.record(peer, 10_000, 11_050, 10_100);
+        assert_eq!(skews.get(&peer), None);
+
+        // not the time the probe was sent at, which would fake the round trip
+        skews.probed(peer, 10_000);
+        skews.record(peer, 9_000, 11_050, 10_100);
+        assert_eq!(skews.get(&peer), None);
+
+        // answered once only
+        skews.probed(peer, 10_000);
+        skews.record(peer, 10_000, 11_050, 10_100);
+        assert!(skews.get(&peer).is_some());
+        skews.forget(&peer);
+        skews.record(peer, 10_000, 11_050, 10_100);
+        assert_eq!(skews.get(&peer), None);
+    }
+
+    #[test]
+    fn unrepresentable_samples_are_dropped() {
+        let skews = ClockSkews::default();
+        let peer: SocketAddr = "203.0.113.1:31337".parse().unwrap();
+        skews.probed(peer, 10_000);
+        skews.record(peer, 10_000, u64::MAX, 10_100);
+        assert_eq!(skews.get(&peer), None);
+
+        let skew = ClockSkew {
+            offset_ms: i64::MIN,
+            uncertainty_ms: u64::MAX,
+        };
+        assert_eq!(skew.tolerance(), Duration::from_millis(u64::MAX));
+    }
+}
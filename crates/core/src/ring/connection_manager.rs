@@ -1,7 +1,22 @@
+use std::collections::BTreeSet;
+
 use parking_lot::Mutex;
 
+use crate::node::{GatewayService, GatewayServiceConfig, PeerFeatures};
 use crate::topology::{Limits, TopologyManager};
+use crate::transport::{LoadHints, QueueDepth};
+use crate::util::time_source::{SharedClock, TokioClock};
 
+use super::acceptance::{self, AcceptancePolicy, ConnectionCandidate, Verdict};
+use super::ban_list::BanList;
+use super::circuit_breaker::CircuitBreakers;
+use super::cooldown::ConnectionCooldowns;
+use super::journal::{EvictionReason, TopologyEventKind, TopologyJournal};
+use super::known_peers::KnownPeers;
+use super::liveness::NeighbourLiveness;
+use super::location_assignment::LocationAssigner;
+use super::reputation::PeerReputation;
+use super::standby::StandbyPool;
 use super::*;
 
 #[derive(Clone)]
@@ -15,10 +30,50 @@ pub(crate) struct ConnectionManager {
     /// Is important to keep track of this so no more connections are accepted prematurely.
     own_location: Arc<AtomicU64>,
     peer_key: Arc<Mutex<Option<PeerId>>>,
-    pub min_connections: usize,
-    pub max_connections: usize,
+    min_connections: Arc<AtomicUsize>,
+    max_connections: Arc<AtomicUsize>,
     pub rnd_if_htl_above: usize,
     pub pub_key: Arc<TransportPublicKey>,
+    /// Load hints exchanged with the connected peers in keep-alive messages.
+    pub load_hints: LoadHints,
+    /// Skews between the clocks of the connected peers and the one of this node.
+    pub clock_skews: ClockSkews,
+    /// Optional features of the connected peers, as they said in their greeting.
+    pub peer_features: PeerFeatures,
+    /// Reputation of the peers this node interacts with.
+    pub peer_reputation: PeerReputation,
+    /// Circuit breakers cutting off the peers which fail most of the requests forwarded to them.
+    pub circuit_breakers: CircuitBreakers,
+    /// Peers recently disconnected from, which are not accepted again until they cool down.
+    cooldowns: ConnectionCooldowns,
+    /// Peers connected to in this and previous sessions.
+    pub known_peers: KnownPeers,
+    /// Peers banned for abusing this node, which are never accepted while banned.
+    pub bans: BanList,
+    /// Pings in flight to the connected peers, to find out the ones which are no longer alive.
+    pub liveness: NeighbourLiveness,
+    /// Idle connections to peers which did not make the cut, promoted when a connection is needed.
+    pub standby: StandbyPool,
+    /// Changes in the connections of this node, for post-mortem analysis.
+    pub journal: TopologyJournal,
+    /// Peers which refused to take part in a join for being busy, and until when they are
+    /// not asked again.
+    busy_peers: Arc<RwLock<BTreeMap<PeerId, Instant>>>,
+    /// Neighbours which never cache contract states, so they are not counted among the holders
+    /// of the contracts close to them.
+    light_clients: Arc<RwLock<BTreeSet<PeerId>>>,
+    /// Gateways this node knows about, which are never evicted to make room for others.
+    known_gateways: Arc<BTreeSet<PeerId>>,
+    /// Connections evicted to make room for better placed ones, pending to be dropped.
+    evicted: Arc<Mutex<Vec<PeerId>>>,
+    /// Assigns locations to the peers joining the network through this node.
+    location_assigner: LocationAssigner,
+    /// Only set if this node is a gateway.
+    gateway: Option<GatewayService>,
+    /// Clock the backoffs, bans and cooldowns of the peers are measured with.
+    pub clock: SharedClock,
+    /// Decides which peers asking to connect are accepted.
+    acceptance: Arc<dyn AcceptancePolicy>,
 }
 
 #[cfg(test)]
@@ -29,6 +84,7 @@ impl ConnectionManager {
         let max_upstream_bandwidth = Ring::DEFAULT_MAX_UPSTREAM_BANDWIDTH;
         let max_downstream_bandwidth = Ring::DEFAULT_MAX_DOWNSTREAM_BANDWIDTH;
         let rnd_if_htl_above = Ring::DEFAULT_RAND_WALK_ABOVE_HTL;
+        let policing = crate::node::MessagePolicingConfig::default();
 
         Self::init(
             max_upstream_bandwidth,
@@ -36,14 +92,30 @@ impl ConnectionManager {
             min_connections,
             max_connections,
             rnd_if_htl_above,
+            crate::config::DEFAULT_REPUTATION_THRESHOLD,
+            crate::config::DEFAULT_CONNECTION_COOLDOWN,
+            NeighbourLiveness::new(
+                crate::config::DEFAULT_KEEPALIVE_INTERVAL,
+                crate::config::DEFAULT_KEEPALIVE_FAILURE_THRESHOLD,
+            ),
+            CircuitBreakers::new(super::CircuitBreakerConfig::default()),
+            KnownPeers::in_memory(),
+            BanList::in_memory(policing.ban_duration, policing.bans_to_permanent),
+            StandbyPool::new(0),
+            TopologyJournal::in_memory(),
             pub_key,
             None,
+            BTreeSet::new(),
+            LocationAssignment::default(),
+            Some(GatewayService::new(GatewayServiceConfig::default())),
+            Arc::new(acceptance::default_policy()),
+            Arc::new(TokioClock),
         )
     }
 }
 
 impl ConnectionManager {
-    pub fn new(config: &NodeConfig) -> Self {
+    pub fn new(config: &NodeConfig, clock: SharedClock) -> Self {
         let min_connections = if let Some(v) = config.min_number_conn {
             v
         } else {
@@ -74,14 +146,64 @@ impl ConnectionManager {
             Ring::DEFAULT_RAND_WALK_ABOVE_HTL
         };
 
+        let reputation_threshold = config
+            .reputation_threshold
+            .unwrap_or(crate::config::DEFAULT_REPUTATION_THRESHOLD);
+
+        let connection_cooldown = config
+            .connection_cooldown
+            .unwrap_or(crate::config::DEFAULT_CONNECTION_COOLDOWN);
+
+        let liveness = NeighbourLiveness::new(
+            config
+                .keepalive_interval
+                .unwrap_or(crate::config::DEFAULT_KEEPALIVE_INTERVAL),
+            config
+                .keepalive_failure_threshold
+                .unwrap_or(crate::config::DEFAULT_KEEPALIVE_FAILURE_THRESHOLD),
+        );
+
         Self::init(
             max_upstream_bandwidth,
             max_downstream_bandwidth,
             min_connections,
             max_connections,
             rnd_if_htl_above,
+            reputation_threshold,
+            connection_cooldown,
+            liveness,
+            CircuitBreakers::new(config.circuit_breakers),
+            KnownPeers::load(&config.config.db_dir(), clock.unix_time()),
+            BanList::load(
+                &config.config.db_dir(),
+                config.message_policing.ban_duration,
+                config.message_policing.bans_to_permanent,
+            ),
+            StandbyPool::new(
+                config
+                    .standby_connections
+                    .unwrap_or(crate::config::DEFAULT_STANDBY_CONNECTIONS),
+            ),
+            config
+                .topology_journal
+                .as_deref()
+                .map_or_else(TopologyJournal::in_memory, TopologyJournal::with_sink),
             config.key_pair.public().clone(),
             config.peer_id.clone(),
+            config
+                .gateways
+                .iter()
+                .map(|gw| gw.peer_id.clone())
+                .collect(),
+            config.location_assignment,
+            config
+                .is_gateway
+                .then(|| GatewayService::new(config.gateway_service)),
+            config
+                .acceptance_policy
+                .clone()
+                .unwrap_or_else(|| Arc::new(acceptance::default_policy())),
+            clock,
         )
     }
 
@@ -91,8 +213,21 @@ impl ConnectionManager {
         min_connections: usize,
         max_connections: usize,
         rnd_if_htl_above: usize,
+        reputation_threshold: f64,
+        connection_cooldown: Duration,
+        liveness: NeighbourLiveness,
+        circuit_breakers: CircuitBreakers,
+        known_peers: KnownPeers,
+        bans: BanList,
+        standby: StandbyPool,
+        journal: TopologyJournal,
         pub_key: TransportPublicKey,
         peerid: Option<PeerId>,
+        known_gateways: BTreeSet<PeerId>,
+        location_assignment: LocationAssignment,
+        gateway: Option<GatewayService>,
+        acceptance: Arc<dyn AcceptancePolicy>,
+        clock: SharedClock,
     ) -> Self {
         let own_location = if let Some(peer_key) = &peerid {
             // if the peer id is set, then the location must be set, since it is a gateway
@@ -118,13 +253,121 @@ impl ConnectionManager {
             topology_manager,
             own_location: own_location.into(),
             peer_key: Arc::new(Mutex::new(peerid)),
-            min_connections,
-            max_connections,
+            min_connections: Arc::new(AtomicUsize::new(min_connections)),
+            max_connections: Arc::new(AtomicUsize::new(max_connections)),
             rnd_if_htl_above,
             pub_key: Arc::new(pub_key),
+            load_hints: LoadHints::default(),
+            clock_skews: ClockSkews::default(),
+            peer_features: PeerFeatures::default(),
+            peer_reputation: PeerReputation::new(reputation_threshold),
+            circuit_breakers,
+            cooldowns: ConnectionCooldowns::new(connection_cooldown),
+            known_peers,
+            bans,
+            liveness,
+            standby,
+            journal,
+            busy_peers: Arc::new(RwLock::new(BTreeMap::new())),
+            light_clients: Arc::new(RwLock::new(BTreeSet::new())),
+            known_gateways: Arc::new(known_gateways),
+            evicted: Arc::new(Mutex::new(Vec::new())),
+            location_assigner: LocationAssigner::new(location_assignment),
+            gateway,
+            acceptance,
+            clock,
         }
     }
 
+    pub fn min_connections(&self) -> usize {
+        self.min_connections
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Changes the number of connections the node aims to keep open while it runs. Excess
+    /// connections are not dropped right away, but no new ones are accepted until below it.
+    pub fn set_connection_limits(&self, min_connections: usize, max_connections: usize) {
+        self.min_connections
+            .store(min_connections, std::sync::atomic::Ordering::Relaxed);
+        self.max_connections
+            .store(max_connections, std::sync::atomic::Ordering::Relaxed);
+        self.topology_manager
+            .write()
+            .set_connection_limits(min_connections, max_connections);
+    }
+
+    /// How long a peer which refused a join for being busy is not asked to join again.
+    const BUSY_PEER_BACKOFF: Duration = Duration::from_secs(60);
+    /// Max time a peer can ask this node to refrain from joining through it.
+    const MAX_PEER_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+    /// Whether this node is out of resources to take part in new joins, regardless of the
+    /// location of the joiner.
+    ///
+    /// The budget is exhausted if as many joins as the max number of connections are
+    /// already in progress, the bandwidth usage is above the desired limits or the node
+    /// is saturated with open transactions.
+    pub fn is_busy(&self) -> bool {
+        let reserved = self
+            .reserved_connections
+            .load(std::sync::atomic::Ordering::SeqCst);
+        if reserved >= self.max_connections() {
+            tracing::debug!(reserved, "Too many joins in progress");
+            return true;
+        }
+        if self.load_hints.local().queue_depth == QueueDepth::Saturated {
+            tracing::debug!("Saturated with open transactions");
+            return true;
+        }
+        if self
+            .topology_manager
+            .write()
+            .is_over_budget(self.clock.now())
+        {
+            tracing::debug!("Bandwidth usage over budget");
+            return true;
+        }
+        false
+    }
+
+    /// Avoids asking a peer which replied it is busy to join again for a while.
+    pub fn backoff_busy_peer(&self, peer: &PeerId) {
+        tracing::debug!(%peer, "Peer is busy, backing off");
+        self.backoff_peer(peer, Self::BUSY_PEER_BACKOFF);
+    }
+
+    /// Refrain from joining through the peer for the given time, as requested by it, up to
+    /// a max backoff.
+    pub fn backoff_peer(&self, peer: &PeerId, duration: Duration) {
+        let Some(until) = self
+            .clock
+            .now()
+            .checked_add(duration.min(Self::MAX_PEER_BACKOFF))
+        else {
+            return;
+        };
+        self.busy_peers.write().insert(peer.clone(), until);
+    }
+
+    /// Whether the peer recently refused a join for being busy.
+    pub fn is_backing_off(&self, peer: &PeerId) -> bool {
+        let now = self.clock.now();
+        let mut busy_peers = self.busy_peers.write();
+        busy_peers.retain(|_, until| *until > now);
+        busy_peers.contains_key(peer)
+    }
+
+    /// Current reputation score of the peer; zero is neutral and negative scores denote
+    /// misbehaviour.
+    pub fn reputation(&self, peer: &PeerId) -> f64 {
+        self.peer_reputation.score(peer, self.clock.now())
+    }
+
     /// Whether a node should accept a new node connection or not based
     /// on the relative location and other conditions.
     ///
@@ -132,13 +375,44 @@ impl ConnectionManager {
     /// Will panic if the node checking for this condition has no location assigned.
     pub fn should_accept(&self, location: Location, peer_id: &PeerId) -> bool {
         tracing::debug!("Checking if should accept connection");
+        if self.bans.is_banned(&peer_id.pub_key, self.clock.now()) {
+            tracing::debug!(%peer_id, "Rejecting connection from banned peer");
+            return false;
+        }
         let open = self
             .open_connections
             .load(std::sync::atomic::Ordering::SeqCst);
-        let total_conn = self
-            .reserved_connections
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
-            + open;
+        let my_location = self
+            .own_location()
+            .location
+            .unwrap_or_else(Location::random);
+        let neighbours: Vec<_> = self
+            .connections_by_location
+            .read()
+            .keys()
+            .copied()
+            .collect();
+        let verdict = self.acceptance.evaluate(&ConnectionCandidate {
+            peer: peer_id,
+            location,
+            own_location: my_location,
+            neighbours: &neighbours,
+            connections: open
+                + self
+                    .reserved_connections
+                    .load(std::sync::atomic::Ordering::SeqCst),
+            min_connections: self.min_connections(),
+            max_connections: self.max_connections(),
+            reputation: self.peer_reputation.score(peer_id, self.clock.now()),
+            reputation_threshold: self.peer_reputation.threshold(),
+            is_gateway: self.gateway.is_some(),
+        });
+        if verdict == Verdict::Reject {
+            tracing::debug!(%peer_id, "Connection rejected by the acceptance policy");
+            return false;
+        }
+        self.reserved_connections
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
         if open == 0 {
             // if this is the first connection, then accept it
@@ -148,7 +422,9 @@ impl ConnectionManager {
             return true;
         }
 
-        if self.location_for_peer.read().get(peer_id).is_some() {
+        if self.location_for_peer.read().get(peer_id).is_some()
+            || self.standby.contains(peer_id, self.clock.now())
+        {
             // avoid connecting more than once to the same peer
             self.reserved_connections
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
@@ -156,27 +432,41 @@ impl ConnectionManager {
             return false;
         }
 
-        let my_location = self
-            .own_location()
-            .location
-            .unwrap_or_else(Location::random);
-        let accepted = if location == my_location
-            || self.connections_by_location.read().contains_key(&location)
-        {
-            false
-        } else if total_conn < self.min_connections {
-            true
-        } else if total_conn >= self.max_connections {
+        if self.cooldowns.is_cooling_down(peer_id, self.clock.now()) {
+            // unless this node is isolated, give the peer time before connecting again
+            self.reserved_connections
+                .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            tracing::debug!(%peer_id, "Rejecting connection from recently dropped peer");
+            return false;
+        }
+
+        // peers at the location of this node or of a neighbour add nothing to the coverage
+        let placeable = location != my_location && !neighbours.contains(&location);
+        let accepted = if !placeable {
             false
         } else {
-            self.topology_manager
-                .write()
-                .evaluate_new_connection(location, Instant::now())
-                .unwrap_or(true)
+            match verdict {
+                Verdict::Accept => true,
+                Verdict::Reject => false,
+                Verdict::Replace => self.evict_for(location),
+                Verdict::Abstain => self
+                    .topology_manager
+                    .write()
+                    .evaluate_new_connection(location, Instant::now())
+                    .unwrap_or(true),
+            }
         };
         if !accepted {
             self.reserved_connections
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            if placeable
+                && self
+                    .standby
+                    .reserve(my_location, peer_id, location, self.clock.now())
+            {
+                tracing::debug!(%peer_id, "Accepted connection on standby");
+                return true;
+            }
         } else {
             tracing::debug!(%peer_id, "Accepted connection, reserving spot");
             self.location_for_peer
@@ -186,9 +476,110 @@ impl ConnectionManager {
         accepted
     }
 
+    /// Promotes the open standby connection best placed to serve as a connection around the
+    /// given location, if any, returning it so it is added as an active connection.
+    pub fn promote_standby(&self, ideal_location: Location) -> Option<(PeerId, Location)> {
+        let own = self.own_location().location?;
+        self.standby.take_closest(own, ideal_location)
+    }
+
+    /// Makes room for a connection at the given location when at capacity, evicting the
+    /// connection which contributes the least to the coverage of the ring, if the new one
+    /// would contribute more.
+    fn evict_for(&self, location: Location) -> bool {
+        let connections = self.connections_by_location.read();
+        if connections.len() <= self.min_connections() {
+            return false;
+        }
+        let mut pending = self.evicted.lock();
+        let Some((evicted, overlap)) = least_coverage(&connections, |peer| {
+            !self.known_gateways.contains(peer) && !pending.contains(peer)
+        }) else {
+            return false;
+        };
+        let coverage = connections
+            .values()
+            .flatten()
+            .filter(|conn| conn.location.peer != evicted.peer)
+            .filter_map(|conn| conn.location.location)
+            .map(|loc| loc.distance(location))
+            .min();
+        std::mem::drop(connections);
+        if coverage.is_some_and(|coverage| coverage <= overlap) {
+            return false;
+        }
+        tracing::debug!(
+            peer = %evicted.peer,
+            %location,
+            "At capacity, evicting connection to make room for a better placed one"
+        );
+        self.journal.record(
+            TopologyEventKind::evicted(&evicted.peer, EvictionReason::Coverage),
+            self.clock.unix_time(),
+        );
+        pending.push(evicted.peer);
+        true
+    }
+
+    /// Connections which must be dropped, either because they were evicted to make room for
+    /// better placed ones or because this node is above the max number of connections.
+    ///
+    /// Gateways are never evicted, nor will this node be left below the min number of
+    /// connections.
+    pub fn take_evictions(&self) -> Vec<PeerId> {
+        let mut evictions = std::mem::take(&mut *self.evicted.lock());
+        let mut connections = self.connections_by_location.read().clone();
+        for peer in &evictions {
+            connections.retain(|_, conns| {
+                conns.retain(|conn| &conn.location.peer != peer);
+                !conns.is_empty()
+            });
+        }
+        let open = connections.values().map(Vec::len).sum::<usize>();
+        let excess = open.saturating_sub(self.max_connections().max(self.min_connections()));
+        for _ in 0..excess {
+            let Some((evicted, _)) =
+                least_coverage(&connections, |peer| !self.known_gateways.contains(peer))
+            else {
+                break;
+            };
+            connections.retain(|_, conns| {
+                conns.retain(|conn| conn.location.peer != evicted.peer);
+                !conns.is_empty()
+            });
+            tracing::debug!(peer = %evicted.peer, "Above max connections, evicting connection");
+            evictions.push(evicted.peer);
+        }
+        evictions
+    }
+
+    /// The gateway service, if this node is a gateway.
+    pub fn gateway(&self) -> Option<&GatewayService> {
+        self.gateway.as_ref()
+    }
+
+    /// Location assigned to a peer joining the network through this node.
+    pub fn assign_location(&self, joiner: &SocketAddr) -> Location {
+        let covered = self
+            .own_location()
+            .location
+            .into_iter()
+            .chain(self.connections_by_location.read().keys().copied())
+            .collect::<Vec<_>>();
+        self.location_assigner
+            .assign(joiner, covered, self.clock.now())
+    }
+
     /// Update this node location.
     pub fn update_location(&self, loc: Option<Location>) {
         if let Some(loc) = loc {
+            let previous = self.location();
+            if previous != Some(loc) {
+                self.journal.record(
+                    TopologyEventKind::location_changed(None, previous, loc),
+                    self.clock.unix_time(),
+                );
+            }
             self.own_location.store(
                 u64::from_le_bytes(loc.0.to_le_bytes()),
                 std::sync::atomic::Ordering::Release,
@@ -207,18 +598,22 @@ impl ConnectionManager {
     ///
     /// Will panic if the node has no peer id assigned yet.
     pub fn own_location(&self) -> PeerKeyLocation {
+        let location = self.location();
+        let peer = self.get_peer_key().expect("peer key not set");
+        PeerKeyLocation { peer, location }
+    }
+
+    fn location(&self) -> Option<Location> {
         let location = f64::from_le_bytes(
             self.own_location
                 .load(std::sync::atomic::Ordering::Acquire)
                 .to_le_bytes(),
         );
-        let location = if (location - -1f64).abs() < f64::EPSILON {
+        if (location - -1f64).abs() < f64::EPSILON {
             None
         } else {
             Some(Location(location))
-        };
-        let peer = self.get_peer_key().expect("peer key not set");
-        PeerKeyLocation { peer, location }
+        }
     }
 
     pub fn get_peer_key(&self) -> Option<PeerId> {
@@ -236,8 +631,18 @@ impl ConnectionManager {
         }
     }
 
+    /// Updates the address of this peer, returning the previous peer id, if any.
+    pub fn update_peer_addr(&self, addr: SocketAddr) -> Option<PeerId> {
+        self.peer_key
+            .lock()
+            .replace(PeerId::new(addr, (*self.pub_key).clone()))
+    }
+
+    /// Removes an open connection, the peer is not accepted again until it cools down.
     pub fn prune_alive_connection(&self, peer: &PeerId) -> Option<Location> {
-        self.prune_connection(peer, true)
+        let loc = self.prune_connection(peer, true)?;
+        self.cooldowns.start(peer, self.clock.now());
+        Some(loc)
     }
 
     pub fn prune_in_transit_connection(&self, peer: &PeerId) -> Option<Location> {
@@ -246,6 +651,10 @@ impl ConnectionManager {
 
     pub fn add_connection(&self, loc: Location, peer: PeerId, was_reserved: bool) {
         debug_assert!(self.get_peer_key().expect("should be set") != peer);
+        if self.standby.establish(&peer) {
+            tracing::debug!(%peer, "Standby connection established");
+            return;
+        }
         if was_reserved {
             let old = self
                 .reserved_connections
@@ -269,13 +678,97 @@ impl ConnectionManager {
             },
             open_at: Instant::now(),
         });
+        self.location_assigner.joined(&peer.addr);
+        if let Some(gateway) = &self.gateway {
+            gateway.joined();
+        }
         self.location_for_peer.write().insert(peer.clone(), loc);
         std::mem::drop(cbl);
+        self.journal.record(
+            TopologyEventKind::connected(&peer, loc),
+            self.clock.unix_time(),
+        );
+        self.known_peers.record(peer, loc, self.clock.unix_time());
+    }
+
+    /// Replaces the connection with a peer by the connection with the same peer at its new
+    /// address, keeping its location.
+    pub fn readdress(&self, previous: &PeerId, moved: PeerId) -> bool {
+        let mut location_for_peer = self.location_for_peer.write();
+        let Some(loc) = location_for_peer
+            .get_key_value(previous)
+            .filter(|(known, _)| known.pub_key == moved.pub_key)
+            .map(|(_, loc)| *loc)
+        else {
+            return false;
+        };
+        location_for_peer.remove(previous);
+        location_for_peer.insert(moved.clone(), loc);
+        std::mem::drop(location_for_peer);
+
+        if let Some(conns) = self.connections_by_location.write().get_mut(&loc) {
+            for conn in conns.iter_mut().filter(|c| &c.location.peer == previous) {
+                conn.location.peer = moved.clone();
+            }
+        }
+        self.load_hints.forget(&previous.addr);
+        self.clock_skews.forget(&previous.addr);
+        self.peer_features.readdress(&previous.addr, moved.addr);
+        self.peer_reputation.readdress(previous, moved.clone());
+        self.circuit_breakers.readdress(previous, moved.clone());
+        self.liveness.readdress(previous, moved.clone());
+        let mut light_clients = self.light_clients.write();
+        if light_clients.remove(previous) {
+            light_clients.insert(moved.clone());
+        }
+        std::mem::drop(light_clients);
+        self.cooldowns.readdress(previous, moved);
+        true
+    }
+
+    /// Moves the connection with a peer to the new location of the peer.
+    pub fn relocate_peer(&self, peer: &PeerId, location: Location) -> bool {
+        let mut location_for_peer = self.location_for_peer.write();
+        let Some(previous) = location_for_peer.insert(peer.clone(), location) else {
+            location_for_peer.remove(peer);
+            return false;
+        };
+        std::mem::drop(location_for_peer);
+
+        let mut cbl = self.connections_by_location.write();
+        let Some(conns) = cbl.get_mut(&previous) else {
+            return true;
+        };
+        let Some(pos) = conns.iter().position(|c| &c.location.peer == peer) else {
+            return true;
+        };
+        let mut conn = conns.swap_remove(pos);
+        if conns.is_empty() {
+            cbl.remove(&previous);
+        }
+        conn.location.location = Some(location);
+        cbl.entry(location).or_default().push(conn);
+        std::mem::drop(cbl);
+        self.journal.record(
+            TopologyEventKind::location_changed(Some(peer), Some(previous), location),
+            self.clock.unix_time(),
+        );
+        self.known_peers
+            .record(peer.clone(), location, self.clock.unix_time());
+        true
     }
 
     fn prune_connection(&self, peer: &PeerId, is_alive: bool) -> Option<Location> {
         let connection_type = if is_alive { "active" } else { "in transit" };
         tracing::debug!(%peer, "Pruning {} connection", connection_type);
+        if self.standby.remove(peer) {
+            return None;
+        }
+        self.load_hints.forget(&peer.addr);
+        self.clock_skews.forget(&peer.addr);
+        self.peer_features.forget(&peer.addr);
+        self.liveness.forget(peer);
+        self.light_clients.write().remove(peer);
 
         let Some(loc) = self.location_for_peer.write().remove(peer) else {
             if is_alive {
@@ -298,6 +791,10 @@ impl ConnectionManager {
         if is_alive {
             self.open_connections
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            self.journal.record(
+                TopologyEventKind::dropped(peer, loc),
+                self.clock.unix_time(),
+            );
         } else {
             self.reserved_connections
                 .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
@@ -316,10 +813,13 @@ impl ConnectionManager {
     }
 
     /// Get a random peer from the known ring connections.
+    ///
+    /// Peers with an open circuit are skipped like when routing, unless due a probe.
     pub fn random_peer<F>(&self, filter_fn: F) -> Option<PeerKeyLocation>
     where
         F: Fn(&PeerId) -> bool,
     {
+        let now = self.clock.now();
         let peers = &*self.location_for_peer.read();
         let amount = peers.len();
         if amount == 0 {
@@ -333,10 +833,11 @@ impl ConnectionManager {
             }
             let selected = rng.gen_range(0..amount);
             let (peer, loc) = peers.iter().nth(selected).expect("infallible");
-            if !filter_fn(peer) {
+            if !filter_fn(peer) || !self.circuit_breakers.allows(peer, now) {
                 attempts += 1;
                 continue;
             } else {
+                self.circuit_breakers.forwarding(peer, now);
                 return Some(PeerKeyLocation {
                     peer: peer.clone(),
                     location: Some(*loc),
@@ -345,7 +846,32 @@ impl ConnectionManager {
         }
     }
 
+    /// Picks a random peer with a probability inversely proportional to its distance to the
+    /// target location, following the ideal small-world distribution around it, instead of
+    /// uniformly like [`Self::random_peer`].
+    pub fn random_peer_towards<F>(&self, target: Location, filter_fn: F) -> Option<PeerKeyLocation>
+    where
+        F: Fn(&PeerId) -> bool,
+    {
+        let now = self.clock.now();
+        let peers = &*self.location_for_peer.read();
+        let candidates: Vec<_> = peers
+            .iter()
+            .filter(|(peer, _)| filter_fn(peer) && self.circuit_breakers.allows(peer, now))
+            .collect();
+        let (peer, loc) = sample_towards(target, &candidates, &mut rand::thread_rng())?;
+        self.circuit_breakers.forwarding(peer, now);
+        Some(PeerKeyLocation {
+            peer: peer.clone(),
+            location: Some(*loc),
+        })
+    }
+
     /// Route an op to the most optimal target.
+    ///
+    /// Peers which reported being overloaded, or with a low reputation, are only considered
+    /// if there are no alternatives. Peers with an open circuit are never routed to, other
+    /// than to probe them once they are due.
     pub fn routing(
         &self,
         target: Location,
@@ -354,6 +880,7 @@ impl ConnectionManager {
         router: &Router,
     ) -> Option<PeerKeyLocation> {
         use rand::seq::SliceRandom;
+        let now = self.clock.now();
         let connections = self.connections_by_location.read();
         let peers = connections.values().filter_map(|conns| {
             let conn = conns.choose(&mut rand::thread_rng())?;
@@ -362,17 +889,261 @@ impl ConnectionManager {
                     return None;
                 }
             }
-            (!skip_list.has_element(&conn.location.peer)).then_some(&conn.location)
+            (!skip_list.has_element(&conn.location.peer)
+                && self.circuit_breakers.allows(&conn.location.peer, now))
+            .then_some(&conn.location)
+        });
+        let (avoided, available): (Vec<_>, Vec<_>) = peers.partition(|peer| {
+            self.load_hints.is_overloaded(&peer.peer.addr)
+                || self.peer_reputation.is_demoted(&peer.peer, now)
         });
-        router.select_peer(peers, target).cloned()
+        let selected = if available.is_empty() {
+            router.select_peer(avoided, target).cloned()
+        } else {
+            router.select_peer(available, target).cloned()
+        }?;
+        self.circuit_breakers.forwarding(&selected.peer, now);
+        Some(selected)
     }
 
     pub fn num_connections(&self) -> usize {
         self.connections_by_location.read().len()
     }
 
-    pub(super) fn connected_peers(&self) -> impl Iterator<Item = PeerId> {
+    /// Locations of the peers this node is connected to.
+    pub fn neighbour_locations(&self) -> Vec<Location> {
+        self.connections_by_location
+            .read()
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Locations of the peers this node is connected to which cache contract states, leaving
+    /// out the light clients.
+    pub fn caching_neighbour_locations(&self) -> Vec<Location> {
+        let light_clients = self.light_clients.read();
+        self.connections_by_location
+            .read()
+            .iter()
+            .filter(|(_, conns)| {
+                conns
+                    .iter()
+                    .any(|conn| !light_clients.contains(&conn.location.peer))
+            })
+            .map(|(location, _)| *location)
+            .collect()
+    }
+
+    /// Records that the neighbour is a light client, which never caches contract states.
+    pub fn light_client_neighbour(&self, peer: PeerId) {
+        self.light_clients.write().insert(peer);
+    }
+
+    /// Neighbours which are light clients.
+    pub fn light_client_neighbours(&self) -> Vec<PeerId> {
+        self.light_clients.read().iter().cloned().collect()
+    }
+
+    /// Location of the given neighbour, if connected to it.
+    pub fn location_of(&self, peer: &PeerId) -> Option<Location> {
+        self.location_for_peer.read().get(peer).copied()
+    }
+
+    pub fn connected_peers(&self) -> impl Iterator<Item = PeerId> {
         let read = self.location_for_peer.read();
         read.keys().cloned().collect::<Vec<_>>().into_iter()
     }
 }
+
+/// Among the evictable connections, the one contributing the least to the coverage of the ring,
+/// which is the one closest to another neighbour (the one overlapping the most with it),
+/// together with the distance to that neighbour.
+fn least_coverage(
+    connections: &BTreeMap<Location, Vec<Connection>>,
+    evictable: impl Fn(&PeerId) -> bool,
+) -> Option<(PeerKeyLocation, Distance)> {
+    if connections.values().map(Vec::len).sum::<usize>() < 2 {
+        return None;
+    }
+    let locations = connections.keys().copied().collect::<Vec<_>>();
+    connections
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, (loc, conns))| {
+            let overlap = if conns.len() > 1 {
+                Distance::new(0.0)
+            } else {
+                // locations are sorted, so the closest neighbour is adjacent in the ring
+                let prev = locations[(idx + locations.len() - 1) % locations.len()];
+                let next = locations[(idx + 1) % locations.len()];
+                loc.distance(prev).min(loc.distance(next))
+            };
+            conns.iter().map(move |conn| (conn, overlap))
+        })
+        .filter(|(conn, _)| evictable(&conn.location.peer))
+        .min_by_key(|(_, overlap)| *overlap)
+        .map(|(conn, overlap)| (conn.location.clone(), overlap))
+}
+
+/// Distances to the target location are clamped to at least this when weighting peers, so
+/// a peer right at the target doesn't take all the probability.
+const MIN_SAMPLING_DISTANCE: f64 = 1e-3;
+
+fn sample_towards<'a, R: Rng>(
+    target: Location,
+    candidates: &[(&'a PeerId, &'a Location)],
+    rng: &mut R,
+) -> Option<(&'a PeerId, &'a Location)> {
+    use rand::distributions::{Distribution, WeightedIndex};
+    let weights = candidates
+        .iter()
+        .map(|(_, loc)| 1.0 / loc.distance(target).as_f64().max(MIN_SAMPLING_DISTANCE));
+    let index = WeightedIndex::new(weights).ok()?;
+    Some(candidates[index.sample(rng)])
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::transport::TransportKeypair;
+    use crate::util::time_source::VirtualClock;
+
+    fn connection(location: f64) -> (Location, Vec<Connection>) {
+        let location = Location::new(location);
+        (location, vec![Connection::new(PeerId::random(), location)])
+    }
+
+    #[test]
+    fn evict_most_overlapping_connection() {
+        let connections: BTreeMap<_, _> = [0.1, 0.3, 0.32, 0.6, 0.85]
+            .into_iter()
+            .map(connection)
+            .collect();
+        let peer_at = |loc: f64| connections[&Location::new(loc)][0].location.peer.clone();
+
+        let (evicted, overlap) = least_coverage(&connections, |_| true).unwrap();
+        assert!(evicted.peer == peer_at(0.3) || evicted.peer == peer_at(0.32));
+        assert!((overlap.as_f64() - 0.02).abs() < 1e-9);
+
+        // gateways are never evicted
+        let gateways = [peer_at(0.3), peer_at(0.32)];
+        let (evicted, overlap) =
+            least_coverage(&connections, |peer| !gateways.contains(peer)).unwrap();
+        assert_eq!(evicted.peer, peer_at(0.1));
+        assert!((overlap.as_f64() - 0.2).abs() < 1e-9);
+
+        // a single connection covers the whole ring
+        let single: BTreeMap<_, _> = [connection(0.5)].into_iter().collect();
+        assert!(least_coverage(&single, |_| true).is_none());
+    }
+
+    #[tokio::test]
+    async fn regular_peers_assign_locations_to_joiners() -> anyhow::Result<()> {
+        let mut config =
+            crate::test_utils::node_config("regular_peers_assign_locations_to_joiners").await?;
+        config.with_location_assignment(LocationAssignment::SparsestArc);
+        let manager = ConnectionManager::new(&config, Arc::new(TokioClock));
+        assert!(manager.gateway().is_none());
+
+        manager.update_location(Some(Location::new(0.5)));
+        manager.add_connection(Location::new(0.6), PeerId::random(), false);
+        // joiners are placed in the widest arc left uncovered, from 0.6 to 0.5
+        let location = manager.assign_location(&PeerId::random().addr).as_f64();
+        assert!(!(0.3..0.8).contains(&location), "{location}");
+        Ok(())
+    }
+
+    #[test]
+    fn light_clients_are_not_caching_neighbours() {
+        let manager = ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        let (caching, light) = (PeerId::random(), PeerId::random());
+        manager.add_connection(Location::new(0.2), caching.clone(), false);
+        manager.add_connection(Location::new(0.4), light.clone(), false);
+        manager.light_client_neighbour(light.clone());
+        assert_eq!(
+            manager.caching_neighbour_locations(),
+            vec![Location::new(0.2)]
+        );
+
+        // once disconnected the peer is forgotten, even if it connects again as a caching peer
+        manager.prune_alive_connection(&light);
+        manager.add_connection(Location::new(0.4), light, false);
+        assert_eq!(
+            manager.caching_neighbour_locations(),
+            vec![Location::new(0.2), Location::new(0.4)]
+        );
+    }
+
+    #[test]
+    fn peer_backoff_is_bounded() {
+        let manager = ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        let peer = PeerId::random();
+        manager.backoff_peer(&peer, Duration::MAX);
+        assert!(manager.is_backing_off(&peer));
+        let until = manager.busy_peers.read()[&peer];
+        assert!(until <= Instant::now() + ConnectionManager::MAX_PEER_BACKOFF);
+    }
+
+    #[test]
+    fn backoffs_follow_the_clock() {
+        let clock = VirtualClock::new();
+        let mut manager =
+            ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        manager.clock = Arc::new(clock.clone());
+        let peer = PeerId::random();
+        manager.backoff_busy_peer(&peer);
+        assert!(manager.is_backing_off(&peer));
+        clock.advance(ConnectionManager::BUSY_PEER_BACKOFF);
+        assert!(!manager.is_backing_off(&peer));
+    }
+
+    #[test]
+    fn random_peers_skip_open_circuits() {
+        let manager = ConnectionManager::default_with_key(TransportKeypair::new().public().clone());
+        let (failing, healthy) = (PeerId::random(), PeerId::random());
+        manager.add_connection(Location::new(0.2), failing.clone(), false);
+        manager.add_connection(Location::new(0.4), healthy.clone(), false);
+        let now = manager.clock.now();
+        for _ in 0..crate::ring::CircuitBreakerConfig::default().window {
+            manager.circuit_breakers.record_failure(&failing, now);
+        }
+
+        for _ in 0..20 {
+            let picked = manager.random_peer(|_| true).unwrap();
+            assert_eq!(picked.peer, healthy);
+            let picked = manager
+                .random_peer_towards(Location::new(0.2), |_| true)
+                .unwrap();
+            assert_eq!(picked.peer, healthy);
+        }
+        assert!(manager.random_peer(|peer| peer != &healthy).is_none());
+    }
+
+    #[test]
+    fn sample_closer_peers_more_often() {
+        let peers: Vec<_> = [0.1, 0.2, 0.4]
+            .into_iter()
+            .map(|loc| (PeerId::random(), Location::new(loc)))
+            .collect();
+        let candidates: Vec<_> = peers.iter().map(|(peer, loc)| (peer, loc)).collect();
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(7);
+        let target = Location::new(0.15);
+
+        let mut picks = [0usize; 3];
+        for _ in 0..3000 {
+            let (peer, _) = sample_towards(target, &candidates, &mut rng).unwrap();
+            picks[peers.iter().position(|(p, _)| p == peer).unwrap()] += 1;
+        }
+        // weights are 1/0.05, 1/0.05 and 1/0.25, so 5:5:1
+        assert!(
+            picks[0] > picks[2] * 3 && picks[1] > picks[2] * 3,
+            "{picks:?}"
+        );
+        assert!(picks[2] > 0);
+
+        assert!(sample_towards(target, &[], &mut rng).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Cooldown of the peers this node recently disconnected from.
+//!
+//! Dropping a connection and accepting the same peer right away again can get two nodes into a
+//! drop/reconnect loop, e.g. when the topology manager of one of them keeps shedding the
+//! connection the other keeps asking for. Peers are refused for a while after being dropped to
+//! dampen that churn.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+use crate::node::PeerId;
+
+/// Shared handle to the peers which are cooling down after a disconnection.
+#[derive(Clone)]
+pub(crate) struct ConnectionCooldowns {
+    interval: Duration,
+    until: Arc<RwLock<HashMap<PeerId, Instant>>>,
+}
+
+impl ConnectionCooldowns {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            until: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Starts the cooldown of a peer which was just disconnected.
+    pub fn start(&self, peer: &PeerId, now: Instant) {
+        if self.interval.is_zero() {
+            return;
+        }
+        tracing::debug!(%peer, cooldown = ?self.interval, "Peer disconnected, cooling down");
+        let mut until = self.until.write();
+        until.retain(|_, until| *until > now);
+        until.insert(peer.clone(), now + self.interval);
+    }
+
+    /// Whether the peer was disconnected recently and should not be connected to again yet.
+    pub fn is_cooling_down(&self, peer: &PeerId, now: Instant) -> bool {
+        self.until
+            .read()
+            .get(peer)
+            .is_some_and(|until| *until > now)
+    }
+
+    pub fn readdress(&self, previous: &PeerId, moved: PeerId) {
+        let mut until = self.until.write();
+        if let Some(cooldown) = until.remove(previous) {
+            until.insert(moved, cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_peers_cool_down() {
+        let cooldowns = ConnectionCooldowns::new(Duration::from_secs(60));
+        let peer = PeerId::random();
+        let now = Instant::now();
+        assert!(!cooldowns.is_cooling_down(&peer, now));
+
+        cooldowns.start(&peer, now);
+        assert!(cooldowns.is_cooling_down(&peer, now + Duration::from_secs(59)));
+        assert!(!cooldowns.is_cooling_down(&PeerId::random(), now));
+        assert!(!cooldowns.is_cooling_down(&peer, now + Duration::from_secs(60)));
+
+        // expired cooldowns are forgotten once other peers are dropped
+        cooldowns.start(&PeerId::random(), now + Duration::from_secs(61));
+        assert_eq!(cooldowns.until.read().len(), 1);
+
+        let disabled = ConnectionCooldowns::new(Duration::ZERO);
+        disabled.start(&peer, now);
+        assert!(!disabled.is_cooling_down(&peer, now));
+    }
+}
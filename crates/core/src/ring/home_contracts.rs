@@ -0,0 +1,138 @@
+//! Cached contracts this node is "home" for.
+//!
+//! Requests for a contract are routed towards its location, so the peers closest to it are
+//! the ones expected to hold its latest state. Out of the contracts this node caches it is
+//! home for those no more than `k - 1` of its neighbours are closer to. Which ones those are
+//! changes along with the topology: as neighbours come and go, or this node relocates, it
+//! becomes home for some contracts, whose state it should make sure is up to date, and stops
+//! being home for others.
+
+use std::{collections::HashSet, sync::Arc};
+
+use freenet_stdlib::prelude::ContractKey;
+use parking_lot::RwLock;
+use tokio::sync::broadcast;
+
+use super::Location;
+
+/// Number of peers closest to a contract considered home for it.
+pub(super) const HOME_REPLICAS: usize = 3;
+
+/// Contracts this node became, or stopped being, home for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct HomeChanges {
+    pub gained: Vec<ContractKey>,
+    pub lost: Vec<ContractKey>,
+}
+
+impl HomeChanges {
+    pub fn is_empty(&self) -> bool {
+        self.gained.is_empty() && self.lost.is_empty()
+    }
+}
+
+/// Shared handle to the contracts this node is home for.
+#[derive(Clone)]
+pub(crate) struct HomeContracts {
+    replicas: usize,
+    home: Arc<RwLock<HashSet<ContractKey>>>,
+    changes: broadcast::Sender<HomeChanges>,
+}
+
+impl HomeContracts {
+    pub fn new(replicas: usize) -> Self {
+        Self {
+            replicas,
+            home: Arc::default(),
+            changes: broadcast::channel(16).0,
+        }
+    }
+
+    pub fn contains(&self, key: &ContractKey) -> bool {
+        self.home.read().contains(key)
+    }
+
+    /// Notified of the changes in the contracts this node is home for.
+    pub fn subscribe(&self) -> broadcast::Receiver<HomeChanges> {
+        self.changes.subscribe()
+    }
+
+    /// Re-evaluates which of the cached contracts this node is home for given its current
+    /// location and the ones of its neighbours, notifying any changes.
+    pub fn refresh(
+        &self,
+        own: Location,
+        neighbours: &[Location],
+        cached: impl IntoIterator<Item = ContractKey>,
+    ) -> HomeChanges {
+        let current: HashSet<_> = cached
+            .into_iter()
+            .filter(|key| is_among_closest(own, neighbours, Location::from(key), self.replicas))
+            .collect();
+        let mut home = self.home.write();
+        let changes = HomeChanges {
+            gained: current.difference(&home).copied().collect(),
+            lost: home.difference(&current).copied().collect(),
+        };
+        *home = current;
+        drop(home);
+        if !changes.is_empty() {
+            // nobody may be listening
+            let _ = self.changes.send(changes.clone());
+        }
+        changes
+    }
+}
+
+/// Whether the peer at `own` is among the `k` closest to `target`, counting its neighbours.
+pub(super) fn is_among_closest(
+    own: Location,
+    neighbours: &[Location],
+    target: Location,
+    k: usize,
+) -> bool {
+    let distance = own.distance(target);
+    neighbours
+        .iter()
+        .filter(|neighbour| neighbour.distance(target) < distance)
+        .count()
+        < k
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    #[test]
+    fn home_follows_topology() {
+        let home = HomeContracts::new(2);
+        let mut changes = home.subscribe();
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let at = |offset: f64| Location::new_rounded(Location::from(&key).as_f64() + offset);
+        let own = at(0.1);
+
+        // only one neighbour is closer to the contract
+        let neighbours = [at(0.01), at(-0.2), at(0.3)];
+        let gained = home.refresh(own, &neighbours, [key]);
+        assert_eq!(gained.gained, [key]);
+        assert!(home.home.read().contains(&key));
+        assert_eq!(changes.try_recv().unwrap(), gained);
+
+        // no changes are not notified
+        assert!(home.refresh(own, &neighbours, [key]).is_empty());
+        assert!(changes.try_recv().is_err());
+
+        // a second neighbour closer to the contract joins
+        let neighbours = [at(0.01), at(-0.05), at(0.3)];
+        let lost = home.refresh(own, &neighbours, [key]);
+        assert_eq!(lost.lost, [key]);
+        assert!(!home.home.read().contains(&key));
+        assert_eq!(changes.try_recv().unwrap(), lost);
+
+        // contracts no longer cached are no longer home
+        home.refresh(own, &[], [key]);
+        assert_eq!(home.refresh(own, &[], []).lost, [key]);
+    }
+}
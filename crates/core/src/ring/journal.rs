@@ -0,0 +1,261 @@
+//! Journal of the changes in the topology around this node.
+//!
+//! Connections come and go for many reasons, and by the time someone looks into why a node
+//! ended up badly connected the topology it had is long gone. The connection manager records
+//! every change in a journal instead: the latest events are kept in memory, to be queried by
+//! operators, and optionally appended to a file as JSON lines for post-mortem analysis.
+//!
+//! Events are recorded while the connection manager holds its locks, so the file is written
+//! by a thread of its own.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::Location;
+use crate::node::PeerId;
+
+/// Number of events kept in memory, the oldest are discarded first.
+const JOURNAL_CAPACITY: usize = 1024;
+/// Number of events waiting to be written to the journal file, newer ones are not written.
+const SINK_CAPACITY: usize = JOURNAL_CAPACITY * 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TopologyEvent {
+    /// Position of the event in the journal, increasing by one with every event.
+    pub seq: u64,
+    /// Time of the event, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: TopologyEventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TopologyEventKind {
+    Connected {
+        peer: String,
+        location: f64,
+    },
+    Dropped {
+        peer: String,
+        location: f64,
+    },
+    /// The location of a neighbour, or of this node if no peer is given, changed.
+    LocationChanged {
+        peer: Option<String>,
+        previous: Option<f64>,
+        location: f64,
+    },
+    /// This node decided to drop the connection with a neighbour.
+    Evicted {
+        peer: String,
+        reason: EvictionReason,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// Made room for a connection better placed to cover the ring.
+    Coverage,
+    /// The topology manager found the connection superfluous.
+    Topology,
+    LowReputation,
+    /// The neighbour stopped answering pings.
+    Unresponsive,
+}
+
+impl TopologyEventKind {
+    pub(crate) fn connected(peer: &PeerId, location: Location) -> Self {
+        Self::Connected {
+            peer: peer.to_string(),
+            location: location.as_f64(),
+        }
+    }
+
+    pub(crate) fn dropped(peer: &PeerId, location: Location) -> Self {
+        Self::Dropped {
+            peer: peer.to_string(),
+            location: location.as_f64(),
+        }
+    }
+
+    pub(crate) fn location_changed(
+        peer: Option<&PeerId>,
+        previous: Option<Location>,
+        location: Location,
+    ) -> Self {
+        Self::LocationChanged {
+            peer: peer.map(|peer| peer.to_string()),
+            previous: previous.map(|loc| loc.as_f64()),
+            location: location.as_f64(),
+        }
+    }
+
+    pub(crate) fn evicted(peer: &PeerId, reason: EvictionReason) -> Self {
+        Self::Evicted {
+            peer: peer.to_string(),
+            reason,
+        }
+    }
+}
+
+/// Shared handle to the topology journal of this node.
+#[derive(Clone)]
+pub(crate) struct TopologyJournal {
+    events: Arc<Mutex<VecDeque<TopologyEvent>>>,
+    next_seq: Arc<AtomicU64>,
+    /// Events waiting to be appended to the journal file.
+    sink: Option<mpsc::SyncSender<TopologyEvent>>,
+}
+
+impl TopologyJournal {
+    pub fn in_memory() -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(JOURNAL_CAPACITY))),
+            next_seq: Arc::default(),
+            sink: None,
+        }
+    }
+
+    /// A journal also appending every event to the given file.
+    pub fn with_sink(path: &Path) -> Self {
+        let sink = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(spawn_writer)
+            .map_err(|error| {
+                tracing::warn!(%error, path = %path.display(), "Failed to open the topology journal file");
+            })
+            .ok();
+        Self {
+            sink,
+            ..Self::in_memory()
+        }
+    }
+
+    /// Records the event as happened at the given time since the unix epoch.
+    pub fn record(&self, kind: TopologyEventKind, now: Duration) {
+        let mut events = self.events.lock();
+        let event = TopologyEvent {
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            timestamp: now.as_millis() as u64,
+            kind,
+        };
+        if let Some(sink) = &self.sink {
+            if let Err(mpsc::TrySendError::Full(event)) = sink.try_send(event.clone()) {
+                tracing::warn!(
+                    seq = event.seq,
+                    "Topology journal file lagging behind, event not written"
+                );
+            }
+        }
+        if events.len() == JOURNAL_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// The events kept in memory from the given sequence number on, from the oldest to the newest.
+    pub fn since(&self, seq: u64) -> Vec<TopologyEvent> {
+        self.events
+            .lock()
+            .iter()
+            .filter(|event| event.seq >= seq)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Spawns the thread appending the events sent to the returned channel to the file.
+fn spawn_writer(file: File) -> std::io::Result<mpsc::SyncSender<TopologyEvent>> {
+    fn write_event(sink: &mut BufWriter<File>, event: &TopologyEvent) -> std::io::Result<()> {
+        serde_json::to_writer(&mut *sink, event)?;
+        sink.write_all(b"\n")
+    }
+
+    let (sender, events) = mpsc::sync_channel::<TopologyEvent>(SINK_CAPACITY);
+    std::thread::Builder::new()
+        .name("topology-journal".into())
+        .spawn(move || {
+            let mut sink = BufWriter::new(file);
+            while let Ok(event) = events.recv() {
+                // the events already waiting are written before flushing
+                let written = std::iter::once(event)
+                    .chain(events.try_iter())
+                    .try_for_each(|event| write_event(&mut sink, &event))
+                    .and_then(|_| sink.flush());
+                if let Err(error) = written {
+                    tracing::warn!(%error, "Failed to write to the topology journal file");
+                }
+            }
+        })?;
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_journal_with_sink() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal");
+        let journal = TopologyJournal::with_sink(&path);
+        let peer = PeerId::random();
+        let now = Duration::from_secs(1_700_000_000);
+        for i in 0..JOURNAL_CAPACITY + 10 {
+            journal.record(
+                TopologyEventKind::connected(&peer, Location::new(i as f64 / 2000.0)),
+                now,
+            );
+        }
+        journal.record(
+            TopologyEventKind::evicted(&peer, EvictionReason::Coverage),
+            now + Duration::from_millis(1),
+        );
+
+        let events = journal.since(0);
+        assert_eq!(events.len(), JOURNAL_CAPACITY);
+        assert_eq!(events[0].seq, 11);
+        assert_eq!(journal.since(1034).len(), 1);
+        assert_eq!(
+            events.last().unwrap().kind,
+            TopologyEventKind::evicted(&peer, EvictionReason::Coverage)
+        );
+        assert_eq!(events.last().unwrap().timestamp, 1_700_000_000_001);
+
+        // the file is written in the background
+        let mut lines: Vec<TopologyEvent> = vec![];
+        for _ in 0..100 {
+            // a line may be half written
+            let written: Result<Vec<_>, _> = std::fs::read_to_string(&path)?
+                .lines()
+                .map(serde_json::from_str)
+                .collect();
+            if let Ok(written) = written {
+                lines = written;
+                if lines.len() == JOURNAL_CAPACITY + 11 {
+                    break;
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert_eq!(lines.len(), JOURNAL_CAPACITY + 11);
+        assert_eq!(lines.last(), events.last());
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+//! Peers this node was connected to in previous sessions.
+//!
+//! The connections of a node are lost on restart, and rebuilding a neighbourhood through the
+//! gateways from scratch takes a while. The peers connected to are persisted to disk along
+//! with their locations and when they were last seen, as well as the location of this node,
+//! so on restart the node can dial its previous neighbours directly and take its previous
+//! place in the ring, only joining through the gateways if none of them can be reached.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::Location;
+use crate::node::PeerId;
+
+const KNOWN_PEERS_FILE: &str = "known_peers";
+
+/// Max number of peers remembered, the least recently seen are forgotten first.
+const MAX_KNOWN_PEERS: usize = 256;
+
+/// Peers not seen for longer are forgotten, they most likely moved or left the network.
+const MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct KnownPeer {
+    pub peer: PeerId,
+    pub location: Location,
+    /// Last time the peer was connected to, in seconds since the unix epoch.
+    pub last_seen: u64,
+}
+
+/// What is persisted to disk.
+#[derive(Default, Serialize, Deserialize)]
+struct Persisted {
+    location: Option<Location>,
+    peers: Vec<KnownPeer>,
+}
+
+/// Shared handle to the peers this node has been connected to, which survive restarts.
+#[derive(Clone)]
+pub(crate) struct KnownPeers {
+    peers: Arc<Mutex<HashMap<PeerId, KnownPeer>>>,
+    /// Last known location of this node.
+    location: Arc<Mutex<Option<Location>>>,
+    /// Not persisted if unset.
+    path: Option<Arc<PathBuf>>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl KnownPeers {
+    /// Loads the peers persisted in the given directory, if any, as of the given time since
+    /// the unix epoch.
+    pub fn load(dir: &Path, now: Duration) -> Self {
+        let path = dir.join(KNOWN_PEERS_FILE);
+        let persisted = match std::fs::read(&path) {
+            Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|error| {
+                tracing::warn!(%error, "Failed to load known peers, discarding");
+                Persisted::default()
+            }),
+            Err(_) => Persisted::default(),
+        };
+        let this = Self {
+            peers: Arc::new(Mutex::new(
+                persisted
+                    .peers
+                    .into_iter()
+                    .map(|k| (k.peer.clone(), k))
+                    .collect(),
+            )),
+            location: Arc::new(Mutex::new(persisted.location)),
+            path: Some(Arc::new(path)),
+            dirty: Arc::default(),
+        };
+        this.expire(now.as_secs());
+        this
+    }
+
+    /// A store which is not persisted.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            peers: Arc::default(),
+            location: Arc::default(),
+            path: None,
+            dirty: Arc::default(),
+        }
+    }
+
+    /// Records that this node is connected to the peer at the given location.
+    pub fn record(&self, peer: PeerId, location: Location, now: Duration) {
+        self.peers.lock().insert(
+            peer.clone(),
+            KnownPeer {
+                peer,
+                location,
+                last_seen: now.as_secs(),
+            },
+        );
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Records the current location of this node.
+    pub fn record_location(&self, location: Location) {
+        let mut current = self.location.lock();
+        if *current != Some(location) {
+            *current = Some(location);
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// Location of this node when it was last persisted.
+    pub fn location(&self) -> Option<Location> {
+        *self.location.lock()
+    }
+
+    /// The most recently seen peers, up to `limit`, from the most to the least recent.
+    pub fn recent(&self, limit: usize) -> Vec<KnownPeer> {
+        let mut known: Vec<_> = self.peers.lock().values().cloned().collect();
+        known.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        known.truncate(limit);
+        known
+    }
+
+    /// Forgets the peers not seen for too long and, above the max number of remembered
+    /// peers, the least recently seen.
+    fn expire(&self, now: u64) {
+        let mut peers = self.peers.lock();
+        let cutoff = now.saturating_sub(MAX_AGE.as_secs());
+        peers.retain(|_, known| known.last_seen >= cutoff);
+        if peers.len() > MAX_KNOWN_PEERS {
+            let mut last_seen: Vec<_> = peers.values().map(|known| known.last_seen).collect();
+            last_seen.sort_unstable_by(|a, b| b.cmp(a));
+            let oldest_kept = last_seen[MAX_KNOWN_PEERS - 1];
+            peers.retain(|_, known| known.last_seen >= oldest_kept);
+        }
+    }
+
+    /// Forgets the stale peers and writes the rest to disk if they changed since the last time,
+    /// off the async runtime.
+    pub async fn persist(&self, now: Duration) -> std::io::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.expire(now.as_secs());
+        let persisted = Persisted {
+            location: self.location(),
+            peers: self.peers.lock().values().cloned().collect(),
+        };
+        let bytes = bincode::serialize(&persisted).map_err(std::io::Error::other)?;
+        tokio::task::spawn_blocking(move || {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, bytes)?;
+            std::fs::rename(tmp, path.as_path())
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::time_source::{Clock, TokioClock};
+
+    #[tokio::test]
+    async fn persisted_across_restarts() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let now = TokioClock.unix_time();
+        let known = KnownPeers::load(dir.path(), now);
+        let peers: Vec<_> = (0..3).map(|_| PeerId::random()).collect();
+        for (i, peer) in peers.iter().enumerate() {
+            known.record(
+                peer.clone(),
+                Location::new(0.1 * i as f64),
+                now - Duration::from_secs(10 - i as u64),
+            );
+        }
+        known.record_location(Location::new(0.25));
+        known.persist(now).await?;
+
+        let known = KnownPeers::load(dir.path(), now);
+        assert_eq!(known.location(), Some(Location::new(0.25)));
+        let recent: Vec<_> = known.recent(2).into_iter().map(|k| k.peer).collect();
+        assert_eq!(recent, [peers[2].clone(), peers[1].clone()]);
+        assert_eq!(known.recent(10)[2].location, Location::new(0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn forget_stale_peers() {
+        let known = KnownPeers::in_memory();
+        let now = TokioClock.unix_time();
+        let stale = PeerId::random();
+        known.record(
+            stale.clone(),
+            Location::new(0.5),
+            now - MAX_AGE - Duration::from_secs(1),
+        );
+        for i in 0..MAX_KNOWN_PEERS as u64 + 10 {
+            known.record(
+                PeerId::random(),
+                Location::new(0.5),
+                now - Duration::from_secs(i),
+            );
+        }
+        known.expire(now.as_secs());
+        let recent = known.recent(usize::MAX);
+        assert_eq!(recent.len(), MAX_KNOWN_PEERS);
+        assert!(recent.iter().all(|k| k.peer != stale));
+        assert_eq!(recent[0].last_seen, now.as_secs());
+    }
+}
@@ -0,0 +1,145 @@
+//! Liveness of the connections with the neighbours of this node.
+//!
+//! A connection with a peer which went away silently would otherwise linger until some
+//! operation tries to send through it and fails midway. Neighbours are pinged periodically
+//! instead, and the ones failing to answer a number of pings in a row are considered dead,
+//! so their connections are dropped and replaced before any operation is routed through them.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+
+use crate::node::PeerId;
+
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    /// Nonce of the ping awaiting an answer, if any.
+    pending: Option<u64>,
+    /// Number of pings in a row which were not answered in time.
+    failures: u32,
+}
+
+/// Peers to ping and peers considered dead after a round of pings.
+#[derive(Debug, Default)]
+pub(crate) struct PingRound {
+    pub ping: Vec<(PeerId, u64)>,
+    pub dead: Vec<PeerId>,
+}
+
+/// Shared handle to the liveness of the connected peers.
+#[derive(Clone)]
+pub(crate) struct NeighbourLiveness {
+    /// Time between pings, a zero interval disables them.
+    pub interval: Duration,
+    /// Number of pings in a row a peer has to miss to be considered dead.
+    failure_threshold: u32,
+    probes: Arc<Mutex<HashMap<PeerId, Probe>>>,
+}
+
+impl NeighbourLiveness {
+    pub fn new(interval: Duration, failure_threshold: u32) -> Self {
+        Self {
+            interval,
+            failure_threshold,
+            probes: Arc::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// Starts a new round of pings to the connected peers, returning the pings to send and
+    /// the peers which did not answer enough of the previous ones in a row.
+    ///
+    /// Peers which are no longer connected are forgotten.
+    pub fn next_round(&self, connected: impl IntoIterator<Item = PeerId>) -> PingRound {
+        let mut probes = self.probes.lock();
+        let mut tracked = std::mem::take(&mut *probes);
+        let mut round = PingRound::default();
+        for peer in connected {
+            let mut probe = tracked.remove(&peer).unwrap_or(Probe {
+                pending: None,
+                failures: 0,
+            });
+            if probe.pending.is_some() {
+                probe.failures += 1;
+            }
+            if probe.failures >= self.failure_threshold {
+                tracing::debug!(%peer, failures = probe.failures, "Peer failed to answer pings");
+                round.dead.push(peer);
+                continue;
+            }
+            let nonce = rand::random();
+            probe.pending = Some(nonce);
+            round.ping.push((peer.clone(), nonce));
+            probes.insert(peer, probe);
+        }
+        round
+    }
+
+    /// Records the answer of a peer to a ping, returns false if it was not expected.
+    pub fn pong(&self, peer: &PeerId, nonce: u64) -> bool {
+        let mut probes = self.probes.lock();
+        match probes.get_mut(peer) {
+            Some(probe) if probe.pending == Some(nonce) => {
+                probe.pending = None;
+                probe.failures = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn forget(&self, peer: &PeerId) {
+        self.probes.lock().remove(peer);
+    }
+
+    pub fn readdress(&self, previous: &PeerId, moved: PeerId) {
+        let mut probes = self.probes.lock();
+        if let Some(probe) = probes.remove(previous) {
+            probes.insert(moved, probe);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unresponsive_peers_are_dead() {
+        let liveness = NeighbourLiveness::new(Duration::from_secs(10), 2);
+        let alive = PeerId::random();
+        let silent = PeerId::random();
+        let connected = || [alive.clone(), silent.clone()];
+
+        let answer = |round: &PingRound| {
+            for (peer, nonce) in &round.ping {
+                if peer == &alive {
+                    assert!(liveness.pong(peer, *nonce));
+                }
+            }
+        };
+
+        let round = liveness.next_round(connected());
+        assert_eq!(round.ping.len(), 2);
+        assert!(round.dead.is_empty());
+        answer(&round);
+        // answers to unknown pings are ignored
+        assert!(!liveness.pong(&alive, 0));
+
+        let round = liveness.next_round(connected());
+        assert!(round.dead.is_empty());
+        answer(&round);
+
+        let round = liveness.next_round(connected());
+        assert_eq!(round.dead, [silent.clone()]);
+        assert_eq!(round.ping.len(), 1);
+        assert_eq!(round.ping[0].0, alive);
+
+        // disconnected peers are forgotten
+        liveness.next_round([]);
+        assert!(liveness.probes.lock().is_empty());
+    }
+}
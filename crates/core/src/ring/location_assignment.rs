@@ -0,0 +1,136 @@
+//! Assignment of ring locations to the peers joining the network through this node.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::ring::Location;
+
+/// How long a location handed to a joiner is reserved while the join completes.
+const PENDING_ASSIGNMENT_TTL: Duration = Duration::from_secs(60);
+
+/// Strategy used to assign locations to the peers joining through this node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LocationAssignment {
+    /// Derived from the address of the joiner.
+    #[default]
+    FromAddress,
+    /// Within the widest arc of the ring left uncovered by this node and its neighbours.
+    SparsestArc,
+}
+
+#[derive(Clone)]
+pub(crate) struct LocationAssigner {
+    strategy: LocationAssignment,
+    /// Locations handed to joiners which have not completed their join yet, so every step of
+    /// the join agrees on the location and concurrent joiners are spread apart.
+    pending: Arc<Mutex<HashMap<SocketAddr, (Location, Instant)>>>,
+}
+
+impl LocationAssigner {
+    pub fn new(strategy: LocationAssignment) -> Self {
+        Self {
+            strategy,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Location for the given joiner, considering the locations already covered around this
+    /// node.
+    pub fn assign(
+        &self,
+        joiner: &SocketAddr,
+        covered: impl IntoIterator<Item = Location>,
+        now: Instant,
+    ) -> Location {
+        match self.strategy {
+            LocationAssignment::FromAddress => Location::from_address(joiner),
+            LocationAssignment::SparsestArc => {
+                let mut pending = self.pending.lock();
+                pending.retain(|_, (_, assigned_at)| {
+                    now.duration_since(*assigned_at) < PENDING_ASSIGNMENT_TTL
+                });
+                if let Some((location, _)) = pending.get(joiner) {
+                    return *location;
+                }
+                let covered = covered
+                    .into_iter()
+                    .chain(pending.values().map(|(loc, _)| *loc));
+                let location = within_sparsest_arc(covered, rand::random());
+                pending.insert(*joiner, (location, now));
+                location
+            }
+        }
+    }
+
+    /// Forgets the location reserved for a joiner, once it is connected.
+    pub fn joined(&self, joiner: &SocketAddr) {
+        self.pending.lock().remove(joiner);
+    }
+}
+
+/// A location within the middle half of the widest arc between the covered locations,
+/// `jitter` (in `[0, 1)`) choosing where in it.
+fn within_sparsest_arc(covered: impl IntoIterator<Item = Location>, jitter: f64) -> Location {
+    let mut covered: Vec<f64> = covered.into_iter().map(|loc| loc.as_f64()).collect();
+    if covered.is_empty() {
+        return Location::random();
+    }
+    covered.sort_by(f64::total_cmp);
+    let wrap_around = (covered[covered.len() - 1], covered[0] + 1.0);
+    let (start, end) = covered
+        .windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .chain(std::iter::once(wrap_around))
+        .max_by(|(a0, a1), (b0, b1)| (a1 - a0).total_cmp(&(b1 - b0)))
+        .expect("at least the wrap-around arc");
+    Location::new_rounded(start + (end - start) * (0.25 + 0.5 * jitter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joiners_fill_the_sparsest_arcs() {
+        let covered = [0.1, 0.15, 0.2, 0.9].map(Location::new);
+        // the widest arc is between 0.2 and 0.9
+        let loc = within_sparsest_arc(covered, 0.5).as_f64();
+        assert!((loc - 0.55).abs() < 1e-9, "{loc}");
+        // arcs wrapping around the ring origin are considered too
+        let covered = [0.3, 0.5, 0.7].map(Location::new);
+        let loc = within_sparsest_arc(covered, 0.5).as_f64();
+        assert!(
+            (loc - 0.0).abs() < 1e-9 || (loc - 1.0).abs() < 1e-9,
+            "{loc}"
+        );
+
+        let assigner = LocationAssigner::new(LocationAssignment::SparsestArc);
+        let first: SocketAddr = ([10, 0, 0, 1], 1000).into();
+        let second: SocketAddr = ([10, 0, 0, 2], 1000).into();
+        let covered = [0.0, 0.5].map(Location::new);
+        let now = Instant::now();
+        let first_loc = assigner.assign(&first, covered, now);
+        // the same joiner is always given the same location while joining
+        assert_eq!(assigner.assign(&first, covered, now), first_loc);
+        // while concurrent joiners are placed in the other half of the ring
+        let second_loc = assigner.assign(&second, covered, now);
+        assert_ne!(
+            first_loc.as_f64() < 0.5,
+            second_loc.as_f64() < 0.5,
+            "{first_loc} {second_loc}"
+        );
+        // locations not claimed in time are forgotten
+        let later = now + PENDING_ASSIGNMENT_TTL;
+        assigner.assign(&second, covered, later);
+        assert!(assigner.pending.lock().get(&first).is_none());
+        assigner.joined(&second);
+        assert!(assigner.pending.lock().get(&second).is_none());
+    }
+}
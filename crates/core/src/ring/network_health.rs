@@ -0,0 +1,121 @@
+//! Detection of this node being cut off the network.
+//!
+//! Once the node has joined the network it is left alone to keep its connections healthy.
+//! If all of them drop anyway, e.g. because the host lost connectivity for a while, the node
+//! would sit idle without neighbours. Having had no live neighbour for long enough, the node
+//! is considered disconnected so it joins the network again through the gateways.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Connectivity of a node with the rest of the network.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum NetworkStatus {
+    /// Joining the network for the first time.
+    Connecting,
+    Connected,
+    /// All connections were lost, joining the network again.
+    Disconnected,
+}
+
+/// Changes in the connectivity of a node the event loop has to act upon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum HealthChange {
+    /// Joined the network for the first time.
+    Joined,
+    Disconnected,
+    /// Joined the network again after being disconnected for the given time.
+    Reconnected {
+        offline_for: Duration,
+    },
+}
+
+pub(super) struct PartitionDetector {
+    /// Time without live neighbours after which the node is considered disconnected.
+    grace_period: Duration,
+    status: NetworkStatus,
+    /// Since when the node has had no live neighbours, if that is the case.
+    isolated_since: Option<Instant>,
+}
+
+impl PartitionDetector {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            status: NetworkStatus::Connecting,
+            isolated_since: None,
+        }
+    }
+
+    /// Updates the status with the number of live neighbours at the given time, returning
+    /// the change in connectivity, if any.
+    pub fn observe(&mut self, open_connections: usize, now: Instant) -> Option<HealthChange> {
+        if open_connections > 0 {
+            let isolated_since = self.isolated_since.take();
+            return match self.status {
+                NetworkStatus::Connected => None,
+                NetworkStatus::Connecting => {
+                    self.status = NetworkStatus::Connected;
+                    Some(HealthChange::Joined)
+                }
+                NetworkStatus::Disconnected => {
+                    self.status = NetworkStatus::Connected;
+                    Some(HealthChange::Reconnected {
+                        offline_for: isolated_since
+                            .map(|since| now.saturating_duration_since(since))
+                            .unwrap_or_default(),
+                    })
+                }
+            };
+        }
+        if self.status != NetworkStatus::Connected {
+            // joining is already in progress
+            return None;
+        }
+        let isolated_since = *self.isolated_since.get_or_insert(now);
+        if now.saturating_duration_since(isolated_since) < self.grace_period {
+            return None;
+        }
+        self.status = NetworkStatus::Disconnected;
+        Some(HealthChange::Disconnected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnected_after_grace_period() {
+        let grace = Duration::from_secs(30);
+        let mut detector = PartitionDetector::new(grace);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // not having joined yet is not a disconnection
+        assert_eq!(detector.observe(0, at(100)), None);
+        assert_eq!(detector.observe(2, at(101)), Some(HealthChange::Joined));
+        assert_eq!(detector.observe(3, at(102)), None);
+
+        // short drops of all connections are tolerated
+        assert_eq!(detector.observe(0, at(110)), None);
+        assert_eq!(detector.observe(1, at(120)), None);
+        assert_eq!(detector.observe(0, at(130)), None);
+        assert_eq!(detector.observe(0, at(159)), None);
+        assert_eq!(
+            detector.observe(0, at(160)),
+            Some(HealthChange::Disconnected)
+        );
+        assert_eq!(detector.status, NetworkStatus::Disconnected);
+        assert_eq!(detector.observe(0, at(200)), None);
+
+        assert_eq!(
+            detector.observe(1, at(205)),
+            Some(HealthChange::Reconnected {
+                offline_for: Duration::from_secs(75)
+            })
+        );
+        assert_eq!(detector.status, NetworkStatus::Connected);
+    }
+}
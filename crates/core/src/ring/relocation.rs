@@ -0,0 +1,92 @@
+//! Detection of peers placed in an over-crowded arc of the ring.
+//!
+//! The arc a peer is responsible for spans from its closest neighbour on one side to its
+//! closest neighbour on the other. When that arc is much narrower than the widest arc left
+//! between neighbours, the peer is better off moving into the latter, where it spreads the
+//! load of the contracts located there instead of duplicating the work of its neighbours.
+
+use std::time::Duration;
+
+use super::Location;
+
+/// Min time between relocations of a peer, so it settles before being re-evaluated.
+pub(super) const MIN_RELOCATION_INTERVAL: Duration = Duration::from_secs(60 * 30);
+
+/// Times the widest arc between neighbours has to be wider than the arc of this peer
+/// for it to be considered over-crowded.
+const CROWDING_FACTOR: f64 = 8.0;
+
+/// Min number of neighbour locations known for the arcs to be meaningful.
+const MIN_KNOWN_LOCATIONS: usize = 4;
+
+/// A location to move to if the peer at `own` is in an over-crowded arc, within the middle
+/// half of the widest arc between its neighbours, `jitter` (in `[0, 1)`) choosing where in it.
+pub(super) fn relocation_target(
+    own: Location,
+    neighbours: impl IntoIterator<Item = Location>,
+    jitter: f64,
+) -> Option<Location> {
+    let own = own.as_f64();
+    let mut neighbours: Vec<f64> = neighbours
+        .into_iter()
+        .map(|loc| loc.as_f64())
+        .filter(|loc| *loc != own)
+        .collect();
+    neighbours.sort_by(f64::total_cmp);
+    neighbours.dedup();
+    if neighbours.len() < MIN_KNOWN_LOCATIONS {
+        return None;
+    }
+
+    let wrap_around = (neighbours[neighbours.len() - 1], neighbours[0] + 1.0);
+    let arcs = || {
+        neighbours
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain(std::iter::once(wrap_around))
+    };
+    let contains_own = |&(start, end): &(f64, f64)| {
+        (start..end).contains(&own) || (start..end).contains(&(own + 1.0))
+    };
+    let (own_start, own_end) = arcs().find(contains_own)?;
+    let (start, end) = arcs()
+        .filter(|arc| !contains_own(arc))
+        .max_by(|(a0, a1), (b0, b1)| (a1 - a0).total_cmp(&(b1 - b0)))?;
+    if (own_end - own_start) * CROWDING_FACTOR > end - start {
+        return None;
+    }
+    Some(Location::new_rounded(
+        start + (end - start) * (0.25 + 0.5 * jitter),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocate_out_of_crowded_arcs() {
+        let neighbours = |locs: &[f64]| locs.iter().copied().map(Location::new).collect::<Vec<_>>();
+
+        // the arc between 0.1 and 0.11 is far narrower than the one between 0.12 and 0.9
+        let crowded = neighbours(&[0.1, 0.11, 0.12, 0.9]);
+        let target = relocation_target(Location::new(0.105), crowded.clone(), 0.5).unwrap();
+        assert!((target.as_f64() - 0.51).abs() < 1e-9, "{target}");
+
+        // peers already within the widest arc stay where they are
+        assert!(relocation_target(Location::new(0.5), crowded, 0.5).is_none());
+
+        // as do peers in evenly spread neighbourhoods
+        let even = neighbours(&[0.0, 0.25, 0.5, 0.75]);
+        assert!(relocation_target(Location::new(0.1), even, 0.5).is_none());
+
+        // arcs wrapping around the ring origin are considered too
+        let wrapping = neighbours(&[0.95, 0.96, 0.1, 0.7]);
+        let target = relocation_target(Location::new(0.955), wrapping, 0.5).unwrap();
+        assert!((target.as_f64() - 0.4).abs() < 1e-9, "{target}");
+
+        // too few neighbours to tell
+        let few = neighbours(&[0.1, 0.11, 0.9]);
+        assert!(relocation_target(Location::new(0.105), few, 0.5).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! Reputation of the peers this node interacts with, aggregated from the outcome of those
+//! interactions.
+//!
+//! Every peer starts with a neutral score, which misbehaviour lowers and well served requests
+//! raise. Scores decay back towards neutral over time, so peers are judged on their recent
+//! behaviour and misbehaving peers can eventually be given another chance.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+use crate::node::PeerId;
+
+/// Outcome of an interaction with a peer which affects its reputation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReputationSignal {
+    /// A request routed to the peer was served.
+    Succeeded,
+    /// A request routed to the peer failed.
+    FailedTransaction,
+    /// The peer sent a message which could not be decoded.
+    InvalidMessage,
+    /// A request sent to the peer timed out.
+    Timeout,
+    /// A request routed to the peer was served much slower than the router predicted.
+    Underperformed,
+}
+
+impl ReputationSignal {
+    fn weight(self) -> f64 {
+        match self {
+            Self::Succeeded => 0.05,
+            Self::FailedTransaction => -0.25,
+            Self::InvalidMessage => -1.0,
+            Self::Timeout => -0.25,
+            Self::Underperformed => -0.1,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Score {
+    value: f64,
+    updated: Instant,
+}
+
+impl Score {
+    fn at(self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let half_lives = elapsed.as_secs_f64() / PeerReputation::HALF_LIFE.as_secs_f64();
+        self.value * 0.5f64.powf(half_lives)
+    }
+}
+
+/// Shared handle to the reputation scores of peers.
+#[derive(Clone)]
+pub(crate) struct PeerReputation {
+    threshold: f64,
+    scores: Arc<RwLock<HashMap<PeerId, Score>>>,
+}
+
+impl PeerReputation {
+    /// Time it takes for a score to decay half way back to neutral.
+    const HALF_LIFE: Duration = Duration::from_secs(15 * 60);
+    /// Upper bound of the scores, so a long history of good behaviour doesn't excuse
+    /// sudden misbehaviour.
+    const MAX_SCORE: f64 = 1.0;
+    /// Number of peers tracked above which the scores which decayed back to neutral are
+    /// forgotten.
+    const MAX_TRACKED: usize = 1024;
+    const NEGLIGIBLE_SCORE: f64 = 0.01;
+
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            scores: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn report(&self, peer: &PeerId, signal: ReputationSignal, now: Instant) {
+        let mut scores = self.scores.write();
+        let previous = scores.get(peer).map_or(0.0, |score| score.at(now));
+        let value = (previous + signal.weight()).min(Self::MAX_SCORE);
+        if signal != ReputationSignal::Succeeded {
+            tracing::debug!(%peer, ?signal, score = value, "Peer reputation lowered");
+        }
+        scores.insert(
+            peer.clone(),
+            Score {
+                value,
+                updated: now,
+            },
+        );
+        if scores.len() > Self::MAX_TRACKED {
+            scores.retain(|_, score| score.at(now).abs() >= Self::NEGLIGIBLE_SCORE);
+        }
+    }
+
+    /// Current score of the peer; zero is neutral and negative scores denote misbehaviour.
+    pub fn score(&self, peer: &PeerId, now: Instant) -> f64 {
+        self.scores
+            .read()
+            .get(peer)
+            .map_or(0.0, |score| score.at(now))
+    }
+
+    /// Score below which peers are disconnected.
+    pub fn threshold(&self) -> f64 {
+        self.threshold
+    }
+
+    /// Whether the peer should only be routed to when there are no alternatives.
+    pub fn is_demoted(&self, peer: &PeerId, now: Instant) -> bool {
+        self.score(peer, now) < self.threshold / 2.0
+    }
+
+    /// Whether the peer misbehaved enough to be disconnected.
+    pub fn is_below_threshold(&self, peer: &PeerId, now: Instant) -> bool {
+        self.score(peer, now) < self.threshold
+    }
+
+    pub fn readdress(&self, previous: &PeerId, moved: PeerId) {
+        let mut scores = self.scores.write();
+        if let Some(score) = scores.remove(previous) {
+            scores.insert(moved, score);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misbehaving_peers_fall_below_threshold_and_recover() {
+        let reputation = PeerReputation::new(crate::config::DEFAULT_REPUTATION_THRESHOLD);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        // good behaviour is capped, and doesn't offset repeated invalid messages
+        for _ in 0..100 {
+            reputation.report(&peer, ReputationSignal::Succeeded, now);
+        }
+        assert_eq!(reputation.score(&peer, now), PeerReputation::MAX_SCORE);
+        for _ in 0..4 {
+            reputation.report(&peer, ReputationSignal::InvalidMessage, now);
+        }
+        assert!(reputation.score(&peer, now) < reputation.threshold);
+
+        // the score decays back towards neutral, through the demotion range
+        let later = now + PeerReputation::HALF_LIFE;
+        let score = reputation.score(&peer, later);
+        assert!(score > reputation.threshold && score < reputation.threshold / 2.0);
+        assert_eq!(reputation.score(&PeerId::random(), later), 0.0);
+    }
+}
@@ -0,0 +1,219 @@
+//! Standby connections, kept open but idle to be promoted when a connection is needed.
+//!
+//! Looking for a new connection takes a round trip through the network to find a peer,
+//! plus the handshake with it, which delays the operations waiting to be forwarded. Instead of
+//! turning down the peers offering to connect that did not make the cut, a few of them are
+//! kept connected on standby: they are not routed to nor count towards the connections of
+//! this node, but are promoted to active right away when a connection around their location
+//! is needed.
+//!
+//! The standby connections are spread among bands of distance to this node, each band twice as
+//! wide as the previous one, so promoted connections fit in the small-world shape of the ring.
+//!
+//! A spot is reserved for a connection while it is being established, and given up if the
+//! connection is not open within [`RESERVATION_TTL`], so failed handshakes do not take up the
+//! spots for good.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use super::Location;
+use crate::node::PeerId;
+
+/// Number of distance bands; the closest one covers distances below `0.5 / 2^(BANDS - 1)`.
+const BANDS: u32 = 5;
+
+/// Time a reserved spot is kept for a connection still being established.
+const RESERVATION_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+struct Standby {
+    peer: PeerId,
+    location: Location,
+    band: u32,
+    /// Whether the connection is already open, or still being established.
+    established: bool,
+    reserved_at: Instant,
+}
+
+impl Standby {
+    fn expired(&self, now: Instant) -> bool {
+        !self.established && now.saturating_duration_since(self.reserved_at) >= RESERVATION_TTL
+    }
+}
+
+/// Shared handle to the standby connections of this node.
+#[derive(Clone)]
+pub(crate) struct StandbyPool {
+    /// Max number of standby connections per distance band, none are kept if zero.
+    per_band: usize,
+    connections: Arc<Mutex<Vec<Standby>>>,
+}
+
+impl StandbyPool {
+    pub fn new(per_band: usize) -> Self {
+        Self {
+            per_band,
+            connections: Arc::default(),
+        }
+    }
+
+    pub fn contains(&self, peer: &PeerId, now: Instant) -> bool {
+        let mut connections = self.connections.lock();
+        reap(&mut connections, now);
+        connections.iter().any(|s| &s.peer == peer)
+    }
+
+    /// Reserves a spot for a connection with the peer if there is room left in its band.
+    pub fn reserve(&self, own: Location, peer: &PeerId, location: Location, now: Instant) -> bool {
+        if self.per_band == 0 {
+            return false;
+        }
+        let band = band(own, location);
+        let mut connections = self.connections.lock();
+        reap(&mut connections, now);
+        if connections.iter().any(|s| &s.peer == peer)
+            || connections.iter().filter(|s| s.band == band).count() >= self.per_band
+        {
+            return false;
+        }
+        connections.push(Standby {
+            peer: peer.clone(),
+            location,
+            band,
+            established: false,
+            reserved_at: now,
+        });
+        true
+    }
+
+    /// Marks the connection with the peer as open, returns false if it is not on standby.
+    pub fn establish(&self, peer: &PeerId) -> bool {
+        let mut connections = self.connections.lock();
+        match connections.iter_mut().find(|s| &s.peer == peer) {
+            Some(standby) => {
+                standby.established = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the connection with the peer, returns false if it was not on standby.
+    pub fn remove(&self, peer: &PeerId) -> bool {
+        let mut connections = self.connections.lock();
+        let before = connections.len();
+        connections.retain(|s| &s.peer != peer);
+        connections.len() != before
+    }
+
+    /// Takes the open standby connection closest to the target location, among the ones in
+    /// the same band as it.
+    pub fn take_closest(&self, own: Location, target: Location) -> Option<(PeerId, Location)> {
+        let band = band(own, target);
+        let mut connections = self.connections.lock();
+        let closest = connections
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.established && s.band == band)
+            .min_by_key(|(_, s)| s.location.distance(target))
+            .map(|(idx, _)| idx)?;
+        let standby = connections.swap_remove(closest);
+        Some((standby.peer, standby.location))
+    }
+}
+
+/// Gives up the spots reserved for connections which took too long to be established.
+fn reap(connections: &mut Vec<Standby>, now: Instant) {
+    connections.retain(|standby| {
+        let expired = standby.expired(now);
+        if expired {
+            tracing::debug!(peer = %standby.peer, "Standby reservation expired");
+        }
+        !expired
+    });
+}
+
+/// Distance band of the location relative to this node, from the farthest to the closest.
+fn band(own: Location, location: Location) -> u32 {
+    let distance = own.distance(location).as_f64();
+    if distance <= 0.0 {
+        return BANDS - 1;
+    }
+    ((0.5 / distance).log2().floor().max(0.0) as u32).min(BANDS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promote_closest_in_band() {
+        let pool = StandbyPool::new(1);
+        let own = Location::new(0.0);
+        let now = Instant::now();
+        let far = PeerId::random();
+        let near = PeerId::random();
+        let other = PeerId::random();
+
+        assert!(pool.reserve(own, &far, Location::new(0.4), now));
+        // the band between 0.25 and 0.5 is already taken
+        assert!(!pool.reserve(own, &other, Location::new(0.3), now));
+        assert!(pool.reserve(own, &near, Location::new(0.01), now));
+        assert!(!pool.reserve(own, &near, Location::new(0.01), now));
+
+        // connections still being established are not promoted
+        assert!(pool.take_closest(own, Location::new(0.35)).is_none());
+        assert!(pool.establish(&far));
+        assert!(pool.establish(&near));
+        assert!(!pool.establish(&other));
+
+        // nor connections in a different band than the one needed
+        assert!(pool.take_closest(own, Location::new(0.2)).is_none());
+        assert_eq!(
+            pool.take_closest(own, Location::new(0.35)),
+            Some((far.clone(), Location::new(0.4)))
+        );
+        assert!(!pool.contains(&far, now));
+
+        assert!(pool.remove(&near));
+        assert!(!pool.remove(&near));
+        assert!(pool.take_closest(own, Location::new(0.01)).is_none());
+
+        let disabled = StandbyPool::new(0);
+        assert!(!disabled.reserve(own, &far, Location::new(0.4), now));
+    }
+
+    #[test]
+    fn expired_reservations_are_given_up() {
+        let pool = StandbyPool::new(1);
+        let own = Location::new(0.0);
+        let now = Instant::now();
+        let stalled = PeerId::random();
+        let open = PeerId::random();
+        let other = PeerId::random();
+
+        assert!(pool.reserve(own, &stalled, Location::new(0.4), now));
+        assert!(pool.reserve(own, &open, Location::new(0.01), now));
+        assert!(pool.establish(&open));
+
+        // the band is still taken by the connection being established
+        let later = now + RESERVATION_TTL;
+        assert!(!pool.reserve(
+            own,
+            &other,
+            Location::new(0.3),
+            later - Duration::from_secs(1)
+        ));
+        assert!(pool.contains(&stalled, later - Duration::from_secs(1)));
+
+        // until its reservation expires, open connections are kept
+        assert!(!pool.contains(&stalled, later));
+        assert!(pool.contains(&open, later));
+        assert!(pool.reserve(own, &other, Location::new(0.3), later));
+    }
+}
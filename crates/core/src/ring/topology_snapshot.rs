@@ -0,0 +1,163 @@
+//! Introspection of the local view of the ring topology, so diagnosis tools can check whether
+//! the neighbourhood of a node has the expected small-world shape.
+//!
+//! In a healthy small-world ring the number of neighbours at distance `d` is proportional
+//! to `1/d`, so neighbours are spread evenly among distance buckets of doubling width.
+
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ClockSkew, Location, PeerKeyLocation};
+
+/// Number of buckets of the distance histogram; the closest one covers distances below
+/// `0.5 / 2^(DISTANCE_BUCKETS - 1)`.
+const DISTANCE_BUCKETS: u32 = 10;
+
+/// Arcs of the ring without neighbours this many times wider than the expected spacing
+/// between neighbours are reported as coverage gaps.
+const GAP_FACTOR: f64 = 3.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TopologySnapshot {
+    /// Location of this node, if already assigned.
+    pub location: Option<f64>,
+    pub neighbours: Vec<NeighbourInfo>,
+    /// Number of neighbours per ring distance bucket, from the closest to the farthest.
+    pub distance_histogram: Vec<DistanceBucket>,
+    /// Arcs of the ring not covered by any neighbour, from the widest to the narrowest.
+    pub coverage_gaps: Vec<CoverageGap>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NeighbourInfo {
+    pub peer: String,
+    pub address: SocketAddr,
+    pub location: Option<f64>,
+    /// Ring distance to this node, if both locations are known.
+    pub distance: Option<f64>,
+    /// Skew between the clock of the neighbour and the one of this node, once estimated.
+    pub clock_skew: Option<ClockSkew>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct DistanceBucket {
+    /// Distances in the bucket are below this bound, and at or above the one of the previous bucket.
+    pub upper_bound: f64,
+    pub neighbours: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct CoverageGap {
+    /// Location at which the gap starts, going clockwise.
+    pub start: f64,
+    /// Location at which the gap ends, which may have wrapped around the ring.
+    pub end: f64,
+    pub width: f64,
+}
+
+impl TopologySnapshot {
+    pub(super) fn new(
+        own_location: Option<Location>,
+        neighbours: impl IntoIterator<Item = PeerKeyLocation>,
+    ) -> Self {
+        let neighbours: Vec<_> = neighbours
+            .into_iter()
+            .map(|neighbour| NeighbourInfo {
+                peer: neighbour.peer.to_string(),
+                address: neighbour.peer.addr,
+                location: neighbour.location.map(|loc| loc.as_f64()),
+                distance: own_location
+                    .zip(neighbour.location)
+                    .map(|(own, loc)| own.distance(loc).as_f64()),
+                clock_skew: None,
+            })
+            .collect();
+
+        let mut distance_histogram: Vec<_> = (0..DISTANCE_BUCKETS)
+            .rev()
+            .map(|exp| DistanceBucket {
+                upper_bound: 0.5 / 2f64.powi(exp as i32),
+                neighbours: 0,
+            })
+            .collect();
+        for distance in neighbours.iter().filter_map(|n| n.distance) {
+            let bucket = distance_histogram
+                .iter()
+                .position(|bucket| distance < bucket.upper_bound)
+                .unwrap_or(distance_histogram.len() - 1);
+            distance_histogram[bucket].neighbours += 1;
+        }
+
+        let mut locations: Vec<_> = neighbours
+            .iter()
+            .filter_map(|n| n.location)
+            .chain(own_location.map(|loc| loc.as_f64()))
+            .collect();
+        locations.sort_by(f64::total_cmp);
+        locations.dedup();
+        let mut coverage_gaps = Vec::new();
+        if locations.len() > 1 {
+            let max_width = GAP_FACTOR / locations.len() as f64;
+            let arcs = locations.windows(2).map(|pair| (pair[0], pair[1])).chain(
+                // the arc wrapping around the ring
+                [(locations[locations.len() - 1], locations[0])],
+            );
+            for (start, end) in arcs {
+                let width = (end - start).rem_euclid(1.0);
+                if width > max_width {
+                    coverage_gaps.push(CoverageGap { start, end, width });
+                }
+            }
+            coverage_gaps.sort_by(|a, b| b.width.total_cmp(&a.width));
+        }
+
+        Self {
+            location: own_location.map(|loc| loc.as_f64()),
+            neighbours,
+            distance_histogram,
+            coverage_gaps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::PeerId;
+
+    #[test]
+    fn histogram_and_gaps() {
+        let neighbour = |loc| PeerKeyLocation {
+            peer: PeerId::random(),
+            location: Some(Location::new(loc)),
+        };
+        let neighbours = [
+            neighbour(0.101),
+            neighbour(0.13),
+            neighbour(0.2),
+            neighbour(0.3),
+            neighbour(0.4),
+        ];
+        let snapshot = TopologySnapshot::new(Some(Location::new(0.1)), neighbours);
+
+        let counts: Vec<_> = snapshot
+            .distance_histogram
+            .iter()
+            .map(|bucket| bucket.neighbours)
+            .collect();
+        // distances: 0.001, 0.03, 0.1, 0.2, 0.3
+        assert_eq!(counts, [0, 1, 0, 0, 0, 1, 0, 1, 1, 1]);
+        assert_eq!(snapshot.distance_histogram.last().unwrap().upper_bound, 0.5);
+
+        // the arc from 0.4 back to 0.1 is the only one wider than 3 / 6
+        assert_eq!(snapshot.coverage_gaps.len(), 1);
+        let gap = snapshot.coverage_gaps[0];
+        assert_eq!((gap.start, gap.end), (0.4, 0.1));
+        assert!((gap.width - 0.7).abs() < 1e-9);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: TopologySnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+    }
+}
@@ -2,7 +2,7 @@ mod isotonic_estimator;
 mod util;
 
 use crate::ring::{Location, PeerKeyLocation};
-use isotonic_estimator::{EstimatorType, IsotonicEstimator, IsotonicEvent};
+use isotonic_estimator::{EstimatorType, IsotonicEstimator, IsotonicEvent, Prior};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use util::{Mean, TransferSpeed};
@@ -17,6 +17,7 @@ pub(crate) struct Router {
     failure_estimator: IsotonicEstimator,
     mean_transfer_size: Mean,
     consider_n_closest_peers: usize,
+    priors: Option<RoutingPriors>,
 }
 
 impl Router {
@@ -108,10 +109,61 @@ impl Router {
             ),
             mean_transfer_size,
             consider_n_closest_peers: 2,
+            priors: None,
         }
     }
 
+    /// Seeds the estimators with network-wide priors, which are used for routing until
+    /// enough local samples have been gathered.
+    ///
+    /// The priors are left unchanged if any of the curves is invalid.
+    pub fn set_priors(&mut self, priors: RoutingPriors) -> Result<(), RoutingError> {
+        let prior = |estimation, points: &[(f64, f64)], estimator_type| {
+            Prior::new(points, priors.weight, estimator_type)
+                .map_err(|source| RoutingError::EstimationError { estimation, source })
+        };
+        let response_start_time = prior(
+            "start time",
+            &priors.response_start_time,
+            EstimatorType::Positive,
+        )?;
+        let failure = prior(
+            "failure",
+            &priors.failure_probability,
+            EstimatorType::Positive,
+        )?;
+        let transfer_rate = prior(
+            "transfer rate",
+            &priors.transfer_rate,
+            EstimatorType::Negative,
+        )?;
+        self.response_start_time_estimator
+            .set_prior(response_start_time);
+        self.failure_estimator.set_prior(failure);
+        self.transfer_rate_estimator.set_prior(transfer_rate);
+        self.priors = Some(priors);
+        Ok(())
+    }
+
+    /// The priors this router was seeded with, if any.
+    pub fn priors(&self) -> Option<&RoutingPriors> {
+        self.priors.as_ref()
+    }
+
     #[allow(dead_code)]
+    /// Curves estimated from the requests routed by this node alone, sampled at the given
+    /// distances, in the same format the network-wide priors are published in.
+    ///
+    /// `None` until enough requests have been routed to estimate every curve.
+    pub fn local_estimates(&self, distances: &[f64]) -> Option<RoutingPriors> {
+        Some(RoutingPriors {
+            weight: self.failure_estimator.len() as u64,
+            response_start_time: self.response_start_time_estimator.local_curve(distances)?,
+            failure_probability: self.failure_estimator.local_curve(distances)?,
+            transfer_rate: self.transfer_rate_estimator.local_curve(distances)?,
+        })
+    }
+
     pub fn considering_n_closest_peers(mut self, n: u32) -> Self {
         self.consider_n_closest_peers = n as usize;
         self
@@ -216,6 +268,18 @@ impl Router {
         }
     }
 
+    /// Expected time, in seconds, until the peer starts responding to a request for the
+    /// target location. Only known once there is enough historical data.
+    pub fn expected_response_start(
+        &self,
+        peer: &PeerKeyLocation,
+        target_location: Location,
+    ) -> Option<f64> {
+        self.predict_routing_outcome(peer, target_location)
+            .ok()
+            .map(|prediction| prediction.time_to_response_start)
+    }
+
     fn predict_routing_outcome(
         &self,
         peer: &PeerKeyLocation,
@@ -267,12 +331,13 @@ impl Router {
 
     fn has_sufficient_historical_data(&self) -> bool {
         let minimum_historical_data_for_global_prediction = 200;
-        self.response_start_time_estimator.len() >= minimum_historical_data_for_global_prediction
+        self.response_start_time_estimator.effective_len()
+            >= minimum_historical_data_for_global_prediction
     }
 }
 
 #[derive(Debug, thiserror::Error)]
-enum RoutingError {
+pub(crate) enum RoutingError {
     #[error("Insufficient data provided")]
     InsufficientDataError,
     #[error("failed {estimation} estimation: {source}")]
@@ -291,6 +356,30 @@ struct RoutingPrediction {
     expected_total_time: f64,
 }
 
+/// Network-wide routing estimations, published in a contract so fresh nodes can use them
+/// as priors until they have gathered enough samples of their own.
+///
+/// Each curve is a list of `(distance, value)` points.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RoutingPriors {
+    /// Number of local samples the priors are worth; their influence decays as local
+    /// samples outnumber it.
+    pub weight: u64,
+    /// Time to response start, in seconds.
+    pub response_start_time: Vec<(f64, f64)>,
+    /// Probability of a request failing.
+    pub failure_probability: Vec<(f64, f64)>,
+    /// Transfer rate, in bytes per second.
+    pub transfer_rate: Vec<(f64, f64)>,
+}
+
+impl RoutingPriors {
+    /// Parses the priors from the state of the contract they are published in.
+    pub fn from_state(state: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(state)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
 pub(crate) struct RouteEvent {
@@ -431,6 +520,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn predict_with_priors() {
+        let peer = PeerKeyLocation::random();
+        let contract_location = Location::random();
+        let distance = peer.location.unwrap().distance(contract_location).as_f64();
+
+        let mut router = Router::new(&[]);
+        assert!(!router.has_sufficient_historical_data());
+        router
+            .set_priors(RoutingPriors {
+                weight: 200,
+                response_start_time: vec![(0.0, 1.0), (0.5, 2.0)],
+                failure_probability: vec![(0.0, 0.1), (0.5, 0.1)],
+                transfer_rate: vec![(0.0, 1000.0), (0.5, 1000.0)],
+            })
+            .unwrap();
+        assert!(router.has_sufficient_historical_data());
+        let prediction = router
+            .predict_routing_outcome(&peer, contract_location)
+            .unwrap();
+        assert!((prediction.time_to_response_start - (1.0 + 2.0 * distance)).abs() < 0.01);
+
+        // local samples progressively take over the priors
+        for _ in 0..1000 {
+            router.add_event(RouteEvent {
+                peer: peer.clone(),
+                contract_location,
+                outcome: RouteOutcome::Success {
+                    time_to_response_start: Duration::from_secs(10),
+                    payload_size: 1000,
+                    payload_transfer_time: Duration::from_secs(1),
+                },
+            });
+        }
+        let prediction = router
+            .predict_routing_outcome(&peer, contract_location)
+            .unwrap();
+        assert!(prediction.time_to_response_start > 8.0);
+    }
+
     #[test]
     fn test_select_closest_peers_size() {
         const NUM_PEERS: u32 = 45;
@@ -522,4 +651,41 @@ mod tests {
 
         peers
     }
+
+    #[test]
+    fn invalid_priors_are_rejected() {
+        let mut router = Router::new(&[]);
+        let result = router.set_priors(RoutingPriors {
+            weight: 200,
+            response_start_time: vec![(0.0, 1.0), (0.5, 2.0)],
+            failure_probability: vec![(0.0, f64::NAN), (0.5, 0.1)],
+            transfer_rate: vec![(0.0, 1000.0), (f64::INFINITY, 1000.0)],
+        });
+        assert!(matches!(
+            result,
+            Err(RoutingError::EstimationError {
+                source: isotonic_estimator::EstimationError::InvalidPrior,
+                ..
+            })
+        ));
+        // none of the curves is used if any of them is invalid
+        assert!(router.priors().is_none());
+        assert!(!router.has_sufficient_historical_data());
+    }
+
+    #[test]
+    fn untrusted_prior_weight_is_capped() {
+        let mut router = Router::new(&[]);
+        router
+            .set_priors(RoutingPriors {
+                weight: u64::MAX,
+                response_start_time: vec![(0.0, 1.0), (0.5, 2.0)],
+                failure_probability: vec![(0.0, 0.1), (0.5, 0.1)],
+                transfer_rate: vec![(0.0, 1000.0), (0.5, 1000.0)],
+            })
+            .unwrap();
+        assert!(router.has_sufficient_historical_data());
+        let effective_len = router.response_start_time_estimator.effective_len();
+        assert!(effective_len < u32::MAX as usize);
+    }
 }
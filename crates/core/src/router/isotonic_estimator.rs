@@ -17,6 +17,52 @@ const MIN_POINTS_FOR_REGRESSION: usize = 5;
 pub(super) struct IsotonicEstimator {
     pub global_regression: IsotonicRegression<f64>,
     pub peer_adjustments: HashMap<PeerKeyLocation, Adjustment>,
+    /// Prior estimation, used until enough samples have been gathered locally.
+    prior: Option<Prior>,
+}
+
+/// A prior estimation curve, like the ones published for the whole network.
+///
+/// The prior is weighted as if it had been built from `weight` samples, so its influence
+/// decays as local samples accumulate.
+/// Max number of samples a prior is worth. Priors come from the network, so their weight
+/// can't be trusted to be sensible.
+const MAX_PRIOR_WEIGHT: u64 = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct Prior {
+    regression: IsotonicRegression<f64>,
+    weight: f64,
+}
+
+impl Prior {
+    /// Builds a prior estimation curve from `(distance, result)` points, as if it had been
+    /// built from `weight` samples.
+    ///
+    /// Returns `None` if there are no points or no weight, so there is no prior to use.
+    pub fn new(
+        points: &[(f64, f64)],
+        weight: u64,
+        estimator_type: EstimatorType,
+    ) -> Result<Option<Self>, EstimationError> {
+        // the points come from the network, so they can't be trusted to be well formed
+        if points
+            .iter()
+            .any(|&(x, y)| !x.is_finite() || !y.is_finite())
+        {
+            return Err(EstimationError::InvalidPrior);
+        }
+        let points: Vec<_> = points.iter().map(|&(x, y)| Point::new(x, y)).collect();
+        let regression = match estimator_type {
+            EstimatorType::Positive => IsotonicRegression::new_ascending(&points),
+            EstimatorType::Negative => IsotonicRegression::new_descending(&points),
+        }
+        .map_err(|_| EstimationError::InvalidPrior)?;
+        Ok((regression.len() > 0 && weight > 0).then_some(Prior {
+            regression,
+            weight: weight.min(MAX_PRIOR_WEIGHT) as f64,
+        }))
+    }
 }
 
 impl IsotonicEstimator {
@@ -84,9 +130,15 @@ impl IsotonicEstimator {
         IsotonicEstimator {
             global_regression,
             peer_adjustments,
+            prior: None,
         }
     }
 
+    /// Sets the prior estimation curve, replacing the previous one (if any).
+    pub fn set_prior(&mut self, prior: Option<Prior>) {
+        self.prior = prior;
+    }
+
     /// Adds a new event to the estimator.
     pub fn add_event(&mut self, event: IsotonicEvent) {
         let route_distance = event.route_distance();
@@ -122,16 +174,39 @@ impl IsotonicEstimator {
         // garbage output, but users of this class must implement their own checks
         // to ensure that the model is sufficiently accurate as this is an
         // extremely low bar.
-        if self.global_regression.len() < MIN_POINTS_FOR_REGRESSION {
+        let local_samples = self.global_regression.len();
+        if local_samples < MIN_POINTS_FOR_REGRESSION && self.prior.is_none() {
             return Err(EstimationError::InsufficientData);
         }
 
         let distance: f64 = contract_location.distance(peer.location.unwrap()).as_f64();
 
-        let global_estimate = self.global_regression.interpolate(distance).unwrap();
-
         // Regression can sometimes produce negative estimates
-        let global_estimate = global_estimate.max(0.0);
+        let local_estimate = (local_samples >= MIN_POINTS_FOR_REGRESSION).then(|| {
+            self.global_regression
+                .interpolate(distance)
+                .unwrap()
+                .max(0.0)
+        });
+
+        let global_estimate = match (&self.prior, local_estimate) {
+            (Some(prior), local_estimate) => {
+                let prior_estimate = prior
+                    .regression
+                    .interpolate(distance)
+                    .expect("Regression should always produce an estimate")
+                    .max(0.0);
+                match local_estimate {
+                    Some(local_estimate) => {
+                        let prior_share = prior.weight / (prior.weight + local_samples as f64);
+                        prior_share * prior_estimate + (1.0 - prior_share) * local_estimate
+                    }
+                    None => prior_estimate,
+                }
+            }
+            (None, Some(local_estimate)) => local_estimate,
+            (None, None) => unreachable!(),
+        };
 
         Ok(self
             .peer_adjustments
@@ -148,9 +223,27 @@ impl IsotonicEstimator {
             }))
     }
 
+    /// Estimations of the regression built from local samples alone at the given distances,
+    /// `None` until enough samples have been gathered.
+    pub(crate) fn local_curve(&self, distances: &[f64]) -> Option<Vec<(f64, f64)>> {
+        if self.len() < MIN_POINTS_FOR_REGRESSION {
+            return None;
+        }
+        distances
+            .iter()
+            .map(|&d| Some((d, self.global_regression.interpolate(d)?)))
+            .collect()
+    }
+
     pub(crate) fn len(&self) -> usize {
         self.global_regression.len()
     }
+
+    /// Number of samples backing the estimations, including the prior weight (if any).
+    pub(crate) fn effective_len(&self) -> usize {
+        self.len()
+            .saturating_add(self.prior.as_ref().map_or(0, |prior| prior.weight as usize))
+    }
 }
 
 pub(super) enum EstimatorType {
@@ -161,9 +254,11 @@ pub(super) enum EstimatorType {
 }
 
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
-pub(super) enum EstimationError {
+pub(crate) enum EstimationError {
     #[error("Insufficient data for estimation")]
     InsufficientData,
+    #[error("Invalid prior estimation curve")]
+    InvalidPrior,
 }
 
 /// A routing event is a single request to a peer for a contract, and some value indicating
@@ -1,9 +1,11 @@
 pub(crate) mod app_packaging;
+mod client_storage;
 pub(crate) mod errors;
 mod http_gateway;
 pub(crate) mod path_handlers;
+pub mod registry;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
 use freenet_stdlib::{
     client_api::{ClientError, ClientRequest, HostResponse},
@@ -14,7 +16,10 @@ use http_gateway::HttpGateway;
 use tower_http::trace::TraceLayer;
 
 use crate::{
-    client_events::{websocket::WebSocketProxy, AuthToken, BoxedClient, ClientId, HostResult},
+    client_events::{
+        websocket::WebSocketProxy, AuthToken, BoxedClient, ClientId, ConnectivityEvent,
+        NotificationReceiver,
+    },
     config::WebsocketApiConfig,
 };
 
@@ -46,7 +51,11 @@ pub(crate) enum HostCallbackResult {
     SubscriptionChannel {
         id: ClientId,
         key: ContractKey,
-        callback: tokio::sync::mpsc::UnboundedReceiver<HostResult>,
+        callback: NotificationReceiver,
+    },
+    Connectivity {
+        id: ClientId,
+        event: ConnectivityEvent,
     },
 }
 
@@ -62,7 +71,10 @@ fn serve(socket: SocketAddr, router: axum::Router) {
 
 pub mod local_node {
     use freenet_stdlib::client_api::{ClientRequest, ErrorKind};
-    use std::net::{IpAddr, SocketAddr};
+    use std::{
+        net::{IpAddr, SocketAddr},
+        path::PathBuf,
+    };
     use tower_http::trace::TraceLayer;
 
     use crate::{
@@ -72,7 +84,11 @@ pub mod local_node {
 
     use super::{http_gateway::HttpGateway, serve};
 
-    pub async fn run_local_node(mut executor: Executor, socket: SocketAddr) -> anyhow::Result<()> {
+    pub async fn run_local_node(
+        mut executor: Executor,
+        socket: SocketAddr,
+        client_storage_dir: PathBuf,
+    ) -> anyhow::Result<()> {
         match socket.ip() {
             IpAddr::V4(ip) if !ip.is_loopback() => {
                 anyhow::bail!("invalid ip: {ip}, expecting localhost")
@@ -82,7 +98,7 @@ pub mod local_node {
             }
             _ => {}
         }
-        let (mut gw, gw_router) = HttpGateway::as_router(&socket);
+        let (mut gw, gw_router) = HttpGateway::as_router(&socket, client_storage_dir)?;
         let (mut ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
 
         serve(socket, ws_router.layer(TraceLayer::new_for_http()));
@@ -181,15 +197,23 @@ pub mod local_node {
     }
 }
 
-pub async fn serve_gateway(config: WebsocketApiConfig) -> [BoxedClient; 2] {
-    let (gw, ws_proxy) = serve_gateway_in(config).await;
-    [Box::new(gw), Box::new(ws_proxy)]
+/// Serves the HTTP gateway and websocket API, keeping the client storage entries in the
+/// given directory.
+pub async fn serve_gateway(
+    config: WebsocketApiConfig,
+    client_storage_dir: PathBuf,
+) -> std::io::Result<[BoxedClient; 2]> {
+    let (gw, ws_proxy) = serve_gateway_in(config, client_storage_dir).await?;
+    Ok([Box::new(gw), Box::new(ws_proxy)])
 }
 
-pub(crate) async fn serve_gateway_in(config: WebsocketApiConfig) -> (HttpGateway, WebSocketProxy) {
+pub(crate) async fn serve_gateway_in(
+    config: WebsocketApiConfig,
+    client_storage_dir: PathBuf,
+) -> std::io::Result<(HttpGateway, WebSocketProxy)> {
     let ws_socket = (config.address, config.port).into();
-    let (gw, gw_router) = HttpGateway::as_router(&ws_socket);
+    let (gw, gw_router) = HttpGateway::as_router(&ws_socket, client_storage_dir)?;
     let (ws_proxy, ws_router) = WebSocketProxy::as_router(gw_router);
     serve(ws_socket, ws_router.layer(TraceLayer::new_for_http()));
-    (gw, ws_proxy)
+    Ok((gw, ws_proxy))
 }
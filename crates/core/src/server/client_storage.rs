@@ -0,0 +1,386 @@
+//! Small encrypted key-value storage for web applications, kept by the node instead of in the
+//! network state (e.g. UI preferences).
+//!
+//! Entries are scoped per client identity and contract. The identity is a secret held by the
+//! application (e.g. generated on first use and kept by the user), and the encryption key of
+//! each scope is derived from it, so the entries can't be read back without the identity,
+//! which is never written to disk.
+//!
+//! Identities are made up by the clients, so besides the quota of each scope, the entries of
+//! all the identities of a contract share a quota, and the entries of all the contracts
+//! another one: opening new scopes doesn't grant more room.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chacha20poly1305::{aead::Aead, Key, KeyInit, XChaCha20Poly1305, XNonce};
+use freenet_stdlib::prelude::ContractInstanceId;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Header carrying the client identity on storage requests.
+pub(crate) const IDENTITY_HEADER: &str = "x-freenet-client-identity";
+
+const MIN_IDENTITY_LEN: usize = 16;
+const MAX_KEY_LEN: usize = 256;
+/// Max size of the entries stored per scope, keys included.
+const MAX_SCOPE_SIZE: usize = 1024 * 1024;
+/// Max number of entries stored per scope.
+const MAX_SCOPE_ENTRIES: usize = 1024;
+/// Max size of the files of all the scopes of a contract.
+const MAX_CONTRACT_SIZE: u64 = 16 * 1024 * 1024;
+/// Max size of the files of all the scopes.
+const MAX_TOTAL_SIZE: u64 = 256 * 1024 * 1024;
+
+const NONCE_SIZE: usize = 24;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ClientStorageError {
+    #[error("client identity must be at least {MIN_IDENTITY_LEN} bytes long")]
+    InvalidIdentity,
+    #[error("entry keys must be between 1 and {MAX_KEY_LEN} bytes long")]
+    InvalidKey,
+    #[error("missing entry: {0}")]
+    MissingEntry(String),
+    #[error("storage quota exceeded: {size} bytes in {entries} entries")]
+    QuotaExceeded { size: usize, entries: usize },
+    #[error("storage quota of the {origin} exceeded: {size} bytes")]
+    SharedQuotaExceeded { origin: &'static str, size: u64 },
+    #[error("failed encrypting the entries")]
+    Encryption,
+    #[error("stored entries could not be decrypted")]
+    Decryption,
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Entries of a scope, as exported and imported by clients.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StorageExport {
+    pub entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl StorageExport {
+    fn check_quota(&self) -> Result<(), ClientStorageError> {
+        let size: usize = self.entries.iter().map(|(k, v)| k.len() + v.len()).sum();
+        let entries = self.entries.len();
+        if size > MAX_SCOPE_SIZE || entries > MAX_SCOPE_ENTRIES {
+            return Err(ClientStorageError::QuotaExceeded { size, entries });
+        }
+        if self
+            .entries
+            .keys()
+            .any(|k| k.is_empty() || k.len() > MAX_KEY_LEN)
+        {
+            return Err(ClientStorageError::InvalidKey);
+        }
+        Ok(())
+    }
+}
+
+/// Storage scope of a client identity for a given contract.
+#[derive(Clone)]
+pub(crate) struct Scope {
+    cipher: XChaCha20Poly1305,
+    /// Directory of the scopes of the contract.
+    contract_dir: String,
+    file_name: String,
+}
+
+impl Scope {
+    pub fn new(identity: &[u8], contract: &ContractInstanceId) -> Result<Self, ClientStorageError> {
+        if identity.len() < MIN_IDENTITY_LEN {
+            return Err(ClientStorageError::InvalidIdentity);
+        }
+        let key = blake3::Hasher::new_derive_key("freenet client storage v1")
+            .update(identity)
+            .update(contract.as_bytes())
+            .finalize();
+        Ok(Self {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key.as_bytes())),
+            contract_dir: contract.encode(),
+            file_name: blake3::hash(key.as_bytes()).to_hex().to_string(),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ClientStorage {
+    base_path: Arc<PathBuf>,
+    /// Serializes the read-modify-write cycles on the scope files.
+    lock: Arc<Mutex<()>>,
+}
+
+impl ClientStorage {
+    pub fn new(base_path: PathBuf) -> std::io::Result<Self> {
+        fs::create_dir_all(&base_path)?;
+        Ok(Self {
+            base_path: Arc::new(base_path),
+            lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub async fn get(&self, scope: &Scope, key: &str) -> Result<Vec<u8>, ClientStorageError> {
+        let key = key.to_owned();
+        self.blocking(scope, move |storage, scope| {
+            storage
+                .load(scope)?
+                .entries
+                .remove(&key)
+                .ok_or(ClientStorageError::MissingEntry(key))
+        })
+        .await
+    }
+
+    pub async fn set(
+        &self,
+        scope: &Scope,
+        key: String,
+        value: Vec<u8>,
+    ) -> Result<(), ClientStorageError> {
+        self.blocking(scope, move |storage, scope| {
+            let mut stored = storage.load(scope)?;
+            stored.entries.insert(key, value);
+            storage.store(scope, &stored)
+        })
+        .await
+    }
+
+    pub async fn remove(&self, scope: &Scope, key: &str) -> Result<(), ClientStorageError> {
+        let key = key.to_owned();
+        self.blocking(scope, move |storage, scope| {
+            let mut stored = storage.load(scope)?;
+            if stored.entries.remove(&key).is_none() {
+                return Err(ClientStorageError::MissingEntry(key));
+            }
+            storage.store(scope, &stored)
+        })
+        .await
+    }
+
+    pub async fn export(&self, scope: &Scope) -> Result<StorageExport, ClientStorageError> {
+        self.blocking(scope, |storage, scope| storage.load(scope))
+            .await
+    }
+
+    /// Replaces all the entries of the scope with the imported ones.
+    pub async fn import(
+        &self,
+        scope: &Scope,
+        entries: StorageExport,
+    ) -> Result<(), ClientStorageError> {
+        self.blocking(scope, move |storage, scope| storage.store(scope, &entries))
+            .await
+    }
+
+    /// Runs the read-modify-write cycle on the files of the scope on a blocking thread, one
+    /// at a time.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        scope: &Scope,
+        f: impl FnOnce(&Self, &Scope) -> Result<T, ClientStorageError> + Send + 'static,
+    ) -> Result<T, ClientStorageError> {
+        let storage = self.clone();
+        let scope = scope.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = storage.lock.lock();
+            f(&storage, &scope)
+        })
+        .await
+        .map_err(|err| std::io::Error::other(err.to_string()))?
+    }
+
+    fn scope_path(&self, scope: &Scope) -> PathBuf {
+        self.base_path
+            .join(&scope.contract_dir)
+            .join(&scope.file_name)
+    }
+
+    fn load(&self, scope: &Scope) -> Result<StorageExport, ClientStorageError> {
+        let data = match fs::read(self.scope_path(scope)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(StorageExport::default())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if data.len() < NONCE_SIZE {
+            return Err(ClientStorageError::Decryption);
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let plaintext = scope
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| ClientStorageError::Decryption)?;
+        Ok(bincode::deserialize(&plaintext)?)
+    }
+
+    fn store(&self, scope: &Scope, entries: &StorageExport) -> Result<(), ClientStorageError> {
+        entries.check_quota()?;
+        let path = self.scope_path(scope);
+        if entries.entries.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let ciphertext = scope
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                bincode::serialize(entries)?.as_ref(),
+            )
+            .map_err(|_| ClientStorageError::Encryption)?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        self.check_shared_quota(scope, data.len() as u64)?;
+        fs::create_dir_all(self.base_path.join(&scope.contract_dir))?;
+        write_atomically(&path, &data)?;
+        Ok(())
+    }
+
+    /// Checks the file of the scope, once replaced by one of the given size, fits in the
+    /// quotas of its contract and of all the scopes.
+    fn check_shared_quota(&self, scope: &Scope, size: u64) -> Result<(), ClientStorageError> {
+        let replaced = file_size(&self.scope_path(scope))?;
+        let contract = dir_size(&self.base_path.join(&scope.contract_dir))? - replaced + size;
+        if contract > MAX_CONTRACT_SIZE {
+            return Err(ClientStorageError::SharedQuotaExceeded {
+                origin: "contract",
+                size: contract,
+            });
+        }
+        let mut total = 0;
+        for dir in fs::read_dir(self.base_path.as_path())? {
+            let dir = dir?;
+            if dir.file_type()?.is_dir() {
+                total += dir_size(&dir.path())?;
+            }
+        }
+        let total = total - replaced + size;
+        if total > MAX_TOTAL_SIZE {
+            return Err(ClientStorageError::SharedQuotaExceeded {
+                origin: "node",
+                size: total,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn file_size(path: &Path) -> std::io::Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// Size of the files in the directory, if it exists.
+fn dir_size(dir: &Path) -> std::io::Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut size = 0;
+    for entry in entries {
+        size += entry?.metadata()?.len();
+    }
+    Ok(size)
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scoped_encrypted_entries() -> Result<(), ClientStorageError> {
+        let dir = tempfile::tempdir()?;
+        let storage = ClientStorage::new(dir.path().to_owned())?;
+        let contract = ContractInstanceId::new([1; 32]);
+        let scope = Scope::new(b"alice's identity secret", &contract)?;
+        storage
+            .set(&scope, "theme".into(), b"dark".to_vec())
+            .await?;
+        assert_eq!(storage.get(&scope, "theme").await?, b"dark");
+
+        // other identities or contracts don't see the entries
+        let other_identity = Scope::new(b"bob's identity secret..", &contract)?;
+        assert!(matches!(
+            storage.get(&other_identity, "theme").await,
+            Err(ClientStorageError::MissingEntry(_))
+        ));
+        let other_contract = Scope::new(
+            b"alice's identity secret",
+            &ContractInstanceId::new([2; 32]),
+        )?;
+        assert!(storage.export(&other_contract).await?.entries.is_empty());
+        assert!(matches!(
+            Scope::new(b"short", &contract),
+            Err(ClientStorageError::InvalidIdentity)
+        ));
+
+        // nothing is stored in plain text
+        for file in fs::read_dir(dir.path().join(contract.encode()))? {
+            let data = fs::read(file?.path())?;
+            assert!(!data.windows(4).any(|w| w == b"dark" || w == b"them"));
+        }
+
+        let exported = storage.export(&scope).await?;
+        storage.import(&other_identity, exported).await?;
+        assert_eq!(storage.get(&other_identity, "theme").await?, b"dark");
+
+        let too_big = vec![0; MAX_SCOPE_SIZE];
+        assert!(matches!(
+            storage.set(&scope, "big".into(), too_big).await,
+            Err(ClientStorageError::QuotaExceeded { .. })
+        ));
+        storage.remove(&scope, "theme").await?;
+        assert!(storage.export(&scope).await?.entries.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identities_share_the_quota_of_the_contract() -> Result<(), ClientStorageError> {
+        let dir = tempfile::tempdir()?;
+        let storage = ClientStorage::new(dir.path().to_owned())?;
+        let contract = ContractInstanceId::new([1; 32]);
+        let identity = |i: usize| format!("made up identity {i:04}");
+        let value = vec![0; 1_000_000];
+
+        let filling = (MAX_CONTRACT_SIZE / value.len() as u64) as usize;
+        for i in 0..filling {
+            let scope = Scope::new(identity(i).as_bytes(), &contract)?;
+            storage.set(&scope, "entry".into(), value.clone()).await?;
+        }
+        // a new identity doesn't get more room
+        let scope = Scope::new(identity(filling).as_bytes(), &contract)?;
+        assert!(matches!(
+            storage.set(&scope, "entry".into(), value.clone()).await,
+            Err(ClientStorageError::SharedQuotaExceeded {
+                origin: "contract",
+                ..
+            })
+        ));
+        // the entries stored can still be replaced, and other contracts have their own quota
+        let scope = Scope::new(identity(0).as_bytes(), &contract)?;
+        storage.set(&scope, "entry".into(), value.clone()).await?;
+        let other = Scope::new(identity(0).as_bytes(), &ContractInstanceId::new([2; 32]))?;
+        storage.set(&other, "entry".into(), value).await?;
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use freenet_stdlib::client_api::ErrorKind;
 use freenet_stdlib::prelude::ContractKey;
 use std::fmt::{Display, Formatter};
 
+use super::client_storage::ClientStorageError;
+
 #[derive(Debug)]
 pub(super) enum WebSocketApiError {
     /// Something went wrong when calling the user repo.
@@ -19,6 +21,13 @@ pub(super) enum WebSocketApiError {
     MissingContract {
         key: ContractKey,
     },
+    Storage(ClientStorageError),
+}
+
+impl From<ClientStorageError> for WebSocketApiError {
+    fn from(error: ClientStorageError) -> Self {
+        WebSocketApiError::Storage(error)
+    }
 }
 
 impl WebSocketApiError {
@@ -28,6 +37,15 @@ impl WebSocketApiError {
             WebSocketApiError::NodeError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             WebSocketApiError::AxumError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             WebSocketApiError::MissingContract { .. } => StatusCode::NOT_FOUND,
+            WebSocketApiError::Storage(error) => match error {
+                ClientStorageError::InvalidIdentity | ClientStorageError::InvalidKey => {
+                    StatusCode::BAD_REQUEST
+                }
+                ClientStorageError::MissingEntry(_) => StatusCode::NOT_FOUND,
+                ClientStorageError::QuotaExceeded { .. }
+                | ClientStorageError::SharedQuotaExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
         }
     }
 
@@ -39,6 +57,7 @@ impl WebSocketApiError {
             WebSocketApiError::NodeError { error_cause } => format!("Node error: {}", error_cause),
             WebSocketApiError::AxumError { error } => format!("Server error: {}", error),
             WebSocketApiError::MissingContract { key } => format!("Missing contract {key}"),
+            WebSocketApiError::Storage(error) => format!("Client storage error: {error}"),
         }
     }
 }
@@ -76,6 +95,7 @@ impl IntoResponse for WebSocketApiError {
             WebSocketApiError::AxumError { error } => {
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{error}"))
             }
+            err @ WebSocketApiError::Storage(_) => (err.status_code(), err.error_message()),
         };
 
         let body = Html(error_message);
@@ -1,20 +1,30 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
+use axum::body::Bytes;
 use axum::extract::Path;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::get;
+use axum::Json;
 use axum::{Extension, Router};
-use freenet_stdlib::client_api::{ClientError, ErrorKind, HostResponse};
-use freenet_stdlib::prelude::ContractInstanceId;
+use freenet_stdlib::client_api::{
+    ClientError, ClientRequest, ContractRequest, ErrorKind, HostResponse,
+};
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use tokio::sync::mpsc;
 
-use crate::client_events::{ClientEventsProxy, ClientId, OpenRequest};
+use crate::client_events::{notification_channel, ClientEventsProxy, ClientId, OpenRequest};
 use crate::server::HostCallbackResult;
 
-use super::{errors::WebSocketApiError, path_handlers, AuthToken, ClientConnection};
+use super::{
+    client_storage::{ClientStorage, Scope, StorageExport, IDENTITY_HEADER},
+    errors::WebSocketApiError,
+    path_handlers, AuthToken, ClientConnection,
+};
 
 mod v1;
 
@@ -43,8 +53,11 @@ pub(crate) struct HttpGateway {
 
 impl HttpGateway {
     /// Returns the uninitialized axum router to compose with other routing handling or websockets.
-    pub fn as_router(socket: &SocketAddr) -> (Self, Router) {
-        Self::as_router_v1(socket)
+    pub fn as_router(
+        socket: &SocketAddr,
+        client_storage_dir: PathBuf,
+    ) -> std::io::Result<(Self, Router)> {
+        Self::as_router_v1(socket, client_storage_dir)
     }
 }
 
@@ -81,7 +94,39 @@ impl ClientEventsProxy for HttpGateway {
                         client_id,
                         req,
                         auth_token,
-                    } => return Ok(OpenRequest::new(client_id, req).with_token(auth_token)),
+                    } => {
+                        let attested_contract = auth_token
+                            .as_ref()
+                            .and_then(|token| self.attested_contracts.get(token))
+                            .map(|(contract, _)| *contract);
+                        if let ClientRequest::Disconnect { .. } = &*req {
+                            // fixme: token must live for a bit to allow reconnections
+                            self.attested_contracts
+                                .retain(|_, (_, id)| *id != client_id);
+                        }
+                        let mut open_req = OpenRequest::new(client_id, req)
+                            .with_token(auth_token)
+                            .with_attested_contract(attested_contract);
+                        if let ClientRequest::ContractOp(ContractRequest::Subscribe {
+                            key, ..
+                        }) = &*open_req.request
+                        {
+                            // subscriptions require a channel to send the notifications through
+                            let Some(ch) = self.response_channels.get(&client_id) else {
+                                tracing::warn!("client: {client_id} not found");
+                                return Err(ErrorKind::UnknownClient(client_id.into()).into());
+                            };
+                            let (tx, rx) = notification_channel();
+                            ch.send(HostCallbackResult::SubscriptionChannel {
+                                key: *key,
+                                id: client_id,
+                                callback: rx,
+                            })
+                            .map_err(|_| ErrorKind::ChannelClosed)?;
+                            open_req = open_req.with_notification(tx);
+                        }
+                        return Ok(open_req);
+                    }
                 }
             }
             tracing::warn!("Shutting down http gateway receiver");
@@ -1,8 +1,17 @@
+use axum::extract::Query;
+
 use super::*;
+use crate::{
+    server::registry::{RegistryEntry, RegistryIndexes},
+    util::contract_key::CheckedKey,
+};
 
 impl HttpGateway {
     /// Returns the uninitialized axum router to compose with other routing handling or websockets.
-    pub fn as_router_v1(socket: &SocketAddr) -> (Self, Router) {
+    pub fn as_router_v1(
+        socket: &SocketAddr,
+        client_storage_dir: PathBuf,
+    ) -> std::io::Result<(Self, Router)> {
         let localhost = match socket.ip() {
             IpAddr::V4(ip) if ip.is_loopback() => true,
             IpAddr::V6(ip) if ip.is_loopback() => true,
@@ -13,6 +22,8 @@ impl HttpGateway {
 
         let (proxy_request_sender, request_to_server) = mpsc::channel(1);
 
+        let client_storage = ClientStorage::new(client_storage_dir)?;
+
         let config = Config { localhost };
 
         let router = Router::new()
@@ -20,16 +31,24 @@ impl HttpGateway {
             .route("/v1/contract/web/:key/", get(web_home))
             .with_state(config)
             .route("/v1/contract/web/:key/*path", get(web_subpages))
+            .route("/v1/registry/:key/search", get(registry_search))
+            .route("/v1/storage/:key", get(storage_export).put(storage_import))
+            .route(
+                "/v1/storage/:key/:entry",
+                get(storage_get).put(storage_set).delete(storage_remove),
+            )
+            .layer(Extension(client_storage))
+            .layer(Extension(RegistryIndexes::default()))
             .layer(Extension(HttpGatewayRequest(proxy_request_sender)));
 
-        (
+        Ok((
             Self {
                 proxy_server_request: request_to_server,
                 attested_contracts: HashMap::new(),
                 response_channels: HashMap::new(),
             },
             router,
-        )
+        ))
     }
 }
 
@@ -77,3 +96,89 @@ async fn web_subpages(
         .map_err(|e| *e)
         .map(|r| r.into_response())
 }
+
+/// Default max number of results of a registry search.
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+#[derive(serde::Deserialize)]
+struct SearchParams {
+    term: String,
+    limit: Option<usize>,
+}
+
+async fn registry_search(
+    Path(key): Path<String>,
+    Query(params): Query<SearchParams>,
+    Extension(rs): Extension<HttpGatewayRequest>,
+    Extension(indexes): Extension<RegistryIndexes>,
+) -> Result<Json<Vec<RegistryEntry>>, WebSocketApiError> {
+    let mut results = path_handlers::registry_search(key, &params.term, rs, indexes).await?;
+    results.truncate(params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT));
+    Ok(Json(results))
+}
+
+/// Storage scope of the requesting client, identified by the identity header.
+fn storage_scope(key: String, headers: &HeaderMap) -> Result<Scope, WebSocketApiError> {
+    let key = key
+        .parse::<CheckedKey>()
+        .map(ContractKey::from)
+        .map_err(|err| WebSocketApiError::InvalidParam {
+            error_cause: format!("{err}"),
+        })?;
+    let identity = headers
+        .get(IDENTITY_HEADER)
+        .ok_or_else(|| WebSocketApiError::InvalidParam {
+            error_cause: format!("missing {IDENTITY_HEADER} header"),
+        })?;
+    Ok(Scope::new(identity.as_bytes(), key.id())?)
+}
+
+async fn storage_get(
+    Path((key, entry)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(storage): Extension<ClientStorage>,
+) -> Result<Vec<u8>, WebSocketApiError> {
+    let scope = storage_scope(key, &headers)?;
+    Ok(storage.get(&scope, &entry).await?)
+}
+
+async fn storage_set(
+    Path((key, entry)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(storage): Extension<ClientStorage>,
+    value: Bytes,
+) -> Result<StatusCode, WebSocketApiError> {
+    let scope = storage_scope(key, &headers)?;
+    storage.set(&scope, entry, value.to_vec()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn storage_remove(
+    Path((key, entry)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(storage): Extension<ClientStorage>,
+) -> Result<StatusCode, WebSocketApiError> {
+    let scope = storage_scope(key, &headers)?;
+    storage.remove(&scope, &entry).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn storage_export(
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    Extension(storage): Extension<ClientStorage>,
+) -> Result<Json<StorageExport>, WebSocketApiError> {
+    let scope = storage_scope(key, &headers)?;
+    Ok(Json(storage.export(&scope).await?))
+}
+
+async fn storage_import(
+    Path(key): Path<String>,
+    headers: HeaderMap,
+    Extension(storage): Extension<ClientStorage>,
+    Json(entries): Json<StorageExport>,
+) -> Result<StatusCode, WebSocketApiError> {
+    let scope = storage_scope(key, &headers)?;
+    storage.import(&scope, entries).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
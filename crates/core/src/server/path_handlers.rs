@@ -2,19 +2,30 @@
 
 use std::path::{Path, PathBuf};
 
-use axum::response::{Html, IntoResponse};
+use axum::{
+    http::{header, HeaderValue},
+    response::{Html, IntoResponse, Response},
+};
 use freenet_stdlib::{
     client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse},
     prelude::*,
 };
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use tokio::{fs::File, io::AsyncReadExt, sync::mpsc};
 
-use crate::client_events::AuthToken;
+use crate::{
+    client_events::{AuthToken, ClientId, ClientNotification, NotificationReceiver},
+    config::GlobalExecutor,
+    util::{contract_key::CheckedKey, lru::LruCache},
+    wasm_runtime::declares_immutable,
+};
 
 use super::{
     app_packaging::{WebApp, WebContractError},
     errors::WebSocketApiError,
     http_gateway::HttpGatewayRequest,
+    registry::{RegistryEntry, RegistryIndex, RegistryIndexes},
     ClientConnection, HostCallbackResult,
 };
 
@@ -22,12 +33,24 @@ mod v1;
 
 const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
 
+/// Cache policy of the content of immutable contracts, which never changes.
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Max number of web apps served from immutable contracts which are remembered as such.
+const MAX_IMMUTABLE_WEBS: usize = 1024;
+
+/// Most recently served web apps from immutable contracts.
+static IMMUTABLE_WEBS: Lazy<Mutex<LruCache<ContractInstanceId, ()>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(MAX_IMMUTABLE_WEBS)));
+
 pub(super) async fn contract_home(
     key: String,
     request_sender: HttpGatewayRequest,
     assigned_token: AuthToken,
 ) -> Result<impl IntoResponse, WebSocketApiError> {
-    let key = ContractKey::from_id(key)
+    let key = key
+        .parse::<CheckedKey>()
+        .map(ContractKey::from)
         .map_err(|err| WebSocketApiError::InvalidParam {
             error_cause: format!("{err}"),
         })
@@ -79,8 +102,17 @@ pub(super) async fn contract_home(
         }) => match contract {
             Some(contract) => {
                 let key = contract.key();
+                let immutable = match &contract {
+                    ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract)) => {
+                        declares_immutable(contract.code().data())
+                    }
+                    _ => false,
+                };
+                if immutable {
+                    IMMUTABLE_WEBS.lock().insert(*key.id(), (), 1);
+                }
                 let path = contract_web_path(&key);
-                let web_body = match get_web_body(&path).await {
+                let mut web_body = match get_web_body(&path).await {
                     Ok(b) => b.into_response(),
                     Err(err) => match err {
                         WebSocketApiError::NodeError {
@@ -120,6 +152,9 @@ pub(super) async fn contract_home(
                         }
                     },
                 };
+                if immutable {
+                    set_immutable_cache_control(&mut web_body);
+                }
                 web_body
             }
             None => {
@@ -155,14 +190,222 @@ pub(super) async fn contract_home(
     Ok(response)
 }
 
+/// Searches the packages published in the given metadata registry contract. The first search
+/// indexes the state of the registry as known by the node and subscribes to it, so the index
+/// is kept up to date with its changes from then on.
+pub(super) async fn registry_search(
+    key: String,
+    term: &str,
+    request_sender: HttpGatewayRequest,
+    indexes: RegistryIndexes,
+) -> Result<Vec<RegistryEntry>, WebSocketApiError> {
+    let registry = key.clone();
+    let key = key
+        .parse::<CheckedKey>()
+        .map(ContractKey::from)
+        .map_err(|err| WebSocketApiError::InvalidParam {
+            error_cause: format!("{err}"),
+        })?;
+    if let Some(results) = indexes.search(&key, term) {
+        return Ok(results);
+    }
+
+    let (response_sender, mut response_recv) = mpsc::unbounded_channel();
+    request_sender
+        .send(ClientConnection::NewConnection {
+            callbacks: response_sender,
+            assigned_token: None,
+        })
+        .await
+        .map_err(|err| WebSocketApiError::NodeError {
+            error_cause: format!("{err}"),
+        })?;
+    let Some(HostCallbackResult::NewId { id: client_id }) = response_recv.recv().await else {
+        return Err(WebSocketApiError::NodeError {
+            error_cause: "Couldn't register new client in the node".into(),
+        });
+    };
+    let indexed = index_registry(
+        client_id,
+        registry,
+        key,
+        &request_sender,
+        &mut response_recv,
+    )
+    .await;
+    match indexed {
+        Ok((index, notifications)) => {
+            let results = index.search(term).into_iter().cloned().collect();
+            if indexes.insert(key, index) {
+                GlobalExecutor::spawn(keep_registry_indexed(
+                    client_id,
+                    key,
+                    notifications,
+                    request_sender,
+                    indexes,
+                ));
+            } else {
+                // indexed meanwhile by a concurrent search
+                disconnect(client_id, &request_sender).await;
+            }
+            Ok(results)
+        }
+        Err(err) => {
+            disconnect(client_id, &request_sender).await;
+            Err(err)
+        }
+    }
+}
+
+/// Indexes the current state of the registry contract and subscribes to its changes.
+async fn index_registry(
+    client_id: ClientId,
+    registry: String,
+    key: ContractKey,
+    request_sender: &HttpGatewayRequest,
+    response_recv: &mut mpsc::UnboundedReceiver<HostCallbackResult>,
+) -> Result<(RegistryIndex, NotificationReceiver), WebSocketApiError> {
+    let state = match request(
+        client_id,
+        ContractRequest::Get {
+            key,
+            return_contract_code: false,
+        },
+        request_sender,
+        response_recv,
+    )
+    .await?
+    {
+        HostResponse::ContractResponse(ContractResponse::GetResponse { state, .. }) => state,
+        _ => {
+            return Err(WebSocketApiError::NodeError {
+                error_cause: format!("Registry contract not found: {key}"),
+            })
+        }
+    };
+    let index = RegistryIndex::from_state(registry, state.as_ref()).map_err(|err| {
+        WebSocketApiError::InvalidParam {
+            error_cause: format!("{err}"),
+        }
+    })?;
+
+    let subscribe = ContractRequest::Subscribe { key, summary: None };
+    request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(subscribe.into()),
+            auth_token: None,
+        })
+        .await
+        .map_err(|err| WebSocketApiError::NodeError {
+            error_cause: format!("{err}"),
+        })?;
+    let Some(HostCallbackResult::SubscriptionChannel { callback, .. }) = response_recv.recv().await
+    else {
+        return Err(WebSocketApiError::NodeError {
+            error_cause: format!("Couldn't subscribe to the registry contract {key}"),
+        });
+    };
+    match response(response_recv).await? {
+        HostResponse::ContractResponse(ContractResponse::SubscribeResponse {
+            subscribed: true,
+            ..
+        }) => Ok((index, callback)),
+        _ => Err(WebSocketApiError::NodeError {
+            error_cause: format!("Couldn't subscribe to the registry contract {key}"),
+        }),
+    }
+}
+
+/// Applies the changes of the registry contract to its index for as long as the subscription
+/// lasts, dropping the index then.
+async fn keep_registry_indexed(
+    client_id: ClientId,
+    key: ContractKey,
+    mut notifications: NotificationReceiver,
+    request_sender: HttpGatewayRequest,
+    indexes: RegistryIndexes,
+) {
+    while let Some(notification) = notifications.recv().await {
+        match notification {
+            ClientNotification::Result(Ok(HostResponse::ContractResponse(
+                ContractResponse::UpdateNotification { update, .. },
+            ))) => {
+                if let Err(err) = indexes.apply(&key, &update) {
+                    tracing::warn!(registry = %key, "Dropping registry index: {err}");
+                    break;
+                }
+            }
+            ClientNotification::Result(Err(err)) => {
+                tracing::warn!(registry = %key, "Registry subscription failed: {err}");
+                break;
+            }
+            _ => {}
+        }
+    }
+    indexes.remove(&key);
+    disconnect(client_id, &request_sender).await;
+}
+
+async fn request(
+    client_id: ClientId,
+    req: ContractRequest<'static>,
+    request_sender: &HttpGatewayRequest,
+    response_recv: &mut mpsc::UnboundedReceiver<HostCallbackResult>,
+) -> Result<HostResponse, WebSocketApiError> {
+    request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(req.into()),
+            auth_token: None,
+        })
+        .await
+        .map_err(|err| WebSocketApiError::NodeError {
+            error_cause: format!("{err}"),
+        })?;
+    response(response_recv).await
+}
+
+async fn response(
+    response_recv: &mut mpsc::UnboundedReceiver<HostCallbackResult>,
+) -> Result<HostResponse, WebSocketApiError> {
+    match response_recv.recv().await {
+        Some(HostCallbackResult::Result {
+            result: Ok(res), ..
+        }) => Ok(res),
+        Some(HostCallbackResult::Result {
+            result: Err(err), ..
+        }) => Err(WebSocketApiError::AxumError {
+            error: err.kind().clone(),
+        }),
+        _ => Err(WebSocketApiError::NodeError {
+            error_cause: "Unexpected response from the node".into(),
+        }),
+    }
+}
+
+/// Disconnects the client from the node, which may be gone already.
+async fn disconnect(client_id: ClientId, request_sender: &HttpGatewayRequest) {
+    let _ = request_sender
+        .send(ClientConnection::Request {
+            client_id,
+            req: Box::new(ClientRequest::Disconnect { cause: None }),
+            auth_token: None,
+        })
+        .await;
+}
+
 pub(super) async fn variable_content(
     key: String,
     req_path: String,
 ) -> Result<impl IntoResponse, Box<WebSocketApiError>> {
     // compose the correct absolute path
-    let key = ContractKey::from_id(key).map_err(|err| WebSocketApiError::InvalidParam {
-        error_cause: format!("{err}"),
-    })?;
+    let key = key
+        .parse::<CheckedKey>()
+        .map(ContractKey::from)
+        .map_err(|err| WebSocketApiError::InvalidParam {
+            error_cause: format!("{err}"),
+        })?;
     let base_path = contract_web_path(&key);
     let req_uri = req_path
         .parse()
@@ -183,7 +426,20 @@ pub(super) async fn variable_content(
             }
             .into()
         })
-        .map(|r| r.into_response())
+        .map(|r| {
+            let mut response = r.into_response();
+            if IMMUTABLE_WEBS.lock().contains(key.id()) {
+                set_immutable_cache_control(&mut response);
+            }
+            response
+        })
+}
+
+fn set_immutable_cache_control(response: &mut Response) {
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(IMMUTABLE_CACHE_CONTROL),
+    );
 }
 
 async fn get_web_body(path: &Path) -> Result<impl IntoResponse, WebSocketApiError> {
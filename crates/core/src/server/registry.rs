@@ -0,0 +1,242 @@
+//! Index of the apps, contracts and delegates published in a metadata registry contract.
+
+use std::{collections::HashMap, fs::File, path::Path, sync::Arc};
+
+use freenet_stdlib::prelude::{ContractKey, UpdateData};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+/// A package published in the metadata registry contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RegistryEntry {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub publisher: String,
+    /// Base58 encoded hash of the contract or delegate code.
+    pub code_hash: String,
+    /// Base58 encoded key of the contract or delegate instance.
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct RegistryIndex {
+    registry: String,
+    entries: Vec<RegistryEntry>,
+}
+
+impl RegistryIndex {
+    /// Builds the index from the state of the registry contract, which is expected
+    /// to be a JSON encoded list of entries.
+    pub fn from_state(registry: String, state: &[u8]) -> anyhow::Result<Self> {
+        let entries: Vec<RegistryEntry> = serde_json::from_slice(state)
+            .map_err(|e| anyhow::anyhow!("invalid registry contract state: {e}"))?;
+        Ok(Self { registry, entries })
+    }
+
+    /// Applies an update of the registry contract state to the index. A new state replaces
+    /// all the entries, while a delta, a JSON encoded list of entries too, adds the entries
+    /// published since and replaces the ones republished under the same key.
+    pub fn apply(&mut self, update: &UpdateData<'_>) -> anyhow::Result<()> {
+        let (replace, changed) = match update {
+            UpdateData::State(state) | UpdateData::StateAndDelta { state, .. } => {
+                (true, state.as_ref())
+            }
+            UpdateData::Delta(delta) => (false, delta.as_ref()),
+            _ => return Ok(()),
+        };
+        let changed: Vec<RegistryEntry> = serde_json::from_slice(changed)
+            .map_err(|e| anyhow::anyhow!("invalid registry contract update: {e}"))?;
+        if replace {
+            self.entries = changed;
+            return Ok(());
+        }
+        for entry in changed {
+            match self.entries.iter_mut().find(|e| e.key == entry.key) {
+                Some(existing) => *existing = entry,
+                None => self.entries.push(entry),
+            }
+        }
+        Ok(())
+    }
+
+    /// The registry contract the index was built from.
+    pub fn registry(&self) -> &str {
+        &self.registry
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let index = serde_json::from_reader(File::open(path)?)?;
+        Ok(Some(index))
+    }
+
+    /// Writes the index to the given file, creating its directory if missing.
+    pub fn store(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        serde_json::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Returns the entries matching the term, sorted by relevance.
+    pub fn search(&self, term: &str) -> Vec<&RegistryEntry> {
+        let term = term.trim().to_lowercase();
+        if term.is_empty() {
+            return vec![];
+        }
+        let mut results: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let score =
+                    if entry.key.to_lowercase() == term || entry.code_hash.to_lowercase() == term {
+                        4
+                    } else if entry.name.to_lowercase() == term {
+                        3
+                    } else if entry.name.to_lowercase().contains(&term) {
+                        2
+                    } else if entry.description.to_lowercase().contains(&term)
+                        || entry.publisher.to_lowercase().contains(&term)
+                    {
+                        1
+                    } else {
+                        return None;
+                    };
+                Some((score, entry))
+            })
+            .collect();
+        results.sort_by(|(a_score, a), (b_score, b)| {
+            b_score.cmp(a_score).then_with(|| a.name.cmp(&b.name))
+        });
+        results.into_iter().map(|(_, entry)| entry).collect()
+    }
+}
+
+/// Indexes of the registry contracts searched through the node, kept up to date with the
+/// changes of their state.
+#[derive(Clone, Default)]
+pub(crate) struct RegistryIndexes(Arc<RwLock<HashMap<ContractKey, RegistryIndex>>>);
+
+impl RegistryIndexes {
+    /// Searches the index of the registry, `None` if it is not indexed.
+    pub fn search(&self, registry: &ContractKey, term: &str) -> Option<Vec<RegistryEntry>> {
+        let indexes = self.0.read();
+        let index = indexes.get(registry)?;
+        Some(index.search(term).into_iter().cloned().collect())
+    }
+
+    /// Keeps the index of the registry, returning false if it was already indexed.
+    pub fn insert(&self, registry: ContractKey, index: RegistryIndex) -> bool {
+        let mut indexes = self.0.write();
+        if indexes.contains_key(&registry) {
+            return false;
+        }
+        indexes.insert(registry, index);
+        true
+    }
+
+    pub fn apply(&self, registry: &ContractKey, update: &UpdateData<'_>) -> anyhow::Result<()> {
+        match self.0.write().get_mut(registry) {
+            Some(index) => index.apply(update),
+            None => Ok(()),
+        }
+    }
+
+    pub fn remove(&self, registry: &ContractKey) {
+        self.0.write().remove(registry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, description: &str, code_hash: &str) -> RegistryEntry {
+        RegistryEntry {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            publisher: "freenet".to_owned(),
+            code_hash: code_hash.to_owned(),
+            key: format!("{code_hash}-key"),
+        }
+    }
+
+    #[test]
+    fn search_by_relevance() -> anyhow::Result<()> {
+        let entries = vec![
+            entry("chat-room", "a simple chat", "hash1"),
+            entry("chat", "messaging app", "hash2"),
+            entry(
+                "microblogging",
+                "share short posts, chat with followers",
+                "hash3",
+            ),
+            entry("inbox", "private messages", "hash4"),
+        ];
+        let index =
+            RegistryIndex::from_state("registry".to_owned(), &serde_json::to_vec(&entries)?)?;
+
+        let results: Vec<_> = index.search("Chat").into_iter().map(|e| &e.name).collect();
+        assert_eq!(results, ["chat", "chat-room", "microblogging"]);
+
+        let results: Vec<_> = index.search("hash4").into_iter().map(|e| &e.name).collect();
+        assert_eq!(results, ["inbox"]);
+
+        assert!(index.search("  ").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn updated_incrementally() -> anyhow::Result<()> {
+        let entries = vec![
+            entry("chat", "messaging app", "hash1"),
+            entry("inbox", "private messages", "hash2"),
+        ];
+        let mut index =
+            RegistryIndex::from_state("registry".to_owned(), &serde_json::to_vec(&entries)?)?;
+
+        // a delta adds the new entries and replaces the republished ones
+        let delta = vec![
+            entry("chat", "group messaging app", "hash1"),
+            entry("blog", "share posts", "hash3"),
+        ];
+        index.apply(&UpdateData::Delta(serde_json::to_vec(&delta)?.into()))?;
+        let results: Vec<_> = index
+            .search("messag")
+            .into_iter()
+            .map(|e| &e.description)
+            .collect();
+        assert_eq!(results, ["group messaging app", "private messages"]);
+        assert_eq!(index.search("blog"), [&delta[1]]);
+
+        // a new state replaces all the entries
+        let state = vec![entry("blog", "share posts", "hash3")];
+        index.apply(&UpdateData::State(serde_json::to_vec(&state)?.into()))?;
+        assert!(index.search("chat").is_empty());
+        assert_eq!(index.search("blog"), [&state[0]]);
+
+        assert!(index
+            .apply(&UpdateData::Delta(b"nope".to_vec().into()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stored_in_missing_dir() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("fdev").join("registry_index.json");
+        let entries = vec![entry("chat", "messaging app", "hash1")];
+        let index =
+            RegistryIndex::from_state("registry".to_owned(), &serde_json::to_vec(&entries)?)?;
+        index.store(&path)?;
+
+        let loaded = RegistryIndex::load(&path)?.expect("stored");
+        assert_eq!(loaded.registry(), "registry");
+        assert_eq!(loaded.search("chat"), [&entries[0]]);
+        Ok(())
+    }
+}
@@ -1,3 +1,12 @@
+use std::sync::Arc;
+
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::{
+    message::NetMessage,
+    node::{ConnectionError, NetworkBridge, NodeConfig, OpManager, PeerId},
+};
+
 pub fn with_tracing<T>(f: impl FnOnce() -> T) -> T {
     let subscriber = tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
@@ -7,3 +16,39 @@ pub fn with_tracing<T>(f: impl FnOnce() -> T) -> T {
         .finish();
     tracing::subscriber::with_default(subscriber, f)
 }
+
+/// Default configuration of a node, whose data is kept apart from the one of other tests
+/// by naming the node after the test.
+pub(crate) async fn node_config(id: &str) -> anyhow::Result<NodeConfig> {
+    let mut config_args = crate::config::ConfigArgs::default();
+    config_args.id = Some(id.to_string());
+    NodeConfig::new(config_args.build().await?).await
+}
+
+/// Operation manager of a node with the default configuration which is not part of any
+/// network. See [`node_config`].
+pub(crate) async fn op_manager(id: &str) -> anyhow::Result<OpManager> {
+    OpManager::in_memory(&node_config(id).await?).await
+}
+
+/// Network bridge keeping the messages sent through it.
+#[derive(Clone, Default)]
+pub(crate) struct RecordingBridge(Arc<Mutex<Vec<(PeerId, NetMessage)>>>);
+
+impl RecordingBridge {
+    /// The messages sent so far, with the peer they were sent to.
+    pub fn sent(&self) -> MutexGuard<'_, Vec<(PeerId, NetMessage)>> {
+        self.0.lock()
+    }
+}
+
+impl NetworkBridge for RecordingBridge {
+    async fn send(&self, target: &PeerId, msg: NetMessage) -> Result<(), ConnectionError> {
+        self.0.lock().push((target.clone(), msg));
+        Ok(())
+    }
+
+    async fn drop_connection(&mut self, _: &PeerId) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+}
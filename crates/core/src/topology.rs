@@ -18,6 +18,7 @@ pub(crate) mod outbound_request_counter;
 pub(crate) mod rate;
 pub mod request_density_tracker;
 pub(crate) mod running_average;
+mod small_world;
 mod small_world_rand;
 
 use crate::ring::{Connection, PeerKeyLocation};
@@ -165,6 +166,11 @@ impl TopologyManager {
         Ok(best_location)
     }
 
+    pub(crate) fn set_connection_limits(&mut self, min_connections: usize, max_connections: usize) {
+        self.limits.min_connections = min_connections;
+        self.limits.max_connections = max_connections;
+    }
+
     #[cfg(test)]
     pub(self) fn update_limits(&mut self, limits: Limits) {
         self.limits = limits;
@@ -276,6 +282,12 @@ impl TopologyManager {
         self.meter.attributed_usage_rate(source, resource_type, now)
     }
 
+    /// Whether the usage of any resource is above the maximum desired proportion of its limit.
+    pub(crate) fn is_over_budget(&mut self, at_time: Instant) -> bool {
+        let (_, usage) = self.calculate_usage_proportion(at_time);
+        usage > RateProportion::new(MAXIMUM_DESIRED_RESOURCE_USAGE_PROPORTION)
+    }
+
     // A function that will determine if any peers should be added or removed based on
     // the current resource usage, and either add or remove them
     pub(crate) fn adjust_topology(
@@ -403,6 +415,17 @@ impl TopologyManager {
         adjustment.unwrap_or(TopologyAdjustment::NoChange)
     }
 
+    /// Proposes an adjustment moving the distribution of connections closer to the ideal
+    /// small-world one, only dropping connections among the `removable` ones.
+    pub(crate) fn adjust_small_world(
+        &self,
+        connections: &BTreeMap<Location, Vec<Connection>>,
+        removable: &BTreeMap<Location, Vec<Connection>>,
+        my_location: Location,
+    ) -> TopologyAdjustment {
+        small_world::adjust(my_location, connections, removable, &self.limits)
+    }
+
     fn calculate_usage_proportion(&mut self, at_time: Instant) -> (ResourceType, RateProportion) {
         let mut usage_rate_per_type = HashMap::new();
         for resource_type in ResourceType::all() {
@@ -0,0 +1,192 @@
+//! Evaluation of the distribution of connections against the ideal small-world one, where the
+//! probability of linking to a peer at distance `d` is proportional to `1/d`.
+//!
+//! Under that distribution every band of a set of logarithmically sized distance bands is
+//! expected to hold the same share of the connections, so each band is compared against its
+//! fair share: overcrowded close bands have redundant links dropped, and under-represented
+//! bands get new connections requested towards them.
+
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+use super::{Limits, TopologyAdjustment};
+use crate::ring::{Connection, Distance, Location, PeerKeyLocation};
+
+/// Links closer than this are accounted in the closest band.
+const MIN_DISTANCE: f64 = 1.0 / 1024.0;
+const MAX_DISTANCE: f64 = 0.5;
+const NUM_BANDS: usize = 6;
+
+/// A band is overcrowded when holding more than this many times its fair share.
+const OVERCROWDED_FACTOR: f64 = 2.0;
+/// A band is under-represented when holding less than this proportion of its fair share.
+const UNDER_REPRESENTED_FACTOR: f64 = 0.5;
+
+fn band_of(distance: Distance) -> usize {
+    let d = distance.as_f64();
+    if d < MIN_DISTANCE {
+        return 0;
+    }
+    let band = NUM_BANDS as f64 * (d / MIN_DISTANCE).ln() / (MAX_DISTANCE / MIN_DISTANCE).ln();
+    (band as usize).min(NUM_BANDS - 1)
+}
+
+/// Lower and upper distance bounds of a band.
+fn band_bounds(band: usize) -> (f64, f64) {
+    let edge =
+        |i: usize| MIN_DISTANCE * (MAX_DISTANCE / MIN_DISTANCE).powf(i as f64 / NUM_BANDS as f64);
+    let lower = if band == 0 { MIN_DISTANCE } else { edge(band) };
+    (lower, edge(band + 1))
+}
+
+/// A random location within the given band from this peer, following the `1/d` distribution.
+fn random_location_in_band(own: Location, band: usize) -> Location {
+    let (lower, upper) = band_bounds(band);
+    let mut rng = rand::thread_rng();
+    let distance = lower * (upper / lower).powf(rng.gen_range(0.0..1.0));
+    let sign = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+    Location::new_rounded(own.as_f64() + sign * distance)
+}
+
+/// Proposes a single adjustment moving the connections closer to the small-world distribution.
+///
+/// All the connections are accounted for, but only the `removable` ones are considered for
+/// dropping.
+pub(super) fn adjust(
+    own: Location,
+    connections: &BTreeMap<Location, Vec<Connection>>,
+    removable: &BTreeMap<Location, Vec<Connection>>,
+    limits: &Limits,
+) -> TopologyAdjustment {
+    let total: usize = connections.values().map(Vec::len).sum();
+    if total == 0 {
+        return TopologyAdjustment::NoChange;
+    }
+    let mut bands = [0usize; NUM_BANDS];
+    for (loc, conns) in connections {
+        bands[band_of(own.distance(loc))] += conns.len();
+    }
+    let fair_share = total as f64 / NUM_BANDS as f64;
+
+    if total > limits.min_connections {
+        let overcrowded = (0..NUM_BANDS / 2)
+            .find(|&band| bands[band] >= 2 && bands[band] as f64 > fair_share * OVERCROWDED_FACTOR);
+        if let Some(band) = overcrowded {
+            if let Some(peer) = most_redundant(own, band, connections, removable) {
+                tracing::debug!(%peer, band, "Dropping redundant close connection");
+                return TopologyAdjustment::RemoveConnections(vec![peer]);
+            }
+        }
+    }
+
+    if total < limits.max_connections {
+        let under_represented = (0..NUM_BANDS)
+            .filter(|&band| (bands[band] as f64) < fair_share * UNDER_REPRESENTED_FACTOR)
+            .min_by_key(|&band| bands[band]);
+        if let Some(band) = under_represented {
+            let target = random_location_in_band(own, band);
+            tracing::debug!(%target, band, "Requesting connection for under-represented band");
+            return TopologyAdjustment::AddConnections(vec![target]);
+        }
+    }
+    TopologyAdjustment::NoChange
+}
+
+/// The removable connection within the band which is the closest to some other neighbour,
+/// and so contributes the least to the coverage of the ring.
+fn most_redundant(
+    own: Location,
+    band: usize,
+    connections: &BTreeMap<Location, Vec<Connection>>,
+    removable: &BTreeMap<Location, Vec<Connection>>,
+) -> Option<PeerKeyLocation> {
+    removable
+        .iter()
+        .filter(|(loc, _)| band_of(own.distance(*loc)) == band)
+        .filter_map(|(loc, conns)| {
+            let closest_neighbour = connections
+                .keys()
+                .filter(|other| *other != loc)
+                .map(|other| loc.distance(other))
+                .min()?;
+            Some((closest_neighbour, conns.first()?.get_location().clone()))
+        })
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, peer)| peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node::PeerId, topology::rate::Rate};
+
+    fn limits() -> Limits {
+        Limits {
+            max_upstream_bandwidth: Rate::new_per_second(100000.0),
+            max_downstream_bandwidth: Rate::new_per_second(100000.0),
+            max_connections: 20,
+            min_connections: 5,
+        }
+    }
+
+    fn connections(own: f64, distances: &[f64]) -> BTreeMap<Location, Vec<Connection>> {
+        distances
+            .iter()
+            .map(|d| {
+                let loc = Location::new_rounded(own + d);
+                (loc, vec![Connection::new(PeerId::random(), loc)])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drops_close_links_and_fills_far_bands() {
+        let own = Location::new(0.5);
+        // most links are crowded next to this peer
+        let conns = connections(0.5, &[0.0001, 0.0002, 0.0003, 0.0005, 0.0007, 0.001, 0.2]);
+        let TopologyAdjustment::RemoveConnections(removed) = adjust(own, &conns, &conns, &limits())
+        else {
+            panic!("expected a connection to be dropped");
+        };
+        let removed_loc = removed[0].location.unwrap();
+        assert_eq!(band_of(own.distance(removed_loc)), 0);
+
+        // close links which are not removable (e.g. too young) are kept
+        let removable = BTreeMap::new();
+        let TopologyAdjustment::AddConnections(targets) =
+            adjust(own, &conns, &removable, &limits())
+        else {
+            panic!("expected a connection to be requested");
+        };
+        let band = band_of(own.distance(targets[0]));
+        assert!(band > 0 && band < NUM_BANDS - 1, "band {band}");
+
+        // an evenly spread set of links is left alone
+        let spread: Vec<_> = (0..NUM_BANDS)
+            .map(|band| {
+                let (lower, upper) = band_bounds(band);
+                (lower * upper).sqrt()
+            })
+            .collect();
+        let conns = connections(0.5, &spread);
+        assert!(matches!(
+            adjust(own, &conns, &conns, &limits()),
+            TopologyAdjustment::NoChange
+        ));
+    }
+
+    #[test]
+    fn accounts_every_connection_at_a_location() {
+        let own = Location::new(0.5);
+        // fewer locations than the min connections, but more connections
+        let mut conns = connections(0.5, &[0.0001, 0.0002, 0.2]);
+        for (loc, at_loc) in conns.iter_mut().take(2) {
+            at_loc.extend((0..2).map(|_| Connection::new(PeerId::random(), *loc)));
+        }
+        assert!(matches!(
+            adjust(own, &conns, &conns, &limits()),
+            TopologyAdjustment::RemoveConnections(_)
+        ));
+    }
+}
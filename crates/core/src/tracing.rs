@@ -27,6 +27,9 @@ use crate::node::OpManager;
 
 /// An append-only log for network events.
 mod aof;
+mod replay;
+
+pub(crate) use replay::{read_recording, replay_into, replay_messages, RecordedMessage};
 
 #[derive(Debug, Clone, Copy)]
 #[allow(dead_code)]
@@ -41,6 +44,10 @@ pub(crate) trait NetEventRegister: std::any::Any + Send + Sync + 'static {
     fn notify_of_time_out(&mut self, tx: Transaction) -> BoxFuture<()>;
     fn trait_clone(&self) -> Box<dyn NetEventRegister>;
     fn get_router_events(&self, number: usize) -> BoxFuture<anyhow::Result<Vec<RouteEvent>>>;
+    /// Records a message received from a peer, before it is processed.
+    fn register_inbound<'a>(&'a self, _msg: &'a NetMessage) -> BoxFuture<'a, ()> {
+        async {}.boxed()
+    }
 }
 
 #[cfg(feature = "trace-ot")]
@@ -92,6 +99,15 @@ impl<const N: usize> NetEventRegister for CombinedRegister<N> {
         }
         .boxed()
     }
+
+    fn register_inbound<'a>(&'a self, msg: &'a NetMessage) -> BoxFuture<'a, ()> {
+        async move {
+            for registry in &self.0 {
+                registry.register_inbound(msg).await;
+            }
+        }
+        .boxed()
+    }
 }
 
 #[cfg(feature = "trace-ot")]
@@ -385,6 +401,8 @@ impl<'a> From<&'a NetLogMessage> for Option<Vec<opentelemetry::KeyValue>> {
 pub(crate) struct EventRegister {
     log_file: Arc<PathBuf>,
     log_sender: mpsc::Sender<NetLogMessage>,
+    /// Frames of the messages received from peers, if recording them.
+    message_sender: Option<replay::FrameSender>,
 }
 
 /// Records from a new session must have higher than this ts.
@@ -401,9 +419,16 @@ impl EventRegister {
         Self {
             log_sender,
             log_file,
+            message_sender: None,
         }
     }
 
+    /// Also records the messages received from peers to the file, so they can be replayed.
+    pub fn record_messages(mut self, path: PathBuf) -> Self {
+        self.message_sender = Some(replay::start_recording(path));
+        self
+    }
+
     async fn record_logs(
         mut log_recv: mpsc::Receiver<NetLogMessage>,
         event_log_path: Arc<PathBuf>,
@@ -486,6 +511,15 @@ impl NetEventRegister for EventRegister {
     fn get_router_events(&self, number: usize) -> BoxFuture<anyhow::Result<Vec<RouteEvent>>> {
         async move { aof::LogFile::get_router_events(number, &self.log_file).await }.boxed()
     }
+
+    fn register_inbound<'a>(&'a self, msg: &'a NetMessage) -> BoxFuture<'a, ()> {
+        async {
+            if let Some(message_sender) = &self.message_sender {
+                message_sender.record(msg);
+            }
+        }
+        .boxed()
+    }
 }
 
 async fn connect_to_metrics_server() -> Option<WebSocketStream<MaybeTlsStream<TcpStream>>> {
@@ -657,36 +691,39 @@ mod opentelemetry_tracer {
     };
 
     use super::*;
+    use crate::transport::TransportPublicKey;
 
     struct OTSpan {
         inner: global::BoxedSpan,
         last_log: SystemTime,
     }
 
+    /// Id of the span of the transaction at the given peer. Every peer a transaction goes
+    /// through records its own span, all of them in the trace of the transaction.
+    fn span_id(transaction: &Transaction, peer: &PeerId) -> trace::SpanId {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        transaction.as_bytes().hash(&mut hasher);
+        peer.hash(&mut hasher);
+        // the all zeros span id is invalid
+        trace::SpanId::from_bytes(hasher.finish().max(1).to_be_bytes())
+    }
+
     impl OTSpan {
-        fn new(transaction: Transaction) -> Self {
-            use trace::{Tracer, TracerProvider};
-
-            let tracer = {
-                let tracer_provider = global::tracer_provider();
-                tracer_provider
-                    .tracer_builder("freenet")
-                    .with_version(env!("CARGO_PKG_VERSION"))
-                    .with_schema_url("https://opentelemetry.io/schemas/1.21.0")
-                    .build()
-            };
-            let tx_bytes = transaction.as_bytes();
-            let mut span_id = [0; 8];
-            span_id.copy_from_slice(&tx_bytes[8..]);
+        fn new(tracer: &global::BoxedTracer, transaction: Transaction, peer: &PeerId) -> Self {
+            use trace::Tracer;
+
             let start_time = transaction.started();
             let inner = tracer.build(trace::SpanBuilder {
                 name: transaction.transaction_type().description().into(),
                 start_time: Some(start_time),
-                span_id: Some(trace::SpanId::from_bytes(span_id)),
-                trace_id: Some(trace::TraceId::from_bytes(tx_bytes)),
+                span_id: Some(span_id(&transaction, peer)),
+                trace_id: Some(trace::TraceId::from_bytes(transaction.as_bytes())),
                 attributes: Some(vec![
                     KeyValue::new("transaction", transaction.to_string()),
                     KeyValue::new("tx_type", transaction.transaction_type().description()),
+                    KeyValue::new("peer", peer.to_string()),
                 ]),
                 ..Default::default()
             });
@@ -759,6 +796,8 @@ mod opentelemetry_tracer {
     pub(crate) struct OTEventRegister {
         log_sender: mpsc::Sender<NetLogMessage>,
         finished_tx_notifier: mpsc::Sender<Transaction>,
+        /// Provider exporting the spans, if not the global one; kept alive with the register.
+        _provider: Option<opentelemetry_sdk::trace::TracerProvider>,
     }
 
     /// For tests running in a single process is importart that span tracking is global across threads and simulated peers.  
@@ -766,21 +805,66 @@ mod opentelemetry_tracer {
         std::sync::OnceLock::new();
 
     impl OTEventRegister {
+        /// Records the spans with the global tracer provider.
         pub fn new() -> Self {
+            let tracer = global::tracer_provider()
+                .tracer_builder("freenet")
+                .with_version(env!("CARGO_PKG_VERSION"))
+                .with_schema_url("https://opentelemetry.io/schemas/1.21.0")
+                .build();
+            Self::with_tracer(tracer, None)
+        }
+
+        /// Exports the spans to the OpenTelemetry collector at the endpoint over OTLP/gRPC.
+        pub fn with_otlp_exporter(
+            endpoint: &str,
+            peer: &TransportPublicKey,
+        ) -> anyhow::Result<Self> {
+            use opentelemetry::trace::TracerProvider as _;
+            use opentelemetry_otlp::WithExportConfig;
+
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint)
+                .build()?;
+            let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+                .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+                .with_resource(opentelemetry_sdk::Resource::new([
+                    KeyValue::new("service.name", "freenet-core"),
+                    KeyValue::new("service.instance.id", peer.to_string()),
+                ]))
+                .build();
+            let tracer = provider
+                .tracer_builder("freenet")
+                .with_version(env!("CARGO_PKG_VERSION"))
+                .with_schema_url("https://opentelemetry.io/schemas/1.21.0")
+                .build();
+            Ok(Self::with_tracer(
+                global::BoxedTracer::new(Box::new(tracer)),
+                Some(provider),
+            ))
+        }
+
+        fn with_tracer(
+            tracer: global::BoxedTracer,
+            provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+        ) -> Self {
             if cfg!(test) {
                 UNIQUE_REGISTER.get_or_init(DashMap::new);
             }
             let (sender, finished_tx_notifier) = mpsc::channel(100);
             let (log_sender, log_recv) = mpsc::channel(1000);
             NEW_RECORDS_TS.get_or_init(SystemTime::now);
-            GlobalExecutor::spawn(Self::record_logs(log_recv, finished_tx_notifier));
+            GlobalExecutor::spawn(Self::record_logs(tracer, log_recv, finished_tx_notifier));
             Self {
                 log_sender,
                 finished_tx_notifier: sender,
+                _provider: provider,
             }
         }
 
         async fn record_logs(
+            tracer: global::BoxedTracer,
             mut log_recv: mpsc::Receiver<NetLogMessage>,
             mut finished_tx_notifier: mpsc::Receiver<Transaction>,
         ) {
@@ -788,7 +872,11 @@ mod opentelemetry_tracer {
             let mut logs = HashMap::new();
 
             #[cfg(not(test))]
-            fn process_log(logs: &mut HashMap<Transaction, OTSpan>, log: NetLogMessage) {
+            fn process_log(
+                tracer: &global::BoxedTracer,
+                logs: &mut HashMap<Transaction, OTSpan>,
+                log: NetLogMessage,
+            ) {
                 let span_completed = log.span_completed();
                 match logs.entry(log.tx) {
                     std::collections::hash_map::Entry::Occupied(mut val) => {
@@ -801,7 +889,7 @@ mod opentelemetry_tracer {
                         }
                     }
                     std::collections::hash_map::Entry::Vacant(empty) => {
-                        let span = empty.insert(OTSpan::new(log.tx));
+                        let span = empty.insert(OTSpan::new(tracer, log.tx, &log.peer_id));
                         // does not make much sense to treat a single isolated event as a span,
                         // so just ignore those in case they were to happen
                         if !span_completed {
@@ -812,7 +900,11 @@ mod opentelemetry_tracer {
             }
 
             #[cfg(test)]
-            fn process_log(logs: &DashMap<Transaction, OTSpan>, log: NetLogMessage) {
+            fn process_log(
+                tracer: &global::BoxedTracer,
+                logs: &DashMap<Transaction, OTSpan>,
+                log: NetLogMessage,
+            ) {
                 let span_completed = log.span_completed();
                 match logs.entry(log.tx) {
                     dashmap::mapref::entry::Entry::Occupied(mut val) => {
@@ -825,7 +917,7 @@ mod opentelemetry_tracer {
                         }
                     }
                     dashmap::mapref::entry::Entry::Vacant(empty) => {
-                        let mut span = empty.insert(OTSpan::new(log.tx));
+                        let mut span = empty.insert(OTSpan::new(tracer, log.tx, &log.peer_id));
                         // does not make much sense to treat a single isolated event as a span,
                         // so just ignore those in case they were to happen
                         if !span_completed {
@@ -851,11 +943,11 @@ mod opentelemetry_tracer {
                         if let Some(log) = log_msg {
                             #[cfg(not(test))]
                             {
-                                process_log(&mut logs, log);
+                                process_log(&tracer, &mut logs, log);
                             }
                             #[cfg(test)]
                             {
-                                process_log(UNIQUE_REGISTER.get().expect("should be set"), log);
+                                process_log(&tracer, UNIQUE_REGISTER.get().expect("should be set"), log);
                             }
                         } else {
                             break;
@@ -909,6 +1001,32 @@ mod opentelemetry_tracer {
         fn get_router_events(&self, _number: usize) -> BoxFuture<anyhow::Result<Vec<RouteEvent>>> {
             async { Ok(vec![]) }.boxed()
         }
+
+        fn register_inbound<'a>(&'a self, msg: &'a NetMessage) -> BoxFuture<'a, ()> {
+            async {
+                if let Some(message_sender) = &self.message_sender {
+                    message_sender.record(msg);
+                }
+            }
+            .boxed()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::operations::get::GetMsg;
+
+        #[test]
+        fn span_per_peer_of_transaction() {
+            let tx = Transaction::new::<GetMsg>();
+            let (peer, other_peer) = (PeerId::random(), PeerId::random());
+            let id = span_id(&tx, &peer);
+            assert_ne!(id, trace::SpanId::INVALID);
+            assert_eq!(id, span_id(&tx, &peer));
+            assert_ne!(id, span_id(&tx, &other_peer));
+            assert_ne!(id, span_id(&Transaction::new::<GetMsg>(), &peer));
+        }
     }
 }
 
@@ -1019,8 +1137,28 @@ enum PutEvent {
 
 #[cfg(feature = "trace")]
 pub(crate) mod tracer {
+    use std::sync::OnceLock;
+
     use tracing::level_filters::LevelFilter;
-    use tracing_subscriber::{Layer, Registry};
+    use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+    /// Handle to change the filter of the logs once the tracer is set.
+    static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+    fn env_filter(default_level: LevelFilter) -> EnvFilter {
+        EnvFilter::builder()
+            .with_default_directive(default_level.into())
+            .from_env_lossy()
+            .add_directive("stretto=off".parse().expect("infallible"))
+            .add_directive("sqlx=error".parse().expect("infallible"))
+    }
+
+    /// Changes the default level of the logs, returns false if the tracer is not set.
+    pub fn set_log_level(level: LevelFilter) -> bool {
+        LOG_FILTER
+            .get()
+            .is_some_and(|handle| handle.reload(env_filter(level)).is_ok())
+    }
 
     pub fn init_tracer(level: Option<LevelFilter>, endpoint: Option<String>) -> anyhow::Result<()> {
         let default_filter = if cfg!(any(test, debug_assertions)) {
@@ -1028,12 +1166,9 @@ pub(crate) mod tracer {
         } else {
             LevelFilter::INFO
         };
-        let default_filter = level.unwrap_or(default_filter);
-        let filter_layer = tracing_subscriber::EnvFilter::builder()
-            .with_default_directive(default_filter.into())
-            .from_env_lossy()
-            .add_directive("stretto=off".parse().expect("infallible"))
-            .add_directive("sqlx=error".parse().expect("infallible"));
+        let (filter_layer, filter_handle) =
+            reload::Layer::new(env_filter(level.unwrap_or(default_filter)));
+        let _ = LOG_FILTER.set(filter_handle);
 
         // use opentelemetry_sdk::propagation::TraceContextPropagator;
         use tracing_subscriber::layer::SubscriberExt;
@@ -1131,6 +1266,8 @@ pub(super) mod test {
         logs: Arc<tokio::sync::Mutex<Vec<NetLogMessage>>>,
         network_metrics_server:
             Arc<tokio::sync::Mutex<Option<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
+        /// Frames of the messages received from peers, if recording them.
+        message_sender: Option<super::replay::FrameSender>,
     }
 
     impl TestEventListener {
@@ -1142,6 +1279,16 @@ pub(super) mod test {
                 network_metrics_server: Arc::new(tokio::sync::Mutex::new(
                     connect_to_metrics_server().await,
                 )),
+                message_sender: None,
+            }
+        }
+
+        /// A listener sharing the logs of this one which also records the messages received
+        /// from peers to the file, so they can be replayed.
+        pub fn record_messages(&self, path: PathBuf) -> Self {
+            Self {
+                message_sender: Some(super::replay::start_recording(path)),
+                ..self.clone()
             }
         }
 
@@ -1258,6 +1405,15 @@ pub(super) mod test {
         fn get_router_events(&self, _number: usize) -> BoxFuture<anyhow::Result<Vec<RouteEvent>>> {
             async { Ok(vec![]) }.boxed()
         }
+
+        fn register_inbound<'a>(&'a self, msg: &'a NetMessage) -> BoxFuture<'a, ()> {
+            async {
+                if let Some(message_sender) = &self.message_sender {
+                    message_sender.record(msg);
+                }
+            }
+            .boxed()
+        }
     }
 
     #[tokio::test]
@@ -1301,4 +1457,27 @@ pub(super) mod test {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn records_messages_received_by_simulated_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages");
+        let listener = TestEventListener::new().await.record_messages(path.clone());
+        let tx = Transaction::new::<connect::ConnectMsg>();
+        listener
+            .register_inbound(&NetMessage::V1(NetMessageV1::Aborted(tx)))
+            .await;
+
+        let recorded = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                match read_recording(&path) {
+                    Ok(messages) if !messages.is_empty() => break messages,
+                    _ => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(recorded.len(), 1);
+    }
 }
@@ -0,0 +1,266 @@
+//! Recording of the messages a node receives from its peers, to feed them back into a node,
+//! simulated or on the network, and reproduce a bug deterministically.
+//!
+//! Messages are appended to the recording as they are received, each one with the time it
+//! arrived at, as a length-prefixed bincode frame. When replayed they are fed to the node in
+//! the same order and with the same spacing as they were received, measured with the clock of
+//! the node, so with a virtual clock the replay does not depend on how fast the host runs.
+//!
+//! The event loop never waits for the recording: while writing it falls behind, the messages
+//! received are left out of it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::{
+    config::GlobalExecutor, message::NetMessage, node::OpManager, util::time_source::SharedClock,
+};
+
+const FRAME_LENGTH: usize = core::mem::size_of::<u32>();
+
+/// A message received by the node, with the time it arrived at.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct RecordedMessage<M = NetMessage> {
+    datetime: DateTime<Utc>,
+    msg: M,
+}
+
+/// Frame of the recording for the message, received now.
+fn encode(msg: &NetMessage) -> bincode::Result<Vec<u8>> {
+    let recorded = RecordedMessage {
+        datetime: Utc::now(),
+        msg,
+    };
+    let serialized = bincode::serialize(&recorded)?;
+    let mut frame = Vec::with_capacity(FRAME_LENGTH + serialized.len());
+    frame.extend((serialized.len() as u32).to_be_bytes());
+    frame.extend(serialized);
+    Ok(frame)
+}
+
+/// Starts appending the messages queued through the returned sender to the recording at the
+/// path.
+pub(super) fn start_recording(path: PathBuf) -> FrameSender {
+    let (frames, queued) = mpsc::channel(1000);
+    GlobalExecutor::spawn(async move { record(&path, queued).await });
+    FrameSender::new(frames)
+}
+
+/// Queue of the frames to append to the recording, which drops them instead of waiting while
+/// the recording falls behind.
+#[derive(Clone)]
+pub(super) struct FrameSender {
+    frames: mpsc::Sender<Vec<u8>>,
+    /// Frames dropped since the last one queued.
+    dropped: Arc<AtomicU64>,
+}
+
+impl FrameSender {
+    pub fn new(frames: mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            frames,
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Queues the message, received now, to be appended to the recording.
+    pub fn record(&self, msg: &NetMessage) {
+        match encode(msg) {
+            Ok(frame) => self.send(frame),
+            Err(error) => tracing::error!(%error, "Failed encoding message to record"),
+        }
+    }
+
+    fn send(&self, frame: Vec<u8>) {
+        match self.frames.try_send(frame) {
+            Ok(()) => {
+                let dropped = self.dropped.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    tracing::warn!(
+                        dropped,
+                        "Messages left out of the recording, it fell behind"
+                    );
+                }
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// Appends the frames to the recording at the path until the sender is dropped.
+async fn record(path: &Path, mut frames: mpsc::Receiver<Vec<u8>>) {
+    let mut file = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+    {
+        Ok(file) => file,
+        Err(error) => {
+            tracing::error!(?path, %error, "Failed opening message recording");
+            return;
+        }
+    };
+    while let Some(frame) = frames.recv().await {
+        // flushed on every message, so the recording survives the node crashing
+        let written = async {
+            file.write_all(&frame).await?;
+            file.flush().await
+        };
+        if let Err(error) = written.await {
+            tracing::error!(?path, %error, "Failed writing message recording, stopping");
+            return;
+        }
+    }
+}
+
+/// Reads the messages of the recording at the path, in the order they were received.
+pub(crate) fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordedMessage>> {
+    let data = std::fs::read(path)?;
+    let mut messages = Vec::new();
+    let mut rest = data.as_slice();
+    while rest.len() >= FRAME_LENGTH {
+        let (length, frame) = rest.split_at(FRAME_LENGTH);
+        let length = u32::from_be_bytes(length.try_into().expect("frame length")) as usize;
+        if frame.len() < length {
+            break;
+        }
+        messages.push(bincode::deserialize(&frame[..length])?);
+        rest = &frame[length..];
+    }
+    if !rest.is_empty() {
+        tracing::warn!(
+            ?path,
+            "Message recording ends with a partial frame, ignoring it"
+        );
+    }
+    Ok(messages)
+}
+
+/// Yields the recorded messages with the same spacing they were received with, starting right
+/// away with the first one.
+pub(crate) fn replay_messages(
+    messages: Vec<RecordedMessage>,
+    clock: SharedClock,
+) -> impl Stream<Item = NetMessage> {
+    let first = messages.first().map(|recorded| recorded.datetime);
+    let start = clock.unix_time();
+    futures::stream::unfold(messages.into_iter(), move |mut messages| {
+        let clock = clock.clone();
+        async move {
+            let recorded = messages.next()?;
+            let offset = first
+                .and_then(|first| (recorded.datetime - first).to_std().ok())
+                .unwrap_or_default();
+            let elapsed = clock.unix_time().saturating_sub(start);
+            clock.sleep(offset.saturating_sub(elapsed)).await;
+            Some((recorded.msg, messages))
+        }
+    })
+}
+
+/// Feeds the recorded messages to the event loop of the node as if received from its peers,
+/// with the same spacing they were received with.
+pub(crate) async fn replay_into(op_manager: Arc<OpManager>, messages: Vec<RecordedMessage>) {
+    let replayed = replay_messages(messages, op_manager.clock.clone());
+    futures::pin_mut!(replayed);
+    while let Some(msg) = replayed.next().await {
+        tracing::debug!(tx = %msg.id(), "Replaying recorded message");
+        if op_manager.notify_message(msg).await.is_err() {
+            return;
+        }
+    }
+    tracing::info!("Finished replaying recorded messages");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::{
+        message::{NetMessageV1, Transaction},
+        operations::connect::ConnectMsg,
+        util::time_source::VirtualClock,
+    };
+
+    #[tokio::test]
+    async fn replay_recorded_messages_with_their_timing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages");
+        let txs: Vec<_> = (0..3).map(|_| Transaction::new::<ConnectMsg>()).collect();
+
+        let (sender, frames) = mpsc::channel(10);
+        for (i, tx) in txs.iter().enumerate() {
+            let mut frame = encode(&NetMessage::V1(NetMessageV1::Aborted(*tx))).unwrap();
+            if i == 2 {
+                // received a second after the first ones
+                let mut recorded: RecordedMessage =
+                    bincode::deserialize(&frame[FRAME_LENGTH..]).unwrap();
+                recorded.datetime += chrono::Duration::seconds(1);
+                let serialized = bincode::serialize(&recorded).unwrap();
+                frame = (serialized.len() as u32).to_be_bytes().to_vec();
+                frame.extend(serialized);
+            }
+            sender.send(frame).await.unwrap();
+        }
+        drop(sender);
+        record(&path, frames).await;
+        // a partial frame, as left by a crash while writing
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, &[0, 0, 1]))
+            .unwrap();
+
+        let messages = read_recording(&path).unwrap();
+        assert_eq!(messages.len(), 3);
+
+        let clock = VirtualClock::new();
+        let replayed = replay_messages(messages, Arc::new(clock.clone()));
+        futures::pin_mut!(replayed);
+        let mut next_tx = || {
+            replayed
+                .next()
+                .now_or_never()
+                .flatten()
+                .map(|msg| *msg.id())
+        };
+        assert_eq!(next_tx(), Some(txs[0]));
+        assert_eq!(next_tx(), Some(txs[1]));
+        assert_eq!(next_tx(), None);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(next_tx(), Some(txs[2]));
+    }
+
+    #[test]
+    fn frames_dropped_while_recording_falls_behind() {
+        let (sender, mut frames) = mpsc::channel(1);
+        let sender = FrameSender::new(sender);
+        for frame in 0..3 {
+            sender.send(vec![frame]);
+        }
+        assert_eq!(sender.dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(frames.try_recv().unwrap(), [0]);
+        assert!(frames.try_recv().is_err());
+
+        sender.send(vec![3]);
+        assert_eq!(sender.dropped.load(Ordering::Relaxed), 0);
+        assert_eq!(frames.try_recv().unwrap(), [3]);
+    }
+}
@@ -35,21 +35,114 @@ use futures::{
     Future,
 };
 use tokio::net::UdpSocket;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task;
 use tracing::span;
 use tracing::Instrument;
 
 use super::{
     crypto::{TransportKeypair, TransportPublicKey},
+    dual_stack::AddressBook,
+    multi_transport::{MultiTransportSocket, TransportKind},
     packet_data::{PacketData, SymmetricAES, MAX_PACKET_SIZE},
     peer_connection::{PeerConnection, RemoteConnection},
+    rate_limiter::BandwidthLimits,
     sent_packet_tracker::SentPacketTracker,
     symmetric_message::{SymmetricMessage, SymmetricMessagePayload},
-    Socket, TransportError,
+    traffic::{CountedSocket, TrafficCounters},
+    NetworkId, Socket, TransportError,
 };
 
-const PROTOC_VERSION: [u8; 2] = 1u16.to_le_bytes();
+/// Version of the wire protocol spoken by this node.
+pub(crate) const PROTOC_VERSION: u16 = 2;
+/// Oldest version of the wire protocol this node still speaks. Peers of version 1 predate
+/// network ids, and only ever joined the main network.
+pub(crate) const MIN_PROTOC_VERSION: u16 = 1;
+const PROTOC_VERSION_LEN: usize = 2;
+/// Offset of the symmetric key in the intro packet, which starts with the protocol version
+/// and, since version 2, the id of the network the sender belongs to.
+const INTRO_KEY_OFFSET: usize = PROTOC_VERSION_LEN + NetworkId::LEN;
+const INTRO_PACKET_LEN: usize = INTRO_KEY_OFFSET + 16;
+const V1_INTRO_PACKET_LEN: usize = PROTOC_VERSION_LEN + 16;
+
+/// Flag appended to the intro packet by nodes which greet the peers they connect to with the
+/// greeting frame of the network bridge. Older nodes ignore anything past the key, and never
+/// append it, so they are neither sent greetings nor messages they can't decode.
+const GREETS_FLAG: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+enum IntroError {
+    Invalid,
+    Version(u16),
+}
+
+/// What the remote said about itself in its intro packet.
+#[derive(Debug, PartialEq, Eq)]
+struct Intro<'a> {
+    /// Network the sender belongs to.
+    network: NetworkId,
+    /// Key the sender asked packets to be encrypted with.
+    key: &'a [u8],
+    /// Whether the sender understands greetings.
+    greets: bool,
+}
+
+/// Intro packet of this node, carrying the key the remote must encrypt the packets sent to
+/// this node with.
+///
+/// Nodes of the main network keep sending intros of version 1 until no peer of that version
+/// is left, so they can still connect to them; a version 1 intro implies the main network.
+fn intro_packet(network_id: &NetworkId, inbound_key: &[u8; 16]) -> Vec<u8> {
+    if *network_id == NetworkId::default() {
+        [
+            &MIN_PROTOC_VERSION.to_le_bytes()[..],
+            inbound_key,
+            &[GREETS_FLAG],
+        ]
+        .concat()
+    } else {
+        [
+            &PROTOC_VERSION.to_le_bytes()[..],
+            network_id.as_bytes(),
+            inbound_key,
+            &[GREETS_FLAG],
+        ]
+        .concat()
+    }
+}
+
+/// Parses the intro packet of a remote, ignoring anything past the flags it knows about.
+fn parse_intro_packet(data: &[u8]) -> Result<Intro<'_>, IntroError> {
+    let version = data.get(..PROTOC_VERSION_LEN).ok_or(IntroError::Invalid)?;
+    let greets = |len: usize| data.get(len) == Some(&GREETS_FLAG);
+    match u16::from_le_bytes([version[0], version[1]]) {
+        1 => {
+            let key = data
+                .get(PROTOC_VERSION_LEN..V1_INTRO_PACKET_LEN)
+                .ok_or(IntroError::Invalid)?;
+            Ok(Intro {
+                network: NetworkId::default(),
+                key,
+                greets: greets(V1_INTRO_PACKET_LEN),
+            })
+        }
+        2 => {
+            let network = data
+                .get(PROTOC_VERSION_LEN..INTRO_KEY_OFFSET)
+                .ok_or(IntroError::Invalid)?;
+            let key = data
+                .get(INTRO_KEY_OFFSET..INTRO_PACKET_LEN)
+                .ok_or(IntroError::Invalid)?;
+            let network = NetworkId::from_bytes(network.try_into().expect("network id length"));
+            Ok(Intro {
+                network,
+                key,
+                greets: greets(INTRO_PACKET_LEN),
+            })
+        }
+        other => Err(IntroError::Version(other)),
+    }
+}
 
 // Constants for interval increase
 const INITIAL_INTERVAL: Duration = Duration::from_millis(200);
@@ -57,23 +150,57 @@ const INTERVAL_INCREASE_FACTOR: u64 = 2;
 const MAX_INTERVAL: Duration = Duration::from_millis(5000); // Maximum interval limit
 
 const DEFAULT_BW_TRACKER_WINDOW_SIZE: Duration = Duration::from_secs(10);
-const BANDWITH_LIMIT: usize = 1024 * 1024 * 10; // 10 MB/s
+/// Packets handed over to the rate limiter before the senders wait for it to catch up.
+const OUTBOUND_QUEUE_LEN: usize = 128;
 
 pub type SerializedMessage = Vec<u8>;
 
-pub(crate) async fn create_connection_handler<S: Socket>(
+/// Listens on the given transports, from the most to the least preferred, and over UDP on the
+/// secondary listen host too if given.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create_connection_handler(
+    transports: &[TransportKind],
+    bandwidth_limits: watch::Receiver<BandwidthLimits>,
+    traffic: Arc<TrafficCounters>,
     keypair: TransportKeypair,
     listen_host: IpAddr,
     listen_port: u16,
+    secondary_listen_host: Option<IpAddr>,
+    is_gateway: bool,
+    network_id: NetworkId,
+    addresses: AddressBook,
+) -> Result<(OutboundConnectionHandler, InboundConnectionHandler), TransportError> {
+    let addr = (listen_host, listen_port).into();
+    // always over the multi-transport socket, which also routes the packets to the
+    // alternative addresses of the peers
+    let socket =
+        MultiTransportSocket::bind_transports(transports, addr, secondary_listen_host, addresses)
+            .await?;
+    connection_handler_over(
+        CountedSocket::new(socket, traffic),
+        keypair,
+        addr,
+        is_gateway,
+        network_id,
+        bandwidth_limits,
+    )
+}
+
+fn connection_handler_over<S: Socket>(
+    socket: S,
+    keypair: TransportKeypair,
+    addr: SocketAddr,
     is_gateway: bool,
+    network_id: NetworkId,
+    bandwidth_limits: watch::Receiver<BandwidthLimits>,
 ) -> Result<(OutboundConnectionHandler, InboundConnectionHandler), TransportError> {
-    // Bind the UDP socket to the specified port
-    let socket = S::bind((listen_host, listen_port).into()).await?;
     let (och, new_connection_notifier) = OutboundConnectionHandler::config_listener(
         Arc::new(socket),
         keypair,
         is_gateway,
-        (listen_host, listen_port).into(),
+        network_id,
+        addr,
+        bandwidth_limits,
     )?;
     Ok((
         och,
@@ -121,16 +248,19 @@ impl OutboundConnectionHandler {
         socket: Arc<impl Socket>,
         keypair: TransportKeypair,
         is_gateway: bool,
+        network_id: NetworkId,
         socket_addr: SocketAddr,
+        bandwidth_limits: watch::Receiver<BandwidthLimits>,
     ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
         // Channel buffer is one so senders will await until the receiver is ready, important for bandwidth limiting
         let (conn_handler_sender, conn_handler_receiver) = mpsc::channel(100);
         let (new_connection_sender, new_connection_notifier) = mpsc::channel(100);
 
-        // Channel buffer is one so senders will await until the receiver is ready, important for bandwidth limiting
-        let (outbound_sender, outbound_recv) = mpsc::channel(1);
+        // Channel buffer is bounded so senders will await until the rate limiter catches up, important for bandwidth limiting
+        let (outbound_sender, outbound_recv) = mpsc::channel(OUTBOUND_QUEUE_LEN);
         let transport = UdpPacketsListener {
             is_gateway,
+            network_id,
             socket_listener: socket.clone(),
             this_peer_keypair: keypair,
             remote_connections: BTreeMap::new(),
@@ -147,7 +277,7 @@ impl OutboundConnectionHandler {
             send_queue: conn_handler_sender,
         };
 
-        task::spawn(bw_tracker.rate_limiter(BANDWITH_LIMIT, socket));
+        task::spawn(bw_tracker.rate_limiter(bandwidth_limits, socket));
         task::spawn(transport.listen());
 
         Ok((connection_handler, new_connection_notifier))
@@ -159,8 +289,16 @@ impl OutboundConnectionHandler {
         socket: Arc<impl Socket>,
         keypair: TransportKeypair,
         is_gateway: bool,
+        network_id: NetworkId,
     ) -> Result<(Self, mpsc::Receiver<PeerConnection>), TransportError> {
-        Self::config_listener(socket, keypair, is_gateway, socket_addr)
+        Self::config_listener(
+            socket,
+            keypair,
+            is_gateway,
+            network_id,
+            socket_addr,
+            watch::channel(BandwidthLimits::default()).1,
+        )
     }
 
     pub async fn connect(
@@ -202,6 +340,8 @@ struct UdpPacketsListener<S = UdpSocket> {
     connection_handler: mpsc::Receiver<(SocketAddr, ConnectionEvent)>,
     this_peer_keypair: TransportKeypair,
     is_gateway: bool,
+    /// Network this peer belongs to, peers from other networks are refused.
+    network_id: NetworkId,
     new_connection_notifier: mpsc::Sender<PeerConnection>,
     outbound_packets: mpsc::Sender<(SocketAddr, Arc<[u8]>)>,
     this_addr: SocketAddr,
@@ -403,6 +543,7 @@ impl<S: Socket> UdpPacketsListener<S> {
     ) {
         let secret = self.this_peer_keypair.secret.clone();
         let outbound_packets = self.outbound_packets.clone();
+        let network_id = self.network_id;
 
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
@@ -412,26 +553,38 @@ impl<S: Socket> UdpPacketsListener<S> {
                     tracing::debug!(%remote_addr, %err, "Failed to decrypt intro packet");
                     err
                 })?;
-            let protoc = &decrypted_intro_packet[..PROTOC_VERSION.len()];
-            let outbound_key_bytes =
-                &decrypted_intro_packet[PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16];
+            let Intro {
+                network,
+                key: outbound_key_bytes,
+                greets: remote_greets,
+            } = match parse_intro_packet(&decrypted_intro_packet) {
+                Ok(intro) => intro,
+                Err(IntroError::Version(version)) => {
+                    return Err(TransportError::ConnectionEstablishmentFailure {
+                        cause: format!("remote is using a different protocol version: {version}")
+                            .into(),
+                    });
+                }
+                Err(IntroError::Invalid) => {
+                    return Err(TransportError::ConnectionEstablishmentFailure {
+                        cause: "invalid intro packet".into(),
+                    });
+                }
+            };
             let outbound_key = Aes128Gcm::new_from_slice(outbound_key_bytes).map_err(|_| {
                 TransportError::ConnectionEstablishmentFailure {
                     cause: "invalid symmetric key".into(),
                 }
             })?;
-            if protoc != PROTOC_VERSION {
+            if network != network_id {
                 let packet = SymmetricMessage::ack_error(&outbound_key)?;
                 outbound_packets
                     .send((remote_addr, packet.prepared_send()))
                     .await
                     .map_err(|_| TransportError::ChannelClosed)?;
+                tracing::debug!(%remote_addr, "Refusing connection from a different network");
                 return Err(TransportError::ConnectionEstablishmentFailure {
-                    cause: format!(
-                        "remote is using a different protocol version: {:?}",
-                        String::from_utf8_lossy(protoc)
-                    )
-                    .into(),
+                    cause: "remote belongs to a different network".into(),
                 });
             }
 
@@ -496,6 +649,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                 inbound_symmetric_key: inbound_key,
                 inbound_symmetric_key_bytes: inbound_key_bytes,
                 my_address: None,
+                remote_greets,
             };
 
             let inbound_conn = InboundRemoteConnection {
@@ -547,21 +701,28 @@ impl<S: Socket> UdpPacketsListener<S> {
             remote_addr: SocketAddr,
             packet: &PacketData<UnknownEncryption>,
             transport_secret_key: &TransportSecretKey,
+            network_id: &NetworkId,
             outbound_sym_key: &mut Option<Aes128Gcm>,
+            remote_greets: &mut bool,
             state: &mut ConnectionState,
         ) -> Result<(), ()> {
             // probably the first packet to punch through the NAT
             if let Ok(decrypted_intro_packet) = packet.try_decrypt_asym(transport_secret_key) {
                 tracing::debug!(%remote_addr, "received intro packet");
-                let protoc = &decrypted_intro_packet.data()[..PROTOC_VERSION.len()];
-                if protoc != PROTOC_VERSION {
-                    todo!("return error");
+                let intro = match parse_intro_packet(decrypted_intro_packet.data()) {
+                    Ok(intro) => intro,
+                    Err(error) => {
+                        tracing::debug!(%remote_addr, ?error, "invalid intro packet");
+                        return Err(());
+                    }
+                };
+                if intro.network != *network_id {
+                    tracing::debug!(%remote_addr, "Refusing connection from a different network");
+                    return Err(());
                 }
-                let outbound_key_bytes =
-                    &decrypted_intro_packet.data()[PROTOC_VERSION.len()..PROTOC_VERSION.len() + 16];
-                let outbound_key =
-                    Aes128Gcm::new_from_slice(outbound_key_bytes).expect("correct length");
+                let outbound_key = Aes128Gcm::new_from_slice(intro.key).expect("correct length");
                 *outbound_sym_key = Some(outbound_key.clone());
+                *remote_greets = intro.greets;
                 *state = ConnectionState::RemoteInbound {
                     intro_packet: packet.assert_assymetric(),
                 };
@@ -573,6 +734,7 @@ impl<S: Socket> UdpPacketsListener<S> {
 
         let outbound_packets = self.outbound_packets.clone();
         let transport_secret_key = self.this_peer_keypair.secret.clone();
+        let network_id = self.network_id;
         let (inbound_from_remote, mut next_inbound) =
             mpsc::channel::<PacketData<UnknownEncryption>>(1);
         let this_addr = self.this_addr;
@@ -589,10 +751,11 @@ impl<S: Socket> UdpPacketsListener<S> {
             let inbound_sym_key = Aes128Gcm::new(&inbound_sym_key_bytes.into());
 
             let mut outbound_sym_key: Option<Aes128Gcm> = None;
+            // only known if the intro of the remote gets through, otherwise the remote
+            // greets first if it understands greetings
+            let mut remote_greets = false;
             let outbound_intro_packet = {
-                let mut data = [0u8; { 16 + PROTOC_VERSION.len() }];
-                data[..PROTOC_VERSION.len()].copy_from_slice(&PROTOC_VERSION);
-                data[PROTOC_VERSION.len()..].copy_from_slice(&inbound_sym_key_bytes);
+                let data = intro_packet(&network_id, &inbound_sym_key_bytes);
                 PacketData::<_, MAX_PACKET_SIZE>::encrypt_with_pubkey(&data, &remote_public_key)
             };
 
@@ -690,6 +853,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                                     inbound_symmetric_key_bytes:
                                                         inbound_sym_key_bytes,
                                                     my_address: Some(my_address),
+                                                    remote_greets,
                                                 },
                                                 InboundRemoteConnection {
                                                     inbound_packet_sender: inbound_sender,
@@ -718,7 +882,9 @@ impl<S: Socket> UdpPacketsListener<S> {
                                     remote_addr,
                                     &packet,
                                     &transport_secret_key,
+                                    &network_id,
                                     &mut outbound_sym_key,
+                                    &mut remote_greets,
                                     &mut state,
                                 )
                                 .is_ok()
@@ -758,6 +924,7 @@ impl<S: Socket> UdpPacketsListener<S> {
                                         inbound_symmetric_key: inbound_sym_key,
                                         inbound_symmetric_key_bytes: inbound_sym_key_bytes,
                                         my_address: None,
+                                        remote_greets,
                                     },
                                     InboundRemoteConnection {
                                         inbound_packet_sender: inbound_sender,
@@ -943,7 +1110,7 @@ mod test {
         packet_drop_policy: PacketDropPolicy,
         channels: Channels,
     ) -> anyhow::Result<(TransportPublicKey, OutboundConnectionHandler, SocketAddr)> {
-        set_peer_connection_in(packet_drop_policy, false, channels)
+        set_peer_connection_in(packet_drop_policy, false, NetworkId::default(), channels)
             .await
             .map(|(pk, (o, _), s)| (pk, o, s))
     }
@@ -959,12 +1126,13 @@ mod test {
         ),
         anyhow::Error,
     > {
-        set_peer_connection_in(packet_drop_policy, true, channels).await
+        set_peer_connection_in(packet_drop_policy, true, NetworkId::default(), channels).await
     }
 
     async fn set_peer_connection_in(
         packet_drop_policy: PacketDropPolicy,
         gateway: bool,
+        network_id: NetworkId,
         channels: Channels,
     ) -> Result<
         (
@@ -992,6 +1160,7 @@ mod test {
             socket,
             peer_keypair,
             gateway,
+            network_id,
         )
         .expect("failed to create peer");
         Ok((
@@ -1342,6 +1511,63 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn intro_packets_of_both_versions() {
+        let key = [7; 16];
+        let intro = |network| Intro {
+            network,
+            key: &key[..],
+            greets: true,
+        };
+        // nodes of the main network still introduce themselves as version 1 peers
+        let main = intro_packet(&NetworkId::default(), &key);
+        assert_eq!(main.len(), V1_INTRO_PACKET_LEN + 1);
+        assert_eq!(parse_intro_packet(&main), Ok(intro(NetworkId::default())));
+
+        let testnet = NetworkId::from_name("testnet");
+        let other = intro_packet(&testnet, &key);
+        assert_eq!(other.len(), INTRO_PACKET_LEN + 1);
+        assert_eq!(parse_intro_packet(&other), Ok(intro(testnet)));
+
+        // intros of nodes which predate greetings
+        assert_eq!(
+            parse_intro_packet(&main[..V1_INTRO_PACKET_LEN]),
+            Ok(Intro {
+                greets: false,
+                ..intro(NetworkId::default())
+            })
+        );
+        assert_eq!(
+            parse_intro_packet(&other[..INTRO_PACKET_LEN - 1]),
+            Err(IntroError::Invalid)
+        );
+        let mut newer = other.clone();
+        newer[..PROTOC_VERSION_LEN].copy_from_slice(&3u16.to_le_bytes());
+        assert_eq!(parse_intro_packet(&newer), Err(IntroError::Version(3)));
+    }
+
+    #[tokio::test]
+    async fn simulate_gateway_connection_from_other_network() -> anyhow::Result<()> {
+        let channels = Arc::new(DashMap::new());
+        let (_peer_a_pub, (mut peer_a, _), _peer_a_addr) = set_peer_connection_in(
+            Default::default(),
+            false,
+            NetworkId::from_name("testnet"),
+            channels.clone(),
+        )
+        .await?;
+        let (gw_pub, (_oc, _gw_conn), gw_addr) =
+            set_gateway_connection(Default::default(), channels).await?;
+
+        let peer_a_conn = peer_a.connect(gw_pub, gw_addr).await;
+        let result = tokio::time::timeout(Duration::from_secs(60), peer_a_conn).await?;
+        assert!(matches!(
+            result,
+            Err(TransportError::ConnectionEstablishmentFailure { .. })
+        ));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn simulate_gateway_connection_drop_first_packets_of_gateway() -> anyhow::Result<()> {
         let channels = Arc::new(DashMap::new());
@@ -1,5 +1,5 @@
 use rand::rngs::OsRng;
-use rsa::{pkcs8, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use rsa::{pkcs8, Pkcs1v15Encrypt, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -38,6 +38,15 @@ impl TransportKeypair {
         &self.public
     }
 
+    /// Signs the hash of the given data with the secret key.
+    pub(crate) fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let digest = blake3::hash(data);
+        self.secret
+            .0
+            .sign(Pkcs1v15Sign::new_unprefixed(), digest.as_bytes())
+            .expect("failed to sign")
+    }
+
     #[cfg(test)]
     pub(crate) fn secret(&self) -> &TransportSecretKey {
         &self.secret
@@ -55,6 +64,14 @@ impl TransportPublicKey {
             .encrypt(&mut rng, padding, data)
             .expect("failed to encrypt")
     }
+
+    /// Verifies the signature of the given data was produced by the owner of this key.
+    pub(crate) fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        let digest = blake3::hash(data);
+        self.0
+            .verify(Pkcs1v15Sign::new_unprefixed(), digest.as_bytes(), signature)
+            .is_ok()
+    }
 }
 
 impl std::fmt::Debug for TransportPublicKey {
@@ -86,6 +103,11 @@ impl From<RsaPublicKey> for TransportPublicKey {
     }
 }
 
+#[cfg(unix)]
+const LINE_ENDING: pkcs8::LineEnding = pkcs8::LineEnding::LF;
+#[cfg(windows)]
+const LINE_ENDING: pkcs8::LineEnding = pkcs8::LineEnding::CRLF;
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub(crate) struct TransportSecretKey(RsaPrivateKey);
 
@@ -94,18 +116,20 @@ impl TransportSecretKey {
         self.0.decrypt(Pkcs1v15Encrypt, data)
     }
 
-    #[cfg(test)]
     pub fn to_pkcs8_pem(&self) -> Result<Vec<u8>, pkcs8::Error> {
         use pkcs8::EncodePrivateKey;
 
-        #[cfg(unix)]
-        let line_endings = pkcs8::LineEnding::LF;
+        self.0
+            .to_pkcs8_pem(LINE_ENDING)
+            .map(|s| s.as_str().as_bytes().to_vec())
+    }
 
-        #[cfg(windows)]
-        let line_endings = pkcs8::LineEnding::CRLF;
+    /// The key encrypted with the passphrase, in a PKCS#8 PEM document.
+    pub fn to_encrypted_pkcs8_pem(&self, passphrase: &str) -> Result<Vec<u8>, pkcs8::Error> {
+        use pkcs8::EncodePrivateKey;
 
         self.0
-            .to_pkcs8_pem(line_endings)
+            .to_pkcs8_encrypted_pem(OsRng, passphrase, LINE_ENDING)
             .map(|s| s.as_str().as_bytes().to_vec())
     }
 }
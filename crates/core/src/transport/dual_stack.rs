@@ -0,0 +1,447 @@
+//! Reaching peers over both IP families.
+//!
+//! A node may listen on an IPv4 and an IPv6 address at the same time, and have a port mapped
+//! on its router. Once connected, peers advertise the addresses they can be reached at, and one
+//! of them, preferably of the other family, is probed and, once the peer answers from it,
+//! recorded as an alternative to the address the peer is known by. Packets to the peer go to whichever of
+//! its addresses it was last heard from, or to both if it was not heard from recently; packets
+//! received from the alternative address are reported as coming from the address the peer is
+//! known by, so the rest of the node keeps a single address per peer.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Time after which a peer not heard from over the address it was last heard from is sent
+/// packets over all of its addresses again.
+const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// Time a peer has to answer the probe sent to the address it advertised.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Once tracking this many addresses, the ones not heard from recently are forgotten.
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+/// Documentation addresses, only used to find the source address the routes of the host
+/// would pick; nothing is sent to them.
+const ROUTE_PROBE_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 9);
+const ROUTE_PROBE_V6: SocketAddr = SocketAddr::new(
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+    9,
+);
+
+/// Listener of the other IP family, besides the main listener address of the node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DualStack {
+    /// On the unspecified address of the other family if the main listener address is
+    /// unspecified too, as long as the host supports it.
+    #[default]
+    Auto,
+    /// On the given address, which must be of the other family.
+    Address(IpAddr),
+    Disabled,
+}
+
+impl DualStack {
+    /// Address of the other family to listen on besides the main listener address.
+    pub(crate) fn secondary(&self, primary: IpAddr) -> Option<IpAddr> {
+        match *self {
+            Self::Auto if primary.is_unspecified() => Some(match primary {
+                IpAddr::V4(_) => Ipv6Addr::UNSPECIFIED.into(),
+                IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED.into(),
+            }),
+            Self::Address(ip) => Some(ip),
+            Self::Auto | Self::Disabled => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Book {
+    /// Addresses the sockets of this node are bound to.
+    bound: Vec<SocketAddr>,
+    /// Addresses this node can be reached at from the internet.
+    own: Vec<SocketAddr>,
+    /// External address of the port mapped on the router, if any.
+    mapped: Option<SocketAddr>,
+    /// Alternative address of each peer, by the address the peer is known by.
+    alternatives: HashMap<SocketAddr, SocketAddr>,
+    known_by: HashMap<SocketAddr, SocketAddr>,
+    /// Last time each address of the peers with an alternative was heard from.
+    heard: HashMap<SocketAddr, Instant>,
+    /// Peers this node advertised its addresses to over their current connection.
+    advertised_to: HashSet<SocketAddr>,
+    /// Addresses advertised by the peers and not yet confirmed, by the address the peer is
+    /// known by.
+    probing: HashMap<SocketAddr, Probe>,
+}
+
+struct Probe {
+    alternative: SocketAddr,
+    nonce: u64,
+    sent_at: Instant,
+}
+
+/// Shared handle to the addresses of both families of this node and of its peers.
+#[derive(Clone, Default)]
+pub(crate) struct AddressBook(Arc<Mutex<Book>>);
+
+impl AddressBook {
+    /// Records the addresses the sockets of this node are bound to, the unspecified ones
+    /// replaced by the address the routes of the host pick for their family.
+    pub fn set_bound(&self, bound: &[SocketAddr]) {
+        let mut book = self.0.lock();
+        book.bound = bound.to_vec();
+        book.own = bound
+            .iter()
+            .filter_map(|addr| reachable_at(*addr))
+            .collect();
+    }
+
+    pub fn bound(&self) -> Vec<SocketAddr> {
+        self.0.lock().bound.clone()
+    }
+
+    /// Address of the interface the packets of the main socket of this node currently go out
+    /// through, which changes along with the network the host is connected to.
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        let bound = *self.0.lock().bound.first()?;
+        if bound.ip().is_unspecified() {
+            route_source(bound)
+        } else {
+            Some(bound.ip())
+        }
+    }
+
+    /// Records the external address of the port mapped on the router. Returns whether it
+    /// changed, in which case the addresses are advertised again to the connected peers.
+    pub fn set_mapped(&self, mapped: Option<SocketAddr>) -> bool {
+        let mut book = self.0.lock();
+        if book.mapped == mapped {
+            return false;
+        }
+        book.mapped = mapped;
+        book.advertised_to.clear();
+        true
+    }
+
+    /// Addresses to advertise to the peer, unless this node has none or already advertised
+    /// them over the current connection.
+    pub fn advertisement_for(&self, remote: SocketAddr) -> Option<Vec<SocketAddr>> {
+        let mut book = self.0.lock();
+        let addrs: Vec<_> = book.mapped.iter().chain(&book.own).copied().collect();
+        if addrs.is_empty() || !book.advertised_to.insert(remote) {
+            return None;
+        }
+        Some(addrs)
+    }
+
+    /// Records the addresses advertised by the peer known by the given address, which must be
+    /// the address of the connection they were received over so a peer can only advertise its
+    /// own addresses. Returns the address to probe, and the nonce the peer must echo back over
+    /// the connection for it to be recorded as an alternative.
+    pub fn advertised(
+        &self,
+        known_by: SocketAddr,
+        addrs: &[SocketAddr],
+    ) -> Option<(SocketAddr, u64)> {
+        self.advertised_at(known_by, addrs, Instant::now())
+    }
+
+    fn advertised_at(
+        &self,
+        known_by: SocketAddr,
+        addrs: &[SocketAddr],
+        now: Instant,
+    ) -> Option<(SocketAddr, u64)> {
+        let mut book = self.0.lock();
+        // only a single alternative, so a peer can't have packets sent to many addresses
+        // which are not its own
+        let candidates = addrs.iter().copied().filter(|addr| {
+            *addr != known_by
+                && is_global(addr.ip())
+                && addr.port() != 0
+                && !book.alternatives.contains_key(addr)
+        });
+        let alternative = candidates
+            .clone()
+            .find(|addr| addr.is_ipv4() != known_by.is_ipv4())
+            .or_else(|| candidates.clone().next())?;
+        if book
+            .known_by
+            .get(&alternative)
+            .is_some_and(|other| *other != known_by)
+        {
+            tracing::debug!(%known_by, %alternative, "Address advertised by more than one peer");
+            return None;
+        }
+        if book.probing.len() >= MAX_TRACKED_ADDRESSES {
+            book.probing
+                .retain(|_, probe| now.saturating_duration_since(probe.sent_at) < PROBE_TIMEOUT);
+            if book.probing.len() >= MAX_TRACKED_ADDRESSES {
+                return None;
+            }
+        }
+        let nonce = rand::random();
+        book.probing.insert(
+            known_by,
+            Probe {
+                alternative,
+                nonce,
+                sent_at: now,
+            },
+        );
+        Some((alternative, nonce))
+    }
+
+    /// The peer known by the given address echoed the nonce of the probe sent to the address it
+    /// advertised, which is recorded as its alternative. Returns whether it was.
+    pub fn confirmed(&self, known_by: SocketAddr, nonce: u64) -> bool {
+        self.confirmed_at(known_by, nonce, Instant::now())
+    }
+
+    fn confirmed_at(&self, known_by: SocketAddr, nonce: u64, now: Instant) -> bool {
+        let mut book = self.0.lock();
+        let Some(probe) = book.probing.remove(&known_by) else {
+            return false;
+        };
+        if probe.nonce != nonce || now.saturating_duration_since(probe.sent_at) >= PROBE_TIMEOUT {
+            tracing::debug!(
+                %known_by,
+                alternative = %probe.alternative,
+                "Probe of advertised address not answered"
+            );
+            return false;
+        }
+        let alternative = probe.alternative;
+        // claimed by another peer while being probed
+        if book
+            .known_by
+            .get(&alternative)
+            .is_some_and(|other| *other != known_by)
+        {
+            return false;
+        }
+        if book.alternatives.len() >= MAX_TRACKED_ADDRESSES {
+            book.forget_stale(now);
+        }
+        if let Some(previous) = book.alternatives.insert(known_by, alternative) {
+            book.known_by.remove(&previous);
+            book.heard.remove(&previous);
+        }
+        book.known_by.insert(alternative, known_by);
+        tracing::debug!(%known_by, %alternative, "Peer reachable at an alternative address");
+        true
+    }
+
+    /// Address the peer which sent a packet from the given address is known by.
+    pub fn received_from(&self, from: SocketAddr) -> SocketAddr {
+        self.received_from_at(from, Instant::now())
+    }
+
+    fn received_from_at(&self, from: SocketAddr, now: Instant) -> SocketAddr {
+        let mut book = self.0.lock();
+        let known_by = book.known_by.get(&from).copied().unwrap_or(from);
+        if book.alternatives.contains_key(&known_by) {
+            book.heard.insert(from, now);
+        }
+        known_by
+    }
+
+    /// Addresses to send the packets for the peer known by the given address to.
+    pub fn targets(&self, remote: SocketAddr) -> Vec<SocketAddr> {
+        self.targets_at(remote, Instant::now())
+    }
+
+    fn targets_at(&self, remote: SocketAddr, now: Instant) -> Vec<SocketAddr> {
+        let book = self.0.lock();
+        let Some(alternative) = book.alternatives.get(&remote).copied() else {
+            return vec![remote];
+        };
+        let last_heard = |addr| book.heard.get(&addr).copied();
+        let preferred = match (last_heard(remote), last_heard(alternative)) {
+            (Some(primary), Some(other)) if other > primary => Some((alternative, other)),
+            (Some(primary), _) => Some((remote, primary)),
+            (None, Some(other)) => Some((alternative, other)),
+            (None, None) => None,
+        };
+        match preferred {
+            Some((addr, heard)) if now.saturating_duration_since(heard) < STALE_AFTER => {
+                vec![addr]
+            }
+            _ => vec![remote, alternative],
+        }
+    }
+
+    /// The connection to the peer closed, the addresses of this node are advertised again
+    /// over the next one.
+    pub fn connection_closed(&self, remote: &SocketAddr) {
+        let mut book = self.0.lock();
+        book.advertised_to.remove(remote);
+        book.probing.remove(remote);
+    }
+}
+
+impl Book {
+    fn forget_stale(&mut self, now: Instant) {
+        let heard = &self.heard;
+        let recent = |addr: &SocketAddr| {
+            heard
+                .get(addr)
+                .is_some_and(|at| now.saturating_duration_since(*at) < STALE_AFTER)
+        };
+        self.alternatives
+            .retain(|known_by, alternative| recent(known_by) || recent(alternative));
+        let alternatives = &self.alternatives;
+        self.known_by
+            .retain(|_, known_by| alternatives.contains_key(known_by));
+        let known_by = &self.known_by;
+        self.heard
+            .retain(|addr, _| alternatives.contains_key(addr) || known_by.contains_key(addr));
+    }
+}
+
+/// Address peers on the internet can reach the socket bound to the given address at, if any.
+pub(crate) fn reachable_at(bound: SocketAddr) -> Option<SocketAddr> {
+    let ip = if bound.ip().is_unspecified() {
+        route_source(bound)?
+    } else {
+        bound.ip()
+    };
+    is_global(ip).then_some(SocketAddr::new(ip, bound.port()))
+}
+
+/// Address of the interface the routes of the host pick for packets sent from a socket bound
+/// to the given address.
+fn route_source(bound: SocketAddr) -> Option<IpAddr> {
+    // connecting a UDP socket sends nothing, it only picks the source address
+    let probe = std::net::UdpSocket::bind(SocketAddr::new(bound.ip(), 0)).ok()?;
+    probe
+        .connect(match bound {
+            SocketAddr::V4(_) => ROUTE_PROBE_V4,
+            SocketAddr::V6(_) => ROUTE_PROBE_V6,
+        })
+        .ok()?;
+    Some(probe.local_addr().ok()?.ip())
+}
+
+fn is_global(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            let link_local = first & 0xffc0 == 0xfe80;
+            let unique_local = first & 0xfe00 == 0xfc00;
+            !(ip.is_unspecified() || ip.is_loopback() || link_local || unique_local)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertise(book: &AddressBook, known_by: SocketAddr, addrs: &[SocketAddr], now: Instant) {
+        if let Some((_, nonce)) = book.advertised_at(known_by, addrs, now) {
+            assert!(book.confirmed_at(known_by, nonce, now));
+        }
+    }
+
+    #[test]
+    fn prefer_family_last_heard() {
+        let book = AddressBook::default();
+        let v4: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+        let v6: SocketAddr = "[2a01:4f8::7]:4000".parse().unwrap();
+        let now = Instant::now();
+        assert_eq!(book.targets_at(v4, now), [v4]);
+
+        // addresses not reachable from the internet are ignored
+        assert!(book
+            .advertised_at(v4, &["198.51.100.1:4000".parse().unwrap()], now)
+            .is_none());
+        assert!(book
+            .advertised_at(v4, &["[fe80::1]:4000".parse().unwrap()], now)
+            .is_none());
+        assert_eq!(book.targets_at(v4, now), [v4]);
+
+        advertise(&book, v4, &[v4, v6], now);
+        // never heard from over either family, both are tried
+        assert_eq!(book.targets_at(v4, now), [v4, v6]);
+
+        // packets from the alternative are reported as coming from the peer address
+        assert_eq!(book.received_from_at(v6, now), v4);
+        assert_eq!(book.targets_at(v4, now), [v6]);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(book.received_from_at(v4, later), v4);
+        assert_eq!(book.targets_at(v4, later), [v4]);
+
+        // both are tried again once the peer has been silent for a while
+        assert_eq!(book.targets_at(v4, later + STALE_AFTER), [v4, v6]);
+
+        // another peer can't claim the alternative address
+        let other: SocketAddr = "192.0.2.200:4000".parse().unwrap();
+        assert!(book.advertised_at(other, &[v6], later).is_none());
+        assert_eq!(book.received_from_at(v6, later), v4);
+    }
+
+    #[test]
+    fn alternative_only_once_probed() {
+        let book = AddressBook::default();
+        let peer: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+        let victim: SocketAddr = "[2a01:4f8::7]:4000".parse().unwrap();
+        let now = Instant::now();
+
+        // not used until the peer echoes the nonce sent to the address
+        let (probed, nonce) = book.advertised_at(peer, &[victim], now).unwrap();
+        assert_eq!(probed, victim);
+        assert_eq!(book.targets_at(peer, now), [peer]);
+        assert_eq!(book.received_from_at(victim, now), victim);
+        assert!(!book.confirmed_at(peer, nonce.wrapping_add(1), now));
+        assert!(!book.confirmed_at(peer, nonce, now));
+        assert_eq!(book.targets_at(peer, now), [peer]);
+
+        // nor once the probe expired
+        let (_, nonce) = book.advertised_at(peer, &[victim], now).unwrap();
+        assert!(!book.confirmed_at(peer, nonce, now + PROBE_TIMEOUT));
+        assert_eq!(book.received_from_at(victim, now), victim);
+
+        // the address of another peer can't be claimed
+        let (_, nonce) = book.advertised_at(peer, &[victim], now).unwrap();
+        assert!(book.confirmed_at(peer, nonce, now));
+        let other: SocketAddr = "198.18.0.1:4000".parse().unwrap();
+        assert!(book.advertised_at(other, &[peer], now).is_none());
+    }
+
+    #[test]
+    fn advertise_again_once_mapping_changes() {
+        let book = AddressBook::default();
+        let peer: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+        let mapped: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+        assert!(book.advertisement_for(peer).is_none());
+
+        assert!(book.set_mapped(Some(mapped)));
+        assert_eq!(book.advertisement_for(peer), Some(vec![mapped]));
+        assert!(book.advertisement_for(peer).is_none());
+
+        // renewing the same mapping is not a change
+        assert!(!book.set_mapped(Some(mapped)));
+        assert!(book.advertisement_for(peer).is_none());
+
+        let remapped: SocketAddr = "198.51.100.9:40001".parse().unwrap();
+        assert!(book.set_mapped(Some(remapped)));
+        assert_eq!(book.advertisement_for(peer), Some(vec![remapped]));
+    }
+}
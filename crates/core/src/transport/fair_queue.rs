@@ -0,0 +1,202 @@
+//! Fair scheduling of the outbound packets among the remote peers.
+//!
+//! All the packets sent by this peer go through the same rate limiter, so a large transfer to
+//! one peer could fill the upstream bandwidth and delay the keep-alives and control messages
+//! to every other peer. Packets are queued per remote instead, and taken from the queues in
+//! deficit round robin: in each round every remote may send up to a quantum of bytes, carrying
+//! over what it did not use while it still has packets waiting. Each remote can also be capped
+//! to a share of the total bandwidth, even when no other remote is waiting to send.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct RemoteQueue {
+    packets: VecDeque<Arc<[u8]>>,
+    /// Bytes the remote may still send in the current round.
+    deficit: usize,
+    /// Packets sent within the window, with the time they were sent at.
+    sent: VecDeque<(usize, Instant)>,
+    sent_bytes: usize,
+}
+
+impl RemoteQueue {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some(&(size, at)) = self.sent.front() {
+            if now.saturating_duration_since(at) <= window {
+                break;
+            }
+            self.sent.pop_front();
+            self.sent_bytes -= size;
+        }
+    }
+}
+
+pub(super) struct FairQueue {
+    /// Bytes each remote may send per round, at least the size of the largest packet.
+    quantum: usize,
+    /// Max bytes a single remote may send within the window.
+    remote_limit: Option<usize>,
+    window: Duration,
+    remotes: HashMap<SocketAddr, RemoteQueue>,
+    /// Remotes with packets waiting, in the order they are served.
+    round: VecDeque<SocketAddr>,
+    queued: usize,
+}
+
+impl FairQueue {
+    pub fn new(quantum: usize, remote_limit: Option<usize>, window: Duration) -> Self {
+        Self {
+            quantum: quantum.max(1),
+            remote_limit,
+            window,
+            remotes: HashMap::new(),
+            round: VecDeque::new(),
+            queued: 0,
+        }
+    }
+
+    pub fn set_remote_limit(&mut self, remote_limit: Option<usize>) {
+        self.remote_limit = remote_limit;
+    }
+
+    /// Number of packets waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.queued
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued == 0
+    }
+
+    pub fn push(&mut self, remote: SocketAddr, packet: Arc<[u8]>) {
+        let queue = self.remotes.entry(remote).or_default();
+        if queue.packets.is_empty() {
+            queue.deficit = self.quantum;
+            self.round.push_back(remote);
+        }
+        queue.packets.push_back(packet);
+        self.queued += 1;
+    }
+
+    /// Next packet to send, if any remote with packets waiting is below its share of the
+    /// bandwidth.
+    pub fn pop(&mut self, now: Instant) -> Option<(SocketAddr, Arc<[u8]>)> {
+        loop {
+            let mut waiting = false;
+            for turn in 0..self.round.len() {
+                let remote = self.round[turn];
+                let queue = self.remotes.get_mut(&remote).expect("queued remote");
+                queue.expire(now, self.window);
+                let size = queue.packets.front().expect("queued packet").len();
+                if self
+                    .remote_limit
+                    .is_some_and(|limit| queue.sent_bytes + size > limit)
+                {
+                    continue;
+                }
+                if queue.deficit < size {
+                    waiting = true;
+                    continue;
+                }
+                // the remotes skipped over, without credit left, wait for the next round
+                self.round.rotate_left(turn);
+                let packet = queue.packets.pop_front().expect("queued packet");
+                queue.deficit -= size;
+                queue.sent.push_back((size, now));
+                queue.sent_bytes += size;
+                self.queued -= 1;
+                if queue.packets.is_empty() {
+                    self.round.pop_front();
+                    self.forget_idle(now);
+                }
+                return Some((remote, packet));
+            }
+            if !waiting {
+                return None;
+            }
+            // none of the remotes below their share has credit left, start a new round
+            for remote in &self.round {
+                let queue = self.remotes.get_mut(remote).expect("queued remote");
+                queue.deficit += self.quantum;
+            }
+        }
+    }
+
+    /// Drops the state of the remotes without packets waiting nor sent within the window.
+    fn forget_idle(&mut self, now: Instant) {
+        let window = self.window;
+        self.remotes.retain(|_, queue| {
+            queue.expire(now, window);
+            !queue.packets.is_empty() || !queue.sent.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_transfer_does_not_starve_others() {
+        let window = Duration::from_secs(1);
+        let mut queue = FairQueue::new(1000, None, window);
+        let bulk: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let control: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..10 {
+            queue.push(bulk, Arc::from([0u8; 1000].as_slice()));
+        }
+        queue.push(control, Arc::from([1u8; 50].as_slice()));
+        queue.push(control, Arc::from([1u8; 50].as_slice()));
+        assert_eq!(queue.len(), 12);
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop(now))
+            .map(|(remote, _)| remote)
+            .collect();
+        assert_eq!(order.len(), 12);
+        // the small packets are sent within the first round, not after the bulk transfer
+        assert_eq!(order[..3], [bulk, control, control]);
+        assert!(queue.is_empty());
+
+        // capped remotes wait for their usage to leave the window
+        let mut queue = FairQueue::new(1000, Some(1500), window);
+        queue.push(bulk, Arc::from([0u8; 1000].as_slice()));
+        queue.push(bulk, Arc::from([0u8; 1000].as_slice()));
+        assert!(queue.pop(now).is_some());
+        assert!(queue.pop(now).is_none());
+        assert_eq!(queue.len(), 1);
+        assert!(queue.pop(now + window * 2).is_some());
+    }
+
+    #[test]
+    fn remotes_with_credit_are_served() {
+        let window = Duration::from_secs(1);
+        let first: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let now = Instant::now();
+
+        // the remote at the front is over its share, the next one is served meanwhile
+        let mut queue = FairQueue::new(1000, Some(1000), window);
+        queue.push(first, Arc::from([0u8; 1000].as_slice()));
+        queue.push(first, Arc::from([0u8; 1000].as_slice()));
+        queue.push(second, Arc::from([1u8; 500].as_slice()));
+        assert_eq!(queue.pop(now).unwrap().0, first);
+        assert_eq!(queue.pop(now).unwrap().0, second);
+        assert!(queue.pop(now).is_none());
+
+        // packets larger than the quantum are sent once enough rounds added up their credit
+        let mut queue = FairQueue::new(100, None, window);
+        queue.push(first, Arc::from([0u8; 250].as_slice()));
+        queue.push(second, Arc::from([1u8; 50].as_slice()));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop(now))
+            .map(|(remote, _)| remote)
+            .collect();
+        assert_eq!(order, [second, first]);
+        assert!(queue.is_empty());
+    }
+}
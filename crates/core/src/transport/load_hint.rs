@@ -0,0 +1,134 @@
+//! Lightweight load hints exchanged between peers in keep-alive messages.
+//!
+//! Peers which are up but overloaded still answer keep-alives, so each keep-alive carries
+//! a coarse summary of the sender's load, which can be used to avoid routing through them
+//! without requiring a separate telemetry protocol.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// Coarse classification of the number of operations queued at a peer.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum QueueDepth {
+    #[default]
+    Idle,
+    Normal,
+    Busy,
+    Saturated,
+}
+
+impl QueueDepth {
+    pub fn from_usage(in_progress: usize, capacity: usize) -> Self {
+        if capacity == 0 || in_progress >= capacity {
+            return Self::Saturated;
+        }
+        match in_progress * 4 / capacity {
+            0 if in_progress == 0 => Self::Idle,
+            0 | 1 => Self::Normal,
+            _ => Self::Busy,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Idle,
+            1 => Self::Normal,
+            2 => Self::Busy,
+            _ => Self::Saturated,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LoadHint {
+    pub queue_depth: QueueDepth,
+    /// Percentage of the operations which failed recently at the peer.
+    pub error_rate: u8,
+}
+
+impl LoadHint {
+    const OVERLOADED_ERROR_RATE: u8 = 50;
+
+    /// Whether the peer should be avoided when there are alternatives.
+    pub fn is_overloaded(&self) -> bool {
+        self.queue_depth == QueueDepth::Saturated || self.error_rate >= Self::OVERLOADED_ERROR_RATE
+    }
+
+    fn pack(self) -> u16 {
+        u16::from_le_bytes([self.queue_depth as u8, self.error_rate])
+    }
+
+    fn unpack(value: u16) -> Self {
+        let [queue_depth, error_rate] = value.to_le_bytes();
+        Self {
+            queue_depth: QueueDepth::from_u8(queue_depth),
+            error_rate,
+        }
+    }
+}
+
+/// Load hints of this peer and of the remote peers it is connected to.
+#[derive(Clone, Default)]
+pub(crate) struct LoadHints {
+    local: Arc<AtomicU16>,
+    remote: Arc<DashMap<SocketAddr, LoadHint>>,
+}
+
+impl LoadHints {
+    pub fn set_local(&self, hint: LoadHint) {
+        self.local.store(hint.pack(), Ordering::Release);
+    }
+
+    pub fn local(&self) -> LoadHint {
+        LoadHint::unpack(self.local.load(Ordering::Acquire))
+    }
+
+    /// Last load hint reported by the peer at the given address, if any.
+    pub fn remote(&self, addr: &SocketAddr) -> Option<LoadHint> {
+        self.remote.get(addr).map(|hint| *hint)
+    }
+
+    pub fn is_overloaded(&self, addr: &SocketAddr) -> bool {
+        self.remote(addr)
+            .map(|hint| hint.is_overloaded())
+            .unwrap_or(false)
+    }
+
+    pub(super) fn record_remote(&self, addr: SocketAddr, hint: LoadHint) {
+        self.remote.insert(addr, hint);
+    }
+
+    pub fn forget(&self, addr: &SocketAddr) {
+        self.remote.remove(addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_depth_classes() {
+        assert_eq!(QueueDepth::from_usage(0, 100), QueueDepth::Idle);
+        assert_eq!(QueueDepth::from_usage(10, 100), QueueDepth::Normal);
+        assert_eq!(QueueDepth::from_usage(60, 100), QueueDepth::Busy);
+        assert_eq!(QueueDepth::from_usage(100, 100), QueueDepth::Saturated);
+
+        let hints = LoadHints::default();
+        let hint = LoadHint {
+            queue_depth: QueueDepth::Busy,
+            error_rate: 12,
+        };
+        hints.set_local(hint);
+        assert_eq!(hints.local(), hint);
+        assert!(!hint.is_overloaded());
+    }
+}
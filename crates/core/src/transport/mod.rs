@@ -9,19 +9,31 @@ use tokio::net::UdpSocket;
 
 mod connection_handler;
 mod crypto;
+mod dual_stack;
+mod fair_queue;
+mod load_hint;
+mod multi_transport;
+mod network_id;
 mod packet_data;
 mod peer_connection;
+mod quic;
 mod rate_limiter;
 // todo: optimize trackers
 mod received_packet_tracker;
 mod sent_packet_tracker;
+mod signature_verifier;
 mod symmetric_message;
+mod traffic;
+mod websocket;
 
 type MessagePayload = Vec<u8>;
 
 type PacketId = u32;
 
 pub use self::crypto::{TransportKeypair, TransportPublicKey};
+pub use self::dual_stack::DualStack;
+pub use self::multi_transport::TransportKind;
+pub use self::network_id::NetworkId;
 #[cfg(test)]
 pub(crate) use self::{
     connection_handler::ConnectionEvent,
@@ -32,8 +44,14 @@ pub(crate) use self::{
 pub(crate) use self::{
     connection_handler::{
         create_connection_handler, InboundConnectionHandler, OutboundConnectionHandler,
+        MIN_PROTOC_VERSION, PROTOC_VERSION,
     },
+    dual_stack::{reachable_at, AddressBook},
+    load_hint::{LoadHint, LoadHints, QueueDepth},
     peer_connection::PeerConnection,
+    rate_limiter::BandwidthLimits,
+    signature_verifier::{Overloaded, SignatureVerifier},
+    traffic::TrafficCounters,
 };
 
 #[derive(Debug, thiserror::Error)]
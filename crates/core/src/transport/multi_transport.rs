@@ -0,0 +1,303 @@
+//! Carrying transport packets over more than one kind of network transport.
+//!
+//! Some peers are better reached over a transport other than plain UDP, e.g. those whose
+//! network only lets HTTP traffic through. A node may listen on several transports at once,
+//! ordered by preference. Remotes are dialed over every transport at the same time, and once
+//! one answers the packets to it go through the most preferred transport it was heard over.
+//!
+//! UDP may be listened on over both IP families, the packets to each remote going through the
+//! socket of its family.
+//!
+//! QUIC listens on the port following the UDP one, so when both are enabled it is bound once
+//! the port UDP got is known.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+use super::{dual_stack::AddressBook, quic::QuicTunnel, websocket::WebSocketTunnel, Socket};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Udp,
+    /// Packets tunnelled over WebSocket connections.
+    WebSocket,
+    /// Packets carried over QUIC connections.
+    Quic,
+}
+
+/// Transport to send the packets to each remote over.
+#[derive(Debug)]
+struct Routes {
+    /// Transports of this node, from the most to the least preferred.
+    priority: Vec<TransportKind>,
+    heard_over: HashMap<SocketAddr, TransportKind>,
+}
+
+impl Routes {
+    fn new(priority: Vec<TransportKind>) -> Self {
+        Self {
+            priority,
+            heard_over: HashMap::new(),
+        }
+    }
+
+    fn rank(&self, kind: TransportKind) -> usize {
+        self.priority
+            .iter()
+            .position(|k| *k == kind)
+            .unwrap_or(usize::MAX)
+    }
+
+    fn heard_from(&mut self, remote: SocketAddr, kind: TransportKind) {
+        let rank = self.rank(kind);
+        let preferred = self
+            .heard_over
+            .get(&remote)
+            .map_or(true, |current| rank < self.rank(*current));
+        if preferred {
+            self.heard_over.insert(remote, kind);
+        }
+    }
+
+    /// Transports to send a packet to the remote over, all of them if never heard from it.
+    fn targets(&self, remote: SocketAddr) -> Vec<TransportKind> {
+        match self.heard_over.get(&remote) {
+            Some(kind) => vec![*kind],
+            None => self.priority.clone(),
+        }
+    }
+}
+
+pub(crate) struct MultiTransportSocket {
+    udp_v4: Option<UdpSocket>,
+    udp_v6: Option<UdpSocket>,
+    websocket: Option<WebSocketTunnel>,
+    quic: Option<QuicTunnel>,
+    routes: Mutex<Routes>,
+    addresses: AddressBook,
+}
+
+impl MultiTransportSocket {
+    /// Binds the transports to the address, and UDP to the same port of the secondary address
+    /// too if given, which must be of the other IP family.
+    pub async fn bind_transports(
+        transports: &[TransportKind],
+        addr: SocketAddr,
+        secondary: Option<IpAddr>,
+        addresses: AddressBook,
+    ) -> io::Result<Self> {
+        let mut priority = Vec::with_capacity(transports.len());
+        let (mut udp, mut websocket, mut quic) = (Vec::new(), None, false);
+        for kind in transports {
+            if priority.contains(kind) {
+                continue;
+            }
+            match kind {
+                TransportKind::Udp => match secondary {
+                    None => udp.push(UdpSocket::bind(addr).await?),
+                    Some(secondary) => {
+                        let socket = bind_single_family(addr)?;
+                        let secondary = SocketAddr::new(secondary, socket.local_addr()?.port());
+                        udp.push(socket);
+                        match bind_single_family(secondary) {
+                            Ok(socket) => udp.push(socket),
+                            Err(error) => tracing::warn!(
+                                %secondary,
+                                %error,
+                                "Failed to listen on the other IP family"
+                            ),
+                        }
+                    }
+                },
+                TransportKind::WebSocket => websocket = Some(WebSocketTunnel::bind(addr).await?),
+                TransportKind::Quic => quic = true,
+            }
+            priority.push(*kind);
+        }
+        if priority.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no transport configured",
+            ));
+        }
+        let bound = udp
+            .iter()
+            .map(|socket| socket.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        addresses.set_bound(&bound);
+        let quic = match (quic, bound.first()) {
+            (false, _) => None,
+            (true, Some(udp)) => Some(QuicTunnel::bind(SocketAddr::new(addr.ip(), udp.port()))?),
+            (true, None) => Some(QuicTunnel::bind(addr)?),
+        };
+        let (mut udp_v4, mut udp_v6) = (None, None);
+        for (socket, addr) in udp.into_iter().zip(bound) {
+            match addr {
+                SocketAddr::V4(_) => udp_v4 = Some(socket),
+                SocketAddr::V6(_) => udp_v6 = Some(socket),
+            }
+        }
+        Ok(Self {
+            udp_v4,
+            udp_v6,
+            websocket,
+            quic,
+            routes: Mutex::new(Routes::new(priority)),
+            addresses,
+        })
+    }
+
+    /// UDP socket to send the packets to the address through, preferably of its family.
+    fn udp_for(&self, addr: SocketAddr) -> Option<&UdpSocket> {
+        match addr {
+            SocketAddr::V4(_) => self.udp_v4.as_ref().or(self.udp_v6.as_ref()),
+            SocketAddr::V6(_) => self.udp_v6.as_ref().or(self.udp_v4.as_ref()),
+        }
+    }
+
+    async fn send_over_transports(&self, buf: &[u8], target: SocketAddr) -> io::Result<()> {
+        let transports = self.routes.lock().targets(target);
+        for kind in transports {
+            match kind {
+                TransportKind::Udp => {
+                    if let Some(socket) = self.udp_for(target) {
+                        socket.send_to(buf, target).await?;
+                    }
+                }
+                TransportKind::WebSocket => {
+                    if let Some(tunnel) = &self.websocket {
+                        tunnel.send(buf, target);
+                    }
+                }
+                TransportKind::Quic => {
+                    if let Some(tunnel) = &self.quic {
+                        tunnel.send(buf, target);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Binds a UDP socket which doesn't take traffic of the other IP family, so a socket of the
+/// other family can be bound to the same port.
+fn bind_single_family(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+impl Socket for MultiTransportSocket {
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Self::bind_transports(&[TransportKind::Udp], addr, None, AddressBook::default()).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let udp = async {
+            match (&self.udp_v4, &self.udp_v6) {
+                (Some(v4), Some(v6)) => loop {
+                    // waits for either socket to be readable, so only one of them fills the buffer
+                    let socket = tokio::select! {
+                        ready = v4.readable() => { ready?; v4 }
+                        ready = v6.readable() => { ready?; v6 }
+                    };
+                    match socket.try_recv_from(buf) {
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                        received => break received,
+                    }
+                },
+                (Some(socket), None) | (None, Some(socket)) => socket.recv_from(buf).await,
+                (None, None) => std::future::pending().await,
+            }
+        };
+        let websocket = async {
+            match &self.websocket {
+                Some(tunnel) => tunnel.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let quic = async {
+            match &self.quic {
+                Some(tunnel) => tunnel.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            received = udp => {
+                let (size, remote) = received?;
+                self.routes.lock().heard_from(remote, TransportKind::Udp);
+                Ok((size, self.addresses.received_from(remote)))
+            }
+            received = websocket => {
+                let (packet, remote) = received?;
+                self.routes.lock().heard_from(remote, TransportKind::WebSocket);
+                let size = packet.len().min(buf.len());
+                buf[..size].copy_from_slice(&packet[..size]);
+                Ok((size, self.addresses.received_from(remote)))
+            }
+            received = quic => {
+                let (packet, remote) = received?;
+                self.routes.lock().heard_from(remote, TransportKind::Quic);
+                let size = packet.len().min(buf.len());
+                buf[..size].copy_from_slice(&packet[..size]);
+                Ok((size, self.addresses.received_from(remote)))
+            }
+        }
+    }
+
+    /// Sends the packet to every address the remote has to be tried at, failing only if it
+    /// could not be sent to any.
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let mut outcome = None;
+        for addr in self.addresses.targets(target) {
+            match self.send_over_transports(buf, addr).await {
+                Ok(()) => outcome = Some(Ok(buf.len())),
+                Err(error) => {
+                    tracing::debug!(%addr, %error, "Failed to send packet");
+                    outcome.get_or_insert(Err(error));
+                }
+            }
+        }
+        outcome.unwrap_or(Ok(buf.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_over_preferred_transport_heard() {
+        let mut routes = Routes::new(vec![TransportKind::Udp, TransportKind::WebSocket]);
+        let remote: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+
+        // unknown remotes are dialed over every transport
+        assert_eq!(
+            routes.targets(remote),
+            [TransportKind::Udp, TransportKind::WebSocket]
+        );
+
+        routes.heard_from(remote, TransportKind::WebSocket);
+        assert_eq!(routes.targets(remote), [TransportKind::WebSocket]);
+
+        // once heard over a preferred transport it is not left for a less preferred one
+        routes.heard_from(remote, TransportKind::Udp);
+        routes.heard_from(remote, TransportKind::WebSocket);
+        assert_eq!(routes.targets(remote), [TransportKind::Udp]);
+    }
+}
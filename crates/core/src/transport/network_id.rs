@@ -0,0 +1,52 @@
+//! Identifier of the network (the ring) a peer belongs to.
+//!
+//! Peers exchange their network id when establishing a connection and refuse peers from a
+//! different network, so test networks, private deployments and the main network can run in
+//! parallel without being joined by misconfigured gateways.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NetworkId([u8; NetworkId::LEN]);
+
+impl NetworkId {
+    pub(crate) const LEN: usize = 8;
+    /// Name of the main network, which nodes belong to unless configured otherwise.
+    pub const MAIN: &'static str = "freenet";
+
+    /// Derives the identifier of the network with the given name.
+    pub fn from_name(name: &str) -> Self {
+        let hash = blake3::Hasher::new_derive_key("freenet network id v1")
+            .update(name.as_bytes())
+            .finalize();
+        let mut id = [0; Self::LEN];
+        id.copy_from_slice(&hash.as_bytes()[..Self::LEN]);
+        Self(id)
+    }
+
+    pub(crate) fn from_bytes(id: [u8; Self::LEN]) -> Self {
+        Self(id)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+}
+
+impl Default for NetworkId {
+    fn default() -> Self {
+        Self::from_name(Self::MAIN)
+    }
+}
+
+impl std::fmt::Display for NetworkId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", bs58::encode(self.0).into_string())
+    }
+}
+
+impl std::fmt::Debug for NetworkId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NetworkId").field(&self.to_string()).finish()
+    }
+}
@@ -18,6 +18,7 @@ mod outbound_stream;
 
 use super::{
     connection_handler::SerializedMessage,
+    load_hint::LoadHints,
     packet_data::{self, PacketData},
     received_packet_tracker::ReceivedPacketTracker,
     received_packet_tracker::ReportResult,
@@ -45,6 +46,8 @@ pub(crate) struct RemoteConnection {
     pub(super) inbound_symmetric_key: Aes128Gcm,
     pub(super) inbound_symmetric_key_bytes: [u8; 16],
     pub(super) my_address: Option<SocketAddr>,
+    /// Whether the remote said in its intro packet that it understands greetings.
+    pub(super) remote_greets: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -94,6 +97,9 @@ pub(crate) struct PeerConnection {
     inbound_streams: HashMap<StreamId, mpsc::Sender<(u32, Vec<u8>)>>,
     inbound_stream_futures: FuturesUnordered<JoinHandle<InboundStreamResult>>,
     outbound_stream_futures: FuturesUnordered<JoinHandle<Result>>,
+    load_hints: Option<LoadHints>,
+    /// Whether the remote said it reads the load hints, older peers fail decoding them.
+    remote_reads_load: bool,
 }
 
 impl std::fmt::Debug for PeerConnection {
@@ -126,9 +132,23 @@ impl PeerConnection {
             inbound_streams: HashMap::new(),
             inbound_stream_futures: FuturesUnordered::new(),
             outbound_stream_futures: FuturesUnordered::new(),
+            load_hints: None,
+            remote_reads_load: false,
         }
     }
 
+    /// Exchange load hints with the remote peer in keep-alive messages. The local load is only
+    /// sent once the remote said it reads it, see [`Self::send_load_hints`].
+    pub fn exchange_load_hints(&mut self, load_hints: LoadHints) {
+        self.load_hints = Some(load_hints);
+    }
+
+    /// Carry the local load in the keep-alive messages, once the remote greeted saying it
+    /// reads it.
+    pub fn send_load_hints(&mut self) {
+        self.remote_reads_load = true;
+    }
+
     #[cfg(test)]
     pub(crate) fn new_test(
         remote_addr: SocketAddr,
@@ -149,6 +169,7 @@ impl PeerConnection {
             inbound_symmetric_key,
             inbound_symmetric_key_bytes: [1; 16],
             my_address: Some(my_address),
+            remote_greets: true,
         };
         (
             Self::new(remote),
@@ -178,6 +199,7 @@ impl PeerConnection {
                 inbound_symmetric_key,
                 inbound_symmetric_key_bytes: [1; 16],
                 my_address: Some(my_address),
+                remote_greets: true,
             },
             inbound_packet_sender,
             outbound_packets_recv,
@@ -192,6 +214,11 @@ impl PeerConnection {
         let data = tokio::task::spawn_blocking(move || bincode::serialize(&data).unwrap())
             .await
             .unwrap();
+        self.send_frame(data).await
+    }
+
+    /// Sends data already serialized, received as is by the remote.
+    pub(crate) async fn send_frame(&mut self, data: SerializedMessage) -> Result {
         if data.len() + SymmetricMessage::short_message_overhead() > MAX_DATA_SIZE {
             tracing::trace!("sending as stream");
             self.outbound_stream(data).await;
@@ -302,7 +329,7 @@ impl PeerConnection {
                         return Err(TransportError::ConnectionClosed(self.remote_addr()));
                     }
                     tracing::trace!(remote = ?self.remote_conn.remote_addr, "sending keep-alive");
-                    self.noop(vec![]).await?;
+                    self.keep_alive().await?;
                 }
                 _ = resend_check.take().unwrap_or(tokio::time::sleep(Duration::from_millis(10))) => {
                     loop {
@@ -336,6 +363,12 @@ impl PeerConnection {
         self.remote_conn.my_address
     }
 
+    /// Whether the remote said it understands greetings while connecting. Remotes which did
+    /// not may still greet this peer first.
+    pub fn remote_greets(&self) -> bool {
+        self.remote_conn.remote_greets
+    }
+
     pub fn remote_addr(&self) -> SocketAddr {
         self.remote_conn.remote_addr
     }
@@ -393,9 +426,35 @@ impl PeerConnection {
                 Ok(None)
             }
             NoOp => Ok(None),
+            KeepAlive { load } => {
+                if let Some(load_hints) = &self.load_hints {
+                    load_hints.record_remote(self.remote_conn.remote_addr, load);
+                }
+                Ok(None)
+            }
         }
     }
 
+    #[inline]
+    async fn keep_alive(&mut self) -> Result<()> {
+        let Some(load_hints) = self.load_hints.as_ref().filter(|_| self.remote_reads_load) else {
+            return self.noop(vec![]).await;
+        };
+        let load = load_hints.local();
+        packet_sending(
+            self.remote_conn.remote_addr,
+            &self.remote_conn.outbound_packets,
+            self.remote_conn
+                .last_packet_id
+                .fetch_add(1, std::sync::atomic::Ordering::Release),
+            &self.remote_conn.outbound_symmetric_key,
+            vec![],
+            SymmetricMessagePayload::KeepAlive { load },
+            &self.remote_conn.sent_tracker,
+        )
+        .await
+    }
+
     #[inline]
     async fn noop(&mut self, receipts: Vec<u32>) -> Result<()> {
         packet_sending(
@@ -432,6 +491,35 @@ impl PeerConnection {
         Ok(())
     }
 
+    /// Sends a short message once to another address of the peer, to check whether the peer
+    /// can be reached at it. Not resent if lost.
+    pub(crate) async fn probe_address<T>(&mut self, addr: SocketAddr, data: T) -> Result
+    where
+        T: Serialize,
+    {
+        let data = bincode::serialize(&data)?;
+        let packet_id = self
+            .remote_conn
+            .last_packet_id
+            .fetch_add(1, std::sync::atomic::Ordering::Release);
+        let either::Either::Left(packet) = SymmetricMessage::try_serialize_msg_to_packet_data(
+            packet_id,
+            symmetric_message::ShortMessage(data),
+            &self.remote_conn.outbound_symmetric_key,
+            vec![],
+        )?
+        else {
+            return Err(TransportError::Other(anyhow::anyhow!(
+                "probe does not fit in a single packet"
+            )));
+        };
+        self.remote_conn
+            .outbound_packets
+            .send((addr, packet.prepared_send()))
+            .await
+            .map_err(|_| TransportError::ConnectionClosed(self.remote_conn.remote_addr))
+    }
+
     async fn outbound_stream(&mut self, data: SerializedMessage) {
         let stream_id = StreamId::next();
         let task = tokio::spawn(
@@ -617,4 +705,41 @@ mod tests {
         assert_eq!(message, inbound_msg);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn load_only_sent_to_remotes_reading_it() -> Result<(), Box<dyn std::error::Error>> {
+        let key = Aes128Gcm::new(&rand::random::<[u8; 16]>().into());
+        let (mut conn, _inbound, mut outbound) = PeerConnection::new_test(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8080),
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8081),
+            key.clone(),
+            key.clone(),
+        );
+        conn.exchange_load_hints(LoadHints::default());
+        async fn keep_alive(
+            conn: &mut PeerConnection,
+            outbound: &mut mpsc::Receiver<(SocketAddr, Arc<[u8]>)>,
+            key: &Aes128Gcm,
+        ) -> Result<SymmetricMessagePayload, Box<dyn std::error::Error>> {
+            conn.keep_alive().await?;
+            let (_, packet) = outbound.recv().await.ok_or("connection closed")?;
+            let decrypted = PacketData::<_, MAX_PACKET_SIZE>::from_buf(&packet)
+                .try_decrypt_sym(key)
+                .map_err(|e| e.to_string())?;
+            Ok(SymmetricMessage::deser(decrypted.data())?.payload)
+        }
+
+        // peers which did not greet saying they read it fail decoding the load
+        assert_eq!(
+            keep_alive(&mut conn, &mut outbound, &key).await?,
+            SymmetricMessagePayload::NoOp
+        );
+
+        conn.send_load_hints();
+        assert!(matches!(
+            keep_alive(&mut conn, &mut outbound, &key).await?,
+            SymmetricMessagePayload::KeepAlive { .. }
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,367 @@
+//! Transport packets carried over QUIC connections, for networks whose middleboxes let QUIC
+//! through more readily than bare UDP.
+//!
+//! Each packet travels in a unidirectional stream of its own, so the layers above see the same
+//! datagrams they would over UDP, whatever the MTU of the path. The encryption of QUIC is only an
+//! envelope: certificates are self-signed and left unverified, the remotes being authenticated by
+//! the connections carried on top as over any other transport.
+//!
+//! The UDP transport takes the port of the node, so the QUIC endpoint listens on the next one. A
+//! peer listening at an address is dialed at the next port, and the connections it dials come
+//! from that port too, so the address of the remote is known without announcing it.
+//!
+//! A connection which fails to be dialed is redialed with an exponential backoff, the packets for
+//! the remote queueing meanwhile. Streams longer than a transport packet are refused.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use quinn::{
+    crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint, IdleTimeout,
+    ServerConfig, TransportConfig, VarInt,
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use tokio::sync::{mpsc, Mutex, Semaphore};
+
+use super::packet_data::MAX_PACKET_SIZE;
+use crate::{config::GlobalExecutor, util::ExponentialBackoff};
+
+/// Offset from the port of the node to the one the QUIC endpoint listens on.
+const PORT_OFFSET: u16 = 1;
+
+/// Name the self-signed certificates are issued for, and the remotes dialed as.
+const SERVER_NAME: &str = "freenet";
+
+/// Packets queued for a remote before they are dropped, the layers above resend them anyway.
+const QUEUED_PACKETS: usize = 100;
+
+/// Max number of connections accepted from remotes open at the same time.
+const MAX_INBOUND_CONNECTIONS: usize = 256;
+
+/// Packets in flight over a connection at the same time, each in a stream of its own.
+const MAX_STREAMS: u32 = 1024;
+
+/// Times a connection is redialed before giving up on the remote, until the next packet for it.
+const MAX_DIAL_ATTEMPTS: usize = 5;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(20);
+
+type Connections = Arc<DashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>;
+
+pub(super) struct QuicTunnel {
+    endpoint: Endpoint,
+    connections: Connections,
+    inbound: Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+}
+
+impl QuicTunnel {
+    /// Binds the endpoint to the port following the one of the address, or to any port if
+    /// none is given.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let local = match addr.port() {
+            0 => addr,
+            _ => quic_addr(addr)?,
+        };
+        let mut endpoint = Endpoint::server(server_config()?, local)?;
+        endpoint.set_default_client_config(client_config()?);
+        let (inbound_sender, inbound) = mpsc::channel(QUEUED_PACKETS);
+        let connections = Connections::default();
+        GlobalExecutor::spawn(accept_connections(
+            endpoint.clone(),
+            connections.clone(),
+            inbound_sender.clone(),
+        ));
+        Ok(Self {
+            endpoint,
+            connections,
+            inbound: Mutex::new(inbound),
+            inbound_sender,
+        })
+    }
+
+    pub async fn recv(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        self.inbound
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Queues the packet for the remote, dialing it if there is no connection with it yet.
+    pub fn send(&self, packet: &[u8], target: SocketAddr) {
+        let connection = self
+            .connections
+            .entry(target)
+            .or_insert_with(|| {
+                let (outbound_sender, outbound) = mpsc::channel(QUEUED_PACKETS);
+                GlobalExecutor::spawn(dial_connection(
+                    self.endpoint.clone(),
+                    target,
+                    outbound_sender.clone(),
+                    outbound,
+                    self.connections.clone(),
+                    self.inbound_sender.clone(),
+                ));
+                outbound_sender
+            })
+            .clone();
+        if connection.try_send(packet.to_vec()).is_err() {
+            tracing::debug!(%target, "QUIC connection congested, dropping packet");
+        }
+    }
+}
+
+impl Drop for QuicTunnel {
+    fn drop(&mut self) {
+        self.endpoint.close(VarInt::from_u32(0), b"");
+    }
+}
+
+/// Address the QUIC endpoint of a peer listening at the given address is at.
+fn quic_addr(addr: SocketAddr) -> io::Result<SocketAddr> {
+    let port = addr.port().checked_add(PORT_OFFSET).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no port left to listen on QUIC",
+        )
+    })?;
+    Ok(SocketAddr::new(addr.ip(), port))
+}
+
+/// Address the peer with the QUIC endpoint at the given address is listening at.
+fn listening_addr(addr: SocketAddr) -> Option<SocketAddr> {
+    let port = addr.port().checked_sub(PORT_OFFSET)?;
+    Some(SocketAddr::new(addr.ip(), port))
+}
+
+fn transport_config() -> io::Result<Arc<TransportConfig>> {
+    let mut transport = TransportConfig::default();
+    transport
+        .max_concurrent_uni_streams(VarInt::from_u32(MAX_STREAMS))
+        .max_concurrent_bidi_streams(VarInt::from_u32(0))
+        .max_idle_timeout(Some(
+            IdleTimeout::try_from(IDLE_TIMEOUT).map_err(io::Error::other)?,
+        ))
+        .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    Ok(Arc::new(transport))
+}
+
+fn server_config() -> io::Result<ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])
+        .map_err(io::Error::other)?;
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let mut config = ServerConfig::with_single_cert(vec![certified.cert.der().clone()], key.into())
+        .map_err(io::Error::other)?;
+    config.transport_config(transport_config()?);
+    Ok(config)
+}
+
+fn client_config() -> io::Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(UnverifiedCertificates(provider)))
+        .with_no_client_auth();
+    let mut config = ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(crypto).map_err(io::Error::other)?,
+    ));
+    config.transport_config(transport_config()?);
+    Ok(config)
+}
+
+/// Accepts any certificate, checking only the handshake is signed by it.
+#[derive(Debug)]
+struct UnverifiedCertificates(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for UnverifiedCertificates {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+async fn accept_connections(
+    endpoint: Endpoint,
+    connections: Connections,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    let open = Arc::new(Semaphore::new(MAX_INBOUND_CONNECTIONS));
+    while let Some(incoming) = endpoint.accept().await {
+        let from = incoming.remote_address();
+        let Some(remote) = listening_addr(from) else {
+            incoming.refuse();
+            continue;
+        };
+        let Ok(permit) = open.clone().try_acquire_owned() else {
+            tracing::debug!(%from, "Too many QUIC connections open, refusing");
+            incoming.refuse();
+            continue;
+        };
+        let connections = connections.clone();
+        let inbound_sender = inbound_sender.clone();
+        GlobalExecutor::spawn(async move {
+            let _permit = permit;
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    tracing::debug!(%error, %from, "QUIC handshake failed");
+                    return;
+                }
+            };
+            // the address is the one packets come from, as over UDP, so it takes over the
+            // connection dialed to the remote if any
+            let (outbound_sender, outbound) = mpsc::channel(QUEUED_PACKETS);
+            connections.insert(remote, outbound_sender.clone());
+            pump(connection, remote, outbound, inbound_sender).await;
+            connections.remove_if(&remote, |_, connection| {
+                connection.same_channel(&outbound_sender)
+            });
+        });
+    }
+}
+
+async fn dial_connection(
+    endpoint: Endpoint,
+    remote: SocketAddr,
+    outbound_sender: mpsc::Sender<Vec<u8>>,
+    outbound: mpsc::Receiver<Vec<u8>>,
+    connections: Connections,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    let mut backoff = ExponentialBackoff::new(
+        Duration::from_secs(1),
+        Duration::from_secs(30),
+        MAX_DIAL_ATTEMPTS,
+    );
+    loop {
+        match dial(&endpoint, remote).await {
+            Ok(connection) => {
+                pump(connection, remote, outbound, inbound_sender).await;
+                break;
+            }
+            Err(error) => {
+                tracing::debug!(%error, %remote, attempt = backoff.retries(), "Failed dialing QUIC connection");
+                if backoff.sleep().await.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+    connections.remove_if(&remote, |_, connection| {
+        connection.same_channel(&outbound_sender)
+    });
+}
+
+async fn dial(endpoint: &Endpoint, remote: SocketAddr) -> io::Result<Connection> {
+    endpoint
+        .connect(quic_addr(remote)?, SERVER_NAME)
+        .map_err(io::Error::other)?
+        .await
+        .map_err(io::Error::other)
+}
+
+/// Moves packets through the connection until either end closes it.
+async fn pump(
+    connection: Connection,
+    remote: SocketAddr,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    loop {
+        tokio::select! {
+            packet = outbound.recv() => {
+                let Some(packet) = packet else { break };
+                let sent = async {
+                    let mut stream = connection.open_uni().await.map_err(io::Error::other)?;
+                    stream.write_all(&packet).await.map_err(io::Error::other)?;
+                    stream.finish().map_err(io::Error::other)
+                };
+                if let Err(error) = sent.await {
+                    tracing::debug!(%error, %remote, "Failed sending packet over QUIC");
+                    break;
+                }
+            }
+            stream = connection.accept_uni() => {
+                let Ok(mut stream) = stream else { break };
+                let inbound_sender = inbound_sender.clone();
+                GlobalExecutor::spawn(async move {
+                    match stream.read_to_end(MAX_PACKET_SIZE).await {
+                        Ok(packet) => {
+                            let _ = inbound_sender.send((packet, remote)).await;
+                        }
+                        Err(error) => {
+                            tracing::debug!(%error, %remote, "Failed reading packet over QUIC");
+                        }
+                    }
+                });
+            }
+        }
+    }
+    connection.close(VarInt::from_u32(0), b"");
+    tracing::debug!(%remote, "QUIC connection closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remotes_are_dialed_at_the_next_port() {
+        let remote: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let quic = quic_addr(remote).unwrap();
+        assert_eq!(quic.port(), 4001);
+        assert_eq!(listening_addr(quic), Some(remote));
+
+        assert!(quic_addr("10.0.0.1:65535".parse().unwrap()).is_err());
+        assert_eq!(listening_addr("10.0.0.1:0".parse().unwrap()), None);
+    }
+}
@@ -1,13 +1,47 @@
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 use std::collections::VecDeque;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::Socket;
+use super::{fair_queue::FairQueue, packet_data::MAX_PACKET_SIZE, Socket};
 use crate::util::time_source::{InstantTimeSrc, TimeSource};
 
+/// Packets waiting to be scheduled before the senders are made to wait.
+const MAX_QUEUED_PACKETS: usize = 1024;
+/// Time after which remotes which used up their share of the bandwidth are checked again.
+const THROTTLED_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Upstream bandwidth limits of the transport.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BandwidthLimits {
+    /// Max bytes per second sent to all the remotes together.
+    pub total: usize,
+    /// Max fraction of the total bandwidth a single remote may use.
+    pub remote_share: f64,
+}
+
+impl Default for BandwidthLimits {
+    fn default() -> Self {
+        Self {
+            total: crate::config::DEFAULT_UPSTREAM_BANDWIDTH_LIMIT,
+            remote_share: crate::config::DEFAULT_PEER_BANDWIDTH_SHARE,
+        }
+    }
+}
+
+impl BandwidthLimits {
+    fn window_limit(&self, window_size: Duration) -> usize {
+        (self.total as f64 * window_size.as_secs_f64()) as usize
+    }
+
+    fn remote_limit(&self, window_size: Duration) -> Option<usize> {
+        (self.remote_share < 1.0)
+            .then(|| (self.window_limit(window_size) as f64 * self.remote_share) as usize)
+    }
+}
+
 /// Keeps track of the bandwidth used in the last window_size. Recommend a `window_size` of
 /// 10 seconds.
 pub(super) struct PacketRateLimiter<T: TimeSource> {
@@ -34,9 +68,57 @@ impl PacketRateLimiter<InstantTimeSrc> {
 }
 
 impl<T: TimeSource> PacketRateLimiter<T> {
-    pub(super) async fn rate_limiter<S: Socket>(mut self, bandwidth_limit: usize, socket: Arc<S>) {
+    /// Sends the outbound packets within the bandwidth limits, which may change while it runs.
+    pub(super) async fn rate_limiter<S: Socket>(
+        mut self,
+        mut limits: watch::Receiver<BandwidthLimits>,
+        socket: Arc<S>,
+    ) {
+        let current = *limits.borrow_and_update();
+        let mut bandwidth_limit = current.window_limit(self.window_size);
         tracing::info!(bandwidth_limit, "Rate limiter task started");
-        while let Some((socket_addr, packet)) = self.outbound_packets.recv().await {
+        let mut queue = FairQueue::new(
+            MAX_PACKET_SIZE,
+            current.remote_limit(self.window_size),
+            self.window_size,
+        );
+        let mut closed = false;
+        loop {
+            if limits.has_changed().unwrap_or(false) {
+                let current = *limits.borrow_and_update();
+                bandwidth_limit = current.window_limit(self.window_size);
+                queue.set_remote_limit(current.remote_limit(self.window_size));
+                tracing::info!(bandwidth_limit, "Bandwidth limits changed");
+            }
+            // queue up the packets handed over meanwhile, so they are scheduled fairly
+            while !closed && queue.len() < MAX_QUEUED_PACKETS {
+                match self.outbound_packets.try_recv() {
+                    Ok((socket_addr, packet)) => queue.push(socket_addr, packet),
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => closed = true,
+                }
+            }
+            let Some((socket_addr, packet)) = queue.pop(self.time_source.now()) else {
+                if queue.is_empty() {
+                    if closed {
+                        break;
+                    }
+                    match self.outbound_packets.recv().await {
+                        Some((socket_addr, packet)) => queue.push(socket_addr, packet),
+                        None => closed = true,
+                    }
+                } else {
+                    // every remote with packets waiting used up its share of the bandwidth
+                    tokio::select! {
+                        received = self.outbound_packets.recv(), if !closed => match received {
+                            Some((socket_addr, packet)) => queue.push(socket_addr, packet),
+                            None => closed = true,
+                        },
+                        _ = tokio::time::sleep(THROTTLED_RETRY_INTERVAL) => {}
+                    }
+                }
+                continue;
+            };
             if let Some(wait_time) = self.can_send_packet(bandwidth_limit, packet.len()) {
                 tokio::time::sleep(wait_time).await;
                 if let Err(error) = socket.send_to(&packet, socket_addr).await {
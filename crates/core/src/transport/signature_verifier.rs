@@ -0,0 +1,213 @@
+//! Verification of signatures off the async executor threads.
+//!
+//! RSA verification is expensive enough to stall the executor under bursts of signed
+//! messages, so it runs in a small pool of dedicated threads. Successful verifications are
+//! cached by signing key and payload hash, so the same payload arriving several times (e.g.
+//! relayed by different neighbours) is only verified once. The queue of signatures waiting to
+//! be verified is bounded, further ones are rejected as overload until it drains.
+
+use std::sync::Arc;
+
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use stretto::Cache;
+use tokio::sync::oneshot;
+
+use super::TransportPublicKey;
+
+/// Number of verified signatures remembered.
+const CACHE_SIZE: i64 = 4_096;
+/// Max number of signatures waiting to be verified.
+const QUEUE_CAPACITY: usize = 4_096;
+
+type CacheKey = (TransportPublicKey, [u8; 32]);
+
+#[derive(Debug, thiserror::Error)]
+#[error("too many signatures waiting to be verified")]
+pub(crate) struct Overloaded;
+
+enum Submission {
+    Verified(bool),
+    Pending(oneshot::Receiver<bool>),
+}
+
+struct Job {
+    key: TransportPublicKey,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+    result: oneshot::Sender<bool>,
+}
+
+/// Handle to the signature verification pool, cheap to clone.
+#[derive(Clone)]
+pub(crate) struct SignatureVerifier {
+    jobs: Sender<Job>,
+    /// Hash of the valid signature per signing key and payload hash.
+    cache: Arc<Cache<CacheKey, [u8; 32]>>,
+}
+
+impl SignatureVerifier {
+    /// Starts a verification pool with the given number of threads, which are stopped
+    /// once every handle to the pool is dropped.
+    pub fn new(threads: usize) -> Self {
+        let (jobs, rx) = channel::bounded(QUEUE_CAPACITY);
+        let cache = Arc::new(
+            Cache::new(CACHE_SIZE as usize * 10, CACHE_SIZE)
+                .expect("failed to build signature cache"),
+        );
+        for i in 0..threads.max(1) {
+            let rx = rx.clone();
+            let cache = cache.clone();
+            std::thread::Builder::new()
+                .name(format!("signature-verifier-{i}"))
+                .spawn(move || verification_worker(rx, cache))
+                .expect("failed to spawn signature verifier thread");
+        }
+        Self { jobs, cache }
+    }
+
+    /// Verifies the signature of the given payload was produced by the owner of the key.
+    /// Fails if the pool is overloaded, in which case the signature was not checked.
+    pub async fn verify(
+        &self,
+        key: &TransportPublicKey,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<bool, Overloaded> {
+        Ok(match self.submit(key.clone(), payload, signature)? {
+            Submission::Verified(result) => result,
+            Submission::Pending(pending) => pending.await.unwrap_or(false),
+        })
+    }
+
+    /// Verifies a batch of `(key, payload, signature)` entries, spreading them across the
+    /// pool; the results are returned in the same order.
+    pub async fn verify_batch(
+        &self,
+        batch: impl IntoIterator<Item = (TransportPublicKey, Vec<u8>, Vec<u8>)>,
+    ) -> Vec<Result<bool, Overloaded>> {
+        let submitted: Vec<_> = batch
+            .into_iter()
+            .map(|(key, payload, signature)| self.submit(key, payload, signature))
+            .collect();
+        let mut results = Vec::with_capacity(submitted.len());
+        for entry in submitted {
+            results.push(match entry {
+                Ok(Submission::Verified(result)) => Ok(result),
+                Ok(Submission::Pending(pending)) => Ok(pending.await.unwrap_or(false)),
+                Err(overloaded) => Err(overloaded),
+            });
+        }
+        results
+    }
+
+    /// Returns the result right away if the signature was already verified, otherwise queues
+    /// the verification in the pool if it is not full.
+    fn submit(
+        &self,
+        key: TransportPublicKey,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<Submission, Overloaded> {
+        let cache_key = (key, *blake3::hash(&payload).as_bytes());
+        let signature_hash = *blake3::hash(&signature).as_bytes();
+        if let Some(cached) = self.cache.get(&cache_key) {
+            if *cached.value() == signature_hash {
+                return Ok(Submission::Verified(true));
+            }
+        }
+        let (result, pending) = oneshot::channel();
+        let job = Job {
+            key: cache_key.0,
+            payload,
+            signature,
+            result,
+        };
+        match self.jobs.try_send(job) {
+            Ok(()) => Ok(Submission::Pending(pending)),
+            Err(TrySendError::Full(_)) => {
+                tracing::debug!("Signature verification queue full, rejecting signature");
+                Err(Overloaded)
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                tracing::error!("Signature verification pool stopped");
+                Ok(Submission::Verified(false))
+            }
+        }
+    }
+}
+
+fn verification_worker(jobs: Receiver<Job>, cache: Arc<Cache<CacheKey, [u8; 32]>>) {
+    while let Ok(job) = jobs.recv() {
+        let valid = job.key.verify(&job.payload, &job.signature);
+        if valid {
+            let cache_key = (job.key, *blake3::hash(&job.payload).as_bytes());
+            cache.insert(cache_key, *blake3::hash(&job.signature).as_bytes(), 1);
+        }
+        // the requester may have given up waiting
+        let _ = job.result.send(valid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportKeypair;
+
+    #[tokio::test]
+    async fn verify_batch_and_cache() {
+        let verifier = SignatureVerifier::new(2);
+        let keypair = TransportKeypair::new();
+        let other = TransportKeypair::new();
+        let payload = b"signed payload".to_vec();
+        let signature = keypair.sign(&payload);
+
+        let results = verifier
+            .verify_batch([
+                (keypair.public().clone(), payload.clone(), signature.clone()),
+                (other.public().clone(), payload.clone(), signature.clone()),
+                (
+                    keypair.public().clone(),
+                    b"tampered".to_vec(),
+                    signature.clone(),
+                ),
+            ])
+            .await;
+        let results: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, [true, false, false]);
+
+        verifier.cache.wait().unwrap();
+        assert!(matches!(
+            verifier.submit(keypair.public().clone(), payload.clone(), signature.clone()),
+            Ok(Submission::Verified(true))
+        ));
+        // a cached payload is not accepted along some other signature
+        let forged = other.sign(&payload);
+        assert!(!verifier
+            .verify(keypair.public(), payload, forged)
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn full_queue_rejects_signatures() {
+        // no workers taking jobs from the queue
+        let (jobs, _rx) = channel::bounded(QUEUE_CAPACITY);
+        let verifier = SignatureVerifier {
+            jobs,
+            cache: Arc::new(Cache::new(100, 10).unwrap()),
+        };
+        let keypair = TransportKeypair::new();
+        let signature = keypair.sign(b"payload");
+        let submit = |i: usize| {
+            verifier.submit(
+                keypair.public().clone(),
+                i.to_le_bytes().to_vec(),
+                signature.clone(),
+            )
+        };
+        for i in 0..QUEUE_CAPACITY {
+            assert!(matches!(submit(i), Ok(Submission::Pending(_))));
+        }
+        assert!(matches!(submit(QUEUE_CAPACITY), Err(Overloaded)));
+    }
+}
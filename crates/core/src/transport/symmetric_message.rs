@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
 use super::{
-    packet_data::PacketData, packet_data::MAX_DATA_SIZE, peer_connection::StreamId, MessagePayload,
-    PacketId,
+    load_hint::LoadHint, packet_data::PacketData, packet_data::MAX_DATA_SIZE,
+    peer_connection::StreamId, MessagePayload, PacketId,
 };
 
 #[serde_as]
@@ -237,6 +237,10 @@ pub(crate) enum SymmetricMessagePayload {
         payload: MessagePayload,
     },
     NoOp,
+    /// Keep-alive message carrying the load hint of the sender.
+    KeepAlive {
+        load: LoadHint,
+    },
 }
 
 #[cfg(test)]
@@ -263,6 +267,7 @@ impl std::fmt::Display for SymmetricMessagePayload {
                 stream_id, fragment_number
             ),
             SymmetricMessagePayload::NoOp => write!(f, "NoOp"),
+            SymmetricMessagePayload::KeepAlive { load } => write!(f, "KeepAlive: {load:?}"),
         }
     }
 }
@@ -320,6 +325,9 @@ mod test {
                     .collect(),
             },
             SymmetricMessagePayload::NoOp,
+            SymmetricMessagePayload::KeepAlive {
+                load: LoadHint::default(),
+            },
         ];
         let key = gen_key();
 
@@ -0,0 +1,81 @@
+//! Counting of the packets and bytes sent and received over the transport sockets.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use super::Socket;
+
+/// Totals of the traffic of this node since it started.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TrafficStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TrafficCounters {
+    packets_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl TrafficCounters {
+    fn sent(&self, bytes: usize) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn received(&self, bytes: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> TrafficStats {
+        TrafficStats {
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Socket accounting for the traffic going through the socket it wraps.
+pub(super) struct CountedSocket<S> {
+    inner: S,
+    counters: Arc<TrafficCounters>,
+}
+
+impl<S: Socket> CountedSocket<S> {
+    pub fn new(inner: S, counters: Arc<TrafficCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<S: Socket> Socket for CountedSocket<S> {
+    async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self::new(S::bind(addr).await?, Arc::default()))
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (size, remote) = self.inner.recv_from(buf).await?;
+        self.counters.received(size);
+        Ok((size, remote))
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let size = self.inner.send_to(buf, target).await?;
+        self.counters.sent(size);
+        Ok(size)
+    }
+}
@@ -0,0 +1,257 @@
+//! Transport packets tunnelled over WebSocket connections, for peers whose network only lets
+//! HTTP traffic through.
+//!
+//! Each packet travels in a binary frame of its own, so the layers above see the same datagrams
+//! they would over UDP, with the encryption and reliability they already provide on top. The
+//! tunnel listens on the TCP port with the same number as the UDP one, so a peer is reached at
+//! the same address over either transport. Since the port a connection comes from is not the one
+//! the remote peer listens on, the first frame in a connection carries the listening port of the
+//! peer dialing it.
+//!
+//! A tunnel which fails to be dialed is redialed with an exponential backoff, the packets for
+//! the remote queueing meanwhile. Frames larger than a transport packet are refused.
+//!
+//! The announced port is not proven, so an inbound tunnel does not take over the address of a
+//! tunnel already open: the packets to the address keep going through the tunnel it was bound
+//! to first, while those received over the new one are still delivered, authenticated by the
+//! connections on top as any other packet.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex, Semaphore},
+};
+use tokio_tungstenite::{
+    tungstenite::{protocol::WebSocketConfig, Message},
+    WebSocketStream,
+};
+
+use super::packet_data::MAX_PACKET_SIZE;
+use crate::{config::GlobalExecutor, util::ExponentialBackoff};
+
+/// Packets queued for a remote before they are dropped, the layers above resend them anyway.
+const QUEUED_PACKETS: usize = 100;
+
+/// Max number of tunnels accepted from remotes open at the same time.
+const MAX_INBOUND_TUNNELS: usize = 256;
+
+/// Time an accepted tunnel has to complete the handshake and announce its port.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Times a tunnel is redialed before giving up on the remote, until the next packet for it.
+const MAX_DIAL_ATTEMPTS: usize = 5;
+
+/// Tunnels carry a transport packet per frame, and the port announcement, so nothing larger.
+fn tunnel_config() -> WebSocketConfig {
+    WebSocketConfig::default()
+        .max_frame_size(Some(MAX_PACKET_SIZE))
+        .max_message_size(Some(MAX_PACKET_SIZE))
+}
+
+type Tunnels = Arc<DashMap<SocketAddr, mpsc::Sender<Vec<u8>>>>;
+
+pub(super) struct WebSocketTunnel {
+    listen_port: u16,
+    tunnels: Tunnels,
+    inbound: Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+}
+
+impl WebSocketTunnel {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let (inbound_sender, inbound) = mpsc::channel(QUEUED_PACKETS);
+        let tunnels = Tunnels::default();
+        GlobalExecutor::spawn(accept_tunnels(
+            listener,
+            tunnels.clone(),
+            inbound_sender.clone(),
+        ));
+        Ok(Self {
+            listen_port: addr.port(),
+            tunnels,
+            inbound: Mutex::new(inbound),
+            inbound_sender,
+        })
+    }
+
+    pub async fn recv(&self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        self.inbound
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Queues the packet for the remote, dialing it if there is no tunnel with it yet.
+    pub fn send(&self, packet: &[u8], target: SocketAddr) {
+        let tunnel = self
+            .tunnels
+            .entry(target)
+            .or_insert_with(|| {
+                let (outbound_sender, outbound) = mpsc::channel(QUEUED_PACKETS);
+                GlobalExecutor::spawn(dial_tunnel(
+                    target,
+                    self.listen_port,
+                    outbound,
+                    self.tunnels.clone(),
+                    self.inbound_sender.clone(),
+                ));
+                outbound_sender
+            })
+            .clone();
+        if tunnel.try_send(packet.to_vec()).is_err() {
+            tracing::debug!(%target, "WebSocket tunnel congested, dropping packet");
+        }
+    }
+}
+
+async fn accept_tunnels(
+    listener: TcpListener,
+    tunnels: Tunnels,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    let open = Arc::new(Semaphore::new(MAX_INBOUND_TUNNELS));
+    loop {
+        let (stream, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                tracing::debug!(%error, "Failed accepting WebSocket tunnel");
+                continue;
+            }
+        };
+        let Ok(permit) = open.clone().try_acquire_owned() else {
+            tracing::debug!(%from, "Too many WebSocket tunnels open, refusing");
+            continue;
+        };
+        let tunnels = tunnels.clone();
+        let inbound_sender = inbound_sender.clone();
+        GlobalExecutor::spawn(async move {
+            let _permit = permit;
+            let Ok(Some((ws, remote))) =
+                tokio::time::timeout(HANDSHAKE_TIMEOUT, accept_handshake(stream, from)).await
+            else {
+                tracing::debug!(%from, "WebSocket tunnel handshake failed");
+                return;
+            };
+            let (outbound_sender, outbound) = mpsc::channel(QUEUED_PACKETS);
+            let bound = match tunnels.entry(remote) {
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    entry.insert(outbound_sender.clone());
+                    true
+                }
+                dashmap::mapref::entry::Entry::Occupied(_) => {
+                    tracing::debug!(%remote, "Address already tunnelled, only receiving over the new tunnel");
+                    false
+                }
+            };
+            pump(ws, remote, outbound, inbound_sender).await;
+            if bound {
+                tunnels.remove_if(&remote, |_, tunnel| tunnel.same_channel(&outbound_sender));
+            }
+        });
+    }
+}
+
+/// Completes the WebSocket handshake of an accepted tunnel and reads the port announced by the
+/// remote.
+async fn accept_handshake(
+    stream: TcpStream,
+    from: SocketAddr,
+) -> Option<(WebSocketStream<TcpStream>, SocketAddr)> {
+    let mut ws = tokio_tungstenite::accept_async_with_config(stream, Some(tunnel_config()))
+        .await
+        .inspect_err(|error| tracing::debug!(%error, %from, "Failed WebSocket tunnel handshake"))
+        .ok()?;
+    match ws.next().await {
+        Some(Ok(Message::Binary(port))) if port.len() == 2 => Some((
+            ws,
+            SocketAddr::new(from.ip(), u16::from_be_bytes([port[0], port[1]])),
+        )),
+        _ => {
+            tracing::debug!(%from, "WebSocket tunnel did not announce its port");
+            None
+        }
+    }
+}
+
+async fn dial_tunnel(
+    remote: SocketAddr,
+    listen_port: u16,
+    outbound: mpsc::Receiver<Vec<u8>>,
+    tunnels: Tunnels,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) {
+    let mut backoff = ExponentialBackoff::new(
+        Duration::from_secs(1),
+        Duration::from_secs(30),
+        MAX_DIAL_ATTEMPTS,
+    );
+    loop {
+        match dial(remote, listen_port).await {
+            Ok(ws) => {
+                pump(ws, remote, outbound, inbound_sender).await;
+                break;
+            }
+            Err(error) => {
+                tracing::debug!(%error, %remote, attempt = backoff.retries(), "Failed dialing WebSocket tunnel");
+                if backoff.sleep().await.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+    tunnels.remove(&remote);
+}
+
+async fn dial(remote: SocketAddr, listen_port: u16) -> io::Result<WebSocketStream<TcpStream>> {
+    let stream = TcpStream::connect(remote).await?;
+    let (mut ws, _) = tokio_tungstenite::client_async_with_config(
+        format!("ws://{remote}/"),
+        stream,
+        Some(tunnel_config()),
+    )
+    .await
+    .map_err(io::Error::other)?;
+    ws.send(Message::Binary(listen_port.to_be_bytes().to_vec().into()))
+        .await
+        .map_err(io::Error::other)?;
+    Ok(ws)
+}
+
+/// Moves packets through the tunnel until either end closes it.
+async fn pump<S>(
+    ws: WebSocketStream<S>,
+    remote: SocketAddr,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+    inbound_sender: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        tokio::select! {
+            packet = outbound.recv() => {
+                let Some(packet) = packet else { break };
+                if sink.send(Message::Binary(packet.into())).await.is_err() {
+                    break;
+                }
+            }
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Binary(packet))) => {
+                    if inbound_sender.send((packet.to_vec(), remote)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    tracing::debug!(%remote, "WebSocket tunnel closed");
+}
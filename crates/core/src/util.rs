@@ -1,3 +1,5 @@
+pub mod contract_key;
+pub(crate) mod lru;
 pub(crate) mod time_source;
 
 use std::{
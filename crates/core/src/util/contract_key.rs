@@ -0,0 +1,101 @@
+//! Human-readable form of contract keys, safe to copy and paste.
+//!
+//! Keys are written as the base58 of a version byte, the id of the contract instance and a
+//! checksum of both, so a mistyped or truncated key is caught instead of addressing another
+//! contract. Keys are still accepted in the plain base58 form of their instance id.
+
+use std::{fmt::Display, str::FromStr};
+
+use freenet_stdlib::prelude::{ContractInstanceId, ContractKey};
+
+/// Version of the encoding, the first byte encoded.
+const VERSION: u8 = 1;
+const ID_LEN: usize = 32;
+const CHECKSUM_LEN: usize = 4;
+
+/// Contract key in its checksummed string form, parsed from either form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckedKey(pub ContractInstanceId);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum KeyParseError {
+    #[error("invalid base58: {0}")]
+    Encoding(#[from] bs58::decode::Error),
+    #[error("unexpected key length of {0} bytes")]
+    Length(usize),
+    #[error("unsupported key encoding version {0}")]
+    Version(u8),
+    #[error("checksum mismatch, the key is mistyped")]
+    Checksum,
+}
+
+impl CheckedKey {
+    fn checksum(versioned: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let hash = blake3::hash(versioned);
+        let mut checksum = [0; CHECKSUM_LEN];
+        checksum.copy_from_slice(&hash.as_bytes()[..CHECKSUM_LEN]);
+        checksum
+    }
+}
+
+impl Display for CheckedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut encoded = Vec::with_capacity(1 + ID_LEN + CHECKSUM_LEN);
+        encoded.push(VERSION);
+        encoded.extend_from_slice(self.0.as_bytes());
+        let checksum = Self::checksum(&encoded);
+        encoded.extend_from_slice(&checksum);
+        f.write_str(&bs58::encode(encoded).into_string())
+    }
+}
+
+impl FromStr for CheckedKey {
+    type Err = KeyParseError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let decoded = bs58::decode(key.trim()).into_vec()?;
+        let id = match decoded.len() {
+            ID_LEN => &decoded[..],
+            len if len == 1 + ID_LEN + CHECKSUM_LEN => {
+                let (versioned, checksum) = decoded.split_at(1 + ID_LEN);
+                if versioned[0] != VERSION {
+                    return Err(KeyParseError::Version(versioned[0]));
+                }
+                if checksum != Self::checksum(versioned) {
+                    return Err(KeyParseError::Checksum);
+                }
+                &versioned[1..]
+            }
+            len => return Err(KeyParseError::Length(len)),
+        };
+        let id = <[u8; ID_LEN]>::try_from(id).map_err(|_| KeyParseError::Length(id.len()))?;
+        Ok(Self(ContractInstanceId::new(id)))
+    }
+}
+
+impl From<CheckedKey> for ContractKey {
+    fn from(key: CheckedKey) -> Self {
+        ContractKey::from(key.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_forms_are_parsed() {
+        let id = ContractInstanceId::new([7; 32]);
+        let checked = CheckedKey(id).to_string();
+        assert_eq!(checked.parse(), Ok(CheckedKey(id)));
+        assert_eq!(id.to_string().parse(), Ok(CheckedKey(id)));
+
+        // a single character changed is caught by the checksum
+        let mut mistyped = checked.into_bytes();
+        let last = mistyped.last_mut().unwrap();
+        *last = if *last == b'2' { b'3' } else { b'2' };
+        let mistyped = String::from_utf8(mistyped).unwrap();
+        assert_eq!(mistyped.parse::<CheckedKey>(), Err(KeyParseError::Checksum));
+        assert_eq!("".parse::<CheckedKey>(), Err(KeyParseError::Length(0)));
+    }
+}
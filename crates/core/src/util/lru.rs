@@ -0,0 +1,102 @@
+//! A map keeping the most recently used entries within a max total weight.
+
+use std::{collections::HashMap, hash::Hash};
+
+pub(crate) struct LruCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Max total weight of the entries, the least recently used are evicted past it.
+    max_weight: usize,
+    weight: usize,
+    /// Logical clock, increased on every access.
+    clock: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    last_used: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(max_weight: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_weight,
+            weight: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.clock;
+        Some(&entry.value)
+    }
+
+    pub fn contains(&mut self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts the value, evicting the least recently used entries if it goes over the
+    /// max weight. Values weighting more than the max weight are not kept.
+    pub fn insert(&mut self, key: K, value: V, weight: usize) {
+        self.remove(&key);
+        if weight > self.max_weight {
+            return;
+        }
+        self.clock += 1;
+        self.weight += weight;
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                weight,
+                last_used: self.clock,
+            },
+        );
+        while self.weight > self.max_weight {
+            let Some(lru) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&lru);
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.weight -= entry.weight;
+        Some(entry.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        cache.insert(1, "a", 1);
+        cache.insert(2, "b", 1);
+        cache.insert(3, "c", 1);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        cache.insert(4, "d", 1);
+        assert!(!cache.contains(&2));
+        assert!(cache.contains(&1));
+
+        // heavier entries evict as many as needed
+        cache.insert(5, "e", 2);
+        assert!(cache.contains(&5));
+        assert_eq!(cache.entries.len(), 2);
+        // entries heavier than the max weight are not kept at all
+        cache.insert(6, "f", 4);
+        assert!(!cache.contains(&6));
+        assert_eq!(cache.weight, 3);
+    }
+}
@@ -2,7 +2,12 @@ use std::sync::atomic::{AtomicBool, AtomicPtr};
 use std::sync::Arc;
 use std::thread;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
 
 const UPDATE_CACHED_TIME_EVERY: Duration = Duration::from_millis(10);
 
@@ -98,6 +103,120 @@ impl TimeSource for CachingSystemTimeSrc {
     }
 }
 
+/// Clock driving the timeout logic of the node.
+///
+/// Production nodes use [`TokioClock`], while tests and simulated networks can use a
+/// [`VirtualClock`] to move time forward instantly instead of waiting for it to pass.
+pub trait Clock: Send + Sync + 'static {
+    /// Time elapsed since the unix epoch.
+    fn unix_time(&self) -> Duration;
+
+    /// Monotonic reading of this clock, for the deadlines and windows tracked as instants.
+    fn now(&self) -> Instant;
+
+    /// Completes once the given duration has elapsed according to this clock.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+pub(crate) type SharedClock = Arc<dyn Clock>;
+
+/// Clock backed by the system time and tokio timers.
+#[derive(Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn unix_time(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("now should be always be later than unix epoch")
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+/// Clock which only moves forward when advanced explicitly, waking up any sleeper whose
+/// deadline has been reached.
+#[derive(Clone)]
+pub struct VirtualClock {
+    inner: Arc<Mutex<VirtualClockState>>,
+}
+
+struct VirtualClockState {
+    /// Instant matching the unix time the clock started at.
+    origin: (Instant, Duration),
+    now: Duration,
+    sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+impl VirtualClock {
+    /// A new clock starting at the current system time.
+    pub fn new() -> Self {
+        Self::starting_at(TokioClock.unix_time())
+    }
+
+    pub fn starting_at(unix_time: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(VirtualClockState {
+                origin: (Instant::now(), unix_time),
+                now: unix_time,
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.inner.lock();
+        state.now += duration;
+        let now = state.now;
+        let (ready, pending) = std::mem::take(&mut state.sleepers)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = pending;
+        drop(state);
+        for (_, wake_up) in ready {
+            let _ = wake_up.send(());
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn unix_time(&self) -> Duration {
+        self.inner.lock().now
+    }
+
+    fn now(&self) -> Instant {
+        let state = self.inner.lock();
+        let (instant, unix_time) = state.origin;
+        instant + (state.now - unix_time)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        if duration.is_zero() {
+            return futures::future::ready(()).boxed();
+        }
+        let (wake_up, woken) = oneshot::channel();
+        let mut state = self.inner.lock();
+        let deadline = state.now + duration;
+        state.sleepers.push((deadline, wake_up));
+        async move {
+            let _ = woken.await;
+        }
+        .boxed()
+    }
+}
+
 #[cfg(test)]
 #[derive(Clone)]
 pub struct MockTimeSource {
@@ -142,4 +261,29 @@ pub mod tests {
 
         assert!(second_instant > first_instant);
     }
+
+    #[tokio::test]
+    async fn virtual_clock_wakes_up_sleepers() {
+        let clock = VirtualClock::starting_at(Duration::from_secs(1_000));
+        let mut short = clock.sleep(Duration::from_secs(5));
+        let mut long = clock.sleep(Duration::from_secs(300));
+        assert!((&mut short).now_or_never().is_none());
+
+        clock.advance(Duration::from_secs(5));
+        assert!((&mut short).now_or_never().is_some());
+        assert!((&mut long).now_or_never().is_none());
+        assert_eq!(clock.unix_time(), Duration::from_secs(1_005));
+
+        clock.advance(Duration::from_secs(295));
+        assert!(long.now_or_never().is_some());
+    }
+
+    #[test]
+    fn virtual_clock_instants_advance_with_it() {
+        let clock = VirtualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(clock.now() - start, Duration::from_secs(60));
+    }
 }
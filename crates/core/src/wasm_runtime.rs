@@ -3,21 +3,34 @@ mod contract_store;
 mod delegate;
 mod delegate_store;
 mod error;
+mod module_cache;
 mod native_api;
 mod runtime;
+mod sandbox;
 mod secrets_store;
+mod state_journal;
+mod state_quota;
 mod state_store;
 mod store;
 #[cfg(test)]
 mod tests;
 
-pub(crate) use contract::ContractRuntimeInterface;
+pub use contract::{ContractAbi, ContractCall, ContractLimits};
+pub(crate) use contract::{ContractRejection, ContractRuntimeInterface};
 pub use contract_store::ContractStore;
 pub(crate) use delegate::DelegateRuntimeInterface;
+pub use delegate::{DelegateExecStats, DelegateLimits};
 pub use delegate_store::DelegateStore;
 pub(crate) use error::{ContractError, RuntimeInnerError, RuntimeResult};
-pub use runtime::{ContractExecError, Runtime};
+pub(crate) use module_cache::{ModuleCache, DEFAULT_MODULE_CACHE_SIZE};
+pub(crate) use runtime::declares_immutable;
+pub use runtime::{
+    ContractExecError, Runtime, ABI_SECTION, IMMUTABLE_SECTION, MAINTENANCE_SECTION,
+};
+pub use sandbox::{CodeRejection, ContractImports, HostFunction, HostImport};
 pub(crate) use secrets_store::SecretStoreError;
 pub use secrets_store::SecretsStore;
+pub use state_journal::StateJournalLimits;
+pub(crate) use state_quota::{Eviction, Retention, RetentionPolicy, StateQuota};
 pub use state_store::StateStore;
-pub(crate) use state_store::{StateStorage, StateStoreError};
+pub(crate) use state_store::{StateStorage, StateStoreError, StoredState};
@@ -1,12 +1,241 @@
+use std::fmt::Display;
+
 use freenet_stdlib::prelude::{
-    ContractInterfaceResult, ContractKey, Parameters, RelatedContracts, StateDelta, StateSummary,
-    UpdateData, UpdateModification, ValidateResult, WrappedState,
+    CodeHash, ContractError, ContractInstanceId, ContractKey, Parameters, RelatedContracts,
+    StateDelta, StateSummary, UpdateData, UpdateModification, ValidateResult, WrappedState,
+};
+use serde::{Deserialize, Serialize};
+use wasmer::Module;
+
+use super::{
+    sandbox::{Export, HostFunction},
+    RuntimeResult, ABI_SECTION,
 };
-use wasmer::TypedFunction;
 
-use super::{ContractExecError, RuntimeResult};
+mod v1;
+
+/// Structured reason given by a contract when rejecting a state or an update, so clients
+/// can tell the user why (e.g. "sender blocked") instead of a generic failure.
+///
+/// Contracts report a rejection from `validate_state` or `update_state` by returning
+/// [`ContractError::InvalidUpdateWithInfo`] with a reason of the form `rejected:<kind>:<details>`,
+/// where the kind is one of `invalid_delta`, `missing_related`, `size_exceeded` and
+/// `invalid_signature`, or an application defined numeric code for a custom rejection;
+/// any other error is treated as a plain execution failure.
+///
+/// This encoding is provisional, until the stdlib `ContractError` gets a typed rejection
+/// variant which replaces it. Since the kind is parsed out of a free-form reason, a contract
+/// reporting a plain [`ContractError::InvalidUpdateWithInfo`] whose reason happens to start
+/// with `rejected:<kind>:` is misclassified as a rejection of that kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractRejection {
+    /// The delta or update data cannot be applied to the state, `invalid_delta:<message>`.
+    InvalidDelta(String),
+    /// A contract the state depends on was not given, `missing_related:<contract id>`.
+    MissingRelated(ContractInstanceId),
+    /// The state or delta is larger than the contract accepts, `size_exceeded:<size>:<max>`.
+    SizeExceeded { size: u64, max: u64 },
+    /// A signature in the state or delta does not verify, `invalid_signature:<message>`.
+    InvalidSignature(String),
+    /// Application defined rejection, `<code>:<message>`.
+    Custom {
+        /// Application defined code identifying the rejection reason.
+        code: u32,
+        /// Contract provided message explaining the rejection.
+        message: Vec<u8>,
+    },
+}
+
+impl ContractRejection {
+    const PREFIX: &'static str = "rejected:";
+
+    pub fn new(code: u32, message: impl Into<Vec<u8>>) -> Self {
+        Self::Custom {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Extracts the rejection from the error returned by a contract, if it carries one.
+    pub fn from_contract_error(error: &ContractError) -> Option<Self> {
+        let ContractError::InvalidUpdateWithInfo { reason } = error else {
+            return None;
+        };
+        let (kind, details) = reason.strip_prefix(Self::PREFIX)?.split_once(':')?;
+        let rejection = match kind {
+            "invalid_delta" => Self::InvalidDelta(details.to_owned()),
+            "missing_related" => Self::MissingRelated(details.parse().ok()?),
+            "size_exceeded" => {
+                let (size, max) = details.split_once(':')?;
+                Self::SizeExceeded {
+                    size: size.parse().ok()?,
+                    max: max.parse().ok()?,
+                }
+            }
+            "invalid_signature" => Self::InvalidSignature(details.to_owned()),
+            code => Self::new(code.parse().ok()?, details.as_bytes()),
+        };
+        Some(rejection)
+    }
+
+    /// Encodes the rejection as the error a contract must return to report it.
+    pub fn into_contract_error(self) -> ContractError {
+        let details = match self {
+            Self::InvalidDelta(message) => format!("invalid_delta:{message}"),
+            Self::MissingRelated(id) => format!("missing_related:{id}"),
+            Self::SizeExceeded { size, max } => format!("size_exceeded:{size}:{max}"),
+            Self::InvalidSignature(message) => format!("invalid_signature:{message}"),
+            Self::Custom { code, message } => {
+                format!("{code}:{}", String::from_utf8_lossy(&message))
+            }
+        };
+        ContractError::InvalidUpdateWithInfo {
+            reason: format!("{}{details}", Self::PREFIX),
+        }
+    }
+}
+
+impl Display for ContractRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidDelta(message) => {
+                write!(f, "rejected by contract, invalid delta: {message}")
+            }
+            Self::MissingRelated(id) => {
+                write!(f, "rejected by contract, missing related contract {id}")
+            }
+            Self::SizeExceeded { size, max } => write!(
+                f,
+                "rejected by contract, size of {size} bytes exceeds the limit of {max} bytes"
+            ),
+            Self::InvalidSignature(message) => {
+                write!(f, "rejected by contract, invalid signature: {message}")
+            }
+            Self::Custom { code, message } => write!(
+                f,
+                "rejected by contract (code {code}): {}",
+                String::from_utf8_lossy(message)
+            ),
+        }
+    }
+}
+
+/// Function of the contract interface called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractCall {
+    ValidateState,
+    UpdateState,
+    SummarizeState,
+    GetStateDelta,
+    MaintainState,
+}
+
+impl Display for ContractCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::ValidateState => "validate_state",
+            Self::UpdateState => "update_state",
+            Self::SummarizeState => "summarize_state",
+            Self::GetStateDelta => "get_state_delta",
+            Self::MaintainState => "maintain_state",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Version of the ABI through which the contract interface is called, declared by contracts
+/// in the [`ABI_SECTION`] of their module as a little endian u32.
+///
+/// Contracts declaring no version were built against the first one. Each version is called
+/// through its own glue, so contracts already deployed keep running as the interface changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContractAbi {
+    V1,
+}
 
-type FfiReturnTy = i64;
+impl ContractAbi {
+    /// Version declared by the module, the version declared if the runtime does not support it.
+    pub fn of(module: &Module) -> Result<Self, u32> {
+        let Some(section) = module.custom_sections(ABI_SECTION).next() else {
+            return Ok(Self::V1);
+        };
+        match <[u8; 4]>::try_from(&*section).map(u32::from_le_bytes) {
+            Ok(1) => Ok(Self::V1),
+            Ok(version) => Err(version),
+            Err(_) => Err(0),
+        }
+    }
+
+    /// Functions contracts export in this version of the ABI.
+    pub(super) fn exports(&self) -> &'static [Export] {
+        match self {
+            Self::V1 => &v1::EXPORTS,
+        }
+    }
+}
+
+/// Resources a contract can use and host functions it can reach while executed.
+///
+/// Fuel, in wasm instructions, is given per call to each function of the contract interface,
+/// so a contract looping forever is interrupted instead of stalling the executor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractLimits {
+    pub validate_fuel: u64,
+    pub update_fuel: u64,
+    pub summarize_fuel: u64,
+    pub delta_fuel: u64,
+    pub maintain_fuel: u64,
+    /// Max size, in bytes, of the linear memory of a contract instance.
+    pub max_memory: usize,
+    /// Host functions contracts can import, contracts importing anything else are rejected.
+    pub allowed_imports: Vec<HostFunction>,
+    /// Max size, in bytes, of the code of new contracts.
+    pub max_code_size: usize,
+    /// Hashes of the code new contracts are refused with.
+    pub denied_code: Vec<CodeHash>,
+    /// Whether contracts must execute deterministically, so every peer computes the same
+    /// states: the host functions whose results differ between runs cannot be imported.
+    pub deterministic: bool,
+}
+
+impl ContractLimits {
+    pub fn fuel(&self, call: ContractCall) -> u64 {
+        match call {
+            ContractCall::ValidateState => self.validate_fuel,
+            ContractCall::UpdateState => self.update_fuel,
+            ContractCall::SummarizeState => self.summarize_fuel,
+            ContractCall::GetStateDelta => self.delta_fuel,
+            ContractCall::MaintainState => self.maintain_fuel,
+        }
+    }
+
+    /// Host functions contracts can import: the ones allowed, but for the nondeterministic
+    /// ones when contracts must execute deterministically.
+    pub fn importable(&self) -> Vec<HostFunction> {
+        self.allowed_imports
+            .iter()
+            .copied()
+            .filter(|function| !self.deterministic || function.is_deterministic())
+            .collect()
+    }
+}
+
+impl Default for ContractLimits {
+    fn default() -> Self {
+        Self {
+            validate_fuel: 2_000_000_000,
+            update_fuel: 5_000_000_000,
+            summarize_fuel: 1_000_000_000,
+            delta_fuel: 1_000_000_000,
+            maintain_fuel: 5_000_000_000,
+            max_memory: 128 * 1024 * 1024,
+            allowed_imports: HostFunction::ALL.to_vec(),
+            max_code_size: 10 * 1024 * 1024,
+            denied_code: vec![],
+            deterministic: false,
+        }
+    }
+}
 
 pub(crate) trait ContractRuntimeInterface {
     /// Verify that the state is valid, given the parameters. This will be used before a peer
@@ -54,6 +283,18 @@ pub(crate) trait ContractRuntimeInterface {
         state: &WrappedState,
         delta_to: &StateSummary<'_>,
     ) -> RuntimeResult<StateDelta<'static>>;
+
+    /// Perform the periodic maintenance of the state (e.g. compaction or expiry of entries)
+    /// the contract declared in its package, returning the modified state, if any.
+    ///
+    /// Only called for contracts which declare a maintenance interval, see
+    /// [`super::Runtime::maintenance_interval`].
+    fn maintain_state(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+        state: &WrappedState,
+    ) -> RuntimeResult<UpdateModification<'static>>;
 }
 
 impl ContractRuntimeInterface for super::Runtime {
@@ -64,46 +305,9 @@ impl ContractRuntimeInterface for super::Runtime {
         state: &WrappedState,
         related: &RelatedContracts<'_>,
     ) -> RuntimeResult<ValidateResult> {
-        let req_bytes = parameters.size() + state.size();
-        let running = self.prepare_contract_call(key, parameters, req_bytes)?;
-        let linear_mem = self.linear_mem(&running.instance)?;
-
-        let param_buf_ptr = {
-            let mut param_buf = self.init_buf(&running.instance, parameters)?;
-            param_buf.write(parameters)?;
-            param_buf.ptr()
-        };
-        let state_buf_ptr = {
-            let mut state_buf = self.init_buf(&running.instance, state)?;
-            state_buf.write(state)?;
-            state_buf.ptr()
-        };
-        let related_buf_ptr = {
-            let serialized = bincode::serialize(related)?;
-            let mut related_buf = self.init_buf(&running.instance, &serialized)?;
-            related_buf.write(serialized)?;
-            related_buf.ptr()
-        };
-
-        let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> =
-            running
-                .instance
-                .exports
-                .get_typed_function(&self.wasm_store, "validate_state")?;
-        let is_valid = unsafe {
-            ContractInterfaceResult::from_raw(
-                validate_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                    related_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            )
-            .unwrap_validate_state_res(linear_mem)
-            .map_err(Into::<ContractExecError>::into)?
-        };
-        Ok(is_valid)
+        match self.contract_abi(key, parameters)? {
+            ContractAbi::V1 => v1::validate_state(self, key, parameters, state, related),
+        }
     }
 
     fn update_state(
@@ -113,50 +317,9 @@ impl ContractRuntimeInterface for super::Runtime {
         state: &WrappedState,
         update_data: &[UpdateData<'_>],
     ) -> RuntimeResult<UpdateModification<'static>> {
-        // todo: if we keep this hot in memory some things to take into account:
-        //       - over subsequent requests state size may change
-        //       - the delta may not be necessarily the same size
-        let req_bytes =
-            parameters.size() + state.size() + update_data.iter().map(|e| e.size()).sum::<usize>();
-        let running = self.prepare_contract_call(key, parameters, req_bytes)?;
-        let linear_mem = self.linear_mem(&running.instance)?;
-
-        let param_buf_ptr = {
-            let mut param_buf = self.init_buf(&running.instance, parameters)?;
-            param_buf.write(parameters)?;
-            param_buf.ptr()
-        };
-        let state_buf_ptr = {
-            let mut state_buf = self.init_buf(&running.instance, state)?;
-            state_buf.write(state.clone())?;
-            state_buf.ptr()
-        };
-        let update_data_buf_ptr = {
-            let serialized = bincode::serialize(update_data)?;
-            let mut update_data_buf = self.init_buf(&running.instance, &serialized)?;
-            update_data_buf.write(serialized)?;
-            update_data_buf.ptr()
-        };
-
-        let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> =
-            running
-                .instance
-                .exports
-                .get_typed_function(&self.wasm_store, "update_state")?;
-        let update_res = unsafe {
-            ContractInterfaceResult::from_raw(
-                validate_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                    update_data_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            )
-            .unwrap_update_state(linear_mem)
-            .map_err(Into::<ContractExecError>::into)?
-        };
-        Ok(update_res)
+        match self.contract_abi(key, parameters)? {
+            ContractAbi::V1 => v1::update_state(self, key, parameters, state, update_data),
+        }
     }
 
     fn summarize_state(
@@ -165,90 +328,31 @@ impl ContractRuntimeInterface for super::Runtime {
         parameters: &Parameters<'_>,
         state: &WrappedState,
     ) -> RuntimeResult<StateSummary<'static>> {
-        let req_bytes = parameters.size() + state.size();
-        let running = self.prepare_contract_call(key, parameters, req_bytes)?;
-        let linear_mem = self.linear_mem(&running.instance)?;
-
-        let param_buf_ptr = {
-            let mut param_buf = self.init_buf(&running.instance, parameters)?;
-            param_buf.write(parameters)?;
-            param_buf.ptr()
-        };
-        let state_buf_ptr = {
-            let mut state_buf = self.init_buf(&running.instance, state)?;
-            state_buf.write(state.clone())?;
-            state_buf.ptr()
-        };
-
-        let summary_func: TypedFunction<(i64, i64), FfiReturnTy> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "summarize_state")?;
-
-        let result = unsafe {
-            let int_res = ContractInterfaceResult::from_raw(
-                summary_func.call(
-                    &mut self.wasm_store,
-                    param_buf_ptr as i64,
-                    state_buf_ptr as i64,
-                )?,
-                &linear_mem,
-            );
-            int_res
-                .unwrap_summarize_state(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-        Ok(result)
+        match self.contract_abi(key, parameters)? {
+            ContractAbi::V1 => v1::summarize_state(self, key, parameters, state),
+        }
     }
 
-    fn get_state_delta<'a>(
+    fn get_state_delta(
         &mut self,
         key: &ContractKey,
-        parameters: &Parameters<'a>,
+        parameters: &Parameters<'_>,
         state: &WrappedState,
-        summary: &StateSummary<'a>,
+        summary: &StateSummary<'_>,
     ) -> RuntimeResult<StateDelta<'static>> {
-        let req_bytes = parameters.size() + state.size() + summary.size();
-        let running = self.prepare_contract_call(key, parameters, req_bytes)?;
-        let linear_mem = self.linear_mem(&running.instance)?;
-
-        let param_buf_ptr = {
-            let mut param_buf = self.init_buf(&running.instance, parameters)?;
-            param_buf.write(parameters)?;
-            param_buf.ptr()
-        };
-        let state_buf_ptr = {
-            let mut state_buf = self.init_buf(&running.instance, state)?;
-            state_buf.write(state.clone())?;
-            state_buf.ptr()
-        };
-        let summary_buf_ptr = {
-            let mut summary_buf = self.init_buf(&running.instance, summary)?;
-            summary_buf.write(summary)?;
-            summary_buf.ptr()
-        };
+        match self.contract_abi(key, parameters)? {
+            ContractAbi::V1 => v1::get_state_delta(self, key, parameters, state, summary),
+        }
+    }
 
-        let get_state_delta_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
-            .instance
-            .exports
-            .get_typed_function(&self.wasm_store, "get_state_delta")?;
-
-        let result = unsafe {
-            let int_res = {
-                ContractInterfaceResult::from_raw(
-                    get_state_delta_func.call(
-                        &mut self.wasm_store,
-                        param_buf_ptr as i64,
-                        state_buf_ptr as i64,
-                        summary_buf_ptr as i64,
-                    )?,
-                    &linear_mem,
-                )
-            };
-            int_res
-                .unwrap_get_state_delta(linear_mem)
-                .map_err(Into::<ContractExecError>::into)?
-        };
-        Ok(result)
+    fn maintain_state(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+        state: &WrappedState,
+    ) -> RuntimeResult<UpdateModification<'static>> {
+        match self.contract_abi(key, parameters)? {
+            ContractAbi::V1 => v1::maintain_state(self, key, parameters, state),
+        }
     }
 }
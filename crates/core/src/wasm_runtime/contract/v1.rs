@@ -0,0 +1,261 @@
+//! Glue calling the first version of the contract interface ABI.
+//!
+//! The ABI is frozen: contracts built against it keep being called exactly like this, however
+//! the interface changes in later versions. Every argument is written to a buffer in the
+//! linear memory of the instance and passed by pointer, and the result is read back from the
+//! pointer returned.
+
+use freenet_stdlib::prelude::{
+    ContractInterfaceResult, ContractKey, Parameters, RelatedContracts, StateDelta, StateSummary,
+    UpdateData, UpdateModification, ValidateResult, WrappedState,
+};
+use wasmer::{Type, TypedFunction};
+
+use super::super::{sandbox::Export, ContractExecError, Runtime, RuntimeResult};
+use super::ContractCall;
+
+type FfiReturnTy = i64;
+
+/// Functions contracts export, with their parameters and results, and whether they must.
+pub(super) const EXPORTS: [Export; 7] = [
+    ("validate_state", &[Type::I64; 3], &[Type::I64], true),
+    ("update_state", &[Type::I64; 3], &[Type::I64], true),
+    ("summarize_state", &[Type::I64; 2], &[Type::I64], true),
+    ("get_state_delta", &[Type::I64; 3], &[Type::I64], true),
+    ("maintain_state", &[Type::I64; 2], &[Type::I64], false),
+    ("__frnt_set_id", &[Type::I64], &[], true),
+    ("__frnt__initiate_buffer", &[Type::I32], &[Type::I64], true),
+];
+
+pub(super) fn validate_state(
+    rt: &mut Runtime,
+    key: &ContractKey,
+    parameters: &Parameters<'_>,
+    state: &WrappedState,
+    related: &RelatedContracts<'_>,
+) -> RuntimeResult<ValidateResult> {
+    let req_bytes = parameters.size() + state.size();
+    let running =
+        rt.prepare_contract_call(key, parameters, req_bytes, ContractCall::ValidateState)?;
+    let linear_mem = rt.linear_mem(&running.instance)?;
+
+    let param_buf_ptr = {
+        let mut param_buf = rt.init_buf(&running.instance, parameters)?;
+        param_buf.write(parameters)?;
+        param_buf.ptr()
+    };
+    let state_buf_ptr = {
+        let mut state_buf = rt.init_buf(&running.instance, state)?;
+        state_buf.write(state)?;
+        state_buf.ptr()
+    };
+    let related_buf_ptr = {
+        let serialized = bincode::serialize(related)?;
+        let mut related_buf = rt.init_buf(&running.instance, &serialized)?;
+        related_buf.write(serialized)?;
+        related_buf.ptr()
+    };
+
+    let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
+        .instance
+        .exports
+        .get_typed_function(&rt.wasm_store, "validate_state")?;
+    let result = validate_func.call(
+        &mut rt.wasm_store,
+        param_buf_ptr as i64,
+        state_buf_ptr as i64,
+        related_buf_ptr as i64,
+    );
+    let raw = rt.metered(&running.instance, key, ContractCall::ValidateState, result)?;
+    let is_valid = unsafe {
+        ContractInterfaceResult::from_raw(raw, &linear_mem)
+            .unwrap_validate_state_res(linear_mem)
+            .map_err(Into::<ContractExecError>::into)?
+    };
+    Ok(is_valid)
+}
+
+pub(super) fn update_state(
+    rt: &mut Runtime,
+    key: &ContractKey,
+    parameters: &Parameters<'_>,
+    state: &WrappedState,
+    update_data: &[UpdateData<'_>],
+) -> RuntimeResult<UpdateModification<'static>> {
+    // todo: if we keep this hot in memory some things to take into account:
+    //       - over subsequent requests state size may change
+    //       - the delta may not be necessarily the same size
+    let req_bytes =
+        parameters.size() + state.size() + update_data.iter().map(|e| e.size()).sum::<usize>();
+    let running =
+        rt.prepare_contract_call(key, parameters, req_bytes, ContractCall::UpdateState)?;
+    let linear_mem = rt.linear_mem(&running.instance)?;
+
+    let param_buf_ptr = {
+        let mut param_buf = rt.init_buf(&running.instance, parameters)?;
+        param_buf.write(parameters)?;
+        param_buf.ptr()
+    };
+    let state_buf_ptr = {
+        let mut state_buf = rt.init_buf(&running.instance, state)?;
+        state_buf.write(state.clone())?;
+        state_buf.ptr()
+    };
+    let update_data_buf_ptr = {
+        let serialized = bincode::serialize(update_data)?;
+        let mut update_data_buf = rt.init_buf(&running.instance, &serialized)?;
+        update_data_buf.write(serialized)?;
+        update_data_buf.ptr()
+    };
+
+    let validate_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
+        .instance
+        .exports
+        .get_typed_function(&rt.wasm_store, "update_state")?;
+    let result = validate_func.call(
+        &mut rt.wasm_store,
+        param_buf_ptr as i64,
+        state_buf_ptr as i64,
+        update_data_buf_ptr as i64,
+    );
+    let raw = rt.metered(&running.instance, key, ContractCall::UpdateState, result)?;
+    let update_res = unsafe {
+        ContractInterfaceResult::from_raw(raw, &linear_mem)
+            .unwrap_update_state(linear_mem)
+            .map_err(Into::<ContractExecError>::into)?
+    };
+    Ok(update_res)
+}
+
+pub(super) fn summarize_state(
+    rt: &mut Runtime,
+    key: &ContractKey,
+    parameters: &Parameters<'_>,
+    state: &WrappedState,
+) -> RuntimeResult<StateSummary<'static>> {
+    let req_bytes = parameters.size() + state.size();
+    let running =
+        rt.prepare_contract_call(key, parameters, req_bytes, ContractCall::SummarizeState)?;
+    let linear_mem = rt.linear_mem(&running.instance)?;
+
+    let param_buf_ptr = {
+        let mut param_buf = rt.init_buf(&running.instance, parameters)?;
+        param_buf.write(parameters)?;
+        param_buf.ptr()
+    };
+    let state_buf_ptr = {
+        let mut state_buf = rt.init_buf(&running.instance, state)?;
+        state_buf.write(state.clone())?;
+        state_buf.ptr()
+    };
+
+    let summary_func: TypedFunction<(i64, i64), FfiReturnTy> = running
+        .instance
+        .exports
+        .get_typed_function(&rt.wasm_store, "summarize_state")?;
+
+    let result = summary_func.call(
+        &mut rt.wasm_store,
+        param_buf_ptr as i64,
+        state_buf_ptr as i64,
+    );
+    let raw = rt.metered(&running.instance, key, ContractCall::SummarizeState, result)?;
+    let result = unsafe {
+        let int_res = ContractInterfaceResult::from_raw(raw, &linear_mem);
+        int_res
+            .unwrap_summarize_state(linear_mem)
+            .map_err(Into::<ContractExecError>::into)?
+    };
+    Ok(result)
+}
+
+pub(super) fn get_state_delta<'a>(
+    rt: &mut Runtime,
+    key: &ContractKey,
+    parameters: &Parameters<'a>,
+    state: &WrappedState,
+    summary: &StateSummary<'a>,
+) -> RuntimeResult<StateDelta<'static>> {
+    let req_bytes = parameters.size() + state.size() + summary.size();
+    let running =
+        rt.prepare_contract_call(key, parameters, req_bytes, ContractCall::GetStateDelta)?;
+    let linear_mem = rt.linear_mem(&running.instance)?;
+
+    let param_buf_ptr = {
+        let mut param_buf = rt.init_buf(&running.instance, parameters)?;
+        param_buf.write(parameters)?;
+        param_buf.ptr()
+    };
+    let state_buf_ptr = {
+        let mut state_buf = rt.init_buf(&running.instance, state)?;
+        state_buf.write(state.clone())?;
+        state_buf.ptr()
+    };
+    let summary_buf_ptr = {
+        let mut summary_buf = rt.init_buf(&running.instance, summary)?;
+        summary_buf.write(summary)?;
+        summary_buf.ptr()
+    };
+
+    let get_state_delta_func: TypedFunction<(i64, i64, i64), FfiReturnTy> = running
+        .instance
+        .exports
+        .get_typed_function(&rt.wasm_store, "get_state_delta")?;
+
+    let result = get_state_delta_func.call(
+        &mut rt.wasm_store,
+        param_buf_ptr as i64,
+        state_buf_ptr as i64,
+        summary_buf_ptr as i64,
+    );
+    let raw = rt.metered(&running.instance, key, ContractCall::GetStateDelta, result)?;
+    let result = unsafe {
+        let int_res = { ContractInterfaceResult::from_raw(raw, &linear_mem) };
+        int_res
+            .unwrap_get_state_delta(linear_mem)
+            .map_err(Into::<ContractExecError>::into)?
+    };
+    Ok(result)
+}
+
+pub(super) fn maintain_state(
+    rt: &mut Runtime,
+    key: &ContractKey,
+    parameters: &Parameters<'_>,
+    state: &WrappedState,
+) -> RuntimeResult<UpdateModification<'static>> {
+    let req_bytes = parameters.size() + state.size();
+    let running =
+        rt.prepare_contract_call(key, parameters, req_bytes, ContractCall::MaintainState)?;
+    let linear_mem = rt.linear_mem(&running.instance)?;
+
+    let param_buf_ptr = {
+        let mut param_buf = rt.init_buf(&running.instance, parameters)?;
+        param_buf.write(parameters)?;
+        param_buf.ptr()
+    };
+    let state_buf_ptr = {
+        let mut state_buf = rt.init_buf(&running.instance, state)?;
+        state_buf.write(state.clone())?;
+        state_buf.ptr()
+    };
+
+    let maintain_func: TypedFunction<(i64, i64), FfiReturnTy> = running
+        .instance
+        .exports
+        .get_typed_function(&rt.wasm_store, "maintain_state")?;
+
+    // the result is encoded like the one of `update_state`
+    let result = maintain_func.call(
+        &mut rt.wasm_store,
+        param_buf_ptr as i64,
+        state_buf_ptr as i64,
+    );
+    let raw = rt.metered(&running.instance, key, ContractCall::MaintainState, result)?;
+    let result = unsafe {
+        ContractInterfaceResult::from_raw(raw, &linear_mem)
+            .unwrap_update_state(linear_mem)
+            .map_err(Into::<ContractExecError>::into)?
+    };
+    Ok(result)
+}
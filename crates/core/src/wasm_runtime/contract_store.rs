@@ -1,4 +1,11 @@
-use std::{fs::File, io::Write, path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use dashmap::DashMap;
 use freenet_stdlib::prelude::*;
@@ -17,6 +24,9 @@ pub struct ContractStore {
     contract_cache: Cache<CodeHash, Arc<ContractCode<'static>>>,
     key_to_code_part: Arc<DashMap<ContractInstanceId, (u64, CodeHash)>>,
     index_file: SafeWriter<Self>,
+    /// Code no contract stored references, by the time since the unix epoch it was first
+    /// found unreferenced.
+    unreferenced: HashMap<CodeHash, Duration>,
 }
 // TODO: add functionality to delete old contracts which have not been used for a while
 //       to keep the total space used under a configured threshold
@@ -60,6 +70,7 @@ impl ContractStore {
             key_file,
             key_to_code_part,
             index_file,
+            unreferenced: HashMap::new(),
         })
     }
 
@@ -122,15 +133,16 @@ impl ContractStore {
             tracing::warn!("trying to store partially unspecified contract `{}`", key);
             RuntimeInnerError::UnwrapContract
         })?;
+        self.unreferenced.remove(code_hash);
         if self.contract_cache.get(code_hash).is_some() {
-            return Ok(());
+            return self.index(*key.id(), code_hash);
         }
         let key_path = code_hash.encode();
         let key_path = self.contracts_dir.join(key_path).with_extension("wasm");
         if let Ok((code, _ver)) = ContractCode::load_versioned_from_path(&key_path) {
             let size = code.data().len() as i64;
             self.contract_cache.insert(*code_hash, Arc::new(code), size);
-            return Ok(());
+            return self.index(*key.id(), code_hash);
         }
 
         // insert in the memory cache
@@ -147,27 +159,103 @@ impl ContractStore {
         let mut file = File::create(key_path)?;
         file.write_all(output.as_slice())?;
 
-        // Update index
-        let keys = self.key_to_code_part.entry(*key.id());
+        self.index(*key.id(), code_hash)
+    }
+
+    /// Records the code the contract instance runs, unless already recorded, so instances
+    /// sharing the same code are all accounted as referencing it.
+    fn index(&mut self, id: ContractInstanceId, code_hash: &CodeHash) -> RuntimeResult<()> {
+        let keys = self.key_to_code_part.entry(id);
         match keys {
+            dashmap::mapref::entry::Entry::Occupied(v) if v.get().1 == *code_hash => {}
             dashmap::mapref::entry::Entry::Occupied(mut v) => {
                 let current_version_offset = v.get().0;
                 let prev_val = &mut v.get_mut().1;
                 // first mark the old entry (if it exists) as removed
                 Self::remove(&self.key_file, current_version_offset)?;
-                let new_offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                let new_offset = Self::insert(&mut self.index_file, id, code_hash)?;
                 *prev_val = *code_hash;
                 v.get_mut().0 = new_offset;
             }
             dashmap::mapref::entry::Entry::Vacant(v) => {
-                let offset = Self::insert(&mut self.index_file, *key.id(), code_hash)?;
+                let offset = Self::insert(&mut self.index_file, id, code_hash)?;
                 v.insert((offset, *code_hash));
             }
         }
-
         Ok(())
     }
 
+    /// Contract instances whose code is stored.
+    pub fn contracts(&self) -> Vec<ContractInstanceId> {
+        self.key_to_code_part
+            .iter()
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Removes the code none of the given contract instances runs, once it went unreferenced
+    /// for longer than the grace period, returning the hashes of the code removed. The
+    /// instances running it are dropped from the index. Code files no instance in the index
+    /// runs, such as the ones left behind by interrupted writes, are unreferenced too.
+    pub fn collect_garbage(
+        &mut self,
+        referenced: &HashSet<ContractInstanceId>,
+        grace: Duration,
+        now: Duration,
+    ) -> RuntimeResult<Vec<CodeHash>> {
+        let mut instances: HashMap<CodeHash, Vec<(ContractInstanceId, u64)>> = HashMap::new();
+        for entry in self.key_to_code_part.iter() {
+            let (offset, code_hash) = *entry.value();
+            instances
+                .entry(code_hash)
+                .or_default()
+                .push((*entry.key(), offset));
+        }
+        for file in std::fs::read_dir(&self.contracts_dir)? {
+            let path = file?.path();
+            let code_hash = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .filter(|_| path.extension().is_some_and(|ext| ext == "wasm"))
+                .and_then(|stem| bs58::decode(stem).into_vec().ok())
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .map(CodeHash::new);
+            if let Some(code_hash) = code_hash {
+                instances.entry(code_hash).or_default();
+            }
+        }
+        instances.retain(|_, ids| !ids.iter().any(|(id, _)| referenced.contains(id)));
+        self.unreferenced
+            .retain(|code_hash, _| instances.contains_key(code_hash));
+
+        let mut removed = vec![];
+        for (code_hash, ids) in instances {
+            let since = *self.unreferenced.entry(code_hash).or_insert(now);
+            if now.saturating_sub(since) < grace {
+                continue;
+            }
+            for (id, offset) in ids {
+                self.key_to_code_part.remove(&id);
+                Self::remove(&self.key_file, offset)?;
+            }
+            self.contract_cache.remove(&code_hash);
+            let path = self
+                .contracts_dir
+                .join(code_hash.encode())
+                .with_extension("wasm");
+            if let Err(err) = std::fs::remove_file(path) {
+                // another executor sharing the directory may have removed it already
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+            self.unreferenced.remove(&code_hash);
+            tracing::info!(code_hash = %code_hash.encode(), "Removed unreferenced contract code");
+            removed.push(code_hash);
+        }
+        Ok(removed)
+    }
+
     pub fn get_contract_path(&mut self, key: &ContractKey) -> RuntimeResult<PathBuf> {
         let contract_hash = match key.code_hash() {
             Some(k) => *k,
@@ -223,4 +311,85 @@ mod test {
         assert!(f.is_some());
         Ok(())
     }
+
+    #[test]
+    fn unreferenced_code_is_collected() -> Result<(), Box<dyn std::error::Error>> {
+        const GRACE: Duration = Duration::from_secs(60);
+        let contract_dir = crate::util::tests::get_temp_dir();
+        std::fs::create_dir_all(contract_dir.path())?;
+        let mut store = ContractStore::new(contract_dir.path().into(), 10_000)?;
+        let code = Arc::new(ContractCode::from(vec![0, 1, 2]));
+        let first = WrappedContract::new(code.clone(), [0, 1].as_ref().into());
+        let second = WrappedContract::new(code, [2, 3].as_ref().into());
+        let other = WrappedContract::new(
+            Arc::new(ContractCode::from(vec![3, 4, 5])),
+            [0, 1].as_ref().into(),
+        );
+        for contract in [&first, &second, &other] {
+            store.store_contract(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+                contract.clone(),
+            )))?;
+        }
+        let shared_hash = *first.key().code_hash().unwrap();
+        let other_hash = *other.key().code_hash().unwrap();
+
+        // the shared code is still referenced by the second instance
+        let referenced = HashSet::from([*second.key().id()]);
+        let start = Duration::from_secs(1_000);
+        assert!(store.collect_garbage(&referenced, GRACE, start)?.is_empty());
+        assert_eq!(
+            store.collect_garbage(&referenced, GRACE, start + GRACE)?,
+            [other_hash]
+        );
+        assert!(store
+            .fetch_contract(other.key(), &[0, 1].as_ref().into())
+            .is_none());
+        assert_eq!(store.code_hash_from_key(second.key()), Some(shared_hash));
+
+        // storing the code again restarts its grace period
+        let referenced = HashSet::new();
+        assert!(store
+            .collect_garbage(&referenced, GRACE, start + GRACE)?
+            .is_empty());
+        store.store_contract(ContractContainer::Wasm(ContractWasmAPIVersion::V1(
+            first.clone(),
+        )))?;
+        assert!(store
+            .collect_garbage(&referenced, GRACE, start + GRACE * 2)?
+            .is_empty());
+        assert_eq!(
+            store.collect_garbage(&referenced, GRACE, start + GRACE * 3)?,
+            [shared_hash]
+        );
+        assert!(store.contracts().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn code_files_not_indexed_are_collected() -> Result<(), Box<dyn std::error::Error>> {
+        const GRACE: Duration = Duration::from_secs(60);
+        let contract_dir = crate::util::tests::get_temp_dir();
+        std::fs::create_dir_all(contract_dir.path())?;
+        let mut store = ContractStore::new(contract_dir.path().into(), 10_000)?;
+        let orphan = CodeHash::new([1; 32]);
+        let orphan_path = contract_dir
+            .path()
+            .join(orphan.encode())
+            .with_extension("wasm");
+        std::fs::write(&orphan_path, [0, 1, 2])?;
+        let unrelated_path = contract_dir.path().join("notes.txt");
+        std::fs::write(&unrelated_path, [0])?;
+
+        let start = Duration::from_secs(1_000);
+        assert!(store
+            .collect_garbage(&HashSet::new(), GRACE, start)?
+            .is_empty());
+        assert_eq!(
+            store.collect_garbage(&HashSet::new(), GRACE, start + GRACE)?,
+            [orphan]
+        );
+        assert!(!orphan_path.exists());
+        assert!(unrelated_path.exists());
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
 use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use freenet_stdlib::prelude::{
@@ -8,8 +9,10 @@ use freenet_stdlib::prelude::{
 };
 use serde::{Deserialize, Serialize};
 use wasmer::{Instance, TypedFunction};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 use super::error::RuntimeInnerError;
+use super::runtime::RunningInstance;
 use super::{ContractError, Runtime, RuntimeResult};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +41,48 @@ pub enum DelegateExecError {
 
     #[error("Received an unexpected message from the client apps: {0}")]
     UnexpectedMessage(&'static str),
+
+    #[error("delegate {0} ran out of fuel")]
+    OutOfFuel(DelegateKey),
+
+    #[error("delegate {delegate} exceeded its memory quota of {max} bytes")]
+    MemoryQuotaExceeded { delegate: DelegateKey, max: usize },
+
+    #[error("delegate {delegate} exceeded its secrets storage quota of {max} bytes")]
+    SecretsQuotaExceeded { delegate: DelegateKey, max: u64 },
+}
+
+/// Resource quotas delegates are executed under, independent from the contract ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DelegateLimits {
+    /// Max number of wasm instructions a delegate can execute per request.
+    pub max_fuel: u64,
+    /// Max size, in bytes, of the linear memory of a delegate.
+    pub max_memory: usize,
+    /// Max size, in bytes, of the secrets a delegate can keep in the secrets store.
+    pub max_secrets_size: u64,
+}
+
+impl Default for DelegateLimits {
+    fn default() -> Self {
+        Self {
+            max_fuel: 10_000_000_000,
+            max_memory: 256 * 1024 * 1024,
+            max_secrets_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Execution statistics of a delegate since the node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DelegateExecStats {
+    pub requests: u64,
+    pub failed_requests: u64,
+    /// Total fuel consumed over all the requests.
+    pub fuel_consumed: u64,
+    /// Largest size, in bytes, the linear memory of the delegate reached.
+    pub peak_memory: usize,
+    pub exec_time: Duration,
 }
 
 pub(crate) trait DelegateRuntimeInterface {
@@ -168,8 +213,7 @@ impl Runtime {
                 }
                 OutboundDelegateMsg::SetSecretRequest(SetSecretRequest { key, value }) => {
                     if let Some(plaintext) = value {
-                        self.secret_store
-                            .store_secret(delegate_key, &key, plaintext)?;
+                        self.store_delegate_secret(delegate_key, &key, plaintext)?;
                     } else {
                         self.secret_store.remove_secret(delegate_key, &key)?;
                     }
@@ -222,21 +266,16 @@ impl Runtime {
         }
         Ok(last_context)
     }
-}
 
-impl DelegateRuntimeInterface for Runtime {
-    fn inbound_app_message(
+    fn process_inbound(
         &mut self,
         delegate_key: &DelegateKey,
         params: &Parameters,
         attested: Option<&[u8]>,
         inbound: Vec<InboundDelegateMsg>,
+        running: &RunningInstance,
     ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
         let mut results = Vec::with_capacity(inbound.len());
-        if inbound.is_empty() {
-            return Ok(results);
-        }
-        let running = self.prepare_delegate_call(params, delegate_key, 4096)?;
         let process_func: TypedFunction<(i64, i64, i64), i64> = running
             .instance
             .exports
@@ -352,8 +391,7 @@ impl DelegateRuntimeInterface for Runtime {
             match outbound {
                 OutboundDelegateMsg::SetSecretRequest(SetSecretRequest { key, value }) => {
                     if let Some(plaintext) = value {
-                        self.secret_store
-                            .store_secret(delegate_key, &key, plaintext)?;
+                        self.store_delegate_secret(delegate_key, &key, plaintext)?;
                     } else {
                         self.secret_store.remove_secret(delegate_key, &key)?;
                     }
@@ -364,6 +402,93 @@ impl DelegateRuntimeInterface for Runtime {
         Ok(results)
     }
 
+    fn store_delegate_secret(
+        &mut self,
+        delegate_key: &DelegateKey,
+        key: &SecretsId,
+        plaintext: Vec<u8>,
+    ) -> RuntimeResult<()> {
+        let max = self.delegate_limits.max_secrets_size;
+        let stored = self.secret_store.stored_size(delegate_key, key);
+        if stored + plaintext.len() as u64 > max {
+            return Err(DelegateExecError::SecretsQuotaExceeded {
+                delegate: delegate_key.clone(),
+                max,
+            }
+            .into());
+        }
+        self.secret_store.store_secret(delegate_key, key, plaintext)
+    }
+
+    /// Sets the resource quotas delegates are executed under.
+    pub fn set_delegate_limits(&mut self, limits: DelegateLimits) {
+        self.delegate_limits = limits;
+    }
+
+    /// Execution statistics of the delegates run since the runtime was built.
+    pub fn delegate_stats(&self) -> &HashMap<DelegateKey, DelegateExecStats> {
+        &self.delegate_stats
+    }
+
+    /// Runs the inbound messages through the delegate within its quotas, recording the
+    /// execution statistics of the delegate.
+    fn exec_within_limits(
+        &mut self,
+        delegate_key: &DelegateKey,
+        params: &Parameters,
+        attested: Option<&[u8]>,
+        inbound: Vec<InboundDelegateMsg>,
+    ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
+        let limits = self.delegate_limits;
+        let running = self.prepare_delegate_call(params, delegate_key, 4096)?;
+        set_remaining_points(&mut self.wasm_store, &running.instance, limits.max_fuel);
+        let started = Instant::now();
+        let mut result = self.process_inbound(delegate_key, params, attested, inbound, &running);
+        let exec_time = started.elapsed();
+
+        let fuel_consumed = match get_remaining_points(&mut self.wasm_store, &running.instance) {
+            MeteringPoints::Remaining(left) => limits.max_fuel - left,
+            MeteringPoints::Exhausted => {
+                result = Err(DelegateExecError::OutOfFuel(delegate_key.clone()).into());
+                limits.max_fuel
+            }
+        };
+        let memory_size = self.memory_size(&running.instance)?;
+        if result.is_ok() && memory_size > limits.max_memory {
+            result = Err(DelegateExecError::MemoryQuotaExceeded {
+                delegate: delegate_key.clone(),
+                max: limits.max_memory,
+            }
+            .into());
+        }
+
+        let stats = self.delegate_stats.entry(delegate_key.clone()).or_default();
+        stats.requests += 1;
+        stats.fuel_consumed += fuel_consumed;
+        stats.peak_memory = stats.peak_memory.max(memory_size);
+        stats.exec_time += exec_time;
+        if let Err(err) = &result {
+            stats.failed_requests += 1;
+            tracing::debug!(delegate = %delegate_key, ?stats, "Delegate execution failed: {err}");
+        }
+        result
+    }
+}
+
+impl DelegateRuntimeInterface for Runtime {
+    fn inbound_app_message(
+        &mut self,
+        delegate_key: &DelegateKey,
+        params: &Parameters,
+        attested: Option<&[u8]>,
+        inbound: Vec<InboundDelegateMsg>,
+    ) -> RuntimeResult<Vec<OutboundDelegateMsg>> {
+        if inbound.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.exec_within_limits(delegate_key, params, attested, inbound)
+    }
+
     #[inline]
     fn register_delegate(
         &mut self,
@@ -491,4 +616,35 @@ mod test {
         std::mem::drop(temp_dir);
         Ok(())
     }
+
+    #[test]
+    fn fuel_quota_exceeded() -> Result<(), Box<dyn std::error::Error>> {
+        let contract = WrappedContract::new(
+            Arc::new(ContractCode::from(vec![1])),
+            Parameters::from(vec![]),
+        );
+        let (delegate, mut runtime, temp_dir) = setup_runtime(TEST_DELEGATE_1)?;
+        runtime.set_delegate_limits(DelegateLimits {
+            max_fuel: 10,
+            ..Default::default()
+        });
+        let app = ContractInstanceId::try_from(contract.key.to_string()).unwrap();
+
+        let payload: Vec<u8> = bincode::serialize(&InboundAppMessage::CreateInboxRequest).unwrap();
+        let inbound = InboundDelegateMsg::ApplicationMessage(ApplicationMessage::new(app, payload));
+        let err = runtime
+            .inbound_app_message(delegate.key(), &vec![].into(), None, vec![inbound])
+            .unwrap_err();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::DelegateExecError(DelegateExecError::OutOfFuel(_))
+        ));
+
+        let stats = &runtime.delegate_stats()[delegate.key()];
+        assert_eq!(stats.requests, 1);
+        assert_eq!(stats.failed_requests, 1);
+        assert_eq!(stats.fuel_consumed, 10);
+        std::mem::drop(temp_dir);
+        Ok(())
+    }
 }
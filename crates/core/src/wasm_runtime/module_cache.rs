@@ -0,0 +1,351 @@
+//! Cache of the compiled contract modules, keyed by the hash of the contract code.
+//!
+//! Compiling a contract is far more expensive than running it, so the modules compiled are
+//! kept, serialized, in a directory of their own, and loaded back from it instead of compiled
+//! again, also across restarts. Contracts sharing the same code share the same module. The
+//! serialized modules are only valid for the engine which compiled them, so the directory is
+//! named after the version of the engine and how it is configured, and the directories of
+//! other engine versions are removed on start.
+//!
+//! Every module file starts with a header holding the version of the engine which compiled it
+//! and the checksum of the serialized module, both verified before the module is deserialized,
+//! so a corrupted or foreign file is compiled again instead of loaded.
+//!
+//! The cache is bounded by the size of the serialized modules, the least recently used being
+//! evicted first.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use freenet_stdlib::prelude::CodeHash;
+use wasmer::{Module, Store};
+
+use super::RuntimeResult;
+
+/// Version of how this runtime configures the engine it compiles modules with, besides the
+/// version of the engine itself. Must be bumped when changing the compiler or its middlewares.
+const COMPILATION_VERSION: u32 = 2;
+
+const CHECKSUM_LEN: usize = blake3::OUT_LEN;
+
+/// Default max size, in bytes, of the serialized modules cached.
+pub(crate) const DEFAULT_MODULE_CACHE_SIZE: u64 = 512 * 1024 * 1024;
+
+struct Entry {
+    size: u64,
+    last_used: u64,
+    /// Loaded module, if used since the node started.
+    module: Option<Module>,
+}
+
+pub(crate) struct ModuleCache {
+    /// Directory the modules are persisted in, none to only keep them in memory.
+    dir: Option<PathBuf>,
+    max_bytes: u64,
+    used: u64,
+    entries: HashMap<CodeHash, Entry>,
+    /// Incremented on every use, to tell which modules were used last.
+    clock: u64,
+}
+
+impl ModuleCache {
+    pub fn in_memory(max_bytes: u64) -> Self {
+        Self {
+            dir: None,
+            max_bytes,
+            used: 0,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Opens the cache persisted in the given directory, dropping the modules compiled by
+    /// other versions of the engine.
+    pub fn persisted(cache_dir: &Path, max_bytes: u64) -> RuntimeResult<Self> {
+        let engine = engine_version();
+        let dir = cache_dir.join(&engine);
+        fs::create_dir_all(&dir)?;
+        for stale in fs::read_dir(cache_dir)? {
+            let stale = stale?;
+            if stale.file_name() != engine.as_str() {
+                tracing::info!(path = ?stale.path(), "Removing modules compiled by another engine");
+                fs::remove_dir_all(stale.path())?;
+            }
+        }
+
+        let mut found = vec![];
+        for file in fs::read_dir(&dir)? {
+            let file = file?;
+            let path = file.path();
+            let hash = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .filter(|_| path.extension().is_some_and(|ext| ext == "bin"))
+                .and_then(|stem| bs58::decode(stem).into_vec().ok())
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .map(CodeHash::new);
+            let Some(hash) = hash else {
+                // leftovers of interrupted writes
+                fs::remove_file(&path)?;
+                continue;
+            };
+            let metadata = file.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((modified, hash, metadata.len()));
+        }
+        // the modules modified last are taken as the ones used last
+        found.sort_by_key(|(modified, ..)| *modified);
+
+        let mut cache = Self {
+            dir: Some(dir),
+            ..Self::in_memory(max_bytes)
+        };
+        for (_, hash, size) in found {
+            cache.clock += 1;
+            cache.used += size;
+            cache.entries.insert(
+                hash,
+                Entry {
+                    size,
+                    last_used: cache.clock,
+                    module: None,
+                },
+            );
+        }
+        cache.evict(None);
+        Ok(cache)
+    }
+
+    /// Compiled module of the code with the given hash, if cached.
+    pub fn get(&mut self, store: &Store, hash: &CodeHash) -> Option<Module> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(hash)?;
+        entry.last_used = self.clock;
+        if let Some(module) = &entry.module {
+            return Some(module.clone());
+        }
+        let path = module_path(self.dir.as_deref()?, hash);
+        match load(store, &path) {
+            Ok(module) => {
+                entry.module = Some(module.clone());
+                Some(module)
+            }
+            Err(error) => {
+                tracing::warn!(?path, %error, "Failed loading cached module, compiling it again");
+                self.remove(hash);
+                None
+            }
+        }
+    }
+
+    /// All the modules cached, without counting it as a use of them. Modules not used since
+    /// the node started are loaded but not kept in memory.
+    pub fn modules(&self, store: &Store) -> Vec<(CodeHash, Module)> {
+        self.entries
+            .iter()
+            .filter_map(|(hash, entry)| {
+                if let Some(module) = &entry.module {
+                    return Some((*hash, module.clone()));
+                }
+                let path = module_path(self.dir.as_deref()?, hash);
+                match load(store, &path) {
+                    Ok(module) => Some((*hash, module)),
+                    Err(error) => {
+                        tracing::warn!(?path, %error, "Failed loading cached module");
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Compiles the code, without needing the cache, so the runtimes sharing it are not held
+    /// up by the compilation. The module is then cached with [`Self::insert`].
+    pub fn compile(store: &Store, code: &[u8]) -> RuntimeResult<Module> {
+        Ok(Module::new(store, code)?)
+    }
+
+    /// Caches the module compiled from the code with the given hash, unless it already is.
+    pub fn insert(&mut self, hash: CodeHash, module: &Module) {
+        if self.entries.contains_key(&hash) {
+            return;
+        }
+        let serialized = match module.serialize() {
+            Ok(serialized) => serialized,
+            Err(error) => {
+                tracing::warn!(%error, "Failed serializing module, it won't be cached");
+                return;
+            }
+        };
+        let contents = [header(&serialized).as_slice(), &serialized].concat();
+        if let Some(dir) = &self.dir {
+            if let Err(error) = persist(&module_path(dir, &hash), &contents) {
+                tracing::warn!(%error, "Failed persisting module, it won't be cached");
+                return;
+            }
+        }
+        self.clock += 1;
+        let size = contents.len() as u64;
+        self.used += size;
+        self.entries.insert(
+            hash,
+            Entry {
+                size,
+                last_used: self.clock,
+                module: Some(module.clone()),
+            },
+        );
+        self.evict(Some(&hash));
+    }
+
+    /// Evicts the least recently used modules until the cache fits in its max size again,
+    /// besides the given one.
+    fn evict(&mut self, keep: Option<&CodeHash>) {
+        while self.used > self.max_bytes {
+            let Some(lru) = self
+                .entries
+                .iter()
+                .filter(|(hash, _)| Some(*hash) != keep)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| *hash)
+            else {
+                break;
+            };
+            tracing::debug!(hash = %lru.encode(), "Evicting cached module");
+            self.remove(&lru);
+        }
+    }
+
+    /// Drops the module of the code with the given hash, if cached.
+    pub fn remove(&mut self, hash: &CodeHash) {
+        let Some(entry) = self.entries.remove(hash) else {
+            return;
+        };
+        self.used -= entry.size;
+        if let Some(dir) = &self.dir {
+            if let Err(error) = fs::remove_file(module_path(dir, hash)) {
+                if error.kind() != io::ErrorKind::NotFound {
+                    tracing::warn!(%error, "Failed removing cached module");
+                }
+            }
+        }
+    }
+}
+
+/// Identifies the engine the modules are compiled by, and how it is configured.
+fn engine_version() -> String {
+    format!(
+        "wasmer-{}-{}-v{COMPILATION_VERSION}",
+        wasmer::VERSION,
+        std::env::consts::ARCH
+    )
+}
+
+/// Header of a module file: the checksum of the serialized module, then the version of the
+/// engine, prefixed by its length.
+fn header(serialized: &[u8]) -> Vec<u8> {
+    let engine = engine_version();
+    let mut header = Vec::with_capacity(CHECKSUM_LEN + 2 + engine.len());
+    header.extend(blake3::hash(serialized).as_bytes());
+    header.extend((engine.len() as u16).to_be_bytes());
+    header.extend(engine.as_bytes());
+    header
+}
+
+fn load(store: &Store, path: &Path) -> io::Result<Module> {
+    let contents = fs::read(path)?;
+    let corrupted = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+    if contents.len() < CHECKSUM_LEN + 2 {
+        return Err(corrupted("truncated module header"));
+    }
+    let (checksum, rest) = contents.split_at(CHECKSUM_LEN);
+    let (engine_len, rest) = rest.split_at(2);
+    let engine_len = u16::from_be_bytes([engine_len[0], engine_len[1]]) as usize;
+    if rest.len() < engine_len {
+        return Err(corrupted("truncated module header"));
+    }
+    let (engine, serialized) = rest.split_at(engine_len);
+    if engine != engine_version().as_bytes() {
+        return Err(corrupted("module compiled by another engine"));
+    }
+    if blake3::hash(serialized).as_bytes() != checksum {
+        return Err(corrupted("module checksum mismatch"));
+    }
+    // SAFETY: the module was serialized by this same version of the engine, configured the
+    // same way, and its bytes are the ones written then, as the header verified above
+    unsafe { Module::deserialize(store, serialized) }.map_err(io::Error::other)
+}
+
+fn module_path(dir: &Path, hash: &CodeHash) -> PathBuf {
+    dir.join(hash.encode()).with_extension("bin")
+}
+
+/// Writes the module next to its final path, syncs it, then renames it over it, so a crash
+/// halfway through never leaves a truncated module behind.
+fn persist(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smallest valid module, with a single exported function.
+    const WAT: &str = r#"(module (func (export "noop")))"#;
+
+    #[test]
+    fn modules_persist_until_the_engine_changes() -> RuntimeResult<()> {
+        let store = Store::default();
+        let dir = tempfile::tempdir()?;
+        let hash = CodeHash::new([1; 32]);
+
+        let mut cache = ModuleCache::persisted(dir.path(), u64::MAX)?;
+        assert!(cache.get(&store, &hash).is_none());
+        let module = ModuleCache::compile(&store, WAT.as_bytes())?;
+        cache.insert(hash, &module);
+
+        // loaded back from disk after a restart
+        let mut cache = ModuleCache::persisted(dir.path(), u64::MAX)?;
+        let module = cache.get(&store, &hash).unwrap();
+        assert!(module.exports().any(|export| export.name() == "noop"));
+
+        // a module over the max size is kept only until another one is compiled
+        let other = CodeHash::new([2; 32]);
+        let mut cache = ModuleCache::persisted(dir.path(), 1)?;
+        assert!(cache.get(&store, &hash).is_none());
+        cache.insert(hash, &module);
+        cache.insert(other, &module);
+        assert!(cache.get(&store, &hash).is_none());
+        assert!(cache.get(&store, &other).is_some());
+
+        // corrupted modules are dropped and compiled again instead of loaded
+        let path = module_path(cache.dir.as_deref().unwrap(), &other);
+        let mut contents = fs::read(&path)?;
+        *contents.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, contents)?;
+        let mut cache = ModuleCache::persisted(dir.path(), u64::MAX)?;
+        assert!(cache.get(&store, &other).is_none());
+        assert!(!path.exists());
+
+        // modules compiled by other engine versions are removed
+        let stale = dir.path().join("wasmer-0.0.1-x86_64-v1");
+        fs::create_dir_all(&stale)?;
+        ModuleCache::persisted(dir.path(), u64::MAX)?;
+        assert!(!stale.exists());
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use wasmer::{Function, Imports};
 
-use super::runtime::InstanceInfo;
+use super::{runtime::InstanceInfo, sandbox::HostFunction};
 
 /// This is a map of starting addresses of the instance memory space.
 ///
@@ -25,8 +25,8 @@ pub(crate) mod log {
     pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
         let utc_now = Function::new_typed(store, info);
         imports.register_namespace(
-            "freenet_log",
-            [("__frnt__logger__info".to_owned(), utc_now.into())],
+            HostFunction::Log.namespace(),
+            [(HostFunction::Log.name().to_owned(), utc_now.into())],
         );
     }
 
@@ -53,8 +53,8 @@ pub(crate) mod rand {
     pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
         let rand_bytes = Function::new_typed(store, rand_bytes);
         imports.register_namespace(
-            "freenet_rand",
-            [("__frnt__rand__rand_bytes".to_owned(), rand_bytes.into())],
+            HostFunction::Rand.namespace(),
+            [(HostFunction::Rand.name().to_owned(), rand_bytes.into())],
         );
     }
 
@@ -77,8 +77,8 @@ pub(crate) mod time {
     pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
         let utc_now = Function::new_typed(store, utc_now);
         imports.register_namespace(
-            "freenet_time",
-            [("__frnt__time__utc_now".to_owned(), utc_now.into())],
+            HostFunction::Time.namespace(),
+            [(HostFunction::Time.name().to_owned(), utc_now.into())],
         );
     }
 
@@ -95,3 +95,142 @@ pub(crate) mod time {
         };
     }
 }
+
+pub(crate) mod events {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+
+    use freenet_stdlib::prelude::ContractInstanceId;
+
+    use super::*;
+
+    /// Max size of the payload of a single event.
+    const MAX_EVENT_SIZE: usize = 16 * 1024;
+    /// Max number of events a contract can emit within a rate window.
+    const MAX_EVENTS_PER_WINDOW: u32 = 16;
+    const RATE_WINDOW: Duration = Duration::from_secs(1);
+    /// Max number of events pending delivery per contract, the oldest are dropped first.
+    const MAX_PENDING_EVENTS: usize = 64;
+    /// Number of contracts tracked past which those with nothing left to track are forgotten.
+    const MAX_TRACKED_CONTRACTS: usize = 1024;
+
+    const EMITTED: i32 = 0;
+    const TOO_LARGE: i32 = -1;
+    const RATE_LIMITED: i32 = -2;
+    const NOT_A_CONTRACT: i32 = -3;
+
+    struct EmittedEvents {
+        window_start: Instant,
+        in_window: u32,
+        pending: VecDeque<Vec<u8>>,
+    }
+
+    impl EmittedEvents {
+        /// Whether there are no events left to deliver and the rate window is over, so
+        /// forgetting about the contract changes nothing.
+        fn is_done(&self, now: Instant) -> bool {
+            self.pending.is_empty() && now.duration_since(self.window_start) >= RATE_WINDOW
+        }
+    }
+
+    static EVENTS: Lazy<DashMap<ContractInstanceId, EmittedEvents>> = Lazy::new(DashMap::default);
+
+    pub(crate) fn prepare_export(store: &mut wasmer::Store, imports: &mut Imports) {
+        let emit = Function::new_typed(store, emit);
+        imports.register_namespace(
+            HostFunction::Events.namespace(),
+            [(HostFunction::Events.name().to_owned(), emit.into())],
+        );
+    }
+
+    fn emit(id: i64, ptr: i64, len: i32) -> i32 {
+        if id == -1 {
+            panic!("unset module id");
+        }
+        let info = MEM_ADDR.get(&id).expect("instance mem space not recorded");
+        let Some(contract) = info.contract_id() else {
+            tracing::warn!(delegate = %info.key(), "Only contracts can emit events");
+            return NOT_A_CONTRACT;
+        };
+        if len < 0 || len as usize > MAX_EVENT_SIZE {
+            tracing::debug!(%contract, len, "Event payload too large, dropping");
+            return TOO_LARGE;
+        }
+        let ptr = compute_ptr::<u8>(ptr, info.start_ptr);
+        let payload = unsafe { std::slice::from_raw_parts(ptr, len as _) }.to_vec();
+        record(contract, payload, Instant::now())
+    }
+
+    fn record(contract: ContractInstanceId, payload: Vec<u8>, now: Instant) -> i32 {
+        if EVENTS.len() >= MAX_TRACKED_CONTRACTS {
+            EVENTS.retain(|_, events| !events.is_done(now));
+        }
+        let mut events = EVENTS.entry(contract).or_insert_with(|| EmittedEvents {
+            window_start: now,
+            in_window: 0,
+            pending: VecDeque::new(),
+        });
+        if now.duration_since(events.window_start) >= RATE_WINDOW {
+            events.window_start = now;
+            events.in_window = 0;
+        }
+        if events.in_window >= MAX_EVENTS_PER_WINDOW {
+            tracing::debug!(%contract, "Contract emitting events too fast, dropping");
+            return RATE_LIMITED;
+        }
+        events.in_window += 1;
+        if events.pending.len() >= MAX_PENDING_EVENTS {
+            events.pending.pop_front();
+        }
+        events.pending.push_back(payload);
+        EMITTED
+    }
+
+    /// Takes the events emitted by the contract which are pending delivery.
+    pub(crate) fn take(contract: &ContractInstanceId, now: Instant) -> Vec<Vec<u8>> {
+        let taken = EVENTS
+            .get_mut(contract)
+            .map(|mut events| events.pending.drain(..).collect())
+            .unwrap_or_default();
+        EVENTS.remove_if(contract, |_, events| events.is_done(now));
+        taken
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rate_limit_events() {
+            let contract = ContractInstanceId::new([3; 32]);
+            let start = Instant::now();
+            for i in 0..MAX_EVENTS_PER_WINDOW {
+                assert_eq!(record(contract, vec![i as u8], start), EMITTED);
+            }
+            assert_eq!(record(contract, vec![], start), RATE_LIMITED);
+            assert_eq!(take(&contract, start).len(), MAX_EVENTS_PER_WINDOW as usize);
+            assert!(take(&contract, start).is_empty());
+
+            // a new window starts after the rate window elapses
+            let later = start + RATE_WINDOW;
+            assert_eq!(record(contract, vec![1], later), EMITTED);
+            assert_eq!(take(&contract, later), vec![vec![1]]);
+        }
+
+        #[test]
+        fn contracts_are_forgotten_once_done() {
+            let contract = ContractInstanceId::new([4; 32]);
+            let start = Instant::now();
+            assert_eq!(record(contract, vec![1], start), EMITTED);
+            // the rate window is still open
+            assert_eq!(take(&contract, start), vec![vec![1]]);
+            assert!(EVENTS.contains_key(&contract));
+
+            assert_eq!(record(contract, vec![2], start), EMITTED);
+            assert_eq!(take(&contract, start + RATE_WINDOW), vec![vec![2]]);
+            assert!(!EVENTS.contains_key(&contract));
+        }
+    }
+}
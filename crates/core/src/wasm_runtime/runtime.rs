@@ -1,4 +1,8 @@
-use std::{collections::HashMap, sync::atomic::AtomicI64};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::AtomicI64, Arc},
+    time::Duration,
+};
 
 use freenet_stdlib::{
     memory::{
@@ -7,15 +11,46 @@ use freenet_stdlib::{
     },
     prelude::*,
 };
+use parking_lot::Mutex;
 use wasmer::{imports, Bytes, Imports, Instance, Memory, MemoryType, Module, Store, TypedFunction};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 use super::{
-    contract_store::ContractStore, delegate_store::DelegateStore, error::RuntimeInnerError,
-    native_api, secrets_store::SecretsStore, RuntimeResult,
+    contract::{ContractAbi, ContractCall, ContractLimits, ContractRuntimeInterface},
+    contract_store::ContractStore,
+    delegate::{DelegateExecStats, DelegateLimits},
+    delegate_store::DelegateStore,
+    error::RuntimeInnerError,
+    module_cache::{ModuleCache, DEFAULT_MODULE_CACHE_SIZE},
+    native_api,
+    sandbox::{self, CodeRejection, ContractImports},
+    secrets_store::SecretsStore,
+    RuntimeResult,
 };
 
 static INSTANCE_ID: AtomicI64 = AtomicI64::new(0);
 
+/// Name of the custom section of the contract module in which the interval, in seconds,
+/// at which the contract state must be maintained is declared, as a little endian u64.
+pub const MAINTENANCE_SECTION: &str = "freenet_maintenance";
+
+/// Name of the custom section of the contract module whose presence declares the contract
+/// immutable: its state is fixed once stored and updates are rejected.
+pub const IMMUTABLE_SECTION: &str = "freenet_immutable";
+
+/// Name of the custom section of the contract module in which the version of the contract
+/// interface ABI the contract was built against is declared, as a little endian u32. Contracts
+/// without it were built against the first version.
+pub const ABI_SECTION: &str = "freenet_abi";
+
+/// Whether the contract code declares the contract immutable, without compiling it.
+pub(crate) fn declares_immutable(code: &[u8]) -> bool {
+    use wasmer::wasmparser::{Parser, Payload};
+    Parser::new(0).parse_all(code).any(|payload| {
+        matches!(payload, Ok(Payload::CustomSection(section)) if section.name() == IMMUTABLE_SECTION)
+    })
+}
+
 pub(super) struct RunningInstance {
     pub id: i64,
     pub instance: Instance,
@@ -39,6 +74,13 @@ impl InstanceInfo {
             Key::Delegate(k) => k.encode(),
         }
     }
+
+    pub fn contract_id(&self) -> Option<ContractInstanceId> {
+        match &self.key {
+            Key::Contract(k) => Some(*k),
+            Key::Delegate(_) => None,
+        }
+    }
 }
 
 enum Key {
@@ -87,6 +129,31 @@ pub enum ContractExecError {
 
     #[error("unexpected result from contract interface")]
     UnexpectedResult,
+
+    #[error("contract {key} ran out of fuel in {call}, after {fuel} instructions")]
+    OutOfFuel {
+        key: ContractKey,
+        call: ContractCall,
+        fuel: u64,
+    },
+
+    #[error("contract {key} exceeded its memory limit of {max} bytes")]
+    MemoryLimitExceeded { key: ContractKey, max: usize },
+
+    #[error("contract {key} imports {import}, which contracts are not allowed to")]
+    ForbiddenImport { key: ContractKey, import: String },
+
+    #[error("contract {0} produced different results for the same update")]
+    Nondeterministic(ContractKey),
+
+    #[error("contract {key} declares version {version} of the contract interface, which is not supported")]
+    UnsupportedAbi { key: ContractKey, version: u32 },
+
+    #[error("code of contract {key} rejected: {reason}")]
+    CodeRejected {
+        key: ContractKey,
+        reason: CodeRejection,
+    },
 }
 
 pub struct Runtime {
@@ -101,11 +168,17 @@ pub struct Runtime {
     pub(super) delegate_store: DelegateStore,
     /// loaded delegate modules
     pub(super) delegate_modules: HashMap<DelegateKey, Module>,
+    /// resource quotas delegates are executed under
+    pub(super) delegate_limits: DelegateLimits,
+    pub(super) delegate_stats: HashMap<DelegateKey, DelegateExecStats>,
 
     /// Local contract storage.
     pub(crate) contract_store: ContractStore,
-    /// loaded contract modules
-    pub(super) contract_modules: HashMap<ContractKey, Module>,
+    /// compiled contract modules, by the hash of their code, shared with the runtimes forked
+    /// from this one
+    pub(super) module_cache: Arc<Mutex<ModuleCache>>,
+    /// fuel, memory and host functions contracts are executed with
+    pub(super) contract_limits: ContractLimits,
 }
 
 impl Runtime {
@@ -115,21 +188,46 @@ impl Runtime {
         secret_store: SecretsStore,
         host_mem: bool,
     ) -> RuntimeResult<Self> {
-        let mut store = Self::instance_store();
-        let (host_memory, mut top_level_imports) = if host_mem {
-            let mem = Self::instance_host_mem(&mut store)?;
-            let imports = imports! {
-                "env" => {
-                    "memory" =>  mem.clone(),
-                },
-            };
-            (Some(mem), imports)
-        } else {
-            (None, imports! {})
-        };
-        native_api::log::prepare_export(&mut store, &mut top_level_imports);
-        native_api::rand::prepare_export(&mut store, &mut top_level_imports);
-        native_api::time::prepare_export(&mut store, &mut top_level_imports);
+        Self::with_store(
+            Self::instance_store(),
+            contract_store,
+            delegate_store,
+            secret_store,
+            host_mem,
+        )
+    }
+
+    /// Builds another runtime to execute contracts alongside this one, with the same limits.
+    /// Both share the engine, so the modules compiled by either can be run by both, and the
+    /// cache of the compiled modules.
+    pub(crate) fn fork(
+        &self,
+        contract_store: ContractStore,
+        delegate_store: DelegateStore,
+        secret_store: SecretsStore,
+    ) -> RuntimeResult<Self> {
+        let store = Store::new(self.wasm_store.engine().clone());
+        let mut runtime = Self::with_store(
+            store,
+            contract_store,
+            delegate_store,
+            secret_store,
+            self.host_memory.is_some(),
+        )?;
+        runtime.module_cache = self.module_cache.clone();
+        runtime.contract_limits = self.contract_limits.clone();
+        runtime.delegate_limits = self.delegate_limits;
+        Ok(runtime)
+    }
+
+    fn with_store(
+        mut store: Store,
+        contract_store: ContractStore,
+        delegate_store: DelegateStore,
+        secret_store: SecretsStore,
+        host_mem: bool,
+    ) -> RuntimeResult<Self> {
+        let (host_memory, top_level_imports) = Self::host_imports(&mut store, host_mem)?;
 
         Ok(Self {
             wasm_store: store,
@@ -138,13 +236,91 @@ impl Runtime {
 
             secret_store,
             delegate_store,
-            contract_modules: HashMap::new(),
+            module_cache: Arc::new(Mutex::new(ModuleCache::in_memory(
+                DEFAULT_MODULE_CACHE_SIZE,
+            ))),
+            contract_limits: ContractLimits::default(),
 
             contract_store,
             delegate_modules: HashMap::new(),
+            delegate_limits: DelegateLimits::default(),
+            delegate_stats: HashMap::new(),
         })
     }
 
+    /// Functions and memory of the host the modules instantiated in the store can import.
+    fn host_imports(store: &mut Store, host_mem: bool) -> RuntimeResult<(Option<Memory>, Imports)> {
+        let (host_memory, mut imports) = if host_mem {
+            let mem = Self::instance_host_mem(store)?;
+            let imports = imports! {
+                "env" => {
+                    "memory" =>  mem.clone(),
+                },
+            };
+            (Some(mem), imports)
+        } else {
+            (None, imports! {})
+        };
+        native_api::log::prepare_export(store, &mut imports);
+        native_api::rand::prepare_export(store, &mut imports);
+        native_api::time::prepare_export(store, &mut imports);
+        native_api::events::prepare_export(store, &mut imports);
+        Ok((host_memory, imports))
+    }
+
+    /// Persists the compiled contract modules in the given cache, instead of only keeping them
+    /// in memory.
+    pub(crate) fn set_module_cache(&mut self, cache: ModuleCache) {
+        self.module_cache = Arc::new(Mutex::new(cache));
+    }
+
+    /// Sets the fuel, per function of their interface, the memory and the host functions
+    /// contracts are executed with.
+    pub fn set_contract_limits(&mut self, limits: ContractLimits) {
+        self.contract_limits = limits;
+    }
+
+    /// Functions imported by each of the contracts compiled, and whether contracts are
+    /// allowed to import them.
+    pub fn contract_imports(&self) -> Vec<ContractImports> {
+        let allowed = &self.contract_limits.importable();
+        let mut audit: Vec<_> = self
+            .module_cache
+            .lock()
+            .modules(&self.wasm_store)
+            .into_iter()
+            .map(|(code_hash, module)| ContractImports {
+                code_hash,
+                imports: sandbox::imported_functions(&module, allowed),
+            })
+            .collect();
+        audit.sort_by_key(|contract| contract.code_hash.encode());
+        audit
+    }
+
+    /// Removes the contract code none of the referenced contract instances runs, once
+    /// unreferenced for longer than the grace period, along with its compiled module.
+    pub(crate) fn collect_code_garbage(
+        &mut self,
+        referenced: &HashSet<ContractInstanceId>,
+        grace: Duration,
+        now: Duration,
+    ) -> RuntimeResult<Vec<CodeHash>> {
+        let removed = self
+            .contract_store
+            .collect_garbage(referenced, grace, now)?;
+        let mut module_cache = self.module_cache.lock();
+        for code_hash in &removed {
+            module_cache.remove(code_hash);
+        }
+        Ok(removed)
+    }
+
+    /// Takes the events emitted by the contract which are pending delivery to its subscribers.
+    pub(crate) fn take_emitted_events(&self, key: &ContractKey) -> Vec<Vec<u8>> {
+        native_api::events::take(key.id(), std::time::Instant::now())
+    }
+
     pub(super) fn init_buf<T>(&mut self, instance: &Instance, data: T) -> RuntimeResult<BufferMut>
     where
         T: AsRef<[u8]>,
@@ -173,34 +349,283 @@ impl Runtime {
         Ok(unsafe { WasmLinearMem::new(memory.data_ptr() as *const _, memory.data_size()) })
     }
 
+    /// Current size, in bytes, of the linear memory of the instance.
+    pub(super) fn memory_size(&self, instance: &Instance) -> RuntimeResult<usize> {
+        let memory = self
+            .host_memory
+            .as_ref()
+            .map(Ok)
+            .unwrap_or_else(|| instance.exports.get_memory("memory"))?;
+        Ok(memory.view(&self.wasm_store).data_size() as usize)
+    }
+
+    /// Interval at which the state of the contract must be maintained, if the contract
+    /// declared one in its package.
+    pub(crate) fn maintenance_interval(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<Option<Duration>> {
+        let module = self.contract_module(key, parameters)?;
+        let interval = module
+            .custom_sections(MAINTENANCE_SECTION)
+            .next()
+            .and_then(|section| <[u8; 8]>::try_from(&*section).ok())
+            .map(u64::from_le_bytes)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        Ok(interval)
+    }
+
+    /// Whether the contract declared itself immutable in its package.
+    pub(crate) fn is_immutable(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<bool> {
+        let module = self.contract_module(key, parameters)?;
+        let immutable = module.custom_sections(IMMUTABLE_SECTION).next().is_some();
+        Ok(immutable)
+    }
+
+    /// Version of the contract interface ABI the contract must be called through.
+    pub(super) fn contract_abi(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<ContractAbi> {
+        let module = self.contract_module(key, parameters)?;
+        ContractAbi::of(&module).map_err(|version| {
+            tracing::warn!(%key, version, "Contract declares an unsupported ABI version");
+            ContractExecError::UnsupportedAbi { key: *key, version }.into()
+        })
+    }
+
     pub(super) fn prepare_contract_call(
         &mut self,
         key: &ContractKey,
         parameters: &Parameters,
         req_bytes: usize,
+        call: ContractCall,
     ) -> RuntimeResult<RunningInstance> {
-        let module = if let Some(module) = self.contract_modules.get(key) {
-            module
-        } else {
-            let contract = self
-                .contract_store
-                .fetch_contract(key, parameters)
-                .ok_or_else(|| RuntimeInnerError::ContractNotFound(*key))?;
-            let module = match contract {
-                ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
-                    Module::new(&self.wasm_store, contract_v1.code().data())?
-                }
-                _ => unimplemented!(),
-            };
-            self.contract_modules.insert(*key, module);
-            self.contract_modules.get(key).unwrap()
-        }
-        .clone();
-        let instance = self.prepare_instance(&module)?;
+        let module = self.contract_module(key, parameters)?;
+        self.check_sandbox(key, &module, req_bytes)?;
+        let instance = self.prepare_instance(&module, self.contract_limits.max_memory)?;
         self.set_instance_mem(req_bytes, &instance)?;
+        set_remaining_points(
+            &mut self.wasm_store,
+            &instance,
+            self.contract_limits.fuel(call),
+        );
         RunningInstance::new(self, instance, Key::Contract(*key.id()))
     }
 
+    /// Checks the code of a new contract before storing it: its size, whether it is denied,
+    /// and that it compiles, exports a supported version of the contract interface and imports
+    /// only the host functions allowed. The module compiled is cached for when the contract is executed.
+    pub(crate) fn check_contract_code(
+        &mut self,
+        key: &ContractKey,
+        code: &ContractCode,
+    ) -> RuntimeResult<()> {
+        let rejected = |reason| -> RuntimeResult<()> {
+            tracing::warn!(%key, %reason, "Rejected contract code");
+            Err(ContractExecError::CodeRejected { key: *key, reason }.into())
+        };
+        let limits = &self.contract_limits;
+        let size = code.data().len();
+        if size > limits.max_code_size {
+            return rejected(CodeRejection::TooLarge {
+                size,
+                max: limits.max_code_size,
+            });
+        }
+        let hash = *code.hash();
+        if limits.denied_code.contains(&hash) {
+            return rejected(CodeRejection::Denied);
+        }
+        let module = match self.cached_module(hash, code.data()) {
+            Ok(module) => module,
+            Err(err) => return rejected(CodeRejection::Invalid(err.to_string())),
+        };
+        let exported = ContractAbi::of(&module)
+            .map_err(CodeRejection::UnsupportedAbi)
+            .and_then(|abi| sandbox::check_exports(&module, abi));
+        let reason = match exported {
+            Err(reason) => reason,
+            Ok(()) => match sandbox::forbidden_import(
+                &module,
+                &limits.importable(),
+                self.host_memory.is_some(),
+            ) {
+                Some(import) => CodeRejection::ForbiddenImport(import),
+                None => return Ok(()),
+            },
+        };
+        // no contract can run it, so there is no point in keeping it compiled
+        self.module_cache.lock().remove(&hash);
+        rejected(reason)
+    }
+
+    /// Runs the update of the contract twice, with the same state and data, failing if the
+    /// results differ: peers running the contract would diverge on the state.
+    ///
+    /// The second run compiles the contract with the reference engine configuration, and only
+    /// lets it import the deterministic host functions whatever the limits of this runtime, so
+    /// contracts reading the clock or the random bytes of the host fail with a forbidden
+    /// import. Both results are also compared with the `recorded` one, if given: the result of
+    /// the same update recorded on another host, to compare across architectures.
+    ///
+    /// Passing the check is no proof of determinism: it can't detect results diverging only
+    /// on inputs other than the ones given, nor on hosts or runtime versions other than the
+    /// ones the runs and the recorded result come from.
+    pub fn check_update_determinism(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters<'_>,
+        state: &WrappedState,
+        update_data: &[UpdateData<'_>],
+        recorded: Option<&UpdateModification<'_>>,
+    ) -> RuntimeResult<UpdateModification<'static>> {
+        let first = self.update_state(key, parameters, state, update_data)?;
+        let second = self.with_reference_engine(|runtime| {
+            runtime.update_state(key, parameters, state, update_data)
+        })??;
+        let first_encoded = bincode::serialize(&first)?;
+        let diverged = match recorded {
+            Some(recorded) => first_encoded != bincode::serialize(recorded)?,
+            None => false,
+        };
+        if diverged || first_encoded != bincode::serialize(&second)? {
+            tracing::warn!(%key, "Contract update is not deterministic");
+            return Err(ContractExecError::Nondeterministic(*key).into());
+        }
+        Ok(first)
+    }
+
+    /// Runs `f` with the contracts compiled by the reference engine configuration, and only
+    /// allowed to import the deterministic host functions, restoring this runtime afterwards.
+    fn with_reference_engine<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> RuntimeResult<T> {
+        let mut store = Self::engine_store(wasmer::CraneliftOptLevel::None);
+        let (host_memory, imports) = Self::host_imports(&mut store, self.host_memory.is_some())?;
+        let mut limits = self.contract_limits.clone();
+        limits.deterministic = true;
+        // modules compiled by one engine can't be run by the other
+        let cache = Arc::new(Mutex::new(ModuleCache::in_memory(
+            DEFAULT_MODULE_CACHE_SIZE,
+        )));
+        let store = std::mem::replace(&mut self.wasm_store, store);
+        let imports = std::mem::replace(&mut self.top_level_imports, imports);
+        let host_memory = std::mem::replace(&mut self.host_memory, host_memory);
+        let cache = std::mem::replace(&mut self.module_cache, cache);
+        let limits = std::mem::replace(&mut self.contract_limits, limits);
+        let result = f(self);
+        self.wasm_store = store;
+        self.top_level_imports = imports;
+        self.host_memory = host_memory;
+        self.module_cache = cache;
+        self.contract_limits = limits;
+        Ok(result)
+    }
+
+    /// Rejects the contract before instantiating it if it imports anything it is not allowed
+    /// to, or if it would need more memory than it is allowed to start with.
+    fn check_sandbox(
+        &self,
+        key: &ContractKey,
+        module: &Module,
+        req_bytes: usize,
+    ) -> RuntimeResult<()> {
+        let limits = &self.contract_limits;
+        if let Some(import) =
+            sandbox::forbidden_import(module, &limits.importable(), self.host_memory.is_some())
+        {
+            tracing::warn!(%key, %import, "Contract imports a forbidden function");
+            return Err(ContractExecError::ForbiddenImport { key: *key, import }.into());
+        }
+        let declared = module
+            .exports()
+            .memories()
+            .map(|memory| Bytes::from(memory.ty().minimum).0)
+            .max()
+            .unwrap_or(0);
+        if declared.max(req_bytes) > limits.max_memory {
+            return Err(ContractExecError::MemoryLimitExceeded {
+                key: *key,
+                max: limits.max_memory,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Result of a call to the contract, telling apart the contract running out of fuel or
+    /// growing its memory past the limit from any other trap.
+    pub(super) fn metered<T>(
+        &mut self,
+        instance: &Instance,
+        key: &ContractKey,
+        call: ContractCall,
+        result: Result<T, wasmer::RuntimeError>,
+    ) -> RuntimeResult<T> {
+        if let MeteringPoints::Exhausted = get_remaining_points(&mut self.wasm_store, instance) {
+            let fuel = self.contract_limits.fuel(call);
+            tracing::warn!(%key, %call, fuel, "Contract ran out of fuel");
+            return Err(ContractExecError::OutOfFuel {
+                key: *key,
+                call,
+                fuel,
+            }
+            .into());
+        }
+        let max_memory = self.contract_limits.max_memory;
+        if self.memory_size(instance)? > max_memory {
+            tracing::warn!(%key, %call, max_memory, "Contract exceeded its memory limit");
+            return Err(ContractExecError::MemoryLimitExceeded {
+                key: *key,
+                max: max_memory,
+            }
+            .into());
+        }
+        Ok(result?)
+    }
+
+    fn contract_module(
+        &mut self,
+        key: &ContractKey,
+        parameters: &Parameters,
+    ) -> RuntimeResult<Module> {
+        if let Some(module) = key
+            .code_hash()
+            .and_then(|hash| self.module_cache.lock().get(&self.wasm_store, hash))
+        {
+            return Ok(module);
+        }
+        let contract = self
+            .contract_store
+            .fetch_contract(key, parameters)
+            .ok_or_else(|| RuntimeInnerError::ContractNotFound(*key))?;
+        let module = match contract {
+            ContractContainer::Wasm(ContractWasmAPIVersion::V1(contract_v1)) => {
+                let code = contract_v1.code();
+                self.cached_module(*code.hash(), code.data())?
+            }
+            _ => unimplemented!(),
+        };
+        Ok(module)
+    }
+
+    /// Compiled module of the code, compiled outside the lock of the cache shared with the
+    /// forked runtimes if not cached yet, so they can keep running meanwhile.
+    fn cached_module(&self, hash: CodeHash, code: &[u8]) -> RuntimeResult<Module> {
+        if let Some(module) = self.module_cache.lock().get(&self.wasm_store, &hash) {
+            return Ok(module);
+        }
+        let module = ModuleCache::compile(&self.wasm_store, code)?;
+        self.module_cache.lock().insert(hash, &module);
+        Ok(module)
+    }
+
     pub(super) fn prepare_delegate_call(
         &mut self,
         params: &Parameters,
@@ -219,7 +644,7 @@ impl Runtime {
             self.delegate_modules.get(key).unwrap()
         }
         .clone();
-        let instance = self.prepare_instance(&module)?;
+        let instance = self.prepare_instance(&module, self.delegate_limits.max_memory)?;
         self.set_instance_mem(req_bytes, &instance)?;
         RunningInstance::new(self, instance, Key::Delegate(key.clone()))
     }
@@ -250,17 +675,45 @@ impl Runtime {
         Ok(Memory::new(store, MemoryType::new(20u32, None, false))?)
     }
 
-    fn prepare_instance(&mut self, module: &Module) -> RuntimeResult<Instance> {
-        Ok(Instance::new(
-            &mut self.wasm_store,
-            module,
-            &self.top_level_imports,
-        )?)
+    /// Instantiates the module, with its linear memory unable to grow past `max_memory` bytes.
+    fn prepare_instance(&mut self, module: &Module, max_memory: usize) -> RuntimeResult<Instance> {
+        let Self {
+            wasm_store,
+            top_level_imports,
+            ..
+        } = self;
+        Ok(sandbox::with_memory_limit(max_memory, || {
+            Instance::new(wasm_store, module, top_level_imports)
+        })?)
     }
 
     fn instance_store() -> Store {
-        use wasmer::Cranelift;
-        Store::new(Cranelift::new())
+        Self::engine_store(wasmer::CraneliftOptLevel::Speed)
+    }
+
+    /// Store of an engine compiling the modules with the given optimizations. Modules must
+    /// compute the same results whatever the optimizations they are compiled with.
+    fn engine_store(opt_level: wasmer::CraneliftOptLevel) -> Store {
+        use wasmer::{
+            wasmparser::Operator, BaseTunables, CompilerConfig, Cranelift, Engine, NativeEngineExt,
+            Target,
+        };
+        use wasmer_middlewares::Metering;
+        // every instruction costs one unit of fuel, instances are given their fuel quota
+        // before each call
+        let metering = Arc::new(Metering::new(u64::MAX, |_: &Operator| 1));
+        let mut compiler = Cranelift::new();
+        compiler.opt_level(opt_level);
+        // NaNs can have any bit pattern depending on the host, they are made canonical so
+        // floating point results are the same on every peer
+        compiler.canonicalize_nans(true);
+        compiler.push_middleware(metering);
+        let mut engine: Engine = compiler.into();
+        // the memories of the instances are created with the memory limit as their maximum
+        engine.set_tunables(sandbox::LimitingTunables::new(BaseTunables::for_target(
+            &Target::default(),
+        )));
+        Store::new(engine)
     }
 
     // #[cfg(not(test))]
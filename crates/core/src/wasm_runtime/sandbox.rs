@@ -0,0 +1,371 @@
+//! What contract code is allowed to reach out of its sandbox for.
+//!
+//! Contracts can only import the host functions explicitly allowed, any other import is
+//! rejected before the contract is instantiated, including the host functions added to the
+//! runtime in the future. When contracts must execute deterministically, the clock and the
+//! random bytes of the host cannot be imported either. The imports of the contracts compiled
+//! can be audited, to know which host functions each of them relies on.
+//!
+//! The code of new contracts is also checked before being stored: it must fit in the size
+//! limit, not be denied, declare a version of the contract interface the runtime supports and
+//! export its functions with the signatures the runtime calls them with.
+//!
+//! The linear memories of the instances are created with a maximum size, the memory limit of
+//! the contract or delegate being instantiated, so the module cannot grow them past it while
+//! running.
+
+use std::{cell::Cell, ptr::NonNull};
+
+use freenet_stdlib::prelude::CodeHash;
+use serde::{Deserialize, Serialize};
+use wasmer::{
+    vm::{self, MemoryError, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition},
+    BaseTunables, ExternType, MemoryType, Module, Pages, TableType, Tunables, Type,
+};
+
+use super::contract::ContractAbi;
+
+/// Host function the runtime exports to the wasm modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostFunction {
+    /// Logs a message on behalf of the module.
+    Log,
+    /// Fills a buffer with random bytes.
+    Rand,
+    /// Current time of the host.
+    Time,
+    /// Emits an event to the subscribers of the contract.
+    Events,
+}
+
+impl HostFunction {
+    pub const ALL: [Self; 4] = [Self::Log, Self::Rand, Self::Time, Self::Events];
+
+    /// Namespace the function is imported from.
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            Self::Log => "freenet_log",
+            Self::Rand => "freenet_rand",
+            Self::Time => "freenet_time",
+            Self::Events => "freenet_events",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Log => "__frnt__logger__info",
+            Self::Rand => "__frnt__rand__rand_bytes",
+            Self::Time => "__frnt__time__utc_now",
+            Self::Events => "__frnt__events__emit",
+        }
+    }
+
+    /// Whether the function returns the same results every time it is called with the same
+    /// arguments, on any host.
+    pub fn is_deterministic(&self) -> bool {
+        !matches!(self, Self::Rand | Self::Time)
+    }
+
+    fn of(namespace: &str, name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|function| function.namespace() == namespace && function.name() == name)
+    }
+}
+
+/// Function contracts export, with its parameters and results, and whether it must be.
+pub(super) type Export = (&'static str, &'static [Type], &'static [Type], bool);
+
+/// Why the code of a contract was refused before storing it.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeRejection {
+    #[error("code of {size} bytes exceeds the limit of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    #[error("code is denied on this node")]
+    Denied,
+    #[error("invalid wasm module: {0}")]
+    Invalid(String),
+    #[error("missing export `{0}`")]
+    MissingExport(String),
+    #[error("export `{0}` does not have the signature of the contract interface")]
+    WrongSignature(String),
+    #[error("imports {0}, which contracts are not allowed to")]
+    ForbiddenImport(String),
+    #[error("declares version {0} of the contract interface, which this node does not support")]
+    UnsupportedAbi(u32),
+}
+
+/// A function imported by a contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostImport {
+    pub namespace: String,
+    pub name: String,
+    /// The host function imported, none if the runtime exports no such function.
+    pub function: Option<HostFunction>,
+    /// Whether contracts are allowed to import it.
+    pub allowed: bool,
+}
+
+/// Functions imported by a compiled contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractImports {
+    pub code_hash: CodeHash,
+    pub imports: Vec<HostImport>,
+}
+
+/// Functions imported by the module, and whether contracts are allowed to import them.
+pub(super) fn imported_functions(module: &Module, allowed: &[HostFunction]) -> Vec<HostImport> {
+    module
+        .imports()
+        .functions()
+        .map(|import| {
+            let function = HostFunction::of(import.module(), import.name());
+            HostImport {
+                namespace: import.module().to_owned(),
+                name: import.name().to_owned(),
+                function,
+                allowed: function.is_some_and(|function| allowed.contains(&function)),
+            }
+        })
+        .collect()
+}
+
+/// First import of the module contracts are not allowed, as `namespace.name`. Besides the
+/// host functions allowed, only the linear memory shared by the host can be imported, when
+/// the runtime shares one.
+pub(super) fn forbidden_import(
+    module: &Module,
+    allowed: &[HostFunction],
+    host_memory: bool,
+) -> Option<String> {
+    module
+        .imports()
+        .find(|import| match import.ty() {
+            ExternType::Function(_) => !HostFunction::of(import.module(), import.name())
+                .is_some_and(|function| allowed.contains(&function)),
+            ExternType::Memory(_) => {
+                !(host_memory && import.module() == "env" && import.name() == "memory")
+            }
+            _ => true,
+        })
+        .map(|import| format!("{}.{}", import.module(), import.name()))
+}
+
+/// Checks the module exports the functions of the version of the contract interface it
+/// declares, with the right signatures.
+pub(super) fn check_exports(module: &Module, abi: ContractAbi) -> Result<(), CodeRejection> {
+    for &(name, params, results, required) in abi.exports() {
+        let Some(export) = module.exports().find(|export| export.name() == name) else {
+            if required {
+                return Err(CodeRejection::MissingExport(name.to_owned()));
+            }
+            continue;
+        };
+        match export.ty() {
+            ExternType::Function(ty) if ty.params() == params && ty.results() == results => {}
+            _ => return Err(CodeRejection::WrongSignature(name.to_owned())),
+        }
+    }
+    Ok(())
+}
+
+thread_local! {
+    /// Max size of the linear memories created on this thread, while a module is instantiated.
+    static MEMORY_LIMIT: Cell<Option<Pages>> = const { Cell::new(None) };
+}
+
+/// Runs `f`, which instantiates a module, capping the linear memories it creates at
+/// `max_memory` bytes.
+pub(super) fn with_memory_limit<T>(max_memory: usize, f: impl FnOnce() -> T) -> T {
+    let pages = (max_memory / wasmer::WASM_PAGE_SIZE).min(wasmer::WASM_MAX_PAGES as usize);
+    let previous = MEMORY_LIMIT.with(|limit| limit.replace(Some(Pages(pages as u32))));
+    let result = f();
+    MEMORY_LIMIT.with(|limit| limit.set(previous));
+    result
+}
+
+/// Tunables of the engine which cap the maximum of the linear memories at the limit set with
+/// [`with_memory_limit`], so `memory.grow` fails once the module reaches it instead of
+/// allocating past it during the call.
+pub(super) struct LimitingTunables {
+    base: BaseTunables,
+}
+
+impl LimitingTunables {
+    pub(super) fn new(base: BaseTunables) -> Self {
+        Self { base }
+    }
+
+    fn limit(ty: &MemoryType) -> Result<MemoryType, MemoryError> {
+        let Some(limit) = MEMORY_LIMIT.with(Cell::get) else {
+            return Ok(*ty);
+        };
+        if ty.minimum > limit {
+            return Err(MemoryError::Generic(format!(
+                "minimum of {} pages exceeds the limit of {} pages",
+                ty.minimum.0, limit.0
+            )));
+        }
+        let mut limited = *ty;
+        limited.maximum = Some(ty.maximum.map_or(limit, |max| max.min(limit)));
+        Ok(limited)
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        self.base.create_host_memory(&Self::limit(ty)?, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        self.base
+            .create_vm_memory(&Self::limit(ty)?, style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<vm::VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<vm::VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::Store;
+
+    use super::super::contract::ContractLimits;
+    use super::*;
+
+    const WAT: &str = r#"(module
+        (import "freenet_log" "__frnt__logger__info" (func (param i64 i64 i32)))
+        (import "freenet_time" "__frnt__time__utc_now" (func (param i64 i64)))
+        (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+    )"#;
+
+    #[test]
+    fn only_allowed_imports_pass() -> Result<(), Box<dyn std::error::Error>> {
+        let store = Store::default();
+        let module = Module::new(&store, WAT)?;
+        let allowed = [HostFunction::Log];
+
+        let imports = imported_functions(&module, &allowed);
+        let audit: Vec<_> = imports
+            .iter()
+            .map(|import| (import.name.as_str(), import.function, import.allowed))
+            .collect();
+        assert_eq!(
+            audit,
+            [
+                ("__frnt__logger__info", Some(HostFunction::Log), true),
+                ("__frnt__time__utc_now", Some(HostFunction::Time), false),
+                ("fd_write", None, false),
+            ]
+        );
+        assert_eq!(
+            forbidden_import(&module, &allowed, false).as_deref(),
+            Some("freenet_time.__frnt__time__utc_now")
+        );
+        assert_eq!(
+            forbidden_import(&module, &[HostFunction::Log, HostFunction::Time], false).as_deref(),
+            Some("wasi_snapshot_preview1.fd_write")
+        );
+        // the clock of the host is off limits for deterministic contracts
+        let limits = ContractLimits {
+            deterministic: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            forbidden_import(&module, &limits.importable(), false).as_deref(),
+            Some("freenet_time.__frnt__time__utc_now")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn contract_interface_is_exported() -> Result<(), Box<dyn std::error::Error>> {
+        let store = Store::default();
+        let interface = |maintain: &str| {
+            format!(
+                r#"(module
+                (func (export "validate_state") (param i64 i64 i64) (result i64) i64.const 0)
+                (func (export "update_state") (param i64 i64 i64) (result i64) i64.const 0)
+                (func (export "summarize_state") (param i64 i64) (result i64) i64.const 0)
+                (func (export "get_state_delta") (param i64 i64 i64) (result i64) i64.const 0)
+                (func (export "__frnt_set_id") (param i64))
+                (func (export "__frnt__initiate_buffer") (param i32) (result i64) i64.const 0)
+                {maintain}
+            )"#
+            )
+        };
+        let module = Module::new(&store, interface(""))?;
+        assert_eq!(check_exports(&module, ContractAbi::V1), Ok(()));
+
+        let maintain = r#"(func (export "maintain_state") (param i64) (result i64) i64.const 0)"#;
+        let module = Module::new(&store, interface(maintain))?;
+        assert_eq!(
+            check_exports(&module, ContractAbi::V1),
+            Err(CodeRejection::WrongSignature("maintain_state".into()))
+        );
+
+        let module = Module::new(&store, WAT)?;
+        assert_eq!(
+            check_exports(&module, ContractAbi::V1),
+            Err(CodeRejection::MissingExport("validate_state".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn memories_are_capped_at_the_limit() -> Result<(), Box<dyn std::error::Error>> {
+        use wasmer::{imports, Cranelift, Engine, Instance, NativeEngineExt, Target};
+
+        let mut engine: Engine = Cranelift::default().into();
+        engine.set_tunables(LimitingTunables::new(BaseTunables::for_target(
+            &Target::default(),
+        )));
+        let mut store = Store::new(engine);
+        let module = Module::new(&store, r#"(module (memory (export "memory") 1))"#)?;
+        let max_memory = 4 * wasmer::WASM_PAGE_SIZE;
+
+        let instance = with_memory_limit(max_memory, || {
+            Instance::new(&mut store, &module, &imports! {})
+        })?;
+        let memory = instance.exports.get_memory("memory")?;
+        assert_eq!(memory.ty(&store).maximum, Some(Pages(4)));
+        memory.grow(&mut store, 3)?;
+        assert!(memory.grow(&mut store, 1).is_err());
+
+        let module = Module::new(&store, r#"(module (memory (export "memory") 8))"#)?;
+        assert!(with_memory_limit(max_memory, || {
+            Instance::new(&mut store, &module, &imports! {})
+        })
+        .is_err());
+        Ok(())
+    }
+}
@@ -215,6 +215,20 @@ impl SecretsStore {
         }
     }
 
+    /// Size on disk of the secrets stored by the delegate, other than the given one.
+    pub fn stored_size(&self, delegate: &DelegateKey, except: &SecretsId) -> u64 {
+        let except = except.encode();
+        let Ok(entries) = fs::read_dir(self.base_path.join(delegate.encode())) else {
+            return 0;
+        };
+        entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_str() != Some(except.as_str()))
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
     pub fn get_secret(
         &self,
         delegate: &DelegateKey,
@@ -0,0 +1,317 @@
+//! Journal of the recent changes of the contract states.
+//!
+//! Every change of the state of a contract gets a new version. The journal keeps the deltas
+//! the last versions resulted from, and a snapshot of the state every few versions, so any of
+//! the recent versions can be rebuilt by applying the deltas since the snapshot preceding it,
+//! which allows rolling back updates found invalid after being applied.
+//!
+//! It also remembers the summaries of the recent versions, and the deltas computed to bring
+//! them up to date, so the changes since a summary seen recently are answered without running
+//! the contract.
+
+use std::collections::{HashMap, VecDeque};
+
+use freenet_stdlib::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Deltas computed to bring a summary up to date kept per contract.
+const MAX_ANSWERS: usize = 8;
+
+/// How much of the history of the contract states is journaled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateJournalLimits {
+    /// Deltas kept per contract.
+    pub max_deltas: usize,
+    /// Versions between snapshots.
+    pub snapshot_interval: u64,
+    /// Bytes of snapshots and deltas kept over all the contracts, the journals of the
+    /// contracts changed least recently are dropped past it.
+    pub max_bytes: usize,
+}
+
+impl Default for StateJournalLimits {
+    fn default() -> Self {
+        Self {
+            max_deltas: 32,
+            snapshot_interval: 8,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+struct Answer {
+    since: StateSummary<'static>,
+    version: u64,
+    delta: StateDelta<'static>,
+}
+
+#[derive(Default)]
+struct Journal {
+    /// Version of the current state.
+    version: u64,
+    /// States at the versions they were taken, oldest first.
+    snapshots: VecDeque<(u64, WrappedState)>,
+    /// Deltas by the version they resulted in, oldest first.
+    deltas: VecDeque<(u64, StateDelta<'static>)>,
+    /// Summaries by the version they summarize.
+    summaries: VecDeque<(u64, StateSummary<'static>)>,
+    answers: VecDeque<Answer>,
+    /// Change count of the journal when the contract last changed.
+    last_change: u64,
+    bytes: usize,
+}
+
+impl Journal {
+    /// Drops the snapshots, and the deltas depending on them, older than the latest snapshot
+    /// all the deltas kept can be replayed from.
+    fn prune(&mut self, max_deltas: usize) {
+        while self.deltas.len() > max_deltas {
+            let (_, delta) = self.deltas.pop_front().unwrap();
+            self.bytes -= delta.size();
+        }
+        let oldest = self
+            .deltas
+            .front()
+            .map_or(self.version, |(version, _)| version - 1);
+        while self.snapshots.len() > 1 && self.snapshots[1].0 <= oldest {
+            let (_, state) = self.snapshots.pop_front().unwrap();
+            self.bytes -= state.size();
+        }
+        let first = self
+            .snapshots
+            .front()
+            .map_or(self.version, |(version, _)| *version);
+        while self
+            .deltas
+            .front()
+            .is_some_and(|(version, _)| *version <= first)
+        {
+            let (_, delta) = self.deltas.pop_front().unwrap();
+            self.bytes -= delta.size();
+        }
+        self.summaries.retain(|(version, _)| *version >= first);
+        self.answers.retain(|answer| answer.version == self.version);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct StateJournal {
+    limits: StateJournalLimits,
+    contracts: HashMap<ContractKey, Journal>,
+    /// Changes recorded over all the contracts.
+    changes: u64,
+    bytes: usize,
+}
+
+impl StateJournal {
+    /// Sets how much history is journaled, dropping what is over the new limits.
+    pub fn set_limits(&mut self, limits: StateJournalLimits) {
+        self.limits = limits;
+        self.bytes = 0;
+        for journal in self.contracts.values_mut() {
+            journal.prune(limits.max_deltas);
+            self.bytes += journal.bytes;
+        }
+        self.shrink(None);
+    }
+
+    /// Current version of the state of the contract, if journaled.
+    pub fn version(&self, key: &ContractKey) -> Option<u64> {
+        self.contracts.get(key).map(|journal| journal.version)
+    }
+
+    /// Records a new state of the contract, returning its version. When the delta it results
+    /// from, applied on the previous state, is given, the new state is rebuilt from it,
+    /// otherwise it is kept as a snapshot.
+    pub fn record(
+        &mut self,
+        key: &ContractKey,
+        state: &WrappedState,
+        delta: Option<StateDelta<'static>>,
+    ) -> u64 {
+        self.changes += 1;
+        let journal = self.contracts.entry(*key).or_default();
+        let before = journal.bytes;
+        journal.version += 1;
+        journal.last_change = self.changes;
+        match delta {
+            Some(delta)
+                if !journal.snapshots.is_empty()
+                    && journal.version % self.limits.snapshot_interval != 0 =>
+            {
+                journal.bytes += delta.size();
+                journal.deltas.push_back((journal.version, delta));
+            }
+            _ => {
+                journal.bytes += state.size();
+                journal
+                    .snapshots
+                    .push_back((journal.version, state.clone()));
+            }
+        }
+        journal.prune(self.limits.max_deltas);
+        let version = journal.version;
+        self.bytes = self.bytes + journal.bytes - before;
+        self.shrink(Some(key));
+        version
+    }
+
+    /// Forgets the contract, once its state is removed.
+    pub fn remove(&mut self, key: &ContractKey) {
+        if let Some(journal) = self.contracts.remove(key) {
+            self.bytes -= journal.bytes;
+        }
+    }
+
+    /// Remembers the summary of the current state of the contract.
+    pub fn summarized(&mut self, key: &ContractKey, summary: StateSummary<'static>) {
+        if let Some(journal) = self.contracts.get_mut(key) {
+            let version = journal.version;
+            if !journal.summaries.iter().any(|(v, _)| *v == version) {
+                journal.summaries.push_back((version, summary));
+            }
+        }
+    }
+
+    /// Remembers the delta computed to bring the summary up to the current state.
+    pub fn answered(
+        &mut self,
+        key: &ContractKey,
+        since: StateSummary<'static>,
+        delta: StateDelta<'static>,
+    ) {
+        if let Some(journal) = self.contracts.get_mut(key) {
+            if journal.answers.len() >= MAX_ANSWERS {
+                journal.answers.pop_front();
+            }
+            journal.answers.push_back(Answer {
+                since,
+                version: journal.version,
+                delta,
+            });
+        }
+    }
+
+    /// Delta bringing the summary up to the current state of the contract, if known: either
+    /// computed before, or the last one applied when the summary is of the previous state.
+    pub fn delta_since(
+        &self,
+        key: &ContractKey,
+        summary: &StateSummary,
+    ) -> Option<StateDelta<'static>> {
+        let journal = self.contracts.get(key)?;
+        if let Some(answer) = journal
+            .answers
+            .iter()
+            .find(|answer| answer.version == journal.version && answer.since == *summary)
+        {
+            return Some(answer.delta.clone());
+        }
+        let (version, delta) = journal.deltas.back()?;
+        let previous = journal
+            .summaries
+            .iter()
+            .any(|(v, s)| *v + 1 == *version && s == summary);
+        (*version == journal.version && previous).then(|| delta.clone())
+    }
+
+    /// Snapshot, and the deltas to apply on it in order, to rebuild the state of the contract
+    /// at the given version, if still journaled.
+    pub fn replay(
+        &self,
+        key: &ContractKey,
+        version: u64,
+    ) -> Option<(WrappedState, Vec<StateDelta<'static>>)> {
+        let journal = self.contracts.get(key)?;
+        let (since, snapshot) = journal
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(since, _)| *since <= version)?;
+        let deltas: Vec<_> = journal
+            .deltas
+            .iter()
+            .filter(|(v, _)| *v > *since && *v <= version)
+            .map(|(_, delta)| delta.clone())
+            .collect();
+        (deltas.len() as u64 == version - since).then(|| (snapshot.clone(), deltas))
+    }
+
+    /// Drops the versions after the given one, which becomes the current one again.
+    pub fn roll_back(&mut self, key: &ContractKey, version: u64) {
+        let Some(journal) = self.contracts.get_mut(key) else {
+            return;
+        };
+        let before = journal.bytes;
+        while journal.snapshots.back().is_some_and(|(v, _)| *v > version) {
+            let (_, state) = journal.snapshots.pop_back().unwrap();
+            journal.bytes -= state.size();
+        }
+        while journal.deltas.back().is_some_and(|(v, _)| *v > version) {
+            let (_, delta) = journal.deltas.pop_back().unwrap();
+            journal.bytes -= delta.size();
+        }
+        journal.summaries.retain(|(v, _)| *v <= version);
+        journal.answers.clear();
+        journal.version = version;
+        self.bytes = self.bytes + journal.bytes - before;
+    }
+
+    /// Drops the journals of the contracts changed least recently, besides the given one,
+    /// until all fit in the max size again.
+    fn shrink(&mut self, keep: Option<&ContractKey>) {
+        while self.bytes > self.limits.max_bytes {
+            let Some(oldest) = self
+                .contracts
+                .iter()
+                .filter(|(key, _)| Some(*key) != keep)
+                .min_by_key(|(_, journal)| journal.last_change)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_rebuilt_from_snapshots_and_deltas() {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let mut journal = StateJournal::default();
+        let state = |byte| WrappedState::new(vec![byte; 4]);
+        let delta = |byte: u8| StateDelta::from(vec![byte]);
+
+        assert_eq!(journal.record(&key, &state(0), None), 1);
+        for byte in 1..10 {
+            journal.record(&key, &state(byte), Some(delta(byte)));
+        }
+        assert_eq!(journal.version(&key), Some(10));
+
+        // version 8 is a snapshot, the rest are rebuilt from the previous one
+        let (snapshot, deltas) = journal.replay(&key, 7).unwrap();
+        assert_eq!(snapshot.as_ref(), state(0).as_ref());
+        assert_eq!(deltas, (1..7).map(delta).collect::<Vec<_>>());
+        let (snapshot, deltas) = journal.replay(&key, 10).unwrap();
+        assert_eq!(snapshot.as_ref(), state(7).as_ref());
+        assert_eq!(deltas, [delta(8), delta(9)]);
+
+        // the last delta answers for the summary of the previous state
+        let summary = StateSummary::from(vec![9]);
+        assert!(journal.delta_since(&key, &summary).is_none());
+        journal.roll_back(&key, 9);
+        journal.summarized(&key, summary.clone());
+        journal.record(&key, &state(10), Some(delta(10)));
+        assert_eq!(journal.delta_since(&key, &summary), Some(delta(10)));
+
+        // rolling back forgets the later versions
+        journal.roll_back(&key, 9);
+        assert!(journal.delta_since(&key, &summary).is_none());
+        assert!(journal.replay(&key, 10).is_none());
+        assert!(journal.replay(&key, 9).is_some());
+    }
+}
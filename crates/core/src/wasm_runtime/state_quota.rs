@@ -0,0 +1,237 @@
+//! Quota on the bytes of contract states persisted by a node.
+//!
+//! Once the states stored go over the quota, the ones least worth keeping are evicted until
+//! they fit again. States which have not been read or written for longer, and which fewer
+//! peers are subscribed to, go first. States of the contracts the node is home for are never
+//! evicted, since the network relies on it to hold them.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use freenet_stdlib::prelude::ContractKey;
+
+use crate::util::time_source::{SharedClock, TokioClock};
+
+/// How worth keeping the state of a contract is to the node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Whether the node is home for the contract, in which case its state is never evicted.
+    pub home: bool,
+    /// Peers subscribed through this node to the updates of the contract.
+    pub subscribers: usize,
+}
+
+/// A state evicted to get back under the quota.
+#[derive(Debug, Clone)]
+pub struct Eviction {
+    pub key: ContractKey,
+    pub size: u64,
+    /// Time since the state was last read or written.
+    pub idle: Duration,
+    pub subscribers: usize,
+}
+
+/// Decides how worth keeping the states are, and is told of the ones evicted.
+pub trait RetentionPolicy: Send + Sync {
+    fn retention(&self, key: &ContractKey) -> Retention;
+
+    fn evicted(&self, eviction: &Eviction);
+}
+
+/// Policy of a node on its own, with no contracts it is home for.
+struct Unpinned;
+
+impl RetentionPolicy for Unpinned {
+    fn retention(&self, _: &ContractKey) -> Retention {
+        Retention::default()
+    }
+
+    fn evicted(&self, _: &Eviction) {}
+}
+
+struct Entry {
+    size: u64,
+    last_access: Instant,
+}
+
+pub struct StateQuota {
+    max_bytes: u64,
+    used: u64,
+    entries: HashMap<ContractKey, Entry>,
+    policy: Arc<dyn RetentionPolicy>,
+    /// Clock the time states stay idle is measured with.
+    clock: SharedClock,
+}
+
+impl StateQuota {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            used: 0,
+            entries: HashMap::new(),
+            policy: Arc::new(Unpinned),
+            clock: Arc::new(TokioClock),
+        }
+    }
+
+    pub fn with_policy(self, policy: Arc<dyn RetentionPolicy>) -> Self {
+        Self { policy, ..self }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub(super) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub(super) fn accessed(&mut self, key: &ContractKey, now: Instant) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_access = now;
+        }
+    }
+
+    /// When the state was last read or written, if accounted for.
+    pub(super) fn last_access(&self, key: &ContractKey) -> Option<Instant> {
+        self.entries.get(key).map(|entry| entry.last_access)
+    }
+
+    pub(super) fn stored(&mut self, key: ContractKey, size: u64, now: Instant) {
+        let previous = self.entries.insert(
+            key,
+            Entry {
+                size,
+                last_access: now,
+            },
+        );
+        self.used = self.used - previous.map_or(0, |entry| entry.size) + size;
+    }
+
+    /// Stops accounting for the state, removed from the store.
+    pub(super) fn removed(&mut self, key: &ContractKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.used -= entry.size;
+        }
+    }
+
+    /// Picks the states to evict to get back under the quota, which stop being accounted for,
+    /// other than the one to keep. The policy is told of each of them.
+    pub(super) fn over_quota(&mut self, now: Instant, keep: Option<&ContractKey>) -> Vec<Eviction> {
+        if self.used <= self.max_bytes {
+            return vec![];
+        }
+        let mut candidates: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(key, _)| Some(*key) != keep)
+            .filter_map(|(key, entry)| {
+                let retention = self.policy.retention(key);
+                (!retention.home).then(|| Eviction {
+                    key: *key,
+                    size: entry.size,
+                    idle: now.saturating_duration_since(entry.last_access),
+                    subscribers: retention.subscribers,
+                })
+            })
+            .collect();
+        // the longer idle per subscriber, the less worth keeping
+        let weight =
+            |eviction: &Eviction| eviction.idle.as_secs_f64() / (1 + eviction.subscribers) as f64;
+        candidates.sort_by(|a, b| weight(b).total_cmp(&weight(a)));
+
+        let mut evicted = vec![];
+        for candidate in candidates {
+            if self.used <= self.max_bytes {
+                break;
+            }
+            self.entries.remove(&candidate.key);
+            self.used -= candidate.size;
+            self.policy.evicted(&candidate);
+            evicted.push(candidate);
+        }
+        if self.used > self.max_bytes {
+            tracing::warn!(
+                used = self.used,
+                quota = self.max_bytes,
+                "States of home contracts and the last one stored alone go over the storage quota"
+            );
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use freenet_stdlib::prelude::ContractInstanceId;
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    struct Pinned {
+        home: ContractKey,
+        subscribed: ContractKey,
+        evicted: Mutex<Vec<ContractKey>>,
+    }
+
+    impl RetentionPolicy for Pinned {
+        fn retention(&self, key: &ContractKey) -> Retention {
+            Retention {
+                home: key == &self.home,
+                subscribers: if key == &self.subscribed { 4 } else { 0 },
+            }
+        }
+
+        fn evicted(&self, eviction: &Eviction) {
+            self.evicted.lock().push(eviction.key);
+        }
+    }
+
+    #[test]
+    fn evicts_least_worth_keeping() {
+        let key = |byte| ContractKey::from(ContractInstanceId::new([byte; 32]));
+        let policy = Arc::new(Pinned {
+            home: key(1),
+            subscribed: key(2),
+            evicted: Mutex::default(),
+        });
+        let mut quota = StateQuota::new(300).with_policy(policy.clone());
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // the home contract is the oldest, and the subscribed one idle for longer than the last
+        quota.stored(key(1), 100, at(0));
+        quota.stored(key(2), 100, at(1));
+        quota.stored(key(3), 100, at(5));
+        assert!(quota.over_quota(at(10), None).is_empty());
+
+        // a new state goes over the quota, the unsubscribed one goes first
+        quota.stored(key(4), 100, at(10));
+        let evicted = quota.over_quota(at(10), Some(&key(4)));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].key, key(3));
+        assert_eq!(quota.used, 300);
+
+        // growing states count for their new size
+        quota.stored(key(4), 250, at(20));
+        let evicted: Vec<_> = quota
+            .over_quota(at(30), None)
+            .into_iter()
+            .map(|eviction| eviction.key)
+            .collect();
+        assert_eq!(evicted, [key(4)]);
+        assert_eq!(*policy.evicted.lock(), [key(3), key(4)]);
+        assert_eq!(quota.used, 200);
+
+        // the state just stored is kept, even if the others evicted don't make room for it
+        quota.stored(key(5), 250, at(40));
+        let evicted = quota.over_quota(at(40), Some(&key(5)));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].key, key(2));
+        assert_eq!(quota.last_access(&key(5)), Some(at(40)));
+        assert_eq!(quota.used, 350);
+    }
+}
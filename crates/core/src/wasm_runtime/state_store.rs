@@ -1,13 +1,27 @@
 use core::future::Future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
 use freenet_stdlib::prelude::*;
+use parking_lot::{Mutex, MutexGuard};
 use stretto::AsyncCache;
 
+use super::{
+    state_journal::{StateJournal, StateJournalLimits},
+    Eviction, StateQuota,
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum StateStoreError {
     #[error(transparent)]
     Any(#[from] anyhow::Error),
     #[error("missing contract: {0}")]
     MissingContract(ContractKey),
+    #[error("state of contract {0} not kept by the volatile store, it is full")]
+    NotRetained(ContractKey),
 }
 
 impl From<StateStoreError> for crate::wasm_runtime::ContractError {
@@ -16,7 +30,7 @@ impl From<StateStoreError> for crate::wasm_runtime::ContractError {
             StateStoreError::Any(err) => {
                 crate::wasm_runtime::ContractError::from(anyhow::format_err!(err))
             }
-            err @ StateStoreError::MissingContract(_) => {
+            err @ (StateStoreError::MissingContract(_) | StateStoreError::NotRetained(_)) => {
                 crate::wasm_runtime::ContractError::from(anyhow::format_err!(err))
             }
         }
@@ -25,6 +39,7 @@ impl From<StateStoreError> for crate::wasm_runtime::ContractError {
 
 pub trait StateStorage {
     type Error;
+    /// Writes the state of the contract, along with the hash of its bytes.
     fn store(
         &mut self,
         key: ContractKey,
@@ -43,12 +58,48 @@ pub trait StateStorage {
         &'a self,
         key: &'a ContractKey,
     ) -> impl Future<Output = Result<Option<Parameters<'static>>, Self::Error>> + Send + 'a;
+    /// Removes the state and the parameters of the contract, if stored.
+    fn remove(&mut self, key: &ContractKey)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+    /// Bytes of each of the states stored.
+    fn state_sizes(
+        &self,
+    ) -> impl Future<Output = Result<Vec<(ContractKey, u64)>, Self::Error>> + Send;
+    /// The state of the contract along with the hash of its bytes recorded, with it, when
+    /// written, if any. The state is returned even if its bytes no longer match the hash.
+    fn get_with_digest(
+        &self,
+        key: &ContractKey,
+    ) -> impl Future<Output = Result<Option<(WrappedState, Option<blake3::Hash>)>, Self::Error>> + Send;
+}
+
+/// What checking a stored state against the hash recorded when it was written found.
+pub enum StoredState {
+    /// Its bytes are the ones written.
+    Intact(WrappedState),
+    /// Written by a version of the node which did not record its hash, so its bytes can't be
+    /// checked. Its hash is recorded once the state is found valid, see
+    /// [`StateStore::record_digest`].
+    Unrecorded(WrappedState),
+    /// Its bytes changed since written.
+    Corrupted,
+    /// No state of the contract is stored.
+    Missing,
 }
 
+/// Cloning it shares the mem cache, the store, the quota and the journal, so executors running side by side
+/// see the same states.
+#[derive(Clone)]
 pub struct StateStore<S: StateStorage> {
     state_mem_cache: AsyncCache<ContractKey, WrappedState>,
     // params_mem_cache: AsyncCache<ContractKey, Parameters<'static>>,
     store: S,
+    /// Whether states are only kept in the mem cache, and never written to the store.
+    volatile: bool,
+    /// Bytes of states written to the store beyond which the least worth keeping are evicted.
+    quota: Option<Arc<Mutex<StateQuota>>>,
+    /// Recent versions of the states stored.
+    journal: Arc<Mutex<StateJournal>>,
 }
 
 impl<S> StateStore<S>
@@ -68,28 +119,164 @@ where
             // params_mem_cache: AsyncCache::new(counters, max_size as i64)
             //     .map_err(|err| StateStoreError::Any(Box::new(err)))?,
             store,
+            volatile: false,
+            quota: None,
+            journal: Arc::default(),
+        })
+    }
+
+    /// Like [`Self::new`], but states are only kept in the mem cache, so the least used ones
+    /// are lost once it is full. Parameters are still written to the store. Storing a state
+    /// the cache refuses to admit fails with [`StateStoreError::NotRetained`].
+    pub fn volatile(store: S, max_size: u32) -> Result<Self, StateStoreError> {
+        Ok(Self {
+            volatile: true,
+            ..Self::new(store, max_size)?
         })
     }
 
+    /// Evicts the least worth keeping states once the ones written to the store go over the
+    /// quota. Volatile stores write no states, so they are never over it.
+    ///
+    /// The states already in the store, written before the node started, count towards the
+    /// quota too, and are evicted right away if over it.
+    pub async fn set_quota(&mut self, mut quota: StateQuota) -> Result<(), StateStoreError> {
+        let now = quota.now();
+        let evicted = if self.volatile {
+            vec![]
+        } else {
+            for (key, size) in self.store.state_sizes().await.map_err(Into::into)? {
+                quota.stored(key, size, now);
+            }
+            quota.over_quota(now, None)
+        };
+        self.quota = Some(Arc::new(Mutex::new(quota)));
+        self.evict(evicted).await
+    }
+
+    fn accessed(&self, key: &ContractKey) {
+        if let Some(quota) = &self.quota {
+            let mut quota = quota.lock();
+            let now = quota.now();
+            quota.accessed(key, now);
+        }
+    }
+
+    /// Accounts for a state written to the store, evicting others if it went over the quota.
+    async fn stored(&mut self, key: ContractKey, size: usize) -> Result<(), StateStoreError> {
+        let Some(quota) = &self.quota else {
+            return Ok(());
+        };
+        let evicted = {
+            let mut quota = quota.lock();
+            let now = quota.now();
+            quota.stored(key, size as u64, now);
+            // the state just written is kept, it was accepted already
+            quota.over_quota(now, Some(&key))
+        };
+        self.evict(evicted).await
+    }
+
+    async fn evict(&mut self, evicted: Vec<Eviction>) -> Result<(), StateStoreError> {
+        for eviction in evicted {
+            tracing::info!(
+                key = %eviction.key,
+                size = eviction.size,
+                idle = ?eviction.idle,
+                subscribers = eviction.subscribers,
+                "Evicted contract state over the storage quota"
+            );
+            self.state_mem_cache.remove(&eviction.key).await;
+            self.journal.lock().remove(&eviction.key);
+            self.store.remove(&eviction.key).await.map_err(Into::into)?;
+        }
+        Ok(())
+    }
+
+    async fn cache(&self, key: ContractKey, state: WrappedState) -> Result<(), StateStoreError> {
+        let cost = state.size() as i64;
+        self.state_mem_cache.insert(key, state, cost).await;
+        if self.volatile {
+            // the cache is the only copy, so it must be readable right away, and the state is
+            // lost if the cache did not admit it
+            let _ = self.state_mem_cache.wait().await;
+            if self.state_mem_cache.get(&key).await.is_none() {
+                return Err(StateStoreError::NotRetained(key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets how much of the history of the states stored is journaled.
+    pub fn set_journal_limits(&mut self, limits: StateJournalLimits) {
+        self.journal.lock().set_limits(limits);
+    }
+
+    /// Journal of the recent versions of the states stored.
+    pub(crate) fn journal(&self) -> MutexGuard<'_, StateJournal> {
+        self.journal.lock()
+    }
+
     pub async fn update(
         &mut self,
         key: &ContractKey,
         state: WrappedState,
+    ) -> Result<(), StateStoreError> {
+        self.update_by(key, state, None).await
+    }
+
+    /// Like [`Self::update`], for a state resulting from applying the delta on the current one.
+    pub async fn update_by_delta(
+        &mut self,
+        key: &ContractKey,
+        state: WrappedState,
+        delta: StateDelta<'static>,
+    ) -> Result<(), StateStoreError> {
+        self.update_by(key, state, Some(delta)).await
+    }
+
+    async fn update_by(
+        &mut self,
+        key: &ContractKey,
+        state: WrappedState,
+        delta: Option<StateDelta<'static>>,
     ) -> Result<(), StateStoreError> {
         // only allow updates for existing contracts
-        if self.state_mem_cache.get(key).await.is_none() {
-            self.store
+        let cached = self
+            .state_mem_cache
+            .get(key)
+            .await
+            .map(|v| v.value().clone());
+        let current = match cached {
+            Some(state) => state,
+            None if self.volatile => return Err(StateStoreError::MissingContract(*key)),
+            None => self
+                .store
                 .get(key)
                 .await
                 .map_err(Into::into)?
-                .ok_or_else(|| StateStoreError::MissingContract(*key))?;
+                .ok_or_else(|| StateStoreError::MissingContract(*key))?,
+        };
+        {
+            // states stored before the node started, or whose journal was dropped, are the
+            // first version journaled, so the update can still be rolled back
+            let mut journal = self.journal.lock();
+            if journal.version(key).is_none() {
+                journal.record(key, &current, None);
+            }
+        }
+        let size = state.size();
+        if !self.volatile {
+            self.store
+                .store(*key, state.clone())
+                .await
+                .map_err(Into::into)?;
+        }
+        self.cache(*key, state.clone()).await?;
+        self.journal.lock().record(key, &state, delta);
+        if !self.volatile {
+            self.stored(*key, size).await?;
         }
-        self.store
-            .store(*key, state.clone())
-            .await
-            .map_err(Into::into)?;
-        let cost = state.size() as i64;
-        self.state_mem_cache.insert(*key, state, cost).await;
         Ok(())
     }
 
@@ -99,29 +286,137 @@ where
         state: WrappedState,
         params: Parameters<'static>,
     ) -> Result<(), StateStoreError> {
-        self.store
-            .store(key, state.clone())
-            .await
-            .map_err(Into::into)?;
-        let cost = state.size() as i64;
-        self.state_mem_cache.insert(key, state, cost).await;
+        let size = state.size();
+        if !self.volatile {
+            self.store
+                .store(key, state.clone())
+                .await
+                .map_err(Into::into)?;
+        }
+        self.cache(key, state.clone()).await?;
+        self.journal.lock().record(&key, &state, None);
         self.store
             .store_params(key, params.clone())
             .await
             .map_err(Into::into)?;
+        if !self.volatile {
+            self.stored(key, size).await?;
+        }
         // let cost = params.size();
         // self.params_mem_cache.insert(key, params, cost as i64).await;
         Ok(())
     }
 
     pub async fn get(&self, key: &ContractKey) -> Result<WrappedState, StateStoreError> {
+        self.accessed(key);
         if let Some(v) = self.state_mem_cache.get(key).await {
             return Ok(v.value().clone());
         }
+        if self.volatile {
+            return Err(StateStoreError::MissingContract(*key));
+        }
         let r = self.store.get(key).await.map_err(Into::into)?;
         r.ok_or_else(|| StateStoreError::MissingContract(*key))
     }
 
+    /// Whether a state of the contract is stored, without counting it as an access.
+    pub async fn contains(&self, key: &ContractKey) -> Result<bool, StateStoreError> {
+        Ok(self.peek(key).await?.is_some())
+    }
+
+    /// Bytes of the states of the given contracts stored, taken from the index of the store
+    /// rather than read from the states, without counting it as an access. Volatile stores
+    /// keep the states in memory only, where they are measured instead.
+    pub async fn state_sizes(
+        &self,
+        keys: impl IntoIterator<Item = ContractKey>,
+    ) -> Result<HashMap<ContractKey, u64>, StateStoreError> {
+        if self.volatile {
+            let mut sizes = HashMap::new();
+            for key in keys {
+                if let Some(state) = self.peek(&key).await? {
+                    sizes.insert(key, state.size() as u64);
+                }
+            }
+            return Ok(sizes);
+        }
+        let keys: HashSet<_> = keys.into_iter().collect();
+        Ok(self
+            .store
+            .state_sizes()
+            .await
+            .map_err(Into::into)?
+            .into_iter()
+            .filter(|(key, _)| keys.contains(key))
+            .collect())
+    }
+
+    /// Time since the state of the contract was last read or written, if since the quota
+    /// started accounting for it.
+    pub fn idle(&self, key: &ContractKey) -> Option<Duration> {
+        let quota = self.quota.as_ref()?.lock();
+        let last_access = quota.last_access(key)?;
+        Some(quota.now().saturating_duration_since(last_access))
+    }
+
+    /// Checks the bytes of the state of the contract in the store against the hash recorded
+    /// when they were written, without counting it as an access. Volatile stores write no
+    /// states, so the ones they keep are always intact.
+    pub async fn verify(&self, key: &ContractKey) -> Result<StoredState, StateStoreError> {
+        if self.volatile {
+            return Ok(match self.peek(key).await? {
+                Some(state) => StoredState::Intact(state),
+                None => StoredState::Missing,
+            });
+        }
+        let Some((state, recorded)) = self.store.get_with_digest(key).await.map_err(Into::into)?
+        else {
+            return Ok(StoredState::Missing);
+        };
+        match recorded {
+            Some(recorded) if recorded != blake3::hash(state.as_ref()) => {
+                Ok(StoredState::Corrupted)
+            }
+            Some(_) => Ok(StoredState::Intact(state)),
+            None => Ok(StoredState::Unrecorded(state)),
+        }
+    }
+
+    /// Writes the state of the contract again, for the store to record the hash of its bytes
+    /// along with it, once a state stored without one was found valid. It is not counted as
+    /// an access nor as a new version of the state.
+    pub async fn record_digest(
+        &mut self,
+        key: &ContractKey,
+        state: WrappedState,
+    ) -> Result<(), StateStoreError> {
+        if self.volatile {
+            return Ok(());
+        }
+        self.store.store(*key, state).await.map_err(Into::into)
+    }
+
+    /// Takes the state of the contract out of service, along with its parameters, so it is
+    /// fetched anew instead of served.
+    pub async fn quarantine(&mut self, key: &ContractKey) -> Result<(), StateStoreError> {
+        self.state_mem_cache.remove(key).await;
+        self.journal.lock().remove(key);
+        if let Some(quota) = &self.quota {
+            quota.lock().removed(key);
+        }
+        self.store.remove(key).await.map_err(Into::into)
+    }
+
+    async fn peek(&self, key: &ContractKey) -> Result<Option<WrappedState>, StateStoreError> {
+        if let Some(v) = self.state_mem_cache.get(key).await {
+            return Ok(Some(v.value().clone()));
+        }
+        if self.volatile {
+            return Ok(None);
+        }
+        self.store.get(key).await.map_err(Into::into)
+    }
+
     pub async fn get_params<'a>(
         &'a self,
         key: &'a ContractKey,
@@ -133,3 +428,136 @@ where
         Ok(r)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::storages::memory::InMemory;
+
+    #[tokio::test]
+    async fn corrupted_states_are_quarantined() -> Result<(), StateStoreError> {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let backend = InMemory::default();
+        let mut store = StateStore::new(backend.clone(), 10_000)?;
+        store
+            .store(
+                key,
+                WrappedState::new(vec![1, 2, 3]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+        assert!(matches!(store.verify(&key).await?, StoredState::Intact(_)));
+
+        // the bytes change behind the back of the state store, the hash recorded with them
+        // is still checked after a restart
+        backend.corrupt(&key, WrappedState::new(vec![1, 2, 4]));
+        let mut store = StateStore::new(backend.clone(), 10_000)?;
+        assert!(matches!(store.verify(&key).await?, StoredState::Corrupted));
+
+        store.quarantine(&key).await?;
+        assert!(matches!(store.verify(&key).await?, StoredState::Missing));
+        assert!(store.get_params(&key).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn digests_are_recorded_for_states_stored_without() -> Result<(), StateStoreError> {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let backend = InMemory::default();
+        backend.store_without_digest(key, WrappedState::new(vec![1, 2, 3]));
+        let mut store = StateStore::new(backend.clone(), 10_000)?;
+        let StoredState::Unrecorded(state) = store.verify(&key).await? else {
+            panic!("expected a state without its hash recorded");
+        };
+
+        store.record_digest(&key, state).await?;
+        assert!(matches!(store.verify(&key).await?, StoredState::Intact(_)));
+        backend.corrupt(&key, WrappedState::new(vec![1, 2, 4]));
+        assert!(matches!(store.verify(&key).await?, StoredState::Corrupted));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quota_accounts_for_states_stored_before() -> Result<(), StateStoreError> {
+        let key = |byte| ContractKey::from(ContractInstanceId::new([byte; 32]));
+        let state = || WrappedState::new(vec![0; 100]);
+        let mut backend = InMemory::default();
+        backend.store(key(1), state()).await.unwrap();
+        backend.store(key(2), state()).await.unwrap();
+
+        // the states of a previous run are over the quota on their own
+        let mut store = StateStore::new(backend.clone(), 10_000)?;
+        store.set_quota(StateQuota::new(150)).await?;
+        assert_eq!(backend.state_sizes().await.unwrap().len(), 1);
+
+        // a new state makes room for itself
+        store
+            .store(key(3), state(), Parameters::from(vec![]))
+            .await?;
+        assert_eq!(
+            backend.state_sizes().await.unwrap(),
+            [(key(3), state().size() as u64)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sizes_of_the_requested_states() -> Result<(), StateStoreError> {
+        let key = |byte| ContractKey::from(ContractInstanceId::new([byte; 32]));
+        let mut store = StateStore::new(InMemory::default(), 10_000)?;
+        for (byte, size) in [(1, 10), (2, 20)] {
+            store
+                .store(
+                    key(byte),
+                    WrappedState::new(vec![0; size]),
+                    Parameters::from(vec![]),
+                )
+                .await?;
+        }
+        let sizes = store.state_sizes([key(2), key(3)]).await?;
+        assert_eq!(sizes, HashMap::from([(key(2), 20)]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn states_stored_before_are_journaled_on_update() -> Result<(), StateStoreError> {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let mut backend = InMemory::default();
+        backend
+            .store(key, WrappedState::new(vec![1]))
+            .await
+            .unwrap();
+
+        // the state of a previous run is the version the first update can be rolled back to
+        let mut store = StateStore::new(backend, 10_000)?;
+        store.update(&key, WrappedState::new(vec![2])).await?;
+        assert_eq!(store.journal().version(&key), Some(2));
+        let (state, deltas) = store.journal().replay(&key, 1).unwrap();
+        assert_eq!(state.as_ref(), &[1]);
+        assert!(deltas.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn volatile_store_reports_states_not_kept() -> Result<(), StateStoreError> {
+        let key = ContractKey::from(ContractInstanceId::new([1; 32]));
+        let mut store = StateStore::volatile(InMemory::default(), 1_000)?;
+
+        let larger_than_the_cache = WrappedState::new(vec![0; 10_000]);
+        let stored = store
+            .store(key, larger_than_the_cache, Parameters::from(vec![]))
+            .await;
+        assert!(matches!(stored, Err(StateStoreError::NotRetained(k)) if k == key));
+        assert!(!store.contains(&key).await?);
+
+        store
+            .store(
+                key,
+                WrappedState::new(vec![0; 10]),
+                Parameters::from(vec![]),
+            )
+            .await?;
+        assert_eq!(store.get(&key).await?.size(), 10);
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use freenet_stdlib::prelude::*;
 use crate::wasm_runtime::tests::TestSetup;
 
 use super::super::contract::*;
-use super::super::Runtime;
+use super::super::{ContractExecError, Runtime, RuntimeInnerError};
 
 const TEST_CONTRACT_1: &str = "test_contract_1";
 
@@ -62,6 +62,47 @@ fn update_state() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn update_is_deterministic() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_1)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let check = |runtime: &mut Runtime, recorded: Option<&UpdateModification<'_>>| {
+        runtime.check_update_determinism(
+            &contract_key,
+            &Parameters::from([].as_ref()),
+            &WrappedState::new(vec![5, 2, 3]),
+            &[StateDelta::from([4].as_ref()).into()],
+            recorded,
+        )
+    };
+    let new_state = check(&mut runtime, None)?;
+    // the same result recorded elsewhere
+    check(&mut runtime, Some(&new_state))?;
+    assert_eq!(new_state.unwrap_valid().as_ref(), &[5, 2, 3, 4]);
+
+    // a result recorded on a host which diverged
+    let diverged = UpdateModification::valid(State::from(vec![5, 2, 3, 5]));
+    assert!(check(&mut runtime, Some(&diverged)).is_err());
+    // the runtime is left as it was by the check
+    let new_state = runtime
+        .update_state(
+            &contract_key,
+            &Parameters::from([].as_ref()),
+            &WrappedState::new(vec![5, 2, 3]),
+            &[StateDelta::from([4].as_ref()).into()],
+        )?
+        .unwrap_valid();
+    assert_eq!(new_state.as_ref(), &[5, 2, 3, 4]);
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
 #[test]
 fn summarize_state() -> Result<(), Box<dyn std::error::Error>> {
     let TestSetup {
@@ -105,3 +146,175 @@ fn get_state_delta() -> Result<(), Box<dyn std::error::Error>> {
     std::mem::drop(temp_dir);
     Ok(())
 }
+
+#[test]
+fn out_of_fuel() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_1)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    runtime.set_contract_limits(ContractLimits {
+        update_fuel: 10,
+        ..Default::default()
+    });
+
+    let update = |runtime: &mut Runtime| {
+        runtime.update_state(
+            &contract_key,
+            &Parameters::from([].as_ref()),
+            &WrappedState::new(vec![5, 2, 3]),
+            &[StateDelta::from([4].as_ref()).into()],
+        )
+    };
+    let err = update(&mut runtime).unwrap_err();
+    assert!(matches!(
+        err.deref(),
+        RuntimeInnerError::ContractExecError(ContractExecError::OutOfFuel {
+            call: ContractCall::UpdateState,
+            fuel: 10,
+            ..
+        })
+    ));
+    // other calls have their own budget
+    runtime.summarize_state(
+        &contract_key,
+        &Parameters::from([].as_ref()),
+        &WrappedState::new(vec![5, 2, 3, 4]),
+    )?;
+
+    runtime.set_contract_limits(ContractLimits::default());
+    assert!(update(&mut runtime)?.unwrap_valid().as_ref().len() == 4);
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
+#[test]
+fn sandbox_limits() -> Result<(), Box<dyn std::error::Error>> {
+    let TestSetup {
+        contract_store,
+        delegate_store,
+        secrets_store,
+        contract_key,
+        temp_dir,
+    } = super::setup_test_contract(TEST_CONTRACT_1)?;
+    let mut runtime = Runtime::build(contract_store, delegate_store, secrets_store, false).unwrap();
+    let validate = |runtime: &mut Runtime| {
+        runtime.validate_state(
+            &contract_key,
+            &Parameters::from([].as_ref()),
+            &WrappedState::new(vec![1, 2, 3, 4]),
+            &Default::default(),
+        )
+    };
+    validate(&mut runtime)?;
+
+    let audit = runtime.contract_imports();
+    assert_eq!(audit.len(), 1);
+    assert!(audit[0].imports.iter().all(|import| import.allowed));
+
+    runtime.set_contract_limits(ContractLimits {
+        max_memory: 1024,
+        ..Default::default()
+    });
+    let err = validate(&mut runtime).unwrap_err();
+    assert!(matches!(
+        err.deref(),
+        RuntimeInnerError::ContractExecError(ContractExecError::MemoryLimitExceeded {
+            max: 1024,
+            ..
+        })
+    ));
+
+    if let Some(import) = audit[0].imports.first() {
+        runtime.set_contract_limits(ContractLimits {
+            allowed_imports: vec![],
+            ..Default::default()
+        });
+        let err = validate(&mut runtime).unwrap_err();
+        assert!(matches!(
+            err.deref(),
+            RuntimeInnerError::ContractExecError(ContractExecError::ForbiddenImport { import: forbidden, .. })
+                if *forbidden == format!("{}.{}", import.namespace, import.name)
+        ));
+    }
+    std::mem::drop(temp_dir);
+    Ok(())
+}
+
+#[test]
+fn contract_rejection_roundtrip() {
+    let rejection = ContractRejection::new(3, "sender blocked");
+    let error = rejection.clone().into_contract_error();
+    assert_eq!(
+        ContractRejection::from_contract_error(&error),
+        Some(rejection)
+    );
+    assert_eq!(
+        ContractRejection::from_contract_error(&ContractError::InvalidUpdate),
+        None
+    );
+    let unstructured = ContractError::InvalidUpdateWithInfo {
+        reason: "bad delta".to_owned(),
+    };
+    assert_eq!(ContractRejection::from_contract_error(&unstructured), None);
+
+    for rejection in [
+        ContractRejection::InvalidDelta("unknown entry".into()),
+        ContractRejection::MissingRelated(ContractInstanceId::new([1; 32])),
+        ContractRejection::SizeExceeded {
+            size: 2048,
+            max: 1024,
+        },
+        ContractRejection::InvalidSignature("wrong key".into()),
+    ] {
+        let error = rejection.clone().into_contract_error();
+        assert_eq!(
+            ContractRejection::from_contract_error(&error),
+            Some(rejection)
+        );
+    }
+    let malformed = ContractError::InvalidUpdateWithInfo {
+        reason: "rejected:size_exceeded:2048".to_owned(),
+    };
+    assert_eq!(ContractRejection::from_contract_error(&malformed), None);
+}
+
+#[test]
+fn immutable_contract_declaration() {
+    // empty module with a custom section, as appended by the packaging tool
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    let name = super::super::IMMUTABLE_SECTION.as_bytes();
+    module.extend_from_slice(&[0, name.len() as u8 + 1, name.len() as u8]);
+    module.extend_from_slice(name);
+    assert!(super::super::declares_immutable(&module));
+    assert!(!super::super::declares_immutable(b"\0asm\x01\0\0\0"));
+}
+
+#[test]
+fn abi_version_declaration() -> Result<(), Box<dyn std::error::Error>> {
+    let store = wasmer::Store::default();
+    let module = |version: Option<&[u8]>| {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        if let Some(version) = version {
+            let name = super::super::ABI_SECTION.as_bytes();
+            let size = 1 + name.len() + version.len();
+            module.extend_from_slice(&[0, size as u8, name.len() as u8]);
+            module.extend_from_slice(name);
+            module.extend_from_slice(version);
+        }
+        wasmer::Module::new(&store, module)
+    };
+    // contracts declaring no version were built against the first one
+    assert_eq!(ContractAbi::of(&module(None)?), Ok(ContractAbi::V1));
+    assert_eq!(
+        ContractAbi::of(&module(Some(&1u32.to_le_bytes()))?),
+        Ok(ContractAbi::V1)
+    );
+    assert_eq!(ContractAbi::of(&module(Some(&2u32.to_le_bytes()))?), Err(2));
+    assert_eq!(ContractAbi::of(&module(Some(&[1]))?), Err(0));
+    Ok(())
+}
@@ -207,6 +207,12 @@ mod contract {
         pub c_type: Option<ContractType>,
         pub lang: Option<SupportedContractLangs>,
         pub output_dir: Option<PathBuf>,
+        /// Interval, in seconds, at which nodes hosting the contract run its state maintenance.
+        pub maintenance_interval: Option<u64>,
+        /// Whether the contract state is fixed once published (e.g. static web content),
+        /// in which case nodes reject any update to it.
+        #[serde(default)]
+        pub immutable: bool,
     }
 
     #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -477,7 +483,7 @@ mod contract {
                 } else {
                     get_default_ouput_dir(cwd)?.join(package_name)
                 };
-                let output = get_versioned_contract(&output_lib, cli_config)?;
+                let output = get_versioned_contract(&output_lib, &config.contract, cli_config)?;
                 let mut file = File::create(out_file)?;
                 file.write_all(output.as_slice())?;
             }
@@ -489,9 +495,21 @@ mod contract {
 
     fn get_versioned_contract(
         contract_code_path: &Path,
+        contract_config: &Contract,
         cli_config: &BuildToolConfig,
     ) -> anyhow::Result<Vec<u8>> {
-        let code: ContractCode = ContractCode::load_raw(contract_code_path)?;
+        let mut module = fs::read(contract_code_path)?;
+        if let Some(secs) = contract_config.maintenance_interval {
+            append_custom_section(
+                &mut module,
+                freenet::dev_tool::MAINTENANCE_SECTION,
+                &secs.to_le_bytes(),
+            );
+        }
+        if contract_config.immutable {
+            append_custom_section(&mut module, freenet::dev_tool::IMMUTABLE_SECTION, &[]);
+        }
+        let code = ContractCode::from(module);
         tracing::info!("compiled contract code hash: {}", code.hash_str());
         let output = code
             .to_bytes_versioned(
@@ -503,6 +521,29 @@ mod contract {
         Ok(output)
     }
 
+    /// Appends a custom section to the given wasm module.
+    fn append_custom_section(module: &mut Vec<u8>, name: &str, payload: &[u8]) {
+        fn leb128(mut value: usize, out: &mut Vec<u8>) {
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    out.push(byte);
+                    break;
+                }
+                out.push(byte | 0x80);
+            }
+        }
+        let mut content = vec![];
+        leb128(name.len(), &mut content);
+        content.extend_from_slice(name.as_bytes());
+        content.extend_from_slice(payload);
+        // custom sections have id 0
+        module.push(0);
+        leb128(content.len(), module);
+        module.extend(content);
+    }
+
     #[skip_serializing_none]
     #[derive(Default, Serialize)]
     struct DependencyDefinition {
@@ -599,6 +640,8 @@ mod contract {
                         c_type: Some(ContractType::WebApp),
                         lang: Some(SupportedContractLangs::Rust),
                         output_dir: None,
+                        maintenance_interval: None,
+                        immutable: false,
                     },
                     state: None,
                     webapp: Some(WebAppContract {
@@ -663,6 +706,8 @@ mod contract {
                     c_type: Some(ContractType::Standard),
                     lang: Some(SupportedContractLangs::Rust),
                     output_dir: None,
+                    maintenance_interval: None,
+                    immutable: false,
                 },
                 state: Some(Sources {
                     source_dirs: None,
@@ -682,6 +727,20 @@ mod contract {
             Ok(())
         }
 
+        #[test]
+        fn maintenance_interval_section() {
+            let header = b"\0asm\x01\0\0\0";
+            let mut module = header.to_vec();
+            append_custom_section(&mut module, "freenet_maintenance", &3600u64.to_le_bytes());
+
+            let (section, content) = module[header.len()..].split_at(2);
+            // custom section id and size of its content
+            assert_eq!(section, [0, 28]);
+            assert_eq!(content[0] as usize, "freenet_maintenance".len());
+            assert_eq!(&content[1..20], b"freenet_maintenance");
+            assert_eq!(content[20..], 3600u64.to_le_bytes());
+        }
+
         #[test]
         fn deps_parsing() -> anyhow::Result<()> {
             let deps = toml::toml! {
@@ -1,6 +1,6 @@
 use std::{fs::File, io::Read, net::SocketAddr, path::PathBuf};
 
-use freenet::dev_tool::OperationMode;
+use freenet::{dev_tool::OperationMode, util::contract_key::CheckedKey};
 use freenet_stdlib::{
     client_api::{ClientRequest, ContractRequest, DelegateRequest, WebApi},
     prelude::*,
@@ -76,7 +76,7 @@ async fn put_contract(
         Default::default()
     };
 
-    println!("Putting contract {}", contract.key());
+    println!("Putting contract {}", CheckedKey(*contract.key().id()));
     let request = ContractRequest::Put {
         contract,
         state,
@@ -131,7 +131,7 @@ pub async fn update(config: UpdateConfig, other: BaseConfig) -> anyhow::Result<(
     if config.release {
         anyhow::bail!("Cannot publish contracts in the network yet");
     }
-    let key = ContractInstanceId::try_from(config.key)?.into();
+    let key = config.key.parse::<CheckedKey>()?.into();
     println!("Updating contract {key}");
     let data = {
         let mut buf = vec![];
@@ -44,6 +44,9 @@ pub enum SubCommand {
     Publish(PutConfig),
     /// Query the local node for information. Currently only shows open connections.
     Query {},
+    Search(crate::search::SearchConfig),
+    #[clap(name = "loadtest")]
+    LoadTest(crate::loadtest::LoadTestConfig),
     WasmRuntime(ExecutorConfig),
     Execute(RunCliConfig),
     Test(crate::testing::TestConfig),
@@ -0,0 +1,471 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::Read,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use freenet::util::contract_key::CheckedKey;
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse, WebApi},
+    prelude::*,
+};
+use prettytable::{Cell, Row, Table};
+use rand::Rng;
+
+use crate::{
+    commands::{execute_command, start_api_client},
+    config::BaseConfig,
+};
+
+/// Drives a node with a mix of concurrent requests, measuring the latencies per operation
+/// type, and fails if any of the configured latency objectives is violated.
+///
+/// Meant for gating releases on performance regressions and for capacity planning.
+#[derive(clap::Parser, Clone, Debug)]
+pub struct LoadTestConfig {
+    /// Contract ids, in Base58 format, the requests are made against. Can be repeated.
+    #[arg(long = "contract", required = true)]
+    pub(crate) contracts: Vec<String>,
+    /// Relative weight of each operation type in the requests, e.g. `get=8,subscribe=1,update=1`.
+    #[arg(long, default_value = "get=1", value_parser = RequestMix::from_str)]
+    pub(crate) mix: RequestMix,
+    /// A path to the delta sent in update requests. Required if updates are part of the mix.
+    #[arg(long)]
+    pub(crate) delta: Option<PathBuf>,
+    /// Number of clients sending requests concurrently.
+    #[arg(long, default_value_t = 4)]
+    pub(crate) concurrency: usize,
+    /// Total number of requests to send.
+    #[arg(long, default_value_t = 1000)]
+    pub(crate) requests: usize,
+    /// Time in milliseconds after which a request without response is considered failed.
+    #[arg(long, default_value_t = 30_000)]
+    pub(crate) timeout_ms: u64,
+    /// Latency objective, as `<operation>:<percentile>=<latency>`, e.g. `get:p99=500ms`.
+    /// Can be repeated.
+    #[arg(long = "slo", value_parser = Slo::from_str)]
+    pub(crate) slos: Vec<Slo>,
+    /// Max ratio of failed requests per operation type.
+    #[arg(long, default_value_t = 0.01)]
+    pub(crate) max_failure_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum OpKind {
+    Get,
+    Subscribe,
+    Update,
+}
+
+impl FromStr for OpKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "get" => Ok(Self::Get),
+            "subscribe" => Ok(Self::Subscribe),
+            "update" => Ok(Self::Update),
+            other => Err(format!("unknown operation type: {other}")),
+        }
+    }
+}
+
+impl Display for OpKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpKind::Get => write!(f, "get"),
+            OpKind::Subscribe => write!(f, "subscribe"),
+            OpKind::Update => write!(f, "update"),
+        }
+    }
+}
+
+/// Weighted operation types the requests are drawn from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RequestMix(Vec<(OpKind, u32)>);
+
+impl RequestMix {
+    fn pick(&self, rng: &mut impl Rng) -> OpKind {
+        let total: u32 = self.0.iter().map(|(_, weight)| weight).sum();
+        let mut choice = rng.gen_range(0..total);
+        for (op, weight) in &self.0 {
+            if choice < *weight {
+                return *op;
+            }
+            choice -= weight;
+        }
+        unreachable!("the choice is always below the total weight")
+    }
+
+    fn contains(&self, op: OpKind) -> bool {
+        self.0.iter().any(|(other, _)| *other == op)
+    }
+}
+
+impl FromStr for RequestMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = Vec::new();
+        for entry in s.split(',') {
+            let (op, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected `<operation>=<weight>`, found `{entry}`"))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid weight `{weight}`: {e}"))?;
+            if weight > 0 {
+                mix.push((op.parse()?, weight));
+            }
+        }
+        if mix.is_empty() {
+            return Err("the request mix is empty".to_owned());
+        }
+        Ok(Self(mix))
+    }
+}
+
+/// Max latency allowed at a percentile for an operation type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Slo {
+    op: OpKind,
+    percentile: u8,
+    max_latency: Duration,
+}
+
+impl FromStr for Slo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected `<operation>:p<percentile>=<latency>`, found `{s}`");
+        let (op, objective) = s.split_once(':').ok_or_else(err)?;
+        let (percentile, latency) = objective.split_once('=').ok_or_else(err)?;
+        let percentile = percentile
+            .trim()
+            .strip_prefix('p')
+            .and_then(|p| p.parse().ok())
+            .filter(|p| (1..=100).contains(p))
+            .ok_or_else(err)?;
+        Ok(Self {
+            op: op.parse()?,
+            percentile,
+            max_latency: parse_duration(latency)?,
+        })
+    }
+}
+
+impl Display for Slo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:p{}={:?}", self.op, self.percentile, self.max_latency)
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (value, unit) = s
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|idx| s.split_at(idx))
+        .ok_or_else(|| format!("missing time unit in `{s}`"))?;
+    let value: u64 = value
+        .parse()
+        .map_err(|e| format!("invalid latency `{s}`: {e}"))?;
+    match unit {
+        "us" => Ok(Duration::from_micros(value)),
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        other => Err(format!("unknown time unit `{other}`, expected us, ms or s")),
+    }
+}
+
+/// Outcome of the requests of an operation type.
+#[derive(Debug, Default)]
+struct OpResults {
+    /// Latencies of the successful requests.
+    latencies: Vec<Duration>,
+    failed: usize,
+}
+
+impl OpResults {
+    fn total(&self) -> usize {
+        self.latencies.len() + self.failed
+    }
+
+    fn failure_rate(&self) -> f64 {
+        self.failed as f64 / self.total() as f64
+    }
+
+    /// Latency at the given percentile, using the nearest rank method.
+    fn percentile(&self, percentile: u8) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort_unstable();
+        let rank = (sorted.len() * percentile as usize).div_ceil(100).max(1);
+        Some(sorted[rank - 1])
+    }
+}
+
+#[derive(Debug, Default)]
+struct LoadTestReport {
+    results: BTreeMap<OpKind, OpResults>,
+}
+
+impl LoadTestReport {
+    fn record(&mut self, op: OpKind, outcome: Result<Duration, ()>) {
+        let results = self.results.entry(op).or_default();
+        match outcome {
+            Ok(latency) => results.latencies.push(latency),
+            Err(()) => results.failed += 1,
+        }
+    }
+
+    /// Describes every objective not met by the results.
+    fn violations(&self, slos: &[Slo], max_failure_rate: f64) -> Vec<String> {
+        let mut violations = Vec::new();
+        for (op, results) in &self.results {
+            let failure_rate = results.failure_rate();
+            if failure_rate > max_failure_rate {
+                violations.push(format!(
+                    "{op}: failure rate {:.2}% above {:.2}%",
+                    failure_rate * 100.0,
+                    max_failure_rate * 100.0
+                ));
+            }
+        }
+        for slo in slos {
+            match self
+                .results
+                .get(&slo.op)
+                .and_then(|results| results.percentile(slo.percentile))
+            {
+                Some(latency) if latency > slo.max_latency => {
+                    violations.push(format!("{slo} violated, measured {latency:?}"))
+                }
+                Some(_) => {}
+                None => violations.push(format!("{slo} without successful requests")),
+            }
+        }
+        violations
+    }
+
+    fn print(&self) {
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Operation"),
+            Cell::new("Requests"),
+            Cell::new("Failed"),
+            Cell::new("p50"),
+            Cell::new("p95"),
+            Cell::new("p99"),
+        ]));
+        for (op, results) in &self.results {
+            let percentile = |p| {
+                results
+                    .percentile(p)
+                    .map(|latency| format!("{latency:?}"))
+                    .unwrap_or_else(|| "-".to_owned())
+            };
+            table.add_row(Row::new(vec![
+                Cell::new(&op.to_string()),
+                Cell::new(&results.total().to_string()),
+                Cell::new(&results.failed.to_string()),
+                Cell::new(&percentile(50)),
+                Cell::new(&percentile(95)),
+                Cell::new(&percentile(99)),
+            ]));
+        }
+        table.printstd();
+    }
+}
+
+pub async fn load_test(config: LoadTestConfig, other: BaseConfig) -> anyhow::Result<()> {
+    if config.concurrency == 0 {
+        anyhow::bail!("concurrency must be at least 1");
+    }
+    let contracts = config
+        .contracts
+        .iter()
+        .map(|id| Ok(id.parse::<CheckedKey>()?.into()))
+        .collect::<anyhow::Result<Vec<ContractKey>>>()?;
+    let delta = match &config.delta {
+        Some(path) => {
+            let mut buf = vec![];
+            File::open(path)?.read_to_end(&mut buf)?;
+            Some(StateDelta::from(buf))
+        }
+        None if config.mix.contains(OpKind::Update) => {
+            anyhow::bail!("a delta is required to send update requests")
+        }
+        None => None,
+    };
+
+    println!(
+        "Sending {} requests from {} clients",
+        config.requests, config.concurrency
+    );
+    let config = Arc::new(config);
+    let contracts = Arc::new(contracts);
+    let issued = Arc::new(AtomicUsize::new(0));
+    let mut clients = Vec::with_capacity(config.concurrency);
+    for _ in 0..config.concurrency {
+        let client = start_api_client(other.clone()).await?;
+        clients.push(tokio::spawn(run_client(
+            client,
+            config.clone(),
+            contracts.clone(),
+            delta.clone(),
+            issued.clone(),
+        )));
+    }
+
+    let mut report = LoadTestReport::default();
+    for client in clients {
+        for (op, outcome) in client.await?? {
+            report.record(op, outcome);
+        }
+    }
+    report.print();
+
+    let violations = report.violations(&config.slos, config.max_failure_rate);
+    if !violations.is_empty() {
+        for violation in &violations {
+            eprintln!("{violation}");
+        }
+        anyhow::bail!("{} service level objectives violated", violations.len());
+    }
+    Ok(())
+}
+
+type Outcomes = Vec<(OpKind, Result<Duration, ()>)>;
+
+async fn run_client(
+    mut client: WebApi,
+    config: Arc<LoadTestConfig>,
+    contracts: Arc<Vec<ContractKey>>,
+    delta: Option<StateDelta<'static>>,
+    issued: Arc<AtomicUsize>,
+) -> anyhow::Result<Outcomes> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut outcomes = Vec::new();
+    while issued.fetch_add(1, Ordering::SeqCst) < config.requests {
+        let (op, key) = {
+            let mut rng = rand::thread_rng();
+            let op = config.mix.pick(&mut rng);
+            (op, contracts[rng.gen_range(0..contracts.len())])
+        };
+        let request: ClientRequest<'static> = match op {
+            OpKind::Get => ContractRequest::Get {
+                key,
+                return_contract_code: false,
+            },
+            OpKind::Subscribe => ContractRequest::Subscribe { key, summary: None },
+            OpKind::Update => ContractRequest::Update {
+                key,
+                data: delta.clone().expect("checked before starting").into(),
+            },
+        }
+        .into();
+
+        let started = Instant::now();
+        execute_command(request, &mut client).await?;
+        let outcome = match tokio::time::timeout(timeout, wait_for_response(&mut client, op)).await
+        {
+            Ok(Ok(())) => Ok(started.elapsed()),
+            Ok(Err(err)) => {
+                tracing::debug!(%op, %key, "Request failed: {err}");
+                Err(())
+            }
+            Err(_) => {
+                // the response may still arrive later, so don't reuse this client
+                tracing::debug!(%op, %key, "Request timed out");
+                outcomes.push((op, Err(())));
+                break;
+            }
+        };
+        outcomes.push((op, outcome));
+    }
+    Ok(outcomes)
+}
+
+async fn wait_for_response(client: &mut WebApi, op: OpKind) -> anyhow::Result<()> {
+    loop {
+        match (op, client.recv().await?) {
+            (OpKind::Get, HostResponse::ContractResponse(ContractResponse::GetResponse { .. }))
+            | (
+                OpKind::Subscribe,
+                HostResponse::ContractResponse(ContractResponse::SubscribeResponse { .. }),
+            )
+            | (
+                OpKind::Update,
+                HostResponse::ContractResponse(ContractResponse::UpdateResponse { .. }),
+            ) => return Ok(()),
+            // notifications from earlier subscriptions of this client
+            (_, HostResponse::ContractResponse(ContractResponse::UpdateNotification { .. })) => {
+                continue
+            }
+            (_, other) => anyhow::bail!("unexpected response from the host: {other:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mix_and_slos() -> Result<(), String> {
+        let mix: RequestMix = "get=8, subscribe=0,update=2".parse()?;
+        assert_eq!(mix, RequestMix(vec![(OpKind::Get, 8), (OpKind::Update, 2)]));
+        assert!(!mix.contains(OpKind::Subscribe));
+        assert!("get=0".parse::<RequestMix>().is_err());
+        assert!("put=1".parse::<RequestMix>().is_err());
+
+        let slo: Slo = "update:p95=1500ms".parse()?;
+        assert_eq!(
+            slo,
+            Slo {
+                op: OpKind::Update,
+                percentile: 95,
+                max_latency: Duration::from_millis(1500),
+            }
+        );
+        assert!("get:p0=1s".parse::<Slo>().is_err());
+        assert!("get:p99=10".parse::<Slo>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_objectives() {
+        let mut report = LoadTestReport::default();
+        for ms in 1..=100 {
+            report.record(OpKind::Get, Ok(Duration::from_millis(ms)));
+        }
+        report.record(OpKind::Update, Ok(Duration::from_millis(10)));
+        report.record(OpKind::Update, Err(()));
+
+        let get = &report.results[&OpKind::Get];
+        assert_eq!(get.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(get.percentile(99), Some(Duration::from_millis(99)));
+
+        let slos = [
+            "get:p50=50ms".parse().unwrap(),
+            "get:p99=90ms".parse().unwrap(),
+            "subscribe:p50=1s".parse().unwrap(),
+        ];
+        let violations = report.violations(&slos, 0.1);
+        assert_eq!(violations.len(), 3, "{violations:?}");
+        assert!(violations[0].starts_with("update: failure rate 50.00%"));
+        assert!(violations[1].starts_with("get:p99=90ms violated"));
+        assert!(violations[2].starts_with("subscribe:p50=1s without"));
+    }
+}
@@ -7,9 +7,11 @@ mod build;
 mod commands;
 mod config;
 mod inspect;
+mod loadtest;
 pub(crate) mod network_metrics_server;
 mod new_package;
 mod query;
+mod search;
 mod testing;
 mod util;
 mod wasm_runtime;
@@ -71,6 +73,13 @@ fn main() -> anyhow::Result<()> {
                 query::query(config.additional).await?;
                 Ok(())
             }
+            SubCommand::Search(search_config) => {
+                search::search(search_config, config.additional).await?;
+                Ok(())
+            }
+            SubCommand::LoadTest(load_test_config) => {
+                loadtest::load_test(load_test_config, config.additional).await
+            }
         };
         // todo: make all commands return concrete `thiserror` compatible errors so we can use anyhow
         r.map_err(|e| anyhow::format_err!(e))
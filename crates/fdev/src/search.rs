@@ -0,0 +1,97 @@
+use freenet::{server::registry::RegistryIndex, util::contract_key::CheckedKey};
+use freenet_stdlib::{
+    client_api::{ClientRequest, ContractRequest, ContractResponse, HostResponse},
+    prelude::*,
+};
+use prettytable::{Cell, Row, Table};
+
+use crate::{
+    commands::{execute_command, start_api_client},
+    config::BaseConfig,
+};
+
+const INDEX_FILE: &str = "registry_index.json";
+
+/// Search the apps, contracts and delegates published in the metadata registry contract.
+///
+/// A local index is built from the registry contract state the first time it is queried
+/// and reused in later searches, unless a refresh is requested.
+#[derive(clap::Parser, Clone, Debug)]
+pub struct SearchConfig {
+    /// Term to look for in the name, description or publisher of the published packages,
+    /// or an exact code hash or contract key.
+    pub(crate) term: String,
+    /// Contract id of the metadata registry contract in Base58 format.
+    #[arg(long, env = "REGISTRY_CONTRACT")]
+    pub(crate) registry: String,
+    /// Fetch the latest state of the registry contract and rebuild the local index.
+    #[arg(long)]
+    pub(crate) refresh: bool,
+    /// Max number of results to show.
+    #[arg(long, default_value_t = 20)]
+    pub(crate) limit: usize,
+}
+
+pub async fn search(config: SearchConfig, other: BaseConfig) -> anyhow::Result<()> {
+    let index_path = other
+        .paths
+        .clone()
+        .build(None)?
+        .db_dir(other.mode)
+        .join(INDEX_FILE);
+    let index = match RegistryIndex::load(&index_path)? {
+        Some(index) if !config.refresh && index.registry() == config.registry => index,
+        _ => {
+            let index = fetch_index(&config.registry, other).await?;
+            index.store(&index_path)?;
+            index
+        }
+    };
+
+    let results = index.search(&config.term);
+    if results.is_empty() {
+        println!("No results found for `{}`", config.term);
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        Cell::new("Name"),
+        Cell::new("Description"),
+        Cell::new("Publisher"),
+        Cell::new("Code hash"),
+        Cell::new("Key"),
+    ]));
+    for entry in results.into_iter().take(config.limit) {
+        table.add_row(Row::new(vec![
+            Cell::new(&entry.name),
+            Cell::new(&entry.description),
+            Cell::new(&entry.publisher),
+            Cell::new(&entry.code_hash),
+            Cell::new(&entry.key),
+        ]));
+    }
+    table.printstd();
+
+    Ok(())
+}
+
+async fn fetch_index(registry: &str, base_cfg: BaseConfig) -> anyhow::Result<RegistryIndex> {
+    let key: ContractKey = registry.parse::<CheckedKey>()?.into();
+    tracing::info!("Fetching registry contract {key}");
+    let mut client = start_api_client(base_cfg).await?;
+    execute_command(
+        ClientRequest::ContractOp(ContractRequest::Get {
+            key,
+            return_contract_code: false,
+        }),
+        &mut client,
+    )
+    .await?;
+    let HostResponse::ContractResponse(ContractResponse::GetResponse { state, .. }) =
+        client.recv().await?
+    else {
+        anyhow::bail!("Unexpected response from the host");
+    };
+    RegistryIndex::from_state(registry.to_owned(), state.as_ref())
+}
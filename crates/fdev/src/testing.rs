@@ -1,7 +1,7 @@
 use std::{path::PathBuf, time::Duration};
 
 use anyhow::Error;
-use freenet::dev_tool::SimNetwork;
+use freenet::dev_tool::{SimNetwork, WireFeatures};
 
 pub(crate) mod network;
 mod single_process;
@@ -52,6 +52,10 @@ pub struct TestConfig {
     /// Time in milliseconds to wait for the next peer in the simulation to be started.
     #[arg(long)]
     peer_start_backoff_ms: Option<u64>,
+    /// Number of regular nodes running with the baseline wire features, simulating peers
+    /// which did not upgrade to the current release.
+    #[arg(long, default_value_t = 0)]
+    legacy_nodes: usize,
     /// If provided, the execution data will be saved in this directory.
     #[arg(long)]
     execution_data: Option<PathBuf>,
@@ -148,6 +152,9 @@ async fn config_sim_network(base_config: &TestConfig) -> anyhow::Result<SimNetwo
     if base_config.nodes == 0 {
         anyhow::bail!("Nodes should be higher than 0");
     }
+    if base_config.legacy_nodes > base_config.nodes {
+        anyhow::bail!("Legacy nodes should not be higher than the number of nodes");
+    }
     let name = &base_config
         .name
         .as_ref()
@@ -166,6 +173,9 @@ async fn config_sim_network(base_config: &TestConfig) -> anyhow::Result<SimNetwo
     if let Some(backoff) = base_config.peer_start_backoff_ms {
         sim.with_start_backoff(Duration::from_millis(backoff));
     }
+    if base_config.legacy_nodes > 0 {
+        sim.with_legacy_nodes(base_config.legacy_nodes, WireFeatures::BASELINE);
+    }
     Ok(sim)
 }
 
@@ -189,6 +199,7 @@ mod tests {
             event_wait_ms: None,
             connection_wait_ms: None,
             peer_start_backoff_ms: None,
+            legacy_nodes: 0,
             execution_data: None,
             disable_metrics: true,
             command: TestMode::SingleProcess,
@@ -20,6 +20,7 @@ pub(super) async fn run(config: &super::TestConfig) -> anyhow::Result<(), super:
         .map(Duration::from_millis)
         .unwrap_or(Duration::from_millis(200));
     let (connectivity_timeout, network_connection_percent) = config.get_connection_check_params();
+    let wire_breaks = simulated_network.wire_breaks();
     let events_generated = tokio::task::spawn(async move {
         tracing::info!(
             "Waiting for network to be sufficiently connected ({}ms timeout, {}%)",
@@ -31,6 +32,7 @@ pub(super) async fn run(config: &super::TestConfig) -> anyhow::Result<(), super:
         let mut stream = simulated_network.event_chain(events, None);
         while stream.next().await.is_some() {
             tokio::time::sleep(next_event_wait_time).await;
+            wire_breaks.check()?;
         }
         Ok::<_, super::Error>(())
     });